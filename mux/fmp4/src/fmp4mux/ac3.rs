@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! AC-3 and E-AC-3 (ETSI TS 102 366) bit stream information parsing for the `dac3` and `dec3`
+//! boxes.
+
+use bitstream_io::{BigEndian, BitRead, BitReader, BitWrite, BitWriter};
+use std::io::{self, Cursor};
+
+const SYNC_WORD: u16 = 0x0b77;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the AC-3 bit stream information from the first syncframe in `data` and creates the
+/// content of the `dac3` box from it.
+pub fn read_dac3_bytes(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader::endian(Cursor::new(data), BigEndian);
+
+    // syncinfo
+    if reader.read::<u16>(16)? != SYNC_WORD {
+        return Err(invalid_data("no AC-3 sync word"));
+    }
+    // crc1
+    reader.skip(16)?;
+    let fscod = reader.read::<u8>(2)?;
+    if fscod == 0b11 {
+        return Err(invalid_data("reserved AC-3 sample rate code"));
+    }
+    let frmsizecod = reader.read::<u8>(6)?;
+    // Bit rates from 32 to 640 kbit/s
+    let bit_rate_code = frmsizecod >> 1;
+    if bit_rate_code > 18 {
+        return Err(invalid_data("invalid AC-3 frame size code"));
+    }
+
+    // bsi
+    let bsid = reader.read::<u8>(5)?;
+    if bsid > 8 {
+        return Err(invalid_data("not an AC-3 bit stream"));
+    }
+    let bsmod = reader.read::<u8>(3)?;
+    let acmod = reader.read::<u8>(3)?;
+    if acmod & 0b001 != 0 && acmod != 0b001 {
+        // cmixlev
+        reader.skip(2)?;
+    }
+    if acmod & 0b100 != 0 {
+        // surmixlev
+        reader.skip(2)?;
+    }
+    if acmod == 0b010 {
+        // dsurmod
+        reader.skip(2)?;
+    }
+    let lfeon = reader.read_bit()?;
+
+    let mut writer = BitWriter::endian(Vec::with_capacity(3), BigEndian);
+    writer.write(2, fscod)?;
+    writer.write(5, bsid)?;
+    writer.write(3, bsmod)?;
+    writer.write(3, acmod)?;
+    writer.write_bit(lfeon)?;
+    writer.write(5, bit_rate_code)?;
+    // reserved
+    writer.write(5, 0u8)?;
+
+    Ok(writer.into_writer())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct IndependentSubstream {
+    fscod: u8,
+    bsid: u8,
+    acmod: u8,
+    lfeon: bool,
+    num_dep_sub: u8,
+    chan_loc: u16,
+}
+
+/// Reads the E-AC-3 bit stream information from all syncframes in `data`, which is supposed to
+/// be a complete access unit, and creates the content of the `dec3` box from it.
+pub fn read_dec3_bytes(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut substreams = Vec::<IndependentSubstream>::new();
+    let mut total_bits = 0u64;
+    let mut sample_rate = None;
+    let mut samples_per_frame = None;
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut reader = BitReader::endian(Cursor::new(&data[pos..]), BigEndian);
+
+        if reader.read::<u16>(16)? != SYNC_WORD {
+            return Err(invalid_data("no E-AC-3 sync word"));
+        }
+
+        // bsi
+        let strmtyp = reader.read::<u8>(2)?;
+        let _substreamid = reader.read::<u8>(3)?;
+        let frmsiz = reader.read::<u16>(11)?;
+        let fscod = reader.read::<u8>(2)?;
+        let (rate, numblks) = if fscod == 0b11 {
+            let fscod2 = reader.read::<u8>(2)?;
+            let rate = match fscod2 {
+                0b00 => 24_000,
+                0b01 => 22_050,
+                0b10 => 16_000,
+                _ => return Err(invalid_data("reserved E-AC-3 sample rate code")),
+            };
+            (rate, 6)
+        } else {
+            let numblkscod = reader.read::<u8>(2)?;
+            let rate = match fscod {
+                0b00 => 48_000,
+                0b01 => 44_100,
+                _ => 32_000,
+            };
+            (rate, [1, 2, 3, 6][numblkscod as usize])
+        };
+        let acmod = reader.read::<u8>(3)?;
+        let lfeon = reader.read_bit()?;
+        let bsid = reader.read::<u8>(5)?;
+        if !(11..=16).contains(&bsid) {
+            return Err(invalid_data("not an E-AC-3 bit stream"));
+        }
+        // dialnorm
+        reader.skip(5)?;
+        if reader.read_bit()? {
+            // compr
+            reader.skip(8)?;
+        }
+        if acmod == 0b000 {
+            // dialnorm2
+            reader.skip(5)?;
+            if reader.read_bit()? {
+                // compr2
+                reader.skip(8)?;
+            }
+        }
+
+        match strmtyp {
+            // Independent substream
+            0b00 | 0b10 => {
+                if substreams.len() == 8 {
+                    return Err(invalid_data("too many E-AC-3 independent substreams"));
+                }
+                substreams.push(IndependentSubstream {
+                    fscod,
+                    bsid,
+                    acmod,
+                    lfeon,
+                    ..Default::default()
+                });
+            }
+            // Dependent substream of the last independent substream
+            0b01 => {
+                let Some(substream) = substreams.last_mut() else {
+                    return Err(invalid_data("E-AC-3 dependent substream without parent"));
+                };
+                substream.num_dep_sub += 1;
+                if reader.read_bit()? {
+                    let chanmap = reader.read::<u16>(16)?;
+                    substream.chan_loc |= (chanmap >> 5) & 0x1ff;
+                }
+            }
+            _ => return Err(invalid_data("reserved E-AC-3 stream type")),
+        }
+
+        let frame_len = (frmsiz as usize + 1) * 2;
+        total_bits += frame_len as u64 * 8;
+        sample_rate.get_or_insert(rate);
+        samples_per_frame.get_or_insert(numblks * 256);
+
+        pos += frame_len;
+    }
+
+    if substreams.is_empty() {
+        return Err(invalid_data("no E-AC-3 independent substream"));
+    }
+
+    // Data rate in kbit/s of the whole access unit
+    let data_rate = total_bits * sample_rate.unwrap() / samples_per_frame.unwrap() / 1000;
+
+    let mut writer = BitWriter::endian(Vec::with_capacity(2 + 3 * substreams.len()), BigEndian);
+    writer.write(13, data_rate.min(0x1fff) as u16)?;
+    writer.write(3, substreams.len() as u8 - 1)?;
+    for substream in &substreams {
+        writer.write(2, substream.fscod)?;
+        writer.write(5, substream.bsid)?;
+        // reserved
+        writer.write_bit(false)?;
+        // asvc
+        writer.write_bit(false)?;
+        // bsmod is only signalled in the optional info metadata, assume complete main
+        writer.write(3, 0u8)?;
+        writer.write(3, substream.acmod)?;
+        writer.write_bit(substream.lfeon)?;
+        // reserved
+        writer.write(3, 0u8)?;
+        writer.write(4, substream.num_dep_sub)?;
+        if substream.num_dep_sub > 0 {
+            writer.write(9, substream.chan_loc)?;
+        } else {
+            // reserved
+            writer.write_bit(false)?;
+        }
+    }
+
+    Ok(writer.into_writer())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_dac3() {
+        // 48kHz, 192kbit/s, bsid 8, bsmod 0, 3/2 with LFE
+        let frame = [0x0b, 0x77, 0x00, 0x00, 0x14, 0x40, 0xe1, 0xa0];
+
+        let dac3 = read_dac3_bytes(&frame).unwrap();
+        // fscod 0, bsid 8, bsmod 0, acmod 7, lfeon 1, bit_rate_code 10
+        assert_eq!(dac3, [0x10, 0x3d, 0x40]);
+    }
+
+    #[test]
+    fn test_read_dec3() {
+        // Independent substream, 48kHz, 6 blocks, 3/2 with LFE, bsid 16, 768 bytes per frame
+        let mut frame = vec![0u8; 768];
+        frame[..6].copy_from_slice(&[0x0b, 0x77, 0x01, 0x7f, 0x3f, 0x80]);
+
+        let dec3 = read_dec3_bytes(&frame).unwrap();
+        // 192kbit/s, one independent substream
+        assert_eq!(dec3, [0x06, 0x00, 0x20, 0x0f, 0x00]);
+    }
+}
@@ -11,6 +11,7 @@ use gst::prelude::*;
 use anyhow::{anyhow, bail, Context, Error};
 use std::convert::TryFrom;
 
+use super::encryption::{SampleEncryption, TrackEncryption};
 use super::{Buffer, ImageOrientation, IDENTITY_MATRIX};
 
 fn write_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
@@ -164,10 +165,22 @@ fn cmaf_brands_from_caps(caps: &gst::CapsRef, compatible_brands: &mut Vec<&'stat
         "audio/x-opus" => {
             compatible_brands.push(b"opus");
         }
+        "audio/x-ac3" | "audio/x-eac3" => {
+            compatible_brands.push(b"ceac");
+        }
+        "audio/x-ac4" => {
+            compatible_brands.push(b"ca4s");
+        }
         "video/x-av1" => {
             compatible_brands.push(b"av01");
             compatible_brands.push(b"cmf2");
         }
+        "application/x-subtitle-vtt" => {
+            compatible_brands.push(b"cwvt");
+        }
+        "application/ttml+xml" => {
+            compatible_brands.push(b"im1t");
+        }
         "video/x-h265" => {
             let width = s.get::<i32>("width").ok();
             let height = s.get::<i32>("height").ok();
@@ -437,6 +450,27 @@ fn write_moov(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     }
     write_box(v, b"mvex", |v| write_mvex(v, cfg))?;
 
+    for pssh in &cfg.protection_system_headers {
+        write_pssh(v, pssh)?;
+    }
+
+    Ok(())
+}
+
+fn write_pssh(v: &mut Vec<u8>, pssh: &gst::Buffer) -> Result<(), Error> {
+    let map = pssh
+        .map_readable()
+        .context("failed to map protection system header")?;
+
+    if map.len() < 8 || &map[4..8] != b"pssh" {
+        bail!("protection system header is not a pssh box");
+    }
+    if u32::from_be_bytes(map[..4].try_into().unwrap()) as usize != map.len() {
+        bail!("protection system header has invalid size");
+    }
+
+    v.extend_from_slice(&map);
+
     Ok(())
 }
 
@@ -571,8 +605,22 @@ fn write_trak(
         |v| write_tkhd(v, cfg, idx, stream, creation_time),
     )?;
 
-    // TODO: write edts if necessary: for audio tracks to remove initialization samples
-    // TODO: write edts optionally for negative DTS instead of offsetting the DTS
+    if cfg.write_edts_btrt {
+        let start = v.len();
+        if let (true, Some(range)) = (cfg.update, stream.presentation_range) {
+            write_box(v, b"edts", |v| {
+                write_full_box(v, b"elst", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
+                    write_elst(v, cfg, stream, range)
+                })
+            })?;
+        }
+
+        // Fill the remaining space that was reserved for the edit list
+        let len = v.len() - start;
+        if len < EDTS_SIZE {
+            write_free(v, EDTS_SIZE - len)?;
+        }
+    }
 
     write_box(v, b"mdia", |v| write_mdia(v, cfg, stream, creation_time))?;
 
@@ -583,6 +631,74 @@ fn write_trak(
     Ok(())
 }
 
+/// Size of the `edts` box with up to two edit list entries.
+const EDTS_SIZE: usize = 8 + 12 + 4 + 2 * 20;
+
+fn write_elst(
+    v: &mut Vec<u8>,
+    cfg: &super::HeaderConfiguration,
+    stream: &super::HeaderStream,
+    range: super::PresentationRange,
+) -> Result<(), Error> {
+    // Edit durations are in movie header timescale, media times in the track timescale
+    let movie_timescale = header_configuration_to_timescale(cfg);
+    let timescale = header_stream_to_timescale(stream);
+
+    let min_start = cfg
+        .streams
+        .iter()
+        .filter_map(|s| s.presentation_range.map(|r| r.start + r.priming))
+        .min()
+        .unwrap();
+    let start = range.start + range.priming;
+
+    let gap = (start - min_start)
+        .nseconds()
+        .mul_div_round(movie_timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("too big gap")?;
+
+    // Entry count
+    if gap > 0 {
+        v.extend(2u32.to_be_bytes());
+
+        // First entry for the gap
+
+        // Edit duration
+        v.extend(gap.to_be_bytes());
+
+        // Media time
+        v.extend((-1i64).to_be_bytes());
+
+        // Media rate
+        v.extend(1u16.to_be_bytes());
+        v.extend(0u16.to_be_bytes());
+    } else {
+        v.extend(1u32.to_be_bytes());
+    }
+
+    // Edit duration
+    let duration = range
+        .end
+        .saturating_sub(start)
+        .nseconds()
+        .mul_div_round(movie_timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("too big track duration")?;
+    v.extend(duration.to_be_bytes());
+
+    // Media time, skipping any samples before the earliest PTS and the priming samples
+    let media_time = start
+        .nseconds()
+        .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("too big media time")?;
+    v.extend(media_time.to_be_bytes());
+
+    // Media rate
+    v.extend(1u16.to_be_bytes());
+    v.extend(0u16.to_be_bytes());
+
+    Ok(())
+}
+
 fn write_tkhd(
     v: &mut Vec<u8>,
     cfg: &super::HeaderConfiguration,
@@ -613,7 +729,9 @@ fn write_tkhd(
     let s = stream.caps.structure(0).unwrap();
     match s.name().as_str() {
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => v.extend((1u16 << 8).to_be_bytes()),
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            v.extend((1u16 << 8).to_be_bytes())
+        }
         _ => v.extend(0u16.to_be_bytes()),
     }
 
@@ -749,8 +867,12 @@ fn write_hdlr(
         "video/x-h264" | "video/x-h265" | "video/x-vp8" | "video/x-vp9" | "video/x-av1"
         | "image/jpeg" => (b"vide", b"VideoHandler\0".as_slice()),
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => (b"soun", b"SoundHandler\0".as_slice()),
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            (b"soun", b"SoundHandler\0".as_slice())
+        }
         "application/x-onvif-metadata" => (b"meta", b"MetadataHandler\0".as_slice()),
+        "application/x-subtitle-vtt" | "text/x-raw" => (b"text", b"TextHandler\0".as_slice()),
+        "application/ttml+xml" => (b"subt", b"SubtitleHandler\0".as_slice()),
         _ => unreachable!(),
     };
 
@@ -780,16 +902,21 @@ fn write_minf(
             write_full_box(v, b"vmhd", FULL_BOX_VERSION_0, 1, |v| write_vmhd(v, cfg))?
         }
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => {
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
             write_full_box(v, b"smhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
                 write_smhd(v, cfg)
             })?
         }
-        "application/x-onvif-metadata" => {
+        "application/x-onvif-metadata" | "application/x-subtitle-vtt" | "text/x-raw" => {
             write_full_box(v, b"nmhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
                 Ok(())
             })?
         }
+        "application/ttml+xml" => {
+            write_full_box(v, b"sthd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
         _ => unreachable!(),
     }
 
@@ -890,8 +1017,13 @@ fn write_stsd(
         "video/x-h264" | "video/x-h265" | "video/x-vp8" | "video/x-vp9" | "video/x-av1"
         | "image/jpeg" => write_visual_sample_entry(v, cfg, stream)?,
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => write_audio_sample_entry(v, cfg, stream)?,
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            write_audio_sample_entry(v, cfg, stream)?
+        }
         "application/x-onvif-metadata" => write_xml_meta_data_sample_entry(v, cfg, stream)?,
+        "application/x-subtitle-vtt" | "application/ttml+xml" | "text/x-raw" => {
+            write_subtitle_sample_entry(v, cfg, stream)?
+        }
         _ => unreachable!(),
     }
 
@@ -916,7 +1048,7 @@ fn write_sample_entry_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
 
 fn write_visual_sample_entry(
     v: &mut Vec<u8>,
-    _cfg: &super::HeaderConfiguration,
+    cfg: &super::HeaderConfiguration,
     stream: &super::HeaderStream,
 ) -> Result<(), Error> {
     let s = stream.caps.structure(0).unwrap();
@@ -944,7 +1076,13 @@ fn write_visual_sample_entry(
         _ => unreachable!(),
     };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    let sample_entry_fourcc = if stream.encryption.is_some() {
+        b"encv"
+    } else {
+        fourcc
+    };
+
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // pre-defined
         v.extend([0u8; 2]);
         // Reserved
@@ -1161,6 +1299,24 @@ fn write_visual_sample_entry(
         }
 
         if let Ok(par) = s.get::<gst::Fraction>("pixel-aspect-ratio") {
+            // Width and height in the caps are already the cropped display size so the clean
+            // aperture is always the whole frame, but QuickTime expects it together with `pasp`.
+            write_box(v, b"clap", move |v| {
+                // Clean aperture width and height
+                v.extend(u32::from(width).to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+                v.extend(u32::from(height).to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+
+                // Horizontal and vertical offset
+                v.extend(0i32.to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+                v.extend(0i32.to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+
+                Ok(())
+            })?;
+
             write_box(v, b"pasp", move |v| {
                 v.extend((par.numer() as u32).to_be_bytes());
                 v.extend((par.denom() as u32).to_be_bytes());
@@ -1215,7 +1371,7 @@ fn write_visual_sample_entry(
                 v.extend(mastering.white_point().x.to_be_bytes());
                 v.extend(mastering.white_point().y.to_be_bytes());
                 v.extend(mastering.max_display_mastering_luminance().to_be_bytes());
-                v.extend(mastering.max_display_mastering_luminance().to_be_bytes());
+                v.extend(mastering.min_display_mastering_luminance().to_be_bytes());
                 Ok(())
             })?;
         }
@@ -1251,7 +1407,13 @@ fn write_visual_sample_entry(
             })?;
         }
 
-        // TODO: write btrt bitrate box based on tags
+        if cfg.write_edts_btrt {
+            write_btrt(v, cfg, stream)?;
+        }
+
+        if let Some(ref encryption) = stream.encryption {
+            write_box(v, b"sinf", |v| write_sinf(v, fourcc, encryption))?;
+        }
 
         Ok(())
     })?;
@@ -1299,7 +1461,7 @@ fn av1_tier(tier: Option<&str>) -> u8 {
 
 fn write_audio_sample_entry(
     v: &mut Vec<u8>,
-    _cfg: &super::HeaderConfiguration,
+    cfg: &super::HeaderConfiguration,
     stream: &super::HeaderStream,
 ) -> Result<(), Error> {
     let s = stream.caps.structure(0).unwrap();
@@ -1309,6 +1471,9 @@ fn write_audio_sample_entry(
         "audio/x-flac" => b"fLaC",
         "audio/x-alaw" => b"alaw",
         "audio/x-mulaw" => b"ulaw",
+        "audio/x-ac3" => b"ac-3",
+        "audio/x-eac3" => b"ec-3",
+        "audio/x-ac4" => b"ac-4",
         "audio/x-adpcm" => {
             let layout = s.get::<&str>("layout").context("no ADPCM layout field")?;
 
@@ -1332,7 +1497,13 @@ fn write_audio_sample_entry(
         _ => 16u16,
     };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    let sample_entry_fourcc = if stream.encryption.is_some() {
+        b"enca"
+    } else {
+        fourcc
+    };
+
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // Reserved
         v.extend([0u8; 2 * 4]);
 
@@ -1366,7 +1537,7 @@ fn write_audio_sample_entry(
                 if map.len() < 2 {
                     bail!("too small codec_data");
                 }
-                write_esds_aac(v, &map)?;
+                write_esds_aac(v, &map, stream.bitrates)?;
             }
             "audio/x-opus" => {
                 write_dops(v, &stream.caps)?;
@@ -1374,6 +1545,33 @@ fn write_audio_sample_entry(
             "audio/x-flac" => {
                 write_dfla(v, &stream.caps)?;
             }
+            "audio/x-ac3" | "audio/x-eac3" => {
+                let extra_data = stream
+                    .extra_header_data
+                    .as_ref()
+                    .context("no AC-3 bit stream information")?;
+                let fourcc = if s.name() == "audio/x-ac3" {
+                    b"dac3"
+                } else {
+                    b"dec3"
+                };
+                write_box(v, fourcc, move |v| {
+                    v.extend_from_slice(extra_data);
+                    Ok(())
+                })?;
+            }
+            "audio/x-ac4" => {
+                let codec_data = s
+                    .get::<&gst::BufferRef>("codec_data")
+                    .context("no codec_data")?;
+                let map = codec_data
+                    .map_readable()
+                    .context("codec_data not mappable")?;
+                write_box(v, b"dac4", move |v| {
+                    v.extend_from_slice(&map);
+                    Ok(())
+                })?;
+            }
             "audio/x-alaw" | "audio/x-mulaw" | "audio/x-adpcm" => {
                 // Nothing to do here
             }
@@ -1396,17 +1594,113 @@ fn write_audio_sample_entry(
             )?;
         }
 
-        // TODO: write btrt bitrate box based on tags
+        if cfg.write_edts_btrt {
+            write_btrt(v, cfg, stream)?;
+        }
 
         // TODO: chnl box for channel ordering? probably not needed for AAC
 
+        if let Some(ref encryption) = stream.encryption {
+            write_box(v, b"sinf", |v| write_sinf(v, fourcc, encryption))?;
+        }
+
         Ok(())
     })?;
 
     Ok(())
 }
 
-fn write_esds_aac(v: &mut Vec<u8>, codec_data: &[u8]) -> Result<(), Error> {
+/// Size of the `btrt` box.
+const BTRT_SIZE: usize = 8 + 3 * 4;
+
+/// Writes the `btrt` box at the end of the stream, or reserves space for it before.
+fn write_btrt(
+    v: &mut Vec<u8>,
+    cfg: &super::HeaderConfiguration,
+    stream: &super::HeaderStream,
+) -> Result<(), Error> {
+    match stream.bitrates {
+        Some(bitrates) if cfg.update => {
+            write_box(v, b"btrt", |v| {
+                v.extend(bitrates.buffer_size.to_be_bytes());
+                v.extend(bitrates.max_bitrate.to_be_bytes());
+                v.extend(bitrates.avg_bitrate.to_be_bytes());
+
+                Ok(())
+            })?;
+        }
+        _ => write_free(v, BTRT_SIZE)?,
+    }
+
+    Ok(())
+}
+
+fn write_free(v: &mut Vec<u8>, size: usize) -> Result<(), Error> {
+    assert!(size >= 8);
+
+    write_box(v, b"free", |v| {
+        v.resize(v.len() + size - 8, 0);
+        Ok(())
+    })
+}
+
+fn write_sinf(
+    v: &mut Vec<u8>,
+    original_format: &[u8; 4],
+    encryption: &TrackEncryption,
+) -> Result<(), Error> {
+    write_box(v, b"frma", |v| {
+        v.extend(original_format);
+        Ok(())
+    })?;
+
+    write_full_box(v, b"schm", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        // Scheme type
+        v.extend(encryption.scheme_type());
+        // Scheme version 1.0
+        v.extend(0x0001_0000u32.to_be_bytes());
+        Ok(())
+    })?;
+
+    write_box(v, b"schi", |v| {
+        // Version 1 is needed for signalling the pattern
+        let version = if encryption.scheme == super::EncryptionScheme::Cbcs {
+            FULL_BOX_VERSION_1
+        } else {
+            FULL_BOX_VERSION_0
+        };
+
+        write_full_box(v, b"tenc", version, FULL_BOX_FLAGS_NONE, |v| {
+            // Reserved
+            v.push(0);
+            if version == FULL_BOX_VERSION_0 {
+                // Reserved
+                v.push(0);
+            } else {
+                v.push((encryption.crypt_byte_block << 4) | (encryption.skip_byte_block & 0x0f));
+            }
+            // Default is protected
+            v.push(1);
+            // Default per-sample IV size
+            v.push(encryption.per_sample_iv_size);
+            // Default KID
+            v.extend(encryption.key_id);
+            if encryption.per_sample_iv_size == 0 {
+                let constant_iv = encryption.constant_iv.as_ref().unwrap();
+                v.push(constant_iv.len() as u8);
+                v.extend(constant_iv);
+            }
+
+            Ok(())
+        })
+    })
+}
+
+fn write_esds_aac(
+    v: &mut Vec<u8>,
+    codec_data: &[u8],
+    bitrates: Option<super::Bitrates>,
+) -> Result<(), Error> {
     let calculate_len = |mut len| {
         if len > 260144641 {
             bail!("too big descriptor length");
@@ -1480,14 +1774,15 @@ fn write_esds_aac(v: &mut Vec<u8>, codec_data: &[u8]) -> Result<(), Error> {
             // Stream type ESDS_STREAM_TYPE_AUDIO
             v.push((0x05 << 2) | 0x01);
 
-            // Buffer size db?
-            v.extend([0u8; 3]);
+            // Buffer size db
+            let buffer_size = bitrates.map_or(0, |b| b.buffer_size.min(0xff_ff_ff));
+            v.extend(&buffer_size.to_be_bytes()[1..]);
 
             // Max bitrate
-            v.extend(0u32.to_be_bytes());
+            v.extend(bitrates.map_or(0, |b| b.max_bitrate).to_be_bytes());
 
             // Avg bitrate
-            v.extend(0u32.to_be_bytes());
+            v.extend(bitrates.map_or(0, |b| b.avg_bitrate).to_be_bytes());
 
             // Decoder specific info
             v.push(0x05);
@@ -1625,6 +1920,187 @@ fn write_xml_meta_data_sample_entry(
     Ok(())
 }
 
+fn write_subtitle_sample_entry(
+    v: &mut Vec<u8>,
+    _cfg: &super::HeaderConfiguration,
+    stream: &super::HeaderStream,
+) -> Result<(), Error> {
+    let s = stream.caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => write_sample_entry_box(v, b"wvtt", |v| {
+            write_box(v, b"vttC", |v| {
+                // WebVTT file header without any further header blocks
+                v.extend(b"WEBVTT");
+
+                Ok(())
+            })
+        })?,
+        "application/ttml+xml" => write_sample_entry_box(v, b"stpp", |v| {
+            // namespace
+            v.extend(b"http://www.w3.org/ns/ttml");
+            v.push(0);
+
+            // schema_location, empty string list
+            v.push(0);
+
+            // auxiliary_mime_types, empty string list
+            v.push(0);
+
+            Ok(())
+        })?,
+        "text/x-raw" => write_sample_entry_box(v, b"tx3g", |v| {
+            // Display flags
+            v.extend(0u32.to_be_bytes());
+
+            // Horizontal justification: centered
+            v.push(1);
+            // Vertical justification: bottom
+            v.push(0xff);
+
+            // Background color, transparent
+            v.extend([0u8; 4]);
+
+            // Default text box: top, left, bottom, right
+            v.extend([0u8; 4 * 2]);
+
+            // Default style
+            // Start and end character offset
+            v.extend(0u16.to_be_bytes());
+            v.extend(0u16.to_be_bytes());
+            // Font ID
+            v.extend(1u16.to_be_bytes());
+            // Face style flags
+            v.push(0);
+            // Font size
+            v.push(18);
+            // Text color, opaque white
+            v.extend([0xffu8; 4]);
+
+            write_box(v, b"ftab", |v| {
+                // Entry count
+                v.extend(1u16.to_be_bytes());
+
+                // Font ID
+                v.extend(1u16.to_be_bytes());
+                // Font name
+                let font_name = b"Sans-Serif";
+                v.push(font_name.len() as u8);
+                v.extend(font_name);
+
+                Ok(())
+            })
+        })?,
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Converts a subtitle buffer into the sample format of the corresponding sample entry.
+///
+/// Returns `None` if the buffer does not contain a sample, e.g. WebVTT header blocks.
+pub(super) fn subtitle_sample(caps: &gst::CapsRef, data: &[u8]) -> Option<Vec<u8>> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => webvtt_cue_sample(data),
+        "application/ttml+xml" => Some(data.to_vec()),
+        "text/x-raw" => {
+            let text = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+            let len = u16::try_from(text.len()).ok()?;
+
+            let mut v = Vec::with_capacity(2 + text.len());
+            v.extend(len.to_be_bytes());
+            v.extend(text.as_bytes());
+
+            Some(v)
+        }
+        _ => unreachable!(),
+    }
+}
+
+const TTML_EMPTY_DOCUMENT: &[u8] =
+    b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><tt xmlns=\"http://www.w3.org/ns/ttml\"/>";
+
+/// Creates an empty sample for filling gaps between subtitles.
+pub(super) fn subtitle_gap_sample(caps: &gst::CapsRef) -> Vec<u8> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => {
+            let mut v = vec![];
+            write_box(&mut v, b"vtte", |_v| Ok(())).unwrap();
+            v
+        }
+        "application/ttml+xml" => TTML_EMPTY_DOCUMENT.to_vec(),
+        "text/x-raw" => 0u16.to_be_bytes().to_vec(),
+        _ => unreachable!(),
+    }
+}
+
+/// Converts a WebVTT cue block into a `vttc` box.
+///
+/// The timing line is optional as the timing is already part of the buffer.
+fn webvtt_cue_sample(data: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(data).ok()?;
+    let text = text
+        .trim_start_matches('\u{feff}')
+        .trim_matches(['\r', '\n']);
+
+    let mut id = None;
+    let mut settings = None;
+    let mut lines = text.lines().peekable();
+
+    // Header and comment blocks are not cues
+    let first_line = *lines.peek()?;
+    if first_line.starts_with("WEBVTT")
+        || first_line.starts_with("NOTE")
+        || first_line.starts_with("STYLE")
+        || first_line.starts_with("REGION")
+    {
+        return None;
+    }
+
+    // Optional cue identifier followed by the optional timing line
+    if !first_line.contains("-->") && lines.clone().nth(1).is_some_and(|l| l.contains("-->")) {
+        id = lines.next();
+    }
+    if let Some(timing) = lines.next_if(|l| l.contains("-->")) {
+        let (_, rest) = timing.split_once("-->").unwrap();
+        let rest = rest.trim_start();
+        settings = rest
+            .split_once(char::is_whitespace)
+            .map(|(_, settings)| settings.trim())
+            .filter(|settings| !settings.is_empty());
+    }
+
+    let payload = lines.collect::<Vec<_>>().join("\n");
+    if payload.is_empty() {
+        return None;
+    }
+
+    let mut v = vec![];
+    write_box(&mut v, b"vttc", |v| {
+        if let Some(id) = id {
+            write_box(v, b"iden", |v| {
+                v.extend(id.as_bytes());
+                Ok(())
+            })?;
+        }
+        if let Some(settings) = settings {
+            write_box(v, b"sttg", |v| {
+                v.extend(settings.as_bytes());
+                Ok(())
+            })?;
+        }
+        write_box(v, b"payl", |v| {
+            v.extend(payload.as_bytes());
+            Ok(())
+        })
+    })
+    .ok()?;
+
+    Some(v)
+}
+
 fn write_stts(v: &mut Vec<u8>, _cfg: &super::HeaderConfiguration) -> Result<(), Error> {
     // Entry count
     v.extend(0u32.to_be_bytes());
@@ -1751,9 +2227,9 @@ pub(super) fn create_fmp4_fragment_header(
         })?;
     }
 
-    let styp_len = v.len();
-
-    let data_offset_offsets = write_box(&mut v, b"moof", |v| write_moof(v, &cfg))?;
+    // The `moof` is written separately first as the `sidx` needs to know its size.
+    let mut moof = vec![];
+    let data_offset_offsets = write_box(&mut moof, b"moof", |v| write_moof(v, &cfg, 0))?;
 
     let size = cfg
         .buffers
@@ -1761,28 +2237,221 @@ pub(super) fn create_fmp4_fragment_header(
         .map(|buffer| buffer.buffer.size() as u64)
         .sum::<u64>();
     if let Ok(size) = u32::try_from(size + 8) {
-        v.extend(size.to_be_bytes());
-        v.extend(b"mdat");
+        moof.extend(size.to_be_bytes());
+        moof.extend(b"mdat");
     } else {
-        v.extend(1u32.to_be_bytes());
-        v.extend(b"mdat");
-        v.extend((size + 16).to_be_bytes());
+        moof.extend(1u32.to_be_bytes());
+        moof.extend(b"mdat");
+        moof.extend((size + 16).to_be_bytes());
     }
 
-    let data_offset = v.len() - styp_len;
+    let data_offset = moof.len();
     for data_offset_offset in data_offset_offsets {
-        let val = u32::from_be_bytes(v[data_offset_offset..][..4].try_into()?)
+        let val = u32::from_be_bytes(moof[data_offset_offset..][..4].try_into()?)
             .checked_add(u32::try_from(data_offset)?)
             .ok_or_else(|| anyhow!("can't calculate track run data offset"))?;
-        v[data_offset_offset..][..4].copy_from_slice(&val.to_be_bytes());
+        moof[data_offset_offset..][..4].copy_from_slice(&val.to_be_bytes());
+    }
+
+    let mut prft = vec![];
+    if let Some(ntp_time) = cfg.producer_reference_time {
+        write_full_box(
+            &mut prft,
+            b"prft",
+            FULL_BOX_VERSION_1,
+            FULL_BOX_FLAGS_NONE,
+            |v| write_prft(v, &cfg, ntp_time),
+        )?;
+    }
+
+    if let Some(earliest_pts) = cfg.sidx_earliest_pts {
+        write_sidx(
+            &mut v,
+            fragment_header_stream_to_timescale(&cfg.streams[0]),
+            earliest_pts,
+            prft.len() as u64,
+            &[super::SidxReference {
+                size: moof.len() as u64 + size,
+                duration: cfg.duration,
+                starts_with_sap: !cfg.chunk,
+            }],
+        )?;
+    }
+
+    v.extend(prft);
+
+    let moof_offset = v.len();
+    v.extend(moof);
+
+    Ok((gst::Buffer::from_mut_slice(v), moof_offset as u64))
+}
+
+fn write_prft(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    ntp_time: u64,
+) -> Result<(), Error> {
+    let stream = &cfg.streams[0];
+    let timescale = fragment_header_stream_to_timescale(stream);
+
+    // Reference track ID
+    v.extend(1u32.to_be_bytes());
+
+    // NTP timestamp
+    v.extend(ntp_time.to_be_bytes());
+
+    // Media time
+    let media_time = stream
+        .start_time
+        .unwrap()
+        .nseconds()
+        .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("media time overflow")?;
+    v.extend(media_time.to_be_bytes());
+
+    Ok(())
+}
+
+/// Size of a `sidx` box with the given number of references.
+fn sidx_size(num_references: usize) -> usize {
+    // Box header, full box header, fixed fields and 12 bytes per reference
+    8 + 4 + 28 + 12 * num_references
+}
+
+fn write_sidx(
+    v: &mut Vec<u8>,
+    timescale: u32,
+    earliest_pts: gst::ClockTime,
+    first_offset: u64,
+    references: &[super::SidxReference],
+) -> Result<(), Error> {
+    write_full_box(v, b"sidx", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
+        // Reference ID
+        v.extend(1u32.to_be_bytes());
+
+        // Timescale
+        v.extend(timescale.to_be_bytes());
+
+        // Earliest presentation time
+        let earliest_presentation_time = earliest_pts
+            .nseconds()
+            .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+            .context("earliest presentation time overflow")?;
+        v.extend(earliest_presentation_time.to_be_bytes());
+
+        // First offset
+        v.extend(first_offset.to_be_bytes());
+
+        // Reserved
+        v.extend(0u16.to_be_bytes());
+
+        // Reference count
+        v.extend(
+            u16::try_from(references.len())
+                .context("too many sidx references")?
+                .to_be_bytes(),
+        );
+
+        // Convert the end times instead of the durations to avoid accumulating rounding errors
+        let mut end_time = earliest_pts;
+        let mut end_time_scaled = earliest_presentation_time;
+        for reference in references {
+            // Reference type 0 (media) and referenced size
+            let size = u32::try_from(reference.size)
+                .ok()
+                .filter(|size| size >> 31 == 0)
+                .context("too big sidx reference")?;
+            v.extend(size.to_be_bytes());
+
+            // Subsegment duration
+            end_time += reference.duration;
+            let end = end_time
+                .nseconds()
+                .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+                .context("subsegment duration overflow")?;
+            let duration =
+                u32::try_from(end - end_time_scaled).context("too long sidx reference")?;
+            end_time_scaled = end;
+            v.extend(duration.to_be_bytes());
+
+            // Starts with SAP, SAP type and SAP delta time
+            //
+            // Fragments always start with a keyframe, which is assumed to be a SAP of type 1.
+            let sap = if reference.starts_with_sap {
+                (1u32 << 31) | (1u32 << 28)
+            } else {
+                0
+            };
+            v.extend(sap.to_be_bytes());
+        }
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Creates a `free` box that reserves space for a `sidx` box with up to `num_references`
+/// references.
+pub(super) fn create_sidx_placeholder(num_references: usize) -> Result<gst::Buffer, Error> {
+    let mut v = vec![];
+
+    write_box(&mut v, b"free", |v| {
+        v.resize(v.len() + sidx_size(num_references) - 8, 0);
+        Ok(())
+    })?;
+
+    Ok(gst::Buffer::from_mut_slice(v))
+}
+
+/// Creates a `sidx` box for all fragments of the stream that fills the space reserved by
+/// `create_sidx_placeholder()`.
+pub(super) fn create_sidx(
+    caps: &gst::CapsRef,
+    trak_timescale: u32,
+    earliest_pts: gst::ClockTime,
+    references: &[super::SidxReference],
+    num_reserved_references: usize,
+) -> Result<gst::Buffer, Error> {
+    if references.len() > num_reserved_references {
+        bail!(
+            "{} fragments but only space for {} sidx references",
+            references.len(),
+            num_reserved_references
+        );
+    }
+
+    let timescale = if trak_timescale > 0 {
+        trak_timescale
+    } else {
+        caps_to_timescale(caps)
+    };
+
+    // Remaining reserved space is filled with a `free` box after the `sidx`
+    let free_size = sidx_size(num_reserved_references) - sidx_size(references.len());
+
+    let mut v = vec![];
+    write_sidx(
+        &mut v,
+        timescale,
+        earliest_pts,
+        free_size as u64,
+        references,
+    )?;
+    if free_size > 0 {
+        write_box(&mut v, b"free", |v| {
+            v.resize(v.len() + free_size - 8, 0);
+            Ok(())
+        })?;
     }
 
-    Ok((gst::Buffer::from_mut_slice(v), styp_len as u64))
+    Ok(gst::Buffer::from_mut_slice(v))
 }
 
 fn write_moof(
     v: &mut Vec<u8>,
     cfg: &super::FragmentHeaderConfiguration,
+    moof_start: usize,
 ) -> Result<Vec<usize>, Error> {
     write_full_box(v, b"mfhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
         write_mfhd(v, cfg)
@@ -1796,7 +2465,7 @@ fn write_moof(
         }
 
         write_box(v, b"traf", |v| {
-            write_traf(v, cfg, &mut data_offset_offsets, idx, stream)
+            write_traf(v, cfg, &mut data_offset_offsets, moof_start, idx, stream)
         })?;
     }
 
@@ -1994,6 +2663,7 @@ fn write_traf(
     v: &mut Vec<u8>,
     cfg: &super::FragmentHeaderConfiguration,
     data_offset_offsets: &mut Vec<usize>,
+    moof_start: usize,
     idx: usize,
     stream: &super::FragmentHeaderStream,
 ) -> Result<(), Error> {
@@ -2064,11 +2734,101 @@ fn write_traf(
         tr_flags &= !FIRST_SAMPLE_FLAGS_PRESENT;
     }
 
-    // TODO: saio, saiz, sbgp, sgpd, subs?
+    let sample_encryptions = cfg
+        .buffers
+        .iter()
+        .filter(|b| b.idx == idx)
+        .map(|b| b.sample_encryption.as_ref())
+        .collect::<Option<Vec<_>>>();
+    if let Some(sample_encryptions) = sample_encryptions {
+        write_sample_encryption(v, moof_start, &sample_encryptions)?;
+    }
+
+    // TODO: sbgp, sgpd, subs?
 
     Ok(())
 }
 
+const SENC_USE_SUBSAMPLE_ENCRYPTION: u32 = 0x2;
+
+/// Writes `saiz`, `saio` and `senc` boxes with the sample auxiliary information of all samples
+/// of the track fragment.
+fn write_sample_encryption(
+    v: &mut Vec<u8>,
+    moof_start: usize,
+    samples: &[&SampleEncryption],
+) -> Result<(), Error> {
+    let with_subsamples = samples.iter().any(|s| !s.subsamples.is_empty());
+    let sample_count = u32::try_from(samples.len()).context("too many samples")?;
+
+    let info_sizes = samples
+        .iter()
+        .map(|s| u8::try_from(s.info_size(with_subsamples)))
+        .collect::<Result<Vec<_>, _>>()
+        .context("too many subsamples")?;
+
+    write_full_box(v, b"saiz", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        // Default sample info size, or 0 if all samples have their own size
+        if info_sizes.iter().all(|size| *size == info_sizes[0]) {
+            v.push(info_sizes[0]);
+            v.extend(sample_count.to_be_bytes());
+        } else {
+            v.push(0);
+            v.extend(sample_count.to_be_bytes());
+            v.extend(&info_sizes);
+        }
+
+        Ok(())
+    })?;
+
+    let saio_offset_offset =
+        write_full_box(v, b"saio", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+            // Entry count
+            v.extend(1u32.to_be_bytes());
+
+            // Offset relative to the start of the moof, filled in below
+            let offset = v.len();
+            v.extend(0u32.to_be_bytes());
+
+            Ok(offset)
+        })?;
+
+    write_full_box(
+        v,
+        b"senc",
+        FULL_BOX_VERSION_0,
+        if with_subsamples {
+            SENC_USE_SUBSAMPLE_ENCRYPTION
+        } else {
+            FULL_BOX_FLAGS_NONE
+        },
+        |v| {
+            v.extend(sample_count.to_be_bytes());
+
+            let aux_info_offset = u32::try_from(v.len() - moof_start)
+                .context("too big sample auxiliary information offset")?;
+            v[saio_offset_offset..][..4].copy_from_slice(&aux_info_offset.to_be_bytes());
+
+            for sample in samples {
+                v.extend(&sample.iv);
+
+                if with_subsamples {
+                    let subsample_count =
+                        u16::try_from(sample.subsamples.len()).context("too many subsamples")?;
+                    v.extend(subsample_count.to_be_bytes());
+
+                    for subsample in &sample.subsamples {
+                        v.extend(subsample.clear.to_be_bytes());
+                        v.extend(subsample.protected.to_be_bytes());
+                    }
+                }
+            }
+
+            Ok(())
+        },
+    )
+}
+
 fn write_tfhd(
     v: &mut Vec<u8>,
     _cfg: &super::FragmentHeaderConfiguration,
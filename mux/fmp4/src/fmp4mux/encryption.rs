@@ -0,0 +1,399 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Common Encryption (ISO/IEC 23001-7) sample encryption for the `cenc` and `cbcs` schemes.
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use anyhow::{anyhow, bail, Context, Error};
+
+use super::slice_header::{NalFormat, ParameterSets};
+use super::EncryptionScheme;
+
+/// Pattern for `cbcs` video tracks: encrypt 1 block, skip 9 blocks.
+const CBCS_VIDEO_PATTERN: (u8, u8) = (1, 9);
+
+/// Per-track encryption parameters as signalled in the `tenc` box.
+#[derive(Debug, Clone)]
+pub(crate) struct TrackEncryption {
+    pub(crate) scheme: EncryptionScheme,
+    pub(crate) key_id: [u8; 16],
+    /// Size of the per-sample IVs in the `senc` box, 0 if a constant IV is used.
+    pub(crate) per_sample_iv_size: u8,
+    pub(crate) constant_iv: Option<[u8; 16]>,
+    pub(crate) crypt_byte_block: u8,
+    pub(crate) skip_byte_block: u8,
+}
+
+impl TrackEncryption {
+    pub(crate) fn scheme_type(&self) -> &'static [u8; 4] {
+        match self.scheme {
+            EncryptionScheme::Cenc => b"cenc",
+            EncryptionScheme::Cbcs => b"cbcs",
+            EncryptionScheme::None => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Subsample {
+    pub(crate) clear: u16,
+    pub(crate) protected: u32,
+}
+
+/// Auxiliary information of a single encrypted sample as stored in the `senc` box.
+#[derive(Debug, Clone)]
+pub(crate) struct SampleEncryption {
+    /// Per-sample IV, empty if a constant IV is used.
+    pub(crate) iv: Vec<u8>,
+    /// Subsamples, empty if the whole sample is encrypted.
+    pub(crate) subsamples: Vec<Subsample>,
+}
+
+impl SampleEncryption {
+    /// Size of the auxiliary information of this sample.
+    pub(crate) fn info_size(&self, with_subsamples: bool) -> usize {
+        if with_subsamples {
+            self.iv.len() + 2 + 6 * self.subsamples.len()
+        } else {
+            self.iv.len()
+        }
+    }
+}
+
+pub(crate) struct Encryptor {
+    cipher: aes::Aes128,
+    track: TrackEncryption,
+    nal_format: Option<(NalFormat, usize)>,
+    /// SPS/PPS for parsing the slice headers, which have to stay in the clear.
+    parameter_sets: ParameterSets,
+    next_iv: u64,
+}
+
+pub(crate) fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let s = s.trim();
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.len() % 2 != 0 {
+        bail!("Odd number of hex digits");
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex digits at position {i}"))
+        })
+        .collect()
+}
+
+fn parse_hex_16(s: &str) -> Result<[u8; 16], Error> {
+    parse_hex(s)?
+        .try_into()
+        .map_err(|_| anyhow!("Expected 16 bytes"))
+}
+
+impl Encryptor {
+    pub(crate) fn new(
+        scheme: EncryptionScheme,
+        key_id: Option<&str>,
+        key: Option<&str>,
+        iv: Option<&str>,
+        caps: &gst::CapsRef,
+    ) -> Result<Self, Error> {
+        let key_id = parse_hex_16(key_id.ok_or_else(|| anyhow!("No key ID configured"))?)
+            .context("Invalid key ID")?;
+        let key = parse_hex_16(key.ok_or_else(|| anyhow!("No key configured"))?)
+            .context("Invalid key")?;
+        let iv = iv.map(parse_hex).transpose().context("Invalid IV")?;
+
+        let mut parameter_sets = ParameterSets::default();
+        let nal_format = nal_format_from_caps(caps, &mut parameter_sets)?;
+
+        let (track, next_iv) = match scheme {
+            EncryptionScheme::None => unreachable!(),
+            EncryptionScheme::Cenc => {
+                let iv = match iv {
+                    Some(iv) => u64::from_be_bytes(
+                        iv.try_into()
+                            .map_err(|_| anyhow!("IV must be 8 bytes for cenc"))?,
+                    ),
+                    None => rand::random(),
+                };
+
+                (
+                    TrackEncryption {
+                        scheme,
+                        key_id,
+                        per_sample_iv_size: 8,
+                        constant_iv: None,
+                        crypt_byte_block: 0,
+                        skip_byte_block: 0,
+                    },
+                    iv,
+                )
+            }
+            EncryptionScheme::Cbcs => {
+                let iv = match iv {
+                    Some(iv) => iv
+                        .try_into()
+                        .map_err(|_| anyhow!("IV must be 16 bytes for cbcs"))?,
+                    None => rand::random(),
+                };
+
+                // Audio is encrypted as a whole without pattern
+                let (crypt_byte_block, skip_byte_block) = if nal_format.is_some() {
+                    CBCS_VIDEO_PATTERN
+                } else {
+                    (0, 0)
+                };
+
+                (
+                    TrackEncryption {
+                        scheme,
+                        key_id,
+                        per_sample_iv_size: 0,
+                        constant_iv: Some(iv),
+                        crypt_byte_block,
+                        skip_byte_block,
+                    },
+                    0,
+                )
+            }
+        };
+
+        Ok(Encryptor {
+            cipher: aes::Aes128::new(&key.into()),
+            track,
+            nal_format,
+            parameter_sets,
+            next_iv,
+        })
+    }
+
+    /// Updates the NAL unit format after a caps change.
+    pub(crate) fn update_caps(&mut self, caps: &gst::CapsRef) -> Result<(), Error> {
+        let nal_format = nal_format_from_caps(caps, &mut self.parameter_sets)?;
+        if nal_format.is_some() != self.nal_format.is_some() {
+            bail!("Can't change between NAL-based and other formats");
+        }
+        self.nal_format = nal_format;
+
+        Ok(())
+    }
+
+    pub(crate) fn track_encryption(&self) -> &TrackEncryption {
+        &self.track
+    }
+
+    /// Encrypts the sample in place and returns its auxiliary information.
+    pub(crate) fn encrypt(&mut self, data: &mut [u8]) -> Result<SampleEncryption, Error> {
+        let subsamples = match self.nal_format {
+            Some((format, length_size)) => subsamples(
+                format,
+                length_size,
+                data,
+                self.track.scheme,
+                &mut self.parameter_sets,
+            )?,
+            None => vec![],
+        };
+
+        // Without subsamples the whole sample is protected
+        let whole = [0..data.len()];
+        let mut ranges = Vec::with_capacity(subsamples.len());
+        let mut offset = 0;
+        for subsample in &subsamples {
+            offset += subsample.clear as usize;
+            ranges.push(offset..offset + subsample.protected as usize);
+            offset += subsample.protected as usize;
+        }
+        let ranges = if subsamples.is_empty() {
+            &whole[..]
+        } else {
+            &ranges[..]
+        };
+
+        match self.track.scheme {
+            EncryptionScheme::None => unreachable!(),
+            EncryptionScheme::Cenc => {
+                let iv = self.next_iv;
+                self.next_iv = self.next_iv.wrapping_add(1);
+
+                // The keystream continues over all protected ranges of a sample
+                let mut counter = 0u64;
+                let mut keystream = [0u8; 16];
+                let mut keystream_pos = 16;
+                for range in ranges {
+                    for b in &mut data[range.clone()] {
+                        if keystream_pos == 16 {
+                            let mut block = [0u8; 16];
+                            block[..8].copy_from_slice(&iv.to_be_bytes());
+                            block[8..].copy_from_slice(&counter.to_be_bytes());
+                            let mut block = GenericArray::from(block);
+                            self.cipher.encrypt_block(&mut block);
+                            keystream.copy_from_slice(&block);
+                            keystream_pos = 0;
+                            counter = counter.wrapping_add(1);
+                        }
+                        *b ^= keystream[keystream_pos];
+                        keystream_pos += 1;
+                    }
+                }
+
+                Ok(SampleEncryption {
+                    iv: iv.to_be_bytes().to_vec(),
+                    subsamples,
+                })
+            }
+            EncryptionScheme::Cbcs => {
+                let iv = self.track.constant_iv.unwrap();
+                let crypt = self.track.crypt_byte_block as usize;
+                let skip = self.track.skip_byte_block as usize;
+
+                // Each subsample is a separate chain starting with the constant IV. Trailing
+                // partial blocks stay in the clear.
+                for range in ranges {
+                    let mut chain = iv;
+                    for (idx, block) in data[range.clone()].chunks_exact_mut(16).enumerate() {
+                        if crypt != 0 && idx % (crypt + skip) >= crypt {
+                            continue;
+                        }
+                        for (b, c) in block.iter_mut().zip(chain.iter()) {
+                            *b ^= *c;
+                        }
+                        self.cipher
+                            .encrypt_block(GenericArray::from_mut_slice(block));
+                        chain.copy_from_slice(block);
+                    }
+                }
+
+                Ok(SampleEncryption {
+                    iv: vec![],
+                    subsamples,
+                })
+            }
+        }
+    }
+}
+
+/// Returns the NAL unit format and length size of the caps, and collects the parameter sets of
+/// the codec data.
+fn nal_format_from_caps(
+    caps: &gst::CapsRef,
+    parameter_sets: &mut ParameterSets,
+) -> Result<Option<(NalFormat, usize)>, Error> {
+    let s = caps.structure(0).unwrap();
+    let nal_format = match s.name().as_str() {
+        "video/x-h264" | "video/x-h265" => {
+            let format = if s.name() == "video/x-h264" {
+                NalFormat::H264
+            } else {
+                NalFormat::H265
+            };
+
+            let codec_data = s
+                .get::<&gst::BufferRef>("codec_data")
+                .context("Codec data required")?;
+            let map = codec_data
+                .map_readable()
+                .context("Failed to map codec data")?;
+            let length_size = match format {
+                NalFormat::H264 if map.len() > 4 => (map[4] & 0x03) as usize + 1,
+                NalFormat::H265 if map.len() > 21 => (map[21] & 0x03) as usize + 1,
+                _ => bail!("Too short codec data"),
+            };
+            if length_size == 3 {
+                bail!("Unsupported NAL unit length size {length_size}");
+            }
+            parameter_sets
+                .parse_codec_data(format, &map)
+                .context("Failed to parse codec data")?;
+
+            Some((format, length_size))
+        }
+        "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => None,
+        name => bail!("Encryption of {name} not supported"),
+    };
+
+    Ok(nal_format)
+}
+
+/// Splits a length-prefixed NAL unit sample into clear and protected ranges.
+///
+/// The NAL unit header and slice header of slices stay in the clear, everything else of them
+/// is protected. Other NAL units stay in the clear completely, and parameter sets among them
+/// are collected for parsing the following slice headers.
+fn subsamples(
+    format: NalFormat,
+    length_size: usize,
+    data: &[u8],
+    scheme: EncryptionScheme,
+    parameter_sets: &mut ParameterSets,
+) -> Result<Vec<Subsample>, Error> {
+    let mut subsamples = Vec::new();
+    let mut clear = 0usize;
+    let mut offset = 0;
+
+    while offset < data.len() {
+        if offset + length_size > data.len() {
+            bail!("Truncated NAL unit length");
+        }
+        let nal_size = data[offset..][..length_size]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        let nal_start = offset + length_size;
+        if nal_size == 0 || nal_start + nal_size > data.len() {
+            bail!("Invalid NAL unit size {nal_size}");
+        }
+        offset = nal_start + nal_size;
+
+        let slice_header_size = parameter_sets
+            .parse_nal(format, &data[nal_start..offset])
+            .context("Failed to parse NAL unit")?;
+        let Some(slice_header_size) = slice_header_size.filter(|size| *size < nal_size) else {
+            clear += length_size + nal_size;
+            continue;
+        };
+
+        let mut nal_clear = slice_header_size;
+        let mut protected = nal_size - nal_clear;
+        // cenc requires the protected part to be a multiple of the block size
+        if scheme == EncryptionScheme::Cenc {
+            nal_clear += protected % 16;
+            protected -= protected % 16;
+            if protected == 0 {
+                clear += length_size + nal_size;
+                continue;
+            }
+        }
+        clear += length_size + nal_clear;
+
+        while clear > u16::MAX as usize {
+            subsamples.push(Subsample {
+                clear: u16::MAX,
+                protected: 0,
+            });
+            clear -= u16::MAX as usize;
+        }
+        subsamples.push(Subsample {
+            clear: clear as u16,
+            protected: protected as u32,
+        });
+        clear = 0;
+    }
+
+    while clear > 0 {
+        let c = clear.min(u16::MAX as usize);
+        subsamples.push(Subsample {
+            clear: c as u16,
+            protected: 0,
+        });
+        clear -= c;
+    }
+
+    Ok(subsamples)
+}
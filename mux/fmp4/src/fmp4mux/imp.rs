@@ -18,11 +18,13 @@ use std::mem;
 use std::ops::Bound::Excluded;
 use std::sync::Mutex;
 
+use crate::fmp4mux::ac3::{read_dac3_bytes, read_dec3_bytes};
 use crate::fmp4mux::obu::read_seq_header_obu_bytes;
 use crate::fmp4mux::ImageOrientation;
 use once_cell::sync::Lazy;
 
 use super::boxes;
+use super::encryption::Encryptor;
 use super::Buffer;
 use super::DeltaFrames;
 
@@ -103,8 +105,12 @@ const DEFAULT_CHUNK_DURATION: Option<gst::ClockTime> = gst::ClockTime::NONE;
 const DEFAULT_HEADER_UPDATE_MODE: super::HeaderUpdateMode = super::HeaderUpdateMode::None;
 const DEFAULT_WRITE_MFRA: bool = false;
 const DEFAULT_WRITE_MEHD: bool = false;
+const DEFAULT_WRITE_EDTS_BTRT: bool = true;
 const DEFAULT_INTERLEAVE_BYTES: Option<u64> = None;
 const DEFAULT_INTERLEAVE_TIME: Option<gst::ClockTime> = Some(gst::ClockTime::from_mseconds(250));
+const DEFAULT_WRITE_PRFT: bool = false;
+const DEFAULT_SIDX_MODE: super::SidxMode = super::SidxMode::None;
+const DEFAULT_SIDX_RESERVED_REFERENCES: u32 = 1024;
 
 #[derive(Debug, Clone)]
 struct Settings {
@@ -113,10 +119,14 @@ struct Settings {
     header_update_mode: super::HeaderUpdateMode,
     write_mfra: bool,
     write_mehd: bool,
+    write_edts_btrt: bool,
     interleave_bytes: Option<u64>,
     interleave_time: Option<gst::ClockTime>,
     movie_timescale: u32,
     offset_to_zero: bool,
+    write_prft: bool,
+    sidx_mode: super::SidxMode,
+    sidx_reserved_references: u32,
 }
 
 impl Default for Settings {
@@ -127,10 +137,14 @@ impl Default for Settings {
             header_update_mode: DEFAULT_HEADER_UPDATE_MODE,
             write_mfra: DEFAULT_WRITE_MFRA,
             write_mehd: DEFAULT_WRITE_MEHD,
+            write_edts_btrt: DEFAULT_WRITE_EDTS_BTRT,
             interleave_bytes: DEFAULT_INTERLEAVE_BYTES,
             interleave_time: DEFAULT_INTERLEAVE_TIME,
             movie_timescale: 0,
             offset_to_zero: false,
+            write_prft: DEFAULT_WRITE_PRFT,
+            sidx_mode: DEFAULT_SIDX_MODE,
+            sidx_reserved_references: DEFAULT_SIDX_RESERVED_REFERENCES,
         }
     }
 }
@@ -215,6 +229,10 @@ struct Stream {
     delta_frames: DeltaFrames,
     /// Whether this stream might have header frames without timestamps that should be ignored.
     discard_header_buffers: bool,
+    /// Whether this is a sparse stream, e.g. subtitles, that might not have data for a long time.
+    ///
+    /// Gaps between buffers of sparse streams are filled with empty samples.
+    sparse: bool,
 
     /// Currently queued GOPs, including incomplete ones.
     queued_gops: VecDeque<Gop>,
@@ -234,6 +252,132 @@ struct Stream {
     running_time_utc_time_mapping: Option<(gst::Signed<gst::ClockTime>, gst::ClockTime)>,
 
     extra_header_data: Option<Vec<u8>>,
+
+    /// Set if the samples of this stream are encrypted.
+    encryptor: Option<Encryptor>,
+
+    /// Statistics of all drained samples for the edit list and bitrates at EOS.
+    stats: StreamStats,
+}
+
+/// Statistics about the samples of a stream that are only written into the header at EOS.
+#[derive(Debug, Default)]
+struct StreamStats {
+    /// Earliest PTS of all samples.
+    earliest_pts: Option<gst::ClockTime>,
+    /// End PTS of all samples.
+    end_pts: Option<gst::ClockTime>,
+    /// Duration of the priming samples at the start of the stream.
+    priming: gst::ClockTime,
+
+    /// Total size of all samples.
+    total_size: u64,
+    /// Size of the biggest sample.
+    max_sample_size: u64,
+    /// Maximum size of all samples within one second.
+    max_window_size: u64,
+    /// Timestamps and sizes of the samples of the last second.
+    window: VecDeque<(gst::ClockTime, u64)>,
+    /// Size of all samples of the last second.
+    window_size: u64,
+}
+
+impl StreamStats {
+    fn add_sample(&mut self, caps: &gst::CapsRef, buffer: &Buffer) {
+        let pts = match buffer.composition_time_offset {
+            Some(cto) if cto < 0 => buffer
+                .timestamp
+                .saturating_sub(gst::ClockTime::from_nseconds(cto.unsigned_abs())),
+            Some(cto) => buffer.timestamp + gst::ClockTime::from_nseconds(cto as u64),
+            None => buffer.timestamp,
+        };
+
+        if self.earliest_pts.is_none() {
+            self.priming = priming_duration(caps, &buffer.buffer);
+        }
+        if self.earliest_pts.opt_gt(pts).unwrap_or(true) {
+            self.earliest_pts = Some(pts);
+        }
+        let end_pts = pts + buffer.duration;
+        if self.end_pts.opt_lt(end_pts).unwrap_or(true) {
+            self.end_pts = Some(end_pts);
+        }
+
+        let size = buffer.buffer.size() as u64;
+        self.total_size += size;
+        self.max_sample_size = std::cmp::max(self.max_sample_size, size);
+
+        self.window.push_back((buffer.timestamp, size));
+        self.window_size += size;
+        while let Some((timestamp, size)) = self.window.front() {
+            if *timestamp + gst::ClockTime::SECOND > buffer.timestamp {
+                break;
+            }
+            self.window_size -= size;
+            self.window.pop_front();
+        }
+        self.max_window_size = std::cmp::max(self.max_window_size, self.window_size);
+    }
+
+    fn bitrates(&self) -> Option<super::Bitrates> {
+        let duration = self.end_pts?.checked_sub(self.earliest_pts?)?;
+        if duration.is_zero() {
+            return None;
+        }
+
+        let avg_bitrate = (self.total_size * 8)
+            .mul_div_round(gst::ClockTime::SECOND.nseconds(), duration.nseconds())?;
+        // Streams shorter than a second have no complete window
+        let max_bitrate = std::cmp::max(self.max_window_size * 8, avg_bitrate);
+
+        Some(super::Bitrates {
+            buffer_size: u32::try_from(self.max_sample_size).unwrap_or(u32::MAX),
+            max_bitrate: u32::try_from(max_bitrate).unwrap_or(u32::MAX),
+            avg_bitrate: u32::try_from(avg_bitrate).unwrap_or(u32::MAX),
+        })
+    }
+}
+
+/// Duration of the priming samples at the start of an audio stream that are not presented.
+fn priming_duration(caps: &gst::CapsRef, buffer: &gst::BufferRef) -> gst::ClockTime {
+    let s = caps.structure(0).unwrap();
+
+    if let Some(meta) = buffer.meta::<gst_audio::AudioClippingMeta>() {
+        match meta.start() {
+            gst::GenericFormattedValue::Time(Some(start)) => return start,
+            gst::GenericFormattedValue::Default(Some(samples)) => {
+                if let Some(priming) =
+                    s.get::<i32>("rate")
+                        .ok()
+                        .filter(|rate| *rate > 0)
+                        .and_then(|rate| {
+                            (*samples).mul_div_floor(gst::ClockTime::SECOND.nseconds(), rate as u64)
+                        })
+                {
+                    return gst::ClockTime::from_nseconds(priming);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Opus pre-skip is always in 48kHz samples
+    if s.name() == "audio/x-opus" {
+        if let Some((_, _, _, _, _, pre_skip, _)) = s
+            .get::<gst::ArrayRef>("streamheader")
+            .ok()
+            .and_then(|a| a.first().and_then(|v| v.get::<gst::Buffer>().ok()))
+            .and_then(|header| gst_pbutils::codec_utils_opus_parse_header(&header, None).ok())
+        {
+            return gst::ClockTime::from_nseconds(
+                (pre_skip as u64)
+                    .mul_div_floor(gst::ClockTime::SECOND.nseconds(), 48_000)
+                    .unwrap(),
+            );
+        }
+    }
+
+    gst::ClockTime::ZERO
 }
 
 #[derive(Default)]
@@ -258,6 +402,13 @@ struct State {
     current_offset: u64,
     fragment_offsets: Vec<super::FragmentOffset>,
 
+    /// Offset of the space reserved for the global sidx box
+    sidx_offset: Option<u64>,
+    /// Earliest PTS of the reference stream for the global sidx box
+    sidx_earliest_pts: Option<gst::ClockTime>,
+    /// Fragment tracking for the global sidx box
+    sidx_references: Vec<super::SidxReference>,
+
     /// Earliest PTS of the whole stream
     earliest_pts: Option<gst::ClockTime>,
     /// Current end PTS of the whole stream
@@ -713,8 +864,13 @@ impl FMP4Mux {
             ]
             .as_slice(),
             "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-            | "audio/x-adpcm" => ["channels", "rate", "layout", "bitrate", "codec_data"].as_slice(),
-            "application/x-onvif-metadata" => [].as_slice(),
+            | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+                ["channels", "rate", "layout", "bitrate", "codec_data"].as_slice()
+            }
+            "application/x-onvif-metadata"
+            | "application/x-subtitle-vtt"
+            | "application/ttml+xml"
+            | "text/x-raw" => [].as_slice(),
             _ => unreachable!(),
         };
 
@@ -949,6 +1105,31 @@ impl FMP4Mux {
                     })?;
             }
 
+            // For AC-3 / E-AC-3 the content of the 'dac3' / 'dec3' box is taken from the bit
+            // stream information of the first frame.
+            if stream.extra_header_data.is_none()
+                && matches!(s.name().as_str(), "audio/x-ac3" | "audio/x-eac3")
+            {
+                let buf_map = buffer.map_readable().map_err(|_| {
+                    gst::error!(CAT, obj = stream.sinkpad, "Failed to map buffer");
+                    gst::FlowError::Error
+                })?;
+                let res = if s.name() == "audio/x-ac3" {
+                    read_dac3_bytes(buf_map.as_slice())
+                } else {
+                    read_dec3_bytes(buf_map.as_slice())
+                };
+                stream.extra_header_data = Some(res.map_err(|err| {
+                    gst::error!(
+                        CAT,
+                        obj = stream.sinkpad,
+                        "Failed to parse {} bit stream information: {err}",
+                        s.name()
+                    );
+                    gst::FlowError::Error
+                })?);
+            }
+
             let gop = Gop {
                 start_pts: pts,
                 start_dts: dts,
@@ -957,7 +1138,9 @@ impl FMP4Mux {
                 final_earliest_pts: !delta_frames.requires_dts(),
                 end_pts,
                 end_dts,
-                final_end_pts: false,
+                // The end of sparse streams is final once the duration is known as it is not
+                // extended until the next buffer.
+                final_end_pts: stream.sparse && buffer.duration().is_some(),
                 buffers: vec![GopBuffer {
                     buffer,
                     pts,
@@ -977,8 +1160,10 @@ impl FMP4Mux {
                     dts.display(),
                 );
 
-                prev_gop.end_pts = std::cmp::max(prev_gop.end_pts, pts);
-                prev_gop.end_dts = std::cmp::max(prev_gop.end_dts, dts);
+                if !stream.sparse || !prev_gop.final_end_pts {
+                    prev_gop.end_pts = std::cmp::max(prev_gop.end_pts, pts);
+                    prev_gop.end_dts = std::cmp::max(prev_gop.end_dts, dts);
+                }
 
                 if !delta_frames.requires_dts() {
                     prev_gop.final_end_pts = true;
@@ -1126,6 +1311,22 @@ impl FMP4Mux {
             );
         }
 
+        // Sparse streams might be filled now because of the buffers that are waiting in their
+        // pre-queue without having queued anything themselves.
+        for stream in state
+            .streams
+            .iter_mut()
+            .filter(|s| s.sparse && !s.fragment_filled && !s.chunk_filled)
+        {
+            self.check_stream_filled(
+                settings,
+                stream,
+                fragment_start_pts,
+                fragment_end_pts,
+                chunk_start_pts,
+            );
+        }
+
         Ok(())
     }
 
@@ -1170,6 +1371,38 @@ impl FMP4Mux {
             return;
         }
 
+        // Sparse streams are filled if everything queued is final and the next buffer only
+        // starts after the current chunk or fragment.
+        if stream.sparse && stream.queued_gops.iter().all(|gop| gop.final_end_pts) {
+            let chunk_end_pts = settings
+                .chunk_duration
+                .map(|chunk_duration| chunk_start_pts + chunk_duration);
+            let end_pts = match chunk_end_pts {
+                Some(chunk_end_pts) if chunk_end_pts < fragment_end_pts => chunk_end_pts,
+                _ => fragment_end_pts,
+            };
+
+            let next_pts = stream
+                .queued_gops
+                .front()
+                .filter(|gop| gop.start_pts >= end_pts)
+                .map(|gop| gop.start_pts)
+                .or_else(|| stream.pre_queue.front().map(|b| b.pts));
+            if next_pts.is_some_and(|next_pts| next_pts >= end_pts) {
+                gst::debug!(
+                    CAT,
+                    obj = stream.sinkpad,
+                    "Sparse stream has no further data for this chunk or fragment"
+                );
+                if end_pts == fragment_end_pts {
+                    stream.fragment_filled = true;
+                } else {
+                    stream.chunk_filled = true;
+                }
+                return;
+            }
+        }
+
         // Check if this stream is filled enough now.
         if let Some(chunk_duration) = settings.chunk_duration {
             // In chunk mode
@@ -1624,7 +1857,9 @@ impl FMP4Mux {
                     // fragment and the fragment duration.
                     if (gop.final_end_pts || stream.sinkpad.is_eos() || need_new_header)
                         && (gop.end_pts <= dequeue_end_pts
-                            || (gops.is_empty() && chunk_end_pts.is_none()))
+                            || (gops.is_empty()
+                                && chunk_end_pts.is_none()
+                                && !(stream.sparse && gop.start_pts >= dequeue_end_pts)))
                     {
                         gst::trace!(CAT, obj = stream.sinkpad, "Pushing whole GOP",);
                         gops.push(stream.queued_gops.pop_back().unwrap());
@@ -1822,6 +2057,16 @@ impl FMP4Mux {
                     break;
                 }
 
+                // Buffers of sparse streams are only included in the fragment they start in.
+                if !all_eos && stream.sparse && gop.start_pts >= dequeue_end_pts {
+                    gst::trace!(
+                        CAT,
+                        obj = stream.sinkpad,
+                        "Buffer starts after fragment end",
+                    );
+                    break;
+                }
+
                 gst::trace!(CAT, obj = stream.sinkpad, "Pushing complete GOP",);
                 gops.push(stream.queued_gops.pop_back().unwrap());
             }
@@ -1830,6 +2075,145 @@ impl FMP4Mux {
         Ok(gops)
     }
 
+    /// Create an empty sample for a sparse stream that covers the given running time range.
+    fn sparse_gap_buffer(
+        &self,
+        idx: usize,
+        stream: &Stream,
+        start: gst::ClockTime,
+        end: gst::ClockTime,
+    ) -> Result<Buffer, gst::FlowError> {
+        // Positions must be in the same segment as the stream's other buffers
+        let pts_position = if self.obj().class().as_ref().variant.is_single_stream() {
+            stream
+                .sinkpad
+                .segment()
+                .downcast::<gst::ClockTime>()
+                .ok()
+                .and_then(|segment| segment.position_from_running_time(start))
+                .ok_or_else(|| {
+                    gst::error!(
+                        CAT,
+                        obj = stream.sinkpad,
+                        "Couldn't convert running time {start} to position"
+                    );
+                    gst::FlowError::Error
+                })?
+        } else {
+            start + SEGMENT_OFFSET
+        };
+
+        gst::trace!(
+            CAT,
+            obj = stream.sinkpad,
+            "Filling gap from {start} to {end} with empty sample",
+        );
+
+        let mut buffer = gst::Buffer::from_mut_slice(boxes::subtitle_gap_sample(&stream.caps));
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts_position);
+            buffer.set_duration(end - start);
+        }
+
+        Ok(Buffer {
+            idx,
+            buffer,
+            timestamp: start,
+            duration: end - start,
+            composition_time_offset: None,
+            sample_encryption: None,
+        })
+    }
+
+    /// Fill the range between the chunk start and end that is not covered by any drained
+    /// buffers of a sparse stream with empty samples.
+    ///
+    /// The end is clipped to the start of the next queued buffer, which might already have
+    /// started before the chunk end.
+    #[allow(clippy::type_complexity)]
+    fn fill_sparse_stream(
+        &self,
+        idx: usize,
+        stream: &Stream,
+        buffers: Option<(
+            VecDeque<super::Buffer>,
+            gst::ClockTime,
+            gst::ClockTime,
+            gst::ClockTime,
+            Option<gst::ClockTime>,
+            Option<gst::ClockTime>,
+            Option<gst::ClockTime>,
+        )>,
+        chunk_start_pts: gst::ClockTime,
+        chunk_end_pts: gst::ClockTime,
+    ) -> Result<
+        Option<(
+            VecDeque<super::Buffer>,
+            gst::ClockTime,
+            gst::ClockTime,
+            gst::ClockTime,
+            Option<gst::ClockTime>,
+            Option<gst::ClockTime>,
+            Option<gst::ClockTime>,
+        )>,
+        gst::FlowError,
+    > {
+        let fill_end_pts = stream.queued_gops.back().map_or(chunk_end_pts, |gop| {
+            std::cmp::min(gop.start_pts, chunk_end_pts)
+        });
+
+        let Some((
+            mut buffers,
+            mut earliest_pts,
+            mut earliest_pts_position,
+            mut end_pts,
+            start_dts,
+            start_dts_position,
+            end_dts,
+        )) = buffers
+        else {
+            if fill_end_pts <= chunk_start_pts {
+                return Ok(None);
+            }
+
+            let gap = self.sparse_gap_buffer(idx, stream, chunk_start_pts, fill_end_pts)?;
+            let earliest_pts_position = gap.buffer.pts().unwrap();
+
+            return Ok(Some((
+                VecDeque::from([gap]),
+                chunk_start_pts,
+                earliest_pts_position,
+                fill_end_pts,
+                None,
+                None,
+                None,
+            )));
+        };
+
+        if earliest_pts > chunk_start_pts {
+            let gap = self.sparse_gap_buffer(idx, stream, chunk_start_pts, earliest_pts)?;
+            earliest_pts = chunk_start_pts;
+            earliest_pts_position = gap.buffer.pts().unwrap();
+            buffers.push_front(gap);
+        }
+
+        if end_pts < fill_end_pts {
+            buffers.push_back(self.sparse_gap_buffer(idx, stream, end_pts, fill_end_pts)?);
+            end_pts = fill_end_pts;
+        }
+
+        Ok(Some((
+            buffers,
+            earliest_pts,
+            earliest_pts_position,
+            end_pts,
+            start_dts,
+            start_dts_position,
+            end_dts,
+        )))
+    }
+
     /// Flatten all GOPs, remove any gaps and calculate durations.
     #[allow(clippy::type_complexity)]
     fn flatten_gops(
@@ -1886,6 +2270,36 @@ impl FMP4Mux {
                 continue;
             }
 
+            // Convert subtitles into their sample format. Anything that is not a sample on its
+            // own, like WebVTT header or comment blocks, is skipped.
+            let mut buffer = buffer;
+            if stream.sparse {
+                let map = buffer.buffer.map_readable().map_err(|_| {
+                    gst::error!(CAT, obj = stream.sinkpad, "Failed to map buffer");
+                    gst::FlowError::Error
+                })?;
+                let Some(data) = boxes::subtitle_sample(&stream.caps, &map) else {
+                    drop(map);
+                    gst::trace!(
+                        CAT,
+                        obj = stream.sinkpad,
+                        "Skipping non-sample buffer {buffer:?}",
+                    );
+                    continue;
+                };
+                drop(map);
+
+                let mut sample = gst::Buffer::from_mut_slice(data);
+                {
+                    let sample = sample.get_mut().unwrap();
+                    sample.set_pts(buffer.buffer.pts());
+                    sample.set_dts(buffer.buffer.dts());
+                    sample.set_duration(buffer.buffer.duration());
+                    sample.set_flags(buffer.buffer.flags());
+                }
+                buffer.buffer = sample;
+            }
+
             if earliest_pts.map_or(true, |earliest_pts| buffer.pts < earliest_pts) {
                 earliest_pts = Some(buffer.pts);
             }
@@ -1934,6 +2348,16 @@ impl FMP4Mux {
                 .checked_sub(timestamp)
                 .expect("Timestamps going backwards");
 
+            // Sparse streams keep the duration of their buffers and the gap until the next
+            // buffer is filled with an empty sample.
+            let (duration, gap) = match buffer.buffer.duration() {
+                Some(buffer_duration) if stream.sparse && buffer_duration < duration => (
+                    buffer_duration,
+                    Some((timestamp + buffer_duration, end_timestamp)),
+                ),
+                _ => (duration, None),
+            };
+
             let composition_time_offset = if !stream.delta_frames.requires_dts() {
                 None
             } else {
@@ -1957,7 +2381,12 @@ impl FMP4Mux {
                 timestamp,
                 duration,
                 composition_time_offset,
+                sample_encryption: None,
             });
+
+            if let Some((gap_start, gap_end)) = gap {
+                buffers.push_back(self.sparse_gap_buffer(idx, stream, gap_start, gap_end)?);
+            }
         }
 
         if buffers.is_empty() {
@@ -2051,6 +2480,13 @@ impl FMP4Mux {
                 .map(|s| s.fragment_filled)
                 == Some(true);
 
+        // End PTS of what the first stream is supposed to drain
+        let target_end_pts = if fragment_filled {
+            fragment_end_pts
+        } else {
+            chunk_start_pts + settings.chunk_duration.unwrap()
+        };
+
         // The first stream decides how much can be dequeued, if anything at all.
         //
         // In chunk mode:
@@ -2097,33 +2533,45 @@ impl FMP4Mux {
                 fragment_start,
                 fragment_filled,
             )?;
+            let stream_filled = stream.fragment_filled || stream.chunk_filled;
             stream.fragment_filled = false;
             stream.chunk_filled = false;
 
             // If we don't have a next chunk start PTS then this is the first stream as above.
             if chunk_end_pts.is_none() {
                 if let Some(last_gop) = gops.last() {
-                    // Dequeued something so let's take the end PTS of the last GOP
-                    chunk_end_pts = Some(last_gop.end_pts);
+                    // Dequeued something so let's take the end PTS of the last GOP. Sparse
+                    // streams have no data until the next buffer so drain up to the target end.
+                    let end_pts = if stream.sparse && stream_filled && !all_eos {
+                        std::cmp::max(last_gop.end_pts, target_end_pts)
+                    } else {
+                        last_gop.end_pts
+                    };
+                    chunk_end_pts = Some(end_pts);
                     gst::info!(
                         CAT,
                         obj = stream.sinkpad,
                         "Draining up to PTS {} for this chunk",
-                        last_gop.end_pts,
+                        end_pts,
                     );
                 } else {
                     // If nothing was dequeued for the first stream then this is OK if we're at
                     // EOS or this stream simply has only buffers after this chunk: we just
                     // consider the next stream as first stream then.
-                    let stream_after_chunk = stream.queued_gops.back().is_some_and(|gop| {
-                        gop.start_pts
-                            >= if fragment_filled {
-                                fragment_end_pts
-                            } else {
-                                chunk_start_pts + settings.chunk_duration.unwrap()
-                            }
-                    });
-                    if stream.sinkpad.is_eos() || stream_after_chunk {
+                    let stream_after_chunk = stream
+                        .queued_gops
+                        .back()
+                        .is_some_and(|gop| gop.start_pts >= target_end_pts);
+                    if stream.sparse && stream_after_chunk {
+                        // Sparse streams are filled with an empty sample for this chunk
+                        chunk_end_pts = Some(target_end_pts);
+                        gst::info!(
+                            CAT,
+                            obj = stream.sinkpad,
+                            "Draining up to PTS {} for this chunk without data",
+                            target_end_pts,
+                        );
+                    } else if stream.sinkpad.is_eos() || stream_after_chunk {
                         // This is handled below generally if nothing was dequeued
                     } else {
                         if settings.chunk_duration.is_some() {
@@ -2163,47 +2611,51 @@ impl FMP4Mux {
                 }
             }
 
-            if gops.is_empty() {
+            let buffers = if gops.is_empty() {
                 gst::info!(CAT, obj = stream.sinkpad, "Draining no buffers",);
 
-                drained_streams.push((
-                    super::FragmentHeaderStream {
-                        caps: stream.caps.clone(),
-                        start_time: None,
-                        delta_frames: stream.delta_frames,
-                        trak_timescale: stream_settings.trak_timescale,
-                    },
-                    VecDeque::new(),
-                ));
-
-                continue;
-            }
+                None
+            } else {
+                assert!(chunk_end_pts.is_some());
 
-            assert!(chunk_end_pts.is_some());
+                if let Some((prev_gop, first_gop)) = Option::zip(
+                    stream.queued_gops.iter().find(|gop| gop.final_end_pts),
+                    stream.queued_gops.back(),
+                ) {
+                    gst::debug!(
+                        CAT,
+                        obj = stream.sinkpad,
+                        "Queued full GOPs duration updated to {}",
+                        prev_gop.end_pts.saturating_sub(first_gop.earliest_pts),
+                    );
+                }
 
-            if let Some((prev_gop, first_gop)) = Option::zip(
-                stream.queued_gops.iter().find(|gop| gop.final_end_pts),
-                stream.queued_gops.back(),
-            ) {
                 gst::debug!(
                     CAT,
                     obj = stream.sinkpad,
-                    "Queued full GOPs duration updated to {}",
-                    prev_gop.end_pts.saturating_sub(first_gop.earliest_pts),
+                    "Queued duration updated to {}",
+                    Option::zip(stream.queued_gops.front(), stream.queued_gops.back())
+                        .map(|(end, start)| end.end_pts.saturating_sub(start.start_pts))
+                        .unwrap_or(gst::ClockTime::ZERO)
                 );
-            }
 
-            gst::debug!(
-                CAT,
-                obj = stream.sinkpad,
-                "Queued duration updated to {}",
-                Option::zip(stream.queued_gops.front(), stream.queued_gops.back())
-                    .map(|(end, start)| end.end_pts.saturating_sub(start.start_pts))
-                    .unwrap_or(gst::ClockTime::ZERO)
-            );
+                // First flatten all GOPs into a single `Vec`
+                let buffers = self.flatten_gops(idx, stream, gops)?;
+                if buffers.is_none() {
+                    gst::info!(CAT, obj = stream.sinkpad, "Drained only gap buffers",);
+                }
+
+                buffers
+            };
+
+            // Sparse streams are covering the whole chunk, using empty samples where necessary
+            let buffers = match chunk_end_pts {
+                Some(chunk_end_pts) if stream.sparse => {
+                    self.fill_sparse_stream(idx, stream, buffers, chunk_start_pts, chunk_end_pts)?
+                }
+                _ => buffers,
+            };
 
-            // First flatten all GOPs into a single `Vec`
-            let buffers = self.flatten_gops(idx, stream, gops)?;
             let (
                 buffers,
                 earliest_pts,
@@ -2215,12 +2667,11 @@ impl FMP4Mux {
             ) = match buffers {
                 Some(res) => res,
                 None => {
-                    gst::info!(CAT, obj = stream.sinkpad, "Drained only gap buffers",);
-
                     drained_streams.push((
                         super::FragmentHeaderStream {
                             caps: stream.caps.clone(),
                             start_time: None,
+                            earliest_pts: None,
                             delta_frames: stream.delta_frames,
                             trak_timescale: stream_settings.trak_timescale,
                         },
@@ -2269,6 +2720,7 @@ impl FMP4Mux {
                 super::FragmentHeaderStream {
                     caps: stream.caps.clone(),
                     start_time: Some(start_time),
+                    earliest_pts: Some(earliest_pts),
                     delta_frames: stream.delta_frames,
                     trak_timescale: stream_settings.trak_timescale,
                 },
@@ -2482,20 +2934,52 @@ impl FMP4Mux {
         let (mut interleaved_buffers, mut streams) =
             self.interleave_buffers(settings, drained_streams)?;
 
+        // Running time (or UTC time in ONVIF mode) of the reference stream for the prft box,
+        // before it is possibly offset below.
+        let reference_start_time = streams.first().and_then(|s| s.start_time);
+
+        // Collect statistics for the header at EOS
+        for buffer in &interleaved_buffers {
+            let stream = &mut state.streams[buffer.idx];
+            stream.stats.add_sample(&stream.caps, buffer);
+        }
+
+        // Encrypt the buffers of all streams that have encryption configured
+        for buffer in &mut interleaved_buffers {
+            let stream = &mut state.streams[buffer.idx];
+            let Some(ref mut encryptor) = stream.encryptor else {
+                continue;
+            };
+
+            let mut map = buffer.buffer.make_mut().map_writable().map_err(|_| {
+                gst::error!(CAT, obj = stream.sinkpad, "Failed to map buffer");
+                gst::FlowError::Error
+            })?;
+            let sample_encryption = encryptor.encrypt(map.as_mut_slice()).map_err(|err| {
+                gst::error!(
+                    CAT,
+                    obj = stream.sinkpad,
+                    "Failed to encrypt buffer: {}",
+                    err
+                );
+                gst::FlowError::Error
+            })?;
+            buffer.sample_encryption = Some(sample_encryption);
+        }
+
         // Offset stream start time to start at 0 in ONVIF mode, or if 'offset-to-zero' is enabled,
         // instead of using the UTC time verbatim. This would be used for the tfdt box later.
         // FIXME: Should this use the original DTS-or-PTS running time instead?
         //        That might be negative though!
-        if self.obj().class().as_ref().variant == super::Variant::ONVIF || settings.offset_to_zero {
-            let offset = if let Some(start_dts) = state.start_dts {
-                std::cmp::min(start_dts, state.earliest_pts.unwrap())
-            } else {
-                state.earliest_pts.unwrap()
-            };
+        let start_time_offset = self.start_time_offset(state, settings);
+        if let Some(offset) = start_time_offset {
             for stream in &mut streams {
                 if let Some(start_time) = stream.start_time {
                     stream.start_time = Some(start_time.checked_sub(offset).unwrap());
                 }
+                if let Some(earliest_pts) = stream.earliest_pts {
+                    stream.earliest_pts = Some(earliest_pts.checked_sub(offset).unwrap());
+                }
             }
         }
 
@@ -2542,8 +3026,50 @@ impl FMP4Mux {
             state.sent_headers = true;
         }
 
-        // TODO: Write prft boxes before moof
-        // TODO: Write sidx boxes before moof and rewrite once offsets are known
+        // Reserve space for the global sidx box after the first header. It is rewritten at EOS
+        // once all fragments are known.
+        let mut sidx_placeholder = None;
+        if settings.sidx_mode == super::SidxMode::Global && state.sidx_offset.is_none() {
+            if let Some(ref header) = fmp4_header {
+                let mut placeholder =
+                    boxes::create_sidx_placeholder(settings.sidx_reserved_references as usize)
+                        .map_err(|err| {
+                            gst::error!(
+                                CAT,
+                                imp = self,
+                                "Failed to create sidx placeholder: {err}"
+                            );
+                            gst::FlowError::Error
+                        })?;
+                // Placeholder is HEADER like the stream header before it
+                placeholder
+                    .get_mut()
+                    .unwrap()
+                    .set_flags(gst::BufferFlags::HEADER);
+                state.sidx_offset = Some(state.current_offset + header.size() as u64);
+                sidx_placeholder = Some(placeholder);
+            }
+        }
+
+        let chunk_start_pts = state.chunk_start_pts.unwrap_or(min_earliest_pts);
+        let chunk_duration = chunk_end_pts.saturating_sub(chunk_start_pts);
+
+        // Earliest PTS of the reference stream for the sidx box. If the reference stream has no
+        // buffers in this chunk then the chunk start is used instead.
+        let reference_earliest_pts =
+            streams
+                .first()
+                .and_then(|s| s.earliest_pts)
+                .unwrap_or_else(|| {
+                    chunk_start_pts
+                        .saturating_sub(start_time_offset.unwrap_or(gst::ClockTime::ZERO))
+                });
+
+        let producer_reference_time = if settings.write_prft {
+            reference_start_time.and_then(|start_time| self.ntp_time(start_time))
+        } else {
+            None
+        };
 
         // First sequence number must be 1
         if state.sequence_number == 0 {
@@ -2562,6 +3088,10 @@ impl FMP4Mux {
                 chunk: !fragment_start,
                 streams: streams.as_slice(),
                 buffers: interleaved_buffers.as_slice(),
+                duration: chunk_duration,
+                sidx_earliest_pts: (settings.sidx_mode == super::SidxMode::Fragment)
+                    .then_some(reference_earliest_pts),
+                producer_reference_time,
             })
             .map_err(|err| {
                 gst::error!(
@@ -2596,8 +3126,17 @@ impl FMP4Mux {
 
         let moof_offset = state.current_offset
             + fmp4_header.as_ref().map(|h| h.size()).unwrap_or(0) as u64
+            + sidx_placeholder.as_ref().map(|p| p.size()).unwrap_or(0) as u64
             + moof_offset;
 
+        // Everything after the first header and the sidx placeholder is part of the fragments
+        // indexed by the global sidx box.
+        let indexed_offset = if sidx_placeholder.is_some() {
+            state.sidx_offset.unwrap() + sidx_placeholder.as_ref().unwrap().size() as u64
+        } else {
+            state.current_offset
+        };
+
         let buffers_len = interleaved_buffers.len();
         for (idx, buffer) in interleaved_buffers.iter_mut().enumerate() {
             // Fix up buffer flags, all other buffers are DELTA_UNIT
@@ -2613,6 +3152,7 @@ impl FMP4Mux {
 
         let buffer_list = fmp4_header
             .into_iter()
+            .chain(sidx_placeholder)
             .chain(Some(fmp4_fragment_header))
             .chain(interleaved_buffers.into_iter().map(|buffer| buffer.buffer))
             .inspect(|b| {
@@ -2635,6 +3175,28 @@ impl FMP4Mux {
             }
         }
 
+        if settings.sidx_mode == super::SidxMode::Global && state.sidx_offset.is_some() {
+            let size = state.current_offset - indexed_offset;
+
+            if state.sidx_references.is_empty() {
+                state.sidx_earliest_pts = Some(reference_earliest_pts);
+            }
+
+            match state.sidx_references.last_mut() {
+                Some(reference) if !fragment_start => {
+                    reference.size += size;
+                    reference.duration += chunk_duration;
+                }
+                _ => {
+                    state.sidx_references.push(super::SidxReference {
+                        size,
+                        duration: chunk_duration,
+                        starts_with_sap: fragment_start,
+                    });
+                }
+            }
+        }
+
         state.end_pts = Some(chunk_end_pts);
 
         // Update for the start PTS of the next fragment / chunk
@@ -2666,12 +3228,25 @@ impl FMP4Mux {
         // Reset timeout delay now that we've output an actual fragment or chunk
         state.timeout_delay = gst::ClockTime::ZERO;
 
-        // TODO: Write edit list at EOS
-        // TODO: Rewrite bitrates at EOS
-
         Ok((caps, Some(buffer_list)))
     }
 
+    /// Offset that is subtracted from the start times of all streams, to start at 0 in ONVIF mode
+    /// or if 'offset-to-zero' is enabled.
+    fn start_time_offset(&self, state: &State, settings: &Settings) -> Option<gst::ClockTime> {
+        if self.obj().class().as_ref().variant != super::Variant::ONVIF && !settings.offset_to_zero
+        {
+            return None;
+        }
+
+        let earliest_pts = state.earliest_pts?;
+        Some(if let Some(start_dts) = state.start_dts {
+            std::cmp::min(start_dts, earliest_pts)
+        } else {
+            earliest_pts
+        })
+    }
+
     /// Drain all chunks that can currently be drained.
     ///
     /// On error the `caps`, `buffers` or `upstream_events` can contain data of already finished
@@ -2785,6 +3360,7 @@ impl FMP4Mux {
 
             let mut delta_frames = DeltaFrames::IntraOnly;
             let mut discard_header_buffers = false;
+            let mut sparse = false;
             match s.name().as_str() {
                 "video/x-h264" | "video/x-h265" => {
                     if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
@@ -2842,16 +3418,53 @@ impl FMP4Mux {
                 }
                 "audio/x-alaw" | "audio/x-mulaw" => (),
                 "audio/x-adpcm" => (),
+                "audio/x-ac3" | "audio/x-eac3" => (),
+                "audio/x-ac4" => {
+                    if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
+                        gst::error!(CAT, obj = pad, "Received caps without codec_data");
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                }
                 "application/x-onvif-metadata" => (),
+                "application/x-subtitle-vtt" | "application/ttml+xml" | "text/x-raw" => {
+                    sparse = true;
+                }
                 _ => unreachable!(),
             }
 
+            let encryptor = {
+                let settings = pad.imp().settings.lock().unwrap();
+                if settings.encryption_scheme == super::EncryptionScheme::None {
+                    None
+                } else {
+                    match Encryptor::new(
+                        settings.encryption_scheme,
+                        settings.key_id.as_deref(),
+                        settings.key.as_deref(),
+                        settings.iv.as_deref(),
+                        &caps,
+                    ) {
+                        Ok(encryptor) => Some(encryptor),
+                        Err(err) => {
+                            gst::error!(
+                                CAT,
+                                obj = pad,
+                                "Failed to configure encryption: {:#}",
+                                err
+                            );
+                            return Err(gst::FlowError::NotNegotiated);
+                        }
+                    }
+                }
+            };
+
             state.streams.push(Stream {
                 sinkpad: pad,
                 caps,
                 next_caps: None,
                 delta_frames,
                 discard_header_buffers,
+                sparse,
                 pre_queue: VecDeque::new(),
                 queued_gops: VecDeque::new(),
                 fragment_filled: false,
@@ -2860,6 +3473,8 @@ impl FMP4Mux {
                 current_position: gst::ClockTime::ZERO,
                 running_time_utc_time_mapping: None,
                 extra_header_data: None,
+                encryptor,
+                stats: StreamStats::default(),
             });
         }
 
@@ -2868,7 +3483,8 @@ impl FMP4Mux {
             return Err(gst::FlowError::Error);
         }
 
-        // Sort video streams first and then audio streams and then metadata streams, and each group by pad name.
+        // Sort video streams first and then audio streams, subtitle streams and then metadata
+        // streams, and each group by pad name.
         state.streams.sort_by(|a, b| {
             let order_of_caps = |caps: &gst::CapsRef| {
                 let s = caps.structure(0).unwrap();
@@ -2877,8 +3493,13 @@ impl FMP4Mux {
                     0
                 } else if s.name().starts_with("audio/") {
                     1
-                } else if s.name().starts_with("application/x-onvif-metadata") {
+                } else if s.name().starts_with("text/")
+                    || s.name() == "application/x-subtitle-vtt"
+                    || s.name() == "application/ttml+xml"
+                {
                     2
+                } else if s.name().starts_with("application/x-onvif-metadata") {
+                    3
                 } else {
                     unimplemented!();
                 }
@@ -2920,6 +3541,9 @@ impl FMP4Mux {
             .ok()
             .flatten();
 
+        let start_time_offset = self
+            .start_time_offset(state, settings)
+            .unwrap_or(gst::ClockTime::ZERO);
         let streams = state
             .streams
             .iter()
@@ -2928,9 +3552,41 @@ impl FMP4Mux {
                 delta_frames: s.delta_frames,
                 caps: s.caps.clone(),
                 extra_header_data: s.extra_header_data.clone(),
+                encryption: s.encryptor.as_ref().map(|e| e.track_encryption().clone()),
+                presentation_range: if at_eos {
+                    s.stats
+                        .earliest_pts
+                        .zip(s.stats.end_pts)
+                        .map(|(start, end)| super::PresentationRange {
+                            start: start.saturating_sub(start_time_offset),
+                            end: end.saturating_sub(start_time_offset),
+                            priming: s.stats.priming,
+                        })
+                } else {
+                    None
+                },
+                bitrates: if at_eos { s.stats.bitrates() } else { None },
             })
             .collect::<Vec<_>>();
 
+        let mut protection_system_headers = Vec::<gst::Buffer>::new();
+        for s in &state.streams {
+            let settings = s.sinkpad.imp().settings.lock().unwrap();
+            if settings.encryption_scheme == super::EncryptionScheme::None {
+                continue;
+            }
+            for pssh in &settings.protection_system_headers {
+                // Streams usually share the same headers
+                let data = |b: &gst::Buffer| b.map_readable().map(|m| m.to_vec()).ok();
+                if !protection_system_headers
+                    .iter()
+                    .any(|other| data(other) == data(pssh))
+                {
+                    protection_system_headers.push(pssh.clone());
+                }
+            }
+        }
+
         let mut buffer = boxes::create_fmp4_header(super::HeaderConfiguration {
             variant,
             update: at_eos,
@@ -2938,6 +3594,8 @@ impl FMP4Mux {
             streams,
             write_mehd: settings.write_mehd,
             duration: if at_eos { duration } else { None },
+            write_edts_btrt: settings.write_edts_btrt
+                && settings.header_update_mode != super::HeaderUpdateMode::None,
             language_code: state.language_code,
             orientation: state.orientation,
             start_utc_time: if variant == super::Variant::ONVIF {
@@ -2947,6 +3605,7 @@ impl FMP4Mux {
             } else {
                 None
             },
+            protection_system_headers,
         })
         .map_err(|err| {
             gst::error!(CAT, imp = self, "Failed to create FMP4 header: {}", err);
@@ -2983,11 +3642,96 @@ impl FMP4Mux {
         Ok(Some((list, caps)))
     }
 
+    /// Converts the running time of the reference stream to an NTP timestamp for the prft box.
+    ///
+    /// In ONVIF mode the time is already the UTC time, otherwise it is converted via the
+    /// pipeline clock.
+    fn ntp_time(&self, time: gst::ClockTime) -> Option<u64> {
+        let utc_time = if self.obj().class().as_ref().variant == super::Variant::ONVIF {
+            time
+        } else {
+            let obj = self.obj();
+            let clock = obj.clock()?;
+            let base_time = obj.base_time()?;
+            let now = clock.time()?;
+            let system_now = std::time::SystemTime::now()
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .ok()?;
+            let system_now = gst::ClockTime::from_nseconds(system_now.as_nanos() as u64);
+
+            (system_now + base_time + time).checked_sub(now)?
+        };
+
+        let ntp_time = utc_time + NTP_UNIX_OFFSET.seconds();
+        let fraction = (ntp_time.nseconds() % gst::ClockTime::SECOND.nseconds())
+            .mul_div_floor(1 << 32, gst::ClockTime::SECOND.nseconds())?;
+
+        Some((ntp_time.seconds() << 32) | fraction)
+    }
+
+    /// Rewrite the space reserved after the header with the global sidx box.
+    fn rewrite_sidx(&self, settings: &Settings) {
+        let (offset, sidx) = {
+            let state = self.state.lock().unwrap();
+            let Some(offset) = state.sidx_offset else {
+                return;
+            };
+
+            let stream = &state.streams[0];
+            let trak_timescale = stream.sinkpad.imp().settings.lock().unwrap().trak_timescale;
+            match boxes::create_sidx(
+                &stream.caps,
+                trak_timescale,
+                state.sidx_earliest_pts.unwrap_or(gst::ClockTime::ZERO),
+                &state.sidx_references,
+                settings.sidx_reserved_references as usize,
+            ) {
+                Ok(sidx) => (offset, sidx),
+                Err(err) => {
+                    gst::error!(CAT, imp = self, "Failed to create sidx box: {}", err);
+                    return;
+                }
+            }
+        };
+
+        let mut q = gst::query::Seeking::new(gst::Format::Bytes);
+        if !(self.obj().src_pad().peer_query(&mut q) && q.result().0) {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Can't rewrite sidx because downstream is not seekable"
+            );
+            return;
+        }
+
+        gst::debug!(CAT, imp = self, "Rewriting sidx box at offset {offset}");
+
+        let aggregator = self.obj();
+
+        // Seek to the reserved space with a bytes segment
+        let mut segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+        segment.set_start(gst::format::Bytes::from_u64(offset));
+        aggregator.update_segment(&segment);
+
+        if let Err(err) = aggregator.finish_buffer(sidx) {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Failed pushing sidx buffer downstream: {:?}",
+                err,
+            );
+        }
+    }
+
     /// Finish the stream be rewriting / updating headers.
     fn finish(&self, settings: &Settings) {
         // Do remaining EOS handling after the end of the stream was pushed.
         gst::debug!(CAT, imp = self, "Doing EOS handling");
 
+        if settings.sidx_mode == super::SidxMode::Global {
+            self.rewrite_sidx(settings);
+        }
+
         if settings.header_update_mode == super::HeaderUpdateMode::None {
             // Need to output new headers if started again after EOS
             self.state.lock().unwrap().sent_headers = false;
@@ -3146,6 +3890,12 @@ impl ObjectImpl for FMP4Mux {
                     .default_value(DEFAULT_WRITE_MEHD)
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecBoolean::builder("write-edts-btrt")
+                    .nick("Write edts and btrt boxes")
+                    .blurb("Write edit lists and bitrates at the end of the stream and reserve space for them in the initial header (needs a header-update-mode enabled)")
+                    .default_value(DEFAULT_WRITE_EDTS_BTRT)
+                    .mutable_ready()
+                    .build(),
                 glib::ParamSpecUInt64::builder("interleave-bytes")
                     .nick("Interleave Bytes")
                     .blurb("Interleave between streams in bytes")
@@ -3163,6 +3913,25 @@ impl ObjectImpl for FMP4Mux {
                     .blurb("Timescale to use for the movie (units per second, 0 is automatic)")
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecBoolean::builder("write-prft")
+                    .nick("Write prft box")
+                    .blurb("Write producer reference time box with the NTP time from the pipeline clock before each fragment")
+                    .default_value(DEFAULT_WRITE_PRFT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("sidx-mode", DEFAULT_SIDX_MODE)
+                    .nick("sidx mode")
+                    .blurb("Mode for writing segment index boxes (global needs a seekable downstream)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("sidx-reserved-references")
+                    .nick("sidx Reserved References")
+                    .blurb("Number of fragments to reserve space for in the global segment index box")
+                    .minimum(1)
+                    .maximum(u16::MAX as u32)
+                    .default_value(DEFAULT_SIDX_RESERVED_REFERENCES)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -3212,6 +3981,11 @@ impl ObjectImpl for FMP4Mux {
                 settings.write_mehd = value.get().expect("type checked upstream");
             }
 
+            "write-edts-btrt" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.write_edts_btrt = value.get().expect("type checked upstream");
+            }
+
             "interleave-bytes" => {
                 let mut settings = self.settings.lock().unwrap();
                 settings.interleave_bytes = match value.get().expect("type checked upstream") {
@@ -3233,6 +4007,21 @@ impl ObjectImpl for FMP4Mux {
                 settings.movie_timescale = value.get().expect("type checked upstream");
             }
 
+            "write-prft" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.write_prft = value.get().expect("type checked upstream");
+            }
+
+            "sidx-mode" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.sidx_mode = value.get().expect("type checked upstream");
+            }
+
+            "sidx-reserved-references" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.sidx_reserved_references = value.get().expect("type checked upstream");
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -3264,6 +4053,11 @@ impl ObjectImpl for FMP4Mux {
                 settings.write_mehd.to_value()
             }
 
+            "write-edts-btrt" => {
+                let settings = self.settings.lock().unwrap();
+                settings.write_edts_btrt.to_value()
+            }
+
             "interleave-bytes" => {
                 let settings = self.settings.lock().unwrap();
                 settings.interleave_bytes.unwrap_or(0).to_value()
@@ -3279,6 +4073,21 @@ impl ObjectImpl for FMP4Mux {
                 settings.movie_timescale.to_value()
             }
 
+            "write-prft" => {
+                let settings = self.settings.lock().unwrap();
+                settings.write_prft.to_value()
+            }
+
+            "sidx-mode" => {
+                let settings = self.settings.lock().unwrap();
+                settings.sidx_mode.to_value()
+            }
+
+            "sidx-reserved-references" => {
+                let settings = self.settings.lock().unwrap();
+                settings.sidx_reserved_references.to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -3490,7 +4299,9 @@ impl AggregatorImpl for FMP4Mux {
                             state.need_new_header = true;
                         }
                     }
-                } else if let Some(tag_value) = ev.tag().get::<gst::tags::ImageOrientation>() {
+                }
+
+                if let Some(tag_value) = ev.tag().get::<gst::tags::ImageOrientation>() {
                     let orientation = tag_value.get();
                     gst::trace!(
                         CAT,
@@ -3500,33 +4311,19 @@ impl AggregatorImpl for FMP4Mux {
                     );
 
                     let mut state = self.state.lock().unwrap();
-                    let orientation = match orientation {
-                        "rotate-0" => Some(ImageOrientation::Rotate0),
-                        "rotate-90" => Some(ImageOrientation::Rotate90),
-                        "rotate-180" => Some(ImageOrientation::Rotate180),
-                        "rotate-270" => Some(ImageOrientation::Rotate270),
-                        // TODO:
-                        // "flip-rotate-0" => Some(ImageOrientation::FlipRotate0),
-                        // "flip-rotate-90" => Some(ImageOrientation::FlipRotate90),
-                        // "flip-rotate-180" => Some(ImageOrientation::FlipRotate180),
-                        // "flip-rotate-270" => Some(ImageOrientation::FlipRotate270),
-                        _ => {
-                            gst::info!(
-                                CAT,
-                                imp = self,
-                                "Orientation {:?} not yet supported",
-                                orientation
-                            );
-                            None
-                        }
-                    };
+                    let orientation = ImageOrientation::from_tag(orientation);
+                    if orientation.is_none() {
+                        gst::info!(CAT, imp = self, "Unknown orientation {:?}", tag_value.get());
+                    }
 
-                    // If the orientation changed and we have buffers
-                    // trigger caps change
-                    if state.orientation != orientation
-                        && !state.streams.is_empty()
+                    if state.streams.is_empty() {
+                        // No header was written yet, just use it for the first one
+                        state.orientation = orientation;
+                    } else if state.orientation != orientation
                         && self.header_update_allowed("orientation")
                     {
+                        // If the orientation changed and we have buffers
+                        // trigger caps change
                         state.orientation = orientation;
                         state.need_new_header = true;
                     }
@@ -3709,6 +4506,17 @@ impl AggregatorImpl for FMP4Mux {
             state.sent_headers = false;
             for stream in state.streams.iter_mut().filter(|s| s.next_caps.is_some()) {
                 stream.caps = stream.next_caps.take().unwrap();
+                if let Some(ref mut encryptor) = stream.encryptor {
+                    if let Err(err) = encryptor.update_caps(&stream.caps) {
+                        gst::error!(
+                            CAT,
+                            obj = stream.sinkpad,
+                            "Failed to update encryption: {}",
+                            err
+                        );
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                }
             }
         }
 
@@ -3896,6 +4704,27 @@ impl ElementImpl for ISOFMP4Mux {
                         .field("channels", gst::IntRange::<i32>::new(1, 8))
                         .field("rate", gst::IntRange::<i32>::new(1, 10 * u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("audio/x-ac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 6))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-eac3")
+                        .field("framed", true)
+                        .field("alignment", "frame")
+                        .field("channels", gst::IntRange::new(1i32, 16))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-ac4")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -3997,6 +4826,24 @@ impl ElementImpl for CMAFMux {
                         .field("channels", gst::IntRange::new(1i32, 8))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("audio/x-ac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 6))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-eac3")
+                        .field("framed", true)
+                        .field("alignment", "frame")
+                        .field("channels", gst::IntRange::new(1i32, 16))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-ac4")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -4110,6 +4957,24 @@ impl ElementImpl for DASHMP4Mux {
                         .field("channels", gst::IntRange::new(1i32, 8))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("audio/x-ac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 6))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-eac3")
+                        .field("framed", true)
+                        .field("alignment", "frame")
+                        .field("channels", gst::IntRange::new(1i32, 16))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-ac4")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -4237,6 +5102,11 @@ impl FMP4MuxImpl for ONVIFFMP4Mux {
 #[derive(Default, Clone)]
 struct PadSettings {
     trak_timescale: u32,
+    encryption_scheme: super::EncryptionScheme,
+    key_id: Option<String>,
+    key: Option<String>,
+    iv: Option<String>,
+    protection_system_headers: Vec<gst::Buffer>,
 }
 
 #[derive(Default)]
@@ -4254,11 +5124,50 @@ impl ObjectSubclass for FMP4MuxPad {
 impl ObjectImpl for FMP4MuxPad {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-            vec![glib::ParamSpecUInt::builder("trak-timescale")
-                .nick("Track Timescale")
-                .blurb("Timescale to use for the track (units per second, 0 is automatic)")
+            vec![
+                glib::ParamSpecUInt::builder("trak-timescale")
+                    .nick("Track Timescale")
+                    .blurb("Timescale to use for the track (units per second, 0 is automatic)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default(
+                    "encryption-scheme",
+                    super::EncryptionScheme::None,
+                )
+                .nick("Encryption Scheme")
+                .blurb("Common Encryption scheme to use for the samples of this track")
                 .mutable_ready()
-                .build()]
+                .build(),
+                glib::ParamSpecString::builder("key-id")
+                    .nick("Key ID")
+                    .blurb("16 byte key ID as hex string")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("key")
+                    .nick("Key")
+                    .blurb("16 byte AES key as hex string")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("iv")
+                    .nick("IV")
+                    .blurb(
+                        "Initial IV as hex string, 8 bytes for cenc or constant 16 byte IV \
+                         for cbcs (random if not set)",
+                    )
+                    .mutable_ready()
+                    .build(),
+                gst::ParamSpecArray::builder("protection-system-headers")
+                    .nick("Protection System Headers")
+                    .blurb("Complete pssh boxes to include in the header")
+                    .element_spec(
+                        &glib::ParamSpecBoxed::builder::<gst::Buffer>("pssh")
+                            .nick("PSSH")
+                            .blurb("Complete pssh box")
+                            .build(),
+                    )
+                    .mutable_ready()
+                    .build(),
+            ]
         });
 
         &PROPERTIES
@@ -4271,6 +5180,36 @@ impl ObjectImpl for FMP4MuxPad {
                 settings.trak_timescale = value.get().expect("type checked upstream");
             }
 
+            "encryption-scheme" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.encryption_scheme = value.get().expect("type checked upstream");
+            }
+
+            "key-id" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.key_id = value.get().expect("type checked upstream");
+            }
+
+            "key" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.key = value.get().expect("type checked upstream");
+            }
+
+            "iv" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.iv = value.get().expect("type checked upstream");
+            }
+
+            "protection-system-headers" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.protection_system_headers = value
+                    .get::<gst::ArrayRef>()
+                    .expect("type checked upstream")
+                    .iter()
+                    .map(|v| v.get::<gst::Buffer>().expect("type checked upstream"))
+                    .collect();
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -4282,6 +5221,31 @@ impl ObjectImpl for FMP4MuxPad {
                 settings.trak_timescale.to_value()
             }
 
+            "encryption-scheme" => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_scheme.to_value()
+            }
+
+            "key-id" => {
+                let settings = self.settings.lock().unwrap();
+                settings.key_id.to_value()
+            }
+
+            "key" => {
+                let settings = self.settings.lock().unwrap();
+                settings.key.to_value()
+            }
+
+            "iv" => {
+                let settings = self.settings.lock().unwrap();
+                settings.iv.to_value()
+            }
+
+            "protection-system-headers" => {
+                let settings = self.settings.lock().unwrap();
+                gst::Array::new(&settings.protection_system_headers).to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
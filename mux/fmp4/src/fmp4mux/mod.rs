@@ -9,10 +9,13 @@
 use gst::glib;
 use gst::prelude::*;
 
+mod ac3;
 mod boxes;
+mod encryption;
 mod imp;
 
 mod obu;
+mod slice_header;
 
 glib::wrapper! {
     pub(crate) struct FMP4MuxPad(ObjectSubclass<imp::FMP4MuxPad>) @extends gst_base::AggregatorPad, gst::Pad, gst::Object;
@@ -44,6 +47,8 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         FMP4Mux::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
         FMP4MuxPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
         HeaderUpdateMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        EncryptionScheme::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        SidxMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
     gst::Element::register(
         Some(plugin),
@@ -79,11 +84,10 @@ pub(crate) enum ImageOrientation {
     Rotate90,
     Rotate180,
     Rotate270,
-    // TODO:
-    // FlipRotate0,
-    // FlipRotate90,
-    // FlipRotate180,
-    // FlipRotate270,
+    FlipRotate0,
+    FlipRotate90,
+    FlipRotate180,
+    FlipRotate270,
 }
 
 type TransformMatrix = [[u8; 4]; 9];
@@ -136,13 +140,83 @@ const ROTATE_270_MATRIX: TransformMatrix = [
     (1u32 << 30).to_be_bytes(),
 ];
 
+const FLIP_ROTATE_0_MATRIX: TransformMatrix = [
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_90_MATRIX: TransformMatrix = [
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_180_MATRIX: TransformMatrix = [
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_270_MATRIX: TransformMatrix = [
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
 impl ImageOrientation {
+    /// Parses the value of an `image-orientation` tag.
+    pub(crate) fn from_tag(orientation: &str) -> Option<Self> {
+        match orientation {
+            "rotate-0" => Some(ImageOrientation::Rotate0),
+            "rotate-90" => Some(ImageOrientation::Rotate90),
+            "rotate-180" => Some(ImageOrientation::Rotate180),
+            "rotate-270" => Some(ImageOrientation::Rotate270),
+            "flip-rotate-0" => Some(ImageOrientation::FlipRotate0),
+            "flip-rotate-90" => Some(ImageOrientation::FlipRotate90),
+            "flip-rotate-180" => Some(ImageOrientation::FlipRotate180),
+            "flip-rotate-270" => Some(ImageOrientation::FlipRotate270),
+            _ => None,
+        }
+    }
+
+    /// Returns the `tkhd` transformation matrix.
+    ///
+    /// Flipped orientations are mirrored horizontally first and then rotated clockwise.
     pub(crate) fn transform_matrix(&self) -> &'static TransformMatrix {
         match self {
             ImageOrientation::Rotate0 => &IDENTITY_MATRIX,
             ImageOrientation::Rotate90 => &ROTATE_90_MATRIX,
             ImageOrientation::Rotate180 => &ROTATE_180_MATRIX,
             ImageOrientation::Rotate270 => &ROTATE_270_MATRIX,
+            ImageOrientation::FlipRotate0 => &FLIP_ROTATE_0_MATRIX,
+            ImageOrientation::FlipRotate90 => &FLIP_ROTATE_90_MATRIX,
+            ImageOrientation::FlipRotate180 => &FLIP_ROTATE_180_MATRIX,
+            ImageOrientation::FlipRotate270 => &FLIP_ROTATE_270_MATRIX,
         }
     }
 }
@@ -161,12 +235,19 @@ pub(crate) struct HeaderConfiguration {
 
     write_mehd: bool,
     duration: Option<gst::ClockTime>,
+
+    /// Write edit lists and bitrates, or reserve space for them if this is not the update at the
+    /// end of the stream.
+    write_edts_btrt: bool,
     language_code: Option<[u8; 3]>,
     orientation: Option<ImageOrientation>,
 
     /// Start UTC time in ONVIF mode.
     /// Since Jan 1 1601 in 100ns units.
     start_utc_time: Option<u64>,
+
+    /// Complete `pssh` boxes to write into the `moov`.
+    protection_system_headers: Vec<gst::Buffer>,
 }
 
 #[derive(Debug)]
@@ -182,6 +263,39 @@ pub(crate) struct HeaderStream {
 
     // More data to be included in the fragmented stream header
     extra_header_data: Option<Vec<u8>>,
+
+    /// Set if the samples of this stream are encrypted
+    encryption: Option<encryption::TrackEncryption>,
+
+    /// Presentation range of this stream for the edit list, only known at the end of the stream
+    presentation_range: Option<PresentationRange>,
+
+    /// Bitrates of this stream, only known at the end of the stream
+    bitrates: Option<Bitrates>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PresentationRange {
+    /// Earliest PTS of the stream in the media timeline
+    start: gst::ClockTime,
+
+    /// End PTS of the stream in the media timeline
+    end: gst::ClockTime,
+
+    /// Duration of priming samples at the start of the stream that are not presented
+    priming: gst::ClockTime,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bitrates {
+    /// Size of the biggest sample in bytes
+    buffer_size: u32,
+
+    /// Maximum bitrate over any one second window in bits per second
+    max_bitrate: u32,
+
+    /// Average bitrate in bits per second
+    avg_bitrate: u32,
 }
 
 #[derive(Debug)]
@@ -196,6 +310,16 @@ pub(crate) struct FragmentHeaderConfiguration<'a> {
 
     streams: &'a [FragmentHeaderStream],
     buffers: &'a [Buffer],
+
+    /// Duration of this fragment or chunk.
+    duration: gst::ClockTime,
+
+    /// Earliest PTS of the reference stream if a `sidx` box indexing this fragment or chunk
+    /// should be written.
+    sidx_earliest_pts: Option<gst::ClockTime>,
+
+    /// NTP timestamp of the reference stream's start time for the `prft` box.
+    producer_reference_time: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -213,6 +337,11 @@ pub(crate) struct FragmentHeaderStream {
     ///
     /// `None` if this stream has no buffers in this fragment.
     start_time: Option<gst::ClockTime>,
+
+    /// Earliest PTS of this fragment
+    ///
+    /// `None` if this stream has no buffers in this fragment.
+    earliest_pts: Option<gst::ClockTime>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -252,6 +381,9 @@ pub(crate) struct Buffer {
 
     /// Composition time offset
     composition_time_offset: Option<i64>,
+
+    /// Sample auxiliary information if the buffer was encrypted
+    sample_encryption: Option<encryption::SampleEncryption>,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -278,6 +410,18 @@ pub(crate) struct FragmentOffset {
     offset: u64,
 }
 
+#[derive(Debug)]
+pub(crate) struct SidxReference {
+    /// Size of the referenced fragment in bytes
+    size: u64,
+
+    /// Duration of the referenced fragment
+    duration: gst::ClockTime,
+
+    /// Set if the referenced fragment starts with a stream access point
+    starts_with_sap: bool,
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum)]
 #[repr(i32)]
@@ -287,3 +431,23 @@ pub(crate) enum HeaderUpdateMode {
     Rewrite,
     Update,
 }
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(i32)]
+#[enum_type(name = "GstFMP4MuxSidxMode")]
+pub(crate) enum SidxMode {
+    #[default]
+    None,
+    Fragment,
+    Global,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(i32)]
+#[enum_type(name = "GstFMP4MuxEncryptionScheme")]
+pub(crate) enum EncryptionScheme {
+    #[default]
+    None,
+    Cenc,
+    Cbcs,
+}
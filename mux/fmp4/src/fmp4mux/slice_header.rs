@@ -0,0 +1,1226 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Parsing of H.264 and H.265 parameter sets and slice headers, only as far as needed for
+//! finding the size of slice headers. These have to stay in the clear with Common Encryption.
+//!
+//! H.264: ITU-T H.264 (08/2021) 7.3.2.1, 7.3.2.2 and 7.3.3
+//! H.265: ITU-T H.265 (08/2021) 7.3.2.2, 7.3.2.3 and 7.3.6
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NalFormat {
+    H264,
+    H265,
+}
+
+impl NalFormat {
+    pub(crate) fn header_size(self) -> usize {
+        match self {
+            NalFormat::H264 => 1,
+            NalFormat::H265 => 2,
+        }
+    }
+}
+
+/// Bit reader over the RBSP of a NAL unit that removes emulation prevention bytes on the fly
+/// and keeps track of the position in the escaped data.
+struct RbspReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    zeros: usize,
+    current: u8,
+    bits_left: u32,
+}
+
+impl<'a> RbspReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        RbspReader {
+            data,
+            pos: 0,
+            zeros: 0,
+            current: 0,
+            bits_left: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<u8, Error> {
+        loop {
+            let b = *self.data.get(self.pos).context("Truncated NAL unit")?;
+            self.pos += 1;
+
+            if self.zeros >= 2 && b == 0x03 {
+                self.zeros = 0;
+                continue;
+            }
+
+            if b == 0 {
+                self.zeros += 1;
+            } else {
+                self.zeros = 0;
+            }
+
+            return Ok(b);
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        if self.bits_left == 0 {
+            self.current = self.next_byte()?;
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+
+        Ok((self.current >> self.bits_left) & 1 == 1)
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u32, Error> {
+        assert!(n <= 32);
+
+        let mut v = 0u64;
+        for _ in 0..n {
+            v = (v << 1) | u64::from(self.read_bit()?);
+        }
+
+        Ok(v as u32)
+    }
+
+    fn skip_bits(&mut self, mut n: u64) -> Result<(), Error> {
+        while n > 0 {
+            if self.bits_left == 0 {
+                self.current = self.next_byte()?;
+                self.bits_left = 8;
+            }
+            let skip = std::cmp::min(n, u64::from(self.bits_left));
+            self.bits_left -= skip as u32;
+            n -= skip;
+        }
+
+        Ok(())
+    }
+
+    fn read_ue(&mut self) -> Result<u32, Error> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                bail!("Invalid Exp-Golomb code");
+            }
+        }
+
+        Ok(((1u64 << leading_zeros) - 1 + u64::from(self.read_bits(leading_zeros)?)) as u32)
+    }
+
+    /// Reads an Exp-Golomb code and checks that it's not bigger than `max`.
+    fn read_ue_max(&mut self, max: u32) -> Result<u32, Error> {
+        let v = self.read_ue()?;
+        if v > max {
+            bail!("Invalid value {v}, maximum is {max}");
+        }
+
+        Ok(v)
+    }
+
+    fn read_se(&mut self) -> Result<i64, Error> {
+        let k = i64::from(self.read_ue()?);
+        if k % 2 == 1 {
+            Ok((k + 1) / 2)
+        } else {
+            Ok(-(k / 2))
+        }
+    }
+
+    /// Number of bytes of the escaped data that were read so far, including the current
+    /// partially read byte.
+    fn consumed_bytes(&self) -> usize {
+        self.pos
+    }
+}
+
+fn ceil_log2(v: u32) -> u32 {
+    if v <= 1 {
+        0
+    } else {
+        32 - (v - 1).leading_zeros()
+    }
+}
+
+const H264_SLICE_P: u32 = 0;
+const H264_SLICE_B: u32 = 1;
+const H264_SLICE_I: u32 = 2;
+const H264_SLICE_SP: u32 = 3;
+const H264_SLICE_SI: u32 = 4;
+
+#[derive(Debug)]
+struct H264Sps {
+    separate_colour_plane: bool,
+    chroma_array_type: u32,
+    log2_max_frame_num: u32,
+    pic_order_cnt_type: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    delta_pic_order_always_zero: bool,
+    frame_mbs_only: bool,
+}
+
+#[derive(Debug)]
+struct H264Pps {
+    sps_id: u32,
+    entropy_coding_mode: bool,
+    bottom_field_pic_order_in_frame_present: bool,
+    num_ref_idx_l0_default_active: u32,
+    num_ref_idx_l1_default_active: u32,
+    weighted_pred: bool,
+    weighted_bipred_idc: u32,
+    deblocking_filter_control_present: bool,
+    redundant_pic_cnt_present: bool,
+}
+
+const H265_SLICE_B: u32 = 0;
+const H265_SLICE_P: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+struct H265StRefPicSet {
+    num_delta_pocs: u32,
+    /// Number of pictures used for reference by the current picture.
+    num_used: u32,
+}
+
+#[derive(Debug)]
+struct H265Sps {
+    separate_colour_plane: bool,
+    chroma_array_type: u32,
+    pic_size_in_ctbs: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    sample_adaptive_offset_enabled: bool,
+    st_ref_pic_sets: Vec<H265StRefPicSet>,
+    long_term_ref_pics_present: bool,
+    /// `used_by_curr_pic_lt_sps_flag` of each long-term reference picture of the SPS.
+    lt_ref_pics_used: Vec<bool>,
+    temporal_mvp_enabled: bool,
+}
+
+#[derive(Debug)]
+struct H265Pps {
+    sps_id: u32,
+    dependent_slice_segments_enabled: bool,
+    output_flag_present: bool,
+    num_extra_slice_header_bits: u32,
+    cabac_init_present: bool,
+    num_ref_idx_l0_default_active: u32,
+    num_ref_idx_l1_default_active: u32,
+    slice_chroma_qp_offsets_present: bool,
+    weighted_pred: bool,
+    weighted_bipred: bool,
+    tiles_enabled: bool,
+    entropy_coding_sync_enabled: bool,
+    loop_filter_across_slices_enabled: bool,
+    deblocking_filter_override_enabled: bool,
+    deblocking_filter_disabled: bool,
+    lists_modification_present: bool,
+    slice_segment_header_extension_present: bool,
+    chroma_qp_offset_list_enabled: bool,
+}
+
+/// Parameter sets of a stream, collected from the codec data and in-band NAL units.
+#[derive(Debug, Default)]
+pub(crate) struct ParameterSets {
+    h264_sps: HashMap<u32, H264Sps>,
+    h264_pps: HashMap<u32, H264Pps>,
+    h265_sps: HashMap<u32, H265Sps>,
+    h265_pps: HashMap<u32, H265Pps>,
+}
+
+impl ParameterSets {
+    /// Parses the parameter sets of `avcC` or `hvcC` codec data.
+    pub(crate) fn parse_codec_data(
+        &mut self,
+        format: NalFormat,
+        codec_data: &[u8],
+    ) -> Result<(), Error> {
+        fn read_nal<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], Error> {
+            let len = data.get(*pos..*pos + 2).context("Truncated codec data")?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let nal = data
+                .get(*pos + 2..*pos + 2 + len)
+                .context("Truncated codec data")?;
+            *pos += 2 + len;
+            Ok(nal)
+        }
+
+        let mut nals = Vec::new();
+        match format {
+            NalFormat::H264 => {
+                let num_sps = codec_data.get(5).context("Truncated codec data")? & 0x1f;
+                let mut pos = 6;
+                for _ in 0..num_sps {
+                    nals.push(read_nal(codec_data, &mut pos)?);
+                }
+                let num_pps = *codec_data.get(pos).context("Truncated codec data")?;
+                pos += 1;
+                for _ in 0..num_pps {
+                    nals.push(read_nal(codec_data, &mut pos)?);
+                }
+            }
+            NalFormat::H265 => {
+                let num_arrays = *codec_data.get(22).context("Truncated codec data")?;
+                let mut pos = 23;
+                for _ in 0..num_arrays {
+                    // array_completeness, NAL unit type and number of NAL units
+                    let num_nals = codec_data
+                        .get(pos + 1..pos + 3)
+                        .context("Truncated codec data")?;
+                    let num_nals = u16::from_be_bytes([num_nals[0], num_nals[1]]);
+                    pos += 3;
+                    for _ in 0..num_nals {
+                        nals.push(read_nal(codec_data, &mut pos)?);
+                    }
+                }
+            }
+        }
+
+        for nal in nals {
+            self.parse_nal(format, nal)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses a NAL unit, storing parameter sets for later use.
+    ///
+    /// Returns the size of the NAL unit header and the slice header for slices, including
+    /// emulation prevention bytes and a final partial byte, or `None` for other NAL units.
+    pub(crate) fn parse_nal(
+        &mut self,
+        format: NalFormat,
+        nal: &[u8],
+    ) -> Result<Option<usize>, Error> {
+        if nal.len() < format.header_size() {
+            bail!("Truncated NAL unit header");
+        }
+        let mut r = RbspReader::new(&nal[format.header_size()..]);
+
+        match format {
+            NalFormat::H264 => match nal[0] & 0x1f {
+                1 | 5 => {
+                    let size = self.h264_slice_header_size(nal[0], &mut r)?;
+                    return Ok(Some(format.header_size() + size));
+                }
+                2..=4 => bail!("Data partitioning not supported"),
+                7 => {
+                    let (id, sps) = parse_h264_sps(&mut r).context("Invalid SPS")?;
+                    self.h264_sps.insert(id, sps);
+                }
+                8 => {
+                    let (id, pps) = parse_h264_pps(&mut r).context("Invalid PPS")?;
+                    self.h264_pps.insert(id, pps);
+                }
+                _ => (),
+            },
+            NalFormat::H265 => match (nal[0] >> 1) & 0x3f {
+                nal_type @ 0..=31 => {
+                    let size = self.h265_slice_header_size(nal_type, &mut r)?;
+                    return Ok(Some(format.header_size() + size));
+                }
+                33 => {
+                    let (id, sps) = parse_h265_sps(&mut r).context("Invalid SPS")?;
+                    self.h265_sps.insert(id, sps);
+                }
+                34 => {
+                    let (id, pps) = parse_h265_pps(&mut r).context("Invalid PPS")?;
+                    self.h265_pps.insert(id, pps);
+                }
+                _ => (),
+            },
+        }
+
+        Ok(None)
+    }
+
+    fn h264_slice_header_size(&self, header: u8, r: &mut RbspReader) -> Result<usize, Error> {
+        let nal_ref_idc = (header >> 5) & 0x03;
+        let idr = header & 0x1f == 5;
+
+        let _first_mb_in_slice = r.read_ue()?;
+        let slice_type = r.read_ue_max(9)? % 5;
+        let pps_id = r.read_ue_max(255)?;
+        let pps = self
+            .h264_pps
+            .get(&pps_id)
+            .with_context(|| format!("Unknown PPS {pps_id}"))?;
+        let sps = self
+            .h264_sps
+            .get(&pps.sps_id)
+            .with_context(|| format!("Unknown SPS {}", pps.sps_id))?;
+
+        if sps.separate_colour_plane {
+            // colour_plane_id
+            r.skip_bits(2)?;
+        }
+        // frame_num
+        r.skip_bits(u64::from(sps.log2_max_frame_num))?;
+        let mut field_pic = false;
+        if !sps.frame_mbs_only {
+            field_pic = r.read_bit()?;
+            if field_pic {
+                // bottom_field_flag
+                r.skip_bits(1)?;
+            }
+        }
+        if idr {
+            let _idr_pic_id = r.read_ue()?;
+        }
+        if sps.pic_order_cnt_type == 0 {
+            // pic_order_cnt_lsb
+            r.skip_bits(u64::from(sps.log2_max_pic_order_cnt_lsb))?;
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                let _delta_pic_order_cnt_bottom = r.read_se()?;
+            }
+        }
+        if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero {
+            let _delta_pic_order_cnt_0 = r.read_se()?;
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                let _delta_pic_order_cnt_1 = r.read_se()?;
+            }
+        }
+        if pps.redundant_pic_cnt_present {
+            let _redundant_pic_cnt = r.read_ue()?;
+        }
+        if slice_type == H264_SLICE_B {
+            // direct_spatial_mv_pred_flag
+            r.skip_bits(1)?;
+        }
+
+        let mut num_ref_idx_l0_active = pps.num_ref_idx_l0_default_active;
+        let mut num_ref_idx_l1_active = pps.num_ref_idx_l1_default_active;
+        if matches!(slice_type, H264_SLICE_P | H264_SLICE_SP | H264_SLICE_B) {
+            // num_ref_idx_active_override_flag
+            if r.read_bit()? {
+                num_ref_idx_l0_active = r.read_ue_max(31)? + 1;
+                if slice_type == H264_SLICE_B {
+                    num_ref_idx_l1_active = r.read_ue_max(31)? + 1;
+                }
+            }
+        }
+        if slice_type != H264_SLICE_B {
+            num_ref_idx_l1_active = 0;
+        }
+
+        // ref_pic_list_modification()
+        if !matches!(slice_type, H264_SLICE_I | H264_SLICE_SI) {
+            let num_lists = if slice_type == H264_SLICE_B { 2 } else { 1 };
+            for _ in 0..num_lists {
+                // ref_pic_list_modification_flag_lX
+                if !r.read_bit()? {
+                    continue;
+                }
+                loop {
+                    match r.read_ue()? {
+                        0..=2 => {
+                            let _abs_diff_pic_num_or_long_term_pic_num = r.read_ue()?;
+                        }
+                        3 => break,
+                        idc => bail!("Invalid modification_of_pic_nums_idc {idc}"),
+                    }
+                }
+            }
+        }
+
+        if (pps.weighted_pred && matches!(slice_type, H264_SLICE_P | H264_SLICE_SP))
+            || (pps.weighted_bipred_idc == 1 && slice_type == H264_SLICE_B)
+        {
+            // pred_weight_table()
+            let _luma_log2_weight_denom = r.read_ue()?;
+            if sps.chroma_array_type != 0 {
+                let _chroma_log2_weight_denom = r.read_ue()?;
+            }
+            for num_ref_idx_active in [num_ref_idx_l0_active, num_ref_idx_l1_active] {
+                for _ in 0..num_ref_idx_active {
+                    // luma_weight_flag
+                    if r.read_bit()? {
+                        r.read_se()?;
+                        r.read_se()?;
+                    }
+                    // chroma_weight_flag
+                    if sps.chroma_array_type != 0 && r.read_bit()? {
+                        for _ in 0..4 {
+                            r.read_se()?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if nal_ref_idc != 0 {
+            // dec_ref_pic_marking()
+            if idr {
+                // no_output_of_prior_pics_flag, long_term_reference_flag
+                r.skip_bits(2)?;
+            } else if r.read_bit()? {
+                // adaptive_ref_pic_marking_mode_flag
+                loop {
+                    match r.read_ue()? {
+                        0 => break,
+                        1 | 2 | 4 | 6 => {
+                            r.read_ue()?;
+                        }
+                        3 => {
+                            r.read_ue()?;
+                            r.read_ue()?;
+                        }
+                        5 => (),
+                        op => bail!("Invalid memory_management_control_operation {op}"),
+                    }
+                }
+            }
+        }
+
+        if pps.entropy_coding_mode && !matches!(slice_type, H264_SLICE_I | H264_SLICE_SI) {
+            let _cabac_init_idc = r.read_ue()?;
+        }
+        let _slice_qp_delta = r.read_se()?;
+        if matches!(slice_type, H264_SLICE_SP | H264_SLICE_SI) {
+            if slice_type == H264_SLICE_SP {
+                // sp_for_switch_flag
+                r.skip_bits(1)?;
+            }
+            let _slice_qs_delta = r.read_se()?;
+        }
+        if pps.deblocking_filter_control_present {
+            let disable_deblocking_filter_idc = r.read_ue()?;
+            if disable_deblocking_filter_idc != 1 {
+                let _slice_alpha_c0_offset_div2 = r.read_se()?;
+                let _slice_beta_offset_div2 = r.read_se()?;
+            }
+        }
+
+        Ok(r.consumed_bytes())
+    }
+
+    fn h265_slice_header_size(&self, nal_type: u8, r: &mut RbspReader) -> Result<usize, Error> {
+        let first_slice_segment_in_pic = r.read_bit()?;
+        if (16..=23).contains(&nal_type) {
+            // no_output_of_prior_pics_flag
+            r.skip_bits(1)?;
+        }
+        let pps_id = r.read_ue_max(63)?;
+        let pps = self
+            .h265_pps
+            .get(&pps_id)
+            .with_context(|| format!("Unknown PPS {pps_id}"))?;
+        let sps = self
+            .h265_sps
+            .get(&pps.sps_id)
+            .with_context(|| format!("Unknown SPS {}", pps.sps_id))?;
+
+        let mut dependent_slice_segment = false;
+        if !first_slice_segment_in_pic {
+            if pps.dependent_slice_segments_enabled {
+                dependent_slice_segment = r.read_bit()?;
+            }
+            // slice_segment_address
+            r.skip_bits(u64::from(ceil_log2(sps.pic_size_in_ctbs)))?;
+        }
+
+        if !dependent_slice_segment {
+            // slice_reserved_flag
+            r.skip_bits(u64::from(pps.num_extra_slice_header_bits))?;
+            let slice_type = r.read_ue_max(2)?;
+            if pps.output_flag_present {
+                // pic_output_flag
+                r.skip_bits(1)?;
+            }
+            if sps.separate_colour_plane {
+                // colour_plane_id
+                r.skip_bits(2)?;
+            }
+
+            let mut num_pic_total_curr = 0;
+            let mut slice_temporal_mvp_enabled = false;
+            // Not IDR
+            if nal_type != 19 && nal_type != 20 {
+                // slice_pic_order_cnt_lsb
+                r.skip_bits(u64::from(sps.log2_max_pic_order_cnt_lsb))?;
+
+                // short_term_ref_pic_set_sps_flag
+                let st_ref_pic_set = if !r.read_bit()? {
+                    parse_h265_st_ref_pic_set(r, &sps.st_ref_pic_sets, true)?
+                } else {
+                    let num_sets = sps.st_ref_pic_sets.len() as u32;
+                    let idx = r.read_bits(ceil_log2(num_sets))?;
+                    *sps.st_ref_pic_sets
+                        .get(idx as usize)
+                        .context("Invalid short_term_ref_pic_set_idx")?
+                };
+                num_pic_total_curr += st_ref_pic_set.num_used;
+
+                if sps.long_term_ref_pics_present {
+                    let num_lt_ref_pics_sps = sps.lt_ref_pics_used.len() as u32;
+                    let num_long_term_sps = if num_lt_ref_pics_sps > 0 {
+                        r.read_ue_max(num_lt_ref_pics_sps)?
+                    } else {
+                        0
+                    };
+                    let num_long_term_pics = r.read_ue_max(32)?;
+                    for i in 0..num_long_term_sps + num_long_term_pics {
+                        let used_by_curr_pic_lt = if i < num_long_term_sps {
+                            let idx = r.read_bits(ceil_log2(num_lt_ref_pics_sps))?;
+                            *sps.lt_ref_pics_used
+                                .get(idx as usize)
+                                .context("Invalid lt_idx_sps")?
+                        } else {
+                            // poc_lsb_lt
+                            r.skip_bits(u64::from(sps.log2_max_pic_order_cnt_lsb))?;
+                            r.read_bit()?
+                        };
+                        if used_by_curr_pic_lt {
+                            num_pic_total_curr += 1;
+                        }
+                        // delta_poc_msb_present_flag
+                        if r.read_bit()? {
+                            let _delta_poc_msb_cycle_lt = r.read_ue()?;
+                        }
+                    }
+                }
+
+                if sps.temporal_mvp_enabled {
+                    slice_temporal_mvp_enabled = r.read_bit()?;
+                }
+            }
+
+            let mut slice_sao_luma = false;
+            let mut slice_sao_chroma = false;
+            if sps.sample_adaptive_offset_enabled {
+                slice_sao_luma = r.read_bit()?;
+                if sps.chroma_array_type != 0 {
+                    slice_sao_chroma = r.read_bit()?;
+                }
+            }
+
+            if slice_type == H265_SLICE_P || slice_type == H265_SLICE_B {
+                let mut num_ref_idx_l0_active = pps.num_ref_idx_l0_default_active;
+                let mut num_ref_idx_l1_active = pps.num_ref_idx_l1_default_active;
+                // num_ref_idx_active_override_flag
+                if r.read_bit()? {
+                    num_ref_idx_l0_active = r.read_ue_max(14)? + 1;
+                    if slice_type == H265_SLICE_B {
+                        num_ref_idx_l1_active = r.read_ue_max(14)? + 1;
+                    }
+                }
+                if slice_type != H265_SLICE_B {
+                    num_ref_idx_l1_active = 0;
+                }
+
+                if pps.lists_modification_present && num_pic_total_curr > 1 {
+                    // ref_pic_lists_modification()
+                    let bits = u64::from(ceil_log2(num_pic_total_curr));
+                    // ref_pic_list_modification_flag_l0
+                    if r.read_bit()? {
+                        r.skip_bits(u64::from(num_ref_idx_l0_active) * bits)?;
+                    }
+                    // ref_pic_list_modification_flag_l1
+                    if slice_type == H265_SLICE_B && r.read_bit()? {
+                        r.skip_bits(u64::from(num_ref_idx_l1_active) * bits)?;
+                    }
+                }
+
+                if slice_type == H265_SLICE_B {
+                    // mvd_l1_zero_flag
+                    r.skip_bits(1)?;
+                }
+                if pps.cabac_init_present {
+                    // cabac_init_flag
+                    r.skip_bits(1)?;
+                }
+                if slice_temporal_mvp_enabled {
+                    let collocated_from_l0 = if slice_type == H265_SLICE_B {
+                        r.read_bit()?
+                    } else {
+                        true
+                    };
+                    if (collocated_from_l0 && num_ref_idx_l0_active > 1)
+                        || (!collocated_from_l0 && num_ref_idx_l1_active > 1)
+                    {
+                        let _collocated_ref_idx = r.read_ue()?;
+                    }
+                }
+
+                if (pps.weighted_pred && slice_type == H265_SLICE_P)
+                    || (pps.weighted_bipred && slice_type == H265_SLICE_B)
+                {
+                    // pred_weight_table()
+                    let _luma_log2_weight_denom = r.read_ue()?;
+                    if sps.chroma_array_type != 0 {
+                        let _delta_chroma_log2_weight_denom = r.read_se()?;
+                    }
+                    for num_ref_idx_active in [num_ref_idx_l0_active, num_ref_idx_l1_active] {
+                        let mut luma_weight_flags = [false; 15];
+                        let mut chroma_weight_flags = [false; 15];
+                        let count = num_ref_idx_active as usize;
+                        for flag in &mut luma_weight_flags[..count] {
+                            *flag = r.read_bit()?;
+                        }
+                        if sps.chroma_array_type != 0 {
+                            for flag in &mut chroma_weight_flags[..count] {
+                                *flag = r.read_bit()?;
+                            }
+                        }
+                        for (luma, chroma) in luma_weight_flags[..count]
+                            .iter()
+                            .zip(&chroma_weight_flags[..count])
+                        {
+                            if *luma {
+                                r.read_se()?;
+                                r.read_se()?;
+                            }
+                            if *chroma {
+                                for _ in 0..4 {
+                                    r.read_se()?;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let _five_minus_max_num_merge_cand = r.read_ue()?;
+            }
+
+            let _slice_qp_delta = r.read_se()?;
+            if pps.slice_chroma_qp_offsets_present {
+                let _slice_cb_qp_offset = r.read_se()?;
+                let _slice_cr_qp_offset = r.read_se()?;
+            }
+            if pps.chroma_qp_offset_list_enabled {
+                // cu_chroma_qp_offset_enabled_flag
+                r.skip_bits(1)?;
+            }
+
+            let mut deblocking_filter_override = false;
+            if pps.deblocking_filter_override_enabled {
+                deblocking_filter_override = r.read_bit()?;
+            }
+            let mut slice_deblocking_filter_disabled = pps.deblocking_filter_disabled;
+            if deblocking_filter_override {
+                slice_deblocking_filter_disabled = r.read_bit()?;
+                if !slice_deblocking_filter_disabled {
+                    let _slice_beta_offset_div2 = r.read_se()?;
+                    let _slice_tc_offset_div2 = r.read_se()?;
+                }
+            }
+            if pps.loop_filter_across_slices_enabled
+                && (slice_sao_luma || slice_sao_chroma || !slice_deblocking_filter_disabled)
+            {
+                // slice_loop_filter_across_slices_enabled_flag
+                r.skip_bits(1)?;
+            }
+        }
+
+        if pps.tiles_enabled || pps.entropy_coding_sync_enabled {
+            let num_entry_point_offsets = r.read_ue()?;
+            if num_entry_point_offsets > 0 {
+                let offset_len = r.read_ue_max(31)? + 1;
+                r.skip_bits(u64::from(num_entry_point_offsets) * u64::from(offset_len))?;
+            }
+        }
+
+        if pps.slice_segment_header_extension_present {
+            let slice_segment_header_extension_length = r.read_ue_max(256)?;
+            r.skip_bits(u64::from(slice_segment_header_extension_length) * 8)?;
+        }
+
+        // byte_alignment(), the remaining bits of the current byte are zero
+        if !r.read_bit()? {
+            bail!("Invalid byte alignment after slice header");
+        }
+
+        Ok(r.consumed_bytes())
+    }
+}
+
+fn skip_h264_scaling_list(r: &mut RbspReader, size: usize) -> Result<(), Error> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = r.read_se()?;
+            next_scale = (last_scale + delta_scale).rem_euclid(256);
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_h264_sps(r: &mut RbspReader) -> Result<(u32, H264Sps), Error> {
+    let profile_idc = r.read_bits(8)?;
+    // constraint_set flags, level_idc
+    r.skip_bits(16)?;
+    let id = r.read_ue_max(31)?;
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.read_ue_max(3)?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_bit()?;
+        }
+        let _bit_depth_luma_minus8 = r.read_ue()?;
+        let _bit_depth_chroma_minus8 = r.read_ue()?;
+        // qpprime_y_zero_transform_bypass_flag
+        r.skip_bits(1)?;
+        // seq_scaling_matrix_present_flag
+        if r.read_bit()? {
+            let num_lists = if chroma_format_idc != 3 { 8 } else { 12 };
+            for i in 0..num_lists {
+                // seq_scaling_list_present_flag
+                if r.read_bit()? {
+                    skip_h264_scaling_list(r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    let log2_max_frame_num = r.read_ue_max(12)? + 4;
+    let pic_order_cnt_type = r.read_ue_max(2)?;
+    let mut log2_max_pic_order_cnt_lsb = 0;
+    let mut delta_pic_order_always_zero = false;
+    if pic_order_cnt_type == 0 {
+        log2_max_pic_order_cnt_lsb = r.read_ue_max(12)? + 4;
+    } else if pic_order_cnt_type == 1 {
+        delta_pic_order_always_zero = r.read_bit()?;
+        let _offset_for_non_ref_pic = r.read_se()?;
+        let _offset_for_top_to_bottom_field = r.read_se()?;
+        let num_ref_frames_in_pic_order_cnt_cycle = r.read_ue_max(255)?;
+        for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
+            let _offset_for_ref_frame = r.read_se()?;
+        }
+    }
+    let _max_num_ref_frames = r.read_ue()?;
+    // gaps_in_frame_num_value_allowed_flag
+    r.skip_bits(1)?;
+    let _pic_width_in_mbs_minus1 = r.read_ue()?;
+    let _pic_height_in_map_units_minus1 = r.read_ue()?;
+    let frame_mbs_only = r.read_bit()?;
+
+    Ok((
+        id,
+        H264Sps {
+            separate_colour_plane,
+            chroma_array_type: if separate_colour_plane {
+                0
+            } else {
+                chroma_format_idc
+            },
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            delta_pic_order_always_zero,
+            frame_mbs_only,
+        },
+    ))
+}
+
+fn parse_h264_pps(r: &mut RbspReader) -> Result<(u32, H264Pps), Error> {
+    let id = r.read_ue_max(255)?;
+    let sps_id = r.read_ue_max(31)?;
+    let entropy_coding_mode = r.read_bit()?;
+    let bottom_field_pic_order_in_frame_present = r.read_bit()?;
+    if r.read_ue()? != 0 {
+        bail!("Slice groups not supported");
+    }
+    let num_ref_idx_l0_default_active = r.read_ue_max(31)? + 1;
+    let num_ref_idx_l1_default_active = r.read_ue_max(31)? + 1;
+    let weighted_pred = r.read_bit()?;
+    let weighted_bipred_idc = r.read_bits(2)?;
+    let _pic_init_qp_minus26 = r.read_se()?;
+    let _pic_init_qs_minus26 = r.read_se()?;
+    let _chroma_qp_index_offset = r.read_se()?;
+    let deblocking_filter_control_present = r.read_bit()?;
+    // constrained_intra_pred_flag
+    r.skip_bits(1)?;
+    let redundant_pic_cnt_present = r.read_bit()?;
+
+    Ok((
+        id,
+        H264Pps {
+            sps_id,
+            entropy_coding_mode,
+            bottom_field_pic_order_in_frame_present,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            weighted_pred,
+            weighted_bipred_idc,
+            deblocking_filter_control_present,
+            redundant_pic_cnt_present,
+        },
+    ))
+}
+
+fn skip_h265_profile_tier_level(
+    r: &mut RbspReader,
+    max_sub_layers_minus1: usize,
+) -> Result<(), Error> {
+    // General profile space, tier, profile, compatibility and constraint flags, level
+    r.skip_bits(96)?;
+
+    let mut sub_layers_present = [(false, false); 7];
+    for present in &mut sub_layers_present[..max_sub_layers_minus1] {
+        *present = (r.read_bit()?, r.read_bit()?);
+    }
+    if max_sub_layers_minus1 > 0 {
+        // reserved_zero_2bits
+        r.skip_bits(2 * (8 - max_sub_layers_minus1 as u64))?;
+    }
+    for (profile_present, level_present) in &sub_layers_present[..max_sub_layers_minus1] {
+        if *profile_present {
+            r.skip_bits(88)?;
+        }
+        if *level_present {
+            r.skip_bits(8)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_h265_scaling_list_data(r: &mut RbspReader) -> Result<(), Error> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _matrix_id in (0..6).step_by(step) {
+            // scaling_list_pred_mode_flag
+            if !r.read_bit()? {
+                let _scaling_list_pred_matrix_id_delta = r.read_ue()?;
+            } else {
+                let coef_num = std::cmp::min(64, 1 << (4 + (size_id << 1)));
+                if size_id > 1 {
+                    let _scaling_list_dc_coef_minus8 = r.read_se()?;
+                }
+                for _ in 0..coef_num {
+                    let _scaling_list_delta_coef = r.read_se()?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses the `st_ref_pic_set()` following the `previous` ones of the SPS.
+fn parse_h265_st_ref_pic_set(
+    r: &mut RbspReader,
+    previous: &[H265StRefPicSet],
+    in_slice_header: bool,
+) -> Result<H265StRefPicSet, Error> {
+    let idx = previous.len();
+
+    let inter_ref_pic_set_prediction = idx != 0 && r.read_bit()?;
+    if inter_ref_pic_set_prediction {
+        let delta_idx = if in_slice_header {
+            r.read_ue_max(idx as u32 - 1)? + 1
+        } else {
+            1
+        };
+        // delta_rps_sign
+        r.skip_bits(1)?;
+        let _abs_delta_rps_minus1 = r.read_ue()?;
+
+        let ref_rps = &previous[idx - delta_idx as usize];
+        let mut num_delta_pocs = 0;
+        let mut num_used = 0;
+        for _ in 0..=ref_rps.num_delta_pocs {
+            let used_by_curr_pic = r.read_bit()?;
+            let use_delta = used_by_curr_pic || r.read_bit()?;
+            if use_delta {
+                num_delta_pocs += 1;
+            }
+            if used_by_curr_pic {
+                num_used += 1;
+            }
+        }
+
+        Ok(H265StRefPicSet {
+            num_delta_pocs,
+            num_used,
+        })
+    } else {
+        let num_negative_pics = r.read_ue_max(16)?;
+        let num_positive_pics = r.read_ue_max(16)?;
+        let mut num_used = 0;
+        for _ in 0..num_negative_pics + num_positive_pics {
+            let _delta_poc_minus1 = r.read_ue()?;
+            // used_by_curr_pic_flag
+            if r.read_bit()? {
+                num_used += 1;
+            }
+        }
+
+        Ok(H265StRefPicSet {
+            num_delta_pocs: num_negative_pics + num_positive_pics,
+            num_used,
+        })
+    }
+}
+
+fn parse_h265_sps(r: &mut RbspReader) -> Result<(u32, H265Sps), Error> {
+    // sps_video_parameter_set_id
+    r.skip_bits(4)?;
+    let max_sub_layers_minus1 = r.read_bits(3)? as usize;
+    if max_sub_layers_minus1 > 6 {
+        bail!("Invalid sps_max_sub_layers_minus1 {max_sub_layers_minus1}");
+    }
+    // sps_temporal_id_nesting_flag
+    r.skip_bits(1)?;
+    skip_h265_profile_tier_level(r, max_sub_layers_minus1)?;
+
+    let id = r.read_ue_max(15)?;
+    let chroma_format_idc = r.read_ue_max(3)?;
+    let mut separate_colour_plane = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = r.read_bit()?;
+    }
+    let pic_width = r.read_ue()?;
+    let pic_height = r.read_ue()?;
+    // conformance_window_flag
+    if r.read_bit()? {
+        for _ in 0..4 {
+            let _conf_win_offset = r.read_ue()?;
+        }
+    }
+    let _bit_depth_luma_minus8 = r.read_ue()?;
+    let _bit_depth_chroma_minus8 = r.read_ue()?;
+    let log2_max_pic_order_cnt_lsb = r.read_ue_max(12)? + 4;
+    let sub_layer_ordering_info_present = r.read_bit()?;
+    let first_sub_layer = if sub_layer_ordering_info_present {
+        0
+    } else {
+        max_sub_layers_minus1
+    };
+    for _ in first_sub_layer..=max_sub_layers_minus1 {
+        let _max_dec_pic_buffering_minus1 = r.read_ue()?;
+        let _max_num_reorder_pics = r.read_ue()?;
+        let _max_latency_increase_plus1 = r.read_ue()?;
+    }
+    let log2_min_luma_coding_block_size = r.read_ue_max(3)? + 3;
+    let log2_ctb_size = log2_min_luma_coding_block_size + r.read_ue_max(3)?;
+    let _log2_min_luma_transform_block_size_minus2 = r.read_ue()?;
+    let _log2_diff_max_min_luma_transform_block_size = r.read_ue()?;
+    let _max_transform_hierarchy_depth_inter = r.read_ue()?;
+    let _max_transform_hierarchy_depth_intra = r.read_ue()?;
+    // scaling_list_enabled_flag, sps_scaling_list_data_present_flag
+    if r.read_bit()? && r.read_bit()? {
+        skip_h265_scaling_list_data(r)?;
+    }
+    // amp_enabled_flag
+    r.skip_bits(1)?;
+    let sample_adaptive_offset_enabled = r.read_bit()?;
+    // pcm_enabled_flag
+    if r.read_bit()? {
+        // pcm_sample_bit_depth_luma_minus1, pcm_sample_bit_depth_chroma_minus1
+        r.skip_bits(8)?;
+        let _log2_min_pcm_luma_coding_block_size_minus3 = r.read_ue()?;
+        let _log2_diff_max_min_pcm_luma_coding_block_size = r.read_ue()?;
+        // pcm_loop_filter_disabled_flag
+        r.skip_bits(1)?;
+    }
+
+    let num_short_term_ref_pic_sets = r.read_ue_max(64)?;
+    let mut st_ref_pic_sets = Vec::with_capacity(num_short_term_ref_pic_sets as usize);
+    for _ in 0..num_short_term_ref_pic_sets {
+        let st_ref_pic_set = parse_h265_st_ref_pic_set(r, &st_ref_pic_sets, false)?;
+        st_ref_pic_sets.push(st_ref_pic_set);
+    }
+
+    let long_term_ref_pics_present = r.read_bit()?;
+    let mut lt_ref_pics_used = Vec::new();
+    if long_term_ref_pics_present {
+        let num_long_term_ref_pics_sps = r.read_ue_max(32)?;
+        for _ in 0..num_long_term_ref_pics_sps {
+            // lt_ref_pic_poc_lsb_sps
+            r.skip_bits(u64::from(log2_max_pic_order_cnt_lsb))?;
+            lt_ref_pics_used.push(r.read_bit()?);
+        }
+    }
+    let temporal_mvp_enabled = r.read_bit()?;
+
+    let ctb_size = 1u64 << log2_ctb_size;
+    let pic_size_in_ctbs = ((u64::from(pic_width) + ctb_size - 1) / ctb_size)
+        * ((u64::from(pic_height) + ctb_size - 1) / ctb_size);
+    let pic_size_in_ctbs = u32::try_from(pic_size_in_ctbs).context("Too big picture size")?;
+
+    Ok((
+        id,
+        H265Sps {
+            separate_colour_plane,
+            chroma_array_type: if separate_colour_plane {
+                0
+            } else {
+                chroma_format_idc
+            },
+            pic_size_in_ctbs,
+            log2_max_pic_order_cnt_lsb,
+            sample_adaptive_offset_enabled,
+            st_ref_pic_sets,
+            long_term_ref_pics_present,
+            lt_ref_pics_used,
+            temporal_mvp_enabled,
+        },
+    ))
+}
+
+fn parse_h265_pps(r: &mut RbspReader) -> Result<(u32, H265Pps), Error> {
+    let id = r.read_ue_max(63)?;
+    let sps_id = r.read_ue_max(15)?;
+    let dependent_slice_segments_enabled = r.read_bit()?;
+    let output_flag_present = r.read_bit()?;
+    let num_extra_slice_header_bits = r.read_bits(3)?;
+    // sign_data_hiding_enabled_flag
+    r.skip_bits(1)?;
+    let cabac_init_present = r.read_bit()?;
+    let num_ref_idx_l0_default_active = r.read_ue_max(14)? + 1;
+    let num_ref_idx_l1_default_active = r.read_ue_max(14)? + 1;
+    let _init_qp_minus26 = r.read_se()?;
+    // constrained_intra_pred_flag
+    r.skip_bits(1)?;
+    let transform_skip_enabled = r.read_bit()?;
+    // cu_qp_delta_enabled_flag
+    if r.read_bit()? {
+        let _diff_cu_qp_delta_depth = r.read_ue()?;
+    }
+    let _pps_cb_qp_offset = r.read_se()?;
+    let _pps_cr_qp_offset = r.read_se()?;
+    let slice_chroma_qp_offsets_present = r.read_bit()?;
+    let weighted_pred = r.read_bit()?;
+    let weighted_bipred = r.read_bit()?;
+    // transquant_bypass_enabled_flag
+    r.skip_bits(1)?;
+    let tiles_enabled = r.read_bit()?;
+    let entropy_coding_sync_enabled = r.read_bit()?;
+    if tiles_enabled {
+        let num_tile_columns_minus1 = r.read_ue()?;
+        let num_tile_rows_minus1 = r.read_ue()?;
+        // uniform_spacing_flag
+        if !r.read_bit()? {
+            for _ in 0..num_tile_columns_minus1 {
+                let _column_width_minus1 = r.read_ue()?;
+            }
+            for _ in 0..num_tile_rows_minus1 {
+                let _row_height_minus1 = r.read_ue()?;
+            }
+        }
+        // loop_filter_across_tiles_enabled_flag
+        r.skip_bits(1)?;
+    }
+    let loop_filter_across_slices_enabled = r.read_bit()?;
+    let mut deblocking_filter_override_enabled = false;
+    let mut deblocking_filter_disabled = false;
+    // deblocking_filter_control_present_flag
+    if r.read_bit()? {
+        deblocking_filter_override_enabled = r.read_bit()?;
+        deblocking_filter_disabled = r.read_bit()?;
+        if !deblocking_filter_disabled {
+            let _pps_beta_offset_div2 = r.read_se()?;
+            let _pps_tc_offset_div2 = r.read_se()?;
+        }
+    }
+    // pps_scaling_list_data_present_flag
+    if r.read_bit()? {
+        skip_h265_scaling_list_data(r)?;
+    }
+    let lists_modification_present = r.read_bit()?;
+    let _log2_parallel_merge_level_minus2 = r.read_ue()?;
+    let slice_segment_header_extension_present = r.read_bit()?;
+
+    let mut chroma_qp_offset_list_enabled = false;
+    // pps_extension_present_flag
+    if r.read_bit()? {
+        let range_extension = r.read_bit()?;
+        // pps_multilayer_extension_flag, pps_3d_extension_flag, pps_scc_extension_flag,
+        // pps_extension_4bits
+        r.skip_bits(7)?;
+        if range_extension {
+            if transform_skip_enabled {
+                let _log2_max_transform_skip_block_size_minus2 = r.read_ue()?;
+            }
+            // cross_component_prediction_enabled_flag
+            r.skip_bits(1)?;
+            chroma_qp_offset_list_enabled = r.read_bit()?;
+        }
+    }
+
+    Ok((
+        id,
+        H265Pps {
+            sps_id,
+            dependent_slice_segments_enabled,
+            output_flag_present,
+            num_extra_slice_header_bits,
+            cabac_init_present,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            slice_chroma_qp_offsets_present,
+            weighted_pred,
+            weighted_bipred,
+            tiles_enabled,
+            entropy_coding_sync_enabled,
+            loop_filter_across_slices_enabled,
+            deblocking_filter_override_enabled,
+            deblocking_filter_disabled,
+            lists_modification_present,
+            slice_segment_header_extension_present,
+            chroma_qp_offset_list_enabled,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rbsp_reader_exp_golomb() {
+        // ue(0), ue(3), se(-2), ue(7) followed by an emulation prevention byte
+        let data = [0b1001_0000, 0b1010_0010, 0x00, 0x00, 0x03, 0x01];
+        let mut r = RbspReader::new(&data);
+        assert_eq!(r.read_ue().unwrap(), 0);
+        assert_eq!(r.read_ue().unwrap(), 3);
+        assert_eq!(r.read_se().unwrap(), -2);
+        assert_eq!(r.read_ue().unwrap(), 7);
+        r.skip_bits(6 + 8).unwrap();
+        assert_eq!(r.read_bits(8).unwrap(), 0x01);
+        assert_eq!(r.consumed_bytes(), data.len());
+    }
+
+    #[test]
+    fn test_h264_slice_header_size() {
+        let mut parameter_sets = ParameterSets::default();
+        parameter_sets
+            .parse_nal(
+                NalFormat::H264,
+                &[0x67, 0x42, 0xc0, 0x1e, 0xf4, 0x03, 0xc0, 0x11, 0x32],
+            )
+            .unwrap();
+        parameter_sets
+            .parse_nal(NalFormat::H264, &[0x68, 0xce, 0x3c, 0x80])
+            .unwrap();
+
+        let idr = [0x65, 0x88, 0x84, 0x0f, 0xaa, 0xaa];
+        assert_eq!(
+            parameter_sets.parse_nal(NalFormat::H264, &idr).unwrap(),
+            Some(4)
+        );
+
+        // Slice referring to an unknown PPS
+        let slice = [0x41, 0x9c, 0xaa, 0xaa];
+        assert!(parameter_sets.parse_nal(NalFormat::H264, &slice).is_err());
+    }
+}
@@ -2825,3 +2825,1107 @@ fn test_caps_change_before_first_frame() {
 
     assert_eq!(h.buffers_in_queue(), 0);
 }
+
+fn find_fourcc(data: &[u8], fourcc: &[u8; 4]) -> Option<usize> {
+    data.windows(4).position(|w| w == fourcc)
+}
+
+/// `avcC` with a 1920x1080 Baseline SPS with `log2_max_frame_num` and `log2_max_pic_order_cnt_lsb`
+/// of 4, and a CAVLC PPS with deblocking filter control.
+const ENCRYPTION_H264_CODEC_DATA: [u8; 24] = [
+    0x01, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0x00, 0x09, 0x67, 0x42, 0xc0, 0x1e, 0xf4, 0x03, 0xc0, 0x11,
+    0x32, 0x01, 0x00, 0x04, 0x68, 0xce, 0x3c, 0x80,
+];
+
+/// Length-prefixed IDR I slice NAL unit with a 3 byte slice header and `payload_size` bytes
+/// of slice data.
+fn encryption_h264_idr_sample(payload_size: usize) -> Vec<u8> {
+    let mut sample = Vec::from((4 + payload_size as u32).to_be_bytes());
+    sample.extend([0x65, 0x88, 0x84, 0x0f]);
+    sample.extend(std::iter::repeat(0xaa).take(payload_size));
+    sample
+}
+
+#[test]
+fn test_encryption_cenc_h264() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice(ENCRYPTION_H264_CODEC_DATA),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 5.seconds());
+
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property_from_str("encryption-scheme", "cenc");
+    sinkpad.set_property("key-id", "00112233445566778899aabbccddeeff");
+    sinkpad.set_property("key", "000102030405060708090a0b0c0d0e0f");
+    sinkpad.set_property("iv", "0102030405060708");
+
+    h.set_src_caps(caps);
+    h.play();
+
+    // A single IDR slice NAL unit with 100 bytes per buffer
+    let sample = encryption_h264_idr_sample(96);
+
+    for i in 0..2 {
+        let mut buffer = gst::Buffer::from_slice(sample.clone());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    for fourcc in [b"encv", b"sinf", b"frma", b"schm", b"tenc"] {
+        assert!(find_fourcc(&header, fourcc).is_some());
+    }
+    let frma = find_fourcc(&header, b"frma").unwrap();
+    assert_eq!(&header[frma + 4..][..4], b"avc1");
+    let schm = find_fourcc(&header, b"schm").unwrap();
+    assert_eq!(&header[schm + 8..][..4], b"cenc");
+    let tenc = find_fourcc(&header, b"tenc").unwrap();
+    // Protected with 8 byte IVs and the configured key ID
+    assert_eq!(&header[tenc + 10..][..2], &[1, 8]);
+    assert_eq!(
+        &header[tenc + 12..][..16],
+        &[
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff
+        ]
+    );
+
+    let fragment_header = h.pull().unwrap();
+    let fragment_header = fragment_header.map_readable().unwrap();
+    let moof_start = find_fourcc(&fragment_header, b"moof").unwrap() - 4;
+    let saio = find_fourcc(&fragment_header, b"saio").unwrap();
+    let senc = find_fourcc(&fragment_header, b"senc").unwrap();
+    assert!(find_fourcc(&fragment_header, b"saiz").is_some());
+
+    // saio points to the first IV in the senc box
+    let aux_info_offset = u32::from_be_bytes(fragment_header[saio + 12..][..4].try_into().unwrap());
+    let aux_info_pos = moof_start + aux_info_offset as usize;
+    assert_eq!(aux_info_pos, senc + 12);
+
+    // Subsample flag and two samples
+    assert_eq!(&fragment_header[senc + 4..][..4], &[0, 0, 0, 2]);
+    assert_eq!(&fragment_header[senc + 8..][..4], &[0, 0, 0, 2]);
+    // IV, one subsample with the NAL unit length, NAL unit header and slice header in the clear
+    // and the slice data protected
+    assert_eq!(
+        &fragment_header[aux_info_pos..][..16],
+        &[1, 2, 3, 4, 5, 6, 7, 8, 0, 1, 0, 8, 0, 0, 0, 96]
+    );
+    // Next IV
+    assert_eq!(
+        &fragment_header[aux_info_pos + 16..][..8],
+        &[1, 2, 3, 4, 5, 6, 7, 9]
+    );
+
+    for _ in 0..2 {
+        let buffer = h.pull().unwrap();
+        let buffer = buffer.map_readable().unwrap();
+        assert_eq!(buffer.len(), sample.len());
+        assert_eq!(&buffer[..8], &sample[..8]);
+        assert_ne!(&buffer[8..], &sample[8..]);
+    }
+}
+
+#[test]
+fn test_encryption_cbcs_h264_long_slice_header() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice(ENCRYPTION_H264_CODEC_DATA),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 5.seconds());
+
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property_from_str("encryption-scheme", "cbcs");
+    sinkpad.set_property("key-id", "00112233445566778899aabbccddeeff");
+    sinkpad.set_property("key", "000102030405060708090a0b0c0d0e0f");
+    sinkpad.set_property("iv", "0102030405060708090a0b0c0d0e0f10");
+
+    h.set_src_caps(caps);
+    h.play();
+
+    let idr_sample = encryption_h264_idr_sample(96);
+
+    // P slice with 16 memory management control operations, which makes the slice header 403
+    // bits long. It contains two emulation prevention bytes, so the slice header ends in the
+    // 53rd byte after the NAL unit header.
+    let slice_header = [
+        0x9a, 0x24, 0x50, 0x03, 0xe9, 0x40, 0x0f, 0xa9, 0x00, 0x3e, 0xb4, 0x00, 0xfb, 0x10, 0x03,
+        0xed, 0x40, 0x0f, 0xb9, 0x00, 0x3e, 0xf4, 0x00, 0xfc, 0x10, 0x03, 0xf1, 0x40, 0x0f, 0xc9,
+        0x00, 0x3f, 0x34, 0x00, 0xfd, 0x10, 0x03, 0xf5, 0x40, 0x0f, 0xd9, 0x00, 0x3f, 0x74, 0x00,
+        0x00, 0x03, 0x02, 0x00, 0x00, 0x03, 0x03, 0x5f,
+    ];
+    let mut p_sample = Vec::from((1 + slice_header.len() as u32 + 200).to_be_bytes());
+    p_sample.push(0x41);
+    p_sample.extend(slice_header);
+    p_sample.extend(std::iter::repeat(0xaa).take(200));
+
+    for (i, sample) in [&idr_sample, &p_sample].into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_slice(sample.clone());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts((i as u64).seconds());
+            buffer.set_dts((i as u64).seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    let schm = find_fourcc(&header, b"schm").unwrap();
+    assert_eq!(&header[schm + 8..][..4], b"cbcs");
+
+    let fragment_header = h.pull().unwrap();
+    let fragment_header = fragment_header.map_readable().unwrap();
+    let moof_start = find_fourcc(&fragment_header, b"moof").unwrap() - 4;
+    let saio = find_fourcc(&fragment_header, b"saio").unwrap();
+    let aux_info_offset = u32::from_be_bytes(fragment_header[saio + 12..][..4].try_into().unwrap());
+    let aux_info_pos = moof_start + aux_info_offset as usize;
+
+    // No per-sample IVs, one subsample per sample with the NAL unit length, NAL unit header and
+    // the complete slice header in the clear
+    assert_eq!(
+        &fragment_header[aux_info_pos..][..16],
+        &[0, 1, 0, 8, 0, 0, 0, 96, 0, 1, 0, 58, 0, 0, 0, 200]
+    );
+
+    // IDR: 1 encrypted block, the remaining 5 blocks are skipped
+    let buffer = h.pull().unwrap();
+    let buffer = buffer.map_readable().unwrap();
+    assert_eq!(&buffer[..8], &idr_sample[..8]);
+    assert_ne!(&buffer[8..24], &idr_sample[8..24]);
+    assert_eq!(&buffer[24..], &idr_sample[24..]);
+
+    // P: 1 encrypted block, 9 skipped blocks, 1 encrypted block, 1 skipped block and a trailing
+    // partial block
+    let buffer = h.pull().unwrap();
+    let buffer = buffer.map_readable().unwrap();
+    assert_eq!(buffer.len(), p_sample.len());
+    assert_eq!(&buffer[..58], &p_sample[..58]);
+    assert_ne!(&buffer[58..74], &p_sample[58..74]);
+    assert_eq!(&buffer[74..218], &p_sample[74..218]);
+    assert_ne!(&buffer[218..234], &p_sample[218..234]);
+    assert_eq!(&buffer[234..], &p_sample[234..]);
+}
+
+#[test]
+fn test_webvtt_gaps() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", 10.seconds());
+    h.set_src_caps(gst::Caps::builder("application/x-subtitle-vtt").build());
+    h.play();
+
+    for (pts, duration, cue) in [
+        (
+            1.seconds(),
+            2.seconds(),
+            "00:01.000 --> 00:03.000 line:0\nHello",
+        ),
+        (5.seconds(), 1.seconds(), "World"),
+    ] {
+        let mut buffer = gst::Buffer::from_slice(cue);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(duration);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    assert!(find_fourcc(&header, b"cwvt").is_some());
+    assert!(find_fourcc(&header, b"wvtt").is_some());
+    assert!(find_fourcc(&header, b"nmhd").is_some());
+    let vttc = find_fourcc(&header, b"vttC").unwrap();
+    assert_eq!(&header[vttc + 4..][..6], b"WEBVTT");
+    let hdlr = find_fourcc(&header, b"hdlr").unwrap();
+    assert_eq!(&header[hdlr + 12..][..4], b"text");
+
+    let fragment_header = h.pull().unwrap();
+    assert!(fragment_header.flags().contains(gst::BufferFlags::HEADER));
+
+    // Cue, empty sample for the gap and the second cue
+    let expected: [(gst::ClockTime, gst::ClockTime, &[u8; 4]); 3] = [
+        (1.seconds(), 2.seconds(), b"vttc"),
+        (3.seconds(), 2.seconds(), b"vtte"),
+        (5.seconds(), 1.seconds(), b"vttc"),
+    ];
+    for (pts, duration, fourcc) in expected {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(pts));
+        assert_eq!(buffer.duration(), Some(duration));
+
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(&map[4..8], fourcc);
+        if pts == 1.seconds() {
+            let sttg = find_fourcc(&map, b"sttg").unwrap();
+            assert_eq!(&map[sttg + 4..][..6], b"line:0");
+            let payl = find_fourcc(&map, b"payl").unwrap();
+            assert_eq!(&map[payl + 4..], b"Hello");
+        }
+    }
+}
+
+/// Returns the type and content of all boxes in `data`.
+fn parse_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let fourcc = data[4..8].try_into().unwrap();
+        let (header_size, size) = match size {
+            0 => (8, data.len()),
+            1 => (
+                16,
+                u64::from_be_bytes(data[8..16].try_into().unwrap()) as usize,
+            ),
+            size => (8, size),
+        };
+        boxes.push((fourcc, &data[header_size..size]));
+        data = &data[size..];
+    }
+    boxes
+}
+
+/// Returns the duration and data of all samples of the track with `track_id` in `fragment`,
+/// which contains the `moof` and the complete `mdat`.
+fn fragment_samples(fragment: &[u8], track_id: u32) -> Vec<(u32, Vec<u8>)> {
+    let read_u32 =
+        |data: &[u8], pos: usize| u32::from_be_bytes(data[pos..][..4].try_into().unwrap());
+
+    let moof_start = find_fourcc(fragment, b"moof").unwrap() - 4;
+    let (_, moof) = parse_boxes(&fragment[moof_start..])[0];
+
+    let mut samples = Vec::new();
+    for (_, traf) in parse_boxes(moof)
+        .into_iter()
+        .filter(|(fourcc, _)| fourcc == b"traf")
+    {
+        let traf = parse_boxes(traf);
+        let &(_, tfhd) = traf.iter().find(|(fourcc, _)| fourcc == b"tfhd").unwrap();
+        if read_u32(tfhd, 4) != track_id {
+            continue;
+        }
+
+        let tf_flags = read_u32(tfhd, 0) & 0xff_ff_ff;
+        let mut pos = 8;
+        // Base data offset and sample description index
+        if tf_flags & 0x01 != 0 {
+            pos += 8;
+        }
+        if tf_flags & 0x02 != 0 {
+            pos += 4;
+        }
+        let mut default_duration = None;
+        if tf_flags & 0x08 != 0 {
+            default_duration = Some(read_u32(tfhd, pos));
+            pos += 4;
+        }
+        let default_size = (tf_flags & 0x10 != 0).then(|| read_u32(tfhd, pos));
+
+        for &(_, trun) in traf.iter().filter(|(fourcc, _)| fourcc == b"trun") {
+            let tr_flags = read_u32(trun, 0) & 0xff_ff_ff;
+            let sample_count = read_u32(trun, 4);
+            assert_ne!(tr_flags & 0x01, 0);
+            let mut data_pos = moof_start + read_u32(trun, 8) as usize;
+            let mut pos = 12;
+            // First sample flags
+            if tr_flags & 0x04 != 0 {
+                pos += 4;
+            }
+
+            for _ in 0..sample_count {
+                let duration = if tr_flags & 0x1_00 != 0 {
+                    pos += 4;
+                    read_u32(trun, pos - 4)
+                } else {
+                    default_duration.unwrap()
+                };
+                let size = if tr_flags & 0x2_00 != 0 {
+                    pos += 4;
+                    read_u32(trun, pos - 4) as usize
+                } else {
+                    default_size.unwrap() as usize
+                };
+                // Sample flags and composition time offset
+                if tr_flags & 0x4_00 != 0 {
+                    pos += 4;
+                }
+                if tr_flags & 0x8_00 != 0 {
+                    pos += 4;
+                }
+
+                samples.push((duration, fragment[data_pos..][..size].to_vec()));
+                data_pos += size;
+            }
+        }
+    }
+
+    samples
+}
+
+fn test_subtitle_gaps_with_video(
+    caps: gst::Caps,
+    cues: [&[u8]; 2],
+    cue_samples: [&[u8]; 2],
+    gap_sample: &[u8],
+) {
+    let mut h1 = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    let mut h2 = gst_check::Harness::with_element(&h1.element().unwrap(), Some("sink_1"), None);
+
+    let element = h1.element().unwrap();
+    element.set_property("fragment-duration", 10.seconds());
+    for pad in ["sink_0", "sink_1"] {
+        element
+            .static_pad(pad)
+            .unwrap()
+            .set_property("trak-timescale", 1000u32);
+    }
+
+    h1.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h1.play();
+
+    h2.set_src_caps(caps);
+    h2.play();
+
+    // 6 video frames of 1s each and two cues of 1s at 1s and 4s, with gap events in between
+    for i in 0..6 {
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h1.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+        let cue = match i {
+            1 => cues[0],
+            4 => cues[1],
+            _ => {
+                let ev = gst::event::Gap::builder(i.seconds())
+                    .duration(gst::ClockTime::SECOND)
+                    .build();
+                assert!(h2.push_event(ev));
+                continue;
+            }
+        };
+        let mut buffer = gst::Buffer::from_slice(cue.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h2.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h1.push_event(gst::event::Eos::new());
+    h2.push_event(gst::event::Eos::new());
+
+    let _header = h1.pull().unwrap();
+
+    let fragment_header = h1.pull().unwrap();
+    assert!(fragment_header.flags().contains(gst::BufferFlags::HEADER));
+    let mut fragment = fragment_header.map_readable().unwrap().to_vec();
+    let mdat = find_fourcc(&fragment, b"mdat").unwrap();
+    let mdat_end =
+        mdat - 4 + u32::from_be_bytes(fragment[mdat - 4..][..4].try_into().unwrap()) as usize;
+    while fragment.len() < mdat_end {
+        let buffer = h1.pull().unwrap();
+        fragment.extend_from_slice(&buffer.map_readable().unwrap());
+    }
+    assert_eq!(fragment.len(), mdat_end);
+
+    let video_samples = fragment_samples(&fragment, 1);
+    assert_eq!(video_samples.len(), 6);
+    assert!(video_samples.iter().all(|(duration, _)| *duration == 1000));
+
+    // The subtitle track covers the whole fragment, with empty samples before, between and
+    // after the cues
+    let subtitle_samples = fragment_samples(&fragment, 2);
+    let expected = [
+        (1000, gap_sample),
+        (1000, cue_samples[0]),
+        (2000, gap_sample),
+        (1000, cue_samples[1]),
+        (1000, gap_sample),
+    ];
+    assert_eq!(subtitle_samples.len(), expected.len());
+    for ((duration, data), (expected_duration, expected_data)) in
+        subtitle_samples.iter().zip(expected)
+    {
+        assert_eq!(*duration, expected_duration);
+        assert_eq!(data.as_slice(), expected_data);
+    }
+}
+
+#[test]
+fn test_ttml_gaps_with_video() {
+    init();
+
+    let hello = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <tt xmlns=\"http://www.w3.org/ns/ttml\"><body><div><p>Hello</p></div></body></tt>";
+    let world = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <tt xmlns=\"http://www.w3.org/ns/ttml\"><body><div><p>World</p></div></body></tt>";
+
+    test_subtitle_gaps_with_video(
+        gst::Caps::builder("application/ttml+xml").build(),
+        [hello, world],
+        [hello, world],
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><tt xmlns=\"http://www.w3.org/ns/ttml\"/>",
+    );
+}
+
+#[test]
+fn test_tx3g_gaps_with_video() {
+    init();
+
+    test_subtitle_gaps_with_video(
+        gst::Caps::builder("text/x-raw")
+            .field("format", "utf8")
+            .build(),
+        [b"Hello", b"World"],
+        [b"\0\x05Hello", b"\0\x05World"],
+        b"\0\0",
+    );
+}
+
+fn find_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> &'a [u8] {
+    parse_boxes(data)
+        .into_iter()
+        .find(|(f, _)| f == fourcc)
+        .map(|(_, content)| content)
+        .unwrap_or_else(|| panic!("No {} box", std::str::from_utf8(fourcc).unwrap()))
+}
+
+/// Muxes three frames with `cmafmux` and checks that the sample entry is `entry` and contains
+/// a `config` box with the content `expected_config`.
+fn test_audio_sample_entry(
+    caps: gst::Caps,
+    frame: &[u8],
+    entry: &[u8; 4],
+    config: &[u8; 4],
+    expected_config: &[u8],
+) {
+    let mut h = gst_check::Harness::new("cmafmux");
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", 5.seconds());
+    h.set_src_caps(caps);
+    h.play();
+
+    for i in 0..3 {
+        let mut buffer = gst::Buffer::from_slice(frame.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts((i * 32).mseconds());
+            buffer.set_dts((i * 32).mseconds());
+            buffer.set_duration(32.mseconds());
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+
+    let trak = find_box(find_box(&header, b"moov"), b"trak");
+    let stsd = find_box(
+        find_box(find_box(find_box(trak, b"mdia"), b"minf"), b"stbl"),
+        b"stsd",
+    );
+    // Version and flags, and a single sample entry
+    assert_eq!(&stsd[..8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+    let (fourcc, sample_entry) = parse_boxes(&stsd[8..])[0];
+    assert_eq!(&fourcc, entry);
+    // The configuration box follows the 28 bytes of the audio sample entry fields
+    assert_eq!(find_box(&sample_entry[28..], config), expected_config);
+
+    let fragment_header = h.pull().unwrap();
+    assert!(fragment_header.flags().contains(gst::BufferFlags::HEADER));
+    for _ in 0..3 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.size(), frame.len());
+    }
+}
+
+#[test]
+fn test_ac3_sample_entry() {
+    init();
+
+    // 48kHz, 192kbit/s, bsid 8, bsmod 0, 3/2 with LFE
+    let mut frame = vec![0u8; 768];
+    frame[..8].copy_from_slice(&[0x0b, 0x77, 0x00, 0x00, 0x14, 0x40, 0xe1, 0xa0]);
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-ac3")
+            .field("framed", true)
+            .field("channels", 6i32)
+            .field("rate", 48_000i32)
+            .build(),
+        &frame,
+        b"ac-3",
+        b"dac3",
+        // fscod 0, bsid 8, bsmod 0, acmod 7, lfeon 1, bit_rate_code 10
+        &[0x10, 0x3d, 0x40],
+    );
+}
+
+#[test]
+fn test_eac3_sample_entry() {
+    init();
+
+    // Independent substream, 48kHz, 6 blocks, 3/2 with LFE, bsid 16, 768 bytes per frame
+    let mut frame = vec![0u8; 768];
+    frame[..6].copy_from_slice(&[0x0b, 0x77, 0x01, 0x7f, 0x3f, 0x80]);
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-eac3")
+            .field("framed", true)
+            .field("alignment", "frame")
+            .field("channels", 6i32)
+            .field("rate", 48_000i32)
+            .build(),
+        &frame,
+        b"ec-3",
+        b"dec3",
+        // 192kbit/s, one independent substream with fscod 0, bsid 16, acmod 7 and LFE
+        &[0x06, 0x00, 0x20, 0x0f, 0x00],
+    );
+}
+
+#[test]
+fn test_ac4_sample_entry() {
+    init();
+
+    let dac4 = [
+        0x20, 0xa6, 0x01, 0x40, 0x00, 0x00, 0x00, 0x1f, 0xff, 0xff, 0xff, 0xe0, 0x01, 0x0e, 0xf9,
+        0x00, 0x00, 0x09, 0x08, 0x00, 0x00, 0x24, 0x00, 0x08, 0x80, 0x10, 0x00,
+    ];
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-ac4")
+            .field("framed", true)
+            .field("channels", 2i32)
+            .field("rate", 48_000i32)
+            .field("codec_data", gst::Buffer::from_slice(dac4))
+            .build(),
+        &[0xac, 0x40, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00],
+        b"ac-4",
+        b"dac4",
+        // Taken as is from the codec data
+        &dac4,
+    );
+}
+
+#[test]
+fn test_sidx_prft_fragment() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    h.use_testclock();
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00]),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 5.seconds());
+    element.set_property("write-prft", true);
+    element.set_property_from_str("sidx-mode", "fragment");
+
+    h.set_src_caps(caps);
+    h.play();
+
+    let sample = vec![0u8; 100];
+    for i in 0..2 {
+        let mut buffer = gst::Buffer::from_slice(sample.clone());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let _header = h.pull().unwrap();
+
+    let fragment_header = h.pull().unwrap();
+    let fragment_header = fragment_header.map_readable().unwrap();
+    let styp = find_fourcc(&fragment_header, b"styp").unwrap();
+    let sidx = find_fourcc(&fragment_header, b"sidx").unwrap();
+    let prft = find_fourcc(&fragment_header, b"prft").unwrap();
+    let moof_start = find_fourcc(&fragment_header, b"moof").unwrap() - 4;
+    assert!(styp < sidx && sidx < prft && prft < moof_start);
+
+    // Version 1, reference track ID 1
+    assert_eq!(fragment_header[sidx + 4], 1);
+    assert_eq!(&fragment_header[sidx + 8..][..4], &[0, 0, 0, 1]);
+    // Earliest presentation time 0
+    assert_eq!(&fragment_header[sidx + 16..][..8], &[0; 8]);
+    // First offset skips the prft box
+    let prft_size = u32::from_be_bytes(fragment_header[prft - 4..][..4].try_into().unwrap());
+    assert_eq!(prft_size, 32);
+    assert_eq!(
+        u64::from_be_bytes(fragment_header[sidx + 24..][..8].try_into().unwrap()),
+        prft_size as u64
+    );
+    // One reference to the moof and mdat with 2 seconds duration, starting with a SAP
+    assert_eq!(&fragment_header[sidx + 34..][..2], &[0, 1]);
+    let referenced_size = u32::from_be_bytes(fragment_header[sidx + 36..][..4].try_into().unwrap());
+    assert_eq!(
+        referenced_size as usize,
+        fragment_header.len() - moof_start + 2 * sample.len()
+    );
+    let timescale = u32::from_be_bytes(fragment_header[sidx + 12..][..4].try_into().unwrap());
+    assert_eq!(
+        u32::from_be_bytes(fragment_header[sidx + 40..][..4].try_into().unwrap()),
+        2 * timescale
+    );
+    assert_eq!(&fragment_header[sidx + 44..][..4], &[0x90, 0, 0, 0]);
+
+    for _ in 0..2 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.size(), sample.len());
+    }
+}
+
+/// Pushes 1s H.264 frames with the given PTS, starting a new GOP every other frame.
+fn push_h264_frames(h: &mut gst_check::Harness, pts: impl IntoIterator<Item = u64>) {
+    for (i, pts) in pts.into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_slice(vec![0u8; 100]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts.seconds());
+            buffer.set_dts(pts.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i % 2 != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+}
+
+#[test]
+fn test_sidx_global() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00]),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 2.seconds());
+    element.set_property_from_str("sidx-mode", "global");
+    element.set_property("sidx-reserved-references", 4u32);
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property("trak-timescale", 1000u32);
+
+    // The global sidx is only rewritten if downstream is seekable
+    element.static_pad("src").unwrap().add_probe(
+        gst::PadProbeType::QUERY_DOWNSTREAM,
+        |_pad, info| {
+            let Some(gst::PadProbeData::Query(ref mut query)) = info.data else {
+                return gst::PadProbeReturn::Ok;
+            };
+            match query.view_mut() {
+                gst::QueryViewMut::Seeking(q) if q.format() == gst::Format::Bytes => {
+                    q.set(
+                        true,
+                        Some(gst::format::Bytes::ZERO),
+                        gst::format::Bytes::NONE,
+                    );
+                    gst::PadProbeReturn::Handled
+                }
+                _ => gst::PadProbeReturn::Ok,
+            }
+        },
+    );
+
+    h.set_src_caps(caps);
+    h.play();
+
+    // Three fragments of 2s, starting at 1s
+    push_h264_frames(&mut h, 1..7);
+    h.push_event(gst::event::Eos::new());
+
+    let mut data = Vec::new();
+
+    let header = h.pull().unwrap();
+    data.extend_from_slice(&header.map_readable().unwrap());
+    let sidx_offset = data.len();
+
+    // Placeholder for the sidx box
+    let placeholder = h.pull().unwrap();
+    assert!(placeholder.flags().contains(gst::BufferFlags::HEADER));
+    let placeholder = placeholder.map_readable().unwrap();
+    assert_eq!(&placeholder[4..8], b"free");
+    data.extend_from_slice(&placeholder);
+    let placeholder_size = placeholder.len();
+
+    // Fragment header and two frames per fragment
+    for _ in 0..3 * 3 {
+        let buffer = h.pull().unwrap();
+        data.extend_from_slice(&buffer.map_readable().unwrap());
+    }
+
+    // The sidx box is written into the reserved space at EOS
+    let segment = loop {
+        let ev = h.pull_event().unwrap();
+        if let gst::EventView::Segment(ev) = ev.view() {
+            if ev.segment().format() == gst::Format::Bytes {
+                break ev.segment().clone();
+            }
+        }
+    };
+    let segment = segment.downcast::<gst::format::Bytes>().unwrap();
+    assert_eq!(
+        segment.start(),
+        Some(gst::format::Bytes::from_usize(sidx_offset))
+    );
+
+    let sidx = h.pull().unwrap();
+    let sidx = sidx.map_readable().unwrap();
+    assert_eq!(sidx.len(), placeholder_size);
+    data[sidx_offset..][..sidx.len()].copy_from_slice(&sidx);
+
+    let boxes = parse_boxes(&data[sidx_offset..sidx_offset + placeholder_size]);
+    assert_eq!(boxes.len(), 2);
+    let (fourcc, sidx) = boxes[0];
+    assert_eq!(&fourcc, b"sidx");
+    assert_eq!(&boxes[1].0, b"free");
+
+    // Version 1, reference track ID 1, timescale
+    assert_eq!(sidx[0], 1);
+    assert_eq!(&sidx[4..8], &[0, 0, 0, 1]);
+    assert_eq!(&sidx[8..12], &1000u32.to_be_bytes());
+    // Earliest presentation time of the first fragment
+    assert_eq!(&sidx[12..20], &1000u64.to_be_bytes());
+    // First offset skips the remaining reserved space
+    let first_offset = u64::from_be_bytes(sidx[20..28].try_into().unwrap()) as usize;
+    assert_eq!(first_offset, boxes[1].1.len() + 8);
+    // Three references
+    assert_eq!(&sidx[30..32], &[0, 3]);
+
+    // Each reference covers a complete fragment of 2s that starts with a SAP
+    let mut pos = sidx_offset + placeholder_size;
+    for reference in sidx[32..].chunks_exact(12) {
+        let start = pos;
+        loop {
+            let size = u32::from_be_bytes(data[pos..][..4].try_into().unwrap()) as usize;
+            let fourcc = &data[pos + 4..][..4];
+            pos += size;
+            if fourcc == b"mdat" {
+                break;
+            }
+        }
+
+        assert_eq!(&reference[..4], &((pos - start) as u32).to_be_bytes());
+        assert_eq!(&reference[4..8], &2000u32.to_be_bytes());
+        assert_eq!(&reference[8..12], &[0x90, 0, 0, 0]);
+    }
+    assert_eq!(pos, data.len());
+}
+
+#[test]
+fn test_prft_values() {
+    init();
+
+    const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    h.use_testclock();
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00]),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 2.seconds());
+    element.set_property("write-prft", true);
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property("trak-timescale", 1000u32);
+
+    h.set_src_caps(caps);
+    h.play();
+
+    let unix_time = || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+
+    // Two fragments of 2s, starting at 1s
+    let before = unix_time();
+    push_h264_frames(&mut h, 1..5);
+    h.push_event(gst::event::Eos::new());
+
+    let _header = h.pull().unwrap();
+
+    for start in [1, 3] {
+        let fragment_header = h.pull().unwrap();
+        let after = unix_time();
+        let fragment_header = fragment_header.map_readable().unwrap();
+        let prft = find_fourcc(&fragment_header, b"prft").unwrap();
+
+        // Version 1 and reference track ID 1
+        assert_eq!(fragment_header[prft + 4], 1);
+        assert_eq!(&fragment_header[prft + 8..][..4], &[0, 0, 0, 1]);
+
+        // NTP time of the fragment start, i.e. the current wall clock time as the test clock
+        // is not advancing, plus the running time of the fragment start
+        let ntp_time = u64::from_be_bytes(fragment_header[prft + 12..][..8].try_into().unwrap());
+        let ntp_seconds = (ntp_time >> 32) - NTP_UNIX_OFFSET;
+        assert!(
+            (before + start..=after + start).contains(&ntp_seconds),
+            "Unexpected NTP time {ntp_seconds} not in [{}, {}]",
+            before + start,
+            after + start
+        );
+
+        // Media time of the fragment start in the track timescale
+        assert_eq!(
+            &fragment_header[prft + 20..][..8],
+            &(start * 1000).to_be_bytes()
+        );
+
+        for _ in 0..2 {
+            let _buffer = h.pull().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_edit_list_bitrates_update() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00]),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 5.seconds());
+    // Edit lists and bitrates are written by default when updating the header
+    element.set_property_from_str("header-update-mode", "update");
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property("trak-timescale", 1000u32);
+
+    h.set_src_caps(caps);
+    h.play();
+
+    // Frame reordering, the first frame is presented at 1s
+    for (i, (dts, pts)) in [(0, 1), (1, 3), (2, 2)].into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_slice(vec![0u8; 100]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts.seconds());
+            buffer.set_dts(dts.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    assert!(find_fourcc(&header, b"edts").is_none());
+    assert!(find_fourcc(&header, b"btrt").is_none());
+
+    for _ in 0..4 {
+        let _buffer = h.pull().unwrap();
+    }
+
+    let updated_header = h.pull().unwrap();
+    let updated_header = updated_header.map_readable().unwrap();
+    assert_eq!(updated_header.len(), header.len());
+
+    let elst = find_fourcc(&updated_header, b"elst").unwrap();
+    // Version 1 and a single entry
+    assert_eq!(updated_header[elst + 4], 1);
+    assert_eq!(&updated_header[elst + 8..][..4], &[0, 0, 0, 1]);
+    // Edit duration and media time
+    assert_eq!(
+        u64::from_be_bytes(updated_header[elst + 12..][..8].try_into().unwrap()),
+        3000
+    );
+    assert_eq!(
+        i64::from_be_bytes(updated_header[elst + 20..][..8].try_into().unwrap()),
+        1000
+    );
+
+    let btrt = find_fourcc(&updated_header, b"btrt").unwrap();
+    // Buffer size, max and average bitrate
+    assert_eq!(&updated_header[btrt + 4..][..4], &100u32.to_be_bytes());
+    assert_eq!(&updated_header[btrt + 8..][..4], &800u32.to_be_bytes());
+    assert_eq!(&updated_header[btrt + 12..][..4], &800u32.to_be_bytes());
+}
+
+#[test]
+fn test_header_update_without_edts_btrt() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field(
+            "codec_data",
+            gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x28, 0xff, 0xe0, 0x00]),
+        )
+        .build();
+
+    let element = h.element().unwrap();
+    element.set_property("fragment-duration", 5.seconds());
+    element.set_property_from_str("header-update-mode", "update");
+    element.set_property("write-edts-btrt", false);
+    let sinkpad = element.static_pad("sink").unwrap();
+    sinkpad.set_property("trak-timescale", 1000u32);
+
+    h.set_src_caps(caps);
+    h.play();
+
+    for (i, (dts, pts)) in [(0, 1), (1, 3), (2, 2)].into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_slice(vec![0u8; 100]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts.seconds());
+            buffer.set_dts(dts.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    // With write-edts-btrt disabled no space is reserved for edit lists or bitrates
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    assert!(find_fourcc(&header, b"free").is_none());
+
+    for _ in 0..4 {
+        let _buffer = h.pull().unwrap();
+    }
+
+    let updated_header = h.pull().unwrap();
+    let updated_header = updated_header.map_readable().unwrap();
+    assert_eq!(updated_header.len(), header.len());
+    assert!(find_fourcc(&updated_header, b"elst").is_none());
+    assert!(find_fourcc(&updated_header, b"btrt").is_none());
+}
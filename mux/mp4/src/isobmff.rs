@@ -0,0 +1,42 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Box fields of the ISO base media file format shared by the muxer and the demuxer.
+
+pub(crate) const FULL_BOX_VERSION_0: u8 = 0;
+pub(crate) const FULL_BOX_VERSION_1: u8 = 1;
+
+pub(crate) const FULL_BOX_FLAGS_NONE: u32 = 0;
+
+pub(crate) const TKHD_FLAGS_TRACK_ENABLED: u32 = 0x1;
+pub(crate) const TKHD_FLAGS_TRACK_IN_MOVIE: u32 = 0x2;
+pub(crate) const TKHD_FLAGS_TRACK_IN_PREVIEW: u32 = 0x4;
+
+/// Packs an ISO-639-2/T language code like in the `mdhd` box.
+pub(crate) fn language_code(lang: impl std::borrow::Borrow<[u8; 3]>) -> u16 {
+    let lang = lang.borrow();
+
+    assert!(lang.iter().all(u8::is_ascii_lowercase));
+
+    (((lang[0] as u16 - 0x60) & 0x1F) << 10)
+        + (((lang[1] as u16 - 0x60) & 0x1F) << 5)
+        + ((lang[2] as u16 - 0x60) & 0x1F)
+}
+
+/// Inverse of [`language_code`], `None` for invalid codes and the undetermined language.
+pub(crate) fn parse_language_code(code: u16) -> Option<[u8; 3]> {
+    let lang = [
+        (((code >> 10) & 0x1f) as u8) + 0x60,
+        (((code >> 5) & 0x1f) as u8) + 0x60,
+        ((code & 0x1f) as u8) + 0x60,
+    ];
+
+    if lang.iter().all(|c| c.is_ascii_lowercase()) && &lang != b"und" {
+        Some(lang)
+    } else {
+        None
+    }
+}
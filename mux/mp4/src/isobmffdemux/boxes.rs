@@ -0,0 +1,1665 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Context, Error};
+
+use crate::isobmff::{parse_language_code, FULL_BOX_VERSION_0, FULL_BOX_VERSION_1};
+use crate::mp4mux::ImageOrientation;
+
+/// Big-endian reader over the content of a box.
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ByteReader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.remaining() < len {
+            bail!(
+                "short read: need {len} bytes but only {} available",
+                self.remaining()
+            );
+        }
+
+        let bytes = &self.data[self.pos..][..len];
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i16(&mut self) -> Result<i16, Error> {
+        Ok(i16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads a 32 bit value for version 0 boxes and a 64 bit value for version 1 boxes.
+    fn read_u32_or_u64(&mut self, version: u8) -> Result<u64, Error> {
+        if version == FULL_BOX_VERSION_1 {
+            self.read_u64()
+        } else {
+            self.read_u32().map(u64::from)
+        }
+    }
+
+    /// Checks that `count` entries of `entry_size` bytes each fit into the remaining data, so
+    /// that nothing bigger than the box itself is allocated for them.
+    ///
+    /// Entries without any data, like samples of a constant size, are limited to
+    /// [`MAX_SAMPLE_COUNT`] instead.
+    fn check_entry_count(&self, count: u64, entry_size: usize) -> Result<usize, Error> {
+        let max = if entry_size == 0 {
+            MAX_SAMPLE_COUNT
+        } else {
+            self.remaining() / entry_size
+        };
+
+        match usize::try_from(count) {
+            Ok(count) if count <= max => Ok(count),
+            _ => bail!(
+                "{count} entries of {entry_size} bytes don't fit into {} remaining bytes",
+                self.remaining()
+            ),
+        }
+    }
+
+    /// Reads a 32 bit entry count and checks it with [`Self::check_entry_count`].
+    fn read_entry_count(&mut self, entry_size: usize) -> Result<usize, Error> {
+        let count = self.read_u32()?;
+        self.check_entry_count(u64::from(count), entry_size)
+    }
+
+    /// Reads the version and flags of a full box.
+    fn read_full_box_header(&mut self) -> Result<(u8, u32), Error> {
+        let v = self.read_u32()?;
+        Ok(((v >> 24) as u8, v & 0x00_ff_ff_ff))
+    }
+
+    /// Reads a NUL-terminated string.
+    fn read_cstring(&mut self) -> Result<&'a [u8], Error> {
+        let len = self
+            .rest()
+            .iter()
+            .position(|b| *b == 0)
+            .context("unterminated string")?;
+        let s = self.read_bytes(len)?;
+        self.skip(1)?;
+
+        Ok(s)
+    }
+}
+
+/// Header of a box.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct BoxHeader {
+    pub(super) fourcc: [u8; 4],
+    /// Size of the header itself.
+    pub(super) header_size: u64,
+    /// Size of the whole box including the header, or `None` if the box extends until the end
+    /// of the file.
+    pub(super) size: Option<u64>,
+}
+
+/// Maximum size of a box header.
+pub(super) const MAX_BOX_HEADER_SIZE: usize = 16;
+
+/// Maximum number of samples whose size is not stored per sample, corresponding to a sample
+/// table of 50MB like in qtdemux.
+const MAX_SAMPLE_COUNT: usize = 50 * 1024 * 1024 / std::mem::size_of::<Sample>();
+
+/// Parses a box header from the beginning of `data`.
+///
+/// Returns `Ok(None)` if more data is needed.
+pub(super) fn parse_box_header(data: &[u8]) -> Result<Option<BoxHeader>, Error> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+
+    let size = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let fourcc = data[4..8].try_into().unwrap();
+
+    match size {
+        0 => Ok(Some(BoxHeader {
+            fourcc,
+            header_size: 8,
+            size: None,
+        })),
+        1 => {
+            if data.len() < 16 {
+                return Ok(None);
+            }
+
+            let size = u64::from_be_bytes(data[8..16].try_into().unwrap());
+            if size < 16 {
+                bail!("invalid 64 bit box size {size}");
+            }
+
+            Ok(Some(BoxHeader {
+                fourcc,
+                header_size: 16,
+                size: Some(size),
+            }))
+        }
+        2..=7 => bail!("invalid box size {size}"),
+        size => Ok(Some(BoxHeader {
+            fourcc,
+            header_size: 8,
+            size: Some(u64::from(size)),
+        })),
+    }
+}
+
+/// Iterator over the child boxes contained in `data`.
+///
+/// Yields the fourcc and the content of each box without the header.
+struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Boxes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Boxes { data }
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<([u8; 4], &'a [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let header = match parse_box_header(self.data) {
+            Ok(Some(header)) => header,
+            Ok(None) => {
+                self.data = &[];
+                return Some(Err(anyhow!("truncated box header")));
+            }
+            Err(err) => {
+                self.data = &[];
+                return Some(Err(err));
+            }
+        };
+
+        let size = header.size.unwrap_or(self.data.len() as u64);
+        if size > self.data.len() as u64 {
+            self.data = &[];
+            return Some(Err(anyhow!(
+                "box {} with size {size} exceeds parent",
+                fourcc_to_string(&header.fourcc)
+            )));
+        }
+
+        let (b, rest) = self.data.split_at(size as usize);
+        self.data = rest;
+
+        Some(Ok((header.fourcc, &b[header.header_size as usize..])))
+    }
+}
+
+pub(super) fn fourcc_to_string(fourcc: &[u8; 4]) -> String {
+    fourcc.iter().map(|c| char::from(*c)).collect()
+}
+
+/// A sample as described by the sample table or a track run.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Sample {
+    /// Absolute offset of the sample data in the file.
+    pub(super) offset: u64,
+    /// Size of the sample data.
+    pub(super) size: u32,
+    /// Decode time in the track's timescale.
+    pub(super) dts: u64,
+    /// Duration in the track's timescale.
+    pub(super) duration: u32,
+    /// Composition time offset in the track's timescale.
+    pub(super) composition_time_offset: i64,
+    /// Sync point.
+    pub(super) sync_point: bool,
+}
+
+/// Edit list of a track, reduced to what can be expressed by timestamp offsets.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Edit {
+    /// Duration of empty edits at the beginning of the track in the movie timescale.
+    pub(super) empty_duration: u64,
+    /// Media time in the track timescale at which presentation starts.
+    pub(super) media_time: i64,
+}
+
+/// Defaults for track fragments from the `trex` box.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct TrackExtends {
+    pub(super) default_sample_duration: u32,
+    pub(super) default_sample_size: u32,
+    pub(super) default_sample_flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TrackType {
+    Video,
+    Audio,
+    Metadata,
+}
+
+#[derive(Debug)]
+pub(super) struct Track {
+    pub(super) track_id: u32,
+    pub(super) track_type: TrackType,
+    pub(super) timescale: u32,
+    /// Duration in the track timescale, if known.
+    pub(super) duration: Option<u64>,
+    pub(super) language_code: Option<[u8; 3]>,
+    pub(super) orientation: Option<ImageOrientation>,
+    pub(super) caps: gst::Caps,
+    pub(super) edit: Edit,
+    pub(super) extends: TrackExtends,
+    /// All samples from the sample table, sorted by decode time.
+    pub(super) samples: Vec<Sample>,
+}
+
+#[derive(Debug)]
+pub(super) struct Movie {
+    pub(super) timescale: u32,
+    /// Duration in the movie timescale, if known.
+    pub(super) duration: Option<u64>,
+    /// Whether the movie is followed by movie fragments.
+    pub(super) fragmented: bool,
+    pub(super) tracks: Vec<Track>,
+}
+
+impl Movie {
+    pub(super) fn duration(&self) -> Option<gst::ClockTime> {
+        self.duration
+            .filter(|d| *d > 0)
+            .and_then(|d| to_clock_time(d, self.timescale))
+            .or_else(|| {
+                self.tracks
+                    .iter()
+                    .filter_map(|t| t.duration.and_then(|d| to_clock_time(d, t.timescale)))
+                    .max()
+                    .filter(|d| !d.is_zero())
+            })
+    }
+}
+
+/// Converts a value in the given timescale to a `gst::ClockTime`.
+pub(super) fn to_clock_time(value: u64, timescale: u32) -> Option<gst::ClockTime> {
+    use gst::prelude::*;
+
+    if timescale == 0 {
+        return None;
+    }
+
+    value
+        .mul_div_floor(gst::ClockTime::SECOND.nseconds(), timescale as u64)
+        .map(gst::ClockTime::from_nseconds)
+}
+
+/// Parses the content of a `moov` box.
+pub(super) fn parse_moov(data: &[u8]) -> Result<Movie, Error> {
+    let mut timescale = None;
+    let mut duration = None;
+    let mut fragmented = false;
+    let mut fragment_duration = None;
+    let mut tracks = vec![];
+    let mut trex = vec![];
+
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+
+        match &fourcc {
+            b"mvhd" => {
+                let mut r = ByteReader::new(data);
+                let (version, _flags) = r.read_full_box_header()?;
+                // Creation / modification time
+                r.read_u32_or_u64(version)?;
+                r.read_u32_or_u64(version)?;
+                timescale = Some(r.read_u32()?);
+                duration = Some(r.read_u32_or_u64(version)?);
+            }
+            b"trak" => match parse_trak(data) {
+                Ok(Some(track)) => tracks.push(track),
+                Ok(None) => (),
+                Err(err) => return Err(err.context("failed to parse trak")),
+            },
+            b"mvex" => {
+                fragmented = true;
+                for b in Boxes::new(data) {
+                    let (fourcc, data) = b?;
+                    let mut r = ByteReader::new(data);
+                    match &fourcc {
+                        b"mehd" => {
+                            let (version, _flags) = r.read_full_box_header()?;
+                            fragment_duration = Some(r.read_u32_or_u64(version)?);
+                        }
+                        b"trex" => {
+                            let (_version, _flags) = r.read_full_box_header()?;
+                            let track_id = r.read_u32()?;
+                            let _default_sample_description_index = r.read_u32()?;
+                            trex.push((
+                                track_id,
+                                TrackExtends {
+                                    default_sample_duration: r.read_u32()?,
+                                    default_sample_size: r.read_u32()?,
+                                    default_sample_flags: r.read_u32()?,
+                                },
+                            ));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let timescale = timescale.context("no mvhd")?;
+    if timescale == 0 {
+        bail!("invalid movie timescale 0");
+    }
+
+    for (track_id, extends) in trex {
+        if let Some(track) = tracks.iter_mut().find(|t| t.track_id == track_id) {
+            track.extends = extends;
+        }
+    }
+
+    Ok(Movie {
+        timescale,
+        duration: fragment_duration.filter(|d| *d > 0).or(duration),
+        fragmented,
+        tracks,
+    })
+}
+
+/// Parses the content of a `trak` box.
+///
+/// Returns `Ok(None)` for tracks of an unsupported type.
+fn parse_trak(data: &[u8]) -> Result<Option<Track>, Error> {
+    let mut track_id = None;
+    let mut orientation = None;
+    let mut edit = Edit::default();
+    let mut mdia = None;
+
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+
+        match &fourcc {
+            b"tkhd" => {
+                let mut r = ByteReader::new(data);
+                let (version, _flags) = r.read_full_box_header()?;
+                // Creation / modification time
+                r.read_u32_or_u64(version)?;
+                r.read_u32_or_u64(version)?;
+                track_id = Some(r.read_u32()?);
+                // Reserved
+                r.skip(4)?;
+                // Duration
+                r.read_u32_or_u64(version)?;
+                // Reserved, layer, alternate group, volume, reserved
+                r.skip(8 + 2 + 2 + 2 + 2)?;
+                let mut matrix = [[0u8; 4]; 9];
+                for v in &mut matrix {
+                    *v = r.read_array()?;
+                }
+                orientation = ImageOrientation::from_transform_matrix(&matrix);
+            }
+            b"edts" => {
+                for b in Boxes::new(data) {
+                    let (fourcc, data) = b?;
+                    if &fourcc == b"elst" {
+                        edit = parse_elst(data)?;
+                    }
+                }
+            }
+            b"mdia" => {
+                mdia = Some(data);
+            }
+            _ => (),
+        }
+    }
+
+    let track_id = track_id.context("no tkhd")?;
+    let mdia = mdia.context("no mdia")?;
+
+    let mut timescale = None;
+    let mut duration = None;
+    let mut language_code = None;
+    let mut handler_type = None;
+    let mut stbl = None;
+
+    for b in Boxes::new(mdia) {
+        let (fourcc, data) = b?;
+        let mut r = ByteReader::new(data);
+
+        match &fourcc {
+            b"mdhd" => {
+                let (version, _flags) = r.read_full_box_header()?;
+                // Creation / modification time
+                r.read_u32_or_u64(version)?;
+                r.read_u32_or_u64(version)?;
+                timescale = Some(r.read_u32()?);
+                duration = Some(r.read_u32_or_u64(version)?);
+                language_code = parse_language_code(r.read_u16()?);
+            }
+            b"hdlr" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                // Pre-defined
+                r.skip(4)?;
+                handler_type = Some(r.read_array::<4>()?);
+            }
+            b"minf" => {
+                for b in Boxes::new(data) {
+                    let (fourcc, data) = b?;
+                    if &fourcc == b"stbl" {
+                        stbl = Some(data);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let timescale = timescale.context("no mdhd")?;
+    if timescale == 0 {
+        bail!("invalid track timescale 0");
+    }
+
+    let track_type = match &handler_type.context("no hdlr")? {
+        b"vide" => TrackType::Video,
+        b"soun" => TrackType::Audio,
+        b"meta" => TrackType::Metadata,
+        _ => return Ok(None),
+    };
+
+    let (caps, samples) = parse_stbl(stbl.context("no stbl")?, track_type, timescale)?;
+
+    Ok(Some(Track {
+        track_id,
+        track_type,
+        timescale,
+        duration: duration.filter(|d| *d != u64::MAX && *d != u64::from(u32::MAX)),
+        language_code,
+        orientation,
+        caps,
+        edit,
+        extends: TrackExtends::default(),
+        samples,
+    }))
+}
+
+fn parse_elst(data: &[u8]) -> Result<Edit, Error> {
+    let mut r = ByteReader::new(data);
+    let (version, _flags) = r.read_full_box_header()?;
+    let entry_count = r.read_entry_count(if version == FULL_BOX_VERSION_1 {
+        20
+    } else {
+        12
+    })?;
+
+    let mut edit = Edit::default();
+    for _ in 0..entry_count {
+        let (segment_duration, media_time) = if version == FULL_BOX_VERSION_1 {
+            (r.read_u64()?, r.read_i64()?)
+        } else {
+            (u64::from(r.read_u32()?), i64::from(r.read_i32()?))
+        };
+        // Media rate
+        r.skip(4)?;
+
+        if media_time == -1 {
+            edit.empty_duration += segment_duration;
+        } else {
+            // Only the first non-empty edit is considered, everything else can't be expressed
+            // via timestamps.
+            edit.media_time = media_time;
+            break;
+        }
+    }
+
+    Ok(edit)
+}
+
+/// Parses the content of a `stbl` box into the caps of the first sample description and the
+/// list of samples.
+fn parse_stbl(
+    data: &[u8],
+    track_type: TrackType,
+    timescale: u32,
+) -> Result<(gst::Caps, Vec<Sample>), Error> {
+    let mut caps = None;
+    let mut stts = vec![];
+    let mut ctts = vec![];
+    let mut stss = None;
+    let mut sample_sizes = vec![];
+    let mut stsc = vec![];
+    let mut chunk_offsets = vec![];
+
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+        let mut r = ByteReader::new(data);
+
+        match &fourcc {
+            b"stsd" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_u32()?;
+                if entry_count == 0 {
+                    bail!("no sample descriptions");
+                }
+                let (fourcc, data) = Boxes::new(r.rest()).next().context("no sample entry")??;
+                caps = Some(
+                    parse_sample_entry(track_type, &fourcc, data).with_context(|| {
+                        format!("failed to parse sample entry {}", fourcc_to_string(&fourcc))
+                    })?,
+                );
+            }
+            b"stts" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_entry_count(8)?;
+                stts.reserve(entry_count);
+                for _ in 0..entry_count {
+                    stts.push((r.read_u32()?, r.read_u32()?));
+                }
+            }
+            b"ctts" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_entry_count(8)?;
+                ctts.reserve(entry_count);
+                for _ in 0..entry_count {
+                    let count = r.read_u32()?;
+                    // Version 0 is unsigned according to the spec but commonly written with
+                    // negative offsets by other muxers
+                    let offset = i64::from(r.read_i32()?);
+                    ctts.push((count, offset));
+                }
+            }
+            b"stss" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_entry_count(4)?;
+                let mut sync_samples = Vec::with_capacity(entry_count);
+                for _ in 0..entry_count {
+                    sync_samples.push(r.read_u32()?);
+                }
+                stss = Some(sync_samples);
+            }
+            b"stsz" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let sample_size = r.read_u32()?;
+                if sample_size != 0 {
+                    let sample_count = r.read_entry_count(0)?;
+                    sample_sizes = vec![sample_size; sample_count];
+                } else {
+                    let sample_count = r.read_entry_count(4)?;
+                    sample_sizes.reserve(sample_count);
+                    for _ in 0..sample_count {
+                        sample_sizes.push(r.read_u32()?);
+                    }
+                }
+            }
+            b"stsc" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_entry_count(12)?;
+                stsc.reserve(entry_count);
+                for _ in 0..entry_count {
+                    let first_chunk = r.read_u32()?;
+                    let samples_per_chunk = r.read_u32()?;
+                    let _sample_description_index = r.read_u32()?;
+                    stsc.push((first_chunk, samples_per_chunk));
+                }
+            }
+            b"stco" | b"co64" => {
+                let (_version, _flags) = r.read_full_box_header()?;
+                let entry_count = r.read_entry_count(if &fourcc == b"co64" { 8 } else { 4 })?;
+                chunk_offsets.reserve(entry_count);
+                for _ in 0..entry_count {
+                    chunk_offsets.push(if &fourcc == b"co64" {
+                        r.read_u64()?
+                    } else {
+                        u64::from(r.read_u32()?)
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut caps = caps.context("no stsd")?;
+
+    let sample_count = sample_sizes.len();
+    let mut samples = Vec::with_capacity(sample_count);
+
+    // Offsets from the chunks
+    let mut stsc_iter = stsc.iter().peekable();
+    let mut samples_per_chunk = 0;
+    let mut sample_idx = 0;
+    for (chunk_idx, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = chunk_idx as u32 + 1;
+        while let Some((first_chunk, count)) = stsc_iter.peek() {
+            if *first_chunk > chunk_number {
+                break;
+            }
+            samples_per_chunk = *count;
+            stsc_iter.next();
+        }
+
+        let mut offset = *chunk_offset;
+        for _ in 0..samples_per_chunk {
+            let Some(size) = sample_sizes.get(sample_idx) else {
+                break;
+            };
+
+            samples.push(Sample {
+                offset,
+                size: *size,
+                dts: 0,
+                duration: 0,
+                composition_time_offset: 0,
+                sync_point: stss.is_none(),
+            });
+            offset += u64::from(*size);
+            sample_idx += 1;
+        }
+    }
+
+    if samples.len() != sample_count {
+        bail!(
+            "inconsistent sample table: {} samples in chunks, {sample_count} sample sizes",
+            samples.len()
+        );
+    }
+
+    // Decode times and durations
+    let mut dts = 0;
+    let mut sample_iter = samples.iter_mut();
+    'stts: for (count, delta) in &stts {
+        for _ in 0..*count {
+            let Some(sample) = sample_iter.next() else {
+                break 'stts;
+            };
+            sample.dts = dts;
+            sample.duration = *delta;
+            dts += u64::from(*delta);
+        }
+    }
+
+    // Composition time offsets
+    let mut sample_iter = samples.iter_mut();
+    'ctts: for (count, offset) in &ctts {
+        for _ in 0..*count {
+            let Some(sample) = sample_iter.next() else {
+                break 'ctts;
+            };
+            sample.composition_time_offset = *offset;
+        }
+    }
+
+    // Sync samples
+    if let Some(stss) = stss {
+        for sample_number in stss {
+            if let Some(sample) = samples.get_mut((sample_number as usize).wrapping_sub(1)) {
+                sample.sync_point = true;
+            }
+        }
+    }
+
+    // Signal the framerate for video tracks with a constant sample duration
+    if track_type == TrackType::Video && stts.len() == 1 && stts[0].1 > 0 {
+        if let (Ok(numer), Ok(denom)) = (i32::try_from(timescale), i32::try_from(stts[0].1)) {
+            caps.make_mut()
+                .set("framerate", gst::Fraction::new(numer, denom));
+        }
+    }
+
+    Ok((caps, samples))
+}
+
+/// Creates caps from a sample entry.
+fn parse_sample_entry(
+    track_type: TrackType,
+    fourcc: &[u8; 4],
+    data: &[u8],
+) -> Result<gst::Caps, Error> {
+    let mut r = ByteReader::new(data);
+
+    // Reserved
+    r.skip(6)?;
+    // Data reference index
+    r.skip(2)?;
+
+    match track_type {
+        TrackType::Video => parse_visual_sample_entry(fourcc, r),
+        TrackType::Audio => parse_audio_sample_entry(fourcc, r),
+        TrackType::Metadata => parse_meta_data_sample_entry(fourcc, r),
+    }
+}
+
+fn parse_visual_sample_entry(fourcc: &[u8; 4], mut r: ByteReader) -> Result<gst::Caps, Error> {
+    // Pre-defined, reserved, pre-defined
+    r.skip(2 + 2 + 3 * 4)?;
+    let width = r.read_u16()?;
+    let height = r.read_u16()?;
+    // Horizontal resolution, vertical resolution, reserved, frame count, compressor name,
+    // depth, pre-defined
+    r.skip(4 + 4 + 4 + 2 + 32 + 2 + 2)?;
+
+    let mut codec_boxes = vec![];
+    for b in Boxes::new(r.rest()) {
+        codec_boxes.push(b?);
+    }
+    let codec_box = |fourcc: &[u8; 4]| {
+        codec_boxes
+            .iter()
+            .find(|(f, _)| f == fourcc)
+            .map(|(_, data)| *data)
+    };
+
+    let mut s = match fourcc {
+        b"avc1" | b"avc3" => {
+            let avcc = codec_box(b"avcC").context("no avcC")?;
+            gst::Structure::builder("video/x-h264")
+                .field(
+                    "stream-format",
+                    if fourcc == b"avc1" { "avc" } else { "avc3" },
+                )
+                .field("alignment", "au")
+                .field("codec_data", gst::Buffer::from_slice(avcc.to_vec()))
+                .build()
+        }
+        b"hvc1" | b"hev1" => {
+            let hvcc = codec_box(b"hvcC").context("no hvcC")?;
+            gst::Structure::builder("video/x-h265")
+                .field(
+                    "stream-format",
+                    if fourcc == b"hvc1" { "hvc1" } else { "hev1" },
+                )
+                .field("alignment", "au")
+                .field("codec_data", gst::Buffer::from_slice(hvcc.to_vec()))
+                .build()
+        }
+        b"vp08" => gst::Structure::new_empty("video/x-vp8"),
+        b"vp09" => {
+            let mut s = gst::Structure::new_empty("video/x-vp9");
+            if let Some(vpcc) = codec_box(b"vpcC") {
+                parse_vpcc(vpcc, &mut s)?;
+            }
+            s
+        }
+        b"av01" => {
+            let av1c = codec_box(b"av1C").context("no av1C")?;
+            let mut s = gst::Structure::builder("video/x-av1")
+                .field("stream-format", "obu-stream")
+                .field("alignment", "tu")
+                .build();
+            parse_av1c(av1c, &mut s)?;
+            s
+        }
+        b"jpeg" => gst::Structure::new_empty("image/jpeg"),
+        _ => bail!("unsupported visual sample entry"),
+    };
+
+    s.set("width", i32::from(width));
+    s.set("height", i32::from(height));
+
+    if let Some(pasp) = codec_box(b"pasp") {
+        let mut r = ByteReader::new(pasp);
+        let h_spacing = r.read_u32()?;
+        let v_spacing = r.read_u32()?;
+        if let (Ok(numer), Ok(denom)) = (i32::try_from(h_spacing), i32::try_from(v_spacing)) {
+            if numer > 0 && denom > 0 {
+                s.set("pixel-aspect-ratio", gst::Fraction::new(numer, denom));
+            }
+        }
+    }
+
+    if let Some(colr) = codec_box(b"colr") {
+        let mut r = ByteReader::new(colr);
+        if &r.read_array::<4>()? == b"nclx" {
+            let primaries = r.read_u16()?;
+            let transfer = r.read_u16()?;
+            let matrix = r.read_u16()?;
+            let full_range = r.read_u8()? & 0x80 != 0;
+
+            let colorimetry =
+                colorimetry_from_iso(primaries.into(), transfer.into(), matrix.into(), full_range);
+            if !s.has_field("colorimetry") {
+                s.set("colorimetry", colorimetry.to_string());
+            }
+        }
+    }
+
+    Ok(gst::Caps::from(s))
+}
+
+fn colorimetry_from_iso(
+    primaries: u32,
+    transfer: u32,
+    matrix: u32,
+    full_range: bool,
+) -> gst_video::VideoColorimetry {
+    gst_video::VideoColorimetry::new(
+        if full_range {
+            gst_video::VideoColorRange::Range0_255
+        } else {
+            gst_video::VideoColorRange::Range16_235
+        },
+        gst_video::VideoColorMatrix::from_iso(matrix),
+        gst_video::VideoTransferFunction::from_iso(transfer),
+        gst_video::VideoColorPrimaries::from_iso(primaries),
+    )
+}
+
+/// Inverse of the `vpcC` box written by the muxers.
+fn parse_vpcc(data: &[u8], s: &mut gst::Structure) -> Result<(), Error> {
+    let mut r = ByteReader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    let profile = r.read_u8()?;
+    let _level = r.read_u8()?;
+    let byte = r.read_u8()?;
+    let primaries = r.read_u8()?;
+    let transfer = r.read_u8()?;
+    let matrix = r.read_u8()?;
+
+    let bit_depth = u32::from(byte >> 4);
+    let chroma_format = (byte >> 1) & 0x7;
+    let full_range = byte & 0x1 != 0;
+
+    s.set(
+        "profile",
+        match profile {
+            0 => "0",
+            1 => "1",
+            2 => "2",
+            3 => "3",
+            _ => bail!("unsupported vp9 profile {profile}"),
+        },
+    );
+    s.set(
+        "chroma-format",
+        match chroma_format {
+            0 | 1 => "4:2:0",
+            2 => "4:2:2",
+            3 => "4:4:4",
+            _ => bail!("unsupported vp9 chroma format {chroma_format}"),
+        },
+    );
+    if chroma_format == 0 {
+        s.set("chroma-site", "v-cosited");
+    }
+    s.set("bit-depth-luma", bit_depth);
+    s.set("bit-depth-chroma", bit_depth);
+    s.set(
+        "colorimetry",
+        colorimetry_from_iso(primaries.into(), transfer.into(), matrix.into(), full_range)
+            .to_string(),
+    );
+
+    Ok(())
+}
+
+/// Inverse of the `av1C` box written by the muxers.
+fn parse_av1c(data: &[u8], s: &mut gst::Structure) -> Result<(), Error> {
+    if data.len() < 4 {
+        bail!("too short av1C");
+    }
+
+    if data[0] != 0x81 {
+        bail!("unsupported av1C marker / version {:#x}", data[0]);
+    }
+
+    let profile = data[1] >> 5;
+    let high_bitdepth = data[2] & 0x40 != 0;
+    let twelve_bit = data[2] & 0x20 != 0;
+    let monochrome = data[2] & 0x10 != 0;
+    let chroma_sub_x = data[2] & 0x08 != 0;
+    let chroma_sub_y = data[2] & 0x04 != 0;
+
+    s.set(
+        "profile",
+        match profile {
+            0 => "main",
+            1 => "high",
+            2 => "professional",
+            _ => bail!("unsupported av1 profile {profile}"),
+        },
+    );
+
+    let bit_depth = match (high_bitdepth, twelve_bit) {
+        (false, _) => 8u32,
+        (true, false) => 10,
+        (true, true) => 12,
+    };
+    s.set("bit-depth-luma", bit_depth);
+    s.set("bit-depth-chroma", bit_depth);
+
+    s.set(
+        "chroma-format",
+        match (monochrome, chroma_sub_x, chroma_sub_y) {
+            (true, _, _) => "4:0:0",
+            (false, true, true) => "4:2:0",
+            (false, true, false) => "4:2:2",
+            (false, false, false) => "4:4:4",
+            _ => bail!("unsupported av1 chroma subsampling"),
+        },
+    );
+
+    s.set("codec_data", gst::Buffer::from_slice(data.to_vec()));
+
+    Ok(())
+}
+
+fn parse_audio_sample_entry(fourcc: &[u8; 4], mut r: ByteReader) -> Result<gst::Caps, Error> {
+    // QuickTime sound sample description version, zero for ISO
+    let version = r.read_u16()?;
+    // Reserved
+    r.skip(6)?;
+    let channels = r.read_u16()?;
+    let sample_size = r.read_u16()?;
+    // Pre-defined, reserved
+    r.skip(2 + 2)?;
+    let mut rate = r.read_u32()? >> 16;
+
+    // Skip QuickTime sound sample description version 1 extension
+    if version == 1 {
+        r.skip(16)?;
+    }
+
+    let mut codec_boxes = vec![];
+    for b in Boxes::new(r.rest()) {
+        codec_boxes.push(b?);
+    }
+    let codec_box = |fourcc: &[u8; 4]| {
+        codec_boxes
+            .iter()
+            .find(|(f, _)| f == fourcc)
+            .map(|(_, data)| *data)
+    };
+
+    if let Some(srat) = codec_box(b"srat") {
+        let mut r = ByteReader::new(srat);
+        let (_version, _flags) = r.read_full_box_header()?;
+        rate = r.read_u32()?;
+    }
+
+    let mut s = match fourcc {
+        b"mp4a" => {
+            let esds = codec_box(b"esds").context("no esds")?;
+            parse_esds(esds)?
+        }
+        b"Opus" => {
+            let dops = codec_box(b"dOps").context("no dOps")?;
+            parse_dops(dops)?
+        }
+        b"fLaC" => {
+            let dfla = codec_box(b"dfLa").context("no dfLa")?;
+            parse_dfla(dfla)?
+        }
+        b"alaw" => gst::Structure::new_empty("audio/x-alaw"),
+        b"ulaw" => gst::Structure::new_empty("audio/x-mulaw"),
+        b"ms\x00\x45" => gst::Structure::builder("audio/x-adpcm")
+            .field("layout", "g726")
+            .field("bitrate", i32::from(sample_size) * 8000)
+            .build(),
+        _ => bail!("unsupported audio sample entry"),
+    };
+
+    if !s.has_field("channels") {
+        s.set("channels", i32::from(channels));
+    }
+    if !s.has_field("rate") {
+        s.set("rate", i32::try_from(rate).context("too high rate")?);
+    }
+
+    Ok(gst::Caps::from(s))
+}
+
+/// Reads an MPEG-4 descriptor header and returns the tag and the descriptor content.
+fn read_descriptor<'a>(r: &mut ByteReader<'a>) -> Result<(u8, &'a [u8]), Error> {
+    let tag = r.read_u8()?;
+
+    let mut len = 0usize;
+    for _ in 0..4 {
+        let b = r.read_u8()?;
+        len = (len << 7) | usize::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Ok((tag, r.read_bytes(len)?))
+}
+
+/// Inverse of the `esds` box written by the muxers.
+fn parse_esds(data: &[u8]) -> Result<gst::Structure, Error> {
+    let mut r = ByteReader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    let (tag, es_descriptor) = read_descriptor(&mut r)?;
+    if tag != 0x03 {
+        bail!("no ES descriptor");
+    }
+
+    let mut r = ByteReader::new(es_descriptor);
+    // ES ID
+    r.skip(2)?;
+    let flags = r.read_u8()?;
+    if flags & 0x80 != 0 {
+        // Depends on ES ID
+        r.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        // URL
+        let len = r.read_u8()?;
+        r.skip(len as usize)?;
+    }
+    if flags & 0x20 != 0 {
+        // OCR ES ID
+        r.skip(2)?;
+    }
+
+    let (tag, decoder_config) = read_descriptor(&mut r)?;
+    if tag != 0x04 {
+        bail!("no decoder config descriptor");
+    }
+
+    let mut r = ByteReader::new(decoder_config);
+    let object_type = r.read_u8()?;
+    // Stream type, buffer size db, max bitrate, avg bitrate
+    r.skip(1 + 3 + 4 + 4)?;
+
+    let mut decoder_specific_info = None;
+    while r.remaining() > 0 {
+        let (tag, data) = read_descriptor(&mut r)?;
+        if tag == 0x05 {
+            decoder_specific_info = Some(data);
+            break;
+        }
+    }
+
+    match object_type {
+        // MPEG-4 AAC, MPEG-2 AAC Main / LC / SSR
+        0x40 | 0x66 | 0x67 | 0x68 => {
+            let codec_data = decoder_specific_info.context("no decoder specific info")?;
+            if codec_data.len() < 2 {
+                bail!("too small codec_data");
+            }
+
+            Ok(gst::Structure::builder("audio/mpeg")
+                .field("mpegversion", 4i32)
+                .field("stream-format", "raw")
+                .field("framed", true)
+                .field("codec_data", gst::Buffer::from_slice(codec_data.to_vec()))
+                .build())
+        }
+        // MPEG-1 / MPEG-2 audio
+        0x69 | 0x6b => Ok(gst::Structure::builder("audio/mpeg")
+            .field("mpegversion", 1i32)
+            .field("layer", 3i32)
+            .field("parsed", true)
+            .build()),
+        _ => bail!("unsupported object type {object_type:#x}"),
+    }
+}
+
+/// Inverse of the `dOps` box written by the muxers.
+///
+/// Creates the `OpusHead` and `OpusTags` headers from the box content.
+fn parse_dops(data: &[u8]) -> Result<gst::Structure, Error> {
+    let mut r = ByteReader::new(data);
+    let version = r.read_u8()?;
+    if version != 0 {
+        bail!("unsupported dOps version {version}");
+    }
+
+    let channels = r.read_u8()?;
+    let pre_skip = r.read_u16()?;
+    let rate = r.read_u32()?;
+    let output_gain = r.read_i16()?;
+    let channel_mapping_family = r.read_u8()?;
+
+    let mut head = Vec::with_capacity(19 + 2 + usize::from(channels));
+    head.extend(b"OpusHead");
+    head.push(1);
+    head.push(channels);
+    head.extend(pre_skip.to_le_bytes());
+    head.extend(rate.to_le_bytes());
+    head.extend(output_gain.to_le_bytes());
+    head.push(channel_mapping_family);
+
+    let mut s = gst::Structure::builder("audio/x-opus")
+        .field("channel-mapping-family", i32::from(channel_mapping_family))
+        .field("channels", i32::from(channels))
+        .field("rate", rate as i32)
+        .build();
+
+    if channel_mapping_family > 0 {
+        let stream_count = r.read_u8()?;
+        let coupled_count = r.read_u8()?;
+        let channel_mapping = r.read_bytes(usize::from(channels))?;
+
+        head.push(stream_count);
+        head.push(coupled_count);
+        head.extend(channel_mapping);
+
+        s.set("stream-count", i32::from(stream_count));
+        s.set("coupled-count", i32::from(coupled_count));
+        s.set(
+            "channel-mapping",
+            gst::Array::new(channel_mapping.iter().map(|c| i32::from(*c))),
+        );
+    }
+
+    let mut tags = Vec::with_capacity(16);
+    tags.extend(b"OpusTags");
+    // Empty vendor string and no comments
+    tags.extend(0u32.to_le_bytes());
+    tags.extend(0u32.to_le_bytes());
+
+    s.set(
+        "streamheader",
+        gst::Array::new([
+            gst::Buffer::from_mut_slice(head),
+            gst::Buffer::from_mut_slice(tags),
+        ]),
+    );
+
+    Ok(s)
+}
+
+/// Inverse of the `dfLa` box written by the muxers.
+///
+/// Creates the Ogg/FLAC mapping style `streamheader` that `flacparse` outputs.
+fn parse_dfla(data: &[u8]) -> Result<gst::Structure, Error> {
+    let mut r = ByteReader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    let mut blocks = vec![];
+    while r.remaining() > 0 {
+        let header = r.read_array::<4>()?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        let content = r.read_bytes(len as usize)?;
+
+        let mut block = Vec::with_capacity(4 + content.len());
+        block.extend(header);
+        block.extend(content);
+        blocks.push(block);
+    }
+
+    let Some((streaminfo, remainder)) = blocks.split_first() else {
+        bail!("no FLAC metadata blocks");
+    };
+    if streaminfo[0] & 0x7f != 0 || streaminfo.len() != 38 {
+        bail!("first FLAC metadata block is not STREAMINFO");
+    }
+
+    let rate = (u32::from(streaminfo[14]) << 12)
+        | (u32::from(streaminfo[15]) << 4)
+        | (u32::from(streaminfo[16]) >> 4);
+    let channels = ((streaminfo[16] >> 1) & 0x7) + 1;
+
+    let mut first = Vec::with_capacity(13 + 38);
+    first.extend(b"\x7FFLAC\x01\x00");
+    first.extend((remainder.len() as u16).to_be_bytes());
+    first.extend(b"fLaC");
+    first.extend(streaminfo);
+
+    let headers = std::iter::once(gst::Buffer::from_mut_slice(first))
+        .chain(
+            remainder
+                .iter()
+                .map(|block| gst::Buffer::from_slice(block.clone())),
+        )
+        .collect::<Vec<_>>();
+
+    Ok(gst::Structure::builder("audio/x-flac")
+        .field("framed", true)
+        .field("channels", i32::from(channels))
+        .field("rate", rate as i32)
+        .field("streamheader", gst::Array::new(headers))
+        .build())
+}
+
+fn parse_meta_data_sample_entry(fourcc: &[u8; 4], mut r: ByteReader) -> Result<gst::Caps, Error> {
+    if fourcc != b"metx" {
+        bail!("unsupported metadata sample entry");
+    }
+
+    let _content_encoding = r.read_cstring()?;
+    let namespace = r.read_cstring()?;
+
+    match namespace {
+        b"http://www.onvif.org/ver10/schema" => {
+            Ok(gst::Caps::builder("application/x-onvif-metadata")
+                .field("parsed", true)
+                .build())
+        }
+        _ => bail!(
+            "unsupported XML namespace {}",
+            String::from_utf8_lossy(namespace)
+        ),
+    }
+}
+
+/// Default values for the samples of a track fragment.
+#[derive(Debug, Clone, Copy)]
+struct TrackFragmentDefaults {
+    base_data_offset: u64,
+    default_sample_duration: u32,
+    default_sample_size: u32,
+    default_sample_flags: u32,
+}
+
+/// Samples of one track in a movie fragment.
+#[derive(Debug)]
+pub(super) struct TrackFragment {
+    pub(super) track_id: u32,
+    pub(super) samples: Vec<Sample>,
+}
+
+#[derive(Debug)]
+pub(super) struct MovieFragment {
+    pub(super) sequence_number: u32,
+    pub(super) track_fragments: Vec<TrackFragment>,
+}
+
+const TF_BASE_DATA_OFFSET: u32 = 0x01;
+const TF_SAMPLE_DESCRIPTION_INDEX: u32 = 0x02;
+const TF_DEFAULT_SAMPLE_DURATION: u32 = 0x08;
+const TF_DEFAULT_SAMPLE_SIZE: u32 = 0x10;
+const TF_DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+const TF_DEFAULT_BASE_IS_MOOF: u32 = 0x02_00_00;
+
+const TR_DATA_OFFSET: u32 = 0x00_01;
+const TR_FIRST_SAMPLE_FLAGS: u32 = 0x00_04;
+const TR_SAMPLE_DURATION: u32 = 0x01_00;
+const TR_SAMPLE_SIZE: u32 = 0x02_00;
+const TR_SAMPLE_FLAGS: u32 = 0x04_00;
+const TR_COMPOSITION_TIME_OFFSETS: u32 = 0x08_00;
+
+/// `sample_is_non_sync_sample` in the sample flags.
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x01_00_00;
+
+/// Parses the content of a `moof` box that starts at `moof_offset` in the file.
+///
+/// `decode_times` contains the next decode time of each track and is updated with the end of the
+/// samples of this fragment. It is used if a track fragment has no `tfdt` box.
+pub(super) fn parse_moof(
+    data: &[u8],
+    moof_offset: u64,
+    movie: &Movie,
+    decode_times: &mut [u64],
+) -> Result<MovieFragment, Error> {
+    let mut sequence_number = 0;
+    let mut track_fragments = vec![];
+    // Data offset of the end of the previous track fragment, used if there is no explicit base
+    // data offset.
+    let mut prev_data_end = None;
+
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+
+        match &fourcc {
+            b"mfhd" => {
+                let mut r = ByteReader::new(data);
+                let (_version, _flags) = r.read_full_box_header()?;
+                sequence_number = r.read_u32()?;
+            }
+            b"traf" => {
+                if let Some(traf) =
+                    parse_traf(data, moof_offset, prev_data_end, movie, decode_times)?
+                {
+                    prev_data_end = traf
+                        .samples
+                        .last()
+                        .map(|s| s.offset + u64::from(s.size))
+                        .or(prev_data_end);
+                    track_fragments.push(traf);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(MovieFragment {
+        sequence_number,
+        track_fragments,
+    })
+}
+
+/// Parses the content of a `traf` box.
+///
+/// Returns `Ok(None)` for track fragments of unknown tracks.
+fn parse_traf(
+    data: &[u8],
+    moof_offset: u64,
+    prev_data_end: Option<u64>,
+    movie: &Movie,
+    decode_times: &mut [u64],
+) -> Result<Option<TrackFragment>, Error> {
+    let mut defaults = None;
+    let mut track_idx = None;
+    let mut base_decode_time = None;
+    let mut samples = vec![];
+
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+        let mut r = ByteReader::new(data);
+
+        match &fourcc {
+            b"tfhd" => {
+                let (_version, flags) = r.read_full_box_header()?;
+                let track_id = r.read_u32()?;
+                let Some(idx) = movie.tracks.iter().position(|t| t.track_id == track_id) else {
+                    return Ok(None);
+                };
+                let extends = &movie.tracks[idx].extends;
+                track_idx = Some(idx);
+
+                let base_data_offset = if flags & TF_BASE_DATA_OFFSET != 0 {
+                    r.read_u64()?
+                } else if flags & TF_DEFAULT_BASE_IS_MOOF != 0 {
+                    moof_offset
+                } else {
+                    prev_data_end.unwrap_or(moof_offset)
+                };
+                if flags & TF_SAMPLE_DESCRIPTION_INDEX != 0 {
+                    r.skip(4)?;
+                }
+
+                defaults = Some(TrackFragmentDefaults {
+                    base_data_offset,
+                    default_sample_duration: if flags & TF_DEFAULT_SAMPLE_DURATION != 0 {
+                        r.read_u32()?
+                    } else {
+                        extends.default_sample_duration
+                    },
+                    default_sample_size: if flags & TF_DEFAULT_SAMPLE_SIZE != 0 {
+                        r.read_u32()?
+                    } else {
+                        extends.default_sample_size
+                    },
+                    default_sample_flags: if flags & TF_DEFAULT_SAMPLE_FLAGS != 0 {
+                        r.read_u32()?
+                    } else {
+                        extends.default_sample_flags
+                    },
+                });
+            }
+            b"tfdt" => {
+                let (version, _flags) = r.read_full_box_header()?;
+                base_decode_time = Some(r.read_u32_or_u64(version)?);
+            }
+            b"trun" => {
+                let defaults = defaults.context("trun before tfhd")?;
+                let track_idx = track_idx.unwrap();
+
+                let mut dts = samples
+                    .last()
+                    .map(|s: &Sample| s.dts + u64::from(s.duration))
+                    .or(base_decode_time)
+                    .unwrap_or(decode_times[track_idx]);
+                let mut offset = samples
+                    .last()
+                    .map(|s: &Sample| s.offset + u64::from(s.size))
+                    .unwrap_or(defaults.base_data_offset);
+
+                let (version, flags) = r.read_full_box_header()?;
+                let sample_count = r.read_u32()?;
+                // Each optional per-sample field is 4 bytes
+                let sample_size = 4 * [
+                    TR_SAMPLE_DURATION,
+                    TR_SAMPLE_SIZE,
+                    TR_SAMPLE_FLAGS,
+                    TR_COMPOSITION_TIME_OFFSETS,
+                ]
+                .iter()
+                .filter(|flag| flags & **flag != 0)
+                .count();
+                if flags & TR_DATA_OFFSET != 0 {
+                    offset = defaults
+                        .base_data_offset
+                        .checked_add_signed(i64::from(r.read_i32()?))
+                        .context("invalid data offset")?;
+                }
+                let first_sample_flags = if flags & TR_FIRST_SAMPLE_FLAGS != 0 {
+                    Some(r.read_u32()?)
+                } else {
+                    None
+                };
+
+                let sample_count = r.check_entry_count(u64::from(sample_count), sample_size)?;
+                samples.reserve(sample_count);
+                for idx in 0..sample_count {
+                    let duration = if flags & TR_SAMPLE_DURATION != 0 {
+                        r.read_u32()?
+                    } else {
+                        defaults.default_sample_duration
+                    };
+                    let size = if flags & TR_SAMPLE_SIZE != 0 {
+                        r.read_u32()?
+                    } else {
+                        defaults.default_sample_size
+                    };
+                    let sample_flags = if flags & TR_SAMPLE_FLAGS != 0 {
+                        r.read_u32()?
+                    } else if let Some(first_sample_flags) = first_sample_flags.filter(|_| idx == 0)
+                    {
+                        first_sample_flags
+                    } else {
+                        defaults.default_sample_flags
+                    };
+                    let composition_time_offset = if flags & TR_COMPOSITION_TIME_OFFSETS != 0 {
+                        if version == FULL_BOX_VERSION_0 {
+                            i64::from(r.read_u32()?)
+                        } else {
+                            i64::from(r.read_i32()?)
+                        }
+                    } else {
+                        0
+                    };
+
+                    samples.push(Sample {
+                        offset,
+                        size,
+                        dts,
+                        duration,
+                        composition_time_offset,
+                        sync_point: sample_flags & SAMPLE_FLAGS_NON_SYNC == 0,
+                    });
+
+                    offset += u64::from(size);
+                    dts += u64::from(duration);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let Some(track_idx) = track_idx else {
+        bail!("no tfhd");
+    };
+
+    if let Some(last) = samples.last() {
+        decode_times[track_idx] = last.dts + u64::from(last.duration);
+    } else if let Some(base_decode_time) = base_decode_time {
+        decode_times[track_idx] = base_decode_time;
+    }
+
+    Ok(Some(TrackFragment {
+        track_id: movie.tracks[track_idx].track_id,
+        samples,
+    }))
+}
+
+/// Entry of a fragment index, either from a `tfra` or a `sidx` box.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FragmentIndexEntry {
+    pub(super) time: gst::ClockTime,
+    pub(super) offset: u64,
+}
+
+/// Parses the content of a `mfra` box.
+///
+/// Returns the random access points of the first track that has a `tfra` box.
+pub(super) fn parse_mfra(data: &[u8], movie: &Movie) -> Result<Vec<FragmentIndexEntry>, Error> {
+    for b in Boxes::new(data) {
+        let (fourcc, data) = b?;
+        if &fourcc != b"tfra" {
+            continue;
+        }
+
+        let mut r = ByteReader::new(data);
+        let (version, _flags) = r.read_full_box_header()?;
+        let track_id = r.read_u32()?;
+        let Some(track) = movie.tracks.iter().find(|t| t.track_id == track_id) else {
+            continue;
+        };
+
+        let lengths = r.read_u32()?;
+        let traf_number_len = ((lengths >> 4) & 0x3) as usize + 1;
+        let trun_number_len = ((lengths >> 2) & 0x3) as usize + 1;
+        let sample_number_len = (lengths & 0x3) as usize + 1;
+
+        let entry_count = r.read_entry_count(
+            if version == FULL_BOX_VERSION_1 { 16 } else { 8 }
+                + traf_number_len
+                + trun_number_len
+                + sample_number_len,
+        )?;
+        let mut entries = Vec::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            let time = r.read_u32_or_u64(version)?;
+            let offset = r.read_u32_or_u64(version)?;
+            r.skip(traf_number_len + trun_number_len + sample_number_len)?;
+
+            let Some(time) = to_clock_time(time, track.timescale) else {
+                continue;
+            };
+            entries.push(FragmentIndexEntry { time, offset });
+        }
+
+        return Ok(entries);
+    }
+
+    Ok(vec![])
+}
+
+/// Parses the content of a `sidx` box that ends at `sidx_end` in the file.
+pub(super) fn parse_sidx(data: &[u8], sidx_end: u64) -> Result<Vec<FragmentIndexEntry>, Error> {
+    let mut r = ByteReader::new(data);
+    let (version, _flags) = r.read_full_box_header()?;
+    let _reference_id = r.read_u32()?;
+    let timescale = r.read_u32()?;
+    let mut time = r.read_u32_or_u64(version)?;
+    let first_offset = r.read_u32_or_u64(version)?;
+    // Reserved
+    r.skip(2)?;
+    let reference_count = r.read_u16()?;
+    let reference_count = r.check_entry_count(u64::from(reference_count), 12)?;
+
+    let mut offset = sidx_end + first_offset;
+    let mut entries = Vec::with_capacity(reference_count);
+    for _ in 0..reference_count {
+        let reference = r.read_u32()?;
+        let duration = r.read_u32()?;
+        let _sap = r.read_u32()?;
+
+        let reference_type = reference >> 31;
+        let size = reference & 0x7f_ff_ff_ff;
+
+        // Only references to media are of interest here, not other sidx boxes
+        if reference_type == 0 {
+            if let Some(time) = to_clock_time(time, timescale) {
+                entries.push(FragmentIndexEntry { time, offset });
+            }
+        }
+
+        offset += u64::from(size);
+        time += u64::from(duration);
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oversized_entry_counts() {
+        // stsz with 0xffffffff sample sizes but no entries
+        let stsz = [
+            0x00, 0x00, 0x00, 0x14, b's', b't', b's', b'z', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+        ];
+        assert!(parse_stbl(&stsz, TrackType::Video, 90_000).is_err());
+
+        // stsz with 0xffffffff samples of a constant size
+        let mut stsz_constant = stsz;
+        stsz_constant[15] = 0x01;
+        assert!(parse_stbl(&stsz_constant, TrackType::Video, 90_000).is_err());
+
+        // sidx with 0xffff references but only one entry
+        let sidx = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x01, 0x00,
+            0x00, 0x00, 0x03, 0xe8, 0x90, 0x00, 0x00, 0x00,
+        ];
+        assert!(parse_sidx(&sidx, 0).is_err());
+
+        let mut sidx_single = sidx;
+        sidx_single[22] = 0x00;
+        sidx_single[23] = 0x01;
+        let entries = parse_sidx(&sidx_single, 100).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].time, gst::ClockTime::ZERO);
+        assert_eq!(entries[0].offset, 100);
+    }
+}
@@ -0,0 +1,1482 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use super::boxes;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "isobmffdemux",
+        gst::DebugColorFlags::empty(),
+        Some("ISOBMFF Demuxer Element"),
+    )
+});
+
+struct Stream {
+    pad: gst::Pad,
+    /// Index of the track in the movie.
+    track_idx: usize,
+    /// Samples that still have to be output.
+    pending: VecDeque<boxes::Sample>,
+    /// Seek target that still has to be applied to the next samples that are queued.
+    seek_target: Option<gst::ClockTime>,
+    discont: bool,
+    eos: bool,
+}
+
+/// Information about a stream for creating its source pad.
+struct StreamInfo {
+    track_idx: usize,
+    track_type: boxes::TrackType,
+    caps: gst::Caps,
+    tags: gst::TagList,
+}
+
+/// Next action to take after a parsing step in push mode.
+enum Step {
+    NeedData,
+    CreateStreams(Vec<StreamInfo>),
+    Sample(usize, boxes::Sample, gst::Buffer),
+}
+
+struct State {
+    pull_mode: bool,
+    /// Push mode: offset of the first byte in the adapter.
+    /// Pull mode: offset of the next top-level box.
+    offset: u64,
+    adapter: gst_base::UniqueAdapter,
+    /// Push mode: offset until which data is skipped.
+    skip_until: Option<u64>,
+    /// Push mode: end offset of the `mdat` box that is currently read.
+    mdat_end: Option<u64>,
+
+    movie: Option<boxes::Movie>,
+    streams: Vec<Stream>,
+    /// Next decode time per track for fragments without `tfdt`.
+    decode_times: Vec<u64>,
+    /// Offset of the first `moof` in pull mode.
+    first_moof_offset: Option<u64>,
+    /// Fragment index from `mfra` or `sidx` for seeking in fragmented files.
+    fragment_index: Vec<boxes::FragmentIndexEntry>,
+
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    seek_seqnum: Option<gst::Seqnum>,
+    need_segment: bool,
+    last_position: Option<gst::ClockTime>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        segment.set_start(gst::ClockTime::ZERO);
+        segment.set_position(gst::ClockTime::ZERO);
+
+        State {
+            pull_mode: false,
+            offset: 0,
+            adapter: gst_base::UniqueAdapter::new(),
+            skip_until: None,
+            mdat_end: None,
+            movie: None,
+            streams: vec![],
+            decode_times: vec![],
+            first_moof_offset: None,
+            fragment_index: vec![],
+            segment,
+            seek_seqnum: None,
+            need_segment: false,
+            last_position: None,
+        }
+    }
+}
+
+/// Timestamps of a sample in nanoseconds after applying the edit list.
+struct SampleTimes {
+    pts: i64,
+    dts: i64,
+    duration: u64,
+}
+
+fn sample_times(movie: &boxes::Movie, track: &boxes::Track, sample: &boxes::Sample) -> SampleTimes {
+    let nseconds = i128::from(gst::ClockTime::SECOND.nseconds());
+
+    let empty_duration =
+        i128::from(track.edit.empty_duration) * nseconds / i128::from(movie.timescale);
+    let dts = i128::from(sample.dts) - i128::from(track.edit.media_time);
+    let pts = dts + i128::from(sample.composition_time_offset);
+
+    SampleTimes {
+        pts: (empty_duration + pts * nseconds / i128::from(track.timescale)) as i64,
+        dts: (empty_duration + dts * nseconds / i128::from(track.timescale)) as i64,
+        duration: (i128::from(sample.duration) * nseconds / i128::from(track.timescale)) as u64,
+    }
+}
+
+impl State {
+    /// Queues samples of a track for output and applies a pending seek target to them.
+    fn queue_samples(&mut self, track_id: u32, mut samples: Vec<boxes::Sample>) {
+        let movie = self.movie.as_ref().unwrap();
+        let Some(stream) = self
+            .streams
+            .iter_mut()
+            .find(|s| movie.tracks[s.track_idx].track_id == track_id)
+        else {
+            return;
+        };
+        let track = &movie.tracks[stream.track_idx];
+
+        if stream.eos {
+            return;
+        }
+
+        if let Some(target) = stream.seek_target {
+            let target = target.nseconds() as i64;
+
+            let ends_before_target = samples.last().map_or(true, |s| {
+                let times = sample_times(movie, track, s);
+                times.pts + times.duration as i64 <= target
+            });
+
+            if ends_before_target {
+                samples.clear();
+            } else {
+                let start = samples
+                    .iter()
+                    .rposition(|s| s.sync_point && sample_times(movie, track, s).pts <= target)
+                    .unwrap_or(0);
+                samples.drain(..start);
+                stream.seek_target = None;
+            }
+        }
+
+        stream.pending.extend(samples);
+    }
+
+    /// Returns the index of the stream whose next sample comes first in the file, only
+    /// considering samples before `end`.
+    fn next_stream(&self, end: Option<u64>) -> Option<usize> {
+        self.streams
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, s)| s.pending.front().map(|sample| (idx, sample.offset)))
+            .filter(|(_, offset)| end.map_or(true, |end| *offset < end))
+            .min_by_key(|(_, offset)| *offset)
+            .map(|(idx, _)| idx)
+    }
+
+    /// Collects the information for creating the source pads once the movie is known.
+    fn stream_infos(&self) -> Vec<StreamInfo> {
+        let movie = self.movie.as_ref().unwrap();
+
+        movie
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(track_idx, track)| {
+                let mut tags = gst::TagList::new();
+                {
+                    let tags = tags.get_mut().unwrap();
+                    tags.add::<gst::tags::ContainerFormat>(
+                        &"ISO MP4/M4A",
+                        gst::TagMergeMode::Replace,
+                    );
+                    if let Some(language_code) = track.language_code {
+                        let language_code = std::str::from_utf8(&language_code).unwrap();
+                        tags.add::<gst::tags::LanguageCode>(
+                            &language_code,
+                            gst::TagMergeMode::Replace,
+                        );
+                    }
+                    if let Some(orientation) = track.orientation {
+                        let orientation = orientation.as_tag();
+                        tags.add::<gst::tags::ImageOrientation>(
+                            &orientation,
+                            gst::TagMergeMode::Replace,
+                        );
+                    }
+                }
+
+                StreamInfo {
+                    track_idx,
+                    track_type: track.track_type,
+                    caps: track.caps.clone(),
+                    tags,
+                }
+            })
+            .collect()
+    }
+
+    /// Resets all streams to the current segment start.
+    fn reset_streams(&mut self) {
+        let movie = self.movie.as_ref().unwrap();
+        let target = self.segment.start().unwrap_or(gst::ClockTime::ZERO);
+
+        for stream in &mut self.streams {
+            stream.pending.clear();
+            // Nothing to skip when starting from the beginning, samples before the start are
+            // needed for decoder priming
+            stream.seek_target = Some(target).filter(|target| !target.is_zero());
+            stream.discont = true;
+            stream.eos = false;
+        }
+
+        self.decode_times = vec![0; movie.tracks.len()];
+
+        let index_entry = self
+            .fragment_index
+            .iter()
+            .rev()
+            .find(|e| e.time <= target)
+            .copied();
+
+        if movie.fragmented {
+            if let Some(entry) = index_entry.filter(|_| self.pull_mode) {
+                gst::debug!(
+                    CAT,
+                    "Starting at fragment at offset {} for seek target {}",
+                    entry.offset,
+                    target
+                );
+                self.offset = entry.offset;
+                return;
+            }
+
+            if let Some(first_moof_offset) = self.first_moof_offset {
+                self.offset = first_moof_offset;
+            }
+        }
+
+        let samples = movie
+            .tracks
+            .iter()
+            .map(|t| (t.track_id, t.samples.clone()))
+            .collect::<Vec<_>>();
+        for (track_id, samples) in samples {
+            self.queue_samples(track_id, samples);
+        }
+    }
+}
+
+pub struct IsobmffDemux {
+    sinkpad: gst::Pad,
+    state: Mutex<State>,
+    flow_combiner: Mutex<gst_base::UniqueFlowCombiner>,
+}
+
+impl IsobmffDemux {
+    fn sink_activate(&self, pad: &gst::Pad) -> Result<(), gst::LoggableError> {
+        let mode = {
+            let mut query = gst::query::Scheduling::new();
+
+            if !pad.peer_query(&mut query) {
+                gst::debug!(CAT, obj = pad, "Scheduling query failed on peer");
+                gst::PadMode::Push
+            } else if query
+                .has_scheduling_mode_with_flags(gst::PadMode::Pull, gst::SchedulingFlags::SEEKABLE)
+            {
+                gst::debug!(CAT, obj = pad, "Activating in Pull mode");
+                gst::PadMode::Pull
+            } else {
+                gst::debug!(CAT, obj = pad, "Activating in Push mode");
+                gst::PadMode::Push
+            }
+        };
+
+        pad.activate_mode(mode, true)?;
+        Ok(())
+    }
+
+    fn start_task(&self) -> Result<(), gst::LoggableError> {
+        let self_ = self.ref_counted();
+        let res = self.sinkpad.start_task(move || {
+            self_.loop_fn();
+        });
+        if res.is_err() {
+            return Err(gst::loggable_error!(CAT, "Failed to start pad task"));
+        }
+        Ok(())
+    }
+
+    fn sink_activatemode(
+        &self,
+        _pad: &gst::Pad,
+        mode: gst::PadMode,
+        active: bool,
+    ) -> Result<(), gst::LoggableError> {
+        if active {
+            self.state.lock().unwrap().pull_mode = mode == gst::PadMode::Pull;
+        }
+
+        if mode == gst::PadMode::Pull {
+            if active {
+                self.start_task()?;
+            } else {
+                let _ = self.sinkpad.stop_task();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_streams(&self, infos: Vec<StreamInfo>) {
+        let (segment, seek_seqnum) = {
+            let state = self.state.lock().unwrap();
+            (state.segment.clone(), state.seek_seqnum)
+        };
+
+        let group_id = gst::GroupId::next();
+        let mut streams = Vec::with_capacity(infos.len());
+        let (mut num_video, mut num_audio, mut num_meta) = (0, 0, 0);
+
+        for info in infos {
+            let (templ, name) = match info.track_type {
+                boxes::TrackType::Video => {
+                    num_video += 1;
+                    ("video_%u", format!("video_{}", num_video - 1))
+                }
+                boxes::TrackType::Audio => {
+                    num_audio += 1;
+                    ("audio_%u", format!("audio_{}", num_audio - 1))
+                }
+                boxes::TrackType::Metadata => {
+                    num_meta += 1;
+                    ("meta_%u", format!("meta_{}", num_meta - 1))
+                }
+            };
+
+            let templ = self.obj().element_class().pad_template(templ).unwrap();
+            let pad = gst::Pad::builder_from_template(&templ)
+                .name(name.as_str())
+                .event_function(|pad, parent, event| {
+                    IsobmffDemux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux| demux.src_event(pad, event),
+                    )
+                })
+                .query_function(|pad, parent, query| {
+                    IsobmffDemux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux| demux.src_query(pad, query),
+                    )
+                })
+                .build();
+
+            gst::debug!(
+                CAT,
+                obj = pad,
+                "Creating pad for track {} with caps {:?}",
+                info.track_idx,
+                info.caps
+            );
+
+            pad.set_active(true).unwrap();
+
+            let stream_id = pad.create_stream_id(&*self.obj(), Some(name.as_str()));
+            pad.push_event(
+                gst::event::StreamStart::builder(&stream_id)
+                    .group_id(group_id)
+                    .build(),
+            );
+            pad.push_event(gst::event::Caps::new(&info.caps));
+            let mut segment_event = gst::event::Segment::builder(&segment);
+            if let Some(seek_seqnum) = seek_seqnum {
+                segment_event = segment_event.seqnum(seek_seqnum);
+            }
+            pad.push_event(segment_event.build());
+            pad.push_event(gst::event::Tag::new(info.tags));
+
+            self.flow_combiner.lock().unwrap().add_pad(&pad);
+            self.obj().add_pad(&pad).unwrap();
+
+            streams.push(Stream {
+                pad,
+                track_idx: info.track_idx,
+                pending: VecDeque::new(),
+                seek_target: None,
+                discont: true,
+                eos: false,
+            });
+        }
+
+        self.obj().no_more_pads();
+
+        let mut state = self.state.lock().unwrap();
+        state.streams = streams;
+        state.reset_streams();
+    }
+
+    fn push_sample(
+        &self,
+        stream_idx: usize,
+        sample: boxes::Sample,
+        mut buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        let movie = state.movie.as_ref().unwrap();
+        let track = &movie.tracks[state.streams[stream_idx].track_idx];
+        let times = sample_times(movie, track, &sample);
+
+        if times.pts + (times.duration as i64) < 0 {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Dropping sample before the start of the presentation"
+            );
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let pts = gst::ClockTime::from_nseconds(times.pts.max(0) as u64);
+        if state.segment.stop().is_some_and(|stop| pts >= stop) {
+            let stream = &mut state.streams[stream_idx];
+            gst::debug!(CAT, obj = stream.pad, "Reached segment stop");
+            stream.pending.clear();
+            stream.eos = true;
+
+            if state.streams.iter().all(|s| s.eos) {
+                return Err(gst::FlowError::Eos);
+            }
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(pts);
+            buffer.set_dts(
+                u64::try_from(times.dts)
+                    .ok()
+                    .map(gst::ClockTime::from_nseconds),
+            );
+            buffer.set_duration(gst::ClockTime::from_nseconds(times.duration));
+            buffer.set_offset(gst::BUFFER_OFFSET_NONE);
+            buffer.set_offset_end(gst::BUFFER_OFFSET_NONE);
+
+            let mut flags = gst::BufferFlags::empty();
+            if !sample.sync_point {
+                flags |= gst::BufferFlags::DELTA_UNIT;
+            }
+            if state.streams[stream_idx].discont {
+                flags |= gst::BufferFlags::DISCONT;
+            }
+            buffer.set_flags(flags);
+        }
+
+        state.streams[stream_idx].discont = false;
+        state.last_position = state.last_position.opt_max(pts).or(Some(pts));
+
+        let segment_events = if state.need_segment {
+            state.need_segment = false;
+            let mut segment_event = gst::event::Segment::builder(&state.segment);
+            if let Some(seek_seqnum) = state.seek_seqnum {
+                segment_event = segment_event.seqnum(seek_seqnum);
+            }
+            let segment_event = segment_event.build();
+
+            state
+                .streams
+                .iter()
+                .map(|s| (s.pad.clone(), segment_event.clone()))
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+
+        let pad = state.streams[stream_idx].pad.clone();
+        drop(state);
+
+        for (pad, event) in segment_events {
+            pad.push_event(event);
+        }
+
+        gst::trace!(CAT, obj = pad, "Pushing buffer {:?}", buffer);
+        let res = pad.push(buffer);
+        gst::trace!(CAT, obj = pad, "Pushing buffer returned {:?}", res);
+
+        self.flow_combiner
+            .lock()
+            .unwrap()
+            .update_pad_flow(&pad, res)
+    }
+
+    fn push_eos(&self) {
+        let state = self.state.lock().unwrap();
+
+        if state.streams.is_empty() {
+            drop(state);
+            gst::element_imp_error!(self, gst::StreamError::Demux, ["No supported tracks found"]);
+            return;
+        }
+
+        let mut eos_event = gst::event::Eos::builder();
+        if let Some(seek_seqnum) = state.seek_seqnum {
+            eos_event = eos_event.seqnum(seek_seqnum);
+        }
+        let eos_event = eos_event.build();
+
+        let pads = state
+            .streams
+            .iter()
+            .map(|s| s.pad.clone())
+            .collect::<Vec<_>>();
+        drop(state);
+
+        for pad in pads {
+            gst::debug!(CAT, obj = pad, "Pushing EOS");
+            pad.push_event(eos_event.clone());
+        }
+    }
+
+    /// Pulls and parses the box header at `offset`.
+    ///
+    /// Returns `Ok(None)` at the end of the file.
+    fn pull_box_header(&self, offset: u64) -> Result<Option<boxes::BoxHeader>, gst::FlowError> {
+        let buffer = match self
+            .sinkpad
+            .pull_range(offset, boxes::MAX_BOX_HEADER_SIZE as u32)
+        {
+            Ok(buffer) => buffer,
+            Err(gst::FlowError::Eos) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::error!(CAT, imp = self, "Failed to map buffer");
+            gst::FlowError::Error
+        })?;
+
+        boxes::parse_box_header(&map).map_err(|err| {
+            gst::element_imp_error!(
+                self,
+                gst::StreamError::Demux,
+                ["Invalid box header at offset {offset}: {err:?}"]
+            );
+            gst::FlowError::Error
+        })
+    }
+
+    /// Pulls the content of a box without its header.
+    fn pull_box(
+        &self,
+        offset: u64,
+        header: &boxes::BoxHeader,
+        size: u64,
+    ) -> Result<gst::MappedBuffer<gst::buffer::Readable>, gst::FlowError> {
+        let size = u32::try_from(size - header.header_size).map_err(|_| {
+            gst::element_imp_error!(
+                self,
+                gst::StreamError::Demux,
+                [
+                    "Too big {} box at offset {offset}",
+                    boxes::fourcc_to_string(&header.fourcc)
+                ]
+            );
+            gst::FlowError::Error
+        })?;
+
+        let buffer = self.sinkpad.pull_range(offset + header.header_size, size)?;
+        if buffer.size() != size as usize {
+            gst::element_imp_error!(
+                self,
+                gst::StreamError::Demux,
+                [
+                    "Truncated {} box at offset {offset}",
+                    boxes::fourcc_to_string(&header.fourcc)
+                ]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        buffer.into_mapped_buffer_readable().map_err(|_| {
+            gst::error!(CAT, imp = self, "Failed to map buffer");
+            gst::FlowError::Error
+        })
+    }
+
+    /// Reads the fragment index from the `mfra` box at the end of the file, if any.
+    fn pull_mfra(&self, movie: &boxes::Movie) -> Vec<boxes::FragmentIndexEntry> {
+        let Some(size) = self.sinkpad.peer_query_duration::<gst::format::Bytes>() else {
+            return vec![];
+        };
+        let size = *size;
+        if size < 16 {
+            return vec![];
+        }
+
+        let Ok(mfro) = self.sinkpad.pull_range(size - 16, 16) else {
+            return vec![];
+        };
+        let Ok(mfro) = mfro.map_readable() else {
+            return vec![];
+        };
+        if mfro.len() != 16 || &mfro[4..8] != b"mfro" {
+            return vec![];
+        }
+
+        let mfra_size = u64::from(u32::from_be_bytes(mfro[12..16].try_into().unwrap()));
+        if mfra_size > size || mfra_size < 16 {
+            return vec![];
+        }
+        let offset = size - mfra_size;
+
+        let res = (|| {
+            let header = self.pull_box_header(offset).ok()??;
+            if &header.fourcc != b"mfra" || header.size != Some(mfra_size) {
+                return None;
+            }
+
+            let data = self.pull_box(offset, &header, mfra_size).ok()?;
+            match boxes::parse_mfra(&data, movie) {
+                Ok(entries) => Some(entries),
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to parse mfra: {err:?}");
+                    None
+                }
+            }
+        })();
+
+        res.unwrap_or_default()
+    }
+
+    /// Scans the top-level boxes until the `moov` and, for fragmented files, the first `moof`
+    /// is found.
+    fn pull_movie(&self) -> Result<(), gst::FlowError> {
+        let mut offset = 0;
+        let mut movie = None;
+        let mut first_moof_offset = None;
+        let mut fragment_index = vec![];
+
+        while let Some(header) = self.pull_box_header(offset)? {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Found box {} at offset {offset} with size {:?}",
+                boxes::fourcc_to_string(&header.fourcc),
+                header.size,
+            );
+
+            match &header.fourcc {
+                b"moov" | b"sidx" => {
+                    let Some(size) = header.size else {
+                        gst::element_imp_error!(
+                            self,
+                            gst::StreamError::Demux,
+                            ["Unsupported box size"]
+                        );
+                        return Err(gst::FlowError::Error);
+                    };
+
+                    let data = self.pull_box(offset, &header, size)?;
+                    if &header.fourcc == b"moov" {
+                        match boxes::parse_moov(&data) {
+                            Ok(m) => movie = Some(m),
+                            Err(err) => {
+                                gst::element_imp_error!(
+                                    self,
+                                    gst::StreamError::Demux,
+                                    ["Failed to parse moov: {err:?}"]
+                                );
+                                return Err(gst::FlowError::Error);
+                            }
+                        }
+                    } else if fragment_index.is_empty() {
+                        match boxes::parse_sidx(&data, offset + size) {
+                            Ok(entries) => fragment_index = entries,
+                            Err(err) => {
+                                gst::warning!(CAT, imp = self, "Failed to parse sidx: {err:?}");
+                            }
+                        }
+                    }
+                }
+                b"moof" => {
+                    first_moof_offset = Some(offset);
+                    break;
+                }
+                _ => (),
+            }
+
+            if movie.as_ref().is_some_and(|m: &boxes::Movie| !m.fragmented) {
+                break;
+            }
+
+            let Some(size) = header.size else {
+                break;
+            };
+            offset += size;
+        }
+
+        let Some(movie) = movie else {
+            gst::element_imp_error!(self, gst::StreamError::Demux, ["No moov box found"]);
+            return Err(gst::FlowError::Error);
+        };
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Found movie with {} tracks, fragmented {}",
+            movie.tracks.len(),
+            movie.fragmented
+        );
+
+        if movie.fragmented {
+            let mfra = self.pull_mfra(&movie);
+            if !mfra.is_empty() {
+                fragment_index = mfra;
+            }
+        }
+
+        let infos = {
+            let mut state = self.state.lock().unwrap();
+            state.offset = first_moof_offset.unwrap_or(u64::MAX);
+            state.first_moof_offset = first_moof_offset;
+            state.fragment_index = fragment_index;
+            state.movie = Some(movie);
+            state.stream_infos()
+        };
+
+        self.create_streams(infos);
+
+        Ok(())
+    }
+
+    /// Outputs the next sample in pull mode, parsing `moof` boxes as needed.
+    fn pull_step(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if self.state.lock().unwrap().movie.is_none() {
+            self.pull_movie()?;
+        }
+
+        loop {
+            let mut state = self.state.lock().unwrap();
+
+            if let Some(stream_idx) = state.next_stream(None) {
+                let sample = state.streams[stream_idx].pending.pop_front().unwrap();
+                drop(state);
+
+                let buffer = self.sinkpad.pull_range(sample.offset, sample.size)?;
+                if buffer.size() != sample.size as usize {
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Truncated sample at offset {}",
+                        sample.offset
+                    );
+                    return Err(gst::FlowError::Eos);
+                }
+
+                return self.push_sample(stream_idx, sample, buffer);
+            }
+
+            let movie = state.movie.as_ref().unwrap();
+            if !movie.fragmented || state.streams.iter().all(|s| s.eos) {
+                return Err(gst::FlowError::Eos);
+            }
+
+            let offset = state.offset;
+            drop(state);
+
+            let Some(header) = self.pull_box_header(offset)? else {
+                return Err(gst::FlowError::Eos);
+            };
+            let Some(size) = header.size else {
+                return Err(gst::FlowError::Eos);
+            };
+
+            if &header.fourcc == b"moof" {
+                let data = self.pull_box(offset, &header, size)?;
+
+                let mut state = self.state.lock().unwrap();
+                let state = &mut *state;
+                let fragment = boxes::parse_moof(
+                    &data,
+                    offset,
+                    state.movie.as_ref().unwrap(),
+                    &mut state.decode_times,
+                )
+                .map_err(|err| {
+                    gst::element_imp_error!(
+                        self,
+                        gst::StreamError::Demux,
+                        ["Failed to parse moof: {err:?}"]
+                    );
+                    gst::FlowError::Error
+                })?;
+
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Parsed fragment {} at offset {offset}",
+                    fragment.sequence_number
+                );
+
+                for traf in fragment.track_fragments {
+                    state.queue_samples(traf.track_id, traf.samples);
+                }
+            }
+
+            self.state.lock().unwrap().offset = offset + size;
+        }
+    }
+
+    fn loop_fn(&self) {
+        if let Err(flow) = self.pull_step() {
+            match flow {
+                gst::FlowError::Flushing => {
+                    gst::debug!(CAT, imp = self, "Pausing after flow {:?}", flow);
+                }
+                gst::FlowError::Eos => {
+                    self.push_eos();
+
+                    gst::debug!(CAT, imp = self, "Pausing after flow {:?}", flow);
+                }
+                _ => {
+                    self.push_eos();
+
+                    gst::error!(CAT, imp = self, "Pausing after flow {:?}", flow);
+
+                    gst::element_imp_error!(
+                        self,
+                        gst::StreamError::Failed,
+                        ["Streaming stopped, reason: {:?}", flow]
+                    );
+                }
+            }
+
+            self.sinkpad.pause_task().unwrap();
+        }
+    }
+
+    /// Runs one parsing step on the data in the adapter in push mode.
+    fn push_step(&self, state: &mut State) -> Result<Step, gst::ErrorMessage> {
+        loop {
+            if let Some(skip_until) = state.skip_until {
+                let available = state.adapter.available();
+                if available == 0 {
+                    return Ok(Step::NeedData);
+                }
+
+                let skip = std::cmp::min(available as u64, skip_until - state.offset) as usize;
+                state.adapter.flush(skip);
+                state.offset += skip as u64;
+                if state.offset == skip_until {
+                    state.skip_until = None;
+                }
+                continue;
+            }
+
+            if let Some(mdat_end) = state.mdat_end {
+                let Some(stream_idx) = state.next_stream(Some(mdat_end)) else {
+                    gst::trace!(CAT, imp = self, "No further samples in mdat");
+                    state.mdat_end = None;
+                    state.skip_until = Some(mdat_end);
+                    continue;
+                };
+
+                let sample = *state.streams[stream_idx].pending.front().unwrap();
+                if sample.offset < state.offset {
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Sample at offset {} already passed",
+                        sample.offset
+                    );
+                    state.streams[stream_idx].pending.pop_front();
+                    continue;
+                } else if sample.offset > state.offset {
+                    state.skip_until = Some(sample.offset);
+                    continue;
+                }
+
+                if state.adapter.available() < sample.size as usize {
+                    return Ok(Step::NeedData);
+                }
+
+                let buffer = state.adapter.take_buffer(sample.size as usize).unwrap();
+                state.offset += u64::from(sample.size);
+                state.streams[stream_idx].pending.pop_front();
+
+                return Ok(Step::Sample(stream_idx, sample, buffer));
+            }
+
+            let header = {
+                let available = state.adapter.available();
+                if available < 8 {
+                    return Ok(Step::NeedData);
+                }
+
+                let map = state
+                    .adapter
+                    .map(std::cmp::min(available, boxes::MAX_BOX_HEADER_SIZE))
+                    .unwrap();
+                let header = boxes::parse_box_header(&map).map_err(|err| {
+                    gst::error_msg!(
+                        gst::StreamError::Demux,
+                        ["Invalid box header at offset {}: {err:?}", state.offset]
+                    )
+                })?;
+
+                match header {
+                    Some(header) => header,
+                    None => return Ok(Step::NeedData),
+                }
+            };
+
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Found box {} at offset {} with size {:?}",
+                boxes::fourcc_to_string(&header.fourcc),
+                state.offset,
+                header.size,
+            );
+
+            match &header.fourcc {
+                b"moov" | b"moof" | b"sidx" | b"mfra" => {
+                    let Some(size) = header.size else {
+                        return Err(gst::error_msg!(
+                            gst::StreamError::Demux,
+                            ["Unsupported box size"]
+                        ));
+                    };
+                    let size = usize::try_from(size)
+                        .map_err(|_| gst::error_msg!(gst::StreamError::Demux, ["Too big box"]))?;
+
+                    if state.adapter.available() < size {
+                        return Ok(Step::NeedData);
+                    }
+
+                    let offset = state.offset;
+                    let data = state.adapter.take_buffer(size).unwrap();
+                    state.offset += size as u64;
+                    let data = data.map_readable().unwrap();
+                    let data = &data[header.header_size as usize..];
+
+                    match &header.fourcc {
+                        b"moov" => {
+                            if state.movie.is_some() {
+                                gst::warning!(CAT, imp = self, "Ignoring additional moov");
+                                continue;
+                            }
+
+                            let movie = boxes::parse_moov(data).map_err(|err| {
+                                gst::error_msg!(
+                                    gst::StreamError::Demux,
+                                    ["Failed to parse moov: {err:?}"]
+                                )
+                            })?;
+
+                            gst::debug!(
+                                CAT,
+                                imp = self,
+                                "Found movie with {} tracks, fragmented {}",
+                                movie.tracks.len(),
+                                movie.fragmented
+                            );
+
+                            state.movie = Some(movie);
+                            return Ok(Step::CreateStreams(state.stream_infos()));
+                        }
+                        b"moof" => {
+                            let Some(movie) = state.movie.as_ref() else {
+                                return Err(gst::error_msg!(
+                                    gst::StreamError::Demux,
+                                    ["moof before moov"]
+                                ));
+                            };
+
+                            let fragment =
+                                boxes::parse_moof(data, offset, movie, &mut state.decode_times)
+                                    .map_err(|err| {
+                                        gst::error_msg!(
+                                            gst::StreamError::Demux,
+                                            ["Failed to parse moof: {err:?}"]
+                                        )
+                                    })?;
+
+                            gst::trace!(
+                                CAT,
+                                imp = self,
+                                "Parsed fragment {}",
+                                fragment.sequence_number
+                            );
+
+                            for traf in fragment.track_fragments {
+                                state.queue_samples(traf.track_id, traf.samples);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                b"mdat" => {
+                    if state.movie.is_none() {
+                        return Err(gst::error_msg!(
+                            gst::StreamError::Demux,
+                            ["mdat before moov is only supported in pull mode"]
+                        ));
+                    }
+
+                    state.adapter.flush(header.header_size as usize);
+                    state.mdat_end = Some(header.size.map_or(u64::MAX, |size| state.offset + size));
+                    state.offset += header.header_size;
+                }
+                _ => {
+                    state.skip_until =
+                        Some(header.size.map_or(u64::MAX, |size| state.offset + size));
+                }
+            }
+        }
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj = pad, "Handling buffer {:?}", buffer);
+
+        let mut state = self.state.lock().unwrap();
+        state.adapter.push(buffer);
+
+        loop {
+            let step = match self.push_step(&mut state) {
+                Ok(step) => step,
+                Err(err) => {
+                    drop(state);
+                    self.post_error_message(err);
+                    return Err(gst::FlowError::Error);
+                }
+            };
+
+            match step {
+                Step::NeedData => {
+                    gst::trace!(CAT, imp = self, "Need more data");
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+                Step::CreateStreams(infos) => {
+                    drop(state);
+                    self.create_streams(infos);
+                }
+                Step::Sample(stream_idx, sample, buffer) => {
+                    drop(state);
+                    self.push_sample(stream_idx, sample, buffer)?;
+                }
+            }
+
+            state = self.state.lock().unwrap();
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst::log!(CAT, obj = pad, "Handling event {:?}", event);
+        match event.view() {
+            EventView::Caps(..) | EventView::StreamStart(..) => true,
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                // Upstream byte segments are replaced by our own time segments
+                state.need_segment = true;
+
+                // Data continues at the start of a new byte segment if nothing is pending, e.g.
+                // after a flush
+                if !state.pull_mode
+                    && state.adapter.available() == 0
+                    && state.skip_until.is_none()
+                    && state.mdat_end.is_none()
+                {
+                    if let Some(segment) = e.segment().downcast_ref::<gst::format::Bytes>() {
+                        state.offset = segment.start().map_or(0, |start| *start);
+                    }
+                }
+                true
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if !state.pull_mode {
+                    // Parsing restarts at a box boundary with the data after the flush, and the
+                    // samples of the current fragment can't be found in it anymore
+                    state.adapter.clear();
+                    state.offset = 0;
+                    state.skip_until = None;
+                    state.mdat_end = None;
+                    for stream in &mut state.streams {
+                        stream.pending.clear();
+                        stream.discont = true;
+                        stream.eos = false;
+                    }
+                }
+                drop(state);
+
+                self.flow_combiner.lock().unwrap().reset();
+                gst::Pad::event_default(pad, Some(&*self.obj()), event)
+            }
+            EventView::Eos(..) => {
+                if !self.state.lock().unwrap().pull_mode {
+                    self.push_eos();
+                }
+                true
+            }
+            _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+        }
+    }
+
+    fn perform_seek(&self, event: &gst::event::Seek) -> bool {
+        if !self.state.lock().unwrap().pull_mode {
+            gst::debug!(CAT, imp = self, "Forwarding seek upstream in push mode");
+            return self.sinkpad.push_event(event.clone());
+        }
+
+        let (rate, flags, start_type, start, stop_type, stop) = event.get();
+
+        let start: Option<gst::ClockTime> = match start.try_into() {
+            Ok(start) => start,
+            Err(_) => {
+                gst::error!(CAT, imp = self, "seek has invalid format");
+                return false;
+            }
+        };
+
+        let stop: Option<gst::ClockTime> = match stop.try_into() {
+            Ok(stop) => stop,
+            Err(_) => {
+                gst::error!(CAT, imp = self, "seek has invalid format");
+                return false;
+            }
+        };
+
+        if !flags.contains(gst::SeekFlags::FLUSH) {
+            gst::error!(CAT, imp = self, "only flushing seeks are supported");
+            return false;
+        }
+
+        if rate < 0.0 {
+            gst::error!(CAT, imp = self, "reverse playback is not supported");
+            return false;
+        }
+
+        if start_type == gst::SeekType::End || stop_type == gst::SeekType::End {
+            gst::error!(CAT, imp = self, "Relative seeks are not supported");
+            return false;
+        }
+
+        let seek_seqnum = event.seqnum();
+
+        let event = gst::event::FlushStart::builder()
+            .seqnum(seek_seqnum)
+            .build();
+
+        gst::debug!(CAT, imp = self, "Sending event {:?} upstream", event);
+        self.sinkpad.push_event(event.clone());
+
+        let pads = self
+            .state
+            .lock()
+            .unwrap()
+            .streams
+            .iter()
+            .map(|s| s.pad.clone())
+            .collect::<Vec<_>>();
+        for pad in &pads {
+            gst::debug!(CAT, obj = pad, "Pushing event {:?}", event);
+            pad.push_event(event.clone());
+        }
+
+        self.sinkpad.pause_task().unwrap();
+
+        let event = gst::event::FlushStop::builder(true)
+            .seqnum(seek_seqnum)
+            .build();
+
+        gst::debug!(CAT, imp = self, "Sending event {:?} upstream", event);
+        self.sinkpad.push_event(event.clone());
+        for pad in &pads {
+            gst::debug!(CAT, obj = pad, "Pushing event {:?}", event);
+            pad.push_event(event.clone());
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            state
+                .segment
+                .do_seek(rate, flags, start_type, start, stop_type, stop);
+            state.seek_seqnum = Some(seek_seqnum);
+            state.need_segment = true;
+            state.last_position = None;
+
+            if state.movie.is_some() {
+                state.reset_streams();
+            }
+        }
+
+        self.flow_combiner.lock().unwrap().reset();
+
+        match self.start_task() {
+            Err(error) => {
+                error.log();
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst::log!(CAT, obj = pad, "Handling event {:?}", event);
+        match event.view() {
+            EventView::Seek(e) => self.perform_seek(e),
+            _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryViewMut;
+
+        gst::log!(CAT, obj = pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryViewMut::Seeking(q) => {
+                let state = self.state.lock().unwrap();
+
+                if q.format() == gst::Format::Time && state.pull_mode {
+                    let duration = state.movie.as_ref().and_then(|m| m.duration());
+                    q.set(true, gst::ClockTime::ZERO, duration);
+                    true
+                } else if q.format() == gst::Format::Time {
+                    q.set(false, gst::ClockTime::NONE, gst::ClockTime::NONE);
+                    true
+                } else {
+                    false
+                }
+            }
+            QueryViewMut::Position(q) => {
+                if q.format() == gst::Format::Time {
+                    let state = self.state.lock().unwrap();
+                    q.set(state.last_position);
+                    true
+                } else {
+                    false
+                }
+            }
+            QueryViewMut::Duration(q) => {
+                if q.format() == gst::Format::Time {
+                    let state = self.state.lock().unwrap();
+                    match state.movie.as_ref().and_then(|m| m.duration()) {
+                        Some(duration) => {
+                            q.set(duration);
+                            true
+                        }
+                        None => false,
+                    }
+                } else {
+                    false
+                }
+            }
+            _ => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for IsobmffDemux {
+    const NAME: &'static str = "GstIsobmffDemux";
+    type Type = super::IsobmffDemux;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .activate_function(|pad, parent| {
+                IsobmffDemux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::loggable_error!(CAT, "Panic activating sink pad")),
+                    |demux| demux.sink_activate(pad),
+                )
+            })
+            .activatemode_function(|pad, parent, mode, active| {
+                IsobmffDemux::catch_panic_pad_function(
+                    parent,
+                    || {
+                        Err(gst::loggable_error!(
+                            CAT,
+                            "Panic activating sink pad with mode"
+                        ))
+                    },
+                    |demux| demux.sink_activatemode(pad, mode, active),
+                )
+            })
+            .chain_function(|pad, parent, buffer| {
+                IsobmffDemux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |demux| demux.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                IsobmffDemux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux| demux.sink_event(pad, event),
+                )
+            })
+            .build();
+
+        Self {
+            sinkpad,
+            state: Mutex::new(State::default()),
+            flow_combiner: Mutex::new(gst_base::UniqueFlowCombiner::new()),
+        }
+    }
+}
+
+impl ObjectImpl for IsobmffDemux {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        self.obj().add_pad(&self.sinkpad).unwrap();
+    }
+}
+
+impl GstObjectImpl for IsobmffDemux {}
+
+impl ElementImpl for IsobmffDemux {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "ISOBMFF Demuxer",
+                "Codec/Demuxer",
+                "Demuxes ISO base media file format (MP4) files, including fragmented MP4",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &[
+                    gst::Structure::builder("video/quicktime").build(),
+                    gst::Structure::builder("audio/x-m4a").build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let video_src_pad_template = gst::PadTemplate::new(
+                "video_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &[
+                    gst::Structure::builder("video/x-h264")
+                        .field("stream-format", gst::List::new(["avc", "avc3"]))
+                        .field("alignment", "au")
+                        .build(),
+                    gst::Structure::builder("video/x-h265")
+                        .field("stream-format", gst::List::new(["hvc1", "hev1"]))
+                        .field("alignment", "au")
+                        .build(),
+                    gst::Structure::builder("video/x-vp8").build(),
+                    gst::Structure::builder("video/x-vp9").build(),
+                    gst::Structure::builder("video/x-av1")
+                        .field("stream-format", "obu-stream")
+                        .field("alignment", "tu")
+                        .build(),
+                    gst::Structure::builder("image/jpeg").build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let audio_src_pad_template = gst::PadTemplate::new(
+                "audio_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &[
+                    gst::Structure::builder("audio/mpeg")
+                        .field("mpegversion", 4i32)
+                        .field("stream-format", "raw")
+                        .build(),
+                    gst::Structure::builder("audio/mpeg")
+                        .field("mpegversion", 1i32)
+                        .field("layer", 3i32)
+                        .build(),
+                    gst::Structure::builder("audio/x-opus").build(),
+                    gst::Structure::builder("audio/x-flac").build(),
+                    gst::Structure::builder("audio/x-alaw").build(),
+                    gst::Structure::builder("audio/x-mulaw").build(),
+                    gst::Structure::builder("audio/x-adpcm")
+                        .field("layout", "g726")
+                        .build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let meta_src_pad_template = gst::PadTemplate::new(
+                "meta_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::builder("application/x-onvif-metadata")
+                    .field("parsed", true)
+                    .build(),
+            )
+            .unwrap();
+
+            vec![
+                sink_pad_template,
+                video_src_pad_template,
+                audio_src_pad_template,
+                meta_src_pad_template,
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst::trace!(CAT, imp = self, "Changing state {:?}", transition);
+
+        let res = self.parent_change_state(transition)?;
+
+        if transition == gst::StateChange::PausedToReady {
+            let streams = {
+                let mut state = self.state.lock().unwrap();
+                let streams = std::mem::take(&mut state.streams);
+                *state = State::default();
+                streams
+            };
+
+            let mut flow_combiner = self.flow_combiner.lock().unwrap();
+            for stream in streams {
+                flow_combiner.remove_pad(&stream.pad);
+                let _ = self.obj().remove_pad(&stream.pad);
+            }
+            flow_combiner.reset();
+        }
+
+        Ok(res)
+    }
+}
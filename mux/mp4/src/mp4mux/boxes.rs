@@ -13,6 +13,10 @@ use std::convert::TryFrom;
 use std::str::FromStr;
 
 use super::{ImageOrientation, IDENTITY_MATRIX};
+use crate::isobmff::{
+    language_code, FULL_BOX_FLAGS_NONE, FULL_BOX_VERSION_0, FULL_BOX_VERSION_1,
+    TKHD_FLAGS_TRACK_ENABLED, TKHD_FLAGS_TRACK_IN_MOVIE, TKHD_FLAGS_TRACK_IN_PREVIEW,
+};
 
 fn write_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
     vec: &mut Vec<u8>,
@@ -38,11 +42,6 @@ fn write_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
     Ok(res)
 }
 
-const FULL_BOX_VERSION_0: u8 = 0;
-const FULL_BOX_VERSION_1: u8 = 1;
-
-const FULL_BOX_FLAGS_NONE: u32 = 0;
-
 fn write_full_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
     vec: &mut Vec<u8>,
     fourcc: impl std::borrow::Borrow<[u8; 4]>,
@@ -218,10 +217,29 @@ fn write_moov(v: &mut Vec<u8>, header: &super::Header) -> Result<(), Error> {
                 }
             }
 
+            // Reference the QuickTime chapter track from all other tracks
+            if let Some(chapter_track) = header.chapter_track {
+                if chapter_track != idx {
+                    references.push(TrackReference {
+                        reference_type: *b"chap",
+                        track_ids: vec![chapter_track as u32 + 1],
+                    });
+                }
+            }
+
             write_trak(v, header, idx, stream, creation_time, &references)
         })?;
     }
 
+    let mut udta = vec![];
+    write_udta(&mut udta, header)?;
+    if !udta.is_empty() {
+        write_box(v, b"udta", |v| {
+            v.extend(udta);
+            Ok(())
+        })?;
+    }
+
     Ok(())
 }
 
@@ -328,10 +346,6 @@ fn write_mvhd(v: &mut Vec<u8>, header: &super::Header, creation_time: u64) -> Re
     Ok(())
 }
 
-const TKHD_FLAGS_TRACK_ENABLED: u32 = 0x1;
-const TKHD_FLAGS_TRACK_IN_MOVIE: u32 = 0x2;
-const TKHD_FLAGS_TRACK_IN_PREVIEW: u32 = 0x4;
-
 fn write_trak(
     v: &mut Vec<u8>,
     header: &super::Header,
@@ -340,13 +354,17 @@ fn write_trak(
     creation_time: u64,
     references: &[TrackReference],
 ) -> Result<(), Error> {
-    write_full_box(
-        v,
-        b"tkhd",
-        FULL_BOX_VERSION_1,
-        TKHD_FLAGS_TRACK_ENABLED | TKHD_FLAGS_TRACK_IN_MOVIE | TKHD_FLAGS_TRACK_IN_PREVIEW,
-        |v| write_tkhd(v, header, idx, stream, creation_time),
-    )?;
+    // The QuickTime chapter track is only referenced by the other tracks and not presented
+    // by itself
+    let flags = if header.chapter_track == Some(idx) {
+        TKHD_FLAGS_TRACK_IN_MOVIE
+    } else {
+        TKHD_FLAGS_TRACK_ENABLED | TKHD_FLAGS_TRACK_IN_MOVIE | TKHD_FLAGS_TRACK_IN_PREVIEW
+    };
+
+    write_full_box(v, b"tkhd", FULL_BOX_VERSION_1, flags, |v| {
+        write_tkhd(v, header, idx, stream, creation_time)
+    })?;
 
     write_box(v, b"mdia", |v| write_mdia(v, header, stream, creation_time))?;
     if !references.is_empty() {
@@ -398,7 +416,9 @@ fn write_tkhd(
     let s = stream.caps.structure(0).unwrap();
     match s.name().as_str() {
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => v.extend((1u16 << 8).to_be_bytes()),
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            v.extend((1u16 << 8).to_be_bytes())
+        }
         _ => v.extend(0u16.to_be_bytes()),
     }
 
@@ -457,21 +477,30 @@ fn write_mdia(
         write_hdlr(v, header, stream)
     })?;
 
-    // TODO: write elng if needed
+    // Extended language for everything that can't be represented in the `mdhd` box
+    if let Some(ref lang) = stream.language {
+        if lang.len() != 3 || iso_639_2t_code(lang).is_none() {
+            write_full_box(v, b"elng", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+                v.extend(lang.as_bytes());
+                v.push(0);
+
+                Ok(())
+            })?;
+        }
+    }
 
     write_box(v, b"minf", |v| write_minf(v, header, stream))?;
 
     Ok(())
 }
 
-fn language_code(lang: impl std::borrow::Borrow<[u8; 3]>) -> u16 {
-    let lang = lang.borrow();
+/// Returns the ISO-639-2/T code of an ISO-639-2/T or BCP-47 language if it has one.
+fn iso_639_2t_code(lang: &str) -> Option<[u8; 3]> {
+    let primary = lang.split('-').next()?;
 
-    assert!(lang.iter().all(u8::is_ascii_lowercase));
-
-    (((lang[0] as u16 - 0x60) & 0x1F) << 10)
-        + (((lang[1] as u16 - 0x60) & 0x1F) << 5)
-        + ((lang[2] as u16 - 0x60) & 0x1F)
+    <[u8; 3]>::try_from(primary.as_bytes())
+        .ok()
+        .filter(|code| code.iter().all(u8::is_ascii_lowercase))
 }
 
 fn write_mdhd(
@@ -499,11 +528,13 @@ fn write_mdhd(
     v.extend(duration.to_be_bytes());
 
     // Language as ISO-639-2/T
-    if let Some(lang) = header.language_code {
-        v.extend(language_code(lang).to_be_bytes());
-    } else {
-        v.extend(language_code(b"und").to_be_bytes());
-    }
+    let lang = stream
+        .language
+        .as_deref()
+        .and_then(iso_639_2t_code)
+        .or(header.language_code)
+        .unwrap_or(*b"und");
+    v.extend(language_code(lang).to_be_bytes());
 
     // Pre-defined
     v.extend([0u8; 2]);
@@ -524,8 +555,12 @@ fn write_hdlr(
         "video/x-h264" | "video/x-h265" | "video/x-vp8" | "video/x-vp9" | "video/x-av1"
         | "image/jpeg" => (b"vide", b"VideoHandler\0".as_slice()),
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => (b"soun", b"SoundHandler\0".as_slice()),
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            (b"soun", b"SoundHandler\0".as_slice())
+        }
         "application/x-onvif-metadata" => (b"meta", b"MetadataHandler\0".as_slice()),
+        "application/x-subtitle-vtt" | "text/x-raw" => (b"text", b"TextHandler\0".as_slice()),
+        "application/ttml+xml" => (b"subt", b"SubtitleHandler\0".as_slice()),
         _ => unreachable!(),
     };
 
@@ -555,16 +590,21 @@ fn write_minf(
             write_full_box(v, b"vmhd", FULL_BOX_VERSION_0, 1, |v| write_vmhd(v, header))?
         }
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => {
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
             write_full_box(v, b"smhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
                 write_smhd(v, header)
             })?
         }
-        "application/x-onvif-metadata" => {
+        "application/x-onvif-metadata" | "application/x-subtitle-vtt" | "text/x-raw" => {
             write_full_box(v, b"nmhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
                 Ok(())
             })?
         }
+        "application/ttml+xml" => {
+            write_full_box(v, b"sthd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
         _ => unreachable!(),
     }
 
@@ -713,8 +753,13 @@ fn write_stsd(
         "video/x-h264" | "video/x-h265" | "video/x-vp8" | "video/x-vp9" | "video/x-av1"
         | "image/jpeg" => write_visual_sample_entry(v, header, stream)?,
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
-        | "audio/x-adpcm" => write_audio_sample_entry(v, header, stream)?,
+        | "audio/x-adpcm" | "audio/x-ac3" | "audio/x-eac3" | "audio/x-ac4" => {
+            write_audio_sample_entry(v, header, stream)?
+        }
         "application/x-onvif-metadata" => write_xml_meta_data_sample_entry(v, header, stream)?,
+        "application/x-subtitle-vtt" | "application/ttml+xml" | "text/x-raw" => {
+            write_subtitle_sample_entry(v, header, stream)?
+        }
         _ => unreachable!(),
     }
 
@@ -985,6 +1030,24 @@ fn write_visual_sample_entry(
         }
 
         if let Ok(par) = s.get::<gst::Fraction>("pixel-aspect-ratio") {
+            // Width and height in the caps are already the cropped display size so the clean
+            // aperture is always the whole frame, but QuickTime expects it together with `pasp`.
+            write_box(v, b"clap", move |v| {
+                // Clean aperture width and height
+                v.extend(u32::from(width).to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+                v.extend(u32::from(height).to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+
+                // Horizontal and vertical offset
+                v.extend(0i32.to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+                v.extend(0i32.to_be_bytes());
+                v.extend(1u32.to_be_bytes());
+
+                Ok(())
+            })?;
+
             write_box(v, b"pasp", move |v| {
                 v.extend((par.numer() as u32).to_be_bytes());
                 v.extend((par.denom() as u32).to_be_bytes());
@@ -1039,7 +1102,7 @@ fn write_visual_sample_entry(
                 v.extend(mastering.white_point().x.to_be_bytes());
                 v.extend(mastering.white_point().y.to_be_bytes());
                 v.extend(mastering.max_display_mastering_luminance().to_be_bytes());
-                v.extend(mastering.max_display_mastering_luminance().to_be_bytes());
+                v.extend(mastering.min_display_mastering_luminance().to_be_bytes());
                 Ok(())
             })?;
         }
@@ -1133,6 +1196,9 @@ fn write_audio_sample_entry(
         "audio/x-flac" => b"fLaC",
         "audio/x-alaw" => b"alaw",
         "audio/x-mulaw" => b"ulaw",
+        "audio/x-ac3" => b"ac-3",
+        "audio/x-eac3" => b"ec-3",
+        "audio/x-ac4" => b"ac-4",
         "audio/x-adpcm" => {
             let layout = s.get::<&str>("layout").context("no ADPCM layout field")?;
 
@@ -1198,6 +1264,33 @@ fn write_audio_sample_entry(
             "audio/x-flac" => {
                 write_dfla(v, &stream.caps)?;
             }
+            "audio/x-ac3" | "audio/x-eac3" => {
+                let extra_data = stream
+                    .extra_header_data
+                    .as_ref()
+                    .context("no AC-3 bit stream information")?;
+                let fourcc = if s.name() == "audio/x-ac3" {
+                    b"dac3"
+                } else {
+                    b"dec3"
+                };
+                write_box(v, fourcc, move |v| {
+                    v.extend_from_slice(extra_data);
+                    Ok(())
+                })?;
+            }
+            "audio/x-ac4" => {
+                let codec_data = s
+                    .get::<&gst::BufferRef>("codec_data")
+                    .context("no codec_data")?;
+                let map = codec_data
+                    .map_readable()
+                    .context("codec_data not mappable")?;
+                write_box(v, b"dac4", move |v| {
+                    v.extend_from_slice(&map);
+                    Ok(())
+                })?;
+            }
             "audio/x-alaw" | "audio/x-mulaw" | "audio/x-adpcm" => {
                 // Nothing to do here
             }
@@ -1449,6 +1542,187 @@ fn write_xml_meta_data_sample_entry(
     Ok(())
 }
 
+fn write_subtitle_sample_entry(
+    v: &mut Vec<u8>,
+    _header: &super::Header,
+    stream: &super::Stream,
+) -> Result<(), Error> {
+    let s = stream.caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => write_sample_entry_box(v, b"wvtt", |v| {
+            write_box(v, b"vttC", |v| {
+                // WebVTT file header without any further header blocks
+                v.extend(b"WEBVTT");
+
+                Ok(())
+            })
+        })?,
+        "application/ttml+xml" => write_sample_entry_box(v, b"stpp", |v| {
+            // namespace
+            v.extend(b"http://www.w3.org/ns/ttml");
+            v.push(0);
+
+            // schema_location, empty string list
+            v.push(0);
+
+            // auxiliary_mime_types, empty string list
+            v.push(0);
+
+            Ok(())
+        })?,
+        "text/x-raw" => write_sample_entry_box(v, b"tx3g", |v| {
+            // Display flags
+            v.extend(0u32.to_be_bytes());
+
+            // Horizontal justification: centered
+            v.push(1);
+            // Vertical justification: bottom
+            v.push(0xff);
+
+            // Background color, transparent
+            v.extend([0u8; 4]);
+
+            // Default text box: top, left, bottom, right
+            v.extend([0u8; 4 * 2]);
+
+            // Default style
+            // Start and end character offset
+            v.extend(0u16.to_be_bytes());
+            v.extend(0u16.to_be_bytes());
+            // Font ID
+            v.extend(1u16.to_be_bytes());
+            // Face style flags
+            v.push(0);
+            // Font size
+            v.push(18);
+            // Text color, opaque white
+            v.extend([0xffu8; 4]);
+
+            write_box(v, b"ftab", |v| {
+                // Entry count
+                v.extend(1u16.to_be_bytes());
+
+                // Font ID
+                v.extend(1u16.to_be_bytes());
+                // Font name
+                let font_name = b"Sans-Serif";
+                v.push(font_name.len() as u8);
+                v.extend(font_name);
+
+                Ok(())
+            })
+        })?,
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Converts a subtitle buffer into the sample format of the corresponding sample entry.
+///
+/// Returns `None` if the buffer does not contain a sample, e.g. WebVTT header blocks.
+pub(super) fn subtitle_sample(caps: &gst::CapsRef, data: &[u8]) -> Option<Vec<u8>> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => webvtt_cue_sample(data),
+        "application/ttml+xml" => Some(data.to_vec()),
+        "text/x-raw" => {
+            let text = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+            let len = u16::try_from(text.len()).ok()?;
+
+            let mut v = Vec::with_capacity(2 + text.len());
+            v.extend(len.to_be_bytes());
+            v.extend(text.as_bytes());
+
+            Some(v)
+        }
+        _ => unreachable!(),
+    }
+}
+
+const TTML_EMPTY_DOCUMENT: &[u8] =
+    b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><tt xmlns=\"http://www.w3.org/ns/ttml\"/>";
+
+/// Creates an empty sample for filling gaps between subtitles.
+pub(super) fn subtitle_gap_sample(caps: &gst::CapsRef) -> Vec<u8> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" => {
+            let mut v = vec![];
+            write_box(&mut v, b"vtte", |_v| Ok(())).unwrap();
+            v
+        }
+        "application/ttml+xml" => TTML_EMPTY_DOCUMENT.to_vec(),
+        "text/x-raw" => 0u16.to_be_bytes().to_vec(),
+        _ => unreachable!(),
+    }
+}
+
+/// Converts a WebVTT cue block into a `vttc` box.
+///
+/// The timing line is optional as the timing is already part of the buffer.
+fn webvtt_cue_sample(data: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(data).ok()?;
+    let text = text
+        .trim_start_matches('\u{feff}')
+        .trim_matches(['\r', '\n']);
+
+    let mut id = None;
+    let mut settings = None;
+    let mut lines = text.lines().peekable();
+
+    // Header and comment blocks are not cues
+    let first_line = *lines.peek()?;
+    if first_line.starts_with("WEBVTT")
+        || first_line.starts_with("NOTE")
+        || first_line.starts_with("STYLE")
+        || first_line.starts_with("REGION")
+    {
+        return None;
+    }
+
+    // Optional cue identifier followed by the optional timing line
+    if !first_line.contains("-->") && lines.clone().nth(1).is_some_and(|l| l.contains("-->")) {
+        id = lines.next();
+    }
+    if let Some(timing) = lines.next_if(|l| l.contains("-->")) {
+        let (_, rest) = timing.split_once("-->").unwrap();
+        let rest = rest.trim_start();
+        settings = rest
+            .split_once(char::is_whitespace)
+            .map(|(_, settings)| settings.trim())
+            .filter(|settings| !settings.is_empty());
+    }
+
+    let payload = lines.collect::<Vec<_>>().join("\n");
+    if payload.is_empty() {
+        return None;
+    }
+
+    let mut v = vec![];
+    write_box(&mut v, b"vttc", |v| {
+        if let Some(id) = id {
+            write_box(v, b"iden", |v| {
+                v.extend(id.as_bytes());
+                Ok(())
+            })?;
+        }
+        if let Some(settings) = settings {
+            write_box(v, b"sttg", |v| {
+                v.extend(settings.as_bytes());
+                Ok(())
+            })?;
+        }
+        write_box(v, b"payl", |v| {
+            v.extend(payload.as_bytes());
+            Ok(())
+        })
+    })
+    .ok()?;
+
+    Some(v)
+}
+
 fn write_stts(
     v: &mut Vec<u8>,
     _header: &super::Header,
@@ -1873,3 +2147,188 @@ fn write_elst(
 
     Ok(())
 }
+
+fn write_udta(v: &mut Vec<u8>, header: &super::Header) -> Result<(), Error> {
+    if !header.chapters.is_empty() {
+        write_full_box(v, b"chpl", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
+            write_chpl(v, header)
+        })?;
+    }
+
+    if let Some(ref tags) = header.tags {
+        let mut ilst = vec![];
+        write_ilst(&mut ilst, tags)?;
+
+        if !ilst.is_empty() {
+            write_full_box(v, b"meta", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+                write_full_box(v, b"hdlr", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+                    // Pre-defined
+                    v.extend([0u8; 4]);
+
+                    // Handler type
+                    v.extend(b"mdir");
+
+                    // Reserved, the first field is the manufacturer by convention
+                    v.extend(b"appl");
+                    v.extend([0u8; 2 * 4]);
+
+                    // Name
+                    v.push(0);
+
+                    Ok(())
+                })?;
+
+                write_box(v, b"ilst", |v| {
+                    v.extend(ilst);
+                    Ok(())
+                })
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Truncates a string to at most `len` bytes without splitting characters.
+fn truncate_str(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+fn write_chpl(v: &mut Vec<u8>, header: &super::Header) -> Result<(), Error> {
+    // Reserved
+    v.extend(0u32.to_be_bytes());
+
+    // Chapter count, further chapters can't be represented
+    let count = std::cmp::min(header.chapters.len(), u8::MAX as usize);
+    v.push(count as u8);
+
+    for chapter in header.chapters.iter().take(count) {
+        // Start time in 100ns units
+        v.extend((chapter.start.nseconds() / 100).to_be_bytes());
+
+        // Title
+        let title = truncate_str(&chapter.title, u8::MAX as usize);
+        v.push(title.len() as u8);
+        v.extend(title.as_bytes());
+    }
+
+    Ok(())
+}
+
+const DATA_TYPE_BINARY: u32 = 0;
+const DATA_TYPE_UTF8: u32 = 1;
+const DATA_TYPE_JPEG: u32 = 13;
+const DATA_TYPE_PNG: u32 = 14;
+const DATA_TYPE_BMP: u32 = 27;
+
+fn write_data(v: &mut Vec<u8>, data_type: u32, data: &[u8]) -> Result<(), Error> {
+    write_box(v, b"data", |v| {
+        // Type indicator, well-known type
+        v.extend(data_type.to_be_bytes());
+
+        // Locale, default
+        v.extend(0u32.to_be_bytes());
+
+        v.extend(data);
+
+        Ok(())
+    })
+}
+
+fn write_ilst(v: &mut Vec<u8>, tags: &gst::TagListRef) -> Result<(), Error> {
+    for (fourcc, value) in [
+        (b"\xa9nam", tags.get::<gst::tags::Title>()),
+        (b"\xa9ART", tags.get::<gst::tags::Artist>()),
+        (b"aART", tags.get::<gst::tags::AlbumArtist>()),
+        (b"\xa9alb", tags.get::<gst::tags::Album>()),
+        (b"\xa9gen", tags.get::<gst::tags::Genre>()),
+        (b"\xa9cmt", tags.get::<gst::tags::Comment>()),
+        (b"\xa9wrt", tags.get::<gst::tags::Composer>()),
+        (b"\xa9too", tags.get::<gst::tags::Encoder>()),
+        (b"cprt", tags.get::<gst::tags::Copyright>()),
+        (b"desc", tags.get::<gst::tags::Description>()),
+    ] {
+        if let Some(value) = value {
+            write_box(v, fourcc, |v| {
+                write_data(v, DATA_TYPE_UTF8, value.get().as_bytes())
+            })?;
+        }
+    }
+
+    let date = if let Some(date_time) = tags.get::<gst::tags::DateTime>() {
+        date_time.get().to_iso8601_string().ok().map(String::from)
+    } else {
+        tags.get::<gst::tags::Date>()
+            .map(|date| format!("{:04}", date.get().year()))
+    };
+    if let Some(date) = date {
+        write_box(v, b"\xa9day", |v| {
+            write_data(v, DATA_TYPE_UTF8, date.as_bytes())
+        })?;
+    }
+
+    for (fourcc, number, count) in [
+        (
+            b"trkn",
+            tags.get::<gst::tags::TrackNumber>(),
+            tags.get::<gst::tags::TrackCount>(),
+        ),
+        (
+            b"disk",
+            tags.get::<gst::tags::AlbumVolumeNumber>(),
+            tags.get::<gst::tags::AlbumVolumeCount>(),
+        ),
+    ] {
+        let Some(number) = number else {
+            continue;
+        };
+        let number = u16::try_from(number.get()).unwrap_or(u16::MAX);
+        let count = count.map_or(0, |count| u16::try_from(count.get()).unwrap_or(u16::MAX));
+
+        write_box(v, fourcc, |v| {
+            let mut data = [0u8; 8];
+            data[2..4].copy_from_slice(&number.to_be_bytes());
+            data[4..6].copy_from_slice(&count.to_be_bytes());
+
+            // Only `trkn` has two trailing reserved bytes
+            let len = if fourcc == b"trkn" { 8 } else { 6 };
+            write_data(v, DATA_TYPE_BINARY, &data[..len])
+        })?;
+    }
+
+    let mut covr = vec![];
+    for image in tags.iter_tag::<gst::tags::Image>() {
+        let image = image.get();
+        let (Some(buffer), Some(caps)) = (image.buffer(), image.caps()) else {
+            continue;
+        };
+        let data_type = match caps.structure(0).unwrap().name().as_str() {
+            "image/jpeg" => DATA_TYPE_JPEG,
+            "image/png" => DATA_TYPE_PNG,
+            "image/bmp" => DATA_TYPE_BMP,
+            _ => continue,
+        };
+
+        let map = buffer
+            .map_readable()
+            .context("failed to map image buffer")?;
+        write_data(&mut covr, data_type, &map)?;
+    }
+    if !covr.is_empty() {
+        write_box(v, b"covr", |v| {
+            v.extend(covr);
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
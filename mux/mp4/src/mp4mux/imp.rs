@@ -15,10 +15,11 @@ use gst_base::subclass::prelude::*;
 use std::collections::VecDeque;
 use std::sync::Mutex;
 
+use crate::mp4mux::ac3::{read_dac3_bytes, read_dec3_bytes};
 use crate::mp4mux::obu::read_seq_header_obu_bytes;
 use once_cell::sync::Lazy;
 
-use super::{boxes, ImageOrientation};
+use super::{boxes, ChapterMode, ImageOrientation};
 
 /// Offset between NTP and UNIX epoch in seconds.
 /// NTP = UNIX + NTP_UNIX_OFFSET.
@@ -61,6 +62,14 @@ fn running_time_to_utc_time(
         .and_then(|res| res.positive())
 }
 
+/// Checks if a language is an ISO-639-2/T code or a syntactically valid BCP-47 tag.
+fn is_valid_language(lang: &str) -> bool {
+    !lang.is_empty()
+        && lang
+            .split('-')
+            .all(|subtag| !subtag.is_empty() && subtag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
         "mp4mux",
@@ -71,12 +80,14 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 
 const DEFAULT_INTERLEAVE_BYTES: Option<u64> = None;
 const DEFAULT_INTERLEAVE_TIME: Option<gst::ClockTime> = Some(gst::ClockTime::from_mseconds(500));
+const DEFAULT_CHAPTER_MODE: ChapterMode = ChapterMode::QuickTime;
 
 #[derive(Debug, Clone)]
 struct Settings {
     interleave_bytes: Option<u64>,
     interleave_time: Option<gst::ClockTime>,
     movie_timescale: u32,
+    chapter_mode: ChapterMode,
 }
 
 impl Default for Settings {
@@ -85,6 +96,7 @@ impl Default for Settings {
             interleave_bytes: DEFAULT_INTERLEAVE_BYTES,
             interleave_time: DEFAULT_INTERLEAVE_TIME,
             movie_timescale: 0,
+            chapter_mode: DEFAULT_CHAPTER_MODE,
         }
     }
 }
@@ -111,6 +123,10 @@ struct Stream {
     delta_frames: super::DeltaFrames,
     /// Whether this stream might have header frames without timestamps that should be ignored.
     discard_header_buffers: bool,
+    /// Whether this is a sparse stream, e.g. subtitles, that might not have data for a long time.
+    ///
+    /// Gaps between buffers of sparse streams are filled with empty samples.
+    sparse: bool,
 
     /// Already written out chunks with their samples for this stream
     chunks: Vec<super::Chunk>,
@@ -141,6 +157,17 @@ struct Stream {
 
     /// Orientation from tags
     orientation: Option<ImageOrientation>,
+
+    /// Language from tags
+    language: Option<String>,
+}
+
+struct TocChapter {
+    /// Start time relative to the beginning of the movie
+    start: gst::ClockTime,
+    /// Stop time relative to the beginning of the movie, if known
+    stop: Option<gst::ClockTime>,
+    title: String,
 }
 
 #[derive(Default)]
@@ -162,6 +189,12 @@ struct State {
 
     /// Language code from tags
     language_code: Option<[u8; 3]>,
+
+    /// Merged global tags from all streams
+    tags: Option<gst::TagList>,
+
+    /// Table of contents for the chapters
+    toc: Option<gst::Toc>,
 }
 
 #[derive(Default)]
@@ -579,6 +612,31 @@ impl MP4Mux {
                             })?;
                     }
 
+                    // For AC-3 / E-AC-3 the content of the 'dac3' / 'dec3' box is taken from the
+                    // bit stream information of the first frame.
+                    if stream.extra_header_data.is_none()
+                        && matches!(s.name().as_str(), "audio/x-ac3" | "audio/x-eac3")
+                    {
+                        let buf_map = buffer.map_readable().map_err(|_| {
+                            gst::error!(CAT, obj = stream.sinkpad, "Failed to map buffer");
+                            gst::FlowError::Error
+                        })?;
+                        let res = if s.name() == "audio/x-ac3" {
+                            read_dac3_bytes(buf_map.as_slice())
+                        } else {
+                            read_dec3_bytes(buf_map.as_slice())
+                        };
+                        stream.extra_header_data = Some(res.map_err(|err| {
+                            gst::error!(
+                                CAT,
+                                obj = stream.sinkpad,
+                                "Failed to parse {} bit stream information: {err}",
+                                s.name()
+                            );
+                            gst::FlowError::Error
+                        })?);
+                    }
+
                     return Ok(());
                 }
                 None => {
@@ -834,10 +892,12 @@ impl MP4Mux {
             let stream = &mut state.streams[idx];
             let buffer = stream.pending_buffer.take().unwrap();
 
-            if buffer.buffer.flags().contains(gst::BufferFlags::GAP)
+            let is_gap_buffer = buffer.buffer.flags().contains(gst::BufferFlags::GAP)
                 && buffer.buffer.flags().contains(gst::BufferFlags::DROPPABLE)
-                && buffer.buffer.size() == 0
-            {
+                && buffer.buffer.size() == 0;
+
+            // Gaps in sparse streams are filled with empty samples below instead
+            if is_gap_buffer && !stream.sparse {
                 gst::trace!(CAT, obj = stream.sinkpad, "Skipping gap buffer {buffer:?}");
 
                 // If a new chunk was just started for the gap buffer, don't bother and get rid
@@ -887,41 +947,200 @@ impl MP4Mux {
                 state.current_offset
             );
 
-            let duration = buffer.duration.unwrap();
+            let mut duration = buffer.duration.unwrap();
             let composition_time_offset = buffer.composition_time_offset;
             let mut buffer = buffer.buffer;
 
-            stream.queued_chunk_time += duration;
-            stream.queued_chunk_bytes += buffer.size() as u64;
-
-            stream
-                .chunks
-                .last_mut()
-                .unwrap()
-                .samples
-                .push(super::Sample {
-                    sync_point: !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
-                    duration,
-                    composition_time_offset,
-                    size: buffer.size() as u32,
-                });
+            // Subtitles are converted into their sample format. Gaps, and anything that is not a
+            // sample on its own like WebVTT header blocks, become empty samples. If a subtitle
+            // ends before the next one starts then the time in between is an empty sample too.
+            let mut gap_sample = None;
+            if stream.sparse {
+                let sample = if is_gap_buffer {
+                    None
+                } else {
+                    let map = buffer.map_readable().map_err(|_| {
+                        gst::error!(CAT, obj = stream.sinkpad, "Failed to map buffer");
+                        gst::FlowError::Error
+                    })?;
+                    boxes::subtitle_sample(&stream.caps, &map)
+                };
 
-            {
-                let buffer = buffer.make_mut();
-                buffer.set_dts(None);
-                buffer.set_pts(None);
-                buffer.set_duration(duration);
-                buffer.unset_flags(gst::BufferFlags::all());
+                match sample {
+                    Some(sample) => {
+                        if let Some(buffer_duration) = buffer
+                            .duration()
+                            .filter(|buffer_duration| *buffer_duration < duration)
+                        {
+                            gap_sample = Some((
+                                gst::Buffer::from_mut_slice(boxes::subtitle_gap_sample(
+                                    &stream.caps,
+                                )),
+                                duration - buffer_duration,
+                            ));
+                            duration = buffer_duration;
+                        }
+                        buffer = gst::Buffer::from_mut_slice(sample);
+                    }
+                    None => {
+                        gst::trace!(
+                            CAT,
+                            obj = stream.sinkpad,
+                            "Writing empty sample for {buffer:?}"
+                        );
+                        buffer =
+                            gst::Buffer::from_mut_slice(boxes::subtitle_gap_sample(&stream.caps));
+                    }
+                }
             }
 
-            state.current_offset += buffer.size() as u64;
-            state.mdat_size += buffer.size() as u64;
-            buffers.add(buffer);
+            for (mut buffer, duration) in std::iter::once((buffer, duration)).chain(gap_sample) {
+                stream.queued_chunk_time += duration;
+                stream.queued_chunk_bytes += buffer.size() as u64;
+
+                stream
+                    .chunks
+                    .last_mut()
+                    .unwrap()
+                    .samples
+                    .push(super::Sample {
+                        sync_point: !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
+                        duration,
+                        composition_time_offset,
+                        size: buffer.size() as u32,
+                    });
+
+                {
+                    let buffer = buffer.make_mut();
+                    buffer.set_dts(None);
+                    buffer.set_pts(None);
+                    buffer.set_duration(duration);
+                    buffer.unset_flags(gst::BufferFlags::all());
+                }
+
+                state.current_offset += buffer.size() as u64;
+                state.mdat_size += buffer.size() as u64;
+                buffers.add(buffer);
+            }
         }
 
         Ok(())
     }
 
+    /// Collects all chapters from the TOC, sorted by start time.
+    fn collect_chapters(toc: &gst::TocRef) -> Vec<TocChapter> {
+        fn collect(entries: &[gst::TocEntry], chapters: &mut Vec<TocChapter>) {
+            for entry in entries {
+                // Editions and other containers only group the actual chapters
+                if entry.entry_type() != gst::TocEntryType::Chapter {
+                    collect(&entry.sub_entries(), chapters);
+                    continue;
+                }
+
+                let Some((start, stop)) = entry.start_stop_times() else {
+                    continue;
+                };
+                let Ok(start) = u64::try_from(start) else {
+                    continue;
+                };
+
+                let title = entry
+                    .tags()
+                    .and_then(|tags| {
+                        tags.get::<gst::tags::Title>()
+                            .map(|title| title.get().to_owned())
+                    })
+                    .unwrap_or_default();
+
+                chapters.push(TocChapter {
+                    start: start.nseconds(),
+                    stop: u64::try_from(stop).ok().map(gst::ClockTime::from_nseconds),
+                    title,
+                });
+            }
+        }
+
+        let mut chapters = Vec::new();
+        collect(&toc.entries(), &mut chapters);
+        chapters.sort_by_key(|chapter| chapter.start);
+
+        chapters
+    }
+
+    /// Creates the QuickTime chapter track and its samples, which are placed at `offset`.
+    ///
+    /// Returns `None` if none of the chapters is inside the movie.
+    fn create_chapter_stream(
+        streams: &[super::Stream],
+        chapters: &[TocChapter],
+        offset: u64,
+    ) -> Option<(super::Stream, gst::Buffer)> {
+        let caps = gst::Caps::builder("text/x-raw")
+            .field("format", "utf8")
+            .build();
+
+        let earliest_pts = streams.iter().map(|s| s.earliest_pts).min()?;
+        let end_pts = streams.iter().map(|s| s.end_pts).max()?;
+        let duration = end_pts - earliest_pts;
+
+        let mut data = Vec::new();
+        let mut samples = Vec::new();
+        let mut add_sample = |sample: Vec<u8>, duration: gst::ClockTime| {
+            samples.push(super::Sample {
+                sync_point: true,
+                duration,
+                composition_time_offset: None,
+                size: sample.len() as u32,
+            });
+            data.extend(sample);
+        };
+
+        let mut position = gst::ClockTime::ZERO;
+        for (idx, chapter) in chapters.iter().enumerate() {
+            // Fill the time until the chapter starts with an empty sample
+            let start = chapter.start.clamp(position, duration);
+            if start > position {
+                add_sample(boxes::subtitle_gap_sample(&caps), start - position);
+                position = start;
+            }
+
+            // The last chapter lasts until the end of the movie, all others until the next
+            // chapter starts at the latest
+            let end = match chapters.get(idx + 1) {
+                Some(next) => chapter.stop.map_or(next.start, |stop| stop.min(next.start)),
+                None => duration,
+            }
+            .clamp(position, duration);
+            if end == position {
+                continue;
+            }
+
+            let sample = boxes::subtitle_sample(&caps, chapter.title.as_bytes())
+                .unwrap_or_else(|| boxes::subtitle_gap_sample(&caps));
+            add_sample(sample, end - position);
+            position = end;
+        }
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        let stream = super::Stream {
+            caps,
+            delta_frames: super::DeltaFrames::IntraOnly,
+            trak_timescale: 1000,
+            start_dts: None,
+            earliest_pts,
+            end_pts: earliest_pts + position,
+            chunks: vec![super::Chunk { offset, samples }],
+            extra_header_data: None,
+            orientation: None,
+            language: None,
+        };
+
+        Some((stream, gst::Buffer::from_mut_slice(data)))
+    }
+
     fn create_streams(&self, state: &mut State) -> Result<(), gst::FlowError> {
         gst::info!(CAT, imp = self, "Creating streams");
 
@@ -945,6 +1164,7 @@ impl MP4Mux {
 
             let mut delta_frames = super::DeltaFrames::IntraOnly;
             let mut discard_header_buffers = false;
+            let mut sparse = false;
             match s.name().as_str() {
                 "video/x-h264" | "video/x-h265" => {
                     if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
@@ -1002,16 +1222,41 @@ impl MP4Mux {
                 }
                 "audio/x-alaw" | "audio/x-mulaw" => (),
                 "audio/x-adpcm" => (),
+                "audio/x-ac3" | "audio/x-eac3" => (),
+                "audio/x-ac4" => {
+                    if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
+                        gst::error!(CAT, obj = pad, "Received caps without codec_data");
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                }
                 "application/x-onvif-metadata" => (),
+                "application/x-subtitle-vtt" | "application/ttml+xml" | "text/x-raw" => {
+                    sparse = true;
+                }
                 _ => unreachable!(),
             }
 
+            // Tags might've been received before the stream was created
+            let tags = pad
+                .sticky_event::<gst::event::Tag>(0)
+                .map(|ev| ev.tag_owned());
+            let language = tags.as_ref().and_then(|tags| {
+                tags.get::<gst::tags::LanguageCode>()
+                    .map(|lang| lang.get().to_owned())
+                    .filter(|lang| is_valid_language(lang))
+            });
+            let orientation = tags.as_ref().and_then(|tags| {
+                tags.get::<gst::tags::ImageOrientation>()
+                    .and_then(|orientation| ImageOrientation::from_tag(orientation.get()))
+            });
+
             state.streams.push(Stream {
                 sinkpad: pad,
                 pre_queue: VecDeque::new(),
                 caps,
                 delta_frames,
                 discard_header_buffers,
+                sparse,
                 chunks: Vec::new(),
                 pending_buffer: None,
                 queued_chunk_time: gst::ClockTime::ZERO,
@@ -1021,7 +1266,8 @@ impl MP4Mux {
                 end_pts: None,
                 running_time_utc_time_mapping: None,
                 extra_header_data: None,
-                orientation: None,
+                orientation,
+                language,
             });
         }
 
@@ -1030,7 +1276,8 @@ impl MP4Mux {
             return Err(gst::FlowError::Error);
         }
 
-        // Sort video streams first and then audio streams and then metadata streams, and each group by pad name.
+        // Sort video streams first and then audio streams, subtitle streams and then metadata
+        // streams, and each group by pad name.
         state.streams.sort_by(|a, b| {
             let order_of_caps = |caps: &gst::CapsRef| {
                 let s = caps.structure(0).unwrap();
@@ -1039,8 +1286,13 @@ impl MP4Mux {
                     0
                 } else if s.name().starts_with("audio/") {
                     1
-                } else if s.name().starts_with("application/x-onvif-metadata") {
+                } else if s.name().starts_with("text/")
+                    || s.name() == "application/x-subtitle-vtt"
+                    || s.name() == "application/ttml+xml"
+                {
                     2
+                } else if s.name().starts_with("application/x-onvif-metadata") {
+                    3
                 } else {
                     unimplemented!();
                 }
@@ -1093,6 +1345,11 @@ impl ObjectImpl for MP4Mux {
                     .blurb("Timescale to use for the movie (units per second, 0 is automatic)")
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecEnum::builder_with_default("chapter-mode", DEFAULT_CHAPTER_MODE)
+                    .nick("Chapter Mode")
+                    .blurb("How to write chapters from the table of contents")
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -1122,6 +1379,11 @@ impl ObjectImpl for MP4Mux {
                 settings.movie_timescale = value.get().expect("type checked upstream");
             }
 
+            "chapter-mode" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.chapter_mode = value.get().expect("type checked upstream");
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1143,6 +1405,11 @@ impl ObjectImpl for MP4Mux {
                 settings.movie_timescale.to_value()
             }
 
+            "chapter-mode" => {
+                let settings = self.settings.lock().unwrap();
+                settings.chapter_mode.to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1235,7 +1502,19 @@ impl AggregatorImpl for MP4Mux {
                 self.parent_sink_event_pre_queue(aggregator_pad, event)
             }
             EventView::Tag(ev) => {
-                if let Some(tag_value) = ev.tag().get::<gst::tags::LanguageCode>() {
+                let tags = ev.tag();
+
+                // Only global tags go into the movie-level `udta` box, stream tags are only
+                // used for the track-level language and orientation below
+                if tags.scope() == gst::TagScope::Global {
+                    let mut state = self.state.lock().unwrap();
+                    state.tags = Some(match state.tags.take() {
+                        Some(old_tags) => old_tags.merge(tags, gst::TagMergeMode::Replace),
+                        None => tags.to_owned(),
+                    });
+                }
+
+                if let Some(tag_value) = tags.get::<gst::tags::LanguageCode>() {
                     let lang = tag_value.get();
                     gst::trace!(
                         CAT,
@@ -1244,17 +1523,28 @@ impl AggregatorImpl for MP4Mux {
                         lang
                     );
 
+                    let mut state = self.state.lock().unwrap();
+
                     // Language as ISO-639-2/T
                     if lang.len() == 3 && lang.chars().all(|c| c.is_ascii_lowercase()) {
-                        let mut state = self.state.lock().unwrap();
-
                         let mut language_code: [u8; 3] = [0; 3];
                         for (out, c) in Iterator::zip(language_code.iter_mut(), lang.chars()) {
                             *out = c as u8;
                         }
                         state.language_code = Some(language_code);
                     }
-                } else if let Some(tag_value) = ev.tag().get::<gst::tags::ImageOrientation>() {
+
+                    if is_valid_language(lang) {
+                        for stream in &mut state.streams {
+                            if &stream.sinkpad == aggregator_pad {
+                                stream.language = Some(lang.to_owned());
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                if let Some(tag_value) = ev.tag().get::<gst::tags::ImageOrientation>() {
                     let orientation = tag_value.get();
                     gst::trace!(
                         CAT,
@@ -1266,18 +1556,7 @@ impl AggregatorImpl for MP4Mux {
                     let mut state = self.state.lock().unwrap();
                     for stream in &mut state.streams {
                         if &stream.sinkpad == aggregator_pad {
-                            stream.orientation = match orientation {
-                                "rotate-0" => Some(ImageOrientation::Rotate0),
-                                "rotate-90" => Some(ImageOrientation::Rotate90),
-                                "rotate-180" => Some(ImageOrientation::Rotate180),
-                                "rotate-270" => Some(ImageOrientation::Rotate270),
-                                // TODO:
-                                // "flip-rotate-0" => Some(ImageOrientation::FlipRotate0),
-                                // "flip-rotate-90" => Some(ImageOrientation::FlipRotate90),
-                                // "flip-rotate-180" => Some(ImageOrientation::FlipRotate180),
-                                // "flip-rotate-270" => Some(ImageOrientation::FlipRotate270),
-                                _ => None,
-                            };
+                            stream.orientation = ImageOrientation::from_tag(orientation);
                             break;
                         }
                     }
@@ -1285,6 +1564,14 @@ impl AggregatorImpl for MP4Mux {
 
                 self.parent_sink_event_pre_queue(aggregator_pad, event)
             }
+            EventView::Toc(ev) => {
+                let (toc, _updated) = ev.toc();
+                gst::trace!(CAT, obj = aggregator_pad, "Received TOC {toc:?}");
+
+                self.state.lock().unwrap().toc = Some(toc.to_owned());
+
+                self.parent_sink_event_pre_queue(aggregator_pad, event)
+            }
             _ => self.parent_sink_event_pre_queue(aggregator_pad, event),
         }
     }
@@ -1296,7 +1583,7 @@ impl AggregatorImpl for MP4Mux {
 
         match event.view() {
             EventView::Tag(_ev) => {
-                // TODO: Maybe store for putting into the header at the end?
+                // Already stored in `sink_event_pre_queue()` for putting into the header at the end
 
                 self.parent_sink_event(aggregator_pad, event)
             }
@@ -1487,14 +1774,60 @@ impl AggregatorImpl for MP4Mux {
                     chunks: stream.chunks,
                     extra_header_data: stream.extra_header_data.clone(),
                     orientation: stream.orientation,
+                    language: stream.language,
                 });
             }
 
+            let chapters = state
+                .toc
+                .as_ref()
+                .map(|toc| Self::collect_chapters(toc))
+                .unwrap_or_default();
+
+            let mut chapter_track = None;
+            let mut nero_chapters = Vec::new();
+            match settings.chapter_mode {
+                _ if chapters.is_empty() => (),
+                ChapterMode::None => (),
+                ChapterMode::Nero => {
+                    nero_chapters = chapters
+                        .into_iter()
+                        .map(|chapter| super::Chapter {
+                            start: chapter.start,
+                            title: chapter.title,
+                        })
+                        .collect();
+                }
+                ChapterMode::QuickTime => {
+                    if let Some((stream, samples)) =
+                        Self::create_chapter_stream(&streams, &chapters, state.current_offset)
+                    {
+                        gst::info!(
+                            CAT,
+                            imp = self,
+                            "Creating chapter track with {} samples at offset {}",
+                            stream.chunks[0].samples.len(),
+                            state.current_offset
+                        );
+
+                        state.current_offset += samples.size() as u64;
+                        state.mdat_size += samples.size() as u64;
+                        buffers.get_mut().unwrap().add(samples);
+
+                        chapter_track = Some(streams.len());
+                        streams.push(stream);
+                    }
+                }
+            }
+
             let moov = boxes::create_moov(super::Header {
                 variant: self.obj().class().as_ref().variant,
                 movie_timescale: settings.movie_timescale,
                 streams,
                 language_code: state.language_code,
+                tags: state.tags.clone(),
+                chapters: nero_chapters,
+                chapter_track,
             })
             .map_err(|err| {
                 gst::error!(CAT, imp = self, "Failed to create moov box: {err}");
@@ -1680,6 +2013,27 @@ impl ElementImpl for ISOMP4Mux {
                         .field("channels", gst::IntRange::<i32>::new(1, 8))
                         .field("rate", gst::IntRange::<i32>::new(1, 10 * u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("audio/x-ac3")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 6))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-eac3")
+                        .field("framed", true)
+                        .field("alignment", "frame")
+                        .field("channels", gst::IntRange::new(1i32, 16))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-ac4")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -9,6 +9,7 @@
 use gst::glib;
 use gst::prelude::*;
 
+mod ac3;
 mod boxes;
 mod imp;
 mod obu;
@@ -34,6 +35,7 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     {
         MP4Mux::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
         MP4MuxPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        ChapterMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
     gst::Element::register(
         Some(plugin),
@@ -57,11 +59,10 @@ pub(crate) enum ImageOrientation {
     Rotate90,
     Rotate180,
     Rotate270,
-    // TODO:
-    // FlipRotate0,
-    // FlipRotate90,
-    // FlipRotate180,
-    // FlipRotate270,
+    FlipRotate0,
+    FlipRotate90,
+    FlipRotate180,
+    FlipRotate270,
 }
 
 type TransformMatrix = [[u8; 4]; 9];
@@ -114,15 +115,114 @@ const ROTATE_270_MATRIX: TransformMatrix = [
     (1u32 << 30).to_be_bytes(),
 ];
 
+const FLIP_ROTATE_0_MATRIX: TransformMatrix = [
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_90_MATRIX: TransformMatrix = [
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_180_MATRIX: TransformMatrix = [
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (-1i32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
+const FLIP_ROTATE_270_MATRIX: TransformMatrix = [
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 16).to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    0u32.to_be_bytes(),
+    (1u32 << 30).to_be_bytes(),
+];
+
 impl ImageOrientation {
+    /// Parses the value of an `image-orientation` tag.
+    pub(crate) fn from_tag(orientation: &str) -> Option<Self> {
+        match orientation {
+            "rotate-0" => Some(ImageOrientation::Rotate0),
+            "rotate-90" => Some(ImageOrientation::Rotate90),
+            "rotate-180" => Some(ImageOrientation::Rotate180),
+            "rotate-270" => Some(ImageOrientation::Rotate270),
+            "flip-rotate-0" => Some(ImageOrientation::FlipRotate0),
+            "flip-rotate-90" => Some(ImageOrientation::FlipRotate90),
+            "flip-rotate-180" => Some(ImageOrientation::FlipRotate180),
+            "flip-rotate-270" => Some(ImageOrientation::FlipRotate270),
+            _ => None,
+        }
+    }
+
+    /// Returns the value for an `image-orientation` tag.
+    pub(crate) fn as_tag(&self) -> &'static str {
+        match self {
+            ImageOrientation::Rotate0 => "rotate-0",
+            ImageOrientation::Rotate90 => "rotate-90",
+            ImageOrientation::Rotate180 => "rotate-180",
+            ImageOrientation::Rotate270 => "rotate-270",
+            ImageOrientation::FlipRotate0 => "flip-rotate-0",
+            ImageOrientation::FlipRotate90 => "flip-rotate-90",
+            ImageOrientation::FlipRotate180 => "flip-rotate-180",
+            ImageOrientation::FlipRotate270 => "flip-rotate-270",
+        }
+    }
+
+    /// Returns the `tkhd` transformation matrix.
+    ///
+    /// Flipped orientations are mirrored horizontally first and then rotated clockwise.
     pub(crate) fn transform_matrix(&self) -> &'static TransformMatrix {
         match self {
             ImageOrientation::Rotate0 => &IDENTITY_MATRIX,
             ImageOrientation::Rotate90 => &ROTATE_90_MATRIX,
             ImageOrientation::Rotate180 => &ROTATE_180_MATRIX,
             ImageOrientation::Rotate270 => &ROTATE_270_MATRIX,
+            ImageOrientation::FlipRotate0 => &FLIP_ROTATE_0_MATRIX,
+            ImageOrientation::FlipRotate90 => &FLIP_ROTATE_90_MATRIX,
+            ImageOrientation::FlipRotate180 => &FLIP_ROTATE_180_MATRIX,
+            ImageOrientation::FlipRotate270 => &FLIP_ROTATE_270_MATRIX,
         }
     }
+
+    pub(crate) fn from_transform_matrix(matrix: &TransformMatrix) -> Option<Self> {
+        [
+            ImageOrientation::Rotate0,
+            ImageOrientation::Rotate90,
+            ImageOrientation::Rotate180,
+            ImageOrientation::Rotate270,
+            ImageOrientation::FlipRotate0,
+            ImageOrientation::FlipRotate90,
+            ImageOrientation::FlipRotate180,
+            ImageOrientation::FlipRotate270,
+        ]
+        .into_iter()
+        .find(|orientation| orientation.transform_matrix() == matrix)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -207,6 +307,9 @@ pub(crate) struct Stream {
 
     /// Orientation from tags
     orientation: Option<ImageOrientation>,
+
+    /// Language from tags, ISO-639-2/T or BCP-47
+    language: Option<String>,
 }
 
 #[derive(Debug)]
@@ -217,6 +320,24 @@ pub(crate) struct Header {
     movie_timescale: u32,
     streams: Vec<Stream>,
     language_code: Option<[u8; 3]>,
+
+    /// Global tags to write into the `udta` box
+    tags: Option<gst::TagList>,
+
+    /// Chapters to write as Nero `chpl` box
+    chapters: Vec<Chapter>,
+
+    /// Index of the QuickTime chapter track in `streams`
+    chapter_track: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Chapter {
+    /// Start time relative to the beginning of the movie
+    start: gst::ClockTime,
+
+    /// Title of the chapter
+    title: String,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -225,3 +346,14 @@ pub(crate) enum Variant {
     ISO,
     ONVIF,
 }
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(i32)]
+#[enum_type(name = "GstMP4MuxChapterMode")]
+pub(crate) enum ChapterMode {
+    None,
+    Nero,
+    #[default]
+    #[enum_value(name = "QuickTime", nick = "quicktime")]
+    QuickTime,
+}
@@ -7,7 +7,9 @@
 // SPDX-License-Identifier: MPL-2.0
 //
 
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 use gst::prelude::*;
 use gst_pbutils::prelude::*;
@@ -19,6 +21,7 @@ fn init() {
     INIT.call_once(|| {
         gst::init().unwrap();
         gstmp4::plugin_register_static().unwrap();
+        gstfmp4::plugin_register_static().unwrap();
     });
 }
 
@@ -64,10 +67,14 @@ impl Pipeline {
 }
 
 fn test_basic_with(video_enc: &str, audio_enc: &str, cb: impl FnOnce(&Path)) {
+    test_basic_with_mux("isomp4mux", video_enc, audio_enc, cb)
+}
+
+fn test_basic_with_mux(mux: &str, video_enc: &str, audio_enc: &str, cb: impl FnOnce(&Path)) {
     let Ok(pipeline) = gst::parse::launch(&format!(
         "videotestsrc num-buffers=99 ! {video_enc} ! mux. \
          audiotestsrc num-buffers=140 ! {audio_enc} ! mux. \
-         isomp4mux name=mux ! filesink name=sink"
+         {mux} name=mux ! filesink name=sink"
     )) else {
         println!("could not build encoding pipeline");
         return;
@@ -172,3 +179,829 @@ fn test_roundtrip_av1_aac() {
         pipeline.into_completion();
     })
 }
+
+#[test]
+fn test_demux_roundtrip_vp9_flac() {
+    init();
+    test_basic_with("vp9enc ! vp9parse", "flacenc ! flacparse", |location| {
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! isobmffdemux name=demux \
+             demux.audio_0 ! queue ! flacdec ! fakesink \
+             demux.video_0 ! queue ! vp9dec ! fakesink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+        pipeline.into_completion();
+    })
+}
+
+#[test]
+fn test_demux_roundtrip_av1_aac() {
+    init();
+    test_basic_with("av1enc ! av1parse", "avenc_aac ! aacparse", |location| {
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! isobmffdemux name=demux \
+             demux.audio_0 ! queue ! avdec_aac ! fakesink \
+             demux.video_0 ! queue ! av1dec ! fakesink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+        pipeline.into_completion();
+    })
+}
+
+/// Caps and buffer timestamps of a stream demuxed by `isobmffdemux`.
+#[derive(Debug, Default)]
+struct DemuxedStream {
+    caps: Option<gst::Caps>,
+    pts: Vec<gst::ClockTime>,
+}
+
+/// Demuxes the file at `location` with `isobmffdemux`, in push mode if `push_mode` is set and
+/// otherwise in pull mode, and returns the streams by pad name.
+///
+/// If `seek_target` is set then a flushing seek is done after prerolling, and only the buffers
+/// after the seek are returned.
+fn demux_file(
+    location: &Path,
+    push_mode: bool,
+    seek_target: Option<gst::ClockTime>,
+) -> BTreeMap<String, DemuxedStream> {
+    let pipeline = Pipeline(gst::Pipeline::new());
+
+    let src = gst::ElementFactory::make("filesrc")
+        .property("location", location.to_str().expect("Non-UTF8 filename"))
+        .build()
+        .unwrap();
+    let demux = gst::ElementFactory::make("isobmffdemux").build().unwrap();
+    pipeline.add_many([&src, &demux]).unwrap();
+    if push_mode {
+        // queue only allows push mode downstream
+        let queue = gst::ElementFactory::make("queue").build().unwrap();
+        pipeline.add(&queue).unwrap();
+        gst::Element::link_many([&src, &queue, &demux]).unwrap();
+    } else {
+        src.link(&demux).unwrap();
+    }
+
+    let streams = Arc::new(Mutex::new(BTreeMap::<String, DemuxedStream>::new()));
+    let pipeline_weak = pipeline.downgrade();
+    let streams_clone = streams.clone();
+    demux.connect_pad_added(move |_demux, pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+
+        let streams = streams_clone.clone();
+        pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |pad, info| {
+                let mut streams = streams.lock().unwrap();
+                let stream = streams.entry(pad.name().to_string()).or_default();
+                match info.data {
+                    Some(gst::PadProbeData::Buffer(ref buffer)) => {
+                        stream.pts.push(buffer.pts().unwrap());
+                    }
+                    Some(gst::PadProbeData::Event(ref event)) => match event.view() {
+                        gst::EventView::Caps(e) => stream.caps = Some(e.caps_owned()),
+                        gst::EventView::FlushStop(..) => stream.pts.clear(),
+                        _ => (),
+                    },
+                    _ => (),
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        let queue = gst::ElementFactory::make("queue").build().unwrap();
+        let sink = gst::ElementFactory::make("fakesink")
+            .property("sync", false)
+            .build()
+            .unwrap();
+        pipeline.add_many([&queue, &sink]).unwrap();
+        queue.link(&sink).unwrap();
+        pad.link(&queue.static_pad("sink").unwrap()).unwrap();
+        queue.sync_state_with_parent().unwrap();
+        sink.sync_state_with_parent().unwrap();
+    });
+
+    if let Some(seek_target) = seek_target {
+        pipeline
+            .set_state(gst::State::Paused)
+            .expect("Unable to set the pipeline to the `Paused` state");
+
+        let msg = pipeline
+            .bus()
+            .unwrap()
+            .timed_pop_filtered(
+                gst::ClockTime::NONE,
+                &[gst::MessageType::AsyncDone, gst::MessageType::Error],
+            )
+            .unwrap();
+        if let gst::MessageView::Error(err) = msg.view() {
+            panic!(
+                "Error while prerolling: {} ({:?})",
+                err.error(),
+                err.debug()
+            );
+        }
+
+        pipeline
+            .seek_simple(gst::SeekFlags::FLUSH, seek_target)
+            .expect("Seek failed");
+    }
+
+    pipeline.into_completion();
+
+    let streams = std::mem::take(&mut *streams.lock().unwrap());
+    streams
+}
+
+/// Checks that `pts` are increasing with `count` timestamps from `first` in steps of
+/// `duration`, allowing for rounding errors of the timescale conversion.
+fn check_timestamps(
+    pts: &[gst::ClockTime],
+    count: usize,
+    first: gst::ClockTime,
+    duration: gst::ClockTime,
+) {
+    assert_eq!(pts.len(), count);
+    for (idx, pts) in pts.iter().enumerate() {
+        let expected = first + duration * idx as u64;
+        assert!(
+            pts.absdiff(expected) <= gst::ClockTime::from_mseconds(1),
+            "Unexpected PTS {pts} of buffer {idx}, expected {expected}"
+        );
+    }
+}
+
+#[test]
+fn test_demux_fragmented_push_mode() {
+    init();
+    test_basic_with_mux(
+        "isofmp4mux fragment-duration=500000000",
+        "vp9enc keyframe-max-dist=10 ! vp9parse",
+        "flacenc ! flacparse",
+        |location| {
+            let streams = demux_file(location, true, None);
+            assert_eq!(streams.len(), 2);
+
+            let video = &streams["video_0"];
+            let s = video.caps.as_ref().unwrap().structure(0).unwrap();
+            assert_eq!(s.name(), "video/x-vp9");
+            assert_eq!(s.get::<i32>("width").unwrap(), 320);
+            assert_eq!(s.get::<i32>("height").unwrap(), 240);
+            check_timestamps(
+                &video.pts,
+                99,
+                gst::ClockTime::ZERO,
+                gst::ClockTime::SECOND / 30,
+            );
+
+            let audio = &streams["audio_0"];
+            let s = audio.caps.as_ref().unwrap().structure(0).unwrap();
+            assert_eq!(s.name(), "audio/x-flac");
+            assert_eq!(s.get::<i32>("rate").unwrap(), 44_100);
+            assert_eq!(audio.pts.first(), Some(&gst::ClockTime::ZERO));
+            assert!(audio.pts.windows(2).all(|pts| pts[0] < pts[1]));
+        },
+    );
+}
+
+#[test]
+fn test_demux_cmaf() {
+    init();
+
+    let Ok(pipeline) = gst::parse::launch(
+        "videotestsrc num-buffers=99 ! x264enc bframes=0 key-int-max=10 ! \
+         cmafmux fragment-duration=500000000 ! filesink name=sink",
+    ) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+    pipeline.into_completion();
+
+    for push_mode in [false, true] {
+        let streams = demux_file(&location, push_mode, None);
+        assert_eq!(streams.len(), 1);
+
+        let video = &streams["video_0"];
+        let s = video.caps.as_ref().unwrap().structure(0).unwrap();
+        assert_eq!(s.name(), "video/x-h264");
+        assert_eq!(s.get::<&str>("stream-format").unwrap(), "avc");
+        assert!(s.has_field("codec_data"));
+        check_timestamps(
+            &video.pts,
+            99,
+            gst::ClockTime::ZERO,
+            gst::ClockTime::SECOND / 30,
+        );
+    }
+}
+
+fn check_seek(location: &Path) {
+    let target = gst::ClockTime::from_seconds(2);
+    let streams = demux_file(location, false, Some(target));
+    assert_eq!(streams.len(), 2);
+
+    // Output starts at the last keyframe before the target, and there is one every 10 frames
+    let video = &streams["video_0"];
+    let first = *video.pts.first().unwrap();
+    assert!(first <= target && first + gst::ClockTime::SECOND / 3 > target);
+    let first_idx = (first * 30 + gst::ClockTime::SECOND / 2).seconds() as usize;
+    check_timestamps(
+        &video.pts,
+        99 - first_idx,
+        first,
+        gst::ClockTime::SECOND / 30,
+    );
+
+    // All audio frames are keyframes
+    let audio = &streams["audio_0"];
+    let first = *audio.pts.first().unwrap();
+    assert!(first <= target && first + gst::ClockTime::from_mseconds(200) > target);
+}
+
+#[test]
+fn test_demux_seek() {
+    init();
+    test_basic_with(
+        "vp9enc keyframe-max-dist=10 ! vp9parse",
+        "flacenc ! flacparse",
+        check_seek,
+    );
+}
+
+#[test]
+fn test_demux_seek_fragmented() {
+    init();
+    test_basic_with_mux(
+        "isofmp4mux fragment-duration=500000000 write-mfra=true",
+        "vp9enc keyframe-max-dist=10 ! vp9parse",
+        "flacenc ! flacparse",
+        check_seek,
+    );
+}
+
+#[test]
+fn test_tags_chapters_language() {
+    init();
+
+    let Ok(pipeline) = gst::parse::launch(
+        "audiotestsrc num-buffers=100 samplesperbuffer=800 ! audio/x-raw,rate=8000 ! \
+         alawenc name=enc ! taginject scope=global tags=\"title=Test,artist=Someone\" ! \
+         taginject tags=\"language-code=en-US\" ! \
+         isomp4mux name=mux ! filesink name=sink",
+    ) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+
+    // Insert a TOC with two chapters before the first buffer
+    let mut toc = gst::Toc::new(gst::TocScope::Global);
+    {
+        let toc = toc.get_mut().unwrap();
+        for (idx, (start, stop, title)) in
+            [(0, 5, "First"), (5, 10, "Second")].into_iter().enumerate()
+        {
+            let mut entry = gst::TocEntry::new(gst::TocEntryType::Chapter, &format!("{idx}"));
+            let entry_ref = entry.get_mut().unwrap();
+            entry_ref.set_start_stop_times(
+                start * gst::ClockTime::SECOND.nseconds() as i64,
+                stop * gst::ClockTime::SECOND.nseconds() as i64,
+            );
+            let mut tags = gst::TagList::new();
+            tags.get_mut()
+                .unwrap()
+                .add::<gst::tags::Title>(&title, gst::TagMergeMode::Replace);
+            entry_ref.set_tags(tags);
+            toc.append_entry(entry);
+        }
+    }
+    let toc_event = std::sync::Mutex::new(Some(gst::event::Toc::new(&toc, false)));
+    let enc_src = pipeline.by_name("enc").unwrap().static_pad("src").unwrap();
+    enc_src.add_probe(gst::PadProbeType::BUFFER, move |pad, _info| {
+        if let Some(event) = toc_event.lock().unwrap().take() {
+            pad.push_event(event);
+        }
+        gst::PadProbeReturn::Remove
+    });
+
+    pipeline.into_completion();
+
+    let data = std::fs::read(&location).unwrap();
+    let contains = |needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+
+    // Tags in the udta box
+    assert!(contains(b"\xa9nam"));
+    assert!(contains(b"\xa9ART"));
+    assert!(contains(b"Someone"));
+    // Extended language of the audio track
+    assert!(contains(b"elng"));
+    assert!(contains(b"en-US\0"));
+    // Chapter track referenced by the audio track, and its samples
+    assert!(contains(b"chap"));
+    assert!(contains(b"\0\x05First"));
+    assert!(contains(b"\0\x06Second"));
+
+    let discoverer = gst_pbutils::Discoverer::new(gst::ClockTime::from_seconds(5))
+        .expect("Failed to create discoverer");
+    let info = discoverer
+        .discover_uri(
+            url::Url::from_file_path(&location)
+                .expect("Failed to convert filename to URL")
+                .as_str(),
+        )
+        .expect("Failed to discover MP4 file");
+    let tags = info.tags().expect("No tags");
+    assert_eq!(tags.get::<gst::tags::Title>().unwrap().get(), "Test");
+    assert_eq!(tags.get::<gst::tags::Artist>().unwrap().get(), "Someone");
+}
+
+#[test]
+fn test_stream_tags_not_global() {
+    init();
+
+    let Ok(pipeline) = gst::parse::launch(
+        "audiotestsrc num-buffers=100 samplesperbuffer=800 ! audio/x-raw,rate=8000 ! \
+         alawenc ! taginject tags=\"title=Stream,language-code=en-US\" ! \
+         isomp4mux ! filesink name=sink",
+    ) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+    pipeline.into_completion();
+
+    let data = std::fs::read(&location).unwrap();
+    let contains = |needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+
+    // Stream tags are not written as movie metadata
+    assert!(!contains(b"\xa9nam"));
+    assert!(!contains(b"Stream"));
+    // but still used for the track language
+    assert!(contains(b"elng"));
+    assert!(contains(b"en-US\0"));
+}
+
+#[test]
+fn test_flipped_orientation() {
+    init();
+
+    let Ok(pipeline) = gst::parse::launch(
+        "videotestsrc num-buffers=10 ! video/x-raw,pixel-aspect-ratio=1/1 ! x264enc ! \
+         taginject tags=\"image-orientation=flip-rotate-90\" ! \
+         isomp4mux ! filesink name=sink",
+    ) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+    pipeline.into_completion();
+
+    let data = std::fs::read(&location).unwrap();
+    let contains = |needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+
+    // Mirrored horizontally and then rotated by 90 degrees clockwise
+    let matrix = [
+        0u32.to_be_bytes(),
+        (-1i32 << 16).to_be_bytes(),
+        0u32.to_be_bytes(),
+        (-1i32 << 16).to_be_bytes(),
+        0u32.to_be_bytes(),
+        0u32.to_be_bytes(),
+        0u32.to_be_bytes(),
+        0u32.to_be_bytes(),
+        (1u32 << 30).to_be_bytes(),
+    ]
+    .concat();
+    assert!(contains(&matrix));
+
+    assert!(contains(b"clap"));
+    assert!(contains(b"pasp"));
+}
+
+/// Returns the type and content of all boxes in `data`.
+fn parse_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let fourcc = data[4..8].try_into().unwrap();
+        let (header_size, size) = match size {
+            0 => (8, data.len()),
+            1 => (
+                16,
+                u64::from_be_bytes(data[8..16].try_into().unwrap()) as usize,
+            ),
+            size => (8, size),
+        };
+        boxes.push((fourcc, &data[header_size..size]));
+        data = &data[size..];
+    }
+    boxes
+}
+
+fn find_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> &'a [u8] {
+    parse_boxes(data)
+        .into_iter()
+        .find(|(f, _)| f == fourcc)
+        .map(|(_, content)| content)
+        .unwrap_or_else(|| panic!("No {} box", std::str::from_utf8(fourcc).unwrap()))
+}
+
+/// Returns the duration and data of all samples of the track with `track_id` in the MP4 file
+/// `data`.
+fn track_samples(data: &[u8], track_id: u32) -> Vec<(u32, Vec<u8>)> {
+    let read_u32 =
+        |data: &[u8], pos: usize| u32::from_be_bytes(data[pos..][..4].try_into().unwrap());
+
+    let moov = find_box(data, b"moov");
+    let (_, trak) = parse_boxes(moov)
+        .into_iter()
+        .find(|(fourcc, trak)| {
+            if fourcc != b"trak" {
+                return false;
+            }
+            let tkhd = find_box(trak, b"tkhd");
+            let pos = if tkhd[0] == 1 { 20 } else { 12 };
+            read_u32(tkhd, pos) == track_id
+        })
+        .unwrap();
+    let stbl = find_box(find_box(find_box(trak, b"mdia"), b"minf"), b"stbl");
+
+    let stts = find_box(stbl, b"stts");
+    let mut durations = Vec::new();
+    for i in 0..read_u32(stts, 4) as usize {
+        let count = read_u32(stts, 8 + 8 * i);
+        let delta = read_u32(stts, 12 + 8 * i);
+        durations.extend(std::iter::repeat(delta).take(count as usize));
+    }
+
+    let stsz = find_box(stbl, b"stsz");
+    let sample_size = read_u32(stsz, 4);
+    let sizes = (0..read_u32(stsz, 8) as usize)
+        .map(|i| match sample_size {
+            0 => read_u32(stsz, 12 + 4 * i) as usize,
+            size => size as usize,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(durations.len(), sizes.len());
+
+    let chunk_offsets = match parse_boxes(stbl)
+        .into_iter()
+        .find(|(fourcc, _)| fourcc == b"stco" || fourcc == b"co64")
+        .unwrap()
+    {
+        (fourcc, stco) if &fourcc == b"stco" => (0..read_u32(stco, 4) as usize)
+            .map(|i| read_u32(stco, 8 + 4 * i) as usize)
+            .collect::<Vec<_>>(),
+        (_, co64) => (0..read_u32(co64, 4) as usize)
+            .map(|i| u64::from_be_bytes(co64[8 + 8 * i..][..8].try_into().unwrap()) as usize)
+            .collect::<Vec<_>>(),
+    };
+
+    // First chunk and samples per chunk of each run of chunks
+    let stsc = find_box(stbl, b"stsc");
+    let stsc = (0..read_u32(stsc, 4) as usize)
+        .map(|i| (read_u32(stsc, 8 + 12 * i), read_u32(stsc, 12 + 12 * i)))
+        .collect::<Vec<_>>();
+
+    let mut samples = Vec::new();
+    let mut sample_idx = 0;
+    for (chunk_idx, chunk_offset) in chunk_offsets.into_iter().enumerate() {
+        let (_, samples_per_chunk) = stsc
+            .iter()
+            .rev()
+            .find(|(first_chunk, _)| *first_chunk as usize <= chunk_idx + 1)
+            .unwrap();
+
+        let mut offset = chunk_offset;
+        for _ in 0..*samples_per_chunk {
+            let size = sizes[sample_idx];
+            samples.push((durations[sample_idx], data[offset..][..size].to_vec()));
+            offset += size;
+            sample_idx += 1;
+        }
+    }
+    assert_eq!(samples.len(), sizes.len());
+
+    samples
+}
+
+fn test_subtitle_gaps_with_video(
+    caps: gst::Caps,
+    cues: [&[u8]; 2],
+    cue_samples: [&[u8]; 2],
+    gap_sample: &[u8],
+) {
+    let pipeline = Pipeline(gst::Pipeline::new());
+
+    let video_src = gst::ElementFactory::make("appsrc")
+        .property(
+            "caps",
+            gst::Caps::builder("video/x-h264")
+                .field("width", 1920i32)
+                .field("height", 1080i32)
+                .field("framerate", gst::Fraction::new(30, 1))
+                .field("stream-format", "avc")
+                .field("alignment", "au")
+                .field("codec_data", gst::Buffer::with_size(1).unwrap())
+                .build(),
+        )
+        .property("format", gst::Format::Time)
+        .build()
+        .unwrap();
+    let subtitle_src = gst::ElementFactory::make("appsrc")
+        .property("caps", caps)
+        .property("format", gst::Format::Time)
+        .build()
+        .unwrap();
+    let mux = gst::ElementFactory::make("isomp4mux").build().unwrap();
+    let sink = gst::ElementFactory::make("filesink").build().unwrap();
+    pipeline
+        .add_many([&video_src, &subtitle_src, &mux, &sink])
+        .unwrap();
+    mux.link(&sink).unwrap();
+    for src in [&video_src, &subtitle_src] {
+        let sinkpad = mux.request_pad_simple("sink_%u").unwrap();
+        sinkpad.set_property("trak-timescale", 1000u32);
+        src.static_pad("src").unwrap().link(&sinkpad).unwrap();
+    }
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .expect("Unable to set the pipeline to the `Playing` state");
+
+    // 6 video frames of 1s each and two cues of 1s at 1s and 4s
+    for i in 0..6 {
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(
+            video_src.emit_by_name::<gst::FlowReturn>("push-buffer", &[&buffer]),
+            gst::FlowReturn::Ok
+        );
+    }
+    for (pts, cue) in [(1.seconds(), cues[0]), (4.seconds(), cues[1])] {
+        let mut buffer = gst::Buffer::from_slice(cue.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(
+            subtitle_src.emit_by_name::<gst::FlowReturn>("push-buffer", &[&buffer]),
+            gst::FlowReturn::Ok
+        );
+    }
+    for src in [&video_src, &subtitle_src] {
+        assert_eq!(
+            src.emit_by_name::<gst::FlowReturn>("end-of-stream", &[]),
+            gst::FlowReturn::Ok
+        );
+    }
+
+    pipeline.into_completion();
+
+    let data = std::fs::read(&location).unwrap();
+
+    let video_samples = track_samples(&data, 1);
+    assert_eq!(video_samples.len(), 6);
+    assert!(video_samples.iter().all(|(duration, _)| *duration == 1000));
+
+    // The gap between the cues is filled with an empty sample, the gap before the first cue is
+    // covered by the edit list
+    let subtitle_samples = track_samples(&data, 2);
+    let expected = [
+        (1000, cue_samples[0]),
+        (2000, gap_sample),
+        (1000, cue_samples[1]),
+    ];
+    assert_eq!(subtitle_samples.len(), expected.len());
+    for ((duration, data), (expected_duration, expected_data)) in
+        subtitle_samples.iter().zip(expected)
+    {
+        assert_eq!(*duration, expected_duration);
+        assert_eq!(data.as_slice(), expected_data);
+    }
+}
+
+#[test]
+fn test_ttml_gaps_with_video() {
+    init();
+
+    let hello = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <tt xmlns=\"http://www.w3.org/ns/ttml\"><body><div><p>Hello</p></div></body></tt>";
+    let world = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <tt xmlns=\"http://www.w3.org/ns/ttml\"><body><div><p>World</p></div></body></tt>";
+
+    test_subtitle_gaps_with_video(
+        gst::Caps::builder("application/ttml+xml").build(),
+        [hello, world],
+        [hello, world],
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><tt xmlns=\"http://www.w3.org/ns/ttml\"/>",
+    );
+}
+
+#[test]
+fn test_tx3g_gaps_with_video() {
+    init();
+
+    test_subtitle_gaps_with_video(
+        gst::Caps::builder("text/x-raw")
+            .field("format", "utf8")
+            .build(),
+        [b"Hello", b"World"],
+        [b"\0\x05Hello", b"\0\x05World"],
+        b"\0\0",
+    );
+}
+
+/// Muxes three frames with `isomp4mux` and checks that the sample entry is `entry` and contains
+/// a `config` box with the content `expected_config`.
+fn test_audio_sample_entry(
+    caps: gst::Caps,
+    frame: &[u8],
+    entry: &[u8; 4],
+    config: &[u8; 4],
+    expected_config: &[u8],
+) {
+    let pipeline = Pipeline(gst::Pipeline::new());
+
+    let src = gst::ElementFactory::make("appsrc")
+        .property("caps", caps)
+        .property("format", gst::Format::Time)
+        .build()
+        .unwrap();
+    let mux = gst::ElementFactory::make("isomp4mux").build().unwrap();
+    let sink = gst::ElementFactory::make("filesink").build().unwrap();
+    pipeline.add_many([&src, &mux, &sink]).unwrap();
+    gst::Element::link_many([&src, &mux, &sink]).unwrap();
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .expect("Unable to set the pipeline to the `Playing` state");
+
+    for i in 0..3 {
+        let mut buffer = gst::Buffer::from_slice(frame.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts((i * 32).mseconds());
+            buffer.set_duration(32.mseconds());
+        }
+        assert_eq!(
+            src.emit_by_name::<gst::FlowReturn>("push-buffer", &[&buffer]),
+            gst::FlowReturn::Ok
+        );
+    }
+    assert_eq!(
+        src.emit_by_name::<gst::FlowReturn>("end-of-stream", &[]),
+        gst::FlowReturn::Ok
+    );
+
+    pipeline.into_completion();
+
+    let data = std::fs::read(&location).unwrap();
+
+    let trak = find_box(find_box(&data, b"moov"), b"trak");
+    let stsd = find_box(
+        find_box(find_box(find_box(trak, b"mdia"), b"minf"), b"stbl"),
+        b"stsd",
+    );
+    // Version and flags, and a single sample entry
+    assert_eq!(&stsd[..8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+    let (fourcc, sample_entry) = parse_boxes(&stsd[8..])[0];
+    assert_eq!(&fourcc, entry);
+    // The configuration box follows the 28 bytes of the audio sample entry fields
+    assert_eq!(find_box(&sample_entry[28..], config), expected_config);
+
+    let samples = track_samples(&data, 1);
+    assert_eq!(samples.len(), 3);
+    assert!(samples.iter().all(|(_, sample)| sample == frame));
+}
+
+#[test]
+fn test_ac3_sample_entry() {
+    init();
+
+    // 48kHz, 192kbit/s, bsid 8, bsmod 0, 3/2 with LFE
+    let mut frame = vec![0u8; 768];
+    frame[..8].copy_from_slice(&[0x0b, 0x77, 0x00, 0x00, 0x14, 0x40, 0xe1, 0xa0]);
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-ac3")
+            .field("framed", true)
+            .field("channels", 6i32)
+            .field("rate", 48_000i32)
+            .build(),
+        &frame,
+        b"ac-3",
+        b"dac3",
+        // fscod 0, bsid 8, bsmod 0, acmod 7, lfeon 1, bit_rate_code 10
+        &[0x10, 0x3d, 0x40],
+    );
+}
+
+#[test]
+fn test_eac3_sample_entry() {
+    init();
+
+    // Independent substream, 48kHz, 6 blocks, 3/2 with LFE, bsid 16, 768 bytes per frame
+    let mut frame = vec![0u8; 768];
+    frame[..6].copy_from_slice(&[0x0b, 0x77, 0x01, 0x7f, 0x3f, 0x80]);
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-eac3")
+            .field("framed", true)
+            .field("alignment", "frame")
+            .field("channels", 6i32)
+            .field("rate", 48_000i32)
+            .build(),
+        &frame,
+        b"ec-3",
+        b"dec3",
+        // 192kbit/s, one independent substream with fscod 0, bsid 16, acmod 7 and LFE
+        &[0x06, 0x00, 0x20, 0x0f, 0x00],
+    );
+}
+
+#[test]
+fn test_ac4_sample_entry() {
+    init();
+
+    let dac4 = [
+        0x20, 0xa6, 0x01, 0x40, 0x00, 0x00, 0x00, 0x1f, 0xff, 0xff, 0xff, 0xe0, 0x01, 0x0e, 0xf9,
+        0x00, 0x00, 0x09, 0x08, 0x00, 0x00, 0x24, 0x00, 0x08, 0x80, 0x10, 0x00,
+    ];
+
+    test_audio_sample_entry(
+        gst::Caps::builder("audio/x-ac4")
+            .field("framed", true)
+            .field("channels", 2i32)
+            .field("rate", 48_000i32)
+            .field("codec_data", gst::Buffer::from_slice(dac4))
+            .build(),
+        &[0xac, 0x40, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00],
+        b"ac-4",
+        b"dac4",
+        // Taken as is from the codec data
+        &dac4,
+    );
+}
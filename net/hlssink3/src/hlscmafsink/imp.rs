@@ -352,6 +352,8 @@ impl ElementImpl for HlsCmafSink {
                         .field("channels", gst::IntRange::new(1, u16::MAX as i32))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
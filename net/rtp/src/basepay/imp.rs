@@ -210,6 +210,14 @@ impl RtpBasePay2 {
             .saturating_sub(rtp_types::RtpPacket::MIN_RTP_PACKET_LEN as u32)
     }
 
+    pub(super) fn next_seqnum(&self) -> Option<u16> {
+        let state = self.state.borrow();
+        state
+            .stream
+            .as_ref()
+            .map(|stream| (stream.last_seqnum + Wrapping(1)).0)
+    }
+
     pub(super) fn set_src_caps(&self, src_caps: &gst::Caps) {
         gst::debug!(CAT, imp = self, "Setting src caps {src_caps:?}");
 
@@ -115,6 +115,13 @@ pub trait RtpBasePay2Ext: IsA<RtpBasePay2> {
     fn max_payload_size(&self) -> u32 {
         self.upcast_ref::<RtpBasePay2>().imp().max_payload_size()
     }
+
+    /// Returns the sequence number that will be put on the next queued packet.
+    ///
+    /// This is only known once the element is at least in `Paused` state.
+    fn next_seqnum(&self) -> Option<u16> {
+        self.upcast_ref::<RtpBasePay2>().imp().next_seqnum()
+    }
 }
 
 impl<O: IsA<RtpBasePay2>> RtpBasePay2Ext for O {}
@@ -0,0 +1,505 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! XOR based FEC as used by both ULPFEC (RFC 5109) and FlexFEC (RFC 8627).
+//!
+//! Both formats protect everything after the fixed 12 byte RTP header (CSRCs, header extensions,
+//! payload and padding) together with some of the header fields and only differ in how the FEC
+//! header signals which packets are protected.
+
+use std::collections::{HashMap, VecDeque};
+
+use gst::glib;
+use rtp_types::{RtpPacket, RtpPacketBuilder};
+
+/// Size of the fixed RTP header.
+pub const RTP_HEADER_LEN: usize = 12;
+
+/// Maximum number of media packets kept per SSRC for recovery.
+const MAX_MEDIA_PACKETS: usize = 1024;
+/// Maximum number of FEC packets kept for recovery.
+const MAX_FEC_PACKETS: usize = 128;
+
+/// Which media packets are protected together by a FEC packet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRtpFecMaskType")]
+pub enum MaskType {
+    /// Each FEC packet protects consecutive media packets
+    #[default]
+    #[enum_value(
+        name = "Protect consecutive packets, suitable for random losses",
+        nick = "random"
+    )]
+    Random,
+    /// Each FEC packet protects interleaved media packets
+    #[enum_value(
+        name = "Protect interleaved packets, suitable for bursty losses",
+        nick = "bursty"
+    )]
+    Bursty,
+}
+
+/// XOR of the recovery fields of a set of RTP packets.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// P, X and CC bits
+    pub flags: u8,
+    /// M bit and payload type
+    pub marker_pt: u8,
+    pub timestamp: u32,
+    /// Length of everything after the fixed RTP header
+    pub length: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Recovery {
+    /// XORs the RTP packet `data` into the recovery fields.
+    pub fn add(&mut self, data: &[u8]) {
+        assert!(data.len() >= RTP_HEADER_LEN);
+
+        self.flags ^= data[0] & 0x3f;
+        self.marker_pt ^= data[1];
+        self.timestamp ^= u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        self.length ^= (data.len() - RTP_HEADER_LEN) as u16;
+
+        let payload = &data[RTP_HEADER_LEN..];
+        if self.payload.len() < payload.len() {
+            self.payload.resize(payload.len(), 0);
+        }
+        for (r, p) in self.payload.iter_mut().zip(payload) {
+            *r ^= *p;
+        }
+    }
+
+    /// Builds the RTP packet that is left after XORing all other protected packets into the
+    /// recovery fields.
+    pub fn recover(&self, seqnum: u16, ssrc: u32) -> Option<Vec<u8>> {
+        let length = self.length as usize;
+        if length > self.payload.len() {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(RTP_HEADER_LEN + length);
+        data.push(0x80 | self.flags);
+        data.push(self.marker_pt);
+        data.extend_from_slice(&seqnum.to_be_bytes());
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(&ssrc.to_be_bytes());
+        data.extend_from_slice(&self.payload[..length]);
+
+        // Make sure the result is at least a valid RTP packet
+        RtpPacket::parse(&data).ok()?;
+
+        Some(data)
+    }
+}
+
+/// FEC data for a set of protected media packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FecGroup {
+    /// Sequence number of the first protected packet
+    pub seqnum_base: u16,
+    /// Bit `i` is set if the packet with sequence number `seqnum_base + i` is protected
+    pub mask: u128,
+    /// Timestamp of the last protected packet
+    pub timestamp: u32,
+    pub recovery: Recovery,
+}
+
+impl FecGroup {
+    /// Sequence numbers of all protected packets.
+    pub fn seqnums(seqnum_base: u16, mask: u128) -> impl Iterator<Item = u16> {
+        (0..128)
+            .filter(move |i| mask & (1 << i) != 0)
+            .map(move |i| seqnum_base.wrapping_add(i))
+    }
+}
+
+/// Collects media packets and decides when to generate how many FEC packets for them.
+///
+/// FEC packets are generated at the end of frames, i.e. when a packet has the marker bit set or
+/// a packet with a new timestamp arrives. The protection percentage is accumulated over frames so
+/// that small frames are protected together with the following frames.
+#[derive(Debug, Default)]
+pub struct FecGenerator {
+    packets: Vec<Vec<u8>>,
+    /// Accumulated percentage of the FEC packets that were not generated yet
+    budget: u32,
+}
+
+impl FecGenerator {
+    /// Adds the media packet `data` and returns the FEC data that should be sent now.
+    ///
+    /// `max_packets` is the maximum sequence number range that can be protected by a single FEC
+    /// packet.
+    pub fn push(
+        &mut self,
+        data: Vec<u8>,
+        percentage: u32,
+        mask_type: MaskType,
+        max_packets: usize,
+    ) -> Vec<FecGroup> {
+        if percentage == 0 {
+            self.reset();
+            return vec![];
+        }
+
+        let Ok(packet) = RtpPacket::parse(&data) else {
+            return vec![];
+        };
+        let seqnum = packet.sequence_number();
+        let timestamp = packet.timestamp();
+        let marker = packet.marker_bit();
+
+        let mut groups = vec![];
+
+        if self
+            .packets
+            .last()
+            .is_some_and(|last| packet_timestamp(last) != timestamp)
+        {
+            groups.extend(self.complete_frame(mask_type, false));
+        }
+
+        if self
+            .packets
+            .first()
+            .is_some_and(|first| seqnum.wrapping_sub(packet_seqnum(first)) as usize >= max_packets)
+        {
+            groups.extend(self.complete_frame(mask_type, true));
+        }
+
+        self.budget += percentage;
+        self.packets.push(data);
+
+        if marker {
+            groups.extend(self.complete_frame(mask_type, false));
+        }
+
+        groups
+    }
+
+    pub fn reset(&mut self) {
+        self.packets.clear();
+        self.budget = 0;
+    }
+
+    /// Generates FEC data for the queued packets if the budget allows, or at least one FEC packet
+    /// if `force` is set.
+    fn complete_frame(&mut self, mask_type: MaskType, force: bool) -> Vec<FecGroup> {
+        if self.packets.is_empty() {
+            return vec![];
+        }
+
+        let num_fec = (self.budget / 100) as usize;
+        if num_fec == 0 && !force {
+            return vec![];
+        }
+        self.budget -= (num_fec as u32) * 100;
+
+        let packets = std::mem::take(&mut self.packets);
+        let num_fec = num_fec.clamp(1, packets.len());
+        let seqnum_base = packet_seqnum(&packets[0]);
+
+        let mut groups = Vec::with_capacity(num_fec);
+        for i in 0..num_fec {
+            let protected = match mask_type {
+                MaskType::Random => {
+                    (i * packets.len() / num_fec)..((i + 1) * packets.len() / num_fec)
+                }
+                MaskType::Bursty => 0..packets.len(),
+            }
+            .filter(|j| mask_type != MaskType::Bursty || j % num_fec == i);
+
+            let mut group = FecGroup {
+                seqnum_base,
+                mask: 0,
+                timestamp: 0,
+                recovery: Recovery::default(),
+            };
+            for j in protected {
+                let packet = &packets[j];
+                group.mask |= 1 << packet_seqnum(packet).wrapping_sub(seqnum_base);
+                group.timestamp = packet_timestamp(packet);
+                group.recovery.add(packet);
+            }
+            groups.push(group);
+        }
+
+        groups
+    }
+}
+
+/// Builds a copy of `packet` with the given payload type, sequence number and payload.
+///
+/// Padding is not copied.
+pub fn rewrite_packet(packet: &RtpPacket, pt: u8, seqnum: u16, payload: &[&[u8]]) -> Vec<u8> {
+    let mut builder = RtpPacketBuilder::new()
+        .payload_type(pt)
+        .ssrc(packet.ssrc())
+        .sequence_number(seqnum)
+        .timestamp(packet.timestamp())
+        .marker_bit(packet.marker_bit());
+    for csrc in packet.csrc() {
+        builder = builder.add_csrc(csrc);
+    }
+    if let Some((pattern, data)) = packet.extension() {
+        builder = builder.extension(pattern, data);
+    }
+    for p in payload {
+        builder = builder.payload(*p);
+    }
+
+    // All fields are taken from a valid packet
+    builder.write_vec().unwrap()
+}
+
+fn packet_seqnum(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[2], data[3]])
+}
+
+fn packet_timestamp(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[4], data[5], data[6], data[7]])
+}
+
+/// A received FEC packet.
+#[derive(Debug, Clone)]
+pub struct FecPacket {
+    /// SSRC and sequence number of the protected media packets
+    pub protected: Vec<(u32, u16)>,
+    pub recovery: Recovery,
+}
+
+#[derive(Debug, Default)]
+struct MediaHistory {
+    packets: HashMap<u16, Vec<u8>>,
+    order: VecDeque<u16>,
+}
+
+impl MediaHistory {
+    fn insert(&mut self, seqnum: u16, data: Vec<u8>) {
+        if self.packets.insert(seqnum, data).is_some() {
+            return;
+        }
+
+        self.order.push_back(seqnum);
+        while self.order.len() > MAX_MEDIA_PACKETS {
+            let seqnum = self.order.pop_front().unwrap();
+            self.packets.remove(&seqnum);
+        }
+    }
+}
+
+/// Keeps track of received media and FEC packets and recovers missing media packets.
+#[derive(Debug, Default)]
+pub struct FecDecoder {
+    media: HashMap<u32, MediaHistory>,
+    fec: VecDeque<FecPacket>,
+    /// Number of recovered packets
+    pub num_recovered: u64,
+    /// Number of FEC packets that were discarded without being able to recover a packet
+    pub num_unrecovered: u64,
+}
+
+impl FecDecoder {
+    /// Stores a received media packet and returns all packets that can be recovered now.
+    pub fn add_media(&mut self, ssrc: u32, seqnum: u16, data: Vec<u8>) -> Vec<Vec<u8>> {
+        self.media.entry(ssrc).or_default().insert(seqnum, data);
+        self.recover()
+    }
+
+    /// Stores a received FEC packet and returns all packets that can be recovered now.
+    pub fn add_fec(&mut self, fec: FecPacket) -> Vec<Vec<u8>> {
+        if self.fec.len() >= MAX_FEC_PACKETS {
+            self.fec.pop_front();
+            self.num_unrecovered += 1;
+        }
+        self.fec.push_back(fec);
+        self.recover()
+    }
+
+    pub fn reset(&mut self) {
+        self.media.clear();
+        self.fec.clear();
+    }
+
+    fn recover(&mut self) -> Vec<Vec<u8>> {
+        let mut recovered = vec![];
+
+        loop {
+            let mut progress = false;
+
+            let mut idx = 0;
+            while idx < self.fec.len() {
+                let fec = &self.fec[idx];
+                let mut missing = fec.protected.iter().filter(|(ssrc, seqnum)| {
+                    !self
+                        .media
+                        .get(ssrc)
+                        .is_some_and(|history| history.packets.contains_key(seqnum))
+                });
+                let (Some(&(missing_ssrc, missing_seqnum)), None) =
+                    (missing.next(), missing.next())
+                else {
+                    if fec.protected.iter().all(|(ssrc, seqnum)| {
+                        self.media
+                            .get(ssrc)
+                            .is_some_and(|history| history.packets.contains_key(seqnum))
+                    }) {
+                        // Nothing to recover with this one anymore
+                        self.fec.remove(idx);
+                    } else {
+                        idx += 1;
+                    }
+                    continue;
+                };
+
+                let fec = self.fec.remove(idx).unwrap();
+                let mut recovery = fec.recovery;
+                for (ssrc, seqnum) in &fec.protected {
+                    if (*ssrc, *seqnum) != (missing_ssrc, missing_seqnum) {
+                        recovery.add(&self.media[ssrc].packets[seqnum]);
+                    }
+                }
+
+                if let Some(data) = recovery.recover(missing_seqnum, missing_ssrc) {
+                    self.media
+                        .entry(missing_ssrc)
+                        .or_default()
+                        .insert(missing_seqnum, data.clone());
+                    recovered.push(data);
+                    self.num_recovered += 1;
+                    progress = true;
+                } else {
+                    self.num_unrecovered += 1;
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        recovered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seqnum: u16, timestamp: u32, marker: bool, payload_len: usize) -> Vec<u8> {
+        rtp_types::RtpPacketBuilder::new()
+            .payload_type(96)
+            .ssrc(0x12345678)
+            .sequence_number(seqnum)
+            .timestamp(timestamp)
+            .marker_bit(marker)
+            .payload(vec![seqnum as u8; payload_len].as_slice())
+            .write_vec()
+            .unwrap()
+    }
+
+    #[test]
+    fn recover_single_loss() {
+        let packets = (0..4)
+            .map(|i| packet(100 + i, 1000, i == 3, 10 + i as usize))
+            .collect::<Vec<_>>();
+
+        let mut generator = FecGenerator::default();
+        let mut groups = vec![];
+        for p in &packets {
+            groups.extend(generator.push(p.clone(), 50, MaskType::Random, 48));
+        }
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].seqnum_base, 100);
+        assert_eq!(groups[0].mask, 0b0011);
+        assert_eq!(groups[1].mask, 0b1100);
+
+        let mut decoder = FecDecoder::default();
+        for group in &groups {
+            let protected = FecGroup::seqnums(group.seqnum_base, group.mask)
+                .map(|seqnum| (0x12345678, seqnum))
+                .collect::<Vec<_>>();
+            assert!(decoder
+                .add_fec(FecPacket {
+                    protected,
+                    recovery: group.recovery.clone(),
+                })
+                .is_empty());
+        }
+
+        // Lose one packet of each group
+        assert_eq!(
+            decoder.add_media(0x12345678, 100, packets[0].clone()),
+            vec![packets[1].clone()]
+        );
+        assert_eq!(
+            decoder.add_media(0x12345678, 103, packets[3].clone()),
+            vec![packets[2].clone()]
+        );
+        assert_eq!(decoder.num_recovered, 2);
+    }
+
+    #[test]
+    fn bursty_mask() {
+        let mut generator = FecGenerator::default();
+        let mut groups = vec![];
+        for i in 0..6 {
+            groups.extend(generator.push(packet(i, 0, i == 5, 10), 50, MaskType::Bursty, 48));
+        }
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].mask, 0b001001);
+        assert_eq!(groups[1].mask, 0b010010);
+        assert_eq!(groups[2].mask, 0b100100);
+    }
+
+    #[test]
+    fn accumulate_budget() {
+        let mut generator = FecGenerator::default();
+
+        // One packet per frame, a FEC packet for every 4 frames
+        let mut groups = vec![];
+        for i in 0..8 {
+            let g = generator.push(
+                packet(i, i as u32 * 160, false, 10),
+                25,
+                MaskType::Random,
+                48,
+            );
+            groups.extend(g);
+        }
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].seqnum_base, 0);
+        assert_eq!(groups[0].mask, 0b1111);
+    }
+
+    #[test]
+    fn unrecoverable() {
+        let packets = (0..2).map(|i| packet(i, 0, i == 1, 10)).collect::<Vec<_>>();
+
+        let mut generator = FecGenerator::default();
+        let groups = packets
+            .iter()
+            .flat_map(|p| generator.push(p.clone(), 50, MaskType::Random, 48))
+            .collect::<Vec<_>>();
+        assert_eq!(groups.len(), 1);
+
+        // Both packets lost, nothing can be recovered
+        let mut decoder = FecDecoder::default();
+        assert!(decoder
+            .add_fec(FecPacket {
+                protected: vec![(0x12345678, 0), (0x12345678, 1)],
+                recovery: groups[0].recovery.clone(),
+            })
+            .is_empty());
+        assert!(decoder
+            .add_media(0x12345678, 2, packet(2, 0, true, 10))
+            .is_empty());
+        assert_eq!(decoder.num_recovered, 0);
+    }
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! FlexFEC packet format as specified in RFC 8627.
+//!
+//! FEC packets are sent as a separate RTP stream with its own SSRC and sequence numbers. The
+//! SSRCs of the protected media streams are signalled in the CSRC list of the FEC packets.
+
+use rtp_types::{RtpPacket, RtpPacketBuilder};
+
+use super::common::{FecGroup, FecPacket, Recovery};
+
+/// Maximum sequence number range that can be protected by a flexible mask.
+pub const MAX_PROTECTED_PACKETS: usize = 109;
+
+/// Builds a FlexFEC packet with a flexible mask for the media packets of `media_ssrc` that are
+/// protected by `group`.
+pub fn write_packet(group: &FecGroup, media_ssrc: u32, pt: u8, ssrc: u32, seqnum: u16) -> Vec<u8> {
+    let recovery = &group.recovery;
+
+    let mut header = Vec::with_capacity(8 + 2 + 14);
+    // R and F bits are not set
+    header.push(recovery.flags & 0x3f);
+    header.push(recovery.marker_pt);
+    header.extend_from_slice(&recovery.length.to_be_bytes());
+    header.extend_from_slice(&recovery.timestamp.to_be_bytes());
+    header.extend_from_slice(&group.seqnum_base.to_be_bytes());
+    write_mask(&mut header, group.mask);
+
+    RtpPacketBuilder::new()
+        .payload_type(pt)
+        .ssrc(ssrc)
+        .sequence_number(seqnum)
+        .timestamp(group.timestamp)
+        .add_csrc(media_ssrc)
+        .payload(header.as_slice())
+        .payload(recovery.payload.as_slice())
+        .write_vec()
+        .unwrap()
+}
+
+/// Writes the mask with the k-bits indicating whether it continues.
+fn write_mask(data: &mut Vec<u8>, mask: u128) {
+    let bit = |i: usize| ((mask >> i) & 1) as u64;
+
+    let chunk = (0..15).fold(0u16, |acc, i| acc | ((bit(i) as u16) << (14 - i)));
+    if mask >> 15 == 0 {
+        data.extend_from_slice(&(chunk | 0x8000).to_be_bytes());
+        return;
+    }
+    data.extend_from_slice(&chunk.to_be_bytes());
+
+    let chunk = (0..31).fold(0u32, |acc, i| acc | ((bit(15 + i) as u32) << (30 - i)));
+    if mask >> 46 == 0 {
+        data.extend_from_slice(&(chunk | 0x8000_0000).to_be_bytes());
+        return;
+    }
+    data.extend_from_slice(&chunk.to_be_bytes());
+
+    let chunk = (0..64).fold(0u64, |acc, i| acc | (bit(46 + i) << (63 - i)));
+    data.extend_from_slice(&chunk.to_be_bytes());
+}
+
+/// Parses a FlexFEC packet.
+///
+/// Returns `None` for invalid packets and retransmissions.
+pub fn parse_packet(packet: &RtpPacket) -> Option<FecPacket> {
+    let payload = packet.payload();
+    if payload.len() < 8 {
+        return None;
+    }
+
+    // Retransmission
+    if payload[0] & 0x80 != 0 {
+        return None;
+    }
+    let fixed_mask = payload[0] & 0x40 != 0;
+
+    let recovery = Recovery {
+        flags: payload[0] & 0x3f,
+        marker_pt: payload[1],
+        length: u16::from_be_bytes([payload[2], payload[3]]),
+        timestamp: u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+        payload: vec![],
+    };
+
+    let mut data = &payload[8..];
+    let mut protected = vec![];
+    for ssrc in packet.csrc() {
+        let (base, rest) = split(data, 2)?;
+        let seqnum_base = u16::from_be_bytes([base[0], base[1]]);
+
+        if fixed_mask {
+            let (ld, rest) = split(rest, 2)?;
+            data = rest;
+
+            let (l, d) = (ld[0] as u16, ld[1] as u16);
+            if d == 0 {
+                // Row FEC
+                protected.extend((0..l).map(|i| (ssrc, seqnum_base.wrapping_add(i))));
+            } else {
+                // Column FEC
+                protected.extend((0..d).map(|i| (ssrc, seqnum_base.wrapping_add(i * l))));
+            }
+        } else {
+            let (chunk, rest) = split(rest, 2)?;
+            data = rest;
+            let chunk = u16::from_be_bytes([chunk[0], chunk[1]]);
+            let mut mask = 0u128;
+            for i in 0..15 {
+                mask |= (((chunk >> (14 - i)) & 1) as u128) << i;
+            }
+
+            if chunk & 0x8000 == 0 {
+                let (chunk, rest) = split(data, 4)?;
+                data = rest;
+                let chunk = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                for i in 0..31 {
+                    mask |= (((chunk >> (30 - i)) & 1) as u128) << (15 + i);
+                }
+
+                if chunk & 0x8000_0000 == 0 {
+                    let (chunk, rest) = split(data, 8)?;
+                    data = rest;
+                    let chunk = u64::from_be_bytes(chunk.try_into().unwrap());
+                    for i in 0..64 {
+                        mask |= (((chunk >> (63 - i)) & 1) as u128) << (46 + i);
+                    }
+                }
+            }
+
+            protected.extend(FecGroup::seqnums(seqnum_base, mask).map(|seqnum| (ssrc, seqnum)));
+        }
+    }
+
+    if protected.is_empty() {
+        return None;
+    }
+
+    Some(FecPacket {
+        protected,
+        recovery: Recovery {
+            payload: data.to_vec(),
+            ..recovery
+        },
+    })
+}
+
+fn split(data: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    (data.len() >= len).then(|| data.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_sizes() {
+        for (mask, mask_len) in [
+            (0b101u128, 2),
+            (1 << 14, 2),
+            (1 << 15, 6),
+            (1 << 45 | 1, 6),
+            (1 << 46, 14),
+            (1 << 108 | 1 << 20 | 1, 14),
+        ] {
+            let group = FecGroup {
+                seqnum_base: 65530,
+                mask,
+                timestamp: 1234,
+                recovery: Recovery {
+                    flags: 0x01,
+                    marker_pt: 0x80 | 96,
+                    timestamp: 0x1234_5678,
+                    length: 3,
+                    payload: vec![1, 2, 3],
+                },
+            };
+
+            let data = write_packet(&group, 0x11223344, 100, 0x55667788, 10);
+            let packet = RtpPacket::parse(&data).unwrap();
+            assert_eq!(packet.payload().len(), 8 + 2 + mask_len + 3);
+            assert_eq!(packet.csrc().collect::<Vec<_>>(), [0x11223344]);
+
+            let fec = parse_packet(&packet).unwrap();
+            assert_eq!(fec.recovery, group.recovery);
+            assert_eq!(
+                fec.protected,
+                FecGroup::seqnums(65530, mask)
+                    .map(|seqnum| (0x11223344, seqnum))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpflexfecdec
+ * @see_also: rtpflexfecenc, rtprecv
+ *
+ * Recovers lost RTP packets from FlexFEC packets as specified in [RFC 8627][rfc-8627].
+ *
+ * The element receives both the media packets and the FEC packets, which are identified by their
+ * payload type. Media packets are forwarded unchanged and recovered packets are inserted into the
+ * stream as soon as they can be recovered. FEC packets are not forwarded.
+ *
+ * The element is placed before `rtprecv` so that recovered packets are reordered by its
+ * jitterbuffer:
+ *
+ * ``` shell
+ * gst-launch-1.0 udpsrc port=5004 caps=application/x-rtp,media=video,encoding-name=VP8,clock-rate=90000 ! \
+ *     rtpflexfecdec pt=100 ! rtprecv name=rtprecv rtprecv. ! rtpvp8depay2 ! vp8dec ! videoconvert ! autovideosink
+ * ```
+ *
+ * [rfc-8627]: https://www.rfc-editor.org/rfc/rfc8627.html
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use once_cell::sync::Lazy;
+
+use crate::fec::{common::FecDecoder, flexfec};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpflexfecdec",
+        gst::DebugColorFlags::empty(),
+        Some("RTP FlexFEC Decoder"),
+    )
+});
+
+const DEFAULT_PT: u32 = 100;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pt: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT as u8,
+        }
+    }
+}
+
+pub struct RtpFlexFecDec {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    settings: Mutex<Settings>,
+    decoder: Mutex<FecDecoder>,
+}
+
+impl RtpFlexFecDec {
+    fn sink_chain(
+        &self,
+        _pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::error!(CAT, imp = self, "Failed to map input buffer");
+            gst::FlowError::Error
+        })?;
+        let packet = match rtp_types::RtpPacket::parse(&map) {
+            Ok(packet) => packet,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to parse RTP packet: {err:?}");
+                drop(map);
+                return self.srcpad.push(buffer);
+            }
+        };
+
+        let is_fec = packet.payload_type() == settings.pt;
+        let recovered = if is_fec {
+            let Some(fec) = flexfec::parse_packet(&packet) else {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Ignoring unsupported FEC packet {}",
+                    packet.sequence_number()
+                );
+                return Ok(gst::FlowSuccess::Ok);
+            };
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Received FEC packet protecting {:?}",
+                fec.protected
+            );
+
+            self.decoder.lock().unwrap().add_fec(fec)
+        } else {
+            self.decoder.lock().unwrap().add_media(
+                packet.ssrc(),
+                packet.sequence_number(),
+                map.to_vec(),
+            )
+        };
+        drop(map);
+
+        let (pts, dts) = (buffer.pts(), buffer.dts());
+        if !is_fec {
+            self.srcpad.push(buffer)?;
+        }
+
+        for data in recovered {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Recovered packet {}",
+                u16::from_be_bytes([data[2], data[3]])
+            );
+
+            let mut buffer = gst::Buffer::from_mut_slice(data);
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(pts);
+                buffer.set_dts(dts);
+            }
+            self.srcpad.push(buffer)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            self.decoder.lock().unwrap().reset();
+        }
+
+        gst::Pad::event_default(pad, Some(&*self.obj()), event)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpFlexFecDec {
+    const NAME: &'static str = "GstRtpFlexFecDec";
+    type Type = super::RtpFlexFecDec;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .chain_function(|pad, parent, buffer| {
+                Self::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this| this.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Self::catch_panic_pad_function(parent, || false, |this| this.sink_event(pad, event))
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_from_template(&templ)
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            settings: Mutex::default(),
+            decoder: Mutex::default(),
+        }
+    }
+}
+
+impl ObjectImpl for RtpFlexFecDec {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("pt")
+                    .nick("Payload Type")
+                    .blurb("Payload type of the FEC packets")
+                    .minimum(96)
+                    .maximum(127)
+                    .default_value(DEFAULT_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("recovered")
+                    .nick("Recovered")
+                    .blurb("Number of recovered packets so far")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("unrecovered")
+                    .nick("Unrecovered")
+                    .blurb("Number of FEC packets that could not be used for recovery so far")
+                    .read_only()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "pt" => self.settings.lock().unwrap().pt = value.get::<u32>().unwrap() as u8,
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "pt" => (self.settings.lock().unwrap().pt as u32).to_value(),
+            "recovered" => self.decoder.lock().unwrap().num_recovered.to_value(),
+            "unrecovered" => self.decoder.lock().unwrap().num_unrecovered.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpFlexFecDec {}
+
+impl ElementImpl for RtpFlexFecDec {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP FlexFEC Decoder",
+                "Codec/Decoder/Network/RTP",
+                "Recovers lost packets from FlexFEC packets (RFC 8627)",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("application/x-rtp").build();
+
+            vec![
+                gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+                gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.decoder.lock().unwrap() = FecDecoder::default();
+        }
+
+        self.parent_change_state(transition)
+    }
+}
@@ -0,0 +1,342 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpflexfecenc
+ * @see_also: rtpflexfecdec, rtpsend
+ *
+ * Generates FlexFEC packets as specified in [RFC 8627][rfc-8627] for the RTP packets passing
+ * through and sends them as a separate RTP stream with its own SSRC, interleaved with the media
+ * packets.
+ *
+ * FEC packets are generated at the end of each frame, i.e. when a packet with the marker bit set
+ * or a new RTP timestamp is seen. The number of FEC packets is configured as percentage of the
+ * number of media packets with the #rtpflexfecenc:percentage property. Frames that are too small
+ * for a single FEC packet are protected together with the following frames. The
+ * #rtpflexfecenc:mask-type property selects whether each FEC packet protects consecutive or
+ * interleaved media packets.
+ *
+ * The element is placed between the payloader and `rtpsend`:
+ *
+ * ``` shell
+ * gst-launch-1.0 videotestsrc ! vp8enc ! rtpvp8pay2 ! rtpflexfecenc percentage=20 pt=100 ! \
+ *     rtpsend name=rtpsend rtpsend.rtp_src_0 ! udpsink port=5004
+ * ```
+ *
+ * [rfc-8627]: https://www.rfc-editor.org/rfc/rfc8627.html
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{collections::HashMap, sync::Mutex};
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use once_cell::sync::Lazy;
+
+use crate::fec::{
+    common::{FecGenerator, FecGroup, MaskType},
+    flexfec,
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpflexfecenc",
+        gst::DebugColorFlags::empty(),
+        Some("RTP FlexFEC Encoder"),
+    )
+});
+
+const DEFAULT_PT: u32 = 100;
+const DEFAULT_SSRC: u32 = 0;
+const DEFAULT_PERCENTAGE: u32 = 0;
+const DEFAULT_MASK_TYPE: MaskType = MaskType::Random;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pt: u8,
+    ssrc: u32,
+    percentage: u32,
+    mask_type: MaskType,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT as u8,
+            ssrc: DEFAULT_SSRC,
+            percentage: DEFAULT_PERCENTAGE,
+            mask_type: DEFAULT_MASK_TYPE,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// FEC generator per media SSRC
+    generators: HashMap<u32, FecGenerator>,
+    /// SSRC of the FEC stream, selected with the first FEC packet
+    ssrc: Option<u32>,
+    seqnum: u16,
+    num_packets: u64,
+}
+
+pub struct RtpFlexFecEnc {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl RtpFlexFecEnc {
+    fn sink_chain(
+        &self,
+        _pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let fec_packets = {
+            let map = buffer.map_readable().map_err(|_| {
+                gst::error!(CAT, imp = self, "Failed to map input buffer");
+                gst::FlowError::Error
+            })?;
+            let packet = match rtp_types::RtpPacket::parse(&map) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to parse RTP packet: {err:?}");
+                    drop(map);
+                    return self.srcpad.push(buffer);
+                }
+            };
+            let media_ssrc = packet.ssrc();
+
+            let mut state = self.state.lock().unwrap();
+            let groups = state.generators.entry(media_ssrc).or_default().push(
+                map.to_vec(),
+                settings.percentage,
+                settings.mask_type,
+                flexfec::MAX_PROTECTED_PACKETS,
+            );
+
+            let mut fec_packets = Vec::with_capacity(groups.len());
+            for group in groups {
+                let ssrc = *state.ssrc.get_or_insert_with(|| {
+                    if settings.ssrc != 0 {
+                        settings.ssrc
+                    } else {
+                        rand::random()
+                    }
+                });
+                let seqnum = state.seqnum;
+                state.seqnum = seqnum.wrapping_add(1);
+                state.num_packets += 1;
+
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Generating FEC packet {seqnum} for SSRC {media_ssrc:08x} protecting {:?}",
+                    FecGroup::seqnums(group.seqnum_base, group.mask).collect::<Vec<_>>()
+                );
+
+                let mut fec_buffer = gst::Buffer::from_mut_slice(flexfec::write_packet(
+                    &group,
+                    media_ssrc,
+                    settings.pt,
+                    ssrc,
+                    seqnum,
+                ));
+                {
+                    let fec_buffer = fec_buffer.get_mut().unwrap();
+                    fec_buffer.set_pts(buffer.pts());
+                    fec_buffer.set_dts(buffer.dts());
+                }
+                fec_packets.push(fec_buffer);
+            }
+
+            fec_packets
+        };
+
+        self.srcpad.push(buffer)?;
+        for fec_buffer in fec_packets {
+            self.srcpad.push(fec_buffer)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.generators.clear();
+        }
+
+        gst::Pad::event_default(pad, Some(&*self.obj()), event)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpFlexFecEnc {
+    const NAME: &'static str = "GstRtpFlexFecEnc";
+    type Type = super::RtpFlexFecEnc;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .chain_function(|pad, parent, buffer| {
+                Self::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this| this.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Self::catch_panic_pad_function(parent, || false, |this| this.sink_event(pad, event))
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_from_template(&templ)
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            settings: Mutex::default(),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl ObjectImpl for RtpFlexFecEnc {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("pt")
+                    .nick("Payload Type")
+                    .blurb("Payload type of the FEC packets")
+                    .minimum(96)
+                    .maximum(127)
+                    .default_value(DEFAULT_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("ssrc")
+                    .nick("SSRC")
+                    .blurb("SSRC of the FEC packets (0 = random)")
+                    .default_value(DEFAULT_SSRC)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("percentage")
+                    .nick("Percentage")
+                    .blurb("Number of FEC packets in percent of the number of media packets")
+                    .maximum(100)
+                    .default_value(DEFAULT_PERCENTAGE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("mask-type", DEFAULT_MASK_TYPE)
+                    .nick("Mask Type")
+                    .blurb("Which media packets are protected together by a FEC packet")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt64::builder("num-packets")
+                    .nick("Number of FEC Packets")
+                    .blurb("Number of FEC packets generated so far")
+                    .read_only()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pt" => settings.pt = value.get::<u32>().unwrap() as u8,
+            "ssrc" => settings.ssrc = value.get().unwrap(),
+            "percentage" => settings.percentage = value.get().unwrap(),
+            "mask-type" => settings.mask_type = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pt" => (settings.pt as u32).to_value(),
+            "ssrc" => settings.ssrc.to_value(),
+            "percentage" => settings.percentage.to_value(),
+            "mask-type" => settings.mask_type.to_value(),
+            "num-packets" => self.state.lock().unwrap().num_packets.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpFlexFecEnc {}
+
+impl ElementImpl for RtpFlexFecEnc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP FlexFEC Encoder",
+                "Codec/Encoder/Network/RTP",
+                "Generates FlexFEC packets (RFC 8627)",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("application/x-rtp").build();
+
+            vec![
+                gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+                gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State {
+                seqnum: rand::random(),
+                ..State::default()
+            };
+        }
+
+        self.parent_change_state(transition)
+    }
+}
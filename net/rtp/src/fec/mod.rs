@@ -0,0 +1,38 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! FlexFEC (RFC 8627) and ULPFEC (RFC 5109) encoders and decoders, the latter optionally with RED
+//! (RFC 2198) encapsulation.
+
+use gst::glib;
+
+pub(crate) mod common;
+pub(crate) mod flexfec;
+mod ulpfec;
+
+pub mod flexfecdec;
+pub mod flexfecenc;
+pub mod ulpfecdec;
+pub mod ulpfecenc;
+
+#[cfg(test)]
+mod tests;
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    #[cfg(feature = "doc")]
+    {
+        use gst::prelude::*;
+
+        common::MaskType::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    }
+
+    flexfecdec::register(plugin)?;
+    flexfecenc::register(plugin)?;
+    ulpfecdec::register(plugin)?;
+    ulpfecenc::register(plugin)?;
+
+    Ok(())
+}
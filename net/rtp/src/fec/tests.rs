@@ -0,0 +1,178 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::iter;
+
+use gst::prelude::*;
+use gst_check::Harness;
+use rtp_types::{RtpPacket, RtpPacketBuilder};
+
+const MEDIA_PT: u8 = 96;
+const MEDIA_SSRC: u32 = 0x12345678;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtpfec test");
+    });
+}
+
+/// Two frames of four packets each with different payload sizes.
+fn make_packets(seqnum: u16) -> Vec<Vec<u8>> {
+    (0..8u16)
+        .map(|i| {
+            let payload = vec![i as u8; 10 + 7 * i as usize];
+            RtpPacketBuilder::new()
+                .payload_type(MEDIA_PT)
+                .ssrc(MEDIA_SSRC)
+                .sequence_number(seqnum.wrapping_add(i))
+                .timestamp(1000 + 3000 * (i / 4) as u32)
+                .marker_bit(i % 4 == 3)
+                .payload(payload.as_slice())
+                .write_vec()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn run(h: &mut Harness, input: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    h.set_src_caps_str("application/x-rtp,media=video,encoding-name=VP8,clock-rate=90000");
+    for (i, data) in input.into_iter().enumerate() {
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_pts(gst::ClockTime::from_mseconds(i as u64));
+        h.push(buffer).unwrap();
+    }
+    h.push_event(gst::event::Eos::new());
+
+    iter::from_fn(|| h.try_pull())
+        .map(|buffer| buffer.into_mapped_buffer_readable().unwrap().to_vec())
+        .collect()
+}
+
+/// Payloads of the media packets in sequence number order.
+fn media_payloads(packets: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut packets = packets
+        .iter()
+        .map(|data| RtpPacket::parse(data).unwrap())
+        .collect::<Vec<_>>();
+    assert!(packets
+        .iter()
+        .all(|packet| packet.payload_type() == MEDIA_PT && packet.ssrc() == MEDIA_SSRC));
+
+    let first = packets[0].sequence_number();
+    packets.sort_by_key(|packet| packet.sequence_number().wrapping_sub(first));
+
+    packets
+        .iter()
+        .map(|packet| packet.payload().to_vec())
+        .collect()
+}
+
+#[test]
+fn test_flexfec() {
+    init();
+
+    let input = make_packets(65533);
+
+    let mut h = Harness::new("rtpflexfecenc");
+    let enc = h.element().unwrap();
+    enc.set_property("pt", 100u32);
+    enc.set_property("ssrc", 0x55667788u32);
+    enc.set_property("percentage", 50u32);
+    let output = run(&mut h, input.clone());
+
+    // Two FEC packets for each frame
+    assert_eq!(output.len(), 12);
+    assert_eq!(h.element().unwrap().property::<u64>("num-packets"), 4);
+    let fec = output
+        .iter()
+        .filter(|data| RtpPacket::parse(data).unwrap().payload_type() == 100)
+        .collect::<Vec<_>>();
+    assert_eq!(fec.len(), 4);
+    for data in fec {
+        let packet = RtpPacket::parse(data).unwrap();
+        assert_eq!(packet.ssrc(), 0x55667788);
+        assert_eq!(packet.csrc().collect::<Vec<_>>(), [MEDIA_SSRC]);
+    }
+
+    // Lose one media packet of each frame
+    let received = output
+        .into_iter()
+        .filter(|data| {
+            let seqnum = RtpPacket::parse(data).unwrap().sequence_number();
+            data[1] & 0x7f == 100 || (seqnum != 65534 && seqnum != 3)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(received.len(), 10);
+
+    let mut h = Harness::new("rtpflexfecdec");
+    h.element().unwrap().set_property("pt", 100u32);
+    let output = run(&mut h, received);
+
+    assert_eq!(output.len(), 8);
+    assert_eq!(h.element().unwrap().property::<u64>("recovered"), 2);
+    assert_eq!(output.iter().filter(|data| **data == input[1]).count(), 1);
+    assert_eq!(output.iter().filter(|data| **data == input[6]).count(), 1);
+    assert_eq!(media_payloads(&output), media_payloads(&input));
+}
+
+#[test]
+fn test_ulpfec_red() {
+    init();
+
+    let input = make_packets(100);
+
+    let mut h = Harness::new("rtpulpfecenc2");
+    let enc = h.element().unwrap();
+    enc.set_property("pt", 101u32);
+    enc.set_property("red-pt", 102u32);
+    enc.set_property("percentage", 50u32);
+    let output = run(&mut h, input.clone());
+
+    // Two FEC packets for each frame, all in the same RTP stream and encapsulated in RED
+    assert_eq!(output.len(), 12);
+    assert_eq!(h.element().unwrap().property::<u64>("num-packets"), 4);
+    let mut seqnum = 100u16;
+    let mut num_fec = 0;
+    for data in &output {
+        let packet = RtpPacket::parse(data).unwrap();
+        assert_eq!(packet.payload_type(), 102);
+        assert_eq!(packet.ssrc(), MEDIA_SSRC);
+        assert_eq!(packet.sequence_number(), seqnum);
+        seqnum = seqnum.wrapping_add(1);
+        if packet.payload()[0] == 101 {
+            num_fec += 1;
+        }
+    }
+    assert_eq!(num_fec, 4);
+
+    // Lose one media packet of each frame: 101 and the third packet of the second frame, which
+    // comes after the two FEC packets of the first frame
+    let received = output
+        .into_iter()
+        .filter(|data| {
+            let seqnum = RtpPacket::parse(data).unwrap().sequence_number();
+            seqnum != 101 && seqnum != 108
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(received.len(), 10);
+
+    let mut h = Harness::new("rtpulpfecdec2");
+    let dec = h.element().unwrap();
+    dec.set_property("pt", 101u32);
+    dec.set_property("red-pt", 102u32);
+    let output = run(&mut h, received);
+
+    assert_eq!(output.len(), 8);
+    assert_eq!(h.element().unwrap().property::<u64>("recovered"), 2);
+    assert_eq!(media_payloads(&output), media_payloads(&input));
+}
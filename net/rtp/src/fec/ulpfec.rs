@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! ULPFEC packet format as specified in RFC 5109 and RED encapsulation as specified in
+//! RFC 2198.
+//!
+//! ULPFEC packets are sent in the same RTP stream as the media packets, i.e. with the same SSRC
+//! and sequence number space. Only a single protection level is used.
+
+use rtp_types::RtpPacket;
+
+use super::common::{rewrite_packet, FecGroup, FecPacket, Recovery};
+
+/// Maximum sequence number range that can be protected with the long mask.
+pub const MAX_PROTECTED_PACKETS: usize = 48;
+
+/// Size of the FEC header.
+const FEC_HEADER_LEN: usize = 10;
+
+/// Builds the ULPFEC payload for the media packets that are protected by `group`.
+pub fn write_payload(group: &FecGroup) -> Vec<u8> {
+    let recovery = &group.recovery;
+    let long_mask = group.mask >> 16 != 0;
+
+    let mut data = Vec::with_capacity(FEC_HEADER_LEN + 8 + recovery.payload.len());
+    // E bit is not set
+    data.push(((long_mask as u8) << 6) | (recovery.flags & 0x3f));
+    data.push(recovery.marker_pt);
+    data.extend_from_slice(&group.seqnum_base.to_be_bytes());
+    data.extend_from_slice(&recovery.timestamp.to_be_bytes());
+    data.extend_from_slice(&recovery.length.to_be_bytes());
+
+    // Level 0 header protecting the complete packets
+    data.extend_from_slice(&(recovery.payload.len() as u16).to_be_bytes());
+    let mask_bits = if long_mask { 48 } else { 16 };
+    let mask = (0..mask_bits).fold(0u64, |acc, i| {
+        acc | ((((group.mask >> i) & 1) as u64) << (mask_bits - 1 - i))
+    });
+    data.extend_from_slice(&mask.to_be_bytes()[8 - mask_bits / 8..]);
+
+    data.extend_from_slice(&recovery.payload);
+
+    data
+}
+
+/// Parses the ULPFEC `payload` of a FEC packet of the media stream `ssrc`.
+pub fn parse_payload(payload: &[u8], ssrc: u32) -> Option<FecPacket> {
+    if payload.len() < FEC_HEADER_LEN + 4 {
+        return None;
+    }
+
+    // Extension flag is reserved and must be zero
+    if payload[0] & 0x80 != 0 {
+        return None;
+    }
+    let long_mask = payload[0] & 0x40 != 0;
+    let mask_bytes = if long_mask { 6 } else { 2 };
+
+    let seqnum_base = u16::from_be_bytes([payload[2], payload[3]]);
+    let protection_length = u16::from_be_bytes([payload[10], payload[11]]) as usize;
+    let data = &payload[FEC_HEADER_LEN + 2..];
+    if data.len() < mask_bytes + protection_length {
+        return None;
+    }
+
+    let mask = data[..mask_bytes]
+        .iter()
+        .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+    let mask_bits = mask_bytes * 8;
+    let mask = (0..mask_bits).fold(0u128, |acc, i| {
+        acc | ((((mask >> (mask_bits - 1 - i)) & 1) as u128) << i)
+    });
+
+    let protected = FecGroup::seqnums(seqnum_base, mask)
+        .map(|seqnum| (ssrc, seqnum))
+        .collect::<Vec<_>>();
+    if protected.is_empty() {
+        return None;
+    }
+
+    Some(FecPacket {
+        protected,
+        recovery: Recovery {
+            flags: payload[0] & 0x3f,
+            marker_pt: payload[1],
+            timestamp: u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+            length: u16::from_be_bytes([payload[8], payload[9]]),
+            payload: data[mask_bytes..][..protection_length].to_vec(),
+        },
+    })
+}
+
+/// Encapsulates `packet` into a RED packet with only a primary block.
+pub fn red_encapsulate(packet: &RtpPacket, red_pt: u8) -> Vec<u8> {
+    rewrite_packet(
+        packet,
+        red_pt,
+        packet.sequence_number(),
+        &[&[packet.payload_type()], packet.payload()],
+    )
+}
+
+/// Extracts the primary block of the RED `packet` as RTP packet.
+///
+/// Redundant blocks are skipped as their sequence numbers are unknown.
+pub fn red_decapsulate(packet: &RtpPacket) -> Option<Vec<u8>> {
+    let payload = packet.payload();
+
+    let mut offset = 0;
+    let mut redundant_len = 0;
+    loop {
+        let header = *payload.get(offset)?;
+        if header & 0x80 == 0 {
+            offset += 1;
+            let payload = payload.get(offset + redundant_len..)?;
+            return Some(rewrite_packet(
+                packet,
+                header & 0x7f,
+                packet.sequence_number(),
+                &[payload],
+            ));
+        }
+
+        let header = payload.get(offset..offset + 4)?;
+        redundant_len += (((header[2] & 0x03) as usize) << 8) | header[3] as usize;
+        offset += 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fec_payload() {
+        for (mask, len) in [
+            (0b1011u128, FEC_HEADER_LEN + 4),
+            (1 << 47 | 1, FEC_HEADER_LEN + 8),
+        ] {
+            let group = FecGroup {
+                seqnum_base: 100,
+                mask,
+                timestamp: 1234,
+                recovery: Recovery {
+                    flags: 0x01,
+                    marker_pt: 0x80 | 96,
+                    timestamp: 0x1234_5678,
+                    length: 3,
+                    payload: vec![1, 2, 3],
+                },
+            };
+
+            let payload = write_payload(&group);
+            assert_eq!(payload.len(), len + 3);
+
+            let fec = parse_payload(&payload, 0x11223344).unwrap();
+            assert_eq!(fec.recovery, group.recovery);
+            assert_eq!(
+                fec.protected,
+                FecGroup::seqnums(100, mask)
+                    .map(|seqnum| (0x11223344, seqnum))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn red() {
+        let data = rtp_types::RtpPacketBuilder::new()
+            .payload_type(96)
+            .ssrc(0x12345678)
+            .sequence_number(10)
+            .timestamp(1000)
+            .marker_bit(true)
+            .payload([1u8, 2, 3].as_slice())
+            .write_vec()
+            .unwrap();
+        let packet = RtpPacket::parse(&data).unwrap();
+
+        let red = red_encapsulate(&packet, 120);
+        let red_packet = RtpPacket::parse(&red).unwrap();
+        assert_eq!(red_packet.payload_type(), 120);
+        assert_eq!(red_packet.payload(), [96, 1, 2, 3]);
+
+        assert_eq!(red_decapsulate(&red_packet).unwrap(), data);
+
+        // With a redundant block of 2 bytes and a timestamp offset of 160
+        let red = rtp_types::RtpPacketBuilder::new()
+            .payload_type(120)
+            .ssrc(0x12345678)
+            .sequence_number(10)
+            .timestamp(1000)
+            .marker_bit(true)
+            .payload([0x80u8 | 96, 2, 128, 2, 96, 9, 9, 1, 2, 3].as_slice())
+            .write_vec()
+            .unwrap();
+        let red_packet = RtpPacket::parse(&red).unwrap();
+        assert_eq!(red_decapsulate(&red_packet).unwrap(), data);
+    }
+}
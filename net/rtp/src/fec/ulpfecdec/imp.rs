@@ -0,0 +1,340 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpulpfecdec2
+ * @see_also: rtpulpfecenc2, rtprecv
+ *
+ * Recovers lost RTP packets from ULPFEC packets as specified in [RFC 5109][rfc-5109], optionally
+ * encapsulated in RED as specified in [RFC 2198][rfc-2198].
+ *
+ * The element receives both the media packets and the FEC packets, which are identified by their
+ * payload type. Media packets are forwarded and recovered packets are inserted into the stream as
+ * soon as they can be recovered. FEC packets are not forwarded. If #rtpulpfecdec2:red-pt is set
+ * then RED packets with this payload type are decapsulated and only the primary block is used.
+ *
+ * The element is placed before `rtprecv` so that recovered packets are reordered by its
+ * jitterbuffer:
+ *
+ * ``` shell
+ * gst-launch-1.0 udpsrc port=5004 caps=application/x-rtp,media=video,encoding-name=VP8,clock-rate=90000 ! \
+ *     rtpulpfecdec2 pt=101 red-pt=102 ! rtprecv name=rtprecv rtprecv. ! rtpvp8depay2 ! vp8dec ! videoconvert ! autovideosink
+ * ```
+ *
+ * As ULPFEC packets share the sequence number space with the media packets, the sequence numbers
+ * of the dropped FEC packets show up as gaps in the media stream and can trigger retransmission
+ * requests. Use FlexFEC with `rtpflexfecdec` if this is a problem.
+ *
+ * [rfc-5109]: https://www.rfc-editor.org/rfc/rfc5109.html
+ * [rfc-2198]: https://www.rfc-editor.org/rfc/rfc2198.html
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use once_cell::sync::Lazy;
+
+use crate::fec::{common::FecDecoder, ulpfec};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpulpfecdec2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP ULPFEC Decoder"),
+    )
+});
+
+const DEFAULT_PT: u32 = 101;
+const DEFAULT_RED_PT: u32 = 0;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pt: u8,
+    red_pt: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT as u8,
+            red_pt: DEFAULT_RED_PT as u8,
+        }
+    }
+}
+
+pub struct RtpUlpFecDec {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    settings: Mutex<Settings>,
+    decoder: Mutex<FecDecoder>,
+}
+
+impl RtpUlpFecDec {
+    fn sink_chain(
+        &self,
+        _pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::error!(CAT, imp = self, "Failed to map input buffer");
+            gst::FlowError::Error
+        })?;
+        let packet = match rtp_types::RtpPacket::parse(&map) {
+            Ok(packet) => packet,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to parse RTP packet: {err:?}");
+                drop(map);
+                return self.srcpad.push(buffer);
+            }
+        };
+
+        let decapsulated = if settings.red_pt != 0 && packet.payload_type() == settings.red_pt {
+            let Some(data) = ulpfec::red_decapsulate(&packet) else {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Dropping invalid RED packet {}",
+                    packet.sequence_number()
+                );
+                return Ok(gst::FlowSuccess::Ok);
+            };
+            Some(data)
+        } else {
+            None
+        };
+        let packet = match decapsulated {
+            Some(ref data) => rtp_types::RtpPacket::parse(data).unwrap(),
+            None => packet,
+        };
+
+        let is_fec = packet.payload_type() == settings.pt;
+        let recovered = if is_fec {
+            let Some(fec) = ulpfec::parse_payload(packet.payload(), packet.ssrc()) else {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Ignoring invalid FEC packet {}",
+                    packet.sequence_number()
+                );
+                return Ok(gst::FlowSuccess::Ok);
+            };
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Received FEC packet protecting {:?}",
+                fec.protected
+            );
+
+            self.decoder.lock().unwrap().add_fec(fec)
+        } else {
+            let data = decapsulated.clone().unwrap_or_else(|| map.to_vec());
+            self.decoder
+                .lock()
+                .unwrap()
+                .add_media(packet.ssrc(), packet.sequence_number(), data)
+        };
+        drop(map);
+
+        let (pts, dts) = (buffer.pts(), buffer.dts());
+        if !is_fec {
+            let buffer = match decapsulated {
+                Some(data) => {
+                    let mut media_buffer = gst::Buffer::from_mut_slice(data);
+                    {
+                        let media_buffer = media_buffer.get_mut().unwrap();
+                        media_buffer.set_pts(pts);
+                        media_buffer.set_dts(dts);
+                        media_buffer.set_flags(buffer.flags());
+                    }
+                    media_buffer
+                }
+                None => buffer,
+            };
+            self.srcpad.push(buffer)?;
+        }
+
+        for data in recovered {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Recovered packet {}",
+                u16::from_be_bytes([data[2], data[3]])
+            );
+
+            let mut buffer = gst::Buffer::from_mut_slice(data);
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(pts);
+                buffer.set_dts(dts);
+            }
+            self.srcpad.push(buffer)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            self.decoder.lock().unwrap().reset();
+        }
+
+        gst::Pad::event_default(pad, Some(&*self.obj()), event)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpUlpFecDec {
+    const NAME: &'static str = "GstRtpUlpFecDec2";
+    type Type = super::RtpUlpFecDec;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .chain_function(|pad, parent, buffer| {
+                Self::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this| this.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Self::catch_panic_pad_function(parent, || false, |this| this.sink_event(pad, event))
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_from_template(&templ)
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            settings: Mutex::default(),
+            decoder: Mutex::default(),
+        }
+    }
+}
+
+impl ObjectImpl for RtpUlpFecDec {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("pt")
+                    .nick("Payload Type")
+                    .blurb("Payload type of the FEC packets")
+                    .minimum(96)
+                    .maximum(127)
+                    .default_value(DEFAULT_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("red-pt")
+                    .nick("RED Payload Type")
+                    .blurb("Payload type of the RED packets (0 = no RED encapsulation)")
+                    .maximum(127)
+                    .default_value(DEFAULT_RED_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("recovered")
+                    .nick("Recovered")
+                    .blurb("Number of recovered packets so far")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("unrecovered")
+                    .nick("Unrecovered")
+                    .blurb("Number of FEC packets that could not be used for recovery so far")
+                    .read_only()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "pt" => self.settings.lock().unwrap().pt = value.get::<u32>().unwrap() as u8,
+            "red-pt" => self.settings.lock().unwrap().red_pt = value.get::<u32>().unwrap() as u8,
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "pt" => (self.settings.lock().unwrap().pt as u32).to_value(),
+            "red-pt" => (self.settings.lock().unwrap().red_pt as u32).to_value(),
+            "recovered" => self.decoder.lock().unwrap().num_recovered.to_value(),
+            "unrecovered" => self.decoder.lock().unwrap().num_unrecovered.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpUlpFecDec {}
+
+impl ElementImpl for RtpUlpFecDec {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP ULPFEC Decoder",
+                "Codec/Decoder/Network/RTP",
+                "Recovers lost packets from ULPFEC packets (RFC 5109) with optional RED encapsulation (RFC 2198)",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("application/x-rtp").build();
+
+            vec![
+                gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+                gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.decoder.lock().unwrap() = FecDecoder::default();
+        }
+
+        self.parent_change_state(transition)
+    }
+}
@@ -0,0 +1,379 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpulpfecenc2
+ * @see_also: rtpulpfecdec2, rtpsend
+ *
+ * Generates ULPFEC packets as specified in [RFC 5109][rfc-5109] for the RTP packets passing
+ * through, optionally encapsulating all packets in RED as specified in [RFC 2198][rfc-2198].
+ *
+ * ULPFEC packets are sent in the same RTP stream as the media packets. The sequence numbers of
+ * the media packets are therefore rewritten to make room for the FEC packets.
+ *
+ * FEC packets are generated at the end of each frame, i.e. when a packet with the marker bit set
+ * or a new RTP timestamp is seen. The number of FEC packets is configured as percentage of the
+ * number of media packets with the #rtpulpfecenc2:percentage property. Frames that are too small
+ * for a single FEC packet are protected together with the following frames.
+ *
+ * If #rtpulpfecenc2:red-pt is set then both the media and the FEC packets are encapsulated in RED
+ * packets with this payload type, which is required e.g. by WebRTC.
+ *
+ * ``` shell
+ * gst-launch-1.0 videotestsrc ! vp8enc ! rtpvp8pay2 ! rtpulpfecenc2 percentage=20 pt=101 red-pt=102 ! \
+ *     rtpsend name=rtpsend rtpsend.rtp_src_0 ! udpsink port=5004
+ * ```
+ *
+ * [rfc-5109]: https://www.rfc-editor.org/rfc/rfc5109.html
+ * [rfc-2198]: https://www.rfc-editor.org/rfc/rfc2198.html
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{collections::HashMap, sync::Mutex};
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use once_cell::sync::Lazy;
+
+use crate::fec::{
+    common::{rewrite_packet, FecGenerator, FecGroup, MaskType},
+    ulpfec,
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpulpfecenc2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP ULPFEC Encoder"),
+    )
+});
+
+const DEFAULT_PT: u32 = 101;
+const DEFAULT_RED_PT: u32 = 0;
+const DEFAULT_PERCENTAGE: u32 = 0;
+const DEFAULT_MASK_TYPE: MaskType = MaskType::Random;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pt: u8,
+    red_pt: u8,
+    percentage: u32,
+    mask_type: MaskType,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT as u8,
+            red_pt: DEFAULT_RED_PT as u8,
+            percentage: DEFAULT_PERCENTAGE,
+            mask_type: DEFAULT_MASK_TYPE,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stream {
+    generator: FecGenerator,
+    /// Number of FEC packets inserted into the sequence number space so far
+    seqnum_offset: u16,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    streams: HashMap<u32, Stream>,
+    num_packets: u64,
+}
+
+pub struct RtpUlpFecEnc {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl RtpUlpFecEnc {
+    fn sink_chain(
+        &self,
+        _pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (media_buffer, fec_packets) = {
+            let map = buffer.map_readable().map_err(|_| {
+                gst::error!(CAT, imp = self, "Failed to map input buffer");
+                gst::FlowError::Error
+            })?;
+            let packet = match rtp_types::RtpPacket::parse(&map) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to parse RTP packet: {err:?}");
+                    drop(map);
+                    return self.srcpad.push(buffer);
+                }
+            };
+            let ssrc = packet.ssrc();
+
+            let mut state = self.state.lock().unwrap();
+            let stream = state.streams.entry(ssrc).or_default();
+
+            let seqnum = packet.sequence_number().wrapping_add(stream.seqnum_offset);
+            let media = if seqnum != packet.sequence_number() {
+                rewrite_packet(&packet, packet.payload_type(), seqnum, &[packet.payload()])
+            } else {
+                map.to_vec()
+            };
+
+            let groups = stream.generator.push(
+                media.clone(),
+                settings.percentage,
+                settings.mask_type,
+                ulpfec::MAX_PROTECTED_PACKETS,
+            );
+            stream.seqnum_offset = stream.seqnum_offset.wrapping_add(groups.len() as u16);
+
+            let media_buffer = if settings.red_pt != 0 {
+                let media = rtp_types::RtpPacket::parse(&media).unwrap();
+                Some(ulpfec::red_encapsulate(&media, settings.red_pt))
+            } else if seqnum != packet.sequence_number() {
+                Some(media)
+            } else {
+                None
+            };
+
+            let mut fec_packets = Vec::with_capacity(groups.len());
+            for (i, group) in groups.into_iter().enumerate() {
+                let fec_seqnum = seqnum.wrapping_add(1 + i as u16);
+
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Generating FEC packet {fec_seqnum} for SSRC {ssrc:08x} protecting {:?}",
+                    FecGroup::seqnums(group.seqnum_base, group.mask).collect::<Vec<_>>()
+                );
+
+                let payload = ulpfec::write_payload(&group);
+                let fec = rtp_types::RtpPacketBuilder::new()
+                    .payload_type(settings.pt)
+                    .ssrc(ssrc)
+                    .sequence_number(fec_seqnum)
+                    .timestamp(group.timestamp)
+                    .payload(payload.as_slice())
+                    .write_vec()
+                    .unwrap();
+                let fec = if settings.red_pt != 0 {
+                    let fec = rtp_types::RtpPacket::parse(&fec).unwrap();
+                    ulpfec::red_encapsulate(&fec, settings.red_pt)
+                } else {
+                    fec
+                };
+
+                let mut fec_buffer = gst::Buffer::from_mut_slice(fec);
+                {
+                    let fec_buffer = fec_buffer.get_mut().unwrap();
+                    fec_buffer.set_pts(buffer.pts());
+                    fec_buffer.set_dts(buffer.dts());
+                }
+                fec_packets.push(fec_buffer);
+            }
+            state.num_packets += fec_packets.len() as u64;
+
+            let media_buffer = media_buffer.map(|data| {
+                let mut media_buffer = gst::Buffer::from_mut_slice(data);
+                {
+                    let media_buffer = media_buffer.get_mut().unwrap();
+                    media_buffer.set_pts(buffer.pts());
+                    media_buffer.set_dts(buffer.dts());
+                    media_buffer.set_flags(buffer.flags());
+                }
+                media_buffer
+            });
+
+            (media_buffer, fec_packets)
+        };
+
+        self.srcpad.push(media_buffer.unwrap_or(buffer))?;
+        for fec_buffer in fec_packets {
+            self.srcpad.push(fec_buffer)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        if let gst::EventView::FlushStop(_) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            for stream in state.streams.values_mut() {
+                stream.generator.reset();
+            }
+        }
+
+        gst::Pad::event_default(pad, Some(&*self.obj()), event)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpUlpFecEnc {
+    const NAME: &'static str = "GstRtpUlpFecEnc2";
+    type Type = super::RtpUlpFecEnc;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .chain_function(|pad, parent, buffer| {
+                Self::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this| this.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                Self::catch_panic_pad_function(parent, || false, |this| this.sink_event(pad, event))
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_from_template(&templ)
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            settings: Mutex::default(),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl ObjectImpl for RtpUlpFecEnc {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("pt")
+                    .nick("Payload Type")
+                    .blurb("Payload type of the FEC packets")
+                    .minimum(96)
+                    .maximum(127)
+                    .default_value(DEFAULT_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("red-pt")
+                    .nick("RED Payload Type")
+                    .blurb("Payload type of the RED packets (0 = no RED encapsulation)")
+                    .maximum(127)
+                    .default_value(DEFAULT_RED_PT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("percentage")
+                    .nick("Percentage")
+                    .blurb("Number of FEC packets in percent of the number of media packets")
+                    .maximum(100)
+                    .default_value(DEFAULT_PERCENTAGE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("mask-type", DEFAULT_MASK_TYPE)
+                    .nick("Mask Type")
+                    .blurb("Which media packets are protected together by a FEC packet")
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt64::builder("num-packets")
+                    .nick("Number of FEC Packets")
+                    .blurb("Number of FEC packets generated so far")
+                    .read_only()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pt" => settings.pt = value.get::<u32>().unwrap() as u8,
+            "red-pt" => settings.red_pt = value.get::<u32>().unwrap() as u8,
+            "percentage" => settings.percentage = value.get().unwrap(),
+            "mask-type" => settings.mask_type = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "pt" => (settings.pt as u32).to_value(),
+            "red-pt" => (settings.red_pt as u32).to_value(),
+            "percentage" => settings.percentage.to_value(),
+            "mask-type" => settings.mask_type.to_value(),
+            "num-packets" => self.state.lock().unwrap().num_packets.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpUlpFecEnc {}
+
+impl ElementImpl for RtpUlpFecEnc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP ULPFEC Encoder",
+                "Codec/Encoder/Network/RTP",
+                "Generates ULPFEC packets (RFC 5109) with optional RED encapsulation (RFC 2198)",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("application/x-rtp").build();
+
+            vec![
+                gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+                gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap(),
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        self.parent_change_state(transition)
+    }
+}
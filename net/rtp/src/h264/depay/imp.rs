@@ -0,0 +1,484 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph264depay2
+ * @see_also: rtph264pay2, x264enc, avdec_h264
+ *
+ * Depayload an H.264 video stream from RTP packets as per [RFC 6184][rfc-6184].
+ *
+ * Single NAL unit, STAP-A and FU-A packets are supported. The interleaved packetization mode is
+ * not supported.
+ *
+ * Parameter sets from the `sprop-parameter-sets` field of the caps are inserted in-band before
+ * the first IDR frame if the stream does not contain them already, or are placed into the
+ * `codec_data` if downstream requires the `avc` stream format.
+ *
+ * [rfc-6184]: https://www.rfc-editor.org/rfc/rfc6184.html
+ *
+ * ## Example pipeline
+ *
+ * ```shell
+ * gst-launch-1.0 udpsrc address=127.0.0.1 port=5004 caps='application/x-rtp,media=video,clock-rate=90000,encoding-name=H264' ! rtpjitterbuffer latency=100 ! rtph264depay2 ! decodebin3 ! videoconvertscale ! autovideosink
+ * ```
+ *
+ * This will depayload and decode an incoming RTP H.264 video stream. You can use the
+ * #rtph264pay2 and #x264enc elements to create such an RTP stream.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{mem, ops::RangeInclusive, sync::Mutex};
+
+use atomic_refcell::AtomicRefCell;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basedepay::{PacketToBufferRelation, RtpBaseDepay2Ext},
+    h264::{
+        create_avcc, nal_type, store_parameter_set, NAL_FU_A, NAL_PPS, NAL_SLICE_IDR, NAL_SPS,
+        NAL_STAP_A,
+    },
+    h26x::{parse_sprop, write_nals, ParameterSets, StreamFormat},
+};
+
+#[derive(Clone, Default)]
+struct Settings {
+    request_keyframe: bool,
+    wait_for_keyframe: bool,
+}
+
+#[derive(Default)]
+struct State {
+    /// Output stream format, either byte-stream or avc with 4 byte NAL unit lengths.
+    output_format: StreamFormat,
+
+    /// SPS and PPS from the caps and in-band.
+    parameter_sets: ParameterSets,
+    /// Set if the parameter sets have to be inserted in-band before the next IDR frame.
+    insert_parameter_sets: bool,
+
+    /// Set once the first keyframe was output.
+    seen_keyframe: bool,
+
+    /// NAL units of the current access unit.
+    pending_nals: Vec<Vec<u8>>,
+    /// Extended seqnums of the packets of the current access unit.
+    pending_ext_seqnums: Option<RangeInclusive<u64>>,
+    /// Extended RTP timestamp of the current access unit.
+    pending_ext_timestamp: Option<u64>,
+
+    /// Currently reassembled NAL unit from FU-A packets.
+    pending_fu: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct RtpH264Depay {
+    state: AtomicRefCell<State>,
+    settings: Mutex<Settings>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph264depay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.264 Depayloader"),
+    )
+});
+
+impl RtpH264Depay {
+    fn reset(&self, state: &mut State) {
+        gst::debug!(CAT, imp = self, "resetting state");
+
+        state.seen_keyframe = false;
+        state.pending_nals.clear();
+        state.pending_ext_seqnums = None;
+        state.pending_ext_timestamp = None;
+        state.pending_fu = None;
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH264Depay {
+    const NAME: &'static str = "GstRtpH264Depay2";
+    type Type = super::RtpH264Depay;
+    type ParentType = crate::basedepay::RtpBaseDepay2;
+}
+
+impl ObjectImpl for RtpH264Depay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::builder("request-keyframe")
+                    .nick("Request Keyframe")
+                    .blurb("Request new keyframe when packet loss is detected")
+                    .default_value(Settings::default().request_keyframe)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("wait-for-keyframe")
+                    .nick("Wait For Keyframe")
+                    .blurb("Wait for the next keyframe after packet loss")
+                    .default_value(Settings::default().wait_for_keyframe)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "request-keyframe" => {
+                self.settings.lock().unwrap().request_keyframe = value.get().unwrap();
+            }
+            "wait-for-keyframe" => {
+                self.settings.lock().unwrap().wait_for_keyframe = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "request-keyframe" => self.settings.lock().unwrap().request_keyframe.to_value(),
+            "wait-for-keyframe" => self.settings.lock().unwrap().wait_for_keyframe.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH264Depay {}
+
+impl ElementImpl for RtpH264Depay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.264 Depayloader",
+                "Codec/Depayloader/Network/RTP",
+                "Depayload H.264 from RTP packets",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H264")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h264")
+                    .field("stream-format", gst::List::new(["byte-stream", "avc"]))
+                    .field("alignment", "au")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basedepay::RtpBaseDepay2Impl for RtpH264Depay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        let s = caps.structure(0).unwrap();
+
+        let mut state = self.state.borrow_mut();
+        self.reset(&mut state);
+
+        state.parameter_sets = ParameterSets::default();
+        if let Ok(sprop) = s.get::<&str>("sprop-parameter-sets") {
+            for nal in parse_sprop(sprop) {
+                if let Err(err) = store_parameter_set(&mut state.parameter_sets, &nal) {
+                    gst::warning!(CAT, imp = self, "Invalid parameter set in caps: {err}");
+                }
+            }
+        }
+
+        // Output byte-stream unless downstream only accepts avc
+        let src_pad = self.obj().src_pad().clone();
+        let peer_caps = src_pad.peer_query_caps(Some(&src_pad.pad_template_caps()));
+        let byte_stream_caps = gst::Caps::builder("video/x-h264")
+            .field("stream-format", "byte-stream")
+            .field("alignment", "au")
+            .build();
+        if !peer_caps.is_empty() && !peer_caps.can_intersect(&byte_stream_caps) {
+            state.output_format = StreamFormat::LengthPrefixed(4);
+            state.insert_parameter_sets = false;
+        } else {
+            state.output_format = StreamFormat::ByteStream;
+            state.insert_parameter_sets = !state.parameter_sets.is_empty();
+        }
+
+        self.update_src_caps(&state);
+
+        true
+    }
+
+    fn drain(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.borrow_mut();
+
+        self.finish_access_unit(&mut state, &settings)
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        self.reset(&mut state);
+    }
+
+    fn handle_packet(
+        &self,
+        packet: &crate::basedepay::Packet,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "Handling RTP packet {packet:?}");
+        let mut state = self.state.borrow_mut();
+
+        // A new RTP timestamp starts a new access unit, even if the marker bit of the previous
+        // packet was not set.
+        if state
+            .pending_ext_timestamp
+            .is_some_and(|ext_timestamp| ext_timestamp != packet.ext_timestamp())
+        {
+            gst::trace!(CAT, imp = self, "Timestamp changed, finishing access unit");
+            self.finish_access_unit(&mut state, &settings)?;
+        }
+
+        if packet.discont() && state.pending_fu.is_some() {
+            gst::warning!(CAT, imp = self, "Lost fragments of NAL unit");
+            state.pending_fu = None;
+            self.request_keyframe(&settings);
+        }
+
+        if let Err(err) = self.depayload(&mut state, packet.payload()) {
+            gst::warning!(CAT, imp = self, "Invalid H.264 RTP packet: {err}");
+            self.reset(&mut state);
+            self.obj().drop_packets(..=packet.ext_seqnum());
+            self.request_keyframe(&settings);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        state.pending_ext_seqnums = Some(match state.pending_ext_seqnums {
+            Some(ref ext_seqnums) => *ext_seqnums.start()..=packet.ext_seqnum(),
+            None => packet.ext_seqnum()..=packet.ext_seqnum(),
+        });
+        state.pending_ext_timestamp = Some(packet.ext_timestamp());
+
+        // The marker bit is set for the last packet of an access unit.
+        if packet.marker_bit() {
+            self.finish_access_unit(&mut state, &settings)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+impl RtpH264Depay {
+    fn update_src_caps(&self, state: &State) {
+        let mut caps_builder = gst::Caps::builder("video/x-h264").field("alignment", "au");
+
+        if state.output_format == StreamFormat::ByteStream {
+            caps_builder = caps_builder.field("stream-format", "byte-stream");
+        } else {
+            caps_builder = caps_builder.field("stream-format", "avc");
+            if let Some(avcc) = create_avcc(&state.parameter_sets) {
+                caps_builder = caps_builder.field("codec_data", gst::Buffer::from_mut_slice(avcc));
+            }
+        }
+
+        self.obj().set_src_caps(&caps_builder.build());
+    }
+
+    fn request_keyframe(&self, settings: &Settings) {
+        if settings.request_keyframe {
+            gst::debug!(CAT, imp = self, "Requesting keyframe from upstream");
+            let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build();
+            let _ = self.obj().sink_pad().push_event(event);
+        }
+    }
+
+    /// Extracts the NAL units from the payload and adds them to the current access unit.
+    fn depayload(&self, state: &mut State, payload: &[u8]) -> Result<(), &'static str> {
+        let Some(&nal_header) = payload.first() else {
+            return Err("empty payload");
+        };
+
+        match nal_header & 0x1f {
+            0 | 30 | 31 => return Err("reserved NAL unit type"),
+            1..=23 => {
+                gst::trace!(CAT, imp = self, "Single NAL unit packet");
+                state.pending_nals.push(payload.to_vec());
+            }
+            NAL_STAP_A => {
+                gst::trace!(CAT, imp = self, "STAP-A packet");
+
+                let mut data = &payload[1..];
+                while !data.is_empty() {
+                    if data.len() < 2 {
+                        return Err("truncated STAP-A NAL unit size");
+                    }
+                    let size = u16::from_be_bytes([data[0], data[1]]) as usize;
+                    if size == 0 || data.len() < 2 + size {
+                        return Err("invalid STAP-A NAL unit size");
+                    }
+                    state.pending_nals.push(data[2..][..size].to_vec());
+                    data = &data[2 + size..];
+                }
+            }
+            NAL_FU_A => {
+                let Some(&fu_header) = payload.get(1) else {
+                    return Err("truncated FU-A header");
+                };
+                let start = fu_header & 0x80 != 0;
+                let end = fu_header & 0x40 != 0;
+
+                gst::trace!(CAT, imp = self, "FU-A packet, start {start}, end {end}");
+
+                if start {
+                    if state.pending_fu.is_some() {
+                        gst::warning!(CAT, imp = self, "Dropping incomplete NAL unit");
+                    }
+                    let mut nal = Vec::with_capacity(payload.len() - 1);
+                    nal.push((nal_header & 0xe0) | (fu_header & 0x1f));
+                    nal.extend_from_slice(&payload[2..]);
+                    state.pending_fu = Some(nal);
+                } else if let Some(ref mut nal) = state.pending_fu {
+                    nal.extend_from_slice(&payload[2..]);
+                } else {
+                    return Err("missing start of fragmented NAL unit");
+                }
+
+                if end {
+                    let nal = state.pending_fu.take().unwrap();
+                    state.pending_nals.push(nal);
+                }
+            }
+            _ => return Err("unsupported interleaved mode packet"),
+        }
+
+        Ok(())
+    }
+
+    /// Outputs the NAL units of the current access unit as a single buffer.
+    fn finish_access_unit(
+        &self,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let nals = mem::take(&mut state.pending_nals);
+        state.pending_ext_timestamp = None;
+        let Some(ext_seqnums) = state.pending_ext_seqnums.take() else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+
+        if state.pending_fu.take().is_some() {
+            gst::warning!(CAT, imp = self, "Dropping incomplete NAL unit");
+        }
+
+        if nals.is_empty() {
+            self.obj().drop_packets(..=*ext_seqnums.end());
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let is_keyframe = nals.iter().any(|nal| nal_type(nal) == NAL_SLICE_IDR);
+
+        // If necessary wait for a key frame if we never saw one so far and/or request one
+        // from upstream.
+        if !is_keyframe && !state.seen_keyframe {
+            self.request_keyframe(settings);
+
+            if settings.wait_for_keyframe {
+                gst::trace!(CAT, imp = self, "Waiting for keyframe");
+                self.obj().drop_packets(..=*ext_seqnums.end());
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+        state.seen_keyframe |= is_keyframe;
+
+        let mut parameter_sets_changed = false;
+        let mut has_sps = false;
+        for nal in &nals {
+            let nal_type = nal_type(nal);
+            if nal_type == NAL_SPS || nal_type == NAL_PPS {
+                has_sps |= nal_type == NAL_SPS;
+                match store_parameter_set(&mut state.parameter_sets, nal) {
+                    Ok(changed) => parameter_sets_changed |= changed,
+                    Err(err) => gst::warning!(CAT, imp = self, "Invalid parameter set: {err}"),
+                }
+            }
+        }
+
+        // With avc the parameter sets are part of the caps
+        if parameter_sets_changed && state.output_format != StreamFormat::ByteStream {
+            self.update_src_caps(state);
+        }
+
+        let mut data = Vec::with_capacity(nals.iter().map(|nal| 4 + nal.len()).sum::<usize>());
+        if is_keyframe && state.insert_parameter_sets {
+            if !has_sps {
+                gst::trace!(CAT, imp = self, "Inserting parameter sets");
+                write_nals(state.output_format, state.parameter_sets.iter(), &mut data);
+            }
+            state.insert_parameter_sets = false;
+        }
+        write_nals(
+            state.output_format,
+            nals.iter().map(Vec::as_slice),
+            &mut data,
+        );
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        if !is_keyframe {
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_flags(gst::BufferFlags::DELTA_UNIT);
+            gst::trace!(CAT, imp = self, "Finishing delta-frame");
+        } else {
+            gst::trace!(CAT, imp = self, "Finishing keyframe");
+        }
+
+        self.obj()
+            .queue_buffer(PacketToBufferRelation::Seqnums(ext_seqnums), buffer)
+    }
+}
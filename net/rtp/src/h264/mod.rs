@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use bitstream_io::BitRead as _;
+use std::io;
+
+use crate::h26x::{invalid_data, rbsp_reader, read_ue, ParameterSets};
+
+pub mod depay;
+pub mod pay;
+
+#[cfg(test)]
+mod tests;
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_SLICE_IDR: u8 = 5;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_STAP_A: u8 = 24;
+pub const NAL_FU_A: u8 = 28;
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    nal[0] & 0x1f
+}
+
+/// Returns `true` for NAL units that contain slice data.
+pub fn is_vcl(nal_type: u8) -> bool {
+    (NAL_SLICE..=NAL_SLICE_IDR).contains(&nal_type)
+}
+
+/// Stores `nal` in `parameter_sets` if it is an SPS or PPS and returns `true` if it was not known
+/// before.
+pub fn store_parameter_set(parameter_sets: &mut ParameterSets, nal: &[u8]) -> io::Result<bool> {
+    let nal_type = nal_type(nal);
+
+    let id = match nal_type {
+        NAL_SPS => {
+            let mut r = rbsp_reader(nal, 1)?;
+            // profile_idc, constraint_set flags, level_idc
+            r.skip(24)?;
+            read_ue(&mut r)?
+        }
+        NAL_PPS => {
+            let mut r = rbsp_reader(nal, 1)?;
+            read_ue(&mut r)?
+        }
+        _ => return Ok(false),
+    };
+
+    Ok(parameter_sets.insert(nal_type, id, nal))
+}
+
+/// Parses an `AVCDecoderConfigurationRecord` and returns the NAL unit length size and the
+/// parameter sets from it.
+pub fn parse_avcc(codec_data: &[u8]) -> io::Result<(usize, ParameterSets)> {
+    if codec_data.len() < 7 || codec_data[0] != 1 {
+        return Err(invalid_data("invalid avcC"));
+    }
+
+    let nal_length_size = (codec_data[4] & 0b11) as usize + 1;
+    if nal_length_size == 3 {
+        return Err(invalid_data("invalid NAL unit length size"));
+    }
+
+    let mut parameter_sets = ParameterSets::default();
+    let mut data = &codec_data[5..];
+    for count_mask in [0x1f, 0xff] {
+        let Some((&count, rest)) = data.split_first() else {
+            return Err(invalid_data("truncated avcC"));
+        };
+        data = rest;
+
+        for _ in 0..(count & count_mask) {
+            if data.len() < 2 {
+                return Err(invalid_data("truncated avcC"));
+            }
+            let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            if len == 0 || data.len() < 2 + len {
+                return Err(invalid_data("truncated avcC"));
+            }
+            store_parameter_set(&mut parameter_sets, &data[2..][..len])?;
+            data = &data[2 + len..];
+        }
+    }
+
+    Ok((nal_length_size, parameter_sets))
+}
+
+/// Creates an `AVCDecoderConfigurationRecord` with 4 byte NAL unit lengths from the parameter
+/// sets.
+pub fn create_avcc(parameter_sets: &ParameterSets) -> Option<Vec<u8>> {
+    let sps = parameter_sets.of_type(NAL_SPS).collect::<Vec<_>>();
+    let pps = parameter_sets.of_type(NAL_PPS).collect::<Vec<_>>();
+    let first_sps = sps.first()?;
+    if first_sps.len() < 4 || pps.is_empty() {
+        return None;
+    }
+
+    let mut avcc = vec![
+        1,
+        // profile_idc, profile_compatibility, level_idc
+        first_sps[1],
+        first_sps[2],
+        first_sps[3],
+        // reserved and 4 byte NAL unit lengths
+        0xff,
+        0xe0 | sps.len() as u8,
+    ];
+    for sps in &sps {
+        avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(sps);
+    }
+    avcc.push(pps.len() as u8);
+    for pps in &pps {
+        avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(pps);
+    }
+
+    Some(avcc)
+}
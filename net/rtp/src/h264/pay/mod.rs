@@ -0,0 +1,48 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+
+pub mod imp;
+
+glib::wrapper! {
+    pub struct RtpH264Pay(ObjectSubclass<imp::RtpH264Pay>)
+        @extends crate::basepay::RtpBasePay2, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    #[cfg(feature = "doc")]
+    {
+        AggregateMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    }
+
+    gst::Element::register(
+        Some(plugin),
+        "rtph264pay2",
+        gst::Rank::MARGINAL,
+        RtpH264Pay::static_type(),
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, glib::Enum, Default)]
+#[enum_type(name = "GstRtpH264Pay2AggregateMode")]
+#[repr(i32)]
+pub enum AggregateMode {
+    #[enum_value(name = "Do not aggregate NAL units", nick = "none")]
+    None,
+    #[default]
+    #[enum_value(
+        name = "Aggregate NAL units until a slice is included",
+        nick = "zero-latency"
+    )]
+    ZeroLatency,
+    #[enum_value(
+        name = "Aggregate as many NAL units of an access unit as possible",
+        nick = "max"
+    )]
+    Max,
+}
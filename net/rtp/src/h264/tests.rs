@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::tests::{run_test_pipeline, ExpectedBuffer, ExpectedPacket, Source};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtph264 test");
+    });
+}
+
+const SPS: [u8; 7] = [0x67, 0x42, 0xc0, 0x1e, 0x8c, 0x8d, 0x40];
+const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+/// Creates a NAL unit with the given header byte and size.
+fn nal(header: u8, size: usize) -> Vec<u8> {
+    let mut nal = vec![0xaa; size];
+    nal[0] = header;
+    nal
+}
+
+fn idr(size: usize) -> Vec<u8> {
+    nal(0x65, size)
+}
+
+fn non_idr(size: usize) -> Vec<u8> {
+    nal(0x41, size)
+}
+
+fn buffer(pts_ms: u64, nals: &[&[u8]], length_prefixed: bool) -> gst::Buffer {
+    let mut data = Vec::new();
+    for nal in nals {
+        if length_prefixed {
+            data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        } else {
+            data.extend_from_slice(&[0, 0, 0, 1]);
+        }
+        data.extend_from_slice(nal);
+    }
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    buffer
+        .get_mut()
+        .unwrap()
+        .set_pts(gst::ClockTime::from_mseconds(pts_ms));
+    buffer
+}
+
+#[test]
+fn test_h264_byte_stream() {
+    init();
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .build();
+    let buffers = vec![
+        buffer(0, &[&SPS, &PPS, &idr(3000)], false),
+        buffer(40, &[&non_idr(500)], false),
+        // Parameter sets are inserted by the payloader
+        buffer(80, &[&idr(1000)], false),
+        buffer(120, &[&non_idr(100)], false),
+    ];
+
+    let pay = "rtph264pay2 config-interval=-1";
+    let depay = "rtph264depay2";
+
+    let expected_pay = vec![
+        vec![
+            // SPS and PPS aggregated into a STAP-A packet
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(28)
+                .build(),
+            // IDR slice fragmented into three FU-A packets
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(241)
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(512)
+            .build()],
+        // SPS, PPS and IDR slice aggregated into a STAP-A packet
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(1030)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(120))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(10_800)
+            .marker_bit(true)
+            .size(112)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        // One buffer per access unit
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(3023)
+            .flags(gst::BufferFlags::DISCONT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(504)
+            .flags(gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(1023)
+            .flags(gst::BufferFlags::empty())
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(120))
+            .size(104)
+            .flags(gst::BufferFlags::DELTA_UNIT)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps, buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h264_avc_no_aggregation() {
+    init();
+
+    let mut codec_data = vec![1, 0x42, 0xc0, 0x1e, 0xff, 0xe1];
+    codec_data.extend_from_slice(&(SPS.len() as u16).to_be_bytes());
+    codec_data.extend_from_slice(&SPS);
+    codec_data.push(1);
+    codec_data.extend_from_slice(&(PPS.len() as u16).to_be_bytes());
+    codec_data.extend_from_slice(&PPS);
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field("codec_data", gst::Buffer::from_mut_slice(codec_data))
+        .build();
+    let buffers = vec![
+        buffer(0, &[&idr(3000)], true),
+        buffer(40, &[&non_idr(500)], true),
+    ];
+
+    let pay = "rtph264pay2 config-interval=-1 aggregate-mode=none";
+    let depay = "rtph264depay2 ! video/x-h264,stream-format=avc";
+
+    let expected_pay = vec![
+        vec![
+            // SPS and PPS from the codec_data are sent as single NAL unit packets
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(19)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(16)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(241)
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(512)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(3023)
+            .flags(gst::BufferFlags::DISCONT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(504)
+            .flags(gst::BufferFlags::DELTA_UNIT)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps, buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
@@ -0,0 +1,464 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph265depay2
+ * @see_also: rtph265pay2, x265enc, avdec_h265
+ *
+ * Depayload an H.265 video stream from RTP packets as per [RFC 7798][rfc-7798].
+ *
+ * Single NAL unit, aggregation and fragmentation unit packets are supported. Streams with
+ * decoding order numbers, i.e. with `sprop-max-don-diff` greater than zero, are not supported.
+ *
+ * Parameter sets from the `sprop-vps`, `sprop-sps` and `sprop-pps` fields of the caps are
+ * inserted in-band before the first IRAP frame if the stream does not contain them already.
+ *
+ * [rfc-7798]: https://www.rfc-editor.org/rfc/rfc7798.html
+ *
+ * ## Example pipeline
+ *
+ * ```shell
+ * gst-launch-1.0 udpsrc address=127.0.0.1 port=5004 caps='application/x-rtp,media=video,clock-rate=90000,encoding-name=H265' ! rtpjitterbuffer latency=100 ! rtph265depay2 ! decodebin3 ! videoconvertscale ! autovideosink
+ * ```
+ *
+ * This will depayload and decode an incoming RTP H.265 video stream. You can use the
+ * #rtph265pay2 and #x265enc elements to create such an RTP stream.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{mem, ops::RangeInclusive, sync::Mutex};
+
+use atomic_refcell::AtomicRefCell;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basedepay::{PacketToBufferRelation, RtpBaseDepay2Ext},
+    h265::{is_irap, nal_type, store_parameter_set, NAL_AP, NAL_FU, NAL_PPS, NAL_SPS, NAL_VPS},
+    h26x::{parse_sprop, write_nals, ParameterSets, StreamFormat},
+};
+
+#[derive(Clone, Default)]
+struct Settings {
+    request_keyframe: bool,
+    wait_for_keyframe: bool,
+}
+
+#[derive(Default)]
+struct State {
+    /// VPS, SPS and PPS from the caps and in-band.
+    parameter_sets: ParameterSets,
+    /// Set if the parameter sets have to be inserted in-band before the next IRAP frame.
+    insert_parameter_sets: bool,
+
+    /// Set once the first keyframe was output.
+    seen_keyframe: bool,
+
+    /// NAL units of the current access unit.
+    pending_nals: Vec<Vec<u8>>,
+    /// Extended seqnums of the packets of the current access unit.
+    pending_ext_seqnums: Option<RangeInclusive<u64>>,
+    /// Extended RTP timestamp of the current access unit.
+    pending_ext_timestamp: Option<u64>,
+
+    /// Currently reassembled NAL unit from fragmentation units.
+    pending_fu: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct RtpH265Depay {
+    state: AtomicRefCell<State>,
+    settings: Mutex<Settings>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph265depay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.265 Depayloader"),
+    )
+});
+
+impl RtpH265Depay {
+    fn reset(&self, state: &mut State) {
+        gst::debug!(CAT, imp = self, "resetting state");
+
+        state.seen_keyframe = false;
+        state.pending_nals.clear();
+        state.pending_ext_seqnums = None;
+        state.pending_ext_timestamp = None;
+        state.pending_fu = None;
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH265Depay {
+    const NAME: &'static str = "GstRtpH265Depay2";
+    type Type = super::RtpH265Depay;
+    type ParentType = crate::basedepay::RtpBaseDepay2;
+}
+
+impl ObjectImpl for RtpH265Depay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::builder("request-keyframe")
+                    .nick("Request Keyframe")
+                    .blurb("Request new keyframe when packet loss is detected")
+                    .default_value(Settings::default().request_keyframe)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("wait-for-keyframe")
+                    .nick("Wait For Keyframe")
+                    .blurb("Wait for the next keyframe after packet loss")
+                    .default_value(Settings::default().wait_for_keyframe)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "request-keyframe" => {
+                self.settings.lock().unwrap().request_keyframe = value.get().unwrap();
+            }
+            "wait-for-keyframe" => {
+                self.settings.lock().unwrap().wait_for_keyframe = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "request-keyframe" => self.settings.lock().unwrap().request_keyframe.to_value(),
+            "wait-for-keyframe" => self.settings.lock().unwrap().wait_for_keyframe.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH265Depay {}
+
+impl ElementImpl for RtpH265Depay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.265 Depayloader",
+                "Codec/Depayloader/Network/RTP",
+                "Depayload H.265 from RTP packets",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H265")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h265")
+                    .field("stream-format", "byte-stream")
+                    .field("alignment", "au")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basedepay::RtpBaseDepay2Impl for RtpH265Depay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        let s = caps.structure(0).unwrap();
+
+        // Decoding order numbers are only needed for interleaving, which is not supported
+        let max_don_diff = s
+            .get::<&str>("sprop-max-don-diff")
+            .ok()
+            .and_then(|max_don_diff| max_don_diff.parse::<u32>().ok())
+            .unwrap_or(0);
+        if max_don_diff > 0 {
+            gst::error!(CAT, imp = self, "Decoding order numbers not supported");
+            return false;
+        }
+
+        let mut state = self.state.borrow_mut();
+        self.reset(&mut state);
+
+        state.parameter_sets = ParameterSets::default();
+        for field in ["sprop-vps", "sprop-sps", "sprop-pps"] {
+            let Ok(sprop) = s.get::<&str>(field) else {
+                continue;
+            };
+            for nal in parse_sprop(sprop) {
+                if nal.len() < 2 {
+                    continue;
+                }
+                if let Err(err) = store_parameter_set(&mut state.parameter_sets, &nal) {
+                    gst::warning!(CAT, imp = self, "Invalid parameter set in caps: {err}");
+                }
+            }
+        }
+        state.insert_parameter_sets = !state.parameter_sets.is_empty();
+
+        self.obj()
+            .set_src_caps(&self.obj().src_pad().pad_template_caps());
+
+        true
+    }
+
+    fn drain(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.borrow_mut();
+
+        self.finish_access_unit(&mut state, &settings)
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        self.reset(&mut state);
+    }
+
+    fn handle_packet(
+        &self,
+        packet: &crate::basedepay::Packet,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "Handling RTP packet {packet:?}");
+        let mut state = self.state.borrow_mut();
+
+        // A new RTP timestamp starts a new access unit, even if the marker bit of the previous
+        // packet was not set.
+        if state
+            .pending_ext_timestamp
+            .is_some_and(|ext_timestamp| ext_timestamp != packet.ext_timestamp())
+        {
+            gst::trace!(CAT, imp = self, "Timestamp changed, finishing access unit");
+            self.finish_access_unit(&mut state, &settings)?;
+        }
+
+        if packet.discont() && state.pending_fu.is_some() {
+            gst::warning!(CAT, imp = self, "Lost fragments of NAL unit");
+            state.pending_fu = None;
+            self.request_keyframe(&settings);
+        }
+
+        if let Err(err) = self.depayload(&mut state, packet.payload()) {
+            gst::warning!(CAT, imp = self, "Invalid H.265 RTP packet: {err}");
+            self.reset(&mut state);
+            self.obj().drop_packets(..=packet.ext_seqnum());
+            self.request_keyframe(&settings);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        state.pending_ext_seqnums = Some(match state.pending_ext_seqnums {
+            Some(ref ext_seqnums) => *ext_seqnums.start()..=packet.ext_seqnum(),
+            None => packet.ext_seqnum()..=packet.ext_seqnum(),
+        });
+        state.pending_ext_timestamp = Some(packet.ext_timestamp());
+
+        // The marker bit is set for the last packet of an access unit.
+        if packet.marker_bit() {
+            self.finish_access_unit(&mut state, &settings)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+impl RtpH265Depay {
+    fn request_keyframe(&self, settings: &Settings) {
+        if settings.request_keyframe {
+            gst::debug!(CAT, imp = self, "Requesting keyframe from upstream");
+            let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build();
+            let _ = self.obj().sink_pad().push_event(event);
+        }
+    }
+
+    /// Extracts the NAL units from the payload and adds them to the current access unit.
+    fn depayload(&self, state: &mut State, payload: &[u8]) -> Result<(), &'static str> {
+        if payload.len() < 2 {
+            return Err("truncated payload header");
+        }
+
+        match nal_type(payload) {
+            ty if ty < NAL_AP => {
+                gst::trace!(CAT, imp = self, "Single NAL unit packet");
+                state.pending_nals.push(payload.to_vec());
+            }
+            NAL_AP => {
+                gst::trace!(CAT, imp = self, "Aggregation packet");
+
+                let mut data = &payload[2..];
+                while !data.is_empty() {
+                    if data.len() < 2 {
+                        return Err("truncated aggregation unit size");
+                    }
+                    let size = u16::from_be_bytes([data[0], data[1]]) as usize;
+                    if size < 2 || data.len() < 2 + size {
+                        return Err("invalid aggregation unit size");
+                    }
+                    state.pending_nals.push(data[2..][..size].to_vec());
+                    data = &data[2 + size..];
+                }
+            }
+            NAL_FU => {
+                let Some(&fu_header) = payload.get(2) else {
+                    return Err("truncated FU header");
+                };
+                let start = fu_header & 0x80 != 0;
+                let end = fu_header & 0x40 != 0;
+
+                gst::trace!(CAT, imp = self, "FU packet, start {start}, end {end}");
+
+                if start {
+                    if state.pending_fu.is_some() {
+                        gst::warning!(CAT, imp = self, "Dropping incomplete NAL unit");
+                    }
+                    let mut nal = Vec::with_capacity(payload.len() - 1);
+                    nal.push((payload[0] & 0x81) | ((fu_header & 0x3f) << 1));
+                    nal.push(payload[1]);
+                    nal.extend_from_slice(&payload[3..]);
+                    state.pending_fu = Some(nal);
+                } else if let Some(ref mut nal) = state.pending_fu {
+                    nal.extend_from_slice(&payload[3..]);
+                } else {
+                    return Err("missing start of fragmented NAL unit");
+                }
+
+                if end {
+                    let nal = state.pending_fu.take().unwrap();
+                    state.pending_nals.push(nal);
+                }
+            }
+            // PACI packets and reserved types
+            _ => return Err("unsupported packet type"),
+        }
+
+        Ok(())
+    }
+
+    /// Outputs the NAL units of the current access unit as a single buffer.
+    fn finish_access_unit(
+        &self,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let nals = mem::take(&mut state.pending_nals);
+        state.pending_ext_timestamp = None;
+        let Some(ext_seqnums) = state.pending_ext_seqnums.take() else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+
+        if state.pending_fu.take().is_some() {
+            gst::warning!(CAT, imp = self, "Dropping incomplete NAL unit");
+        }
+
+        if nals.is_empty() {
+            self.obj().drop_packets(..=*ext_seqnums.end());
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let is_keyframe = nals.iter().any(|nal| is_irap(nal_type(nal)));
+
+        // If necessary wait for a key frame if we never saw one so far and/or request one
+        // from upstream.
+        if !is_keyframe && !state.seen_keyframe {
+            self.request_keyframe(settings);
+
+            if settings.wait_for_keyframe {
+                gst::trace!(CAT, imp = self, "Waiting for keyframe");
+                self.obj().drop_packets(..=*ext_seqnums.end());
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+        state.seen_keyframe |= is_keyframe;
+
+        let mut has_sps = false;
+        for nal in &nals {
+            let nal_type = nal_type(nal);
+            if [NAL_VPS, NAL_SPS, NAL_PPS].contains(&nal_type) {
+                has_sps |= nal_type == NAL_SPS;
+                if let Err(err) = store_parameter_set(&mut state.parameter_sets, nal) {
+                    gst::warning!(CAT, imp = self, "Invalid parameter set: {err}");
+                }
+            }
+        }
+
+        let mut data = Vec::with_capacity(nals.iter().map(|nal| 4 + nal.len()).sum::<usize>());
+        if is_keyframe && state.insert_parameter_sets {
+            if !has_sps {
+                gst::trace!(CAT, imp = self, "Inserting parameter sets");
+                write_nals(
+                    StreamFormat::ByteStream,
+                    state.parameter_sets.iter(),
+                    &mut data,
+                );
+            }
+            state.insert_parameter_sets = false;
+        }
+        write_nals(
+            StreamFormat::ByteStream,
+            nals.iter().map(Vec::as_slice),
+            &mut data,
+        );
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        if !is_keyframe {
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_flags(gst::BufferFlags::DELTA_UNIT);
+            gst::trace!(CAT, imp = self, "Finishing delta-frame");
+        } else {
+            gst::trace!(CAT, imp = self, "Finishing keyframe");
+        }
+
+        self.obj()
+            .queue_buffer(PacketToBufferRelation::Seqnums(ext_seqnums), buffer)
+    }
+}
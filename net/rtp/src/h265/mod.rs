@@ -0,0 +1,127 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use bitstream_io::BitRead as _;
+use std::io;
+
+use crate::h26x::{invalid_data, rbsp_reader, read_ue, ParameterSets};
+
+pub mod depay;
+pub mod pay;
+
+#[cfg(test)]
+mod tests;
+
+pub const NAL_BLA_W_LP: u8 = 16;
+pub const NAL_RSV_IRAP_VCL23: u8 = 23;
+pub const NAL_VPS: u8 = 32;
+pub const NAL_SPS: u8 = 33;
+pub const NAL_PPS: u8 = 34;
+pub const NAL_AP: u8 = 48;
+pub const NAL_FU: u8 = 49;
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    (nal[0] >> 1) & 0x3f
+}
+
+/// Returns `true` for NAL units that contain slice data.
+pub fn is_vcl(nal_type: u8) -> bool {
+    nal_type < 32
+}
+
+/// Returns `true` for the NAL units of intra random access point pictures.
+pub fn is_irap(nal_type: u8) -> bool {
+    (NAL_BLA_W_LP..=NAL_RSV_IRAP_VCL23).contains(&nal_type)
+}
+
+/// Stores `nal` in `parameter_sets` if it is a VPS, SPS or PPS and returns `true` if it was not
+/// known before.
+pub fn store_parameter_set(parameter_sets: &mut ParameterSets, nal: &[u8]) -> io::Result<bool> {
+    let nal_type = nal_type(nal);
+
+    let id = match nal_type {
+        NAL_VPS => {
+            let mut r = rbsp_reader(nal, 2)?;
+            r.read::<u32>(4)?
+        }
+        NAL_SPS => {
+            let mut r = rbsp_reader(nal, 2)?;
+            // sps_video_parameter_set_id
+            r.skip(4)?;
+            let max_sub_layers_minus1 = r.read::<u8>(3)?;
+            // sps_temporal_id_nesting_flag
+            r.skip(1)?;
+
+            // profile_tier_level(1, sps_max_sub_layers_minus1): general profile and level
+            r.skip(88 + 8)?;
+            let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1 as usize);
+            for _ in 0..max_sub_layers_minus1 {
+                let profile_present = r.read_bit()?;
+                let level_present = r.read_bit()?;
+                sub_layer_flags.push((profile_present, level_present));
+            }
+            if max_sub_layers_minus1 > 0 {
+                // reserved_zero_2bits
+                r.skip(2 * (8 - max_sub_layers_minus1 as u32))?;
+            }
+            for (profile_present, level_present) in sub_layer_flags {
+                if profile_present {
+                    r.skip(88)?;
+                }
+                if level_present {
+                    r.skip(8)?;
+                }
+            }
+
+            read_ue(&mut r)?
+        }
+        NAL_PPS => {
+            let mut r = rbsp_reader(nal, 2)?;
+            read_ue(&mut r)?
+        }
+        _ => return Ok(false),
+    };
+
+    Ok(parameter_sets.insert(nal_type, id, nal))
+}
+
+/// Parses an `HEVCDecoderConfigurationRecord` and returns the NAL unit length size and the
+/// parameter sets from it.
+pub fn parse_hvcc(codec_data: &[u8]) -> io::Result<(usize, ParameterSets)> {
+    if codec_data.len() < 23 || codec_data[0] != 1 {
+        return Err(invalid_data("invalid hvcC"));
+    }
+
+    let nal_length_size = (codec_data[21] & 0b11) as usize + 1;
+    if nal_length_size == 3 {
+        return Err(invalid_data("invalid NAL unit length size"));
+    }
+
+    let mut parameter_sets = ParameterSets::default();
+    let num_arrays = codec_data[22];
+    let mut data = &codec_data[23..];
+    for _ in 0..num_arrays {
+        if data.len() < 3 {
+            return Err(invalid_data("truncated hvcC"));
+        }
+        let num_nals = u16::from_be_bytes([data[1], data[2]]);
+        data = &data[3..];
+
+        for _ in 0..num_nals {
+            if data.len() < 2 {
+                return Err(invalid_data("truncated hvcC"));
+            }
+            let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            if len < 2 || data.len() < 2 + len {
+                return Err(invalid_data("truncated hvcC"));
+            }
+            store_parameter_set(&mut parameter_sets, &data[2..][..len])?;
+            data = &data[2 + len..];
+        }
+    }
+
+    Ok((nal_length_size, parameter_sets))
+}
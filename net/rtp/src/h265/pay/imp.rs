@@ -0,0 +1,579 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph265pay2
+ * @see_also: rtph265depay2, x265enc, avdec_h265
+ *
+ * Payload an H.265 video stream into RTP packets as per [RFC 7798][rfc-7798].
+ *
+ * NAL units that fit into a single packet are sent as is or aggregated into aggregation packets,
+ * depending on the `aggregate-mode` property. Bigger NAL units are fragmented into fragmentation
+ * units.
+ *
+ * The VPS, SPS and PPS are signalled via the `sprop-vps`, `sprop-sps` and `sprop-pps` fields of
+ * the caps and can additionally be inserted in-band before IRAP frames via the `config-interval`
+ * property.
+ *
+ * [rfc-7798]: https://www.rfc-editor.org/rfc/rfc7798.html
+ *
+ * ## Example pipeline
+ *
+ * |[
+ * gst-launch-1.0 videotestsrc ! video/x-raw,width=1280,height=720,format=I420 ! timeoverlay font-desc=Sans,22 ! x265enc tune=zerolatency ! rtph265pay2 config-interval=-1 ! udpsink host=127.0.0.1 port=5004
+ * ]| This will create and payload an H.265 video stream with a test pattern and
+ * send it out via UDP to localhost port 5004.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use atomic_refcell::AtomicRefCell;
+use gst::{glib, prelude::*, subclass::prelude::*};
+use std::{cmp, mem, sync::Mutex};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basepay::{PacketToBufferRelation, RtpBasePay2Ext},
+    h265::{
+        is_irap, is_vcl, nal_type, parse_hvcc, store_parameter_set, NAL_AP, NAL_FU, NAL_PPS,
+        NAL_SPS, NAL_VPS,
+    },
+    h26x::{ParameterSets, StreamFormat},
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph265pay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.265 Payloader"),
+    )
+});
+
+#[derive(Clone, Default)]
+struct Settings {
+    aggregate_mode: super::AggregateMode,
+    config_interval: i32,
+}
+
+struct PendingNal {
+    id: u64,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    stream_format: StreamFormat,
+    /// Set if upstream provides a complete access unit per buffer.
+    alignment_au: bool,
+
+    /// VPS, SPS and PPS from the caps or in-band.
+    parameter_sets: ParameterSets,
+    /// PTS of the last IRAP frame that was sent together with the parameter sets.
+    last_parameter_sets_pts: Option<gst::ClockTime>,
+
+    /// NAL units that are waiting to be aggregated into an aggregation packet.
+    pending_nals: Vec<PendingNal>,
+    /// PTS of the access unit the pending NAL units belong to.
+    pending_pts: Option<gst::ClockTime>,
+}
+
+#[derive(Default)]
+pub struct RtpH265Pay {
+    settings: Mutex<Settings>,
+    state: AtomicRefCell<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH265Pay {
+    const NAME: &'static str = "GstRtpH265Pay2";
+    type Type = super::RtpH265Pay;
+    type ParentType = crate::basepay::RtpBasePay2;
+}
+
+impl ObjectImpl for RtpH265Pay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::builder::<super::AggregateMode>("aggregate-mode")
+                    .nick("Aggregate Mode")
+                    .blurb("Aggregation of NAL units into aggregation packets")
+                    .default_value(Settings::default().aggregate_mode)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecInt::builder("config-interval")
+                    .nick("Config Interval")
+                    .blurb("Send VPS, SPS and PPS in-band with IRAP frames if at least this many seconds passed since the last time (0 = disabled, -1 = with every IRAP frame)")
+                    .default_value(Settings::default().config_interval)
+                    .minimum(-1)
+                    .maximum(3600)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        &PROPERTIES
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "aggregate-mode" => {
+                self.settings.lock().unwrap().aggregate_mode = value.get().unwrap();
+            }
+            "config-interval" => {
+                self.settings.lock().unwrap().config_interval = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "aggregate-mode" => self.settings.lock().unwrap().aggregate_mode.to_value(),
+            "config-interval" => self.settings.lock().unwrap().config_interval.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH265Pay {}
+
+impl ElementImpl for RtpH265Pay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.265 payloader",
+                "Codec/Payloader/Network/RTP",
+                "Payload H.265 as RTP packets",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h265")
+                    .field(
+                        "stream-format",
+                        gst::List::new(["hvc1", "hev1", "byte-stream"]),
+                    )
+                    .field("alignment", gst::List::new(["au", "nal"]))
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H265")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basepay::RtpBasePay2Impl for RtpH265Pay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        gst::debug!(CAT, imp = self, "received caps {caps:?}");
+
+        let s = caps.structure(0).unwrap();
+        let mut state = self.state.borrow_mut();
+
+        state.alignment_au = s.get::<&str>("alignment").ok() == Some("au");
+        state.last_parameter_sets_pts = None;
+
+        if matches!(s.get::<&str>("stream-format"), Ok("hvc1" | "hev1")) {
+            let Ok(codec_data) = s.get::<gst::Buffer>("codec_data") else {
+                gst::error!(CAT, imp = self, "hvc1/hev1 caps without codec_data");
+                return false;
+            };
+            let Ok(map) = codec_data.map_readable() else {
+                gst::error!(CAT, imp = self, "Failed to map codec_data readable");
+                return false;
+            };
+
+            match parse_hvcc(&map) {
+                Ok((nal_length_size, parameter_sets)) => {
+                    state.stream_format = StreamFormat::LengthPrefixed(nal_length_size);
+                    state.parameter_sets = parameter_sets;
+                }
+                Err(err) => {
+                    gst::error!(CAT, imp = self, "Failed to parse codec_data: {err}");
+                    return false;
+                }
+            }
+        } else {
+            state.stream_format = StreamFormat::ByteStream;
+            state.parameter_sets = ParameterSets::default();
+        }
+
+        self.update_src_caps(&state);
+
+        true
+    }
+
+    fn handle_buffer(
+        &self,
+        buffer: &gst::Buffer,
+        id: u64,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "received buffer of size {}", buffer.size());
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_imp_error!(
+                self,
+                gst::ResourceError::Read,
+                ["Failed to map buffer readable"]
+            );
+
+            gst::FlowError::Error
+        })?;
+
+        let mut nals = match state.stream_format.split(&map) {
+            Ok(nals) => nals,
+            Err(err) => {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Failed to split buffer into NAL units: {err}"
+                );
+                self.obj().drop_buffers(..=id);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        // Every NAL unit has at least the two byte NAL unit header
+        nals.retain(|nal| nal.len() >= 2);
+
+        // NAL units of different access units can't be aggregated
+        let pts = buffer.pts();
+        if !state.pending_nals.is_empty() && state.pending_pts != pts {
+            self.finish_pending_nals(&mut state, true)?;
+        }
+        state.pending_pts = pts;
+
+        let mut parameter_sets_changed = false;
+        let mut has_irap = false;
+        let mut has_vps = false;
+        let mut has_sps = false;
+        let mut has_pps = false;
+        for nal in &nals {
+            match nal_type(nal) {
+                ty if is_irap(ty) => has_irap = true,
+                ps_type @ (NAL_VPS | NAL_SPS | NAL_PPS) => {
+                    has_vps |= ps_type == NAL_VPS;
+                    has_sps |= ps_type == NAL_SPS;
+                    has_pps |= ps_type == NAL_PPS;
+                    match store_parameter_set(&mut state.parameter_sets, nal) {
+                        Ok(changed) => parameter_sets_changed |= changed,
+                        Err(err) => {
+                            gst::warning!(CAT, imp = self, "Failed to parse parameter set: {err}")
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if parameter_sets_changed {
+            gst::debug!(CAT, imp = self, "Parameter sets changed");
+            self.update_src_caps(&state);
+        }
+
+        // Insert the parameter sets in front of IRAP frames if configured and they're not in-band
+        // already.
+        let mut parameter_sets = Vec::new();
+        if has_irap {
+            if has_vps && has_sps && has_pps {
+                state.last_parameter_sets_pts = pts;
+            } else if !state.parameter_sets.is_empty()
+                && match settings.config_interval {
+                    0 => false,
+                    -1 => true,
+                    interval => Option::zip(state.last_parameter_sets_pts, pts).map_or(
+                        true,
+                        |(last_pts, pts)| {
+                            pts.saturating_sub(last_pts)
+                                >= gst::ClockTime::from_seconds(interval as u64)
+                        },
+                    ),
+                }
+            {
+                gst::trace!(CAT, imp = self, "Inserting parameter sets");
+                parameter_sets = state
+                    .parameter_sets
+                    .iter()
+                    .map(<[u8]>::to_vec)
+                    .collect::<Vec<_>>();
+                state.last_parameter_sets_pts = pts;
+            }
+        }
+
+        // The last NAL unit of an access unit gets the marker bit
+        let end_of_au = state.alignment_au || buffer.flags().contains(gst::BufferFlags::MARKER);
+        let num_nals = parameter_sets.len() + nals.len();
+
+        for (idx, nal) in parameter_sets
+            .iter()
+            .map(Vec::as_slice)
+            .chain(nals.iter().copied())
+            .enumerate()
+        {
+            self.packetize_nal(
+                &mut state,
+                settings.aggregate_mode,
+                id,
+                nal,
+                end_of_au && idx == num_nals - 1,
+            )?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn drain(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+        self.finish_pending_nals(&mut state, true)
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        state.pending_nals.clear();
+        state.pending_pts = None;
+    }
+}
+
+impl RtpH265Pay {
+    fn update_src_caps(&self, state: &State) {
+        let mut caps_builder = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90_000i32)
+            .field("encoding-name", "H265");
+
+        for (nal_type, field) in [
+            (NAL_VPS, "sprop-vps"),
+            (NAL_SPS, "sprop-sps"),
+            (NAL_PPS, "sprop-pps"),
+        ] {
+            if let Some(sprop) = state.parameter_sets.to_sprop(nal_type) {
+                caps_builder = caps_builder.field(field, sprop);
+            }
+        }
+
+        self.obj().set_src_caps(&caps_builder.build());
+    }
+
+    fn max_payload_size(&self) -> Result<usize, gst::FlowError> {
+        let max_payload_size = self.obj().max_payload_size() as usize;
+
+        // Payload header and FU header plus at least one byte of data
+        if max_payload_size < 4 {
+            gst::error!(CAT, imp = self, "Too small MTU configured for stream");
+            gst::element_imp_error!(
+                self,
+                gst::LibraryError::Settings,
+                ["Too small MTU configured for stream"]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        Ok(max_payload_size)
+    }
+
+    /// Aggregates the NAL unit with the pending ones, or sends it as single NAL unit packet or
+    /// fragmented into fragmentation units if it's too big.
+    fn packetize_nal(
+        &self,
+        state: &mut State,
+        aggregate_mode: super::AggregateMode,
+        id: u64,
+        nal: &[u8],
+        end_of_au: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let max_payload_size = self.max_payload_size()?;
+        let nal_type = nal_type(nal);
+
+        if aggregate_mode != super::AggregateMode::None {
+            // Payload header plus 2 bytes NAL unit size for each NAL unit
+            let aggregate_size = 2
+                + state
+                    .pending_nals
+                    .iter()
+                    .map(|pending| 2 + pending.data.len())
+                    .sum::<usize>()
+                + 2
+                + nal.len();
+
+            if aggregate_size > max_payload_size {
+                self.finish_pending_nals(state, false)?;
+            }
+
+            if 2 + 2 + nal.len() <= max_payload_size {
+                state.pending_nals.push(PendingNal {
+                    id,
+                    data: nal.to_vec(),
+                });
+
+                if end_of_au
+                    || (aggregate_mode == super::AggregateMode::ZeroLatency && is_vcl(nal_type))
+                {
+                    self.finish_pending_nals(state, end_of_au)?;
+                }
+
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+
+        if nal.len() <= max_payload_size {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Sending NAL unit of type {nal_type} and size {} as single packet",
+                nal.len(),
+            );
+
+            return self.obj().queue_packet(
+                id.into(),
+                rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(end_of_au)
+                    .payload(nal),
+            );
+        }
+
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Fragmenting NAL unit of type {nal_type} and size {}",
+            nal.len(),
+        );
+
+        // Payload header with the F bit, layer id and TID of the NAL unit
+        let payload_header = [(nal[0] & 0x81) | (NAL_FU << 1), nal[1]];
+        let mut data = &nal[2..];
+        let mut start = true;
+        while !data.is_empty() {
+            let fragment_size = cmp::min(max_payload_size - 3, data.len());
+            let end = fragment_size == data.len();
+            let fu_header = ((start as u8) << 7) | ((end as u8) << 6) | nal_type;
+
+            self.obj().queue_packet(
+                id.into(),
+                rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(end_of_au && end)
+                    .payload(&[payload_header[0], payload_header[1], fu_header][..])
+                    .payload(&data[..fragment_size]),
+            )?;
+
+            data = &data[fragment_size..];
+            start = false;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Sends the pending NAL units as single NAL unit packet or aggregation packet.
+    fn finish_pending_nals(
+        &self,
+        state: &mut State,
+        marker: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let pending_nals = mem::take(&mut state.pending_nals);
+        let (Some(first), Some(last)) = (pending_nals.first(), pending_nals.last()) else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+        let ids = first.id..=last.id;
+
+        if let [pending] = pending_nals.as_slice() {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Sending NAL unit of size {} as single packet",
+                pending.data.len(),
+            );
+
+            return self.obj().queue_packet(
+                PacketToBufferRelation::Ids(ids),
+                rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(marker)
+                    .payload(pending.data.as_slice()),
+            );
+        }
+
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Aggregating {} NAL units into aggregation packet",
+            pending_nals.len(),
+        );
+
+        // The F bit is set if it is set for any NAL unit, layer id and TID are the minimum of all
+        // NAL units.
+        let forbidden_bit = pending_nals
+            .iter()
+            .fold(0, |f, pending| f | (pending.data[0] & 0x80));
+        let layer_id = pending_nals
+            .iter()
+            .map(|pending| ((pending.data[0] & 0x01) << 5) | (pending.data[1] >> 3))
+            .min()
+            .unwrap_or(0);
+        let tid = pending_nals
+            .iter()
+            .map(|pending| pending.data[1] & 0x07)
+            .min()
+            .unwrap_or(1);
+
+        let mut payload = Vec::with_capacity(
+            2 + pending_nals
+                .iter()
+                .map(|pending| 2 + pending.data.len())
+                .sum::<usize>(),
+        );
+        payload.push(forbidden_bit | (NAL_AP << 1) | (layer_id >> 5));
+        payload.push((layer_id << 3) | tid);
+        for pending in &pending_nals {
+            payload.extend_from_slice(&(pending.data.len() as u16).to_be_bytes());
+            payload.extend_from_slice(&pending.data);
+        }
+
+        self.obj().queue_packet(
+            PacketToBufferRelation::Ids(ids),
+            rtp_types::RtpPacketBuilder::new()
+                .marker_bit(marker)
+                .payload(payload.as_slice()),
+        )
+    }
+}
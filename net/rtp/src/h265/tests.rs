@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::tests::{run_test_pipeline, ExpectedBuffer, ExpectedPacket, Source};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtph265 test");
+    });
+}
+
+const VPS: [u8; 6] = [0x40, 0x01, 0x0c, 0x01, 0xff, 0xff];
+const SPS: [u8; 16] = [
+    0x42, 0x01, 0x01, 0x01, 0x60, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x5d, 0xa0,
+];
+const PPS: [u8; 3] = [0x44, 0x01, 0xc0];
+
+/// Creates a NAL unit of the given type and size.
+fn nal(nal_type: u8, size: usize) -> Vec<u8> {
+    let mut nal = vec![0xaa; size];
+    nal[0] = nal_type << 1;
+    nal[1] = 0x01;
+    nal
+}
+
+fn idr(size: usize) -> Vec<u8> {
+    // IDR_W_RADL
+    nal(19, size)
+}
+
+fn trail(size: usize) -> Vec<u8> {
+    // TRAIL_R
+    nal(1, size)
+}
+
+fn buffer(pts_ms: u64, nals: &[&[u8]]) -> gst::Buffer {
+    let mut data = Vec::new();
+    for nal in nals {
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(nal);
+    }
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    buffer
+        .get_mut()
+        .unwrap()
+        .set_pts(gst::ClockTime::from_mseconds(pts_ms));
+    buffer
+}
+
+#[test]
+fn test_h265() {
+    init();
+
+    let caps = gst::Caps::builder("video/x-h265")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .build();
+    let buffers = vec![
+        buffer(0, &[&VPS, &SPS, &PPS, &idr(3000)]),
+        buffer(40, &[&trail(500)]),
+        // Parameter sets are inserted by the payloader
+        buffer(80, &[&idr(1000)]),
+    ];
+
+    let pay = "rtph265pay2 config-interval=-1";
+    let depay = "rtph265depay2";
+
+    let expected_pay = vec![
+        vec![
+            // VPS, SPS and PPS aggregated into an aggregation packet
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(45)
+                .build(),
+            // IDR slice fragmented into three fragmentation units
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(243)
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(512)
+            .build()],
+        // VPS, SPS, PPS and IDR slice aggregated into an aggregation packet
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(1047)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        // One buffer per access unit
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(3041)
+            .flags(gst::BufferFlags::DISCONT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(504)
+            .flags(gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(1041)
+            .flags(gst::BufferFlags::empty())
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps, buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Helpers shared between the H.264 and H.265 payloaders and depayloaders for handling NAL units
+//! and parameter sets.
+
+use bitstream_io::{BigEndian, BitRead, BitReader};
+use gst::glib;
+use std::{
+    cmp,
+    collections::BTreeMap,
+    io::{self, Cursor},
+};
+
+/// Stream format of H.264 / H.265 buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFormat {
+    /// Annex B byte-stream with start codes.
+    #[default]
+    ByteStream,
+    /// NAL units prefixed with their length in big-endian of the given number of bytes.
+    LengthPrefixed(usize),
+}
+
+impl StreamFormat {
+    /// Splits `data` into its NAL units.
+    pub fn split<'a>(&self, data: &'a [u8]) -> io::Result<Vec<&'a [u8]>> {
+        match *self {
+            StreamFormat::ByteStream => Ok(ByteStreamIter { data }.collect()),
+            StreamFormat::LengthPrefixed(length_size) => split_length_prefixed(data, length_size),
+        }
+    }
+}
+
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0, 0, 1])
+}
+
+/// Iterator over the NAL units of an Annex B byte-stream, without start codes and trailing zero
+/// bytes.
+struct ByteStreamIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for ByteStreamIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = find_start_code(self.data)? + 3;
+            let rest = &self.data[start..];
+            let end = find_start_code(rest).unwrap_or(rest.len());
+            self.data = &rest[end..];
+
+            // NAL units never end with a zero byte so these belong to the next start code or are
+            // trailing_zero_8bits.
+            let mut nal = &rest[..end];
+            while let [head @ .., 0] = nal {
+                nal = head;
+            }
+
+            if !nal.is_empty() {
+                return Some(nal);
+            }
+        }
+    }
+}
+
+fn split_length_prefixed(mut data: &[u8], length_size: usize) -> io::Result<Vec<&[u8]>> {
+    let mut nals = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < length_size {
+            return Err(invalid_data("truncated NAL unit length"));
+        }
+        let len = data[..length_size]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        data = &data[length_size..];
+        if data.len() < len {
+            return Err(invalid_data("truncated NAL unit"));
+        }
+        if len > 0 {
+            nals.push(&data[..len]);
+        }
+        data = &data[len..];
+    }
+
+    Ok(nals)
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Removes the emulation prevention bytes from the first `max_len` bytes of `nal`.
+pub fn unescape(nal: &[u8], max_len: usize) -> Vec<u8> {
+    let mut res = Vec::with_capacity(cmp::min(nal.len(), max_len));
+    let mut zeros = 0;
+
+    for &b in nal.iter().take(max_len) {
+        if zeros >= 2 && b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        res.push(b);
+    }
+
+    res
+}
+
+/// Creates a bit reader for the RBSP of `nal` after the NAL unit header of `header_len` bytes.
+///
+/// Only the beginning of the NAL unit is considered as this is used for reading the parameter set
+/// ids.
+pub fn rbsp_reader(
+    nal: &[u8],
+    header_len: usize,
+) -> io::Result<BitReader<Cursor<Vec<u8>>, BigEndian>> {
+    if nal.len() <= header_len {
+        return Err(invalid_data("empty NAL unit"));
+    }
+
+    Ok(BitReader::endian(
+        Cursor::new(unescape(&nal[header_len..], 64)),
+        BigEndian,
+    ))
+}
+
+/// Reads an unsigned Exp-Golomb-coded integer.
+pub fn read_ue<R: BitRead>(r: &mut R) -> io::Result<u32> {
+    let mut leading_zeros = 0;
+    while !r.read_bit()? {
+        leading_zeros += 1;
+        if leading_zeros > 31 {
+            return Err(invalid_data("invalid Exp-Golomb code"));
+        }
+    }
+
+    if leading_zeros == 0 {
+        return Ok(0);
+    }
+
+    let value = r.read::<u32>(leading_zeros)?;
+    Ok(((1u64 << leading_zeros) - 1 + value as u64) as u32)
+}
+
+/// Parameter sets of a stream, keyed by their NAL unit type and id.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ParameterSets(BTreeMap<(u8, u32), Vec<u8>>);
+
+impl ParameterSets {
+    /// Stores the parameter set and returns `true` if it was not known before.
+    pub fn insert(&mut self, nal_type: u8, id: u32, nal: &[u8]) -> bool {
+        if self.0.get(&(nal_type, id)).map(Vec::as_slice) == Some(nal) {
+            return false;
+        }
+
+        self.0.insert((nal_type, id), nal.to_vec());
+        true
+    }
+
+    /// Returns all parameter sets of the given type, sorted by their id.
+    pub fn of_type(&self, nal_type: u8) -> impl Iterator<Item = &[u8]> {
+        self.0
+            .range((nal_type, 0)..=(nal_type, u32::MAX))
+            .map(|(_, nal)| nal.as_slice())
+    }
+
+    /// Returns all parameter sets, sorted by their type and id.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.0.values().map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the parameter sets of the given type as base64-encoded comma-separated list as
+    /// used by the `sprop-*` SDP parameters.
+    pub fn to_sprop(&self, nal_type: u8) -> Option<String> {
+        let sprop = self
+            .of_type(nal_type)
+            .map(|nal| glib::base64_encode(nal).to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        (!sprop.is_empty()).then_some(sprop)
+    }
+}
+
+/// Decodes base64-encoded comma-separated NAL units from a `sprop-*` SDP parameter.
+pub fn parse_sprop(sprop: &str) -> Vec<Vec<u8>> {
+    sprop
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(glib::base64_decode)
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+/// Writes `nals` in the given stream format.
+pub fn write_nals<'a>(
+    format: StreamFormat,
+    nals: impl IntoIterator<Item = &'a [u8]>,
+    out: &mut Vec<u8>,
+) {
+    for nal in nals {
+        match format {
+            StreamFormat::ByteStream => out.extend_from_slice(&[0, 0, 0, 1]),
+            StreamFormat::LengthPrefixed(length_size) => {
+                out.extend_from_slice(&(nal.len() as u32).to_be_bytes()[4 - length_size..])
+            }
+        }
+        out.extend_from_slice(nal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_byte_stream() {
+        let data = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 0, 1, 0x65, 0, 0, 3, 1, 4,
+        ];
+
+        let nals = StreamFormat::ByteStream.split(&data).unwrap();
+        assert_eq!(
+            nals,
+            [&[0x67, 1, 2][..], &[0x68, 3], &[0x65, 0, 0, 3, 1, 4]]
+        );
+    }
+
+    #[test]
+    fn test_split_length_prefixed() {
+        let data = [0, 0, 0, 3, 0x67, 1, 2, 0, 0, 0, 2, 0x68, 3];
+
+        let nals = StreamFormat::LengthPrefixed(4).split(&data).unwrap();
+        assert_eq!(nals, [&[0x67, 1, 2][..], &[0x68, 3]]);
+
+        assert!(StreamFormat::LengthPrefixed(4)
+            .split(&data[..data.len() - 1])
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_ue() {
+        // 1, 010, 011, 00100, 0001000
+        let data = [0b1010_0110, 0b0100_0001, 0b0000_0000];
+        let mut r = BitReader::endian(Cursor::new(&data[..]), BigEndian);
+
+        assert_eq!(read_ue(&mut r).unwrap(), 0);
+        assert_eq!(read_ue(&mut r).unwrap(), 1);
+        assert_eq!(read_ue(&mut r).unwrap(), 2);
+        assert_eq!(read_ue(&mut r).unwrap(), 3);
+        assert_eq!(read_ue(&mut r).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(&[0, 0, 3, 1, 0, 0, 3], 16), [0, 0, 1, 0, 0]);
+    }
+}
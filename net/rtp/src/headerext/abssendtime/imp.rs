@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrextabssendtime2
+ *
+ * RTP header extension carrying the absolute send time of each packet as used by WebRTC for
+ * bandwidth estimation, see the [specification][abs-send-time].
+ *
+ * The send time is taken from a `timestamp/x-ntp` reference timestamp meta on the input buffer
+ * if there is one, otherwise the current system time is used. The 6.18 fixed point send time is
+ * wrapping around every 64 seconds.
+ *
+ * On the receiver side a `timestamp/x-abs-send-time` reference timestamp meta with the send time
+ * in the range of 0 to 64 seconds is added to the output buffers.
+ *
+ * [abs-send-time]: http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gst::{glib, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextabssendtime2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Absolute Send Time Header Extension"),
+    )
+});
+
+pub const URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time";
+
+/// Seconds between the NTP and the UNIX epoch.
+const NTP_UNIX_OFFSET: Duration = Duration::from_secs(2_208_988_800);
+
+static NTP_CAPS: Lazy<gst::Caps> = Lazy::new(|| gst::Caps::new_empty_simple("timestamp/x-ntp"));
+static ABS_SEND_TIME_CAPS: Lazy<gst::Caps> =
+    Lazy::new(|| gst::Caps::new_empty_simple("timestamp/x-abs-send-time"));
+
+/// Converts an NTP time to the 24 bit 6.18 fixed point representation.
+pub(crate) fn ntp_time_to_abs_send_time(ntp_time: gst::ClockTime) -> u32 {
+    ((u128::from(ntp_time.nseconds()) << 18) / 1_000_000_000) as u32 & 0x00ff_ffff
+}
+
+/// Converts a 24 bit 6.18 fixed point send time to a time between 0 and 64 seconds.
+pub(crate) fn abs_send_time_to_clock_time(abs_send_time: u32) -> gst::ClockTime {
+    gst::ClockTime::from_nseconds(
+        ((u64::from(abs_send_time & 0x00ff_ffff) * 1_000_000_000) >> 18) as u64,
+    )
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtAbsSendTime {}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtAbsSendTime {
+    const NAME: &'static str = "GstRtpHeaderExtAbsSendTime2";
+    type Type = super::RtpHeaderExtAbsSendTime;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtAbsSendTime {}
+
+impl GstObjectImpl for RtpHeaderExtAbsSendTime {}
+
+impl ElementImpl for RtpHeaderExtAbsSendTime {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Absolute Send Time Header Extension",
+                super::super::KLASS,
+                "Absolute send time RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtAbsSendTime {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+    }
+
+    fn max_size(&self, _input: &gst::BufferRef) -> usize {
+        3
+    }
+
+    fn write(
+        &self,
+        input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        _output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        if output_data.len() < 3 {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+
+        let ntp_time = input
+            .iter_meta::<gst::ReferenceTimestampMeta>()
+            .find(|meta| meta.reference().is_subset(&NTP_CAPS))
+            .map(|meta| meta.timestamp())
+            .unwrap_or_else(|| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    + NTP_UNIX_OFFSET;
+                gst::ClockTime::from_nseconds(now.as_nanos() as u64)
+            });
+
+        let abs_send_time = ntp_time_to_abs_send_time(ntp_time);
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Writing send time {ntp_time} ({abs_send_time:06x})"
+        );
+        output_data[..3].copy_from_slice(&abs_send_time.to_be_bytes()[1..]);
+
+        Ok(3)
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        if input_data.len() < 3 {
+            return Err(gst::loggable_error!(CAT, "Too short extension data"));
+        }
+
+        let abs_send_time = u32::from_be_bytes([0, input_data[0], input_data[1], input_data[2]]);
+        let send_time = abs_send_time_to_clock_time(abs_send_time);
+        gst::trace!(CAT, imp = self, "Read send time {send_time}");
+
+        // Only the send time of the first packet of a buffer is stored
+        if output
+            .iter_meta::<gst::ReferenceTimestampMeta>()
+            .any(|meta| meta.reference().is_subset(&ABS_SEND_TIME_CAPS))
+        {
+            return Ok(());
+        }
+
+        gst::ReferenceTimestampMeta::add(output, &ABS_SEND_TIME_CAPS, send_time, None);
+
+        Ok(())
+    }
+}
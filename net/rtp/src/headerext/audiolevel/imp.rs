@@ -0,0 +1,201 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrextclientaudiolevel2
+ *
+ * Client-to-mixer audio level RTP header extension as per [RFC 6464][rfc-6464].
+ *
+ * The audio level and voice activity are taken from the `GstAudioLevelMeta` of the input buffers
+ * and are stored in a `GstAudioLevelMeta` on the output buffers on the receiver side. Packets
+ * without audio level meta don't carry the extension.
+ *
+ * [rfc-6464]: https://www.rfc-editor.org/rfc/rfc6464.html
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextclientaudiolevel2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Client Audio Level Header Extension"),
+    )
+});
+
+pub const URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+const DEFAULT_VAD: bool = true;
+
+struct Settings {
+    vad: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { vad: DEFAULT_VAD }
+    }
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtClientAudioLevel {
+    settings: Mutex<Settings>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtClientAudioLevel {
+    const NAME: &'static str = "GstRtpHeaderExtClientAudioLevel2";
+    type Type = super::RtpHeaderExtClientAudioLevel;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtClientAudioLevel {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecBoolean::builder("vad")
+                .nick("Voice Activity Detection")
+                .blurb("Whether the voice activity flag is signalled")
+                .default_value(DEFAULT_VAD)
+                .read_only()
+                .build()]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "vad" => self.settings.lock().unwrap().vad.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpHeaderExtClientAudioLevel {}
+
+impl ElementImpl for RtpHeaderExtClientAudioLevel {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Client Audio Level Header Extension",
+                super::super::KLASS,
+                "Client-to-Mixer audio level indication (RFC 6464) RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtClientAudioLevel {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+    }
+
+    fn max_size(&self, _input: &gst::BufferRef) -> usize {
+        1
+    }
+
+    fn write(
+        &self,
+        input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        _output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        let Some(meta) = input.meta::<gst_audio::AudioLevelMeta>() else {
+            gst::trace!(CAT, imp = self, "No audio level meta on input buffer");
+            return Ok(0);
+        };
+
+        if output_data.is_empty() {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+
+        let vad = self.settings.lock().unwrap().vad;
+        let level = meta.level().min(127);
+        let voice_activity = vad && meta.voice_activity();
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Writing level -{level}dBov, voice activity {voice_activity}"
+        );
+        output_data[0] = ((voice_activity as u8) << 7) | level;
+
+        Ok(1)
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        let Some(&b) = input_data.first() else {
+            return Err(gst::loggable_error!(CAT, "Too short extension data"));
+        };
+
+        let vad = self.settings.lock().unwrap().vad;
+        let level = b & 0x7f;
+        let voice_activity = vad && (b & 0x80) != 0;
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Read level -{level}dBov, voice activity {voice_activity}"
+        );
+
+        // Only the audio level of the first packet of a buffer is stored
+        if output.meta::<gst_audio::AudioLevelMeta>().is_none() {
+            gst_audio::AudioLevelMeta::add(output, level, voice_activity);
+        }
+
+        Ok(())
+    }
+
+    fn set_attributes(
+        &self,
+        _direction: gst_rtp::RTPHeaderExtensionDirection,
+        attributes: &str,
+    ) -> Result<(), gst::LoggableError> {
+        let vad = match attributes.trim() {
+            "" | "vad=on" => true,
+            "vad=off" => false,
+            _ => {
+                return Err(gst::loggable_error!(
+                    CAT,
+                    "Invalid attributes '{attributes}'"
+                ))
+            }
+        };
+
+        let mut settings = self.settings.lock().unwrap();
+        if settings.vad != vad {
+            settings.vad = vad;
+            drop(settings);
+            self.obj().notify("vad");
+        }
+
+        Ok(())
+    }
+
+    fn set_caps_from_attributes(&self, caps: &mut gst::CapsRef) -> Result<(), gst::LoggableError> {
+        let vad = self.settings.lock().unwrap().vad;
+        super::super::set_caps_from_attributes_with(
+            self.obj().upcast_ref(),
+            caps,
+            if vad { "vad=on" } else { "vad=off" },
+        )
+    }
+}
@@ -0,0 +1,569 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrextav1dd2
+ *
+ * AV1 dependency descriptor RTP header extension as per the
+ * [AV1 RTP specification][av1-rtp-spec].
+ *
+ * On the sender side a single spatial and temporal layer is signalled: every key frame only
+ * depends on itself and every other frame depends on the previous frame. The template dependency
+ * structure is sent with the first packet of every key frame. Frame boundaries are detected via
+ * the marker bit of the packets.
+ *
+ * On the receiver side the descriptors are parsed and the latest frame number is provided via
+ * the `frame-number` property.
+ *
+ * [av1-rtp-spec]: https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{io::Cursor, sync::Mutex};
+
+use bitstream_io::{BigEndian, BitRead, BitReader, BitWrite, BitWriter};
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextav1dd2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP AV1 Dependency Descriptor Header Extension"),
+    )
+});
+
+pub const URI: &str =
+    "https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension";
+
+/// Size of the mandatory descriptor fields.
+const MANDATORY_SIZE: usize = 3;
+
+/// Decode target indications.
+const DTI_SWITCH: u8 = 2;
+const DTI_REQUIRED: u8 = 3;
+
+/// Template used for key frames.
+const KEY_FRAME_TEMPLATE: u8 = 0;
+/// Template used for all other frames.
+const DELTA_FRAME_TEMPLATE: u8 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FrameTemplate {
+    pub spatial_id: u8,
+    pub temporal_id: u8,
+    /// Decode target indication for each decode target.
+    pub dtis: Vec<u8>,
+    /// Differences to the frame numbers of the referenced frames.
+    pub fdiffs: Vec<u8>,
+    /// Differences to the frame numbers of the previous frame in each chain.
+    pub chain_fdiffs: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TemplateStructure {
+    pub template_id_offset: u8,
+    pub decode_target_count: u8,
+    pub templates: Vec<FrameTemplate>,
+    /// Chain protecting each decode target.
+    pub decode_target_protected_by: Vec<u8>,
+    /// Render resolution of each spatial layer.
+    pub resolutions: Vec<(u16, u16)>,
+}
+
+impl TemplateStructure {
+    /// Structure for a single layer where key frames only depend on themselves and all other
+    /// frames depend on the previous frame.
+    pub(crate) fn single_layer() -> Self {
+        TemplateStructure {
+            template_id_offset: 0,
+            decode_target_count: 1,
+            templates: vec![
+                FrameTemplate {
+                    dtis: vec![DTI_SWITCH],
+                    ..Default::default()
+                },
+                FrameTemplate {
+                    dtis: vec![DTI_REQUIRED],
+                    fdiffs: vec![1],
+                    ..Default::default()
+                },
+            ],
+            decode_target_protected_by: vec![],
+            resolutions: vec![],
+        }
+    }
+
+    fn chain_count(&self) -> usize {
+        self.templates
+            .first()
+            .map_or(0, |template| template.chain_fdiffs.len())
+    }
+
+    fn write<W: BitWrite>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write(6, self.template_id_offset)?;
+        w.write(5, self.decode_target_count - 1)?;
+
+        // template_layers()
+        for (idx, template) in self.templates.iter().enumerate() {
+            let next_layer_idc = match self.templates.get(idx + 1) {
+                None => 3,
+                Some(next) if next.spatial_id > template.spatial_id => 2,
+                Some(next) if next.temporal_id > template.temporal_id => 1,
+                Some(_) => 0,
+            };
+            w.write(2, next_layer_idc)?;
+        }
+
+        // template_dtis()
+        for template in &self.templates {
+            for dti in &template.dtis {
+                w.write(2, *dti)?;
+            }
+        }
+
+        // template_fdiffs()
+        for template in &self.templates {
+            for fdiff in &template.fdiffs {
+                w.write_bit(true)?;
+                w.write(4, *fdiff - 1)?;
+            }
+            w.write_bit(false)?;
+        }
+
+        // template_chains()
+        let chain_count = self.chain_count();
+        write_ns(w, self.decode_target_count as u32 + 1, chain_count as u32)?;
+        if chain_count > 0 {
+            for protected_by in &self.decode_target_protected_by {
+                write_ns(w, chain_count as u32, *protected_by as u32)?;
+            }
+            for template in &self.templates {
+                for fdiff in &template.chain_fdiffs {
+                    w.write(4, *fdiff)?;
+                }
+            }
+        }
+
+        // decode_target_layers() is derived from the templates
+
+        w.write_bit(!self.resolutions.is_empty())?;
+        for (width, height) in &self.resolutions {
+            w.write(16, *width - 1)?;
+            w.write(16, *height - 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn parse<R: BitRead>(r: &mut R) -> std::io::Result<Self> {
+        let template_id_offset = r.read::<u8>(6)?;
+        let decode_target_count = r.read::<u8>(5)? + 1;
+
+        // template_layers()
+        let mut templates = Vec::new();
+        let (mut spatial_id, mut temporal_id) = (0, 0);
+        loop {
+            templates.push(FrameTemplate {
+                spatial_id,
+                temporal_id,
+                ..Default::default()
+            });
+            if templates.len() > 64 {
+                return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+            }
+            match r.read::<u8>(2)? {
+                0 => (),
+                1 => temporal_id += 1,
+                2 => {
+                    temporal_id = 0;
+                    spatial_id += 1;
+                }
+                _ => break,
+            }
+        }
+
+        // template_dtis()
+        for template in &mut templates {
+            for _ in 0..decode_target_count {
+                template.dtis.push(r.read::<u8>(2)?);
+            }
+        }
+
+        // template_fdiffs()
+        for template in &mut templates {
+            while r.read_bit()? {
+                template.fdiffs.push(r.read::<u8>(4)? + 1);
+            }
+        }
+
+        // template_chains()
+        let chain_count = read_ns(r, decode_target_count as u32 + 1)?;
+        let mut decode_target_protected_by = Vec::new();
+        if chain_count > 0 {
+            for _ in 0..decode_target_count {
+                decode_target_protected_by.push(read_ns(r, chain_count)? as u8);
+            }
+            for template in &mut templates {
+                for _ in 0..chain_count {
+                    template.chain_fdiffs.push(r.read::<u8>(4)?);
+                }
+            }
+        }
+
+        let mut resolutions = Vec::new();
+        if r.read_bit()? {
+            for _ in 0..=spatial_id {
+                let width = r.read::<u16>(16)? + 1;
+                let height = r.read::<u16>(16)? + 1;
+                resolutions.push((width, height));
+            }
+        }
+
+        Ok(TemplateStructure {
+            template_id_offset,
+            decode_target_count,
+            templates,
+            decode_target_protected_by,
+            resolutions,
+        })
+    }
+}
+
+/// Writes a non-symmetric unsigned value in the range `0..n`.
+fn write_ns<W: BitWrite>(w: &mut W, n: u32, v: u32) -> std::io::Result<()> {
+    if n <= 1 {
+        return Ok(());
+    }
+    let bits = u32::BITS - n.leading_zeros();
+    let m = (1 << bits) - n;
+    if v < m {
+        w.write(bits - 1, v)
+    } else {
+        let v = v + m;
+        w.write(bits - 1, v >> 1)?;
+        w.write_bit(v & 1 != 0)
+    }
+}
+
+/// Reads a non-symmetric unsigned value in the range `0..n`.
+fn read_ns<R: BitRead>(r: &mut R, n: u32) -> std::io::Result<u32> {
+    if n <= 1 {
+        return Ok(0);
+    }
+    let bits = u32::BITS - n.leading_zeros();
+    let m = (1 << bits) - n;
+    let v = r.read::<u32>(bits - 1)?;
+    if v < m {
+        return Ok(v);
+    }
+    let extra_bit = r.read::<u32>(1)?;
+    Ok((v << 1) - m + extra_bit)
+}
+
+/// The fields of a dependency descriptor up to the template dependency structure.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DependencyDescriptor {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+    pub template_id: u8,
+    pub frame_number: u16,
+    pub structure: Option<TemplateStructure>,
+}
+
+impl DependencyDescriptor {
+    pub(crate) fn write(&self) -> Vec<u8> {
+        let mut w = BitWriter::endian(Cursor::new(Vec::new()), BigEndian);
+        // Writing into a Vec can't fail
+        self.write_bits(&mut w).unwrap();
+        w.into_writer().into_inner()
+    }
+
+    fn write_bits<W: BitWrite>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_bit(self.start_of_frame)?;
+        w.write_bit(self.end_of_frame)?;
+        w.write(6, self.template_id)?;
+        w.write(16, self.frame_number)?;
+
+        if let Some(ref structure) = self.structure {
+            // template_dependency_structure_present_flag, no active decode targets and no
+            // custom dtis, fdiffs or chains
+            w.write(5, 0b1_0000u8)?;
+            structure.write(w)?;
+        }
+
+        w.byte_align()
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> std::io::Result<Self> {
+        if data.len() < MANDATORY_SIZE {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+
+        let mut r = BitReader::endian(Cursor::new(data), BigEndian);
+        let start_of_frame = r.read_bit()?;
+        let end_of_frame = r.read_bit()?;
+        let template_id = r.read::<u8>(6)?;
+        let frame_number = r.read::<u16>(16)?;
+
+        let mut structure = None;
+        if data.len() > MANDATORY_SIZE && r.read_bit()? {
+            // Skip active_decode_targets_present_flag and custom_{dtis,fdiffs,chains}_flag
+            r.skip(4)?;
+            structure = Some(TemplateStructure::parse(&mut r)?);
+        }
+
+        Ok(DependencyDescriptor {
+            start_of_frame,
+            end_of_frame,
+            template_id,
+            frame_number,
+            structure,
+        })
+    }
+}
+
+struct State {
+    /// Whether the last written packet had the marker bit set, i.e. the next packet starts a
+    /// new frame
+    last_marker: bool,
+    /// Whether the current frame is a key frame
+    key_frame: bool,
+    /// Frame number of the current frame
+    frame_number: u16,
+    /// Frame number of the last read descriptor
+    last_read_frame_number: Option<u16>,
+    /// Last received template dependency structure
+    structure: Option<TemplateStructure>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            last_marker: true,
+            key_frame: false,
+            frame_number: u16::MAX,
+            last_read_frame_number: None,
+            structure: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtAv1DependencyDescriptor {
+    state: Mutex<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtAv1DependencyDescriptor {
+    const NAME: &'static str = "GstRtpHeaderExtAv1DependencyDescriptor2";
+    type Type = super::RtpHeaderExtAv1DependencyDescriptor;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtAv1DependencyDescriptor {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecInt::builder("frame-number")
+                .nick("Frame Number")
+                .blurb("Frame number of the last read dependency descriptor, or -1 if none")
+                .minimum(-1)
+                .maximum(u16::MAX as i32)
+                .default_value(-1)
+                .read_only()
+                .build()]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "frame-number" => self
+                .state
+                .lock()
+                .unwrap()
+                .last_read_frame_number
+                .map_or(-1, i32::from)
+                .to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpHeaderExtAv1DependencyDescriptor {}
+
+impl ElementImpl for RtpHeaderExtAv1DependencyDescriptor {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP AV1 Dependency Descriptor Header Extension",
+                super::super::KLASS,
+                "AV1 dependency descriptor RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        self.parent_change_state(transition)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtAv1DependencyDescriptor {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+    }
+
+    fn max_size(&self, input: &gst::BufferRef) -> usize {
+        if input.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+            MANDATORY_SIZE
+        } else {
+            DependencyDescriptor {
+                structure: Some(TemplateStructure::single_layer()),
+                ..Default::default()
+            }
+            .write()
+            .len()
+        }
+    }
+
+    fn write(
+        &self,
+        input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        let marker = {
+            let map = output
+                .map_readable()
+                .map_err(|_| gst::loggable_error!(CAT, "Failed to map output buffer"))?;
+            let packet = rtp_types::RtpPacket::parse(&map)
+                .map_err(|err| gst::loggable_error!(CAT, "Invalid RTP packet: {err:?}"))?;
+            packet.marker_bit()
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let start_of_frame = state.last_marker;
+        if start_of_frame {
+            state.frame_number = state.frame_number.wrapping_add(1);
+            state.key_frame = !input.flags().contains(gst::BufferFlags::DELTA_UNIT);
+        }
+        state.last_marker = marker;
+
+        let descriptor = DependencyDescriptor {
+            start_of_frame,
+            end_of_frame: marker,
+            template_id: if state.key_frame {
+                KEY_FRAME_TEMPLATE
+            } else {
+                DELTA_FRAME_TEMPLATE
+            },
+            frame_number: state.frame_number,
+            structure: (start_of_frame && state.key_frame).then(TemplateStructure::single_layer),
+        };
+        drop(state);
+
+        gst::trace!(CAT, imp = self, "Writing {descriptor:?}");
+        let data = descriptor.write();
+        if output_data.len() < data.len() {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+        output_data[..data.len()].copy_from_slice(&data);
+
+        Ok(data.len())
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        _output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        let descriptor = DependencyDescriptor::parse(input_data)
+            .map_err(|err| gst::loggable_error!(CAT, "Invalid dependency descriptor: {err}"))?;
+        gst::trace!(CAT, imp = self, "Read {descriptor:?}");
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(structure) = descriptor.structure {
+            state.structure = Some(structure);
+        } else if state.structure.is_none() {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "No template dependency structure received yet"
+            );
+        }
+        let changed = state.last_read_frame_number != Some(descriptor.frame_number);
+        state.last_read_frame_number = Some(descriptor.frame_number);
+        drop(state);
+
+        if changed {
+            self.obj().notify("frame-number");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_layer_structure() {
+        let descriptor = DependencyDescriptor {
+            start_of_frame: true,
+            end_of_frame: false,
+            template_id: KEY_FRAME_TEMPLATE,
+            frame_number: 0x1234,
+            structure: Some(TemplateStructure::single_layer()),
+        };
+        let data = descriptor.write();
+        assert_eq!(data.len(), 8);
+        assert_eq!(&data[..3], &[0x80, 0x12, 0x34]);
+        assert_eq!(DependencyDescriptor::parse(&data).unwrap(), descriptor);
+
+        let descriptor = DependencyDescriptor {
+            start_of_frame: false,
+            end_of_frame: true,
+            template_id: DELTA_FRAME_TEMPLATE,
+            frame_number: 0x1235,
+            structure: None,
+        };
+        let data = descriptor.write();
+        assert_eq!(data, [0x41, 0x12, 0x35]);
+        assert_eq!(DependencyDescriptor::parse(&data).unwrap(), descriptor);
+    }
+
+    #[test]
+    fn non_symmetric() {
+        for n in 2..20 {
+            for v in 0..n {
+                let mut w = BitWriter::endian(Cursor::new(Vec::new()), BigEndian);
+                write_ns(&mut w, n, v).unwrap();
+                w.byte_align().unwrap();
+                let data = w.into_writer().into_inner();
+                let mut r = BitReader::endian(Cursor::new(&data), BigEndian);
+                assert_eq!(read_ns(&mut r, n).unwrap(), v);
+            }
+        }
+    }
+}
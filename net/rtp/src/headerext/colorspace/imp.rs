@@ -0,0 +1,421 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrextcolorspace2
+ *
+ * RTP header extension carrying the colorimetry, chroma siting and optionally HDR metadata of a
+ * video stream, see the [specification][color-space].
+ *
+ * On the sender side the values are taken from the caps of the payloader and written into all
+ * packets of key frames. On the receiver side they are put into the caps of the depayloader.
+ *
+ * [color-space]: http://www.webrtc.org/experiments/rtp-hdrext/color-space
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{str::FromStr, sync::Mutex};
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::{prelude::*, subclass::prelude::*};
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextcolorspace2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Color Space Header Extension"),
+    )
+});
+
+pub const URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/color-space";
+
+const SIZE: usize = 4;
+const SIZE_WITH_HDR: usize = 28;
+
+/// Denominator of the chromaticity coordinates, identical to the one used by GStreamer.
+const CHROMATICITY_DENOMINATOR: f32 = 50_000.0;
+/// Units of the luminance values of GStreamer per unit of the maximum luminance.
+const LUMINANCE_MAX_FACTOR: u32 = 10_000;
+
+/// Chroma siting values.
+const CHROMA_SITING_UNSPECIFIED: u8 = 0;
+const CHROMA_SITING_COLLOCATED: u8 = 1;
+const CHROMA_SITING_HALF: u8 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Hdr {
+    /// Red, green and blue primaries in units of 0.00002.
+    pub primaries: [(u16, u16); 3],
+    /// White point in units of 0.00002.
+    pub white_point: (u16, u16),
+    /// Maximum luminance in cd/m².
+    pub luminance_max: u16,
+    /// Minimum luminance in units of 0.0001 cd/m².
+    pub luminance_min: u16,
+    pub max_content_light_level: u16,
+    pub max_frame_average_light_level: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ColorSpace {
+    /// ISO/IEC 23091-4 / ITU-T H.273 colour primaries.
+    pub primaries: u8,
+    /// ISO/IEC 23091-4 / ITU-T H.273 transfer characteristics.
+    pub transfer: u8,
+    /// ISO/IEC 23091-4 / ITU-T H.273 matrix coefficients.
+    pub matrix: u8,
+    /// 0: invalid, 1: limited, 2: full, 3: derived.
+    pub range: u8,
+    pub chroma_siting_horizontal: u8,
+    pub chroma_siting_vertical: u8,
+    pub hdr: Option<Hdr>,
+}
+
+impl ColorSpace {
+    pub(crate) fn from_caps(caps: &gst::CapsRef) -> Option<Self> {
+        let s = caps.structure(0)?;
+        let colorimetry = s
+            .get::<&str>("colorimetry")
+            .ok()
+            .and_then(|c| gst_video::VideoColorimetry::from_str(c).ok())?;
+
+        let range = match colorimetry.range() {
+            gst_video::VideoColorRange::Range16_235 => 1,
+            gst_video::VideoColorRange::Range0_255 => 2,
+            _ => 0,
+        };
+
+        let (chroma_siting_horizontal, chroma_siting_vertical) =
+            match s.get::<&str>("chroma-site").ok() {
+                None => (CHROMA_SITING_UNSPECIFIED, CHROMA_SITING_UNSPECIFIED),
+                Some(chroma_site) => {
+                    let flags = chroma_site.split('+').collect::<Vec<_>>();
+                    let h_cosited = flags
+                        .iter()
+                        .any(|f| ["h-cosited", "cosited", "mpeg2", "dv"].contains(f));
+                    let v_cosited = flags
+                        .iter()
+                        .any(|f| ["v-cosited", "cosited", "dv"].contains(f));
+                    (
+                        if h_cosited {
+                            CHROMA_SITING_COLLOCATED
+                        } else {
+                            CHROMA_SITING_HALF
+                        },
+                        if v_cosited {
+                            CHROMA_SITING_COLLOCATED
+                        } else {
+                            CHROMA_SITING_HALF
+                        },
+                    )
+                }
+            };
+
+        let hdr = gst_video::VideoMasteringDisplayInfo::from_caps(caps)
+            .ok()
+            .map(|info| {
+                let coordinate = |c: gst_video::VideoMasteringDisplayInfoCoordinate| {
+                    (
+                        (c.x() * CHROMATICITY_DENOMINATOR).round() as u16,
+                        (c.y() * CHROMATICITY_DENOMINATOR).round() as u16,
+                    )
+                };
+                let primaries = info.display_primaries();
+                let light_level = gst_video::VideoContentLightLevel::from_caps(caps).ok();
+
+                Hdr {
+                    primaries: [
+                        coordinate(primaries[0]),
+                        coordinate(primaries[1]),
+                        coordinate(primaries[2]),
+                    ],
+                    white_point: coordinate(info.white_point()),
+                    luminance_max: (info.max_display_mastering_luminance() / LUMINANCE_MAX_FACTOR)
+                        .min(u16::MAX as u32) as u16,
+                    luminance_min: info.min_display_mastering_luminance().min(u16::MAX as u32)
+                        as u16,
+                    max_content_light_level: light_level
+                        .as_ref()
+                        .map_or(0, |l| l.max_content_light_level()),
+                    max_frame_average_light_level: light_level
+                        .as_ref()
+                        .map_or(0, |l| l.max_frame_average_light_level()),
+                }
+            });
+
+        Some(ColorSpace {
+            primaries: colorimetry.primaries().to_iso() as u8,
+            transfer: colorimetry.transfer().to_iso() as u8,
+            matrix: colorimetry.matrix().to_iso() as u8,
+            range,
+            chroma_siting_horizontal,
+            chroma_siting_vertical,
+            hdr,
+        })
+    }
+
+    pub(crate) fn update_caps(&self, caps: &mut gst::CapsRef) {
+        let range = match self.range {
+            1 => gst_video::VideoColorRange::Range16_235,
+            2 => gst_video::VideoColorRange::Range0_255,
+            _ => gst_video::VideoColorRange::Unknown,
+        };
+        let colorimetry = gst_video::VideoColorimetry::new(
+            range,
+            gst_video::VideoColorMatrix::from_iso(self.matrix as u32),
+            gst_video::VideoTransferFunction::from_iso(self.transfer as u32),
+            gst_video::VideoColorPrimaries::from_iso(self.primaries as u32),
+        );
+
+        let chroma_site = match (self.chroma_siting_horizontal, self.chroma_siting_vertical) {
+            (CHROMA_SITING_COLLOCATED, CHROMA_SITING_COLLOCATED) => Some("cosited"),
+            (CHROMA_SITING_COLLOCATED, CHROMA_SITING_HALF) => Some("mpeg2"),
+            (CHROMA_SITING_HALF, CHROMA_SITING_COLLOCATED) => Some("v-cosited"),
+            (CHROMA_SITING_HALF, CHROMA_SITING_HALF) => Some("jpeg"),
+            _ => None,
+        };
+
+        {
+            let s = caps.structure_mut(0).unwrap();
+            s.set("colorimetry", colorimetry.to_string());
+            if let Some(chroma_site) = chroma_site {
+                s.set("chroma-site", chroma_site);
+            }
+        }
+
+        if let Some(ref hdr) = self.hdr {
+            let coordinate = |(x, y): (u16, u16)| {
+                gst_video::VideoMasteringDisplayInfoCoordinate::new(
+                    x as f32 / CHROMATICITY_DENOMINATOR,
+                    y as f32 / CHROMATICITY_DENOMINATOR,
+                )
+            };
+            let info = gst_video::VideoMasteringDisplayInfo::new(
+                [
+                    coordinate(hdr.primaries[0]),
+                    coordinate(hdr.primaries[1]),
+                    coordinate(hdr.primaries[2]),
+                ],
+                coordinate(hdr.white_point),
+                hdr.luminance_max as u32 * LUMINANCE_MAX_FACTOR,
+                hdr.luminance_min as u32,
+            );
+            info.add_to_caps(caps);
+
+            let light_level = gst_video::VideoContentLightLevel::new(
+                hdr.max_content_light_level,
+                hdr.max_frame_average_light_level,
+            );
+            light_level.add_to_caps(caps);
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        if self.hdr.is_some() {
+            SIZE_WITH_HDR
+        } else {
+            SIZE
+        }
+    }
+
+    pub(crate) fn write(&self, data: &mut [u8]) -> usize {
+        data[0] = self.primaries;
+        data[1] = self.transfer;
+        data[2] = self.matrix;
+        data[3] =
+            (self.range << 4) | (self.chroma_siting_horizontal << 2) | self.chroma_siting_vertical;
+
+        let Some(ref hdr) = self.hdr else {
+            return SIZE;
+        };
+
+        let values = [
+            hdr.primaries[0].0,
+            hdr.primaries[0].1,
+            hdr.primaries[1].0,
+            hdr.primaries[1].1,
+            hdr.primaries[2].0,
+            hdr.primaries[2].1,
+            hdr.white_point.0,
+            hdr.white_point.1,
+            hdr.luminance_max,
+            hdr.luminance_min,
+            hdr.max_content_light_level,
+            hdr.max_frame_average_light_level,
+        ];
+        for (chunk, value) in data[SIZE..SIZE_WITH_HDR].chunks_exact_mut(2).zip(values) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+
+        SIZE_WITH_HDR
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < SIZE {
+            return None;
+        }
+
+        let hdr = if data.len() >= SIZE_WITH_HDR {
+            let mut values = data[SIZE..SIZE_WITH_HDR]
+                .chunks_exact(2)
+                .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]));
+            let mut next = || values.next().unwrap();
+            Some(Hdr {
+                primaries: [(next(), next()), (next(), next()), (next(), next())],
+                white_point: (next(), next()),
+                luminance_max: next(),
+                luminance_min: next(),
+                max_content_light_level: next(),
+                max_frame_average_light_level: next(),
+            })
+        } else {
+            None
+        };
+
+        Some(ColorSpace {
+            primaries: data[0],
+            transfer: data[1],
+            matrix: data[2],
+            range: (data[3] >> 4) & 0x03,
+            chroma_siting_horizontal: (data[3] >> 2) & 0x03,
+            chroma_siting_vertical: data[3] & 0x03,
+            hdr,
+        })
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Color space configured from the caps on the sender side
+    send: Option<ColorSpace>,
+    /// Last received color space on the receiver side
+    recv: Option<ColorSpace>,
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtColorSpace {
+    state: Mutex<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtColorSpace {
+    const NAME: &'static str = "GstRtpHeaderExtColorSpace2";
+    type Type = super::RtpHeaderExtColorSpace;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtColorSpace {}
+
+impl GstObjectImpl for RtpHeaderExtColorSpace {}
+
+impl ElementImpl for RtpHeaderExtColorSpace {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Color Space Header Extension",
+                super::super::KLASS,
+                "Color space RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtColorSpace {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        let state = self.state.lock().unwrap();
+        // HDR metadata does not fit into the one-byte header format
+        if state.send.is_some_and(|c| c.hdr.is_some()) {
+            gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+        } else {
+            gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+        }
+    }
+
+    fn max_size(&self, input: &gst::BufferRef) -> usize {
+        if input.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+            return 0;
+        }
+
+        let state = self.state.lock().unwrap();
+        state.send.as_ref().map_or(0, ColorSpace::size)
+    }
+
+    fn write(
+        &self,
+        input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        _output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        if input.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+            return Ok(0);
+        }
+
+        let state = self.state.lock().unwrap();
+        let Some(ref color_space) = state.send else {
+            return Ok(0);
+        };
+
+        if output_data.len() < color_space.size() {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+
+        gst::trace!(CAT, imp = self, "Writing {color_space:?}");
+
+        Ok(color_space.write(output_data))
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        _output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        let Some(color_space) = ColorSpace::parse(input_data) else {
+            return Err(gst::loggable_error!(CAT, "Too short extension data"));
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.recv != Some(color_space) {
+            gst::debug!(CAT, imp = self, "Received new {color_space:?}");
+            state.recv = Some(color_space);
+            drop(state);
+            self.obj().set_wants_update_non_rtp_src_caps(true);
+        }
+
+        Ok(())
+    }
+
+    fn set_non_rtp_sink_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        let color_space = ColorSpace::from_caps(caps);
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Configured {color_space:?} from caps {caps}"
+        );
+        self.state.lock().unwrap().send = color_space;
+
+        Ok(())
+    }
+
+    fn update_non_rtp_src_caps(&self, caps: &mut gst::CapsRef) -> Result<(), gst::LoggableError> {
+        let state = self.state.lock().unwrap();
+        if let Some(ref color_space) = state.recv {
+            color_space.update_caps(caps);
+        }
+        drop(state);
+        self.obj().set_wants_update_non_rtp_src_caps(false);
+
+        Ok(())
+    }
+}
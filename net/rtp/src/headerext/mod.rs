@@ -0,0 +1,88 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! RTP header extensions that can be used with the payloaders and depayloaders.
+
+use gst::glib;
+use gst_rtp::prelude::*;
+use once_cell::sync::Lazy;
+
+pub mod abssendtime;
+pub mod audiolevel;
+pub mod av1dd;
+pub mod colorspace;
+pub mod playoutdelay;
+pub mod sdes;
+pub mod twcc;
+
+#[cfg(test)]
+mod tests;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrext",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Header Extensions"),
+    )
+});
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    abssendtime::register(plugin)?;
+    audiolevel::register(plugin)?;
+    av1dd::register(plugin)?;
+    colorspace::register(plugin)?;
+    playoutdelay::register(plugin)?;
+    sdes::register(plugin)?;
+    twcc::register(plugin)?;
+
+    Ok(())
+}
+
+/// Klass of all RTP header extension elements.
+const KLASS: &str = "Network/Extension/RTPHeader";
+
+/// Stores the extension in the caps together with its direction and `attributes`.
+///
+/// This is the equivalent of the default `set_caps_from_attributes()` implementation for
+/// extensions that have attributes.
+fn set_caps_from_attributes_with(
+    ext: &gst_rtp::RTPHeaderExtension,
+    caps: &mut gst::CapsRef,
+    attributes: &str,
+) -> Result<(), gst::LoggableError> {
+    let Some(uri) = ext.uri() else {
+        return Err(gst::loggable_error!(CAT, "Extension without URI"));
+    };
+
+    let direction = ext.direction();
+    let field_name = format!("extmap-{}", ext.id());
+    let s = caps
+        .structure_mut(0)
+        .ok_or_else(|| gst::loggable_error!(CAT, "Empty caps"))?;
+
+    if direction.contains(gst_rtp::RTPHeaderExtensionDirection::INHERITED) && attributes.is_empty()
+    {
+        s.set(field_name, uri.as_str());
+    } else {
+        let direction = if direction.contains(gst_rtp::RTPHeaderExtensionDirection::INHERITED) {
+            ""
+        } else if direction == gst_rtp::RTPHeaderExtensionDirection::SENDRECV {
+            "sendrecv"
+        } else if direction == gst_rtp::RTPHeaderExtensionDirection::SENDONLY {
+            "sendonly"
+        } else if direction == gst_rtp::RTPHeaderExtensionDirection::RECVONLY {
+            "recvonly"
+        } else {
+            "inactive"
+        };
+        s.set(
+            field_name,
+            gst::Array::new([direction, uri.as_str(), attributes]),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrextplayoutdelay2
+ *
+ * RTP header extension for the sender to signal the minimum and maximum playout delay the
+ * receiver should use, see the [specification][playout-delay].
+ *
+ * The delays are configured via the `min-delay` and `max-delay` properties on the sender side.
+ * On the receiver side the properties are updated with the values from the received packets.
+ *
+ * [playout-delay]: http://www.webrtc.org/experiments/rtp-hdrext/playout-delay
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextplayoutdelay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Playout Delay Header Extension"),
+    )
+});
+
+pub const URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay";
+
+/// Granularity of the delays in milliseconds.
+const GRANULARITY_MS: u32 = 10;
+/// Maximum delay in milliseconds that can be signalled with 12 bits.
+const MAX_DELAY_MS: u32 = 0xfff * GRANULARITY_MS;
+
+const DEFAULT_MIN_DELAY_MS: u32 = 0;
+const DEFAULT_MAX_DELAY_MS: u32 = 0;
+
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    min_delay_ms: u32,
+    max_delay_ms: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_delay_ms: DEFAULT_MIN_DELAY_MS,
+            max_delay_ms: DEFAULT_MAX_DELAY_MS,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtPlayoutDelay {
+    settings: Mutex<Settings>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtPlayoutDelay {
+    const NAME: &'static str = "GstRtpHeaderExtPlayoutDelay2";
+    type Type = super::RtpHeaderExtPlayoutDelay;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtPlayoutDelay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("min-delay")
+                    .nick("Minimum Delay")
+                    .blurb("Minimum playout delay in milliseconds")
+                    .maximum(MAX_DELAY_MS)
+                    .default_value(DEFAULT_MIN_DELAY_MS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-delay")
+                    .nick("Maximum Delay")
+                    .blurb("Maximum playout delay in milliseconds")
+                    .maximum(MAX_DELAY_MS)
+                    .default_value(DEFAULT_MAX_DELAY_MS)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "min-delay" => settings.min_delay_ms = value.get().unwrap(),
+            "max-delay" => settings.max_delay_ms = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "min-delay" => settings.min_delay_ms.to_value(),
+            "max-delay" => settings.max_delay_ms.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpHeaderExtPlayoutDelay {}
+
+impl ElementImpl for RtpHeaderExtPlayoutDelay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Playout Delay Header Extension",
+                super::super::KLASS,
+                "Playout delay RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtPlayoutDelay {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+    }
+
+    fn max_size(&self, _input: &gst::BufferRef) -> usize {
+        3
+    }
+
+    fn write(
+        &self,
+        _input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        _output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        if output_data.len() < 3 {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+
+        let settings = *self.settings.lock().unwrap();
+        let min_delay = settings.min_delay_ms / GRANULARITY_MS;
+        let max_delay = settings.max_delay_ms.max(settings.min_delay_ms) / GRANULARITY_MS;
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Writing min delay {}ms, max delay {}ms",
+            min_delay * GRANULARITY_MS,
+            max_delay * GRANULARITY_MS
+        );
+
+        let value = (min_delay << 12) | max_delay;
+        output_data[..3].copy_from_slice(&value.to_be_bytes()[1..]);
+
+        Ok(3)
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        _output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        if input_data.len() < 3 {
+            return Err(gst::loggable_error!(CAT, "Too short extension data"));
+        }
+
+        let value = u32::from_be_bytes([0, input_data[0], input_data[1], input_data[2]]);
+        let min_delay_ms = (value >> 12) * GRANULARITY_MS;
+        let max_delay_ms = (value & 0xfff) * GRANULARITY_MS;
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Read min delay {min_delay_ms}ms, max delay {max_delay_ms}ms"
+        );
+
+        let mut settings = self.settings.lock().unwrap();
+        let min_changed = settings.min_delay_ms != min_delay_ms;
+        let max_changed = settings.max_delay_ms != max_delay_ms;
+        settings.min_delay_ms = min_delay_ms;
+        settings.max_delay_ms = max_delay_ms;
+        drop(settings);
+
+        if min_changed {
+            self.obj().notify("min-delay");
+        }
+        if max_changed {
+            self.obj().notify("max-delay");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrextsdes2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP SDES Header Extensions"),
+    )
+});
+
+pub const MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
+pub const STREAM_ID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+pub const REPAIRED_STREAM_ID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id";
+
+/// Maximum length of a value in the two-byte header format.
+const MAX_LEN: usize = 255;
+/// Maximum length of a value in the one-byte header format.
+const MAX_ONE_BYTE_LEN: usize = 16;
+
+/// Shared implementation of all extensions carrying a single SDES item as per RFC 7941.
+#[derive(Default)]
+struct SdesItem {
+    value: Mutex<Option<String>>,
+}
+
+impl SdesItem {
+    fn pspec(name: &str, nick: &str, blurb: &str) -> glib::ParamSpec {
+        glib::ParamSpecString::builder(name)
+            .nick(nick)
+            .blurb(blurb)
+            .mutable_playing()
+            .build()
+    }
+
+    fn set(&self, value: &glib::Value) {
+        let value = value.get::<Option<String>>().unwrap();
+        if let Some(ref v) = value {
+            if v.len() > MAX_LEN {
+                gst::warning!(CAT, "Value '{v}' longer than {MAX_LEN} bytes, ignoring");
+                return;
+            }
+        }
+
+        *self.value.lock().unwrap() = value.filter(|v| !v.is_empty());
+    }
+
+    fn get(&self) -> glib::Value {
+        self.value.lock().unwrap().to_value()
+    }
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        let value = self.value.lock().unwrap();
+        if value
+            .as_ref()
+            .is_some_and(|value| value.len() > MAX_ONE_BYTE_LEN)
+        {
+            gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+        } else {
+            gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+        }
+    }
+
+    fn max_size(&self) -> usize {
+        self.value.lock().unwrap().as_ref().map_or(0, String::len)
+    }
+
+    fn write(&self, output_data: &mut [u8]) -> Result<usize, gst::LoggableError> {
+        let value = self.value.lock().unwrap();
+        let Some(ref value) = *value else {
+            return Ok(0);
+        };
+
+        if output_data.len() < value.len() {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+        output_data[..value.len()].copy_from_slice(value.as_bytes());
+
+        Ok(value.len())
+    }
+
+    /// Returns `true` if the value has changed.
+    fn read(&self, input_data: &[u8]) -> Result<bool, gst::LoggableError> {
+        // Padding is allowed at the end of the value
+        let len = input_data
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(input_data.len());
+        if len == 0 {
+            return Err(gst::loggable_error!(CAT, "Empty value"));
+        }
+
+        let value = std::str::from_utf8(&input_data[..len])
+            .map_err(|_| gst::loggable_error!(CAT, "Value is not valid UTF-8"))?;
+
+        let mut current = self.value.lock().unwrap();
+        if current.as_deref() == Some(value) {
+            return Ok(false);
+        }
+        gst::trace!(CAT, "Read new value '{value}'");
+        *current = Some(value.to_owned());
+
+        Ok(true)
+    }
+}
+
+macro_rules! sdes_extension {
+    (
+        $name:ident,
+        $gtype_name:literal,
+        $uri:expr,
+        $property:literal,
+        $nick:literal,
+        $blurb:literal,
+        $long_name:literal,
+        $description:literal
+    ) => {
+        #[derive(Default)]
+        pub struct $name {
+            item: SdesItem,
+        }
+
+        #[glib::object_subclass]
+        impl ObjectSubclass for $name {
+            const NAME: &'static str = $gtype_name;
+            type Type = super::$name;
+            type ParentType = gst_rtp::RTPHeaderExtension;
+        }
+
+        impl ObjectImpl for $name {
+            fn properties() -> &'static [glib::ParamSpec] {
+                static PROPERTIES: Lazy<Vec<glib::ParamSpec>> =
+                    Lazy::new(|| vec![SdesItem::pspec($property, $nick, $blurb)]);
+
+                PROPERTIES.as_ref()
+            }
+
+            fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+                match pspec.name() {
+                    $property => self.item.set(value),
+                    _ => unimplemented!(),
+                }
+            }
+
+            fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+                match pspec.name() {
+                    $property => self.item.get(),
+                    _ => unimplemented!(),
+                }
+            }
+        }
+
+        impl GstObjectImpl for $name {}
+
+        impl ElementImpl for $name {
+            fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+                static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        $long_name,
+                        super::super::KLASS,
+                        $description,
+                        "Sebastian Dröge <sebastian@centricular.com>",
+                    )
+                });
+
+                Some(&*ELEMENT_METADATA)
+            }
+        }
+
+        impl RTPHeaderExtensionImpl for $name {
+            const URI: &'static str = $uri;
+
+            fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+                self.item.supported_flags()
+            }
+
+            fn max_size(&self, _input: &gst::BufferRef) -> usize {
+                self.item.max_size()
+            }
+
+            fn write(
+                &self,
+                _input: &gst::BufferRef,
+                _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+                _output: &gst::BufferRef,
+                output_data: &mut [u8],
+            ) -> Result<usize, gst::LoggableError> {
+                self.item.write(output_data)
+            }
+
+            fn read(
+                &self,
+                _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+                input_data: &[u8],
+                _output: &mut gst::BufferRef,
+            ) -> Result<(), gst::LoggableError> {
+                if self.item.read(input_data)? {
+                    self.obj().notify($property);
+                }
+
+                Ok(())
+            }
+        }
+    };
+}
+
+/**
+ * SECTION:element-rtphdrextmid2
+ *
+ * RTP header extension carrying the media identification (MID) of the stream as per
+ * [RFC 8843][rfc-8843].
+ *
+ * The MID is configured via the `mid` property on the sender side. On the receiver side the
+ * property is updated with the MID from the received packets.
+ *
+ * [rfc-8843]: https://www.rfc-editor.org/rfc/rfc8843.html#section-15.2
+ *
+ * Since: plugins-rs-0.13.0
+ */
+sdes_extension!(
+    RtpHeaderExtMid,
+    "GstRtpHeaderExtMid2",
+    MID_URI,
+    "mid",
+    "MID",
+    "The media identification",
+    "RTP BUNDLE MID Header Extension",
+    "Media identification (RFC 8843) RTP header extension"
+);
+
+/**
+ * SECTION:element-rtphdrextstreamid2
+ *
+ * RTP header extension carrying the RTP stream identifier (RID) of the stream as per
+ * [RFC 8852][rfc-8852].
+ *
+ * The RID is configured via the `rid` property on the sender side. On the receiver side the
+ * property is updated with the RID from the received packets.
+ *
+ * [rfc-8852]: https://www.rfc-editor.org/rfc/rfc8852.html#section-3.1
+ *
+ * Since: plugins-rs-0.13.0
+ */
+sdes_extension!(
+    RtpHeaderExtStreamId,
+    "GstRtpHeaderExtStreamId2",
+    STREAM_ID_URI,
+    "rid",
+    "RID",
+    "The RTP stream identifier",
+    "RTP Stream Identifier Header Extension",
+    "RTP stream identifier (RFC 8852) RTP header extension"
+);
+
+/**
+ * SECTION:element-rtphdrextrepairedstreamid2
+ *
+ * RTP header extension carrying the RTP stream identifier (RID) of the stream that is repaired
+ * by a redundancy stream as per [RFC 8852][rfc-8852].
+ *
+ * The RID is configured via the `rid` property on the sender side. On the receiver side the
+ * property is updated with the RID from the received packets.
+ *
+ * [rfc-8852]: https://www.rfc-editor.org/rfc/rfc8852.html#section-3.2
+ *
+ * Since: plugins-rs-0.13.0
+ */
+sdes_extension!(
+    RtpHeaderExtRepairedStreamId,
+    "GstRtpHeaderExtRepairedStreamId2",
+    REPAIRED_STREAM_ID_URI,
+    "rid",
+    "Repaired RID",
+    "The RTP stream identifier of the repaired stream",
+    "RTP Repaired Stream Identifier Header Extension",
+    "Repaired RTP stream identifier (RFC 8852) RTP header extension"
+);
@@ -0,0 +1,48 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+
+pub mod imp;
+
+glib::wrapper! {
+    pub struct RtpHeaderExtMid(ObjectSubclass<imp::RtpHeaderExtMid>)
+        @extends gst_rtp::RTPHeaderExtension, gst::Element, gst::Object;
+}
+
+glib::wrapper! {
+    pub struct RtpHeaderExtStreamId(ObjectSubclass<imp::RtpHeaderExtStreamId>)
+        @extends gst_rtp::RTPHeaderExtension, gst::Element, gst::Object;
+}
+
+glib::wrapper! {
+    pub struct RtpHeaderExtRepairedStreamId(ObjectSubclass<imp::RtpHeaderExtRepairedStreamId>)
+        @extends gst_rtp::RTPHeaderExtension, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rtphdrextmid2",
+        gst::Rank::MARGINAL,
+        RtpHeaderExtMid::static_type(),
+    )?;
+
+    gst::Element::register(
+        Some(plugin),
+        "rtphdrextstreamid2",
+        gst::Rank::MARGINAL,
+        RtpHeaderExtStreamId::static_type(),
+    )?;
+
+    gst::Element::register(
+        Some(plugin),
+        "rtphdrextrepairedstreamid2",
+        gst::Rank::MARGINAL,
+        RtpHeaderExtRepairedStreamId::static_type(),
+    )
+}
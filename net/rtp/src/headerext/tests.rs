@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::iter;
+
+use gst::prelude::*;
+use gst_check::Harness;
+use gst_rtp::prelude::*;
+
+use super::av1dd::imp::{DependencyDescriptor, TemplateStructure};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtphdrext test");
+    });
+}
+
+fn make_extension(factory: &str, id: u32) -> gst_rtp::RTPHeaderExtension {
+    let ext = gst::ElementFactory::make(factory)
+        .build()
+        .unwrap()
+        .downcast::<gst_rtp::RTPHeaderExtension>()
+        .unwrap();
+    ext.set_id(id);
+    ext
+}
+
+struct Output {
+    packets: Vec<gst::Buffer>,
+    buffers: Vec<gst::Buffer>,
+    caps: gst::Caps,
+}
+
+/// Payloads `input` with the given extensions and depayloads the resulting packets again.
+fn run_pay_depay(
+    pay: &str,
+    caps: &str,
+    pay_extensions: &[&gst_rtp::RTPHeaderExtension],
+    depay: &str,
+    depay_extensions: &[&gst_rtp::RTPHeaderExtension],
+    input: Vec<gst::Buffer>,
+) -> Output {
+    let mut h = Harness::new(pay);
+    for ext in pay_extensions {
+        h.element()
+            .unwrap()
+            .emit_by_name::<()>("add-extension", &[ext]);
+    }
+    h.set_src_caps_str(caps);
+    for buffer in input {
+        h.push(buffer).unwrap();
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let packets = iter::from_fn(|| h.try_pull()).collect::<Vec<_>>();
+    let rtp_caps = h.sinkpad().unwrap().current_caps().unwrap();
+
+    let mut h = Harness::new(depay);
+    for ext in depay_extensions {
+        h.element()
+            .unwrap()
+            .emit_by_name::<()>("add-extension", &[ext]);
+    }
+    h.set_src_caps(rtp_caps);
+    for packet in &packets {
+        h.push(packet.clone()).unwrap();
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let buffers = iter::from_fn(|| h.try_pull()).collect::<Vec<_>>();
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+
+    Output {
+        packets,
+        buffers,
+        caps,
+    }
+}
+
+/// Returns the data of the header extension with `id` in `packet`.
+fn extension_data(packet: &gst::Buffer, id: u8) -> Option<Vec<u8>> {
+    let map = packet.map_readable().unwrap();
+    let packet = rtp_types::RtpPacket::parse(&map).unwrap();
+    let (pattern, mut data) = packet.extension()?;
+    let one_byte = pattern == 0xBEDE;
+
+    while !data.is_empty() {
+        let (ext_id, len, header_len) = if one_byte {
+            (data[0] >> 4, (data[0] & 0x0f) as usize + 1, 1)
+        } else {
+            if data.len() < 2 {
+                return None;
+            }
+            (data[0], data[1] as usize, 2)
+        };
+
+        // Padding
+        if ext_id == 0 {
+            data = &data[1..];
+            continue;
+        }
+
+        let ext_data = data.get(header_len..header_len + len)?;
+        if ext_id == id {
+            return Some(ext_data.to_vec());
+        }
+        data = &data[header_len + len..];
+    }
+
+    None
+}
+
+#[test]
+fn test_abs_send_time_audio_level_twcc() {
+    init();
+
+    let pay_abs_send_time = make_extension("rtphdrextabssendtime2", 1);
+    let pay_audio_level = make_extension("rtphdrextclientaudiolevel2", 2);
+    let pay_twcc = make_extension("rtphdrexttwcc2", 3);
+    let depay_abs_send_time = make_extension("rtphdrextabssendtime2", 1);
+    let depay_audio_level = make_extension("rtphdrextclientaudiolevel2", 2);
+    let depay_twcc = make_extension("rtphdrexttwcc2", 3);
+
+    let mut buffer = gst::Buffer::with_size(400).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::ZERO);
+        gst::ReferenceTimestampMeta::add(
+            buffer,
+            &gst::Caps::new_empty_simple("timestamp/x-ntp"),
+            gst::ClockTime::from_mseconds(3_500),
+            None,
+        );
+        gst_audio::AudioLevelMeta::add(buffer, 30, true);
+    }
+
+    let output = run_pay_depay(
+        "rtppcmapay2",
+        "audio/x-alaw,rate=8000,channels=1",
+        &[&pay_abs_send_time, &pay_audio_level, &pay_twcc],
+        "rtppcmadepay2",
+        &[&depay_abs_send_time, &depay_audio_level, &depay_twcc],
+        vec![buffer],
+    );
+
+    assert_eq!(output.packets.len(), 1);
+    // 3.5s as 6.18 fixed point
+    assert_eq!(
+        extension_data(&output.packets[0], 1).unwrap(),
+        [0x0e, 0x00, 0x00]
+    );
+    assert_eq!(extension_data(&output.packets[0], 2).unwrap(), [0x80 | 30]);
+    assert_eq!(extension_data(&output.packets[0], 3).unwrap(), [0x00, 0x00]);
+
+    assert_eq!(output.buffers.len(), 1);
+    let buffer = &output.buffers[0];
+    let send_time = buffer
+        .iter_meta::<gst::ReferenceTimestampMeta>()
+        .find(|meta| meta.reference().structure(0).unwrap().name() == "timestamp/x-abs-send-time")
+        .unwrap();
+    assert_eq!(send_time.timestamp(), gst::ClockTime::from_mseconds(3_500));
+    let level = buffer.meta::<gst_audio::AudioLevelMeta>().unwrap();
+    assert_eq!(level.level(), 30);
+    assert!(level.voice_activity());
+    assert_eq!(depay_twcc.property::<u32>("seqnum"), 0);
+}
+
+#[test]
+fn test_sdes_playout_delay() {
+    init();
+
+    let pay_mid = make_extension("rtphdrextmid2", 1);
+    pay_mid.set_property("mid", "audio0");
+    let pay_rid = make_extension("rtphdrextstreamid2", 2);
+    pay_rid.set_property("rid", "hi");
+    let pay_playout_delay = make_extension("rtphdrextplayoutdelay2", 3);
+    pay_playout_delay.set_property("min-delay", 100u32);
+    pay_playout_delay.set_property("max-delay", 1000u32);
+
+    let depay_mid = make_extension("rtphdrextmid2", 1);
+    let depay_rid = make_extension("rtphdrextstreamid2", 2);
+    let depay_playout_delay = make_extension("rtphdrextplayoutdelay2", 3);
+
+    let mut buffer = gst::Buffer::with_size(400).unwrap();
+    buffer.get_mut().unwrap().set_pts(gst::ClockTime::ZERO);
+
+    let output = run_pay_depay(
+        "rtppcmapay2",
+        "audio/x-alaw,rate=8000,channels=1",
+        &[&pay_mid, &pay_rid, &pay_playout_delay],
+        "rtppcmadepay2",
+        &[&depay_mid, &depay_rid, &depay_playout_delay],
+        vec![buffer],
+    );
+
+    assert_eq!(output.packets.len(), 1);
+    assert_eq!(extension_data(&output.packets[0], 1).unwrap(), b"audio0");
+    assert_eq!(extension_data(&output.packets[0], 2).unwrap(), b"hi");
+    // 10 and 100 in units of 10ms as 12 bit values
+    assert_eq!(
+        extension_data(&output.packets[0], 3).unwrap(),
+        [0x00, 0xa0, 0x64]
+    );
+
+    assert_eq!(output.buffers.len(), 1);
+    assert_eq!(
+        depay_mid.property::<Option<String>>("mid").as_deref(),
+        Some("audio0")
+    );
+    assert_eq!(
+        depay_rid.property::<Option<String>>("rid").as_deref(),
+        Some("hi")
+    );
+    assert_eq!(depay_playout_delay.property::<u32>("min-delay"), 100);
+    assert_eq!(depay_playout_delay.property::<u32>("max-delay"), 1000);
+}
+
+#[test]
+fn test_color_space_dependency_descriptor() {
+    init();
+
+    let pay_color_space = make_extension("rtphdrextcolorspace2", 1);
+    let pay_av1dd = make_extension("rtphdrextav1dd2", 2);
+    let depay_color_space = make_extension("rtphdrextcolorspace2", 1);
+    let depay_av1dd = make_extension("rtphdrextav1dd2", 2);
+
+    #[rustfmt::skip]
+    let mut key_frame = gst::Buffer::from_mut_slice(vec![
+        0b0001_0010, 0,
+        0b0000_1010, 0,
+        0b0011_0010, 0b0000_1100, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    ]);
+    key_frame.get_mut().unwrap().set_pts(gst::ClockTime::ZERO);
+    #[rustfmt::skip]
+    let mut delta_frame = gst::Buffer::from_mut_slice(vec![
+        0b0001_0010, 0,
+        0b0011_0010, 0b0000_0100, 1, 2, 3, 4,
+    ]);
+    {
+        let buffer = delta_frame.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from_mseconds(40));
+        buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+    }
+
+    let output = run_pay_depay(
+        "rtpav1pay",
+        "video/x-av1,parsed=true,stream-format=obu-stream,alignment=tu,colorimetry=bt709,chroma-site=mpeg2",
+        &[&pay_color_space, &pay_av1dd],
+        "rtpav1depay",
+        &[&depay_color_space, &depay_av1dd],
+        vec![key_frame, delta_frame],
+    );
+
+    assert_eq!(output.packets.len(), 2);
+
+    // Color space only on key frames
+    let color_space = extension_data(&output.packets[0], 1).unwrap();
+    assert_eq!(color_space.len(), 4);
+    assert_eq!(&color_space[..3], &[1, 1, 1]);
+    assert!(extension_data(&output.packets[1], 1).is_none());
+
+    // Template structure only on key frames
+    assert_eq!(
+        DependencyDescriptor::parse(&extension_data(&output.packets[0], 2).unwrap()).unwrap(),
+        DependencyDescriptor {
+            start_of_frame: true,
+            end_of_frame: true,
+            template_id: 0,
+            frame_number: 0,
+            structure: Some(TemplateStructure::single_layer()),
+        }
+    );
+    assert_eq!(
+        DependencyDescriptor::parse(&extension_data(&output.packets[1], 2).unwrap()).unwrap(),
+        DependencyDescriptor {
+            start_of_frame: true,
+            end_of_frame: true,
+            template_id: 1,
+            frame_number: 1,
+            structure: None,
+        }
+    );
+
+    assert_eq!(output.buffers.len(), 2);
+    let s = output.caps.structure(0).unwrap();
+    assert_eq!(s.get::<&str>("colorimetry").unwrap(), "bt709");
+    assert_eq!(s.get::<&str>("chroma-site").unwrap(), "mpeg2");
+    assert_eq!(depay_av1dd.property::<i32>("frame-number"), 1);
+}
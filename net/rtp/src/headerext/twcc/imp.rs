@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtphdrexttwcc2
+ *
+ * RTP header extension carrying a transport-wide sequence number as used by transport-wide
+ * congestion control, see the [specification][twcc].
+ *
+ * Every packet written by the extension gets the next sequence number. If multiple streams are
+ * sent over the same transport, the sequence numbers are usually rewritten by the RTP session
+ * afterwards.
+ *
+ * [twcc]: https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::sync::Mutex;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use gst_rtp::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtphdrexttwcc2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Transport-wide CC Header Extension"),
+    )
+});
+
+pub const URI: &str = "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+
+#[derive(Default)]
+struct State {
+    /// Sequence number for the next written packet
+    next_seqnum: u16,
+    /// Sequence number of the last read packet
+    last_read_seqnum: Option<u16>,
+}
+
+#[derive(Default)]
+pub struct RtpHeaderExtTwcc {
+    state: Mutex<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpHeaderExtTwcc {
+    const NAME: &'static str = "GstRtpHeaderExtTwcc2";
+    type Type = super::RtpHeaderExtTwcc;
+    type ParentType = gst_rtp::RTPHeaderExtension;
+}
+
+impl ObjectImpl for RtpHeaderExtTwcc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecUInt::builder("seqnum")
+                .nick("Sequence Number")
+                .blurb("Sequence number of the last written or read packet")
+                .maximum(u16::MAX as u32)
+                .read_only()
+                .build()]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "seqnum" => {
+                let state = self.state.lock().unwrap();
+                let seqnum = state
+                    .last_read_seqnum
+                    .unwrap_or_else(|| state.next_seqnum.wrapping_sub(1));
+                (seqnum as u32).to_value()
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpHeaderExtTwcc {}
+
+impl ElementImpl for RtpHeaderExtTwcc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Transport-wide CC Header Extension",
+                super::super::KLASS,
+                "Transport-wide congestion control sequence number RTP header extension",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        self.parent_change_state(transition)
+    }
+}
+
+impl RTPHeaderExtensionImpl for RtpHeaderExtTwcc {
+    const URI: &'static str = URI;
+
+    fn supported_flags(&self) -> gst_rtp::RTPHeaderExtensionFlags {
+        gst_rtp::RTPHeaderExtensionFlags::ONE_BYTE | gst_rtp::RTPHeaderExtensionFlags::TWO_BYTE
+    }
+
+    fn max_size(&self, _input: &gst::BufferRef) -> usize {
+        2
+    }
+
+    fn write(
+        &self,
+        _input: &gst::BufferRef,
+        _write_flags: gst_rtp::RTPHeaderExtensionFlags,
+        _output: &gst::BufferRef,
+        output_data: &mut [u8],
+    ) -> Result<usize, gst::LoggableError> {
+        if output_data.len() < 2 {
+            return Err(gst::loggable_error!(CAT, "Not enough space"));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let seqnum = state.next_seqnum;
+        state.next_seqnum = seqnum.wrapping_add(1);
+        drop(state);
+
+        gst::trace!(CAT, imp = self, "Writing seqnum {seqnum}");
+        output_data[..2].copy_from_slice(&seqnum.to_be_bytes());
+
+        Ok(2)
+    }
+
+    fn read(
+        &self,
+        _read_flags: gst_rtp::RTPHeaderExtensionFlags,
+        input_data: &[u8],
+        _output: &mut gst::BufferRef,
+    ) -> Result<(), gst::LoggableError> {
+        if input_data.len() < 2 {
+            return Err(gst::loggable_error!(CAT, "Too short extension data"));
+        }
+
+        let seqnum = u16::from_be_bytes([input_data[0], input_data[1]]);
+        gst::trace!(CAT, imp = self, "Read seqnum {seqnum}");
+        self.state.lock().unwrap().last_read_seqnum = Some(seqnum);
+
+        Ok(())
+    }
+}
@@ -23,6 +23,7 @@ use gst::glib;
 #[macro_use]
 mod utils;
 
+mod fec;
 mod gcc;
 mod rtpbin2;
 
@@ -30,9 +31,13 @@ mod audio_discont;
 mod baseaudiopay;
 mod basedepay;
 mod basepay;
+mod h26x;
+mod headerext;
 
 mod ac3;
 mod av1;
+mod h264;
+mod h265;
 mod jpeg;
 mod klv;
 mod mp2t;
@@ -42,11 +47,13 @@ mod opus;
 mod pcmau;
 mod vp8;
 mod vp9;
+mod vraw;
 
 #[cfg(test)]
 mod tests;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    fec::register(plugin)?;
     gcc::register(plugin)?;
     rtpbin2::register(plugin)?;
 
@@ -62,12 +69,20 @@ fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
             .mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
 
+    headerext::register(plugin)?;
+
     ac3::depay::register(plugin)?;
     ac3::pay::register(plugin)?;
 
     av1::depay::register(plugin)?;
     av1::pay::register(plugin)?;
 
+    h264::depay::register(plugin)?;
+    h264::pay::register(plugin)?;
+
+    h265::depay::register(plugin)?;
+    h265::pay::register(plugin)?;
+
     jpeg::depay::register(plugin)?;
     jpeg::pay::register(plugin)?;
 
@@ -95,6 +110,9 @@ fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     vp9::depay::register(plugin)?;
     vp9::pay::register(plugin)?;
 
+    vraw::depay::register(plugin)?;
+    vraw::pay::register(plugin)?;
+
     Ok(())
 }
 
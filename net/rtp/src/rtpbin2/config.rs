@@ -6,7 +6,9 @@ use gst::subclass::prelude::*;
 use once_cell::sync::Lazy;
 use std::sync::{Mutex, Weak};
 
+use crate::fec::common::MaskType;
 use crate::rtpbin2::internal::SharedSessionInner;
+use crate::rtpbin2::xr::XrConfig;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -16,6 +18,40 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
+#[glib::flags(name = "GstRtp2XrFlags")]
+pub enum XrFlags {
+    #[flags_value(name = "Receiver Reference Time report blocks", nick = "rrt")]
+    RRT = 0b0000_0001,
+    #[flags_value(name = "DLRR report blocks", nick = "dlrr")]
+    DLRR = 0b0000_0010,
+    #[flags_value(name = "Loss RLE report blocks", nick = "loss-rle")]
+    LOSS_RLE = 0b0000_0100,
+    #[flags_value(name = "VoIP metrics report blocks", nick = "voip-metrics")]
+    VOIP_METRICS = 0b0000_1000,
+}
+
+impl From<XrFlags> for XrConfig {
+    fn from(flags: XrFlags) -> Self {
+        Self {
+            rrt: flags.contains(XrFlags::RRT),
+            dlrr: flags.contains(XrFlags::DLRR),
+            loss_rle: flags.contains(XrFlags::LOSS_RLE),
+            voip_metrics: flags.contains(XrFlags::VOIP_METRICS),
+        }
+    }
+}
+
+impl From<XrConfig> for XrFlags {
+    fn from(config: XrConfig) -> Self {
+        let mut flags = Self::empty();
+        flags.set(Self::RRT, config.rrt);
+        flags.set(Self::DLRR, config.dlrr);
+        flags.set(Self::LOSS_RLE, config.loss_rle);
+        flags.set(Self::VOIP_METRICS, config.voip_metrics);
+        flags
+    }
+}
+
 glib::wrapper! {
     pub struct Rtp2Session(ObjectSubclass<imp::Rtp2Session>);
 }
@@ -97,6 +133,108 @@ mod imp {
             ret.build()
         }
 
+        pub fn set_srtp_keys(&self, send: bool, keys: Option<gst::Structure>) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            session.set_srtp_keys(send, keys.as_deref());
+        }
+
+        pub fn srtp_keys(&self, send: bool) -> gst::Structure {
+            let Some(session) = self.session() else {
+                return gst::Structure::new_empty("application/x-rtp2-srtp-keys");
+            };
+            let session = session.lock().unwrap();
+            session.srtp_keys(send)
+        }
+
+        pub fn set_sdes(&self, sdes: Option<gst::Structure>) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            let sdes =
+                sdes.unwrap_or_else(|| gst::Structure::new_empty("application/x-rtp-source-sdes"));
+            session.set_sdes(&sdes);
+        }
+
+        pub fn sdes(&self) -> gst::Structure {
+            let Some(session) = self.session() else {
+                return gst::Structure::new_empty("application/x-rtp-source-sdes");
+            };
+            let session = session.lock().unwrap();
+            session.sdes()
+        }
+
+        pub fn set_xr_flags(&self, flags: XrFlags) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            session.session.set_xr_config(flags.into());
+        }
+
+        pub fn xr_flags(&self) -> XrFlags {
+            let Some(session) = self.session() else {
+                return XrFlags::empty();
+            };
+            let session = session.lock().unwrap();
+            session.session.xr_config().into()
+        }
+
+        pub fn set_fec_percentage(&self, percentage: u32) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            session.fec.set_percentage(percentage);
+        }
+
+        pub fn fec_percentage(&self) -> u32 {
+            let Some(session) = self.session() else {
+                return 0;
+            };
+            let session = session.lock().unwrap();
+            session.fec.percentage()
+        }
+
+        pub fn set_fec_mask_type(&self, mask_type: MaskType) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            session.fec.set_mask_type(mask_type);
+        }
+
+        pub fn fec_mask_type(&self) -> MaskType {
+            let Some(session) = self.session() else {
+                return MaskType::default();
+            };
+            let session = session.lock().unwrap();
+            session.fec.mask_type()
+        }
+
+        pub fn send_app(&self, name: &str, sub_type: u32, data: &[u8]) -> bool {
+            let Some(session) = self.session() else {
+                return false;
+            };
+            let Ok(sub_type) = u8::try_from(sub_type) else {
+                gst::warning!(CAT, "Invalid APP subtype {sub_type}");
+                return false;
+            };
+            let mut session = session.lock().unwrap();
+            if !session.session.send_app(name, sub_type, data.to_vec()) {
+                gst::warning!(
+                    CAT,
+                    "Invalid APP packet {name} with subtype {sub_type} and {} bytes",
+                    data.len()
+                );
+                return false;
+            }
+            true
+        }
+
         pub fn stats(&self) -> Option<gst::Structure> {
             let session = self.session()?;
             let session = session.lock().unwrap();
@@ -114,10 +252,42 @@ mod imp {
     impl ObjectImpl for Rtp2Session {
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-                vec![glib::ParamSpecBoxed::builder::<gst::Structure>("pt-map")
-                    .nick("RTP Payload Type Map")
-                    .blurb("Mapping of RTP payload type to caps")
-                    .build()]
+                vec![
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("pt-map")
+                        .nick("RTP Payload Type Map")
+                        .blurb("Mapping of RTP payload type to caps")
+                        .build(),
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("srtp-send-keys")
+                        .nick("SRTP Send Keys")
+                        .blurb("Mapping of SSRC or \"default\" to SRTP key parameters for sending")
+                        .build(),
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("srtp-recv-keys")
+                        .nick("SRTP Receive Keys")
+                        .blurb(
+                            "Mapping of SSRC or \"default\" to SRTP key parameters for receiving",
+                        )
+                        .build(),
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("sdes")
+                        .nick("SDES")
+                        .blurb("The SDES items sent for the local sources of this session")
+                        .build(),
+                    glib::ParamSpecFlags::builder::<XrFlags>("rtcp-xr")
+                        .nick("RTCP XR")
+                        .blurb("Extended report blocks to send with regular RTCP packets")
+                        .build(),
+                    glib::ParamSpecUInt::builder("fec-percentage")
+                        .nick("FEC Percentage")
+                        .blurb(
+                            "Number of FlexFEC packets sent in percent of the number of media \
+                             packets, if a FlexFEC payload type is configured in the pt-map",
+                        )
+                        .maximum(100)
+                        .build(),
+                    glib::ParamSpecEnum::builder::<MaskType>("fec-mask-type")
+                        .nick("FEC Mask Type")
+                        .blurb("Which media packets are protected together by a FlexFEC packet")
+                        .build(),
+                ]
             });
 
             PROPERTIES.as_ref()
@@ -126,6 +296,12 @@ mod imp {
         fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
             match pspec.name() {
                 "pt-map" => self.pt_map().to_value(),
+                "srtp-send-keys" => self.srtp_keys(true).to_value(),
+                "srtp-recv-keys" => self.srtp_keys(false).to_value(),
+                "sdes" => self.sdes().to_value(),
+                "rtcp-xr" => self.xr_flags().to_value(),
+                "fec-percentage" => self.fec_percentage().to_value(),
+                "fec-mask-type" => self.fec_mask_type().to_value(),
                 "stats" => self.stats().to_value(),
                 _ => unreachable!(),
             }
@@ -138,6 +314,32 @@ mod imp {
                         .get::<Option<gst::Structure>>()
                         .expect("Type checked upstream"),
                 ),
+                "srtp-send-keys" => self.set_srtp_keys(
+                    true,
+                    value
+                        .get::<Option<gst::Structure>>()
+                        .expect("Type checked upstream"),
+                ),
+                "srtp-recv-keys" => self.set_srtp_keys(
+                    false,
+                    value
+                        .get::<Option<gst::Structure>>()
+                        .expect("Type checked upstream"),
+                ),
+                "sdes" => self.set_sdes(
+                    value
+                        .get::<Option<gst::Structure>>()
+                        .expect("Type checked upstream"),
+                ),
+                "rtcp-xr" => {
+                    self.set_xr_flags(value.get::<XrFlags>().expect("Type checked upstream"))
+                }
+                "fec-percentage" => {
+                    self.set_fec_percentage(value.get::<u32>().expect("Type checked upstream"))
+                }
+                "fec-mask-type" => {
+                    self.set_fec_mask_type(value.get::<MaskType>().expect("Type checked upstream"))
+                }
                 _ => unreachable!(),
             }
         }
@@ -151,6 +353,30 @@ mod imp {
                     glib::subclass::Signal::builder("bye-ssrc")
                         .param_types([u32::static_type()])
                         .build(),
+                    glib::subclass::Signal::builder("app-received")
+                        .param_types([
+                            u32::static_type(),
+                            String::static_type(),
+                            u32::static_type(),
+                            glib::Bytes::static_type(),
+                        ])
+                        .build(),
+                    glib::subclass::Signal::builder("send-app")
+                        .param_types([
+                            String::static_type(),
+                            u32::static_type(),
+                            glib::Bytes::static_type(),
+                        ])
+                        .return_type::<bool>()
+                        .action()
+                        .class_handler(|_token, args| {
+                            let session = args[0].get::<super::Rtp2Session>().expect("signal arg");
+                            let name = args[1].get::<&str>().expect("signal arg");
+                            let sub_type = args[2].get::<u32>().expect("signal arg");
+                            let data = args[3].get::<glib::Bytes>().expect("signal arg");
+                            Some(session.imp().send_app(name, sub_type, &data).to_value())
+                        })
+                        .build(),
                 ]
             });
 
@@ -232,6 +458,143 @@ mod tests {
         assert!(prop.has_name("application/x-rtp2-pt-map"));
     }
 
+    fn srtp_keys() -> gst::Structure {
+        let key =
+            hex::decode("e1f97a0d3e018be0d64fa32c06de41390ec675ad498afeebb6960b3aabe6").unwrap();
+        let params = gst::Structure::builder("application/x-srtp")
+            .field("srtp-key", gst::Buffer::from_slice(key))
+            .field("srtp-cipher", "aes-128-icm")
+            .field("srtp-auth", "hmac-sha1-80")
+            .field("srtcp-cipher", "aes-128-icm")
+            .field("srtcp-auth", "hmac-sha1-80")
+            .build();
+        gst::Structure::builder("application/x-rtp2-srtp-keys")
+            .field("default", params)
+            .build()
+    }
+
+    #[test]
+    fn srtp_keys_set() {
+        test_init();
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let _pad = rtpbin2.request_pad_simple("rtp_sink_0").unwrap();
+        let session = rtpbin2.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        session.set_property("srtp-send-keys", srtp_keys());
+
+        let prop = session.property::<gst::Structure>("srtp-send-keys");
+        assert!(prop.has_name("application/x-rtp2-srtp-keys"));
+        let params = prop.get::<gst::Structure>("default").unwrap();
+        assert_eq!(params.get::<&str>("srtp-cipher"), Ok("aes-128-icm"));
+        assert_eq!(params.get::<&str>("srtcp-auth"), Ok("hmac-sha1-80"));
+        assert_eq!(params.get::<u32>("roc"), Ok(0));
+
+        let prop = session.property::<gst::Structure>("srtp-recv-keys");
+        assert_eq!(prop.fields().len(), 0);
+
+        session.set_property("srtp-send-keys", None::<gst::Structure>);
+        let prop = session.property::<gst::Structure>("srtp-send-keys");
+        assert_eq!(prop.fields().len(), 0);
+    }
+
+    #[test]
+    fn srtp_protect_send() {
+        test_init();
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let mut h =
+            gst_check::Harness::with_element(&rtpbin2, Some("rtp_sink_0"), Some("rtp_src_0"));
+        let session = h
+            .element()
+            .unwrap()
+            .emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        session.set_property("srtp-send-keys", srtp_keys());
+        h.set_src_caps_str("application/x-rtp,payload=15,clock-rate=8000");
+        let mut segment = gst::Segment::new();
+        segment.set_format(gst::Format::Time);
+        h.push_event(gst::event::Segment::builder(&segment).build());
+
+        let mut data = hex::decode("800f1234decafbadcafebabe").unwrap();
+        data.extend_from_slice(&[0xab; 16]);
+        h.push(gst::Buffer::from_mut_slice(data)).unwrap();
+
+        // RFC 3711 AES-CM-128 / HMAC-SHA1-80 test packet
+        let buf = h.pull().unwrap();
+        let map = buf.map_readable().unwrap();
+        assert_eq!(
+            hex::encode(&map),
+            "800f1234decafbadcafebabe4e55dc4ce79978d88ca4d215949d2402b78d6acc99ea179b8dbb"
+        );
+    }
+
+    #[test]
+    fn sdes_set() {
+        test_init();
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let _pad = rtpbin2.request_pad_simple("rtp_sink_0").unwrap();
+        let session = rtpbin2.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        let sdes = session.property::<gst::Structure>("sdes");
+        assert!(sdes.has_name("application/x-rtp-source-sdes"));
+        let cname = sdes.get::<String>("cname").unwrap();
+
+        session.set_property(
+            "sdes",
+            gst::Structure::builder("application/x-rtp-source-sdes")
+                .field("name", "name")
+                .field("tool", "tool")
+                .build(),
+        );
+        let sdes = session.property::<gst::Structure>("sdes");
+        assert_eq!(sdes.get::<String>("cname"), Ok(cname));
+        assert_eq!(sdes.get::<&str>("name"), Ok("name"));
+        assert_eq!(sdes.get::<&str>("tool"), Ok("tool"));
+        assert!(!sdes.has_field("email"));
+    }
+
+    #[test]
+    fn rtcp_xr_set() {
+        test_init();
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let _pad = rtpbin2.request_pad_simple("rtp_sink_0").unwrap();
+        let session = rtpbin2.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        assert_eq!(session.property::<XrFlags>("rtcp-xr"), XrFlags::empty());
+        session.set_property("rtcp-xr", XrFlags::RRT | XrFlags::LOSS_RLE);
+        assert_eq!(
+            session.property::<XrFlags>("rtcp-xr"),
+            XrFlags::RRT | XrFlags::LOSS_RLE
+        );
+    }
+
+    #[test]
+    fn send_app() {
+        test_init();
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let _pad = rtpbin2.request_pad_simple("rtp_sink_0").unwrap();
+        let session = rtpbin2.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        let data = glib::Bytes::from_static(&[1, 2, 3, 4]);
+        assert!(session.emit_by_name::<bool>("send-app", &[&"TEST", &1u32, &data]));
+        assert!(!session.emit_by_name::<bool>("send-app", &[&"TOOLONG", &1u32, &data]));
+        assert!(!session.emit_by_name::<bool>("send-app", &[&"TEST", &32u32, &data]));
+    }
+
     #[test]
     fn new_send_ssrc() {
         test_init();
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Forward error correction with FlexFEC packets as specified in RFC 8627, sent as a separate
+//! stream in the same session as the protected streams.
+
+use std::collections::HashMap;
+
+use rtp_types::RtpPacket;
+
+use crate::fec::common::{FecDecoder, FecGenerator, MaskType};
+use crate::fec::flexfec;
+
+/// Encoding name of the FlexFEC payload format in caps and SDP
+pub const FLEXFEC_ENCODING_NAME: &str = "flexfec-03";
+
+/// Generation of FlexFEC packets for the sent streams of a session
+#[derive(Debug, Default)]
+pub struct FecSender {
+    generators: HashMap<u32, FecGenerator>,
+    // SSRC and next sequence number of the FEC stream
+    stream: Option<(u32, u16)>,
+    percentage: u32,
+    mask_type: MaskType,
+    num_sent: u64,
+}
+
+impl FecSender {
+    pub fn set_percentage(&mut self, percentage: u32) {
+        self.percentage = percentage.min(100);
+    }
+
+    pub fn percentage(&self) -> u32 {
+        self.percentage
+    }
+
+    pub fn set_mask_type(&mut self, mask_type: MaskType) {
+        self.mask_type = mask_type;
+    }
+
+    pub fn mask_type(&self) -> MaskType {
+        self.mask_type
+    }
+
+    /// Add a sent packet and produce the FEC packets with payload type `fec_pt` that should be
+    /// sent after it.  `fec_ssrc` is called to select the SSRC of the FEC stream the first time a
+    /// FEC packet is produced.
+    pub fn protect_packet(
+        &mut self,
+        rtp: &RtpPacket,
+        data: &[u8],
+        fec_pt: u8,
+        fec_ssrc: impl FnOnce() -> u32,
+    ) -> Vec<Vec<u8>> {
+        let media_ssrc = rtp.ssrc();
+        let groups = self.generators.entry(media_ssrc).or_default().push(
+            data.to_vec(),
+            self.percentage,
+            self.mask_type,
+            flexfec::MAX_PROTECTED_PACKETS,
+        );
+        if groups.is_empty() {
+            return vec![];
+        }
+
+        let (ssrc, seqnum) = self
+            .stream
+            .get_or_insert_with(|| (fec_ssrc(), rand::random()));
+        self.num_sent += groups.len() as u64;
+
+        groups
+            .iter()
+            .map(|group| {
+                let packet = flexfec::write_packet(group, media_ssrc, fec_pt, *ssrc, *seqnum);
+                *seqnum = seqnum.wrapping_add(1);
+                packet
+            })
+            .collect()
+    }
+
+    /// The SSRC of the FEC stream, if any FEC packet was produced yet
+    pub fn fec_ssrc(&self) -> Option<u32> {
+        self.stream.map(|(ssrc, _seqnum)| ssrc)
+    }
+
+    /// The number of FEC packets produced
+    pub fn num_sent(&self) -> u64 {
+        self.num_sent
+    }
+
+    pub fn remove_ssrc(&mut self, ssrc: u32) {
+        self.generators.remove(&ssrc);
+    }
+}
+
+/// Recovery of lost packets from received FlexFEC packets
+#[derive(Debug, Default)]
+pub struct FecReceiver {
+    decoder: FecDecoder,
+}
+
+impl FecReceiver {
+    /// Add a received media packet and return the packets that can be recovered now
+    pub fn add_media_packet(&mut self, rtp: &RtpPacket, data: &[u8]) -> Vec<Vec<u8>> {
+        self.decoder
+            .add_media(rtp.ssrc(), rtp.sequence_number(), data.to_vec())
+    }
+
+    /// Add a received FEC packet and return the packets that can be recovered now
+    pub fn add_fec_packet(&mut self, rtp: &RtpPacket) -> Vec<Vec<u8>> {
+        let Some(fec) = flexfec::parse_packet(rtp) else {
+            return vec![];
+        };
+        self.decoder.add_fec(fec)
+    }
+
+    /// The number of recovered and unrecovered packets
+    pub fn stats(&self) -> (u64, u64) {
+        (self.decoder.num_recovered, self.decoder.num_unrecovered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    const FEC_PT: u8 = 100;
+
+    #[test]
+    fn protect_recover() {
+        let mut sender = FecSender::default();
+        sender.set_percentage(100);
+        let mut receiver = FecReceiver::default();
+
+        let mut fec_packets = vec![];
+        let mut media_packets = vec![];
+        for seqnum in 100..104 {
+            let rtp_data = generate_rtp_packet(0x12345678, seqnum, seqnum as u32 * 3000, 8);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            fec_packets.extend(sender.protect_packet(&packet, &rtp_data, FEC_PT, || 0x87654321));
+            media_packets.push(rtp_data);
+        }
+        assert!(!fec_packets.is_empty());
+        assert_eq!(sender.fec_ssrc(), Some(0x87654321));
+        assert_eq!(sender.num_sent(), fec_packets.len() as u64);
+
+        for data in &fec_packets {
+            let packet = RtpPacket::parse(data).unwrap();
+            assert_eq!(packet.ssrc(), 0x87654321);
+            assert_eq!(packet.payload_type(), FEC_PT);
+        }
+
+        // Lose the first packet and recover it from the FEC packet protecting it
+        for data in &media_packets[1..] {
+            let packet = RtpPacket::parse(data).unwrap();
+            assert!(receiver.add_media_packet(&packet, data).is_empty());
+        }
+        let mut recovered = vec![];
+        for data in &fec_packets {
+            let packet = RtpPacket::parse(data).unwrap();
+            recovered.extend(receiver.add_fec_packet(&packet));
+        }
+        assert_eq!(recovered, vec![media_packets[0].clone()]);
+        assert_eq!(receiver.stats().0, 1);
+    }
+
+    #[test]
+    fn no_protection() {
+        let mut sender = FecSender::default();
+
+        for seqnum in 100..104 {
+            let rtp_data = generate_rtp_packet(0x12345678, seqnum, seqnum as u32 * 3000, 8);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            assert!(sender
+                .protect_packet(&packet, &rtp_data, FEC_PT, || 0x87654321)
+                .is_empty());
+        }
+        assert_eq!(sender.fec_ssrc(), None);
+    }
+}
@@ -4,15 +4,20 @@ use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
     task::Waker,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use gst::glib;
 use once_cell::sync::{Lazy, OnceCell};
 
 use super::config::Rtp2Session;
+use super::fec::{FecReceiver, FecSender, FLEXFEC_ENCODING_NAME};
+use super::rtx::{RtxReceiver, RtxSender};
 use super::session::{RtpProfile, Session};
 use super::source::ReceivedRb;
+use super::srtp::{self, Auth, Cipher, KeyParams, SrtpContext};
+use super::twcc::{self, TwccSender};
+use super::xr::ReceivedXr;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -22,6 +27,17 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
+/// Mapping of the SDES item types to their field names in the `sdes` structure
+const SDES_ITEMS: [(u8, &str); 7] = [
+    (rtcp_types::SdesItem::CNAME, "cname"),
+    (rtcp_types::SdesItem::NAME, "name"),
+    (rtcp_types::SdesItem::EMAIL, "email"),
+    (rtcp_types::SdesItem::PHONE, "phone"),
+    (rtcp_types::SdesItem::LOC, "location"),
+    (rtcp_types::SdesItem::TOOL, "tool"),
+    (rtcp_types::SdesItem::NOTE, "note"),
+];
+
 static SHARED_RTP_STATE: OnceCell<Mutex<HashMap<String, SharedRtpState>>> = OnceCell::new();
 
 #[derive(Debug, Clone)]
@@ -154,6 +170,16 @@ pub(crate) struct SharedSessionInner {
 
     pub(crate) rtcp_waker: Option<Waker>,
     pub(crate) rtp_send_sinkpad: Option<gst::Pad>,
+    pub(crate) rtp_send_srcpad: Option<gst::Pad>,
+
+    pub(crate) rtx: RtxSender,
+    pub(crate) rtx_recv: RtxReceiver,
+    pub(crate) twcc: TwccSender,
+    pub(crate) fec: FecSender,
+    pub(crate) fec_recv: FecReceiver,
+
+    pub(crate) srtp_send: SrtpContext,
+    pub(crate) srtp_recv: SrtpContext,
 }
 
 impl SharedSessionInner {
@@ -166,6 +192,16 @@ impl SharedSessionInner {
             pt_map: HashMap::default(),
             rtcp_waker: None,
             rtp_send_sinkpad: None,
+            rtp_send_srcpad: None,
+
+            rtx: RtxSender::default(),
+            rtx_recv: RtxReceiver::default(),
+            twcc: TwccSender::default(),
+            fec: FecSender::default(),
+            fec_recv: FecReceiver::default(),
+
+            srtp_send: SrtpContext::default(),
+            srtp_recv: SrtpContext::default(),
         }
     }
 
@@ -197,6 +233,345 @@ impl SharedSessionInner {
         self.pt_map.iter().map(|(&k, v)| (k, v))
     }
 
+    /// Returns the RTX payload type associated with the given payload type via its `apt`
+    pub(crate) fn rtx_pt_from_pt(&self, pt: u8) -> Option<u8> {
+        self.pt_map.iter().find_map(|(&rtx_pt, caps)| {
+            let s = caps.structure(0)?;
+            let is_rtx = s
+                .get::<&str>("encoding-name")
+                .is_ok_and(|encoding_name| encoding_name.eq_ignore_ascii_case("RTX"));
+            let apt = s.get::<i32>("apt").ok()?;
+
+            (is_rtx && apt == pt as i32).then_some(rtx_pt)
+        })
+    }
+
+    /// Returns the payload type associated via `apt` if the given payload type is a RTX payload
+    /// type
+    pub(crate) fn apt_from_rtx_pt(&self, rtx_pt: u8) -> Option<u8> {
+        let s = self.pt_map.get(&rtx_pt)?.structure(0)?;
+        if !s
+            .get::<&str>("encoding-name")
+            .is_ok_and(|encoding_name| encoding_name.eq_ignore_ascii_case("RTX"))
+        {
+            return None;
+        }
+
+        s.get::<i32>("apt")
+            .ok()
+            .and_then(|apt| u8::try_from(apt).ok())
+    }
+
+    /// Restore the original packet from a received RTX packet.  Other packets are returned
+    /// unchanged, and `None` is returned if the RTX packet can't be associated with a media SSRC.
+    pub(crate) fn restore_rtx_packet(&mut self, buffer: gst::Buffer) -> Option<gst::Buffer> {
+        let data = {
+            let Ok(mapped) = buffer.map_readable() else {
+                return Some(buffer);
+            };
+            let Ok(rtp) = rtp_types::RtpPacket::parse(&mapped) else {
+                return Some(buffer);
+            };
+            let Some(apt) = self.apt_from_rtx_pt(rtp.payload_type()) else {
+                return Some(buffer);
+            };
+
+            self.rtx_recv.restore_packet(&rtp, apt)?
+        };
+
+        let mut restored = gst::Buffer::from_mut_slice(data);
+        buffer
+            .copy_into(
+                restored.get_mut().unwrap(),
+                gst::BufferCopyFlags::METADATA,
+                ..,
+            )
+            .ok()?;
+
+        Some(restored)
+    }
+
+    /// Store a sent packet for retransmission if a RTX payload type is configured for it
+    pub(crate) fn store_rtx_packet(
+        &mut self,
+        rtp: &rtp_types::RtpPacket,
+        buffer: &gst::Buffer,
+        now: Instant,
+    ) {
+        let Some(rtx_pt) = self.rtx_pt_from_pt(rtp.payload_type()) else {
+            return;
+        };
+
+        let session = &self.session;
+        self.rtx.store_packet(rtp, buffer, rtx_pt, now, || loop {
+            let rtx_ssrc = rand::random::<u32>();
+            if !session.ssrcs().any(|ssrc| ssrc == rtx_ssrc) {
+                gst::debug!(
+                    CAT,
+                    "Using RTX ssrc {rtx_ssrc} for ssrc {} with pt {rtx_pt}",
+                    rtp.ssrc()
+                );
+                break rtx_ssrc;
+            }
+        });
+    }
+
+    /// Returns the FlexFEC payload type if one is configured in the payload type map
+    pub(crate) fn flexfec_pt(&self) -> Option<u8> {
+        self.pt_map.iter().find_map(|(&pt, caps)| {
+            let s = caps.structure(0)?;
+            s.get::<&str>("encoding-name")
+                .is_ok_and(|encoding_name| {
+                    encoding_name.eq_ignore_ascii_case(FLEXFEC_ENCODING_NAME)
+                })
+                .then_some(pt)
+        })
+    }
+
+    /// Produce the FlexFEC packets to send after a sent packet if a FlexFEC payload type is
+    /// configured.  `data` is the packet as it is sent, before SRTP protection.
+    pub(crate) fn protect_fec_packet(
+        &mut self,
+        rtp: &rtp_types::RtpPacket,
+        data: &[u8],
+    ) -> Vec<Vec<u8>> {
+        let Some(fec_pt) = self.flexfec_pt() else {
+            return vec![];
+        };
+        if rtp.payload_type() == fec_pt {
+            return vec![];
+        }
+
+        let session = &self.session;
+        self.fec.protect_packet(rtp, data, fec_pt, || loop {
+            let fec_ssrc = rand::random::<u32>();
+            if !session.ssrcs().any(|ssrc| ssrc == fec_ssrc) {
+                gst::debug!(CAT, "Using FEC ssrc {fec_ssrc} with pt {fec_pt}");
+                break fec_ssrc;
+            }
+        })
+    }
+
+    /// Pass a received packet to the FEC decoder if a FlexFEC payload type is configured.  Packets
+    /// that can be recovered now are added to `recovered`, with the metadata of `buffer`.  Returns
+    /// `true` if `buffer` is a FEC packet, which is not handled any further.
+    pub(crate) fn recover_fec_packets(
+        &mut self,
+        buffer: &gst::Buffer,
+        recovered: &mut Vec<gst::Buffer>,
+    ) -> bool {
+        let Some(fec_pt) = self.flexfec_pt() else {
+            return false;
+        };
+        let Ok(mapped) = buffer.map_readable() else {
+            return false;
+        };
+        let Ok(rtp) = rtp_types::RtpPacket::parse(&mapped) else {
+            return false;
+        };
+
+        let is_fec = rtp.payload_type() == fec_pt;
+        let packets = if is_fec {
+            self.fec_recv.add_fec_packet(&rtp)
+        } else {
+            self.fec_recv.add_media_packet(&rtp, &mapped)
+        };
+        for data in packets {
+            gst::debug!(
+                CAT,
+                "Recovered packet {} from FEC",
+                u16::from_be_bytes([data[2], data[3]])
+            );
+            recovered.push(buffer_with_data(buffer, data));
+        }
+
+        is_fec
+    }
+
+    /// Returns the id of the transport-wide sequence number header extension negotiated for the
+    /// given payload type via an `extmap-N` caps field
+    pub(crate) fn twcc_ext_id(&self, pt: u8) -> Option<u8> {
+        let s = self.pt_map.get(&pt)?.structure(0)?;
+
+        s.iter().find_map(|(name, value)| {
+            let id = name.strip_prefix("extmap-")?.parse::<u8>().ok()?;
+            let is_twcc = if let Ok(uri) = value.get::<&str>() {
+                uri == twcc::TWCC_EXTMAP_URI
+            } else if let Ok(array) = value.get::<gst::ArrayRef>() {
+                array.iter().any(|v| {
+                    v.get::<&str>()
+                        .is_ok_and(|uri| uri == twcc::TWCC_EXTMAP_URI)
+                })
+            } else {
+                false
+            };
+
+            is_twcc.then_some(id)
+        })
+    }
+
+    /// Assign the next transport-wide sequence number to a packet to be sent if the header
+    /// extension is negotiated for its payload type, or for the associated payload type of a RTX
+    /// payload type.  Returns the data of the packet with the header extension set.
+    pub(crate) fn stamp_twcc_packet(
+        &mut self,
+        rtp: &rtp_types::RtpPacket,
+        size: usize,
+        local_ts: gst::ClockTime,
+    ) -> Option<Vec<u8>> {
+        let pt = rtp.payload_type();
+        let ext_id = self.twcc_ext_id(pt).or_else(|| {
+            self.apt_from_rtx_pt(pt)
+                .and_then(|apt| self.twcc_ext_id(apt))
+        })?;
+        let seqnum = self
+            .twcc
+            .send_packet(rtp.ssrc(), rtp.payload_type(), size as u32, local_ts);
+
+        twcc::write_seqnum(rtp, ext_id, seqnum)
+    }
+
+    /// Protect a RTP packet to be sent if SRTP keys are configured, otherwise the buffer is
+    /// returned unchanged
+    pub(crate) fn srtp_protect(&mut self, buffer: gst::Buffer) -> Result<gst::Buffer, srtp::Error> {
+        if !self.srtp_send.is_enabled() {
+            return Ok(buffer);
+        }
+
+        let mut data = buffer
+            .map_readable()
+            .map_err(|_| srtp::Error::InvalidPacket)?
+            .to_vec();
+        self.srtp_send.protect_rtp(&mut data)?;
+
+        Ok(buffer_with_data(&buffer, data))
+    }
+
+    /// Unprotect a received SRTP or SRTCP packet if SRTP keys are configured, otherwise the
+    /// buffer is returned unchanged
+    pub(crate) fn srtp_unprotect(
+        &mut self,
+        buffer: gst::Buffer,
+        rtcp: bool,
+    ) -> Result<gst::Buffer, srtp::Error> {
+        if !self.srtp_recv.is_enabled() {
+            return Ok(buffer);
+        }
+
+        let mut data = buffer
+            .map_readable()
+            .map_err(|_| srtp::Error::InvalidPacket)?
+            .to_vec();
+        if rtcp {
+            self.srtp_recv.unprotect_rtcp(&mut data)?;
+        } else {
+            self.srtp_recv.unprotect_rtp(&mut data)?;
+        }
+
+        Ok(buffer_with_data(&buffer, data))
+    }
+
+    /// Configure the SRTP keys of one direction from a structure mapping SSRCs, or `default`,
+    /// to `application/x-srtp` key parameters.  Keys not in the structure are removed.
+    pub(crate) fn set_srtp_keys(&mut self, send: bool, keys: Option<&gst::StructureRef>) {
+        let ctx = if send {
+            &mut self.srtp_send
+        } else {
+            &mut self.srtp_recv
+        };
+
+        let mut configured = Vec::new();
+        for (name, value) in keys.into_iter().flat_map(|keys| keys.iter()) {
+            let ssrc = if name == "default" {
+                None
+            } else if let Ok(ssrc) = name.parse::<u32>() {
+                Some(ssrc)
+            } else {
+                gst::warning!(CAT, "Failed to parse SRTP key name {name} as ssrc");
+                continue;
+            };
+            let Some(params) = value
+                .get::<gst::Structure>()
+                .ok()
+                .and_then(|s| srtp_key_params_from_structure(&s))
+            else {
+                gst::warning!(CAT, "Invalid SRTP key parameters for {name}");
+                continue;
+            };
+            configured.push((ssrc, params));
+        }
+
+        for ssrc in ctx.key_ssrcs().collect::<Vec<_>>() {
+            if !configured.iter().any(|(configured, _)| *configured == ssrc) {
+                let _ = ctx.set_key(ssrc, None);
+            }
+        }
+        for (ssrc, params) in configured {
+            if let Err(err) = ctx.set_key(ssrc, Some(params)) {
+                gst::warning!(CAT, "Failed to set SRTP key for {ssrc:?}: {err}");
+            }
+        }
+    }
+
+    /// The SRTP keys of one direction in the format accepted by [`Self::set_srtp_keys`]
+    pub(crate) fn srtp_keys(&self, send: bool) -> gst::Structure {
+        let ctx = if send {
+            &self.srtp_send
+        } else {
+            &self.srtp_recv
+        };
+
+        let mut ret = gst::Structure::builder("application/x-rtp2-srtp-keys");
+        for ssrc in ctx.key_ssrcs() {
+            let params = ctx.key(ssrc).unwrap();
+            let name = ssrc.map_or_else(|| String::from("default"), |ssrc| ssrc.to_string());
+            ret = ret.field(name, srtp_key_params_to_structure(params));
+        }
+
+        ret.build()
+    }
+
+    /// Configure the key of one direction from SRTP fields in caps.  The key applies to the
+    /// `ssrc` in the caps, or to all SSRCs without a specific key.
+    pub(crate) fn set_srtp_key_from_caps(&mut self, send: bool, caps: &gst::CapsRef) {
+        let Some(s) = caps.structure(0) else {
+            return;
+        };
+        if !s.has_field("srtp-key") {
+            return;
+        }
+
+        let ssrc = s.get::<u32>("ssrc").ok();
+        let Some(params) = srtp_key_params_from_structure(s) else {
+            gst::warning!(CAT, "Invalid SRTP key parameters in caps {caps:?}");
+            return;
+        };
+
+        let ctx = if send {
+            &mut self.srtp_send
+        } else {
+            &mut self.srtp_recv
+        };
+        if let Err(err) = ctx.set_key(ssrc, Some(params)) {
+            gst::warning!(CAT, "Failed to set SRTP key from caps: {err}");
+        }
+    }
+
+    /// Configure the SDES items of all local sources from an `application/x-rtp-source-sdes`
+    /// structure.  The CNAME is kept if the structure does not contain one.
+    pub(crate) fn set_sdes(&mut self, s: &gst::StructureRef) {
+        let items = SDES_ITEMS
+            .iter()
+            .filter_map(|&(ty, name)| Some((ty, s.get::<String>(name).ok()?)))
+            .filter(|(_ty, value)| !value.is_empty());
+        self.session.set_sdes(items);
+    }
+
+    /// The SDES items of all local sources in the format accepted by [`Self::set_sdes`]
+    pub(crate) fn sdes(&self) -> gst::Structure {
+        sdes_to_structure(self.session.sdes())
+    }
+
     pub fn stats(&self) -> gst::Structure {
         let mut session_stats = gst::Structure::builder("application/x-rtpbin2-session-stats")
             .field("id", self.id as u64);
@@ -210,6 +585,15 @@ impl SharedSessionInner {
                         .field("packets-sent", ls.packet_count())
                         .field("octets-sent", ls.octet_count())
                         .field("bitrate", ls.bitrate() as u64);
+                if let Some((rtx_requested, rtx_sent)) = self.rtx.stats(ls.ssrc()) {
+                    source_stats = source_stats
+                        .field("rtx-ssrc", self.rtx.rtx_ssrc(ls.ssrc()).unwrap())
+                        .field("rtx-requested", rtx_requested)
+                        .field("rtx-sent", rtx_sent);
+                }
+                if self.fec.fec_ssrc() == Some(ls.ssrc()) {
+                    source_stats = source_stats.field("fec-sent", self.fec.num_sent());
+                }
                 if let Some(pt) = ls.payload_type() {
                     if let Some(clock_rate) = self.session.clock_rate_from_pt(pt) {
                         source_stats = source_stats.field("clock-rate", clock_rate);
@@ -305,6 +689,10 @@ impl SharedSessionInner {
                             rb.delay_since_last_sr(),
                         );
                 }
+                source_stats = source_stats.field("sdes", sdes_to_structure(rs.sdes()));
+                if let Some(xr) = self.session.received_xr(rs.ssrc()) {
+                    source_stats = xr_stats(source_stats, xr);
+                }
                 let rbs = gst::List::new(rs.received_report_blocks().map(
                     |(sender_ssrc, ReceivedRb { rb, .. })| {
                         gst::Structure::builder("application/x-rtcp-report-block")
@@ -330,19 +718,146 @@ impl SharedSessionInner {
                 }
                 session_stats = session_stats.field(rs.ssrc().to_string(), source_stats.build());
             } else if let Some(rr) = self.session.remote_receive_source_by_ssrc(ssrc) {
-                let source_stats = gst::Structure::builder("application/x-rtpbin2-source-stats")
-                    .field("ssrc", rr.ssrc())
-                    .field("sender", false)
-                    .field("local", false)
-                    .build();
-                session_stats = session_stats.field(rr.ssrc().to_string(), source_stats);
+                let mut source_stats =
+                    gst::Structure::builder("application/x-rtpbin2-source-stats")
+                        .field("ssrc", rr.ssrc())
+                        .field("sender", false)
+                        .field("local", false)
+                        .field("sdes", sdes_to_structure(rr.sdes()));
+                if let Some(xr) = self.session.received_xr(rr.ssrc()) {
+                    source_stats = xr_stats(source_stats, xr);
+                }
+                session_stats = session_stats.field(rr.ssrc().to_string(), source_stats.build());
             }
         }
 
+        if self.flexfec_pt().is_some() {
+            let (fec_recovered, fec_unrecovered) = self.fec_recv.stats();
+            session_stats = session_stats
+                .field("fec-recovered", fec_recovered)
+                .field("fec-unrecovered", fec_unrecovered);
+        }
+
         session_stats.build()
     }
 }
 
+fn sdes_to_structure(sdes: &HashMap<u8, String>) -> gst::Structure {
+    let mut ret = gst::Structure::builder("application/x-rtp-source-sdes");
+    for (ty, name) in SDES_ITEMS {
+        if let Some(value) = sdes.get(&ty) {
+            ret = ret.field(name, value);
+        }
+    }
+    ret.build()
+}
+
+fn xr_stats(mut builder: gst::structure::Builder, xr: &ReceivedXr) -> gst::structure::Builder {
+    if let Some(ntp_time) = xr.rrt_ntp_time {
+        builder = builder.field("xr-rrt-ntptime", ntp_time);
+    }
+    if let Some(rtt) = xr.round_trip_time {
+        builder = builder.field("xr-round-trip-time", rtt.as_nanos() as u64);
+    }
+    if let Some(ref rle) = xr.loss_rle {
+        builder = builder.field(
+            "xr-loss-rle",
+            gst::Structure::builder("application/x-rtcp-xr-loss-rle")
+                .field("ssrc", rle.ssrc)
+                .field("begin-seq", rle.begin_seq as u32)
+                .field("end-seq", rle.end_seq as u32)
+                .field("packets-received", rle.packets_received())
+                .field("packets-lost", rle.packets_lost())
+                .build(),
+        );
+    }
+    if let Some(ref voip) = xr.voip_metrics {
+        builder = builder.field(
+            "xr-voip-metrics",
+            gst::Structure::builder("application/x-rtcp-xr-voip-metrics")
+                .field("ssrc", voip.ssrc)
+                .field("loss-rate", voip.loss_rate as u32)
+                .field("discard-rate", voip.discard_rate as u32)
+                .field("burst-density", voip.burst_density as u32)
+                .field("gap-density", voip.gap_density as u32)
+                .field("burst-duration", voip.burst_duration as u32)
+                .field("gap-duration", voip.gap_duration as u32)
+                .field("round-trip-delay", voip.round_trip_delay as u32)
+                .field("end-system-delay", voip.end_system_delay as u32)
+                .field("signal-level", voip.signal_level as i8 as i32)
+                .field("noise-level", voip.noise_level as i8 as i32)
+                .field("rerl", voip.rerl as u32)
+                .field("gmin", voip.gmin as u32)
+                .field("r-factor", voip.r_factor as u32)
+                .field("ext-r-factor", voip.ext_r_factor as u32)
+                .field("mos-lq", voip.mos_lq as u32)
+                .field("mos-cq", voip.mos_cq as u32)
+                .field("rx-config", voip.rx_config as u32)
+                .field("jb-nominal", voip.jb_nominal as u32)
+                .field("jb-maximum", voip.jb_maximum as u32)
+                .field("jb-abs-max", voip.jb_abs_max as u32)
+                .build(),
+        );
+    }
+    builder
+}
+
+/// Parse SRTP key parameters in the format used by `srtpenc` and `srtpdec` caps
+pub(crate) fn srtp_key_params_from_structure(s: &gst::StructureRef) -> Option<KeyParams> {
+    let key = s.get::<gst::Buffer>("srtp-key").ok()?;
+    let key = key.map_readable().ok()?.to_vec();
+
+    let cipher = |field| s.get::<&str>(field).ok().and_then(Cipher::from_name);
+    let auth = |field| s.get::<&str>(field).ok().and_then(Auth::from_name);
+
+    Some(KeyParams {
+        rtp_cipher: cipher("srtp-cipher")?,
+        rtp_auth: auth("srtp-auth")?,
+        rtcp_cipher: cipher("srtcp-cipher")?,
+        rtcp_auth: auth("srtcp-auth")?,
+        key,
+        roc: s.get::<u32>("roc").unwrap_or(0),
+    })
+}
+
+fn srtp_key_params_to_structure(params: &KeyParams) -> gst::Structure {
+    gst::Structure::builder("application/x-srtp")
+        .field("srtp-key", gst::Buffer::from_slice(params.key.clone()))
+        .field("srtp-cipher", params.rtp_cipher.name())
+        .field("srtp-auth", params.rtp_auth.name())
+        .field("srtcp-cipher", params.rtcp_cipher.name())
+        .field("srtcp-auth", params.rtcp_auth.name())
+        .field("roc", params.roc)
+        .build()
+}
+
+/// Converts `application/x-srtp` caps to `application/x-rtp` caps without the SRTP fields
+pub(crate) fn srtp_caps_to_rtp(caps: &gst::CapsRef) -> gst::Caps {
+    let mut caps = caps.to_owned();
+    for s in caps.make_mut().iter_mut() {
+        if s.name() == "application/x-srtp" {
+            s.set_name("application/x-rtp");
+        }
+        s.remove_fields([
+            "srtp-key",
+            "srtp-cipher",
+            "srtp-auth",
+            "srtcp-cipher",
+            "srtcp-auth",
+            "roc",
+            "mki",
+        ]);
+    }
+    caps
+}
+
+/// Create a buffer with the given data and all metadata of `buffer`
+pub(crate) fn buffer_with_data(buffer: &gst::BufferRef, data: Vec<u8>) -> gst::Buffer {
+    let mut ret = gst::Buffer::from_mut_slice(data);
+    let _ = buffer.copy_into(ret.get_mut().unwrap(), gst::BUFFER_COPY_METADATA, ..);
+    ret
+}
+
 pub fn pt_clock_rate_from_caps(caps: &gst::CapsRef) -> Option<(u8, u32)> {
     let Some(s) = caps.structure(0) else {
         gst::debug!(CAT, "no structure!");
@@ -4,12 +4,18 @@ use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::time::{Duration, Instant};
 
+// Gaps larger than this are considered a discontinuity of the stream and are not
+// requested for retransmission
+const MAX_NACK_GAP: u64 = 128;
+
 #[derive(Debug, Clone, Copy)]
 struct Stats {
     num_late: u64,
     num_lost: u64,
     num_duplicates: u64,
     num_pushed: u64,
+    num_requested: u64,
+    num_repaired: u64,
 }
 
 impl From<Stats> for gst::Structure {
@@ -19,6 +25,8 @@ impl From<Stats> for gst::Structure {
             .field("num-duplicates", stats.num_duplicates)
             .field("num-lost", stats.num_lost)
             .field("num-pushed", stats.num_pushed)
+            .field("num-requested", stats.num_requested)
+            .field("num-repaired", stats.num_repaired)
             .build()
     }
 }
@@ -32,6 +40,10 @@ pub struct JitterBuffer {
     // queue
     seqnums: BTreeSet<u64>,
     items: BTreeSet<Item>,
+    // Extended seqnums detected as missing that were not requested for retransmission yet
+    missing_seqnums: BTreeSet<u64>,
+    // Extended seqnums that were requested for retransmission
+    requested_seqnums: BTreeSet<u64>,
     latency: Duration,
     // Arrival time, PTS
     base_times: Option<(Instant, u64)>,
@@ -97,6 +109,8 @@ impl JitterBuffer {
             packet_counter: 0,
             seqnums: BTreeSet::new(),
             items: BTreeSet::new(),
+            missing_seqnums: BTreeSet::new(),
+            requested_seqnums: BTreeSet::new(),
             latency,
             base_times: None,
             last_input_ts: None,
@@ -107,6 +121,8 @@ impl JitterBuffer {
                 num_lost: 0,
                 num_duplicates: 0,
                 num_pushed: 0,
+                num_requested: 0,
+                num_repaired: 0,
             },
             flushing: true,
         }
@@ -168,6 +184,9 @@ impl JitterBuffer {
             return QueueResult::Duplicate;
         }
 
+        // Gaps are detected against the highest sequence number that was queued or output, so
+        // that they are also detected while the queue is empty
+        let max_seqnum = self.seqnums.last().copied().max(self.last_output_seqnum);
         self.seqnums.insert(seqnum);
 
         if let Some(last_output_seqnum) = self.last_output_seqnum {
@@ -182,6 +201,21 @@ impl JitterBuffer {
             }
         }
 
+        if self.requested_seqnums.remove(&seqnum) {
+            trace!("Received requested packet {seqnum}");
+            self.stats.num_repaired += 1;
+        } else if !self.missing_seqnums.remove(&seqnum) {
+            if let Some(max_seqnum) = max_seqnum.filter(|&max_seqnum| seqnum > max_seqnum + 1) {
+                let gap = seqnum - max_seqnum - 1;
+                if gap <= MAX_NACK_GAP {
+                    trace!("Detected {gap} missing packets before {seqnum}");
+                    self.missing_seqnums.extend(max_seqnum + 1..seqnum);
+                } else {
+                    debug!("Gap of {gap} packets before {seqnum} is too large, not requesting");
+                }
+            }
+        }
+
         let id = self.packet_counter;
         self.packet_counter += 1;
         let item = Item {
@@ -257,6 +291,9 @@ impl JitterBuffer {
             };
 
             self.last_output_seqnum = Some(item.seqnum);
+            // Anything before the output position can't be repaired anymore
+            self.missing_seqnums = self.missing_seqnums.split_off(&(item.seqnum + 1));
+            self.requested_seqnums = self.requested_seqnums.split_off(&(item.seqnum + 1));
             // Safe unwrap, we know the queue isn't empty at this point
             let packet = self.items.pop_first().unwrap();
 
@@ -272,6 +309,19 @@ impl JitterBuffer {
         }
     }
 
+    /// Returns the sequence numbers of the packets that were detected as missing since the last
+    /// call and marks them as requested for retransmission.
+    pub fn take_missing_seqnums(&mut self) -> Vec<u16> {
+        let missing = std::mem::take(&mut self.missing_seqnums);
+        self.stats.num_requested += missing.len() as u64;
+        self.requested_seqnums.extend(missing.iter().copied());
+
+        missing
+            .into_iter()
+            .map(|seqnum| (seqnum & 0xffff) as u16)
+            .collect()
+    }
+
     pub fn stats(&self) -> gst::Structure {
         self.stats.into()
     }
@@ -577,6 +627,74 @@ mod tests {
         assert_stats(&jb, 1, 1, 3, 2);
     }
 
+    #[test]
+    fn missing_seqnums() {
+        let mut jb = JitterBuffer::new(Duration::from_secs(1));
+        jb.set_flushing(false);
+
+        let mut now = Instant::now();
+
+        let rtp_data = generate_rtp_packet(0x12345678, 0, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+        assert!(jb.take_missing_seqnums().is_empty());
+
+        // Packets 1 and 2 are missing
+        let rtp_data = generate_rtp_packet(0x12345678, 3, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+        assert_eq!(jb.take_missing_seqnums(), vec![1, 2]);
+        assert!(jb.take_missing_seqnums().is_empty());
+
+        // Packet 1 arrives in time
+        let rtp_data = generate_rtp_packet(0x12345678, 1, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+
+        let stats = jb.stats();
+        assert_eq!(stats.get::<u64>("num-requested").unwrap(), 2);
+        assert_eq!(stats.get::<u64>("num-repaired").unwrap(), 1);
+
+        // Packet 4 is missing but is output before it was requested
+        let rtp_data = generate_rtp_packet(0x12345678, 5, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+
+        now += Duration::from_secs(1);
+        while let PollResult::Forward { .. } = jb.poll(now) {}
+        assert!(jb.take_missing_seqnums().is_empty());
+
+        // Too large gaps are not requested
+        let rtp_data = generate_rtp_packet(0x12345678, 1000, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+        assert!(jb.take_missing_seqnums().is_empty());
+
+        let stats = jb.stats();
+        assert_eq!(stats.get::<u64>("num-requested").unwrap(), 2);
+        assert_eq!(stats.get::<u64>("num-repaired").unwrap(), 1);
+    }
+
+    #[test]
+    fn missing_seqnums_empty_queue() {
+        let mut jb = JitterBuffer::new(Duration::from_secs(0));
+        jb.set_flushing(false);
+
+        let now = Instant::now();
+
+        let rtp_data = generate_rtp_packet(0x12345678, 0, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+        assert!(matches!(jb.poll(now), PollResult::Forward { .. }));
+        assert_eq!(jb.poll(now), PollResult::Empty);
+
+        // Packet 1 is missing while nothing is queued
+        let rtp_data = generate_rtp_packet(0x12345678, 2, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, now);
+        assert_eq!(jb.take_missing_seqnums(), vec![1]);
+    }
+
     #[test]
     fn serialized_items() {
         let mut jb = JitterBuffer::new(Duration::from_secs(0));
@@ -4,14 +4,19 @@ use gst::glib;
 use gst::prelude::*;
 use once_cell::sync::Lazy;
 mod config;
+mod fec;
 mod internal;
 mod jitterbuffer;
 mod rtprecv;
 mod rtpsend;
+mod rtx;
 mod session;
 mod source;
+mod srtp;
 mod sync;
 mod time;
+mod twcc;
+mod xr;
 
 glib::wrapper! {
     pub struct RtpSend(ObjectSubclass<rtpsend::RtpSend>) @extends gst::Element, gst::Object;
@@ -27,6 +32,8 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
             .mark_as_plugin_api(gst::PluginAPIFlags::empty());
         crate::rtpbin2::config::Rtp2Session::static_type()
             .mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        crate::rtpbin2::config::XrFlags::static_type()
+            .mark_as_plugin_api(gst::PluginAPIFlags::empty());
         crate::rtpbin2::rtpsend::Profile::static_type()
             .mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
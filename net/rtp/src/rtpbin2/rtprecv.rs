@@ -12,18 +12,23 @@ use futures::StreamExt;
 use gst::{glib, prelude::*, subclass::prelude::*};
 use once_cell::sync::Lazy;
 
-use super::internal::{pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession};
+use super::internal::{
+    pt_clock_rate_from_caps, srtp_caps_to_rtp, GstRustLogger, SharedRtpState, SharedSession,
+};
 use super::jitterbuffer::{self, JitterBuffer};
 use super::session::{
-    KeyUnitRequestType, RecvReply, RequestRemoteKeyUnitReply, RtcpRecvReply, RtpProfile,
-    RTCP_MIN_REPORT_INTERVAL,
+    KeyUnitRequestType, RecvReply, RequestNackReply, RequestRemoteKeyUnitReply, RtcpRecvReply,
+    RtpProfile, SendReply, RTCP_MIN_REPORT_INTERVAL,
 };
 use super::source::SourceState;
+use super::srtp;
 use super::sync;
+use super::twcc;
 
 use crate::rtpbin2::RUNTIME;
 
 const DEFAULT_LATENCY: gst::ClockTime = gst::ClockTime::from_mseconds(200);
+const DEFAULT_TWCC_FEEDBACK_INTERVAL: gst::ClockTime = gst::ClockTime::from_mseconds(100);
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -38,6 +43,7 @@ struct Settings {
     rtp_id: String,
     latency: gst::ClockTime,
     timestamping_mode: sync::TimestampingMode,
+    twcc_feedback_interval: gst::ClockTime,
 }
 
 impl Default for Settings {
@@ -46,6 +52,7 @@ impl Default for Settings {
             rtp_id: String::from("rtp-id"),
             latency: DEFAULT_LATENCY,
             timestamping_mode: sync::TimestampingMode::default(),
+            twcc_feedback_interval: DEFAULT_TWCC_FEEDBACK_INTERVAL,
         }
     }
 }
@@ -296,10 +303,16 @@ struct RecvSession {
 }
 
 impl RecvSession {
-    fn new(shared_state: &SharedRtpState, id: usize) -> Self {
+    fn new(shared_state: &SharedRtpState, id: usize, settings: &Settings) -> Self {
         let internal_session = shared_state.session_get_or_init(id, || {
             SharedSession::new(id, RtpProfile::Avp, RTCP_MIN_REPORT_INTERVAL, false)
         });
+        internal_session
+            .inner
+            .lock()
+            .unwrap()
+            .session
+            .set_twcc_feedback_interval(settings.twcc_feedback_interval.into());
         Self {
             internal_session,
             rtp_recv_sinkpad: None,
@@ -631,6 +644,7 @@ impl RtpRecv {
         gst::Iterator::from_vec(vec![])
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_buffer_locked<const H: usize, const P: usize>(
         &self,
         pad: &gst::Pad,
@@ -639,6 +653,59 @@ impl RtpRecv {
         now: Instant,
         items_to_pre_push: &mut smallvec::SmallVec<[HeldRecvItem; P]>,
         held_buffers: &mut smallvec::SmallVec<[HeldRecvBuffer; H]>,
+        recovered: &mut Vec<gst::Buffer>,
+    ) -> Result<RecvRtpBuffer, gst::FlowError> {
+        {
+            let mut session_inner = session.internal_session.inner.lock().unwrap();
+            if session_inner.srtp_recv.is_enabled() {
+                // SRTCP packets muxed with the RTP stream are unprotected by the RTCP handling
+                if buffer
+                    .map_readable()
+                    .is_ok_and(|mapped| srtp::is_rtcp(&mapped))
+                {
+                    return Ok(RecvRtpBuffer::IsRtcp(buffer));
+                }
+
+                buffer = match session_inner.srtp_unprotect(buffer, false) {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        gst::debug!(CAT, obj = pad, "Failed to unprotect srtp packet: {err}");
+                        return Ok(RecvRtpBuffer::Drop);
+                    }
+                };
+            }
+
+            // Retransmissions are handled like the original packets from here on
+            buffer = match session_inner.restore_rtx_packet(buffer) {
+                Some(buffer) => buffer,
+                None => {
+                    gst::debug!(
+                        CAT,
+                        obj = pad,
+                        "Dropping RTX packet that can't be associated with a stream"
+                    );
+                    return Ok(RecvRtpBuffer::Drop);
+                }
+            };
+
+            // FEC packets are only used for recovering lost packets, which are then handled like
+            // received packets by the caller
+            if session_inner.recover_fec_packets(&buffer, recovered) {
+                return Ok(RecvRtpBuffer::Drop);
+            }
+        }
+
+        self.handle_rtp_buffer_locked(pad, session, buffer, now, items_to_pre_push, held_buffers)
+    }
+
+    fn handle_rtp_buffer_locked<const H: usize, const P: usize>(
+        &self,
+        pad: &gst::Pad,
+        session: &mut RecvSession,
+        mut buffer: gst::Buffer,
+        now: Instant,
+        items_to_pre_push: &mut smallvec::SmallVec<[HeldRecvItem; P]>,
+        held_buffers: &mut smallvec::SmallVec<[HeldRecvBuffer; H]>,
     ) -> Result<RecvRtpBuffer, gst::FlowError> {
         // TODO: this is different from the old C implementation, where we
         // simply used the RTP timestamps as they were instead of doing any
@@ -677,6 +744,7 @@ impl RtpRecv {
                         .map(|a| a.into())
                         .ok()
                 });
+
         let mapped = buffer.map_readable().map_err(|e| {
             gst::error!(CAT, imp = self, "Failed to map input buffer {e:?}");
             gst::FlowError::Error
@@ -731,6 +799,21 @@ impl RtpRecv {
             .unwrap();
         gst::debug!(CAT, obj = pad, "Calculated PTS: {}", pts);
 
+        if let Some(twcc_seqnum) = session_inner
+            .twcc_ext_id(rtp.payload_type())
+            .and_then(|ext_id| twcc::read_seqnum(&rtp, ext_id))
+        {
+            if session_inner
+                .session
+                .handle_twcc_recv(rtp.ssrc(), twcc_seqnum, now)
+            {
+                if let Some(waker) = session_inner.rtcp_waker.take() {
+                    // wake the rtcp task to schedule the feedback
+                    waker.wake();
+                }
+            }
+        }
+
         loop {
             let recv_ret = session_inner.session.handle_recv(&rtp, addr, now);
             gst::trace!(CAT, obj = pad, "session handle_recv ret: {recv_ret:?}");
@@ -811,6 +894,42 @@ impl RtpRecv {
         Ok(RecvRtpBuffer::Drop)
     }
 
+    /// Handle packets recovered from FEC like received packets
+    #[allow(clippy::too_many_arguments)]
+    fn handle_recovered_buffers_locked<const H: usize, const P: usize>(
+        &self,
+        pad: &gst::Pad,
+        session: &mut RecvSession,
+        recovered: Vec<gst::Buffer>,
+        now: Instant,
+        items_to_pre_push: &mut smallvec::SmallVec<[HeldRecvItem; P]>,
+        held_buffers: &mut smallvec::SmallVec<[HeldRecvBuffer; H]>,
+        ssrc_collision: &mut smallvec::SmallVec<[u32; 4]>,
+    ) -> Result<(), gst::FlowError> {
+        for buffer in recovered {
+            match self.handle_rtp_buffer_locked(
+                pad,
+                session,
+                buffer,
+                now,
+                items_to_pre_push,
+                held_buffers,
+            )? {
+                RecvRtpBuffer::SsrcCollision(ssrc) => ssrc_collision.push(ssrc),
+                RecvRtpBuffer::Forward((buffer, jb)) => {
+                    items_to_pre_push.push(HeldRecvItem::Buffer(HeldRecvBuffer {
+                        hold_id: None,
+                        buffer,
+                        jb,
+                    }))
+                }
+                RecvRtpBuffer::IsRtcp(_) | RecvRtpBuffer::Drop => (),
+            }
+        }
+
+        Ok(())
+    }
+
     fn handle_ssrc_collision(
         &self,
         session: &mut RecvSession,
@@ -872,6 +991,10 @@ impl RtpRecv {
                         }
                     };
 
+                    let ssrc = rtp.ssrc();
+                    let nack_enabled = Self::nack_enabled(&state, id, rtp.payload_type());
+                    let mut missing_seqnums = Vec::new();
+
                     // FIXME: Should block if too many packets are stored here because the source pad task
                     // is blocked
                     let mut jitterbuffer_store = buffer.jb.lock().unwrap();
@@ -889,6 +1012,11 @@ impl RtpRecv {
                         jitterbuffer::QueueResult::Queued(id) => {
                             drop(mapped);
 
+                            if nack_enabled {
+                                missing_seqnums =
+                                    jitterbuffer_store.jitterbuffer.take_missing_seqnums();
+                            }
+
                             jitterbuffer_store
                                 .store
                                 .insert(id, JitterBufferItem::Packet(buffer.buffer));
@@ -903,8 +1031,24 @@ impl RtpRecv {
                             gst::warning!(CAT, "Duplicate buffer was dropped");
                         }
                     }
+                    drop(jitterbuffer_store);
+
+                    if !missing_seqnums.is_empty() {
+                        self.request_nack(&state, id, ssrc, missing_seqnums, now);
+                    }
                 }
                 HeldRecvItem::BufferList(list) => {
+                    // All packets of the list end up in the same jitterbuffer and have the same
+                    // ssrc and pt
+                    let pt_ssrc = list.list.get(0).and_then(|buffer| {
+                        let mapped = buffer.map_readable().ok()?;
+                        let rtp = rtp_types::RtpPacket::parse(&mapped).ok()?;
+                        Some((rtp.payload_type(), rtp.ssrc()))
+                    });
+                    let nack_enabled =
+                        pt_ssrc.is_some_and(|(pt, _ssrc)| Self::nack_enabled(&state, id, pt));
+                    let mut missing_seqnums = Vec::new();
+
                     // FIXME: Should block if too many packets are stored here because the source pad task
                     // is blocked
                     let mut jitterbuffer_store = list.jb.lock().unwrap();
@@ -939,6 +1083,12 @@ impl RtpRecv {
                             jitterbuffer::QueueResult::Queued(id) => {
                                 drop(mapped);
 
+                                if nack_enabled {
+                                    missing_seqnums.extend(
+                                        jitterbuffer_store.jitterbuffer.take_missing_seqnums(),
+                                    );
+                                }
+
                                 jitterbuffer_store
                                     .store
                                     .insert(id, JitterBufferItem::Packet(buffer));
@@ -955,6 +1105,11 @@ impl RtpRecv {
                             }
                         }
                     }
+                    drop(jitterbuffer_store);
+
+                    if let Some((_pt, ssrc)) = pt_ssrc.filter(|_| !missing_seqnums.is_empty()) {
+                        self.request_nack(&state, id, ssrc, missing_seqnums, now);
+                    }
                 }
             }
         }
@@ -962,6 +1117,128 @@ impl RtpRecv {
         Ok(state)
     }
 
+    fn nack_enabled(state: &State, id: usize, pt: u8) -> bool {
+        let Some(session) = state.session_by_id(id) else {
+            return false;
+        };
+        let session_inner = session.internal_session.inner.lock().unwrap();
+        let caps = session_inner.caps_from_pt(pt);
+
+        caps.structure(0)
+            .is_some_and(|s| s.has_field("rtcp-fb-nack"))
+    }
+
+    fn request_nack(&self, state: &State, id: usize, ssrc: u32, seqnums: Vec<u16>, now: Instant) {
+        let Some(session) = state.session_by_id(id) else {
+            return;
+        };
+        let latency = self.settings.lock().unwrap().latency;
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Requesting retransmission of {seqnums:?} for ssrc {ssrc}"
+        );
+
+        let mut session_inner = session.internal_session.inner.lock().unwrap();
+        session_inner.rtx_recv.add_requested(ssrc, &seqnums);
+        let replies = session_inner
+            .session
+            .request_nack(now, ssrc, seqnums, latency.into());
+        for reply in replies {
+            match reply {
+                RequestNackReply::TimerReconsideration => {
+                    if let Some(waker) = session_inner.rtcp_waker.take() {
+                        // reconsider timers means that we wake the rtcp task to get a new timeout
+                        waker.wake();
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_nack(
+        &self,
+        internal_session: &SharedSession,
+        ssrc: u32,
+        seqnums: Vec<u16>,
+        now: Instant,
+    ) {
+        let mut session_inner = internal_session.inner.lock().unwrap();
+        let (Some(sinkpad), Some(srcpad)) = (
+            session_inner.rtp_send_sinkpad.clone(),
+            session_inner.rtp_send_srcpad.clone(),
+        ) else {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Can't retransmit packets because of missing send pads"
+            );
+            return;
+        };
+
+        let mut buffers = Vec::new();
+        for seqnum in seqnums {
+            let Some(data) = session_inner.rtx.retransmit(ssrc, seqnum) else {
+                continue;
+            };
+            let Ok(rtp) = rtp_types::RtpPacket::parse(&data) else {
+                continue;
+            };
+
+            let passthrough = loop {
+                match session_inner.session.handle_send(&rtp, now) {
+                    SendReply::NewSsrc(rtx_ssrc, _pt) => {
+                        drop(session_inner);
+                        internal_session
+                            .config
+                            .emit_by_name::<()>("new-ssrc", &[&rtx_ssrc]);
+                        session_inner = internal_session.inner.lock().unwrap();
+                    }
+                    SendReply::Passthrough => break true,
+                    SendReply::SsrcCollision(_) | SendReply::Drop => break false,
+                }
+            };
+
+            if !passthrough {
+                continue;
+            }
+
+            // Retransmissions are reported in the congestion control feedback like any other
+            // sent packet
+            let twcc_data = session_inner.stamp_twcc_packet(
+                &rtp,
+                data.len(),
+                gst::SystemClock::obtain().time().unwrap(),
+            );
+            let mut data = twcc_data.unwrap_or(data);
+            if session_inner.srtp_send.is_enabled() {
+                if let Err(err) = session_inner.srtp_send.protect_rtp(&mut data) {
+                    gst::warning!(CAT, imp = self, "Failed to protect RTX packet: {err}");
+                    continue;
+                }
+            }
+            buffers.push(gst::Buffer::from_mut_slice(data));
+        }
+        drop(session_inner);
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Retransmitting {} packets for ssrc {ssrc}",
+            buffers.len()
+        );
+
+        // Serialize with the packets sent by rtpsend
+        let _stream_lock = sinkpad.stream_lock();
+        for buffer in buffers {
+            if let Err(err) = srcpad.push(buffer) {
+                gst::debug!(CAT, obj = srcpad, "Failed to push RTX packet: {err:?}");
+                break;
+            }
+        }
+    }
+
     fn rtp_sink_chain_list(
         &self,
         pad: &gst::Pad,
@@ -978,6 +1255,7 @@ impl RtpRecv {
         let mut items_to_pre_push: smallvec::SmallVec<[HeldRecvItem; 4]> =
             smallvec::SmallVec::with_capacity(list.len() + 2);
         let mut held_buffers: smallvec::SmallVec<[HeldRecvBuffer; 4]> = Default::default();
+        let mut recovered = Vec::new();
         let mut split_bufferlist = false;
         let mut previous_jb = None;
         let list_mut = list.make_mut();
@@ -990,6 +1268,7 @@ impl RtpRecv {
                 now,
                 &mut items_to_pre_push,
                 &mut held_buffers,
+                &mut recovered,
             ) {
                 Ok(RecvRtpBuffer::SsrcCollision(ssrc)) => {
                     ssrc_collision.push(ssrc);
@@ -1025,6 +1304,15 @@ impl RtpRecv {
             }
         });
         ret?;
+        self.handle_recovered_buffers_locked(
+            pad,
+            session,
+            recovered,
+            now,
+            &mut items_to_pre_push,
+            &mut held_buffers,
+            &mut ssrc_collision,
+        )?;
         session
             .recv_store
             .extend(held_buffers.into_iter().map(HeldRecvItem::Buffer));
@@ -1059,14 +1347,12 @@ impl RtpRecv {
             });
             state = maybe_state.unwrap();
             ret?;
-        } else {
+        } else if let Some(jb) = previous_jb {
+            // The list is empty if all packets were dropped, e.g. FEC packets
             state = self.handle_push_jitterbuffer(
                 state,
                 id,
-                [HeldRecvItem::BufferList(HeldRecvBufferList {
-                    list,
-                    jb: previous_jb.unwrap(),
-                })],
+                [HeldRecvItem::BufferList(HeldRecvBufferList { list, jb })],
                 now,
             )?;
         }
@@ -1089,6 +1375,8 @@ impl RtpRecv {
         let now = Instant::now();
         let mut items_to_pre_push: smallvec::SmallVec<[HeldRecvItem; 4]> = Default::default();
         let mut held_buffers: smallvec::SmallVec<[HeldRecvBuffer; 4]> = Default::default();
+        let mut ssrc_collision: smallvec::SmallVec<[u32; 4]> = Default::default();
+        let mut recovered = Vec::new();
         let forward = match self.handle_buffer_locked(
             pad,
             session,
@@ -1096,18 +1384,31 @@ impl RtpRecv {
             now,
             &mut items_to_pre_push,
             &mut held_buffers,
+            &mut recovered,
         )? {
             RecvRtpBuffer::SsrcCollision(ssrc) => {
-                return self.handle_ssrc_collision(session, [ssrc])
+                ssrc_collision.push(ssrc);
+                None
             }
             RecvRtpBuffer::IsRtcp(buffer) => return Self::rtcp_sink_chain(self, id, buffer),
             RecvRtpBuffer::Drop => None,
             RecvRtpBuffer::Forward((buffer, jb)) => Some((buffer, jb)),
         };
+        self.handle_recovered_buffers_locked(
+            pad,
+            session,
+            recovered,
+            now,
+            &mut items_to_pre_push,
+            &mut held_buffers,
+            &mut ssrc_collision,
+        )?;
         session
             .recv_store
             .extend(held_buffers.into_iter().map(HeldRecvItem::Buffer));
 
+        self.handle_ssrc_collision(session, ssrc_collision)?;
+
         state = self.handle_push_jitterbuffer(state, id, items_to_pre_push, now)?;
         if let Some((buffer, jb)) = forward {
             state = self.handle_push_jitterbuffer(
@@ -1136,6 +1437,20 @@ impl RtpRecv {
             return Err(gst::FlowError::Error);
         };
 
+        let buffer = match session
+            .internal_session
+            .inner
+            .lock()
+            .unwrap()
+            .srtp_unprotect(buffer, true)
+        {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                gst::debug!(CAT, imp = self, "Failed to unprotect srtcp packet: {err}");
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
         let addr: Option<SocketAddr> =
             buffer
                 .meta::<gst_net::NetAddressMeta>()
@@ -1171,6 +1486,13 @@ impl RtpRecv {
             session_inner
                 .session
                 .handle_rtcp_recv(rtcp, mapped.len(), addr, now, ntp_now);
+        session_inner
+            .session
+            .handle_rtcp_xr_recv(&mapped, now, ntp_now);
+        let twcc_packets = twcc::parse_feedback(&mapped)
+            .iter()
+            .flat_map(|feedback| session_inner.twcc.handle_feedback(feedback))
+            .collect::<Vec<_>>();
         let rtp_send_sinkpad = session_inner.rtp_send_sinkpad.clone();
         drop(session_inner);
         drop(state);
@@ -1240,13 +1562,63 @@ impl RtpRecv {
                         .unwrap()
                         .add_sender_report(ssrc, rtp, ntp);
                 }
-                RtcpRecvReply::SsrcBye(ssrc) => internal_session
-                    .config
-                    .emit_by_name::<()>("bye-ssrc", &[&ssrc]),
+                RtcpRecvReply::SsrcBye(ssrc) => {
+                    internal_session
+                        .inner
+                        .lock()
+                        .unwrap()
+                        .rtx_recv
+                        .remove_ssrc(ssrc);
+                    internal_session
+                        .config
+                        .emit_by_name::<()>("bye-ssrc", &[&ssrc])
+                }
+                RtcpRecvReply::Nack { ssrc, seqnums } => {
+                    self.handle_nack(&internal_session, ssrc, seqnums, now)
+                }
+                RtcpRecvReply::App(app) => internal_session.config.emit_by_name::<()>(
+                    "app-received",
+                    &[
+                        &app.ssrc,
+                        &app.name,
+                        &(app.sub_type as u32),
+                        &glib::Bytes::from_owned(app.data),
+                    ],
+                ),
             }
         }
         drop(mapped);
 
+        if !twcc_packets.is_empty() {
+            if let Some(ref rtp_send_sinkpad) = rtp_send_sinkpad {
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Received transport-wide cc feedback for {} packets",
+                    twcc_packets.len()
+                );
+                let packets = glib::ValueArray::new(twcc_packets.iter().map(|packet| {
+                    gst::Structure::builder("RTPTWCCPacket")
+                        .field("seqnum", packet.seqnum as u32)
+                        .field("local-ts", packet.local_ts)
+                        .field_if_some("remote-ts", packet.remote_ts)
+                        .field("size", packet.size)
+                        .field("lost", packet.remote_ts.is_none())
+                        .field("ssrc", packet.ssrc)
+                        .field("pt", packet.pt as u32)
+                        .build()
+                }));
+                let event = gst::event::CustomUpstream::builder(
+                    gst::Structure::builder("RTPTWCCPackets")
+                        .field("packets", packets)
+                        .build(),
+                )
+                .build();
+
+                let _ = rtp_send_sinkpad.push_event(event);
+            }
+        }
+
         Ok(gst::FlowSuccess::Ok)
     }
 
@@ -1372,11 +1744,13 @@ impl RtpRecv {
 
                 if let Some((pt, clock_rate)) = pt_clock_rate_from_caps(caps.caps()) {
                     if let Some(session) = state.mut_session_by_id(id) {
-                        let caps = caps.caps_owned();
+                        let srtp_caps = caps.caps();
+                        let caps = srtp_caps_to_rtp(srtp_caps);
                         session.rtp_recv_sink_caps = Some(caps.clone());
 
                         let mut session_inner = session.internal_session.inner.lock().unwrap();
                         session_inner.session.set_pt_clock_rate(pt, clock_rate);
+                        session_inner.set_srtp_key_from_caps(false, srtp_caps);
                         session_inner.add_caps(caps);
                     }
                 } else {
@@ -1596,6 +1970,13 @@ impl ObjectImpl for RtpRecv {
                     .default_value(sync::TimestampingMode::default())
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecUInt::builder("twcc-feedback-interval")
+                    .nick("TWCC Feedback Interval")
+                    .blurb("Interval in ms between transport-wide congestion control feedback packets")
+                    .minimum(1)
+                    .default_value(DEFAULT_TWCC_FEEDBACK_INTERVAL.mseconds() as u32)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -1627,6 +2008,12 @@ impl ObjectImpl for RtpRecv {
                     .get::<sync::TimestampingMode>()
                     .expect("Type checked upstream");
             }
+            "twcc-feedback-interval" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.twcc_feedback_interval = gst::ClockTime::from_mseconds(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
             _ => unimplemented!(),
         }
     }
@@ -1649,6 +2036,10 @@ impl ObjectImpl for RtpRecv {
                 let settings = self.settings.lock().unwrap();
                 settings.timestamping_mode.to_value()
             }
+            "twcc-feedback-interval" => {
+                let settings = self.settings.lock().unwrap();
+                (settings.twcc_feedback_interval.mseconds() as u32).to_value()
+            }
             _ => unimplemented!(),
         }
     }
@@ -1696,8 +2087,13 @@ impl ElementImpl for RtpRecv {
             let rtp_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtp").build())
                 .build();
-            let rtcp_caps = gst::Caps::builder_full()
+            let rtp_sink_caps = gst::Caps::builder_full()
+                .structure(gst::Structure::builder("application/x-rtp").build())
+                .structure(gst::Structure::builder("application/x-srtp").build())
+                .build();
+            let rtcp_sink_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtcp").build())
+                .structure(gst::Structure::builder("application/x-srtcp").build())
                 .build();
 
             vec![
@@ -1705,14 +2101,14 @@ impl ElementImpl for RtpRecv {
                     "rtp_sink_%u",
                     gst::PadDirection::Sink,
                     gst::PadPresence::Request,
-                    &rtp_caps,
+                    &rtp_sink_caps,
                 )
                 .unwrap(),
                 gst::PadTemplate::new(
                     "rtcp_sink_%u",
                     gst::PadDirection::Sink,
                     gst::PadPresence::Request,
-                    &rtcp_caps,
+                    &rtcp_sink_caps,
                 )
                 .unwrap(),
                 gst::PadTemplate::new(
@@ -1815,7 +2211,7 @@ impl ElementImpl for RtpRecv {
                     let shared_state = state
                         .shared_state
                         .get_or_insert_with(|| SharedRtpState::recv_get_or_init(rtp_id));
-                    let mut session = RecvSession::new(shared_state, id);
+                    let mut session = RecvSession::new(shared_state, id, &settings);
                     let ret = new_pad(&mut session);
                     state.sessions.push(session);
                     ret
@@ -1860,7 +2256,7 @@ impl ElementImpl for RtpRecv {
                     let shared_state = state
                         .shared_state
                         .get_or_insert_with(|| SharedRtpState::recv_get_or_init(rtp_id));
-                    let mut session = RecvSession::new(shared_state, id);
+                    let mut session = RecvSession::new(shared_state, id, &settings);
                     let ret = new_pad(&mut session);
                     state.sessions.push(session);
                     ret
@@ -11,7 +11,9 @@ use futures::StreamExt;
 use gst::{glib, prelude::*, subclass::prelude::*};
 use once_cell::sync::Lazy;
 
-use super::internal::{pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession};
+use super::internal::{
+    buffer_with_data, pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession,
+};
 use super::session::{RtcpSendReply, RtpProfile, SendReply, RTCP_MIN_REPORT_INTERVAL};
 use super::source::SourceState;
 
@@ -209,7 +211,15 @@ impl SendSession {
                     continue;
                 };
                 match reply {
-                    RtcpSendReply::Data(data) => {
+                    RtcpSendReply::Data(mut data) => {
+                        let mut session_inner = session.internal_session.inner.lock().unwrap();
+                        if session_inner.srtp_send.is_enabled() {
+                            if let Err(err) = session_inner.srtp_send.protect_rtcp(&mut data) {
+                                gst::warning!(CAT, "Failed to protect rtcp packet: {err}");
+                                continue;
+                            }
+                        }
+                        drop(session_inner);
                         session.rtcp_send_srcpad.clone().map(|pad| (pad, data))
                     }
                     RtcpSendReply::SsrcBye(ssrc) => {
@@ -356,8 +366,67 @@ impl RtpSend {
                 SendReply::Drop => return Ok(gst::FlowSuccess::Ok),
             }
         }
+        session_inner.store_rtx_packet(&rtp, &buffer, now);
+        // Use the same clock as the congestion control for the departure time
+        let twcc_data = session_inner.stamp_twcc_packet(
+            &rtp,
+            mapped.len(),
+            gst::SystemClock::obtain().time().unwrap(),
+        );
+        // FEC packets protect the packet as it is received after SRTP unprotection, i.e. including
+        // the transport-wide sequence number
+        let fec_packets = session_inner
+            .protect_fec_packet(&rtp, twcc_data.as_deref().unwrap_or(mapped.as_slice()));
         // TODO: handle other processing
         drop(mapped);
+        let buffer = match twcc_data {
+            Some(data) => buffer_with_data(&buffer, data),
+            None => buffer,
+        };
+        let buffer = match session_inner.srtp_protect(buffer) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to protect rtp packet: {err}");
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        let mut fec_buffers = Vec::with_capacity(fec_packets.len());
+        for data in fec_packets {
+            let Ok(fec_rtp) = rtp_types::RtpPacket::parse(&data) else {
+                continue;
+            };
+
+            let passthrough = loop {
+                match session_inner.session.handle_send(&fec_rtp, now) {
+                    SendReply::NewSsrc(fec_ssrc, _pt) => {
+                        drop(session_inner);
+                        internal_session
+                            .config
+                            .emit_by_name::<()>("new-ssrc", &[&fec_ssrc]);
+                        session_inner = internal_session.inner.lock().unwrap();
+                    }
+                    SendReply::Passthrough => break true,
+                    SendReply::SsrcCollision(_) | SendReply::Drop => break false,
+                }
+            };
+            if !passthrough {
+                continue;
+            }
+
+            let twcc_data = session_inner.stamp_twcc_packet(
+                &fec_rtp,
+                data.len(),
+                gst::SystemClock::obtain().time().unwrap(),
+            );
+            let fec_buffer = buffer_with_data(&buffer, twcc_data.unwrap_or(data));
+            match session_inner.srtp_protect(fec_buffer) {
+                Ok(fec_buffer) => fec_buffers.push(fec_buffer),
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to protect FEC packet: {err}");
+                }
+            }
+        }
         drop(session_inner);
 
         for ssrc in ssrc_collision {
@@ -373,7 +442,12 @@ impl RtpSend {
             );
         }
 
-        srcpad.push(buffer)
+        srcpad.push(buffer)?;
+        for fec_buffer in fec_buffers {
+            srcpad.push(fec_buffer)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
     }
 
     fn rtp_sink_chain_list(
@@ -428,6 +502,7 @@ impl RtpSend {
                         let mut session = session.internal_session.inner.lock().unwrap();
                         session.session.set_pt_clock_rate(pt, clock_rate);
                         session.add_caps(caps.caps_owned());
+                        session.set_srtp_key_from_caps(true, caps.caps());
                     }
                 } else {
                     gst::warning!(
@@ -744,12 +819,10 @@ impl ElementImpl for RtpSend {
                         .build();
                     session.rtp_send_sinkpad = Some(sinkpad.clone());
                     session.rtp_send_srcpad = Some(srcpad.clone());
-                    session
-                        .internal_session
-                        .inner
-                        .lock()
-                        .unwrap()
-                        .rtp_send_sinkpad = Some(sinkpad.clone());
+                    let mut session_inner = session.internal_session.inner.lock().unwrap();
+                    session_inner.rtp_send_sinkpad = Some(sinkpad.clone());
+                    session_inner.rtp_send_srcpad = Some(srcpad.clone());
+                    drop(session_inner);
                     Some((sinkpad, Some(srcpad), id, vec![]))
                 };
 
@@ -855,12 +928,10 @@ impl ElementImpl for RtpSend {
             if let Some(session) = state.mut_session_by_id(id) {
                 if Some(pad) == session.rtp_send_sinkpad.as_ref() {
                     session.rtp_send_sinkpad = None;
-                    session
-                        .internal_session
-                        .inner
-                        .lock()
-                        .unwrap()
-                        .rtp_send_sinkpad = None;
+                    let mut session_inner = session.internal_session.inner.lock().unwrap();
+                    session_inner.rtp_send_sinkpad = None;
+                    session_inner.rtp_send_srcpad = None;
+                    drop(session_inner);
 
                     if let Some(srcpad) = session.rtp_send_srcpad.take() {
                         removed_pads.push(srcpad);
@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Retransmission of RTP packets in the RTX payload format as specified in RFC 4588.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rtp_types::{RtpPacket, RtpPacketBuilder};
+
+/// Maximum number of packets kept per SSRC for retransmission
+pub const RTX_HISTORY_MAX_PACKETS: usize = 1000;
+/// Maximum age of packets kept for retransmission
+pub const RTX_HISTORY_MAX_AGE: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct HistoryPacket {
+    seqnum: u16,
+    time: Instant,
+    buffer: gst::Buffer,
+}
+
+#[derive(Debug)]
+struct RtxStream {
+    rtx_ssrc: u32,
+    rtx_pt: u8,
+    next_seqnum: u16,
+    history: VecDeque<HistoryPacket>,
+    num_requested: u64,
+    num_sent: u64,
+}
+
+/// Bounded history of sent RTP packets, per SSRC, for retransmission as RTX packets
+#[derive(Debug, Default)]
+pub struct RtxSender {
+    streams: HashMap<u32, RtxStream>,
+}
+
+impl RtxSender {
+    /// Store a sent packet for possible retransmission.  `rtx_ssrc` is called to select the RTX
+    /// SSRC the first time a packet for a new SSRC is stored.
+    pub fn store_packet(
+        &mut self,
+        rtp: &RtpPacket,
+        buffer: &gst::Buffer,
+        rtx_pt: u8,
+        now: Instant,
+        rtx_ssrc: impl FnOnce() -> u32,
+    ) {
+        let stream = self.streams.entry(rtp.ssrc()).or_insert_with(|| RtxStream {
+            rtx_ssrc: rtx_ssrc(),
+            rtx_pt,
+            next_seqnum: rand::random(),
+            history: VecDeque::new(),
+            num_requested: 0,
+            num_sent: 0,
+        });
+        stream.rtx_pt = rtx_pt;
+
+        while stream.history.len() >= RTX_HISTORY_MAX_PACKETS
+            || stream
+                .history
+                .front()
+                .is_some_and(|packet| now.duration_since(packet.time) > RTX_HISTORY_MAX_AGE)
+        {
+            stream.history.pop_front();
+        }
+
+        stream.history.push_back(HistoryPacket {
+            seqnum: rtp.sequence_number(),
+            time: now,
+            buffer: buffer.clone(),
+        });
+    }
+
+    /// Produce a RTX packet for the packet with the given ssrc and sequence number, or `None` if
+    /// the packet is not in the history anymore.
+    pub fn retransmit(&mut self, ssrc: u32, seqnum: u16) -> Option<Vec<u8>> {
+        let stream = self.streams.get_mut(&ssrc)?;
+        stream.num_requested += 1;
+
+        let Some(packet) = stream
+            .history
+            .iter()
+            .rev()
+            .find(|packet| packet.seqnum == seqnum)
+        else {
+            trace!("Packet {seqnum} for ssrc {ssrc} not in history anymore");
+            return None;
+        };
+
+        let mapped = packet.buffer.map_readable().ok()?;
+        let rtp = RtpPacket::parse(&mapped).ok()?;
+
+        // The RTX payload starts with the original sequence number
+        let osn = rtp.sequence_number().to_be_bytes();
+        let mut builder = RtpPacketBuilder::new()
+            .payload_type(stream.rtx_pt)
+            .ssrc(stream.rtx_ssrc)
+            .sequence_number(stream.next_seqnum)
+            .timestamp(rtp.timestamp())
+            .marker_bit(rtp.marker_bit());
+        for csrc in rtp.csrc() {
+            builder = builder.add_csrc(csrc);
+        }
+        if let Some((pattern, data)) = rtp.extension() {
+            builder = builder.extension(pattern, data);
+        }
+        let data = builder
+            .payload(osn.as_slice())
+            .payload(rtp.payload())
+            .write_vec()
+            .ok()?;
+
+        trace!(
+            "Retransmitting packet {seqnum} for ssrc {ssrc} as {} on ssrc {}",
+            stream.next_seqnum,
+            stream.rtx_ssrc
+        );
+        stream.next_seqnum = stream.next_seqnum.wrapping_add(1);
+        stream.num_sent += 1;
+
+        Some(data)
+    }
+
+    /// The RTX SSRC used for the given media SSRC
+    pub fn rtx_ssrc(&self, ssrc: u32) -> Option<u32> {
+        self.streams.get(&ssrc).map(|stream| stream.rtx_ssrc)
+    }
+
+    /// Number of packets requested for retransmission and number of packets retransmitted for the
+    /// given media SSRC
+    pub fn stats(&self, ssrc: u32) -> Option<(u64, u64)> {
+        self.streams
+            .get(&ssrc)
+            .map(|stream| (stream.num_requested, stream.num_sent))
+    }
+}
+
+/// Restoring of the original packets from received RTX packets
+#[derive(Debug, Default)]
+pub struct RtxReceiver {
+    // RTX SSRC to media SSRC
+    ssrc_map: HashMap<u32, u32>,
+    // Sequence numbers requested for retransmission and their media SSRC, used for associating
+    // RTX SSRCs with media SSRCs
+    requested: HashMap<u16, u32>,
+}
+
+impl RtxReceiver {
+    /// Remember sequence numbers that were requested for retransmission for the given media SSRC.
+    pub fn add_requested(&mut self, ssrc: u32, seqnums: &[u16]) {
+        if self.requested.len() + seqnums.len() > RTX_HISTORY_MAX_PACKETS {
+            self.requested.clear();
+        }
+
+        self.requested
+            .extend(seqnums.iter().map(|&seqnum| (seqnum, ssrc)));
+    }
+
+    /// Produce the original packet for a RTX packet with the given associated payload type, or
+    /// `None` if the RTX packet can't be associated with a media SSRC.
+    pub fn restore_packet(&mut self, rtp: &RtpPacket, apt: u8) -> Option<Vec<u8>> {
+        let payload = rtp.payload();
+        if payload.len() < 2 {
+            trace!(
+                "RTX packet {} without original sequence number",
+                rtp.sequence_number()
+            );
+            return None;
+        }
+        let osn = u16::from_be_bytes([payload[0], payload[1]]);
+
+        let ssrc = match self.ssrc_map.get(&rtp.ssrc()) {
+            Some(&ssrc) => ssrc,
+            None => {
+                // A new RTX SSRC is associated with the media SSRC the original sequence number
+                // was requested for, as long as that has no RTX SSRC yet
+                let Some(&ssrc) = self.requested.get(&osn) else {
+                    trace!("Can't associate RTX ssrc {} with packet {osn}", rtp.ssrc());
+                    return None;
+                };
+                if self.ssrc_map.values().any(|&media_ssrc| media_ssrc == ssrc) {
+                    trace!("Ssrc {ssrc} is already associated with another RTX ssrc");
+                    return None;
+                }
+                debug!("Associating RTX ssrc {} with ssrc {ssrc}", rtp.ssrc());
+                self.ssrc_map.insert(rtp.ssrc(), ssrc);
+                ssrc
+            }
+        };
+        self.requested.remove(&osn);
+
+        let mut builder = RtpPacketBuilder::new()
+            .payload_type(apt)
+            .ssrc(ssrc)
+            .sequence_number(osn)
+            .timestamp(rtp.timestamp())
+            .marker_bit(rtp.marker_bit());
+        for csrc in rtp.csrc() {
+            builder = builder.add_csrc(csrc);
+        }
+        if let Some((pattern, data)) = rtp.extension() {
+            builder = builder.extension(pattern, data);
+        }
+
+        trace!(
+            "Restored packet {osn} for ssrc {ssrc} from RTX packet {} on ssrc {}",
+            rtp.sequence_number(),
+            rtp.ssrc()
+        );
+
+        builder.payload(&payload[2..]).write_vec().ok()
+    }
+
+    /// Forget about a RTX or media SSRC
+    pub fn remove_ssrc(&mut self, ssrc: u32) {
+        self.ssrc_map
+            .retain(|&rtx_ssrc, &mut media_ssrc| rtx_ssrc != ssrc && media_ssrc != ssrc);
+        self.requested
+            .retain(|_seqnum, &mut media_ssrc| media_ssrc != ssrc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    const RTX_PT: u8 = 97;
+
+    #[test]
+    fn retransmit() {
+        let mut rtx = RtxSender::default();
+        let now = Instant::now();
+
+        for seqnum in 100..110 {
+            let rtp_data = generate_rtp_packet(0x12345678, seqnum, 0, 4);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            let buffer = gst::Buffer::from_mut_slice(rtp_data.clone());
+            rtx.store_packet(&packet, &buffer, RTX_PT, now, || 0x87654321);
+        }
+        assert_eq!(rtx.rtx_ssrc(0x12345678), Some(0x87654321));
+
+        let data = rtx.retransmit(0x12345678, 105).unwrap();
+        let packet = RtpPacket::parse(&data).unwrap();
+        assert_eq!(packet.ssrc(), 0x87654321);
+        assert_eq!(packet.payload_type(), RTX_PT);
+        assert_eq!(packet.payload(), &[0, 105, 1, 1, 1, 1]);
+        let first_seqnum = packet.sequence_number();
+
+        let data = rtx.retransmit(0x12345678, 100).unwrap();
+        let packet = RtpPacket::parse(&data).unwrap();
+        assert_eq!(packet.sequence_number(), first_seqnum.wrapping_add(1));
+        assert_eq!(packet.payload(), &[0, 100, 1, 1, 1, 1]);
+
+        // Not in the history
+        assert!(rtx.retransmit(0x12345678, 99).is_none());
+        assert!(rtx.retransmit(0x11223344, 100).is_none());
+
+        assert_eq!(rtx.stats(0x12345678), Some((3, 2)));
+    }
+
+    #[test]
+    fn history_bounded() {
+        let mut rtx = RtxSender::default();
+        let mut now = Instant::now();
+
+        for seqnum in 0..RTX_HISTORY_MAX_PACKETS as u16 + 10 {
+            let rtp_data = generate_rtp_packet(0x12345678, seqnum, 0, 4);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            let buffer = gst::Buffer::from_mut_slice(rtp_data.clone());
+            rtx.store_packet(&packet, &buffer, RTX_PT, now, || 0x87654321);
+        }
+        assert!(rtx.retransmit(0x12345678, 9).is_none());
+        assert!(rtx.retransmit(0x12345678, 10).is_some());
+
+        now += RTX_HISTORY_MAX_AGE + Duration::from_millis(1);
+        let rtp_data = generate_rtp_packet(0x12345678, 2000, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        let buffer = gst::Buffer::from_mut_slice(rtp_data.clone());
+        rtx.store_packet(&packet, &buffer, RTX_PT, now, || unreachable!());
+        assert!(rtx.retransmit(0x12345678, 10).is_none());
+        assert!(rtx.retransmit(0x12345678, 2000).is_some());
+    }
+
+    #[test]
+    fn restore() {
+        let mut rtx = RtxSender::default();
+        let now = Instant::now();
+
+        for seqnum in 100..110 {
+            let rtp_data = generate_rtp_packet(0x12345678, seqnum, seqnum as u32 * 10, 4);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            let buffer = gst::Buffer::from_mut_slice(rtp_data.clone());
+            rtx.store_packet(&packet, &buffer, RTX_PT, now, || 0x87654321);
+        }
+
+        let mut rtx_recv = RtxReceiver::default();
+        let rtx_data = rtx.retransmit(0x12345678, 105).unwrap();
+        let rtx_packet = RtpPacket::parse(&rtx_data).unwrap();
+
+        // Not requested, can't be associated with the media ssrc
+        assert!(rtx_recv.restore_packet(&rtx_packet, 96).is_none());
+
+        rtx_recv.add_requested(0x12345678, &[105, 107]);
+        let data = rtx_recv.restore_packet(&rtx_packet, 96).unwrap();
+        assert_eq!(data, generate_rtp_packet(0x12345678, 105, 1050, 4));
+
+        // The RTX ssrc is associated now and unrequested packets can be restored too
+        let rtx_data = rtx.retransmit(0x12345678, 101).unwrap();
+        let rtx_packet = RtpPacket::parse(&rtx_data).unwrap();
+        let data = rtx_recv.restore_packet(&rtx_packet, 96).unwrap();
+        assert_eq!(data, generate_rtp_packet(0x12345678, 101, 1010, 4));
+
+        rtx_recv.remove_ssrc(0x87654321);
+        assert!(rtx_recv.restore_packet(&rtx_packet, 96).is_none());
+    }
+}
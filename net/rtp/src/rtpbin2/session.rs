@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::time::{Duration, Instant, SystemTime};
 
@@ -15,6 +15,8 @@ use super::source::{
     LocalReceiveSource, LocalSendSource, RemoteReceiveSource, RemoteSendSource, SourceState,
 };
 use super::time::system_time_to_ntp_time_u64;
+use super::twcc::TwccReceiver;
+use super::xr::{self, ReceivedXr, XrConfig, XrState};
 
 use gst::prelude::MulDiv;
 
@@ -26,6 +28,8 @@ const RTCP_ADDRESS_CONFLICT_TIMEOUT: Duration = RTCP_MIN_REPORT_INTERVAL.saturat
 // 5% of 8kB/s
 const RTCP_MIN_BANDWIDTH: usize = 400;
 const RTCP_MTU: usize = 1200;
+// Maximum size of all APP packets in a single RTCP compound packet
+const RTCP_MAX_APP_SIZE: usize = RTCP_MTU / 2;
 
 const UDP_IP_OVERHEAD_BYTES: usize = 28;
 
@@ -102,6 +106,12 @@ pub struct Session {
     // time for the next early rtcp to be sent
     next_early_rtcp_time: Option<Instant>,
     pending_rtcp_send: VecDeque<RtcpSendReply>,
+    // transport-wide congestion control feedback
+    twcc: TwccReceiver,
+    // extended reports
+    xr: XrState,
+    // APP packets to send with the next regular rtcp packet
+    pending_app: VecDeque<AppPacket>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -154,6 +164,20 @@ pub enum RtcpRecvReply {
     NewRtpNtp((u32, u32, u64)),
     /// A ssrc has byed
     SsrcBye(u32),
+    /// Retransmission of the given sequence numbers was requested for the given SSRC of ours
+    Nack { ssrc: u32, seqnums: Vec<u16> },
+    /// An application-defined packet was received
+    App(AppPacket),
+}
+
+/// An application-defined RTCP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppPacket {
+    pub ssrc: u32,
+    /// Four ASCII characters
+    pub name: String,
+    pub sub_type: u8,
+    pub data: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -170,6 +194,12 @@ pub enum RequestRemoteKeyUnitReply {
     TimerReconsideration,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RequestNackReply {
+    /// RTCP timer needs to be reconsidered.  Call poll_rtcp_send_timeout() to get the new time
+    TimerReconsideration,
+}
+
 impl Session {
     pub fn new() -> Self {
         let cname = generate_cname();
@@ -202,6 +232,9 @@ impl Session {
             last_rtcp_handle_time: None,
             is_point_to_point: true,
             pending_rtcp_send: VecDeque::new(),
+            twcc: TwccReceiver::default(),
+            xr: XrState::default(),
+            pending_app: VecDeque::new(),
         }
     }
 
@@ -220,6 +253,94 @@ impl Session {
         self.reduced_size_rtcp = reduced_size_rtcp;
     }
 
+    /// Set the SDES items sent for all local sources.  The current CNAME is kept if none is
+    /// provided.
+    pub fn set_sdes(&mut self, items: impl IntoIterator<Item = (u8, String)>) {
+        let mut sdes = items.into_iter().collect::<HashMap<_, _>>();
+        if !sdes.contains_key(&SdesItem::CNAME) {
+            let cname = self
+                .sdes
+                .get(&SdesItem::CNAME)
+                .cloned()
+                .unwrap_or_else(generate_cname);
+            sdes.insert(SdesItem::CNAME, cname);
+        }
+
+        for source in self.local_senders.values_mut() {
+            source.set_sdes(sdes.clone());
+        }
+        for source in self.local_receivers.values_mut() {
+            source.set_sdes(sdes.clone());
+        }
+        self.sdes = sdes;
+    }
+
+    /// The SDES items sent for all local sources
+    pub fn sdes(&self) -> &HashMap<u8, String> {
+        &self.sdes
+    }
+
+    /// Set which extended report blocks are sent with regular RTCP packets
+    pub fn set_xr_config(&mut self, config: XrConfig) {
+        self.xr.set_config(config);
+    }
+
+    pub fn xr_config(&self) -> XrConfig {
+        self.xr.config()
+    }
+
+    /// The extended reports last received from the given SSRC
+    pub fn received_xr(&self, ssrc: u32) -> Option<&ReceivedXr> {
+        self.xr.received(ssrc)
+    }
+
+    /// Queue an APP packet to be sent with the next regular RTCP packet.  Returns `false` if the
+    /// packet is invalid: `name` must be four ASCII characters, `sub_type` must fit into 5 bits
+    /// and the length of `data` must be a multiple of 4 bytes.
+    pub fn send_app(&mut self, name: &str, sub_type: u8, data: Vec<u8>) -> bool {
+        if name.len() != 4
+            || !name.is_ascii()
+            || sub_type > 31
+            || data.len() % 4 != 0
+            || data.len() + 12 > RTCP_MAX_APP_SIZE
+        {
+            return false;
+        }
+
+        self.pending_app.push_back(AppPacket {
+            ssrc: 0,
+            name: name.to_string(),
+            sub_type,
+            data,
+        });
+        true
+    }
+
+    /// Set the interval between transport-wide congestion control feedback packets
+    pub fn set_twcc_feedback_interval(&mut self, interval: Duration) {
+        self.twcc.set_feedback_interval(interval);
+    }
+
+    /// Handle a received RTCP extended report (RFC 3611).  Extended reports are not parsed by
+    /// [`Session::handle_rtcp_recv`] and must be passed here as raw RTCP data.
+    pub fn handle_rtcp_xr_recv(&mut self, data: &[u8], now: Instant, ntp_now: SystemTime) {
+        let ntp_now = system_time_to_ntp_time_u64(ntp_now);
+        for packet in xr::parse_xr(data) {
+            trace!("received extended report from {}", packet.ssrc);
+            let local_senders = &self.local_senders;
+            let local_receivers = &self.local_receivers;
+            self.xr.handle_xr(&packet, now, ntp_now, |ssrc| {
+                local_senders.contains_key(&ssrc) || local_receivers.contains_key(&ssrc)
+            });
+        }
+    }
+
+    /// Handle a received RTP packet carrying a transport-wide sequence number.  Returns `true` if
+    /// a feedback packet has been scheduled and `poll_rtcp_send_timeout()` should be called again.
+    pub fn handle_twcc_recv(&mut self, ssrc: u32, seqnum: u16, now: Instant) -> bool {
+        self.twcc.recv_packet(ssrc, seqnum, now)
+    }
+
     fn n_members(&self) -> usize {
         self.bye_state
             .as_ref()
@@ -357,7 +478,7 @@ impl Session {
         let clock_rate = self.clock_rate_from_pt(rtp.payload_type());
 
         if let Some(source) = self.remote_senders.get_mut(&rtp.ssrc()) {
-            match source.recv_packet(
+            let reply = match source.recv_packet(
                 rtp.payload().len() as u32,
                 now,
                 rtp.sequence_number(),
@@ -374,7 +495,11 @@ impl Session {
                 SourceRecvReply::Ignore => RecvReply::Ignore,
                 SourceRecvReply::Forward(id) => RecvReply::Forward(id),
                 SourceRecvReply::Passthrough => RecvReply::Passthrough,
+            };
+            if !matches!(reply, RecvReply::Ignore | RecvReply::Drop(_)) {
+                self.xr.recv_packet(rtp.ssrc(), rtp.sequence_number(), now);
             }
+            reply
         } else {
             let mut source = RemoteSendSource::new(rtp.ssrc());
             source.set_rtp_from(from);
@@ -550,8 +675,14 @@ impl Session {
         for (i, p) in rtcp.enumerate() {
             trace!("recv rtcp {i}th packet: {p:?}");
             match p {
-                // TODO: actually handle App packets
-                Ok(Packet::App(_app)) => (),
+                Ok(Packet::App(app)) => {
+                    replies.push(RtcpRecvReply::App(AppPacket {
+                        ssrc: app.ssrc(),
+                        name: String::from_utf8_lossy(&app.name()).into_owned(),
+                        sub_type: app.sub_type(),
+                        data: app.data().to_vec(),
+                    }));
+                }
                 Ok(Packet::Bye(bye)) => {
                     // https://datatracker.ietf.org/doc/html/rfc3550#section-6.3.4
                     let n_members = self.n_members();
@@ -731,7 +862,17 @@ impl Session {
                         );
                     }
                 }
-                Ok(Packet::TransportFeedback(_)) | Ok(Packet::Unknown(_)) => (),
+                Ok(Packet::TransportFeedback(tf)) => {
+                    if let Ok(nack) = tf.parse_fci::<rtcp_types::Nack>() {
+                        self.handle_remote_nack(
+                            &mut replies,
+                            tf.sender_ssrc(),
+                            tf.media_ssrc(),
+                            nack.entries(),
+                        );
+                    }
+                }
+                Ok(Packet::Unknown(_)) => (),
                 // TODO: in RFC4585 profile, need to listen for feedback messages and remove any
                 // that we would have sent
                 Err(_) => (),
@@ -791,6 +932,30 @@ impl Session {
         }
     }
 
+    fn handle_remote_nack(
+        &mut self,
+        replies: &mut Vec<RtcpRecvReply>,
+        sender_ssrc: u32,
+        media_ssrc: u32,
+        seqnums: impl Iterator<Item = u16>,
+    ) {
+        if !self.local_senders.contains_key(&media_ssrc) {
+            trace!("Not a local sender for ssrc {media_ssrc}");
+            return;
+        }
+
+        let seqnums = seqnums.collect::<Vec<_>>();
+        if seqnums.is_empty() {
+            return;
+        }
+
+        trace!("Retransmission requested from sender ssrc {sender_ssrc} for media ssrc {media_ssrc}: {seqnums:?}");
+        replies.push(RtcpRecvReply::Nack {
+            ssrc: media_ssrc,
+            seqnums,
+        });
+    }
+
     fn generate_sr<'a>(
         &mut self,
         mut rtcp: CompoundBuilder<'a>,
@@ -1076,6 +1241,55 @@ impl Session {
         rtcp
     }
 
+    fn take_pending_app(&mut self) -> Vec<AppPacket> {
+        let mut apps = vec![];
+        let mut size = 0;
+        while let Some(app) = self.pending_app.front() {
+            let app_size = 12 + app.data.len();
+            if size + app_size > RTCP_MAX_APP_SIZE {
+                break;
+            }
+            size += app_size;
+            apps.extend(self.pending_app.pop_front());
+        }
+        apps
+    }
+
+    // the ssrc used for packets that are not tied to a specific local source
+    fn reporting_ssrc(&mut self) -> u32 {
+        self.local_senders
+            .values()
+            .find(|source| source.state() == SourceState::Normal)
+            .map(|source| source.ssrc())
+            .unwrap_or_else(|| self.ensure_internal_send_src())
+    }
+
+    fn generate_app<'a>(
+        &mut self,
+        mut rtcp: CompoundBuilder<'a>,
+        apps: &'a [AppPacket],
+    ) -> CompoundBuilder<'a> {
+        if apps.is_empty() {
+            return rtcp;
+        }
+
+        let ssrc = self.reporting_ssrc();
+        for app in apps {
+            debug!(
+                "Generating APP packet {} with subtype {} and {} bytes",
+                app.name,
+                app.sub_type,
+                app.data.len()
+            );
+            rtcp = rtcp.add_packet(
+                App::builder(ssrc, &app.name)
+                    .sub_type(app.sub_type)
+                    .data(&app.data),
+            );
+        }
+        rtcp
+    }
+
     fn generate_pli<'a>(
         &mut self,
         mut rtcp: CompoundBuilder<'a>,
@@ -1097,6 +1311,27 @@ impl Session {
         rtcp
     }
 
+    fn generate_nack<'a>(
+        &mut self,
+        mut rtcp: CompoundBuilder<'a>,
+        _now: Instant,
+    ) -> CompoundBuilder<'a> {
+        let ssrc = self.ensure_internal_send_src();
+
+        for source in self.remote_senders.values_mut() {
+            let nack = source.generate_nack();
+            if let Some(nack) = nack {
+                debug!("Generating NACK for sender {}: {:?}", source.ssrc(), nack);
+                rtcp = rtcp.add_packet(
+                    rtcp_types::TransportFeedback::builder_owned(nack)
+                        .sender_ssrc(ssrc)
+                        .media_ssrc(source.ssrc()),
+                );
+            }
+        }
+        rtcp
+    }
+
     fn generate_fir<'a>(
         &mut self,
         mut rtcp: CompoundBuilder<'a>,
@@ -1137,6 +1372,13 @@ impl Session {
             .retain(|_ssrc, source| now - source.last_activity() < td);
         self.remote_receivers
             .retain(|_ssrc, source| now - source.last_activity() < td);
+        let known_ssrcs = self
+            .remote_senders
+            .keys()
+            .chain(self.remote_receivers.keys())
+            .copied()
+            .collect::<HashSet<_>>();
+        self.xr.retain(|ssrc| known_ssrcs.contains(&ssrc));
 
         // There is a SHOULD about performing RTCP reverse timer consideration here if any sources
         // were timed out, however we are here before calculating the next rtcp timeout so are
@@ -1186,6 +1428,19 @@ impl Session {
             return Some(event);
         }
 
+        if self
+            .twcc
+            .next_feedback_time()
+            .is_some_and(|time| now >= time)
+        {
+            let ssrc = self.ensure_internal_send_src();
+            if let Some(data) = self.twcc.generate_feedback(now, ssrc) {
+                trace!("generating transport-wide cc feedback at {now:?}");
+                self.update_rtcp_average(data.len() + UDP_IP_OVERHEAD_BYTES);
+                return Some(RtcpSendReply::Data(data));
+            }
+        }
+
         let Some(next_rtcp_send) = self.next_rtcp_send.time else {
             trace!("no next check time yet");
             return None;
@@ -1225,7 +1480,13 @@ impl Session {
                 .duration_since(now)
         );
 
-        let (data, ssrcs_reported) = {
+        let apps = if is_early {
+            vec![]
+        } else {
+            self.take_pending_app()
+        };
+
+        let (mut data, ssrcs_reported) = {
             let mut rtcp = Compound::builder();
             let mut ssrcs_reported = vec![];
 
@@ -1234,7 +1495,9 @@ impl Session {
             rtcp = self.generate_rr(rtcp, now, ntp_now, is_early, &mut ssrcs_reported);
             rtcp = self.generate_sdes(rtcp, is_early);
             rtcp = self.generate_pli(rtcp, now);
+            rtcp = self.generate_nack(rtcp, now);
             rtcp = self.generate_fir(rtcp, now);
+            rtcp = self.generate_app(rtcp, &apps);
             rtcp = self.generate_bye(rtcp, now);
 
             let size = rtcp.calculate_size().unwrap();
@@ -1245,6 +1508,20 @@ impl Session {
             (data, ssrcs_reported)
         };
 
+        if !is_early {
+            let ssrc = self.reporting_ssrc();
+            if let Some(xr) = self
+                .xr
+                .generate(ssrc, now, system_time_to_ntp_time_u64(ntp_now))
+            {
+                if data.len() + xr.len() < RTCP_MTU {
+                    data.extend_from_slice(&xr);
+                } else {
+                    debug!("Not enough space for extended report of {} bytes", xr.len());
+                }
+            }
+        }
+
         for ssrc in ssrcs_reported {
             if let Some(receiver) = self.remote_senders.get_mut(&ssrc) {
                 receiver.update_last_rtcp();
@@ -1288,8 +1565,9 @@ impl Session {
             self.next_early_rtcp_time,
             self.next_rtcp_send.time
         );
+        let twcc_time = self.twcc.next_feedback_time();
         if let Some(early_time) = self.next_early_rtcp_time {
-            return Some(early_time);
+            return Some(twcc_time.map_or(early_time, |time| time.min(early_time)));
         }
         if self.next_rtcp_send.time.is_none() {
             self.update_point_to_point();
@@ -1305,7 +1583,10 @@ impl Session {
                 self.next_rtcp_send.time
             );
         }
-        self.next_rtcp_send.time
+        match (self.next_rtcp_send.time, twcc_time) {
+            (Some(time), Some(twcc_time)) => Some(time.min(twcc_time)),
+            (time, twcc_time) => time.or(twcc_time),
+        }
     }
 
     fn deterministic_rtcp_duration(&self, we_sent: bool) -> Duration {
@@ -1585,6 +1866,37 @@ impl Session {
 
         replies
     }
+
+    /// Request retransmission of the given sequence numbers from the remote sender with the
+    /// given ssrc.  `max_delay` is the time after which a retransmission would not be useful
+    /// anymore.
+    pub(crate) fn request_nack(
+        &mut self,
+        now: Instant,
+        ssrc: u32,
+        seqnums: impl IntoIterator<Item = u16>,
+        max_delay: Duration,
+    ) -> Vec<RequestNackReply> {
+        let mut replies = Vec::new();
+
+        if !self.remote_senders.contains_key(&ssrc) {
+            trace!("No remote sender with ssrc {ssrc} known");
+            return replies;
+        };
+
+        let res = self.request_early_rtcp(now, max_delay);
+        if res == RequestEarlyRtcpResult::TimerReconsideration {
+            replies.push(RequestNackReply::TimerReconsideration);
+        }
+
+        if res != RequestEarlyRtcpResult::NotScheduled {
+            let source = self.remote_senders.get_mut(&ssrc).unwrap();
+            source.request_nack(seqnums);
+            debug!("Requesting retransmission for ssrc {ssrc}");
+        }
+
+        replies
+    }
 }
 
 fn generate_cname() -> String {
@@ -2569,6 +2881,117 @@ pub(crate) mod tests {
         assert_eq!(n_sr_ssrc, 1);
     }
 
+    #[test]
+    fn request_nack() {
+        init_logs();
+        let mut session = Session::new();
+        session.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        session.set_profile(RtpProfile::Avpf);
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let ssrc = 0x11223344;
+
+        let rtp_data = generate_rtp_packet(ssrc, 500, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        session_recv_first_packet_disable_probation(&mut session, &packet, now);
+        assert_eq!(
+            session.handle_recv(&packet, None, now),
+            RecvReply::Passthrough
+        );
+
+        // complete first regular rtcp
+        let (rtcp_data, now, ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(_rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+
+        // unknown ssrc
+        assert!(session
+            .request_nack(now, 0x55667788, [502], RTCP_MIN_REPORT_INTERVAL)
+            .is_empty());
+
+        assert_eq!(
+            session.request_nack(now, ssrc, [502, 503, 505], RTCP_MIN_REPORT_INTERVAL),
+            vec![RequestNackReply::TimerReconsideration]
+        );
+
+        let (rtcp_data, _now, _ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        let mut n_nack = 0;
+        for p in rtcp {
+            if let Ok(Packet::TransportFeedback(tf)) = p {
+                assert_eq!(tf.media_ssrc(), ssrc);
+                let nack = tf.parse_fci::<Nack>().unwrap();
+                assert_eq!(nack.entries().collect::<Vec<_>>(), vec![502, 503, 505]);
+                n_nack += 1;
+            }
+        }
+        assert_eq!(n_nack, 1);
+    }
+
+    #[test]
+    fn receive_nack() {
+        init_logs();
+        let mut session = Session::new();
+        session.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let send_ssrc = 0x11223344;
+        let recv_ssrc = 0x55667788;
+
+        let rtp_data = generate_rtp_packet(send_ssrc, 500, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        assert_eq!(
+            session.handle_send(&packet, now),
+            SendReply::NewSsrc(send_ssrc, TEST_PT)
+        );
+        assert_eq!(session.handle_send(&packet, now), SendReply::Passthrough);
+
+        let mut data = vec![0; 128];
+        let len = Compound::builder()
+            .add_packet(ReceiverReport::builder(recv_ssrc))
+            .add_packet(
+                TransportFeedback::builder_owned(
+                    Nack::builder().add_rtp_sequence(500).add_rtp_sequence(502),
+                )
+                .sender_ssrc(recv_ssrc)
+                .media_ssrc(send_ssrc),
+            )
+            .write_into(&mut data)
+            .unwrap();
+        let rtcp = Compound::parse(&data[..len]).unwrap();
+        assert_eq!(
+            session.handle_rtcp_recv(rtcp, len, None, now, ntp_now),
+            vec![
+                RtcpRecvReply::NewSsrc(recv_ssrc),
+                RtcpRecvReply::Nack {
+                    ssrc: send_ssrc,
+                    seqnums: vec![500, 502]
+                }
+            ]
+        );
+
+        // NACK for a ssrc that is not ours is ignored
+        let mut data = vec![0; 128];
+        let len = Compound::builder()
+            .add_packet(ReceiverReport::builder(recv_ssrc))
+            .add_packet(
+                TransportFeedback::builder_owned(Nack::builder().add_rtp_sequence(500))
+                    .sender_ssrc(recv_ssrc)
+                    .media_ssrc(0x99aabbcc),
+            )
+            .write_into(&mut data)
+            .unwrap();
+        let rtcp = Compound::parse(&data[..len]).unwrap();
+        assert_eq!(
+            session.handle_rtcp_recv(rtcp, len, None, now, ntp_now),
+            vec![]
+        );
+    }
+
     #[test]
     fn point_to_point() {
         let mut session = Session::new();
@@ -2656,4 +3079,217 @@ pub(crate) mod tests {
         );
         assert!(!session.is_point_to_point);
     }
+
+    #[test]
+    fn twcc_feedback() {
+        init_logs();
+        let mut session = Session::new();
+        session.set_twcc_feedback_interval(Duration::from_millis(50));
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let ssrc = 0x11223344;
+
+        let regular_rtcp_time = session.poll_rtcp_send_timeout(now).unwrap();
+        assert!(regular_rtcp_time > now + Duration::from_millis(50));
+
+        assert!(session.handle_twcc_recv(ssrc, 0, now));
+        assert!(!session.handle_twcc_recv(ssrc, 1, now + Duration::from_millis(1)));
+        let twcc_time = now + Duration::from_millis(50);
+        assert_eq!(session.poll_rtcp_send_timeout(now), Some(twcc_time));
+        assert!(session.poll_rtcp_send(now, ntp_now).is_none());
+
+        let Some(RtcpSendReply::Data(rtcp_data)) = session.poll_rtcp_send(twcc_time, ntp_now)
+        else {
+            unreachable!();
+        };
+        let feedback = super::super::twcc::parse_feedback(&rtcp_data);
+        assert_eq!(feedback.len(), 1);
+        assert_eq!(feedback[0].media_ssrc, ssrc);
+        assert_eq!(
+            feedback[0].sender_ssrc,
+            session.internal_rtcp_sender_src.unwrap()
+        );
+        assert_eq!(feedback[0].packets.len(), 2);
+
+        // back to the regular rtcp schedule
+        assert_eq!(
+            session.poll_rtcp_send_timeout(twcc_time),
+            Some(regular_rtcp_time)
+        );
+    }
+
+    #[test]
+    fn extended_reports() {
+        init_logs();
+        let mut recv_session = Session::new();
+        recv_session.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        recv_session.set_xr_config(XrConfig {
+            rrt: true,
+            loss_rle: true,
+            ..Default::default()
+        });
+        let mut send_session = Session::new();
+        send_session.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        send_session.set_xr_config(XrConfig {
+            dlrr: true,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let ssrc = 0x12345678;
+
+        let rtp_data = generate_rtp_packet(ssrc, 100, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        assert_eq!(
+            send_session.handle_send(&packet, now),
+            SendReply::NewSsrc(ssrc, TEST_PT)
+        );
+        assert_eq!(
+            send_session.handle_send(&packet, now),
+            SendReply::Passthrough
+        );
+        session_recv_first_packet_disable_probation(&mut recv_session, &packet, now);
+        for seqnum in 100..110 {
+            // packet 105 is lost
+            if seqnum == 105 {
+                continue;
+            }
+            let rtp_data = generate_rtp_packet(ssrc, seqnum, 0, 4);
+            let packet = RtpPacket::parse(&rtp_data).unwrap();
+            assert_eq!(
+                recv_session.handle_recv(&packet, None, now),
+                RecvReply::Passthrough
+            );
+        }
+
+        let (rtcp_data, now, ntp_now) = next_rtcp_packet(&mut recv_session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let recv_ssrc = recv_session.internal_rtcp_sender_src.unwrap();
+        let xr = xr::parse_xr(&rtcp_data);
+        assert_eq!(xr.len(), 1);
+        assert_eq!(xr[0].ssrc, recv_ssrc);
+
+        send_session.handle_rtcp_xr_recv(&rtcp_data, now, ntp_now);
+        let received = send_session.received_xr(recv_ssrc).unwrap();
+        assert_eq!(
+            received.rrt_ntp_time,
+            Some(system_time_to_ntp_time_u64(ntp_now).as_u64())
+        );
+        let rle = received.loss_rle.as_ref().unwrap();
+        assert_eq!(rle.ssrc, ssrc);
+        assert_eq!(rle.begin_seq, 100);
+        assert_eq!(rle.end_seq, 110);
+        assert_eq!(rle.packets_received(), 9);
+        assert_eq!(rle.packets_lost(), 1);
+
+        // the sender answers the receiver reference time with a DLRR block
+        let delay = Duration::from_millis(100);
+        let (rtcp_data, send_now, send_ntp_now) =
+            next_rtcp_packet(&mut send_session, now + delay, ntp_now + delay);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let rtt = Duration::from_millis(20);
+        recv_session.handle_rtcp_xr_recv(&rtcp_data, send_now + rtt, send_ntp_now + rtt);
+        let received = recv_session.received_xr(ssrc).unwrap();
+        let measured_rtt = received.round_trip_time.unwrap();
+        assert!(measured_rtt >= rtt - Duration::from_millis(1));
+        assert!(measured_rtt <= rtt + Duration::from_millis(1));
+    }
+
+    #[test]
+    fn send_receive_app() {
+        init_logs();
+        let mut session = Session::new();
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+
+        assert!(!session.send_app("TOOLONG", 0, vec![]));
+        assert!(!session.send_app("TEST", 32, vec![]));
+        assert!(!session.send_app("TEST", 0, vec![1, 2, 3]));
+        assert!(session.send_app("TEST", 5, vec![1, 2, 3, 4]));
+
+        let (rtcp_data, now, ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let ssrc = session.internal_rtcp_sender_src.unwrap();
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        let mut n_app = 0;
+        for p in rtcp {
+            if let Ok(Packet::App(app)) = p {
+                assert_eq!(app.ssrc(), ssrc);
+                assert_eq!(&app.name(), b"TEST");
+                assert_eq!(app.sub_type(), 5);
+                assert_eq!(app.data(), &[1, 2, 3, 4]);
+                n_app += 1;
+            }
+        }
+        assert_eq!(n_app, 1);
+
+        // the APP packet is only sent once
+        let (rtcp_data, _now, _ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        assert!(!rtcp.into_iter().any(|p| matches!(p, Ok(Packet::App(_)))));
+
+        let remote_ssrc = 0x12345678;
+        let mut data = vec![0; 128];
+        let len = Compound::builder()
+            .add_packet(ReceiverReport::builder(remote_ssrc))
+            .add_packet(
+                App::builder(remote_ssrc, "ABCD")
+                    .sub_type(1)
+                    .data(&[5, 6, 7, 8]),
+            )
+            .write_into(&mut data)
+            .unwrap();
+        let data = &data[..len];
+        let rtcp = Compound::parse(data).unwrap();
+        let replies = session.handle_rtcp_recv(rtcp, len, None, now, ntp_now);
+        assert!(replies.contains(&RtcpRecvReply::App(AppPacket {
+            ssrc: remote_ssrc,
+            name: String::from("ABCD"),
+            sub_type: 1,
+            data: vec![5, 6, 7, 8],
+        })));
+    }
+
+    #[test]
+    fn set_sdes() {
+        init_logs();
+        let mut session = Session::new();
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let cname = session.sdes().get(&SdesItem::CNAME).cloned().unwrap();
+
+        session.set_sdes([
+            (SdesItem::NAME, String::from("name")),
+            (SdesItem::TOOL, String::from("tool")),
+        ]);
+        assert_eq!(session.sdes().get(&SdesItem::CNAME), Some(&cname));
+
+        let (rtcp_data, _now, _ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        let mut items = HashMap::new();
+        for p in rtcp {
+            if let Ok(Packet::Sdes(sdes)) = p {
+                for chunk in sdes.chunks() {
+                    for item in chunk.items() {
+                        items.insert(item.type_(), item.value().to_vec());
+                    }
+                }
+            }
+        }
+        assert_eq!(items.get(&SdesItem::CNAME), Some(&cname.into_bytes()));
+        assert_eq!(items.get(&SdesItem::NAME), Some(&b"name".to_vec()));
+        assert_eq!(items.get(&SdesItem::TOOL), Some(&b"tool".to_vec()));
+    }
 }
@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, VecDeque},
     net::SocketAddr,
     time::{Duration, Instant, SystemTime},
 };
@@ -21,6 +21,9 @@ pub const DEFAULT_PROBATION_N_PACKETS: usize = 2;
 pub const DEFAULT_MAX_DROPOUT: u32 = 3000;
 pub const DEFAULT_MAX_MISORDER: u32 = 100;
 
+// Upper bound for the number of sequence numbers waiting to be NACKed
+const MAX_PENDING_NACKS: usize = 256;
+
 const BITRATE_WINDOW: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Clone, Copy)]
@@ -258,6 +261,11 @@ impl LocalSendSource {
         }
     }
 
+    /// Replace all sdes items for this source
+    pub(crate) fn set_sdes(&mut self, sdes: HashMap<u8, String>) {
+        self.source.sdes = sdes;
+    }
+
     /// Retrieve the sdes for this source
     pub fn sdes(&self) -> &HashMap<u8, String> {
         &self.source.sdes
@@ -430,6 +438,8 @@ pub struct RemoteSendSource {
     send_fir_seqnum: u8,
     // Count from the ForceKeyUnitEvent to de-duplicate FIR
     send_fir_count: Option<u32>,
+    // Sequence numbers to request retransmission for with the next RTCP packet
+    send_nack: BTreeSet<u16>,
 }
 
 // The first time we recev a packet for jitter calculations
@@ -460,6 +470,7 @@ impl RemoteSendSource {
             send_fir: false,
             send_fir_seqnum: 0,
             send_fir_count: None,
+            send_nack: BTreeSet::new(),
         }
     }
 
@@ -961,6 +972,30 @@ impl RemoteSendSource {
             fir
         }
     }
+
+    pub(crate) fn request_nack(&mut self, seqnums: impl IntoIterator<Item = u16>) {
+        for seqnum in seqnums {
+            if self.send_nack.len() >= MAX_PENDING_NACKS {
+                break;
+            }
+            self.send_nack.insert(seqnum);
+        }
+    }
+
+    pub(crate) fn generate_nack(&mut self) -> Option<rtcp_types::NackBuilder> {
+        if self.send_nack.is_empty() {
+            return None;
+        }
+
+        let seqnums = std::mem::take(&mut self.send_nack);
+        Some(
+            seqnums
+                .into_iter()
+                .fold(rtcp_types::Nack::builder(), |nack, seqnum| {
+                    nack.add_rtp_sequence(seqnum)
+                }),
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -1002,6 +1037,11 @@ impl LocalReceiveSource {
         }
     }
 
+    /// Replace all sdes items for this source
+    pub(crate) fn set_sdes(&mut self, sdes: HashMap<u8, String>) {
+        self.source.sdes = sdes;
+    }
+
     /// Retrieve the sdes for this source
     pub fn sdes(&self) -> &HashMap<u8, String> {
         &self.source.sdes
@@ -1118,6 +1158,7 @@ impl RemoteReceiveSource {
             send_fir: false,
             send_fir_seqnum: 0,
             send_fir_count: None,
+            send_nack: BTreeSet::new(),
         }
     }
 
@@ -0,0 +1,1235 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! SRTP and SRTCP protection as specified in RFC 3711, with the AES-GCM profiles from RFC 7714.
+
+use std::collections::HashMap;
+
+use aes::cipher::{InnerIvInit, KeyInit, StreamCipher};
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// Maximum number of SRTP packets protected with a single key
+const SRTP_MAX_PACKETS: u64 = 1 << 48;
+/// Maximum number of SRTCP packets protected with a single key
+const SRTCP_MAX_PACKETS: u64 = 1 << 31;
+/// Size of the replay window in packets
+const REPLAY_WINDOW_SIZE: u64 = 64;
+/// Length of the authentication tag of the AEAD ciphers
+const AEAD_TAG_LEN: usize = 16;
+/// Length of the E flag and SRTCP index trailer
+const SRTCP_INDEX_LEN: usize = 4;
+/// Length of the derived HMAC-SHA1 authentication key
+const AUTH_KEY_LEN: usize = 20;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("Invalid key length {0}")]
+    InvalidKeyLength(usize),
+    #[error("Invalid combination of cipher and authentication")]
+    InvalidParameters,
+    #[error("Invalid or truncated packet")]
+    InvalidPacket,
+    #[error("No key for ssrc {0:#010x}")]
+    NoKey(u32),
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    #[error("Replayed packet")]
+    Replayed,
+    #[error("Key usage limit reached")]
+    KeyLimitReached,
+}
+
+/// Encryption transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Null,
+    AesCm128,
+    AesCm256,
+    AesGcm128,
+    AesGcm256,
+}
+
+impl Cipher {
+    /// Parse a cipher name as used by `srtpenc` and `srtpdec`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "null" => Some(Cipher::Null),
+            "aes-128-icm" => Some(Cipher::AesCm128),
+            "aes-256-icm" => Some(Cipher::AesCm256),
+            "aes-128-gcm" => Some(Cipher::AesGcm128),
+            "aes-256-gcm" => Some(Cipher::AesGcm256),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Null => "null",
+            Cipher::AesCm128 => "aes-128-icm",
+            Cipher::AesCm256 => "aes-256-icm",
+            Cipher::AesGcm128 => "aes-128-gcm",
+            Cipher::AesGcm256 => "aes-256-gcm",
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Cipher::Null | Cipher::AesCm128 | Cipher::AesGcm128 => 16,
+            Cipher::AesCm256 | Cipher::AesGcm256 => 32,
+        }
+    }
+
+    fn salt_len(self) -> usize {
+        match self {
+            Cipher::AesGcm128 | Cipher::AesGcm256 => 12,
+            _ => 14,
+        }
+    }
+
+    fn is_aead(self) -> bool {
+        matches!(self, Cipher::AesGcm128 | Cipher::AesGcm256)
+    }
+}
+
+/// Message authentication transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    Null,
+    HmacSha1_32,
+    HmacSha1_80,
+}
+
+impl Auth {
+    /// Parse an authentication name as used by `srtpenc` and `srtpdec`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "null" => Some(Auth::Null),
+            "hmac-sha1-32" => Some(Auth::HmacSha1_32),
+            "hmac-sha1-80" => Some(Auth::HmacSha1_80),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Auth::Null => "null",
+            Auth::HmacSha1_32 => "hmac-sha1-32",
+            Auth::HmacSha1_80 => "hmac-sha1-80",
+        }
+    }
+
+    fn tag_len(self) -> usize {
+        match self {
+            Auth::Null => 0,
+            Auth::HmacSha1_32 => 4,
+            Auth::HmacSha1_80 => 10,
+        }
+    }
+}
+
+/// Master key and transforms used for a SSRC
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyParams {
+    pub rtp_cipher: Cipher,
+    pub rtp_auth: Auth,
+    pub rtcp_cipher: Cipher,
+    pub rtcp_auth: Auth,
+    /// Master key followed by the master salt
+    pub key: Vec<u8>,
+    /// Initial rollover counter of new streams
+    pub roc: u32,
+}
+
+impl std::fmt::Debug for KeyParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key material
+        f.debug_struct("KeyParams")
+            .field("rtp_cipher", &self.rtp_cipher)
+            .field("rtp_auth", &self.rtp_auth)
+            .field("rtcp_cipher", &self.rtcp_cipher)
+            .field("rtcp_auth", &self.rtcp_auth)
+            .field("roc", &self.roc)
+            .finish()
+    }
+}
+
+impl KeyParams {
+    fn validate(&self) -> Result<(), Error> {
+        if (self.rtp_cipher.is_aead() && self.rtp_auth != Auth::Null)
+            || (self.rtcp_cipher.is_aead() && self.rtcp_auth != Auth::Null)
+        {
+            return Err(Error::InvalidParameters);
+        }
+
+        // RTP and RTCP keys are derived from the same master key and salt
+        if self.rtp_cipher.key_len() != self.rtcp_cipher.key_len()
+            || self.rtp_cipher.salt_len() != self.rtcp_cipher.salt_len()
+        {
+            return Err(Error::InvalidParameters);
+        }
+
+        if self.key.len() != self.rtp_cipher.key_len() + self.rtp_cipher.salt_len() {
+            return Err(Error::InvalidKeyLength(self.key.len()));
+        }
+
+        Ok(())
+    }
+}
+
+enum AesCm {
+    Aes128(aes::Aes128),
+    Aes256(aes::Aes256),
+}
+
+impl AesCm {
+    fn new(key: &[u8]) -> Self {
+        match key.len() {
+            16 => AesCm::Aes128(aes::Aes128::new_from_slice(key).unwrap()),
+            32 => AesCm::Aes256(aes::Aes256::new_from_slice(key).unwrap()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// XOR the keystream starting at `iv` into `data`
+    fn apply(&self, iv: &[u8; 16], data: &mut [u8]) {
+        match self {
+            AesCm::Aes128(aes) => {
+                ctr::Ctr128BE::inner_iv_init(aes.clone(), iv.into()).apply_keystream(data)
+            }
+            AesCm::Aes256(aes) => {
+                ctr::Ctr128BE::inner_iv_init(aes.clone(), iv.into()).apply_keystream(data)
+            }
+        }
+    }
+}
+
+enum EncKey {
+    Null,
+    AesCm(AesCm),
+    AesGcm128(Box<Aes128Gcm>),
+    AesGcm256(Box<Aes256Gcm>),
+}
+
+impl EncKey {
+    fn encrypt_aead(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8]) -> Vec<u8> {
+        let nonce = aes_gcm::Nonce::from_slice(nonce);
+        let tag = match self {
+            EncKey::AesGcm128(gcm) => gcm.encrypt_in_place_detached(nonce, aad, data),
+            EncKey::AesGcm256(gcm) => gcm.encrypt_in_place_detached(nonce, aad, data),
+            _ => unreachable!(),
+        };
+
+        // Only fails for payloads bigger than 64GB
+        tag.unwrap().to_vec()
+    }
+
+    fn decrypt_aead(
+        &self,
+        nonce: &[u8; 12],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), Error> {
+        let nonce = aes_gcm::Nonce::from_slice(nonce);
+        let tag = aes_gcm::Tag::from_slice(tag);
+        match self {
+            EncKey::AesGcm128(gcm) => gcm.decrypt_in_place_detached(nonce, aad, data, tag),
+            EncKey::AesGcm256(gcm) => gcm.decrypt_in_place_detached(nonce, aad, data, tag),
+            _ => unreachable!(),
+        }
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+}
+
+/// AES-CM key derivation function from RFC 3711 section 4.3
+fn derive_key(master_key: &[u8], master_salt: &[u8], label: u8, len: usize) -> Vec<u8> {
+    let mut iv = [0u8; 16];
+    iv[..master_salt.len()].copy_from_slice(master_salt);
+    iv[7] ^= label;
+
+    let mut key = vec![0; len];
+    AesCm::new(master_key).apply(&iv, &mut key);
+    key
+}
+
+/// Session keys of one direction (RTP or RTCP)
+struct SessionKeys {
+    cipher: Cipher,
+    auth: Auth,
+    enc: EncKey,
+    mac: Option<HmacSha1>,
+    salt: [u8; 14],
+    num_packets: u64,
+}
+
+impl SessionKeys {
+    fn new(cipher: Cipher, auth: Auth, master: &[u8], label_base: u8) -> Self {
+        let (master_key, master_salt) = master.split_at(cipher.key_len());
+
+        let enc = match cipher {
+            Cipher::Null => EncKey::Null,
+            Cipher::AesCm128 | Cipher::AesCm256 => EncKey::AesCm(AesCm::new(&derive_key(
+                master_key,
+                master_salt,
+                label_base,
+                cipher.key_len(),
+            ))),
+            Cipher::AesGcm128 => EncKey::AesGcm128(Box::new(
+                Aes128Gcm::new_from_slice(&derive_key(master_key, master_salt, label_base, 16))
+                    .unwrap(),
+            )),
+            Cipher::AesGcm256 => EncKey::AesGcm256(Box::new(
+                Aes256Gcm::new_from_slice(&derive_key(master_key, master_salt, label_base, 32))
+                    .unwrap(),
+            )),
+        };
+
+        let mac = (auth != Auth::Null).then(|| {
+            let auth_key = derive_key(master_key, master_salt, label_base + 1, AUTH_KEY_LEN);
+            <HmacSha1 as Mac>::new_from_slice(&auth_key).unwrap()
+        });
+
+        let mut salt = [0u8; 14];
+        salt[..cipher.salt_len()].copy_from_slice(&derive_key(
+            master_key,
+            master_salt,
+            label_base + 2,
+            cipher.salt_len(),
+        ));
+
+        Self {
+            cipher,
+            auth,
+            enc,
+            mac,
+            salt,
+            num_packets: 0,
+        }
+    }
+
+    fn count_packet(&mut self, max: u64) -> Result<(), Error> {
+        if self.num_packets >= max {
+            return Err(Error::KeyLimitReached);
+        }
+        self.num_packets += 1;
+        Ok(())
+    }
+
+    fn tag_len(&self) -> usize {
+        if self.cipher.is_aead() {
+            AEAD_TAG_LEN
+        } else {
+            self.auth.tag_len()
+        }
+    }
+
+    fn auth_tag(&self, mac: &HmacSha1, data: &[u8], roc: Option<u32>) -> Vec<u8> {
+        let mut mac = mac.clone();
+        mac.update(data);
+        if let Some(roc) = roc {
+            mac.update(&roc.to_be_bytes());
+        }
+        let mut tag = mac.finalize().into_bytes().to_vec();
+        tag.truncate(self.auth.tag_len());
+        tag
+    }
+
+    fn verify_auth_tag(&self, data: &[u8], roc: Option<u32>, tag: &[u8]) -> Result<(), Error> {
+        let Some(ref mac) = self.mac else {
+            return Ok(());
+        };
+
+        let mut mac = mac.clone();
+        mac.update(data);
+        if let Some(roc) = roc {
+            mac.update(&roc.to_be_bytes());
+        }
+        mac.verify_truncated_left(tag)
+            .map_err(|_| Error::AuthenticationFailed)
+    }
+
+    fn rtp_iv(&self, ssrc: u32, roc: u32, seq: u16) -> [u8; 16] {
+        let mut iv = [0u8; 16];
+        iv[..14].copy_from_slice(&self.salt);
+        xor(&mut iv[4..8], &ssrc.to_be_bytes());
+        xor(&mut iv[8..12], &roc.to_be_bytes());
+        xor(&mut iv[12..14], &seq.to_be_bytes());
+        iv
+    }
+
+    fn rtcp_iv(&self, ssrc: u32, index: u32) -> [u8; 16] {
+        let mut iv = [0u8; 16];
+        iv[..14].copy_from_slice(&self.salt);
+        xor(&mut iv[4..8], &ssrc.to_be_bytes());
+        xor(&mut iv[10..14], &index.to_be_bytes());
+        iv
+    }
+
+    fn rtp_nonce(&self, ssrc: u32, roc: u32, seq: u16) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&self.salt[..12]);
+        xor(&mut nonce[2..6], &ssrc.to_be_bytes());
+        xor(&mut nonce[6..10], &roc.to_be_bytes());
+        xor(&mut nonce[10..12], &seq.to_be_bytes());
+        nonce
+    }
+
+    fn rtcp_nonce(&self, ssrc: u32, index: u32) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&self.salt[..12]);
+        xor(&mut nonce[2..6], &ssrc.to_be_bytes());
+        xor(&mut nonce[8..12], &index.to_be_bytes());
+        nonce
+    }
+
+    fn protect_rtp(&self, data: &mut Vec<u8>, header_len: usize, ssrc: u32, roc: u32, seq: u16) {
+        match self.enc {
+            EncKey::Null => (),
+            EncKey::AesCm(ref aes) => {
+                aes.apply(&self.rtp_iv(ssrc, roc, seq), &mut data[header_len..])
+            }
+            _ => {
+                let nonce = self.rtp_nonce(ssrc, roc, seq);
+                let (header, payload) = data.split_at_mut(header_len);
+                let tag = self.enc.encrypt_aead(&nonce, header, payload);
+                data.extend_from_slice(&tag);
+            }
+        }
+
+        if let Some(ref mac) = self.mac {
+            let tag = self.auth_tag(mac, data, Some(roc));
+            data.extend_from_slice(&tag);
+        }
+    }
+
+    fn unprotect_rtp(
+        &self,
+        data: &mut Vec<u8>,
+        header_len: usize,
+        ssrc: u32,
+        roc: u32,
+        seq: u16,
+    ) -> Result<(), Error> {
+        let tag_len = self.tag_len();
+        if data.len() < header_len + tag_len {
+            return Err(Error::InvalidPacket);
+        }
+        let payload_end = data.len() - tag_len;
+
+        match self.enc {
+            EncKey::Null | EncKey::AesCm(_) => {
+                // Authenticate before decrypting so that the packet is untouched on failure
+                self.verify_auth_tag(&data[..payload_end], Some(roc), &data[payload_end..])?;
+                if let EncKey::AesCm(ref aes) = self.enc {
+                    aes.apply(
+                        &self.rtp_iv(ssrc, roc, seq),
+                        &mut data[header_len..payload_end],
+                    );
+                }
+            }
+            _ => {
+                let nonce = self.rtp_nonce(ssrc, roc, seq);
+                let (header, rest) = data.split_at_mut(header_len);
+                let (payload, tag) = rest.split_at_mut(payload_end - header_len);
+                self.enc.decrypt_aead(&nonce, header, payload, tag)?;
+            }
+        }
+
+        data.truncate(payload_end);
+        Ok(())
+    }
+
+    fn protect_rtcp(&self, data: &mut Vec<u8>, ssrc: u32, index: u32) {
+        let e_index = if self.cipher == Cipher::Null {
+            index
+        } else {
+            index | 0x8000_0000
+        };
+
+        match self.enc {
+            EncKey::Null => (),
+            EncKey::AesCm(ref aes) => aes.apply(&self.rtcp_iv(ssrc, index), &mut data[8..]),
+            _ => {
+                let nonce = self.rtcp_nonce(ssrc, index);
+                let mut aad = [0u8; 8 + SRTCP_INDEX_LEN];
+                aad[..8].copy_from_slice(&data[..8]);
+                aad[8..].copy_from_slice(&e_index.to_be_bytes());
+                let tag = self.enc.encrypt_aead(&nonce, &aad, &mut data[8..]);
+                data.extend_from_slice(&tag);
+            }
+        }
+        data.extend_from_slice(&e_index.to_be_bytes());
+
+        if let Some(ref mac) = self.mac {
+            let tag = self.auth_tag(mac, data, None);
+            data.extend_from_slice(&tag);
+        }
+    }
+
+    /// Position of the E flag and SRTCP index in a protected packet
+    fn rtcp_index_offset(&self, data: &[u8]) -> Result<usize, Error> {
+        let min_len = 8 + SRTCP_INDEX_LEN + self.tag_len();
+        if data.len() < min_len {
+            return Err(Error::InvalidPacket);
+        }
+
+        if self.cipher.is_aead() {
+            Ok(data.len() - SRTCP_INDEX_LEN)
+        } else {
+            Ok(data.len() - self.tag_len() - SRTCP_INDEX_LEN)
+        }
+    }
+
+    fn unprotect_rtcp(&self, data: &mut Vec<u8>, ssrc: u32) -> Result<(), Error> {
+        let index_offset = self.rtcp_index_offset(data)?;
+        let e_index = u32::from_be_bytes(
+            data[index_offset..index_offset + SRTCP_INDEX_LEN]
+                .try_into()
+                .unwrap(),
+        );
+        let index = e_index & 0x7fff_ffff;
+        let encrypted = e_index & 0x8000_0000 != 0;
+
+        match self.enc {
+            EncKey::Null | EncKey::AesCm(_) => {
+                let auth_end = index_offset + SRTCP_INDEX_LEN;
+                self.verify_auth_tag(&data[..auth_end], None, &data[auth_end..])?;
+                if let EncKey::AesCm(ref aes) = self.enc {
+                    if encrypted {
+                        aes.apply(&self.rtcp_iv(ssrc, index), &mut data[8..index_offset]);
+                    }
+                }
+                data.truncate(index_offset);
+            }
+            _ => {
+                if !encrypted {
+                    return Err(Error::InvalidPacket);
+                }
+                let nonce = self.rtcp_nonce(ssrc, index);
+                let mut aad = [0u8; 8 + SRTCP_INDEX_LEN];
+                aad[..8].copy_from_slice(&data[..8]);
+                aad[8..].copy_from_slice(&e_index.to_be_bytes());
+                let payload_end = index_offset - AEAD_TAG_LEN;
+                let (payload, tag) = data[8..index_offset].split_at_mut(payload_end - 8);
+                self.enc.decrypt_aead(&nonce, &aad, payload, tag)?;
+                data.truncate(payload_end);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn xor(dest: &mut [u8], src: &[u8]) {
+    for (d, s) in dest.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
+
+/// RTP and RTCP session keys derived from one master key
+struct Keys {
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+}
+
+impl Keys {
+    fn new(params: &KeyParams) -> Self {
+        Self {
+            rtp: SessionKeys::new(params.rtp_cipher, params.rtp_auth, &params.key, 0),
+            rtcp: SessionKeys::new(params.rtcp_cipher, params.rtcp_auth, &params.key, 3),
+        }
+    }
+}
+
+struct KeySet {
+    params: KeyParams,
+    current: Keys,
+    /// Keys before the last key change, still accepted for received packets until the first
+    /// packet protected with the current keys arrives
+    previous: Option<Keys>,
+}
+
+impl std::fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeySet")
+            .field("params", &self.params)
+            .field("previous", &self.previous.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    fn check(&self, index: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if index > highest => true,
+            Some(highest) => {
+                let delta = highest - index;
+                delta < REPLAY_WINDOW_SIZE && self.bitmap & (1 << delta) == 0
+            }
+        }
+    }
+
+    fn update(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                self.bitmap |= 1 << (highest - index);
+            }
+            Some(highest) => {
+                let shift = index - highest;
+                self.bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                    1
+                } else {
+                    (self.bitmap << shift) | 1
+                };
+                self.highest = Some(index);
+            }
+            None => {
+                self.bitmap = 1;
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+/// Per-SSRC state that persists across key changes
+#[derive(Debug)]
+struct Stream {
+    roc: u32,
+    highest_seq: Option<u16>,
+    rtp_replay: ReplayWindow,
+    rtcp_index: u32,
+    rtcp_replay: ReplayWindow,
+}
+
+impl Stream {
+    fn new(roc: u32) -> Self {
+        Self {
+            roc,
+            highest_seq: None,
+            rtp_replay: ReplayWindow::default(),
+            rtcp_index: 0,
+            rtcp_replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Estimate the rollover counter of a packet as described in RFC 3711 section 3.3.1
+    fn estimate_roc(&self, seq: u16) -> u32 {
+        let Some(s_l) = self.highest_seq else {
+            return self.roc;
+        };
+
+        if s_l < 0x8000 {
+            if seq > s_l && seq - s_l > 0x8000 {
+                self.roc.wrapping_sub(1)
+            } else {
+                self.roc
+            }
+        } else if s_l - 0x8000 > seq {
+            self.roc.wrapping_add(1)
+        } else {
+            self.roc
+        }
+    }
+
+    fn update(&mut self, roc: u32, seq: u16) {
+        match self.highest_seq {
+            Some(s_l) if roc == self.roc && seq <= s_l => (),
+            Some(_) if roc != self.roc && roc != self.roc.wrapping_add(1) => (),
+            _ => {
+                self.roc = roc;
+                self.highest_seq = Some(seq);
+            }
+        }
+    }
+
+    fn rtp_index(roc: u32, seq: u16) -> u64 {
+        ((roc as u64) << 16) | seq as u64
+    }
+}
+
+/// Returns the header length, SSRC and sequence number of a RTP packet
+fn parse_rtp_header(data: &[u8]) -> Result<(usize, u32, u16), Error> {
+    if data.len() < 12 || data[0] >> 6 != 2 {
+        return Err(Error::InvalidPacket);
+    }
+
+    let mut header_len = 12 + 4 * (data[0] & 0x0f) as usize;
+    if data[0] & 0x10 != 0 {
+        if data.len() < header_len + 4 {
+            return Err(Error::InvalidPacket);
+        }
+        let ext_len = u16::from_be_bytes([data[header_len + 2], data[header_len + 3]]) as usize;
+        header_len += 4 + 4 * ext_len;
+    }
+    if data.len() < header_len {
+        return Err(Error::InvalidPacket);
+    }
+
+    let seq = u16::from_be_bytes([data[2], data[3]]);
+    let ssrc = u32::from_be_bytes(data[8..12].try_into().unwrap());
+
+    Ok((header_len, ssrc, seq))
+}
+
+/// Returns the sender SSRC of a (S)RTCP compound packet
+fn parse_rtcp_ssrc(data: &[u8]) -> Result<u32, Error> {
+    if data.len() < 8 || data[0] >> 6 != 2 {
+        return Err(Error::InvalidPacket);
+    }
+
+    Ok(u32::from_be_bytes(data[4..8].try_into().unwrap()))
+}
+
+/// Whether a packet received on a rtcp-mux'd stream is a RTCP packet (RFC 5761 section 4)
+pub fn is_rtcp(data: &[u8]) -> bool {
+    data.len() >= 2 && (64..=95).contains(&(data[1] & 0x7f))
+}
+
+/// SRTP/SRTCP state of one direction of a session
+///
+/// Keys are configured per SSRC, with an optional default key for all SSRCs without a
+/// specific key.  Rollover counters and replay windows are tracked per SSRC and are kept when
+/// keys change.
+#[derive(Debug, Default)]
+pub struct SrtpContext {
+    default_keys: Option<KeySet>,
+    keys: HashMap<u32, KeySet>,
+    streams: HashMap<u32, Stream>,
+}
+
+impl SrtpContext {
+    /// Whether any keys are configured
+    pub fn is_enabled(&self) -> bool {
+        self.default_keys.is_some() || !self.keys.is_empty()
+    }
+
+    /// Set or remove the key for the given SSRC, or the default key if `ssrc` is `None`.
+    ///
+    /// Changing an existing key keeps the previous key for unprotecting packets that were
+    /// still protected with it.
+    pub fn set_key(&mut self, ssrc: Option<u32>, params: Option<KeyParams>) -> Result<(), Error> {
+        if let Some(ref params) = params {
+            params.validate()?;
+        }
+
+        let old = match ssrc {
+            Some(ssrc) => self.keys.remove(&ssrc),
+            None => self.default_keys.take(),
+        };
+
+        let keyset = params.map(|params| match old {
+            Some(keyset) if keyset.params == params => keyset,
+            old => KeySet {
+                current: Keys::new(&params),
+                previous: old.map(|keyset| keyset.current),
+                params,
+            },
+        });
+
+        match ssrc {
+            Some(ssrc) => {
+                if let Some(keyset) = keyset {
+                    self.keys.insert(ssrc, keyset);
+                }
+            }
+            None => self.default_keys = keyset,
+        }
+
+        Ok(())
+    }
+
+    /// SSRCs with a configured key, `None` for the default key
+    pub fn key_ssrcs(&self) -> impl Iterator<Item = Option<u32>> + '_ {
+        self.default_keys
+            .iter()
+            .map(|_| None)
+            .chain(self.keys.keys().map(|&ssrc| Some(ssrc)))
+    }
+
+    /// The key parameters configured for the given SSRC, or the default key if `ssrc` is `None`
+    pub fn key(&self, ssrc: Option<u32>) -> Option<&KeyParams> {
+        match ssrc {
+            Some(ssrc) => self.keys.get(&ssrc),
+            None => self.default_keys.as_ref(),
+        }
+        .map(|keyset| &keyset.params)
+    }
+
+    fn keyset<'a>(
+        keys: &'a mut HashMap<u32, KeySet>,
+        default_keys: &'a mut Option<KeySet>,
+        ssrc: u32,
+    ) -> Result<&'a mut KeySet, Error> {
+        match keys.get_mut(&ssrc) {
+            Some(keyset) => Ok(keyset),
+            None => default_keys.as_mut().ok_or(Error::NoKey(ssrc)),
+        }
+    }
+
+    /// Protect a RTP packet in place
+    pub fn protect_rtp(&mut self, data: &mut Vec<u8>) -> Result<(), Error> {
+        let (header_len, ssrc, seq) = parse_rtp_header(data)?;
+        let keyset = Self::keyset(&mut self.keys, &mut self.default_keys, ssrc)?;
+        let stream = self
+            .streams
+            .entry(ssrc)
+            .or_insert_with(|| Stream::new(keyset.params.roc));
+
+        keyset.current.rtp.count_packet(SRTP_MAX_PACKETS)?;
+        let roc = stream.estimate_roc(seq);
+        stream.update(roc, seq);
+        keyset
+            .current
+            .rtp
+            .protect_rtp(data, header_len, ssrc, roc, seq);
+
+        Ok(())
+    }
+
+    /// Unprotect a SRTP packet in place
+    pub fn unprotect_rtp(&mut self, data: &mut Vec<u8>) -> Result<(), Error> {
+        let (header_len, ssrc, seq) = parse_rtp_header(data)?;
+        let keyset = Self::keyset(&mut self.keys, &mut self.default_keys, ssrc)?;
+        let stream = self
+            .streams
+            .entry(ssrc)
+            .or_insert_with(|| Stream::new(keyset.params.roc));
+
+        let roc = stream.estimate_roc(seq);
+        let index = Stream::rtp_index(roc, seq);
+        if !stream.rtp_replay.check(index) {
+            return Err(Error::Replayed);
+        }
+
+        if let Err(err) = keyset
+            .current
+            .rtp
+            .unprotect_rtp(data, header_len, ssrc, roc, seq)
+        {
+            // Fall back to the previous key while the peer did not switch yet
+            let Some(ref previous) = keyset.previous else {
+                return Err(err);
+            };
+            previous
+                .rtp
+                .unprotect_rtp(data, header_len, ssrc, roc, seq)?;
+        } else {
+            keyset.previous = None;
+        }
+
+        stream.rtp_replay.update(index);
+        stream.update(roc, seq);
+
+        Ok(())
+    }
+
+    /// Protect a RTCP compound packet in place
+    pub fn protect_rtcp(&mut self, data: &mut Vec<u8>) -> Result<(), Error> {
+        let ssrc = parse_rtcp_ssrc(data)?;
+        let keyset = Self::keyset(&mut self.keys, &mut self.default_keys, ssrc)?;
+        let stream = self
+            .streams
+            .entry(ssrc)
+            .or_insert_with(|| Stream::new(keyset.params.roc));
+
+        keyset.current.rtcp.count_packet(SRTCP_MAX_PACKETS)?;
+        let index = stream.rtcp_index;
+        stream.rtcp_index = (index + 1) & 0x7fff_ffff;
+        keyset.current.rtcp.protect_rtcp(data, ssrc, index);
+
+        Ok(())
+    }
+
+    /// Unprotect a SRTCP compound packet in place
+    pub fn unprotect_rtcp(&mut self, data: &mut Vec<u8>) -> Result<(), Error> {
+        let ssrc = parse_rtcp_ssrc(data)?;
+        let keyset = Self::keyset(&mut self.keys, &mut self.default_keys, ssrc)?;
+        let stream = self
+            .streams
+            .entry(ssrc)
+            .or_insert_with(|| Stream::new(keyset.params.roc));
+
+        let index_offset = keyset.current.rtcp.rtcp_index_offset(data)?;
+        let index = u32::from_be_bytes(
+            data[index_offset..index_offset + SRTCP_INDEX_LEN]
+                .try_into()
+                .unwrap(),
+        ) & 0x7fff_ffff;
+        if !stream.rtcp_replay.check(index as u64) {
+            return Err(Error::Replayed);
+        }
+
+        if let Err(err) = keyset.current.rtcp.unprotect_rtcp(data, ssrc) {
+            let Some(ref previous) = keyset.previous else {
+                return Err(err);
+            };
+            previous.rtcp.unprotect_rtcp(data, ssrc)?;
+        }
+
+        stream.rtcp_replay.update(index as u64);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY: &str = "e1f97a0d3e018be0d64fa32c06de4139";
+    const MASTER_SALT: &str = "0ec675ad498afeebb6960b3aabe6";
+
+    fn key_params(cipher: Cipher, auth: Auth, key: &str) -> KeyParams {
+        KeyParams {
+            rtp_cipher: cipher,
+            rtp_auth: auth,
+            rtcp_cipher: cipher,
+            rtcp_auth: auth,
+            key: hex::decode(key).unwrap(),
+            roc: 0,
+        }
+    }
+
+    fn aes_cm_params() -> KeyParams {
+        key_params(
+            Cipher::AesCm128,
+            Auth::HmacSha1_80,
+            &format!("{MASTER_KEY}{MASTER_SALT}"),
+        )
+    }
+
+    fn aes_gcm_params() -> KeyParams {
+        key_params(
+            Cipher::AesGcm128,
+            Auth::Null,
+            &format!("{MASTER_KEY}{}", &MASTER_SALT[..24]),
+        )
+    }
+
+    fn context(params: KeyParams) -> SrtpContext {
+        let mut ctx = SrtpContext::default();
+        ctx.set_key(None, Some(params)).unwrap();
+        ctx
+    }
+
+    fn rtp_packet(seq: u16) -> Vec<u8> {
+        let mut data = hex::decode("800f0000decafbadcafebabe").unwrap();
+        data[2..4].copy_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&[0xab; 16]);
+        data
+    }
+
+    fn rtcp_packet() -> Vec<u8> {
+        let mut data = hex::decode("81c8000bcafebabe").unwrap();
+        data.extend_from_slice(&[0xab; 16]);
+        data
+    }
+
+    #[test]
+    fn key_derivation() {
+        // RFC 3711 appendix B.3
+        let key = hex::decode(MASTER_KEY).unwrap();
+        let salt = hex::decode(MASTER_SALT).unwrap();
+
+        assert_eq!(
+            hex::encode(derive_key(&key, &salt, 0, 16)),
+            "c61e7a93744f39ee10734afe3ff7a087"
+        );
+        assert_eq!(
+            hex::encode(derive_key(&key, &salt, 2, 14)),
+            "30cbbc08863d8c85d49db34a9ae1"
+        );
+        assert_eq!(
+            hex::encode(derive_key(&key, &salt, 1, 20)),
+            "cebe321f6ff7716b6fd4ab49af256a156d38baa4"
+        );
+    }
+
+    #[test]
+    fn aes_cm_keystream() {
+        // RFC 3711 appendix B.2
+        let aes = AesCm::new(&hex::decode("2b7e151628aed2a6abf7158809cf4f3c").unwrap());
+        let iv = hex::decode("f0f1f2f3f4f5f6f7f8f9fafbfcfd0000").unwrap();
+
+        let mut keystream = vec![0; 3 * 16];
+        aes.apply(iv.as_slice().try_into().unwrap(), &mut keystream);
+        assert_eq!(
+            hex::encode(keystream),
+            "e03ead0935c95e80e166b16dd92b4eb4\
+             d23513162b02d0f72a43a2fe4a5f97ab\
+             41e95b3bb0a2e8dd477901e4fca894c0"
+        );
+
+        let mut iv = iv;
+        iv[14..16].copy_from_slice(&[0xfe, 0xff]);
+        let mut keystream = vec![0; 16];
+        aes.apply(iv.as_slice().try_into().unwrap(), &mut keystream);
+        assert_eq!(hex::encode(keystream), "ec8cdf7398607cb0f2d21675ea9ea1e4");
+    }
+
+    #[test]
+    fn aes_cm_rtp() {
+        let mut sender = context(aes_cm_params());
+        let mut receiver = context(aes_cm_params());
+
+        let mut data = rtp_packet(0x1234);
+        sender.protect_rtp(&mut data).unwrap();
+        assert_eq!(
+            hex::encode(&data),
+            "800f1234decafbadcafebabe4e55dc4ce79978d88ca4d215949d2402b78d6acc99ea179b8dbb"
+        );
+
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(data, rtp_packet(0x1234));
+    }
+
+    #[test]
+    fn aes_gcm_rtp() {
+        let mut sender = context(aes_gcm_params());
+        let mut receiver = context(aes_gcm_params());
+
+        let mut data = rtp_packet(0x1234);
+        sender.protect_rtp(&mut data).unwrap();
+        assert_eq!(
+            hex::encode(&data),
+            "800f1234decafbadcafebabe0eca0cf95ee955b26cd3d288b49f6ca959171450975f4144da9cdd7ae989a277"
+        );
+
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(data, rtp_packet(0x1234));
+    }
+
+    #[test]
+    fn aes_cm_rtcp() {
+        let mut sender = context(aes_cm_params());
+        let mut receiver = context(aes_cm_params());
+
+        // The first packet has index 0
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        receiver.unprotect_rtcp(&mut data).unwrap();
+        assert_eq!(data, rtcp_packet());
+
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        assert_eq!(
+            hex::encode(&data),
+            "81c8000bcafebabe7128035be487b9bdbef89041f977a5a880000001993e08cd54d6c1230798"
+        );
+        receiver.unprotect_rtcp(&mut data).unwrap();
+        assert_eq!(data, rtcp_packet());
+    }
+
+    #[test]
+    fn aes_gcm_rtcp() {
+        let mut sender = context(aes_gcm_params());
+        let mut receiver = context(aes_gcm_params());
+
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        receiver.unprotect_rtcp(&mut data).unwrap();
+
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        assert_eq!(
+            hex::encode(&data),
+            "81c8000bcafebabeea6e5eb3c75c462000f59ebf9e4ebd4e934239488f02f9ca1580174557117e3780000001"
+        );
+        receiver.unprotect_rtcp(&mut data).unwrap();
+        assert_eq!(data, rtcp_packet());
+    }
+
+    #[test]
+    fn authentication_failure() {
+        for params in [aes_cm_params(), aes_gcm_params()] {
+            let mut sender = context(params.clone());
+            let mut receiver = context(params);
+
+            let mut data = rtp_packet(1);
+            sender.protect_rtp(&mut data).unwrap();
+            data[15] ^= 0x01;
+            let orig = data.clone();
+            assert_eq!(
+                receiver.unprotect_rtp(&mut data),
+                Err(Error::AuthenticationFailed)
+            );
+            assert_eq!(data, orig);
+
+            let mut data = rtcp_packet();
+            sender.protect_rtcp(&mut data).unwrap();
+            data[10] ^= 0x01;
+            assert_eq!(
+                receiver.unprotect_rtcp(&mut data),
+                Err(Error::AuthenticationFailed)
+            );
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let mut sender = context(aes_cm_params());
+        let mut receiver = context(aes_cm_params());
+
+        let packets = (0..100)
+            .map(|seq| {
+                let mut data = rtp_packet(seq);
+                sender.protect_rtp(&mut data).unwrap();
+                data
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = packets[50].clone();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        let mut data = packets[50].clone();
+        assert_eq!(receiver.unprotect_rtp(&mut data), Err(Error::Replayed));
+
+        // Reordered packets inside the window are accepted once
+        let mut data = packets[40].clone();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        let mut data = packets[40].clone();
+        assert_eq!(receiver.unprotect_rtp(&mut data), Err(Error::Replayed));
+
+        let mut data = packets[99].clone();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        // Too old for the replay window
+        let mut data = packets[30].clone();
+        assert_eq!(receiver.unprotect_rtp(&mut data), Err(Error::Replayed));
+
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        let replayed = data.clone();
+        receiver.unprotect_rtcp(&mut data).unwrap();
+        let mut data = replayed;
+        assert_eq!(receiver.unprotect_rtcp(&mut data), Err(Error::Replayed));
+    }
+
+    #[test]
+    fn rollover_counter() {
+        let mut sender = context(aes_cm_params());
+        let mut receiver = context(aes_cm_params());
+
+        for seq in (0xfff0..=0xffff).chain(0..0x10) {
+            let mut data = rtp_packet(seq);
+            sender.protect_rtp(&mut data).unwrap();
+            receiver.unprotect_rtp(&mut data).unwrap();
+            assert_eq!(data, rtp_packet(seq));
+        }
+        assert_eq!(sender.streams[&0xcafebabe].roc, 1);
+        assert_eq!(receiver.streams[&0xcafebabe].roc, 1);
+
+        // A reordered packet from before the wrap-around still uses the old rollover counter
+        let mut sender_old = context(aes_cm_params());
+        let mut data = rtp_packet(0xffe0);
+        sender_old.protect_rtp(&mut data).unwrap();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(data, rtp_packet(0xffe0));
+        assert_eq!(receiver.streams[&0xcafebabe].roc, 1);
+
+        // Initial rollover counter from the key parameters
+        let mut params = aes_cm_params();
+        params.roc = 5;
+        let mut sender = context(params.clone());
+        let mut receiver = context(params);
+        let mut data = rtp_packet(1);
+        sender.protect_rtp(&mut data).unwrap();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(receiver.streams[&0xcafebabe].roc, 5);
+    }
+
+    #[test]
+    fn key_rollover() {
+        let old_params = aes_cm_params();
+        let mut new_params = aes_cm_params();
+        new_params.key[0] ^= 0xff;
+
+        let mut sender = context(old_params.clone());
+        let mut receiver = SrtpContext::default();
+        receiver
+            .set_key(Some(0xcafebabe), Some(old_params))
+            .unwrap();
+
+        let mut delayed = rtp_packet(1);
+        sender.protect_rtp(&mut delayed).unwrap();
+
+        // Both sides switch to the new key, the delayed packet is still accepted
+        sender.set_key(None, Some(new_params.clone())).unwrap();
+        receiver
+            .set_key(Some(0xcafebabe), Some(new_params))
+            .unwrap();
+
+        let mut data = rtp_packet(2);
+        sender.protect_rtp(&mut data).unwrap();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(data, rtp_packet(2));
+
+        // Rollover counter and replay state are kept across the key change
+        assert_eq!(sender.streams[&0xcafebabe].highest_seq, Some(2));
+
+        // The previous key is dropped once the current key was used
+        assert_eq!(
+            receiver.unprotect_rtp(&mut delayed),
+            Err(Error::AuthenticationFailed)
+        );
+
+        // Setting the same key again is not a key change
+        let mut receiver = context(aes_cm_params());
+        receiver.set_key(None, Some(aes_cm_params())).unwrap();
+        assert!(receiver.default_keys.as_ref().unwrap().previous.is_none());
+    }
+
+    #[test]
+    fn key_rollover_previous_key() {
+        let old_params = aes_cm_params();
+        let mut new_params = aes_cm_params();
+        new_params.key[0] ^= 0xff;
+
+        let mut sender = context(old_params.clone());
+        let mut receiver = context(old_params);
+
+        // The receiver switches to the new key before the sender does
+        receiver.set_key(None, Some(new_params)).unwrap();
+
+        let mut data = rtp_packet(1);
+        sender.protect_rtp(&mut data).unwrap();
+        receiver.unprotect_rtp(&mut data).unwrap();
+        assert_eq!(data, rtp_packet(1));
+
+        let mut data = rtcp_packet();
+        sender.protect_rtcp(&mut data).unwrap();
+        receiver.unprotect_rtcp(&mut data).unwrap();
+        assert_eq!(data, rtcp_packet());
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut ctx = SrtpContext::default();
+        let mut params = aes_cm_params();
+        params.key.pop();
+        assert_eq!(
+            ctx.set_key(None, Some(params)),
+            Err(Error::InvalidKeyLength(29))
+        );
+
+        let mut params = aes_gcm_params();
+        params.rtp_auth = Auth::HmacSha1_80;
+        assert_eq!(
+            ctx.set_key(None, Some(params)),
+            Err(Error::InvalidParameters)
+        );
+        assert!(!ctx.is_enabled());
+
+        let mut data = rtp_packet(1);
+        assert_eq!(ctx.protect_rtp(&mut data), Err(Error::NoKey(0xcafebabe)));
+    }
+}
@@ -0,0 +1,695 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Transport-wide congestion control as specified in
+//! draft-holmer-rmcat-transport-wide-cc-extensions-01.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rtp_types::{RtpPacket, RtpPacketBuilder};
+
+use crate::utils::ExtendedSeqnum;
+
+/// URI of the transport-wide sequence number RTP header extension
+pub const TWCC_EXTMAP_URI: &str =
+    "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+/// Default interval between two feedback packets
+pub const TWCC_DEFAULT_FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+/// Maximum number of packets reported in a single feedback packet
+const MAX_PACKETS_PER_FEEDBACK: u64 = 1000;
+/// Maximum number of sent packets remembered for matching received feedback
+const MAX_SENT_PACKETS: usize = 8192;
+
+/// RTCP transport layer feedback packet type
+const RTPFB_PT: u8 = 205;
+/// Feedback message type of transport-wide congestion control feedback
+const TWCC_FMT: u8 = 15;
+/// Resolution of the receive deltas
+const DELTA_TICK_US: i64 = 250;
+/// Resolution of the reference time
+const REFERENCE_TIME_US: i64 = 64_000;
+
+const SYMBOL_NOT_RECEIVED: u8 = 0;
+const SYMBOL_SMALL_DELTA: u8 = 1;
+const SYMBOL_LARGE_DELTA: u8 = 2;
+
+/// Returns the elements of a one-byte or two-byte RTP header extension (RFC 8285)
+fn extension_elements(profile: u16, data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut elements = Vec::new();
+    let mut i = 0;
+
+    if profile == 0xbede {
+        while i < data.len() {
+            let id = data[i] >> 4;
+            if data[i] == 0 {
+                // padding
+                i += 1;
+                continue;
+            }
+            if id == 15 {
+                break;
+            }
+            let len = (data[i] & 0x0f) as usize + 1;
+            let Some(element) = data.get(i + 1..i + 1 + len) else {
+                break;
+            };
+            elements.push((id, element));
+            i += 1 + len;
+        }
+    } else if profile & 0xfff0 == 0x1000 {
+        while i < data.len() {
+            let id = data[i];
+            if id == 0 {
+                // padding
+                i += 1;
+                continue;
+            }
+            let Some(&len) = data.get(i + 1) else {
+                break;
+            };
+            let Some(element) = data.get(i + 2..i + 2 + len as usize) else {
+                break;
+            };
+            elements.push((id, element));
+            i += 2 + len as usize;
+        }
+    }
+
+    elements
+}
+
+/// Returns the transport-wide sequence number of a RTP packet
+pub fn read_seqnum(rtp: &RtpPacket, ext_id: u8) -> Option<u16> {
+    let (profile, data) = rtp.extension()?;
+    let (_id, element) = extension_elements(profile, data)
+        .into_iter()
+        .find(|(id, _)| *id == ext_id)?;
+
+    Some(u16::from_be_bytes(element.get(..2)?.try_into().unwrap()))
+}
+
+/// Produces a copy of a RTP packet with the transport-wide sequence number header extension set
+/// to `seqnum`, keeping all other header extensions and the padding
+pub fn write_seqnum(rtp: &RtpPacket, ext_id: u8, seqnum: u16) -> Option<Vec<u8>> {
+    let seqnum = seqnum.to_be_bytes();
+    let mut elements = rtp
+        .extension()
+        .map(|(profile, data)| extension_elements(profile, data))
+        .unwrap_or_default();
+    elements.retain(|(id, _)| *id != ext_id);
+    elements.push((ext_id, seqnum.as_slice()));
+
+    let one_byte = elements
+        .iter()
+        .all(|(id, data)| (1..=14).contains(id) && (1..=16).contains(&data.len()));
+    let mut ext = Vec::new();
+    let profile = if one_byte {
+        for (id, data) in &elements {
+            ext.push((id << 4) | (data.len() as u8 - 1));
+            ext.extend_from_slice(data);
+        }
+        0xbede
+    } else {
+        for (id, data) in &elements {
+            ext.push(*id);
+            ext.push(data.len() as u8);
+            ext.extend_from_slice(data);
+        }
+        0x1000
+    };
+    while ext.len() % 4 != 0 {
+        ext.push(0);
+    }
+
+    let mut builder = RtpPacketBuilder::new()
+        .payload_type(rtp.payload_type())
+        .ssrc(rtp.ssrc())
+        .sequence_number(rtp.sequence_number())
+        .timestamp(rtp.timestamp())
+        .marker_bit(rtp.marker_bit())
+        .extension(profile, ext.as_slice());
+    for csrc in rtp.csrc() {
+        builder = builder.add_csrc(csrc);
+    }
+    if let Some(padding) = rtp.padding() {
+        builder = builder.padding(padding);
+    }
+
+    builder.payload(rtp.payload()).write_vec().ok()
+}
+
+/// Generates feedback for received packets with a transport-wide sequence number
+#[derive(Debug)]
+pub struct TwccReceiver {
+    interval: Duration,
+    base_time: Option<Instant>,
+    /// Arrival times of packets not reported yet, by extended sequence number
+    packets: BTreeMap<u64, Instant>,
+    ext_seqnum: ExtendedSeqnum,
+    /// First sequence number of the next feedback packet
+    next_report_seqnum: Option<u64>,
+    media_ssrc: u32,
+    fb_pkt_count: u8,
+    next_feedback: Option<Instant>,
+}
+
+impl Default for TwccReceiver {
+    fn default() -> Self {
+        Self {
+            interval: TWCC_DEFAULT_FEEDBACK_INTERVAL,
+            base_time: None,
+            packets: BTreeMap::new(),
+            ext_seqnum: ExtendedSeqnum::default(),
+            next_report_seqnum: None,
+            media_ssrc: 0,
+            fb_pkt_count: 0,
+            next_feedback: None,
+        }
+    }
+}
+
+impl TwccReceiver {
+    pub fn set_feedback_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Record the arrival of a packet.  Returns `true` if this scheduled a new feedback packet.
+    pub fn recv_packet(&mut self, ssrc: u32, seqnum: u16, now: Instant) -> bool {
+        let seqnum = self.ext_seqnum.next(seqnum);
+        if self
+            .next_report_seqnum
+            .is_some_and(|next_report_seqnum| seqnum < next_report_seqnum)
+        {
+            // Already reported as lost
+            return false;
+        }
+
+        self.base_time.get_or_insert(now);
+        self.packets.entry(seqnum).or_insert(now);
+        self.media_ssrc = ssrc;
+
+        if self.next_feedback.is_none() {
+            self.next_feedback = Some(now + self.interval);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The time the next feedback packet is due
+    pub fn next_feedback_time(&self) -> Option<Instant> {
+        self.next_feedback
+    }
+
+    /// Produce a feedback packet if one is due
+    pub fn generate_feedback(&mut self, now: Instant, sender_ssrc: u32) -> Option<Vec<u8>> {
+        let Some(next_feedback) = self.next_feedback else {
+            return None;
+        };
+        if now < next_feedback {
+            return None;
+        }
+
+        let (Some((&first, _)), Some((&last, _))) = (
+            self.packets.first_key_value(),
+            self.packets.last_key_value(),
+        ) else {
+            self.next_feedback = None;
+            return None;
+        };
+
+        let base = match self.next_report_seqnum {
+            Some(next_report_seqnum) if first - next_report_seqnum < MAX_PACKETS_PER_FEEDBACK => {
+                next_report_seqnum
+            }
+            _ => first,
+        };
+        let end = last.min(base + MAX_PACKETS_PER_FEEDBACK - 1);
+
+        let base_time = self.base_time.unwrap();
+        let arrival_us = |arrival: Instant| arrival.duration_since(base_time).as_micros() as i64;
+
+        let reference_time = arrival_us(self.packets[&first]) / REFERENCE_TIME_US;
+        let mut prev_ticks = reference_time * REFERENCE_TIME_US / DELTA_TICK_US;
+        let mut symbols = Vec::with_capacity((end - base + 1) as usize);
+        let mut deltas = Vec::new();
+        for seqnum in base..=end {
+            let Some(&arrival) = self.packets.get(&seqnum) else {
+                symbols.push(SYMBOL_NOT_RECEIVED);
+                continue;
+            };
+
+            let ticks = arrival_us(arrival) / DELTA_TICK_US;
+            let delta = ticks - prev_ticks;
+            prev_ticks = ticks;
+            if (0..=255).contains(&delta) {
+                symbols.push(SYMBOL_SMALL_DELTA);
+                deltas.push(delta as u8);
+            } else {
+                symbols.push(SYMBOL_LARGE_DELTA);
+                let delta = delta.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+                deltas.extend_from_slice(&delta.to_be_bytes());
+            }
+        }
+
+        let mut data = vec![0x80 | TWCC_FMT, RTPFB_PT, 0, 0];
+        data.extend_from_slice(&sender_ssrc.to_be_bytes());
+        data.extend_from_slice(&self.media_ssrc.to_be_bytes());
+        data.extend_from_slice(&(base as u16).to_be_bytes());
+        data.extend_from_slice(&(symbols.len() as u16).to_be_bytes());
+        data.extend_from_slice(&(reference_time as u32).to_be_bytes()[1..]);
+        data.push(self.fb_pkt_count);
+        write_chunks(&symbols, &mut data);
+        data.extend_from_slice(&deltas);
+
+        let padding = (4 - data.len() % 4) % 4;
+        if padding > 0 {
+            data.resize(data.len() + padding - 1, 0);
+            data.push(padding as u8);
+            data[0] |= 0x20;
+        }
+        let length = (data.len() / 4 - 1) as u16;
+        data[2..4].copy_from_slice(&length.to_be_bytes());
+
+        self.packets = self.packets.split_off(&(end + 1));
+        self.next_report_seqnum = Some(end + 1);
+        self.fb_pkt_count = self.fb_pkt_count.wrapping_add(1);
+        // Send the remaining packets right away if they did not fit into this feedback packet
+        self.next_feedback = if self.packets.is_empty() {
+            None
+        } else {
+            Some(now)
+        };
+
+        Some(data)
+    }
+}
+
+fn write_chunks(symbols: &[u8], data: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < symbols.len() {
+        let run = symbols[i..]
+            .iter()
+            .take_while(|&&symbol| symbol == symbols[i])
+            .count()
+            .min(0x1fff);
+
+        if run >= 7 || i + run == symbols.len() {
+            // run length chunk
+            let chunk = ((symbols[i] as u16) << 13) | run as u16;
+            data.extend_from_slice(&chunk.to_be_bytes());
+            i += run;
+        } else {
+            // status vector chunk with 7 two-bit symbols
+            let mut chunk = 0xc000u16;
+            for (j, &symbol) in symbols[i..].iter().take(7).enumerate() {
+                chunk |= (symbol as u16) << (12 - 2 * j);
+            }
+            data.extend_from_slice(&chunk.to_be_bytes());
+            i += 7;
+        }
+    }
+}
+
+/// Received feedback about packets sent by us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feedback {
+    pub sender_ssrc: u32,
+    pub media_ssrc: u32,
+    pub fb_pkt_count: u8,
+    /// Sequence number and arrival time in microseconds of all reported packets, `None` if the
+    /// packet was not received
+    pub packets: Vec<(u16, Option<i64>)>,
+}
+
+/// Parse all transport-wide congestion control feedback packets of a RTCP compound packet
+pub fn parse_feedback(mut data: &[u8]) -> Vec<Feedback> {
+    let mut ret = Vec::new();
+
+    while data.len() >= 4 && data[0] >> 6 == 2 {
+        let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+        let Some(packet) = data.get(..len) else {
+            break;
+        };
+
+        if packet[1] == RTPFB_PT && packet[0] & 0x1f == TWCC_FMT && packet.len() >= 12 {
+            let sender_ssrc = u32::from_be_bytes(packet[4..8].try_into().unwrap());
+            let media_ssrc = u32::from_be_bytes(packet[8..12].try_into().unwrap());
+            if let Some(feedback) = parse_fci(sender_ssrc, media_ssrc, &packet[12..]) {
+                ret.push(feedback);
+            }
+        }
+
+        data = &data[len..];
+    }
+
+    ret
+}
+
+fn parse_fci(sender_ssrc: u32, media_ssrc: u32, fci: &[u8]) -> Option<Feedback> {
+    let base = u16::from_be_bytes(fci.get(0..2)?.try_into().unwrap());
+    let count = u16::from_be_bytes(fci.get(2..4)?.try_into().unwrap()) as usize;
+    let reference_time = fci.get(4..7)?;
+    // 24 bit signed integer
+    let reference_time =
+        (i32::from_be_bytes([reference_time[0], reference_time[1], reference_time[2], 0]) >> 8)
+            as i64;
+    let fb_pkt_count = *fci.get(7)?;
+
+    let mut i = 8;
+    let mut symbols = Vec::with_capacity(count);
+    while symbols.len() < count {
+        let chunk = u16::from_be_bytes(fci.get(i..i + 2)?.try_into().unwrap());
+        i += 2;
+
+        if chunk & 0x8000 == 0 {
+            let symbol = ((chunk >> 13) & 0x3) as u8;
+            let run = (chunk & 0x1fff) as usize;
+            symbols.resize(symbols.len() + run, symbol);
+        } else if chunk & 0x4000 == 0 {
+            symbols.extend((0..14).map(|j| ((chunk >> (13 - j)) & 0x1) as u8));
+        } else {
+            symbols.extend((0..7).map(|j| ((chunk >> (12 - 2 * j)) & 0x3) as u8));
+        }
+    }
+    symbols.truncate(count);
+
+    let mut arrival = reference_time * REFERENCE_TIME_US;
+    let mut packets = Vec::with_capacity(count);
+    for (seqnum, symbol) in (0..).map(|j| base.wrapping_add(j)).zip(symbols) {
+        let delta = match symbol {
+            SYMBOL_NOT_RECEIVED => {
+                packets.push((seqnum, None));
+                continue;
+            }
+            SYMBOL_SMALL_DELTA => {
+                i += 1;
+                *fci.get(i - 1)? as i64
+            }
+            SYMBOL_LARGE_DELTA => {
+                i += 2;
+                i16::from_be_bytes(fci.get(i - 2..i)?.try_into().unwrap()) as i64
+            }
+            _ => return None,
+        };
+        arrival += delta * DELTA_TICK_US;
+        packets.push((seqnum, Some(arrival)));
+    }
+
+    Some(Feedback {
+        sender_ssrc,
+        media_ssrc,
+        fb_pkt_count,
+        packets,
+    })
+}
+
+#[derive(Debug)]
+struct SentPacket {
+    seqnum: u64,
+    ssrc: u32,
+    pt: u8,
+    size: u32,
+    local_ts: gst::ClockTime,
+}
+
+/// Feedback for a single packet sent by us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketFeedback {
+    pub seqnum: u16,
+    pub ssrc: u32,
+    pub pt: u8,
+    pub size: u32,
+    pub local_ts: gst::ClockTime,
+    /// Arrival time in the clock of the receiver, `None` if the packet was lost
+    pub remote_ts: Option<gst::ClockTime>,
+}
+
+/// Assigns transport-wide sequence numbers to sent packets and matches received feedback to
+/// them
+#[derive(Debug, Default)]
+pub struct TwccSender {
+    next_seqnum: u64,
+    sent: VecDeque<SentPacket>,
+}
+
+impl TwccSender {
+    /// Assign the next transport-wide sequence number to a packet sent at `local_ts`
+    pub fn send_packet(&mut self, ssrc: u32, pt: u8, size: u32, local_ts: gst::ClockTime) -> u16 {
+        let seqnum = self.next_seqnum;
+        self.next_seqnum += 1;
+
+        if self.sent.len() >= MAX_SENT_PACKETS {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(SentPacket {
+            seqnum,
+            ssrc,
+            pt,
+            size,
+            local_ts,
+        });
+
+        seqnum as u16
+    }
+
+    /// Match received feedback to the sent packets
+    pub fn handle_feedback(&self, feedback: &Feedback) -> Vec<PacketFeedback> {
+        trace!(
+            "Feedback {} from ssrc {} for media ssrc {} with {} packets",
+            feedback.fb_pkt_count,
+            feedback.sender_ssrc,
+            feedback.media_ssrc,
+            feedback.packets.len()
+        );
+        let Some(front) = self.sent.front() else {
+            return Vec::new();
+        };
+        let max = self.next_seqnum - 1;
+
+        feedback
+            .packets
+            .iter()
+            .filter_map(|&(seqnum, arrival)| {
+                // Feedback can only be about packets that were already sent
+                let mut ext_seqnum = (max & !0xffff) | seqnum as u64;
+                if ext_seqnum > max {
+                    ext_seqnum = ext_seqnum.checked_sub(1 << 16)?;
+                }
+                let packet = self
+                    .sent
+                    .get(ext_seqnum.checked_sub(front.seqnum)? as usize)?;
+
+                Some(PacketFeedback {
+                    seqnum,
+                    ssrc: packet.ssrc,
+                    pt: packet.pt,
+                    size: packet.size,
+                    local_ts: packet.local_ts,
+                    remote_ts: arrival
+                        .map(|arrival| gst::ClockTime::from_useconds(arrival.max(0) as u64)),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    const TWCC_EXT_ID: u8 = 3;
+
+    #[test]
+    fn header_extension() {
+        let rtp_data = generate_rtp_packet(0x12345678, 100, 0, 4);
+        let rtp = RtpPacket::parse(&rtp_data).unwrap();
+        assert_eq!(read_seqnum(&rtp, TWCC_EXT_ID), None);
+
+        let data = write_seqnum(&rtp, TWCC_EXT_ID, 0x1234).unwrap();
+        let stamped = RtpPacket::parse(&data).unwrap();
+        assert_eq!(read_seqnum(&stamped, TWCC_EXT_ID), Some(0x1234));
+        assert_eq!(
+            stamped.extension(),
+            Some((0xbede, [0x31, 0x12, 0x34, 0x00].as_slice()))
+        );
+        assert_eq!(stamped.payload(), rtp.payload());
+        assert_eq!(stamped.sequence_number(), 100);
+
+        // Other extensions are kept and the sequence number is replaced
+        let data = RtpPacketBuilder::new()
+            .payload_type(96)
+            .ssrc(0x12345678)
+            .extension(
+                0xbede,
+                [0x10, 0xff, 0x31, 0x00, 0x01, 0x00, 0x00, 0x00].as_slice(),
+            )
+            .payload([1, 2, 3].as_slice())
+            .write_vec()
+            .unwrap();
+        let rtp = RtpPacket::parse(&data).unwrap();
+        assert_eq!(read_seqnum(&rtp, TWCC_EXT_ID), Some(1));
+        let data = write_seqnum(&rtp, TWCC_EXT_ID, 2).unwrap();
+        let stamped = RtpPacket::parse(&data).unwrap();
+        assert_eq!(read_seqnum(&stamped, TWCC_EXT_ID), Some(2));
+        assert_eq!(read_seqnum(&stamped, 1), Some(0xff00));
+        assert_eq!(stamped.payload(), &[1, 2, 3]);
+
+        // Two-byte header for ids > 14
+        let data = write_seqnum(&rtp, 20, 5).unwrap();
+        let stamped = RtpPacket::parse(&data).unwrap();
+        assert_eq!(stamped.extension().unwrap().0, 0x1000);
+        assert_eq!(read_seqnum(&stamped, 20), Some(5));
+        assert_eq!(read_seqnum(&stamped, TWCC_EXT_ID), Some(1));
+
+        // Padding is kept
+        let data = RtpPacketBuilder::new()
+            .payload_type(96)
+            .ssrc(0x12345678)
+            .padding(4)
+            .payload([1, 2, 3].as_slice())
+            .write_vec()
+            .unwrap();
+        let rtp = RtpPacket::parse(&data).unwrap();
+        let data = write_seqnum(&rtp, TWCC_EXT_ID, 3).unwrap();
+        let stamped = RtpPacket::parse(&data).unwrap();
+        assert_eq!(read_seqnum(&stamped, TWCC_EXT_ID), Some(3));
+        assert_eq!(stamped.padding(), Some(4));
+        assert_eq!(stamped.payload(), &[1, 2, 3]);
+        assert_eq!(data.len(), 12 + 8 + 3 + 4);
+    }
+
+    #[test]
+    fn feedback_roundtrip() {
+        let mut receiver = TwccReceiver::default();
+        let now = Instant::now();
+
+        // 10 packets, 2ms apart with 3 and 7 lost and 8 arriving late
+        let arrivals = [
+            (0, 0),
+            (1, 2_000),
+            (2, 4_000),
+            (4, 8_000),
+            (5, 10_000),
+            (6, 12_000),
+            (9, 18_000),
+            (8, 200_000),
+        ];
+        assert!(receiver.recv_packet(0x12345678, 0xfffe, now));
+        for (seqnum, arrival_us) in &arrivals[1..] {
+            assert!(!receiver.recv_packet(
+                0x12345678,
+                0xfffe_u16.wrapping_add(*seqnum),
+                now + Duration::from_micros(*arrival_us),
+            ));
+        }
+
+        assert_eq!(
+            receiver.next_feedback_time(),
+            Some(now + TWCC_DEFAULT_FEEDBACK_INTERVAL)
+        );
+        assert!(receiver.generate_feedback(now, 0x87654321).is_none());
+        let data = receiver
+            .generate_feedback(now + TWCC_DEFAULT_FEEDBACK_INTERVAL, 0x87654321)
+            .unwrap();
+        assert_eq!(data.len() % 4, 0);
+        assert_eq!(receiver.next_feedback_time(), None);
+
+        let feedback = parse_feedback(&data);
+        assert_eq!(feedback.len(), 1);
+        let feedback = &feedback[0];
+        assert_eq!(feedback.sender_ssrc, 0x87654321);
+        assert_eq!(feedback.media_ssrc, 0x12345678);
+        assert_eq!(feedback.fb_pkt_count, 0);
+        assert_eq!(feedback.packets.len(), 10);
+
+        let received = feedback
+            .packets
+            .iter()
+            .filter_map(|&(seqnum, arrival)| Some((seqnum, arrival?)))
+            .collect::<Vec<_>>();
+        assert_eq!(received.len(), 8);
+        for (seqnum, arrival_us) in arrivals {
+            let (_, arrival) = received
+                .iter()
+                .find(|(s, _)| *s == 0xfffe_u16.wrapping_add(seqnum))
+                .unwrap();
+            assert_eq!(*arrival - received[0].1, arrival_us as i64);
+        }
+        assert_eq!(feedback.packets[3], (0x0001, None));
+        assert_eq!(feedback.packets[7], (0x0005, None));
+
+        // Late packets for already reported sequence numbers are ignored
+        assert!(!receiver.recv_packet(0x12345678, 0x0001, now + Duration::from_millis(150)));
+        assert!(receiver
+            .generate_feedback(now + Duration::from_millis(300), 0x87654321)
+            .is_none());
+    }
+
+    #[test]
+    fn feedback_long_run() {
+        let mut receiver = TwccReceiver::default();
+        let now = Instant::now();
+
+        receiver.recv_packet(0x12345678, 0, now);
+        receiver.recv_packet(0x12345678, 1500, now + Duration::from_millis(1));
+        let first = receiver
+            .generate_feedback(now + TWCC_DEFAULT_FEEDBACK_INTERVAL, 0x87654321)
+            .unwrap();
+        // The remaining packets are reported right away
+        let second = receiver
+            .generate_feedback(now + TWCC_DEFAULT_FEEDBACK_INTERVAL, 0x87654321)
+            .unwrap();
+
+        let first = &parse_feedback(&first)[0];
+        let second = &parse_feedback(&second)[0];
+        assert_eq!(first.packets.len(), MAX_PACKETS_PER_FEEDBACK as usize);
+        assert_eq!(second.fb_pkt_count, 1);
+        assert_eq!(second.packets.last(), Some(&(1500, Some(1000))));
+        assert_eq!(
+            first.packets.len() + second.packets.len(),
+            1501,
+            "all packets reported"
+        );
+    }
+
+    #[test]
+    fn sender_feedback() {
+        let mut sender = TwccSender::default();
+        let mut receiver = TwccReceiver::default();
+        let now = Instant::now();
+
+        for i in 0..10u64 {
+            let seqnum =
+                sender.send_packet(0x12345678, 96, 100, gst::ClockTime::from_mseconds(1000 + i));
+            assert_eq!(seqnum, i as u16);
+            if i != 5 {
+                receiver.recv_packet(0x12345678, seqnum, now + Duration::from_millis(i));
+            }
+        }
+
+        let data = receiver
+            .generate_feedback(now + TWCC_DEFAULT_FEEDBACK_INTERVAL, 0x87654321)
+            .unwrap();
+        let feedback = &parse_feedback(&data)[0];
+        let packets = sender.handle_feedback(feedback);
+        assert_eq!(packets.len(), 10);
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.seqnum, i as u16);
+            assert_eq!(packet.size, 100);
+            assert_eq!(
+                packet.local_ts,
+                gst::ClockTime::from_mseconds(1000 + i as u64)
+            );
+            if i == 5 {
+                assert_eq!(packet.remote_ts, None);
+            } else {
+                assert_eq!(
+                    packet.remote_ts.unwrap() - packets[0].remote_ts.unwrap(),
+                    gst::ClockTime::from_mseconds(i as u64)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,754 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! RTCP Extended Reports (XR) as specified in RFC 3611.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::utils::ExtendedSeqnum;
+
+use super::time::NtpTime;
+
+/// RTCP packet type of extended reports
+const XR_PT: u8 = 207;
+
+const BT_LOSS_RLE: u8 = 1;
+const BT_RRT: u8 = 4;
+const BT_DLRR: u8 = 5;
+const BT_VOIP_METRICS: u8 = 7;
+
+/// Maximum number of packets covered by a single loss RLE or VoIP metrics report block
+const MAX_REPORT_PACKETS: u64 = 2048;
+/// Minimum number of received packets between two losses for the losses to not be part of the
+/// same burst, RFC 3611 4.7.2
+const GMIN: u8 = 16;
+/// Value of VoIP metrics fields that are not available
+const UNAVAILABLE: u8 = 127;
+
+/// Which extended report blocks to send
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct XrConfig {
+    /// Receiver reference time report block
+    pub rrt: bool,
+    /// DLRR report block in response to received receiver reference times
+    pub dlrr: bool,
+    /// Loss RLE report block for each remote sender
+    pub loss_rle: bool,
+    /// VoIP metrics report block for each remote sender
+    pub voip_metrics: bool,
+}
+
+/// A DLRR sub-block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dlrr {
+    pub ssrc: u32,
+    /// Middle 32 bits of the last received receiver reference time
+    pub last_rr: u32,
+    /// Delay since the last received receiver reference time in units of 1/65536 seconds
+    pub delay_since_last_rr: u32,
+}
+
+/// Loss RLE report block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossRle {
+    pub ssrc: u32,
+    pub begin_seq: u16,
+    /// One past the last sequence number covered by the report
+    pub end_seq: u16,
+    pub chunks: Vec<u16>,
+}
+
+impl LossRle {
+    fn new(ssrc: u32, begin_seq: u64, received: &[bool]) -> Self {
+        let mut chunks = Vec::new();
+        let mut i = 0;
+        while i < received.len() {
+            let run = received[i..]
+                .iter()
+                .take_while(|&&r| r == received[i])
+                .count()
+                .min(0x3fff);
+
+            if run >= 15 || i + run == received.len() {
+                // run length chunk
+                chunks.push(((received[i] as u16) << 14) | run as u16);
+                i += run;
+            } else {
+                // bit vector chunk
+                let mut chunk = 0x8000u16;
+                for (j, &r) in received[i..].iter().take(15).enumerate() {
+                    chunk |= (r as u16) << (14 - j);
+                }
+                chunks.push(chunk);
+                i += 15;
+            }
+        }
+
+        Self {
+            ssrc,
+            begin_seq: begin_seq as u16,
+            end_seq: (begin_seq + received.len() as u64) as u16,
+            chunks,
+        }
+    }
+
+    /// Received status of each packet covered by this report
+    pub fn received(&self) -> Vec<bool> {
+        let count = self.end_seq.wrapping_sub(self.begin_seq) as usize;
+        let mut received = Vec::with_capacity(count);
+
+        for &chunk in &self.chunks {
+            if received.len() >= count {
+                break;
+            }
+            if chunk == 0 {
+                // null chunk
+                continue;
+            }
+            if chunk & 0x8000 == 0 {
+                let run_type = chunk & 0x4000 != 0;
+                let run = (chunk & 0x3fff) as usize;
+                received.resize(received.len() + run, run_type);
+            } else {
+                received.extend((0..15).map(|j| (chunk >> (14 - j)) & 0x1 != 0));
+            }
+        }
+        received.truncate(count);
+
+        received
+    }
+
+    /// Number of packets reported as received
+    pub fn packets_received(&self) -> u32 {
+        self.received().iter().filter(|&&r| r).count() as u32
+    }
+
+    /// Number of packets reported as lost
+    pub fn packets_lost(&self) -> u32 {
+        self.received().iter().filter(|&&r| !r).count() as u32
+    }
+}
+
+/// VoIP metrics report block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoipMetrics {
+    pub ssrc: u32,
+    pub loss_rate: u8,
+    pub discard_rate: u8,
+    pub burst_density: u8,
+    pub gap_density: u8,
+    pub burst_duration: u16,
+    pub gap_duration: u16,
+    pub round_trip_delay: u16,
+    pub end_system_delay: u16,
+    pub signal_level: u8,
+    pub noise_level: u8,
+    pub rerl: u8,
+    pub gmin: u8,
+    pub r_factor: u8,
+    pub ext_r_factor: u8,
+    pub mos_lq: u8,
+    pub mos_cq: u8,
+    pub rx_config: u8,
+    pub jb_nominal: u16,
+    pub jb_maximum: u16,
+    pub jb_abs_max: u16,
+}
+
+impl VoipMetrics {
+    /// Calculate the loss, burst and gap metrics from the received status of each packet and the
+    /// arrival times of the received packets.  Metrics that can't be measured by the RTP session
+    /// are reported as unavailable.
+    fn new(ssrc: u32, received: &[bool], arrival_times: &[Instant]) -> Self {
+        let n_packets = received.len() as u64;
+        let n_lost = received.iter().filter(|&&r| !r).count() as u64;
+
+        // Losses separated by less than Gmin received packets are part of the same burst
+        let mut bursts: Vec<(usize, usize, u64)> = Vec::new();
+        let mut last_loss: Option<usize> = None;
+        let mut group_start = 0;
+        let mut group_lost = 0;
+        for (i, _) in received.iter().enumerate().filter(|(_, &r)| !r) {
+            match last_loss {
+                Some(last) if i - last - 1 < GMIN as usize => group_lost += 1,
+                Some(last) => {
+                    if group_lost > 1 {
+                        bursts.push((group_start, last, group_lost));
+                    }
+                    group_start = i;
+                    group_lost = 1;
+                }
+                None => {
+                    group_start = i;
+                    group_lost = 1;
+                }
+            }
+            last_loss = Some(i);
+        }
+        if let Some(last) = last_loss {
+            if group_lost > 1 {
+                bursts.push((group_start, last, group_lost));
+            }
+        }
+
+        let burst_packets = bursts
+            .iter()
+            .map(|(start, end, _)| (end - start + 1) as u64)
+            .sum::<u64>();
+        let burst_lost = bursts.iter().map(|(_, _, lost)| lost).sum::<u64>();
+        let gap_packets = n_packets - burst_packets;
+        let gap_lost = n_lost - burst_lost;
+        // Gaps are the non-empty periods before, between and after the bursts
+        let mut n_gaps = 0;
+        let mut pos = 0;
+        for (start, end, _) in &bursts {
+            if *start > pos {
+                n_gaps += 1;
+            }
+            pos = end + 1;
+        }
+        if pos < received.len() {
+            n_gaps += 1;
+        }
+
+        let packet_interval = match (arrival_times.first(), arrival_times.last()) {
+            (Some(first), Some(last)) if n_packets > 1 => {
+                last.saturating_duration_since(*first) / (n_packets - 1) as u32
+            }
+            _ => Duration::ZERO,
+        };
+        let density = |lost: u64, packets: u64| {
+            (lost * 256)
+                .checked_div(packets)
+                .map_or(0, |density| density.min(255) as u8)
+        };
+        let duration = |packets: u64, n: u64| {
+            packets.checked_div(n).map_or(0, |mean_packets| {
+                (packet_interval * mean_packets as u32)
+                    .as_millis()
+                    .min(u16::MAX as u128) as u16
+            })
+        };
+
+        Self {
+            ssrc,
+            loss_rate: density(n_lost, n_packets),
+            discard_rate: 0,
+            burst_density: density(burst_lost, burst_packets),
+            gap_density: density(gap_lost, gap_packets),
+            burst_duration: duration(burst_packets, bursts.len() as u64),
+            gap_duration: duration(gap_packets, n_gaps),
+            round_trip_delay: 0,
+            end_system_delay: 0,
+            signal_level: UNAVAILABLE,
+            noise_level: UNAVAILABLE,
+            rerl: UNAVAILABLE,
+            gmin: GMIN,
+            r_factor: UNAVAILABLE,
+            ext_r_factor: UNAVAILABLE,
+            mos_lq: UNAVAILABLE,
+            mos_cq: UNAVAILABLE,
+            rx_config: 0,
+            jb_nominal: 0,
+            jb_maximum: 0,
+            jb_abs_max: 0,
+        }
+    }
+}
+
+/// An extended report block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XrBlock {
+    LossRle(LossRle),
+    /// Receiver reference time as 64 bit NTP timestamp
+    ReceiverReferenceTime(u64),
+    Dlrr(Vec<Dlrr>),
+    VoipMetrics(VoipMetrics),
+}
+
+/// A RTCP extended report packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XrPacket {
+    pub ssrc: u32,
+    pub blocks: Vec<XrBlock>,
+}
+
+impl XrPacket {
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = vec![0x80, XR_PT, 0, 0];
+        data.extend_from_slice(&self.ssrc.to_be_bytes());
+
+        for block in &self.blocks {
+            let start = data.len();
+            match block {
+                XrBlock::LossRle(rle) => {
+                    data.extend_from_slice(&[BT_LOSS_RLE, 0, 0, 0]);
+                    data.extend_from_slice(&rle.ssrc.to_be_bytes());
+                    data.extend_from_slice(&rle.begin_seq.to_be_bytes());
+                    data.extend_from_slice(&rle.end_seq.to_be_bytes());
+                    for chunk in &rle.chunks {
+                        data.extend_from_slice(&chunk.to_be_bytes());
+                    }
+                    if rle.chunks.len() % 2 != 0 {
+                        // null chunk
+                        data.extend_from_slice(&[0, 0]);
+                    }
+                }
+                XrBlock::ReceiverReferenceTime(ntp) => {
+                    data.extend_from_slice(&[BT_RRT, 0, 0, 0]);
+                    data.extend_from_slice(&ntp.to_be_bytes());
+                }
+                XrBlock::Dlrr(dlrrs) => {
+                    data.extend_from_slice(&[BT_DLRR, 0, 0, 0]);
+                    for dlrr in dlrrs {
+                        data.extend_from_slice(&dlrr.ssrc.to_be_bytes());
+                        data.extend_from_slice(&dlrr.last_rr.to_be_bytes());
+                        data.extend_from_slice(&dlrr.delay_since_last_rr.to_be_bytes());
+                    }
+                }
+                XrBlock::VoipMetrics(m) => {
+                    data.extend_from_slice(&[BT_VOIP_METRICS, 0, 0, 0]);
+                    data.extend_from_slice(&m.ssrc.to_be_bytes());
+                    data.extend_from_slice(&[
+                        m.loss_rate,
+                        m.discard_rate,
+                        m.burst_density,
+                        m.gap_density,
+                    ]);
+                    data.extend_from_slice(&m.burst_duration.to_be_bytes());
+                    data.extend_from_slice(&m.gap_duration.to_be_bytes());
+                    data.extend_from_slice(&m.round_trip_delay.to_be_bytes());
+                    data.extend_from_slice(&m.end_system_delay.to_be_bytes());
+                    data.extend_from_slice(&[m.signal_level, m.noise_level, m.rerl, m.gmin]);
+                    data.extend_from_slice(&[m.r_factor, m.ext_r_factor, m.mos_lq, m.mos_cq]);
+                    data.extend_from_slice(&[m.rx_config, 0]);
+                    data.extend_from_slice(&m.jb_nominal.to_be_bytes());
+                    data.extend_from_slice(&m.jb_maximum.to_be_bytes());
+                    data.extend_from_slice(&m.jb_abs_max.to_be_bytes());
+                }
+            }
+            let block_length = ((data.len() - start) / 4 - 1) as u16;
+            data[start + 2..start + 4].copy_from_slice(&block_length.to_be_bytes());
+        }
+
+        let length = (data.len() / 4 - 1) as u16;
+        data[2..4].copy_from_slice(&length.to_be_bytes());
+
+        data
+    }
+
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let ssrc = u32::from_be_bytes(packet.get(4..8)?.try_into().unwrap());
+        let mut blocks = Vec::new();
+
+        let mut data = &packet[8..];
+        while data.len() >= 4 {
+            let block_type = data[0];
+            let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+            let block = data.get(4..len)?;
+            let u16_at = |i: usize| u16::from_be_bytes([block[i], block[i + 1]]);
+            let u32_at = |i: usize| u32::from_be_bytes(block[i..i + 4].try_into().unwrap());
+
+            match block_type {
+                BT_LOSS_RLE if block.len() >= 8 => {
+                    blocks.push(XrBlock::LossRle(LossRle {
+                        ssrc: u32_at(0),
+                        begin_seq: u16_at(4),
+                        end_seq: u16_at(6),
+                        chunks: block[8..]
+                            .chunks_exact(2)
+                            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+                            // null chunks are padding
+                            .filter(|&chunk| chunk != 0)
+                            .collect(),
+                    }));
+                }
+                BT_RRT if block.len() >= 8 => {
+                    blocks.push(XrBlock::ReceiverReferenceTime(
+                        ((u32_at(0) as u64) << 32) | u32_at(4) as u64,
+                    ));
+                }
+                BT_DLRR => {
+                    blocks.push(XrBlock::Dlrr(
+                        block
+                            .chunks_exact(12)
+                            .map(|sub_block| Dlrr {
+                                ssrc: u32::from_be_bytes(sub_block[0..4].try_into().unwrap()),
+                                last_rr: u32::from_be_bytes(sub_block[4..8].try_into().unwrap()),
+                                delay_since_last_rr: u32::from_be_bytes(
+                                    sub_block[8..12].try_into().unwrap(),
+                                ),
+                            })
+                            .collect(),
+                    ));
+                }
+                BT_VOIP_METRICS if block.len() >= 32 => {
+                    blocks.push(XrBlock::VoipMetrics(VoipMetrics {
+                        ssrc: u32_at(0),
+                        loss_rate: block[4],
+                        discard_rate: block[5],
+                        burst_density: block[6],
+                        gap_density: block[7],
+                        burst_duration: u16_at(8),
+                        gap_duration: u16_at(10),
+                        round_trip_delay: u16_at(12),
+                        end_system_delay: u16_at(14),
+                        signal_level: block[16],
+                        noise_level: block[17],
+                        rerl: block[18],
+                        gmin: block[19],
+                        r_factor: block[20],
+                        ext_r_factor: block[21],
+                        mos_lq: block[22],
+                        mos_cq: block[23],
+                        rx_config: block[24],
+                        jb_nominal: u16_at(26),
+                        jb_maximum: u16_at(28),
+                        jb_abs_max: u16_at(30),
+                    }));
+                }
+                _ => trace!("Ignoring XR report block of type {block_type}"),
+            }
+
+            data = &data[len..];
+        }
+
+        Some(Self { ssrc, blocks })
+    }
+}
+
+/// Parse all extended report packets of a RTCP compound packet
+pub fn parse_xr(mut data: &[u8]) -> Vec<XrPacket> {
+    let mut ret = Vec::new();
+
+    while data.len() >= 4 && data[0] >> 6 == 2 {
+        let len = (u16::from_be_bytes([data[2], data[3]]) as usize + 1) * 4;
+        let Some(packet) = data.get(..len) else {
+            break;
+        };
+
+        if packet[1] == XR_PT {
+            // Ignore padding
+            let packet = if packet[0] & 0x20 != 0 {
+                &packet[..len.saturating_sub(packet[len - 1] as usize)]
+            } else {
+                packet
+            };
+            if let Some(xr) = XrPacket::parse(packet) {
+                ret.push(xr);
+            }
+        }
+
+        data = &data[len..];
+    }
+
+    ret
+}
+
+/// Extended reports received from a remote source
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReceivedXr {
+    /// Last received receiver reference time as 64 bit NTP timestamp
+    pub rrt_ntp_time: Option<u64>,
+    /// Round trip time calculated from the last DLRR report block about one of our sources
+    pub round_trip_time: Option<Duration>,
+    pub loss_rle: Option<LossRle>,
+    pub voip_metrics: Option<VoipMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct LossHistory {
+    ext_seqnum: ExtendedSeqnum,
+    /// First sequence number of the next report
+    begin: Option<u64>,
+    /// Arrival times of the packets received since the last report
+    received: BTreeMap<u64, Instant>,
+}
+
+impl LossHistory {
+    fn recv_packet(&mut self, seqnum: u16, now: Instant) {
+        let seqnum = self.ext_seqnum.next(seqnum);
+        let begin = *self.begin.get_or_insert(seqnum);
+        if seqnum < begin {
+            return;
+        }
+
+        self.received.insert(seqnum, now);
+        if seqnum - begin >= MAX_REPORT_PACKETS {
+            let begin = seqnum + 1 - MAX_REPORT_PACKETS;
+            self.received = self.received.split_off(&begin);
+            self.begin = Some(begin);
+        }
+    }
+
+    /// Returns the first sequence number, the received status of all packets since the last
+    /// report and the arrival times of the received packets
+    fn take_report(&mut self) -> Option<(u64, Vec<bool>, Vec<Instant>)> {
+        let begin = self.begin?;
+        let (&end, _) = self.received.last_key_value()?;
+
+        let received = (begin..=end)
+            .map(|seqnum| self.received.contains_key(&seqnum))
+            .collect();
+        let arrival_times = self.received.values().copied().collect();
+        self.received.clear();
+        self.begin = Some(end + 1);
+
+        Some((begin, received, arrival_times))
+    }
+}
+
+/// Extended report state of a session
+#[derive(Debug, Default)]
+pub struct XrState {
+    config: XrConfig,
+    /// Packets received from remote senders for loss RLE and VoIP metrics reports
+    loss: HashMap<u32, LossHistory>,
+    /// Middle 32 bits of the last received receiver reference time per SSRC and its arrival time
+    received_rrt: HashMap<u32, (u32, Instant)>,
+    received: HashMap<u32, ReceivedXr>,
+}
+
+impl XrState {
+    pub fn set_config(&mut self, config: XrConfig) {
+        self.config = config;
+        if !config.loss_rle && !config.voip_metrics {
+            self.loss.clear();
+        }
+    }
+
+    pub fn config(&self) -> XrConfig {
+        self.config
+    }
+
+    /// Record a RTP packet received from a remote sender
+    pub fn recv_packet(&mut self, ssrc: u32, seqnum: u16, now: Instant) {
+        if self.config.loss_rle || self.config.voip_metrics {
+            self.loss.entry(ssrc).or_default().recv_packet(seqnum, now);
+        }
+    }
+
+    /// Produce an extended report packet sent from `ssrc` with the configured report blocks, or
+    /// `None` if there is nothing to report
+    pub fn generate(&mut self, ssrc: u32, now: Instant, ntp_now: NtpTime) -> Option<Vec<u8>> {
+        let mut blocks = Vec::new();
+
+        if self.config.rrt {
+            blocks.push(XrBlock::ReceiverReferenceTime(ntp_now.as_u64()));
+        }
+        if self.config.dlrr && !self.received_rrt.is_empty() {
+            blocks.push(XrBlock::Dlrr(
+                self.received_rrt
+                    .iter()
+                    .map(|(&ssrc, &(last_rr, time))| Dlrr {
+                        ssrc,
+                        last_rr,
+                        delay_since_last_rr: NtpTime::from_duration(
+                            now.saturating_duration_since(time),
+                        )
+                        .as_u32(),
+                    })
+                    .collect(),
+            ));
+        }
+        for (&source_ssrc, history) in self.loss.iter_mut() {
+            let Some((begin, received, arrival_times)) = history.take_report() else {
+                continue;
+            };
+            if self.config.loss_rle {
+                blocks.push(XrBlock::LossRle(LossRle::new(
+                    source_ssrc,
+                    begin,
+                    &received,
+                )));
+            }
+            if self.config.voip_metrics {
+                blocks.push(XrBlock::VoipMetrics(VoipMetrics::new(
+                    source_ssrc,
+                    &received,
+                    &arrival_times,
+                )));
+            }
+        }
+
+        if blocks.is_empty() {
+            return None;
+        }
+
+        Some(XrPacket { ssrc, blocks }.write())
+    }
+
+    /// Handle a received extended report packet.  `is_local` returns whether a SSRC is one of ours.
+    pub fn handle_xr(
+        &mut self,
+        xr: &XrPacket,
+        now: Instant,
+        ntp_now: NtpTime,
+        is_local: impl Fn(u32) -> bool,
+    ) {
+        let received = self.received.entry(xr.ssrc).or_default();
+
+        for block in &xr.blocks {
+            match block {
+                XrBlock::ReceiverReferenceTime(ntp) => {
+                    self.received_rrt
+                        .insert(xr.ssrc, (NtpTime::from(*ntp).as_u32(), now));
+                    received.rrt_ntp_time = Some(*ntp);
+                }
+                XrBlock::Dlrr(dlrrs) => {
+                    for dlrr in dlrrs {
+                        if !is_local(dlrr.ssrc) || dlrr.last_rr == 0 {
+                            continue;
+                        }
+                        // 16.16 fixed point
+                        let rtt = ntp_now
+                            .as_u32()
+                            .wrapping_sub(dlrr.last_rr)
+                            .wrapping_sub(dlrr.delay_since_last_rr);
+                        if rtt > 0x7fff_ffff {
+                            trace!("Ignoring DLRR with bogus round trip time from {}", xr.ssrc);
+                            continue;
+                        }
+                        received.round_trip_time =
+                            Some(Duration::from_nanos(rtt as u64 * 1_000_000_000 / 65_536));
+                    }
+                }
+                XrBlock::LossRle(rle) => received.loss_rle = Some(rle.clone()),
+                XrBlock::VoipMetrics(metrics) => received.voip_metrics = Some(*metrics),
+            }
+        }
+    }
+
+    /// The extended reports last received from the given SSRC
+    pub fn received(&self, ssrc: u32) -> Option<&ReceivedXr> {
+        self.received.get(&ssrc)
+    }
+
+    /// Forget about all SSRCs for which `f` returns `false`
+    pub fn retain(&mut self, f: impl Fn(u32) -> bool) {
+        self.loss.retain(|&ssrc, _| f(ssrc));
+        self.received_rrt.retain(|&ssrc, _| f(ssrc));
+        self.received.retain(|&ssrc, _| f(ssrc));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_rle() {
+        let mut received = vec![true; 40];
+        received[3] = false;
+        received[20..30].fill(false);
+        let rle = LossRle::new(0x12345678, 0xfff0, &received);
+        assert_eq!(rle.begin_seq, 0xfff0);
+        assert_eq!(rle.end_seq, 0x0018);
+        assert_eq!(rle.received(), received);
+        assert_eq!(rle.packets_lost(), 11);
+        assert_eq!(rle.packets_received(), 29);
+
+        let xr = XrPacket {
+            ssrc: 0x87654321,
+            blocks: vec![XrBlock::LossRle(rle)],
+        };
+        let data = xr.write();
+        assert_eq!(data.len() % 4, 0);
+        assert_eq!(parse_xr(&data), vec![xr]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let now = Instant::now();
+        let ntp_now = NtpTime::from(0x1234_5678_9abc_def0);
+        let mut sender = XrState::default();
+        sender.set_config(XrConfig {
+            rrt: true,
+            dlrr: false,
+            loss_rle: true,
+            voip_metrics: true,
+        });
+        let mut receiver = XrState::default();
+        receiver.set_config(XrConfig {
+            dlrr: true,
+            ..Default::default()
+        });
+
+        for seqnum in 100..200u16 {
+            if seqnum % 20 != 0 {
+                sender.recv_packet(
+                    0x11111111,
+                    seqnum,
+                    now + Duration::from_millis(seqnum as u64 * 20),
+                );
+            }
+        }
+
+        let data = sender.generate(0x22222222, now, ntp_now).unwrap();
+        let xr = parse_xr(&data);
+        assert_eq!(xr.len(), 1);
+        assert_eq!(xr[0].ssrc, 0x22222222);
+        receiver.handle_xr(&xr[0], now, ntp_now, |_| false);
+
+        let received = receiver.received(0x22222222).unwrap();
+        assert_eq!(received.rrt_ntp_time, Some(ntp_now.as_u64()));
+        let rle = received.loss_rle.as_ref().unwrap();
+        assert_eq!(rle.ssrc, 0x11111111);
+        assert_eq!((rle.begin_seq, rle.end_seq), (101, 200));
+        assert_eq!(rle.packets_lost(), 4);
+        let metrics = received.voip_metrics.unwrap();
+        assert_eq!(metrics.loss_rate, (4 * 256 / 99) as u8);
+        // isolated losses only
+        assert_eq!(metrics.burst_density, 0);
+        assert_eq!(metrics.gap_density, metrics.loss_rate);
+        assert_eq!(metrics.gap_duration, 1980);
+        assert_eq!(metrics.gmin, GMIN);
+        assert_eq!(metrics.mos_lq, UNAVAILABLE);
+
+        // nothing new received
+        sender.set_config(XrConfig {
+            loss_rle: true,
+            ..Default::default()
+        });
+        assert!(sender.generate(0x22222222, now, ntp_now).is_none());
+
+        // DLRR in response to the receiver reference time gives the round trip time
+        let later = now + Duration::from_millis(500);
+        let data = receiver
+            .generate(0x33333333, later, NtpTime::from(0))
+            .unwrap();
+        let xr = parse_xr(&data);
+        let XrBlock::Dlrr(ref dlrr) = xr[0].blocks[0] else {
+            unreachable!();
+        };
+        assert_eq!(dlrr[0].ssrc, 0x22222222);
+        assert_eq!(dlrr[0].last_rr, ntp_now.as_u32());
+
+        let ntp_recv = NtpTime::from(ntp_now.as_u64() + (1u64 << 32) / 4 * 3);
+        sender.handle_xr(&xr[0], later, ntp_recv, |ssrc| ssrc == 0x22222222);
+        let rtt = sender
+            .received(0x33333333)
+            .unwrap()
+            .round_trip_time
+            .unwrap();
+        assert!(rtt > Duration::from_millis(249) && rtt < Duration::from_millis(251));
+    }
+
+    #[test]
+    fn burst_metrics() {
+        let now = Instant::now();
+        let mut received = vec![true; 100];
+        // burst of 3 losses within 5 packets and an isolated loss
+        received[10] = false;
+        received[12] = false;
+        received[14] = false;
+        received[60] = false;
+        let arrival_times = (0..96)
+            .map(|i| now + Duration::from_millis(i * 20))
+            .collect::<Vec<_>>();
+
+        let metrics = VoipMetrics::new(0x12345678, &received, &arrival_times);
+        assert_eq!(metrics.burst_density, (3 * 256 / 5) as u8);
+        assert_eq!(metrics.gap_density, (256 / 95) as u8);
+    }
+}
@@ -0,0 +1,413 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpvrawdepay2
+ * @see_also: rtpvrawpay2
+ *
+ * Depayload uncompressed video from RTP packets as per [RFC 4175][rfc-4175] and
+ * [SMPTE ST 2110-20][st-2110-20].
+ *
+ * 8 bit RGB, RGBA, BGR, BGRA and YCbCr 4:2:2 as well as 10 bit YCbCr 4:2:2 video is supported.
+ * Interlaced video is output as interleaved frames containing both fields.
+ *
+ * Frames with missing packets are output nonetheless, with the missing parts being left black.
+ *
+ * [rfc-4175]: https://www.rfc-editor.org/rfc/rfc4175.html
+ * [st-2110-20]: https://doi.org/10.5594/SMPTE.ST2110-20.2022
+ *
+ * ## Example pipeline
+ *
+ * ```shell
+ * gst-launch-1.0 udpsrc address=127.0.0.1 port=5004 caps='application/x-rtp,media=video,clock-rate=90000,encoding-name=RAW,sampling=YCbCr-4:2:2,depth=(string)10,width=(string)1280,height=(string)720,exactframerate=(string)30' ! rtpjitterbuffer latency=100 ! rtpvrawdepay2 ! videoconvertscale ! autovideosink
+ * ```
+ *
+ * This will depayload an incoming RTP raw video stream. You can use the #rtpvrawpay2 element to
+ * create such an RTP stream.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::ops::RangeInclusive;
+
+use atomic_refcell::AtomicRefCell;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basedepay::{PacketToBufferRelation, RtpBaseDepay2Ext},
+    vraw::{
+        colorimetry_from_sdp, framerate_from_sdp, Sampling, EXT_SEQNUM_SIZE, SEGMENT_HEADER_SIZE,
+    },
+};
+
+/// Frame that is currently reassembled from line segments.
+struct PendingFrame {
+    data: Vec<u8>,
+    ext_seqnums: RangeInclusive<u64>,
+    ext_timestamp: u64,
+    /// Set once a segment of the second field was received.
+    has_second_field: bool,
+}
+
+#[derive(Default)]
+struct State {
+    video_info: Option<gst_video::VideoInfo>,
+    sampling: Option<&'static Sampling>,
+
+    pending_frame: Option<PendingFrame>,
+}
+
+#[derive(Default)]
+pub struct RtpVRawDepay {
+    state: AtomicRefCell<State>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpvrawdepay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Raw Video Depayloader"),
+    )
+});
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpVRawDepay {
+    const NAME: &'static str = "GstRtpVRawDepay2";
+    type Type = super::RtpVRawDepay;
+    type ParentType = crate::basedepay::RtpBaseDepay2;
+}
+
+impl ObjectImpl for RtpVRawDepay {}
+
+impl GstObjectImpl for RtpVRawDepay {}
+
+impl ElementImpl for RtpVRawDepay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Raw Video Depayloader",
+                "Codec/Depayloader/Network/RTP",
+                "Depayload raw video from RTP packets",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "RAW")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst_video::VideoCapsBuilder::new()
+                    .format_list(Sampling::formats())
+                    .width_range(1..=32767)
+                    .height_range(1..=32767)
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basedepay::RtpBaseDepay2Impl for RtpVRawDepay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        let s = caps.structure(0).unwrap();
+
+        let depth = s
+            .get::<&str>("depth")
+            .ok()
+            .and_then(|depth| depth.parse::<u32>().ok());
+        let Some(sampling) = Option::zip(s.get::<&str>("sampling").ok(), depth)
+            .and_then(|(sampling, depth)| Sampling::from_sampling(sampling, depth))
+        else {
+            gst::error!(CAT, imp = self, "Unsupported or missing sampling and depth");
+            return false;
+        };
+
+        let width = s
+            .get::<&str>("width")
+            .ok()
+            .and_then(|width| width.parse::<u32>().ok());
+        let height = s
+            .get::<&str>("height")
+            .ok()
+            .and_then(|height| height.parse::<u32>().ok());
+        let (Some(width @ 1..=32767), Some(height @ 1..=32767)) = (width, height) else {
+            gst::error!(CAT, imp = self, "Invalid or missing width and height");
+            return false;
+        };
+
+        let interlaced = s.has_field("interlace");
+
+        let fps = s
+            .get::<&str>("exactframerate")
+            .ok()
+            .and_then(framerate_from_sdp)
+            .or_else(|| {
+                s.get::<&str>("a-framerate")
+                    .ok()
+                    .and_then(|framerate| framerate.parse::<f64>().ok())
+                    .and_then(gst::Fraction::approximate_f64)
+            })
+            .unwrap_or_else(|| gst::Fraction::new(0, 1));
+
+        let mut builder = gst_video::VideoInfo::builder(sampling.format, width, height)
+            .fps(fps)
+            .interlace_mode(if interlaced {
+                gst_video::VideoInterlaceMode::Interleaved
+            } else {
+                gst_video::VideoInterlaceMode::Progressive
+            });
+        if interlaced {
+            builder = builder.field_order(gst_video::VideoFieldOrder::TopFieldFirst);
+        }
+
+        // RGB formats always use the default colorimetry
+        let colorimetry = if sampling.is_rgb() {
+            None
+        } else {
+            s.get::<&str>("colorimetry")
+                .ok()
+                .and_then(colorimetry_from_sdp)
+        };
+        if let Some(ref colorimetry) = colorimetry {
+            builder = builder.colorimetry(colorimetry);
+        }
+
+        let video_info = match builder.build() {
+            Ok(video_info) => video_info,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Failed to create video info: {err}");
+                return false;
+            }
+        };
+
+        let src_caps = match video_info.to_caps() {
+            Ok(src_caps) => src_caps,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Failed to create caps: {err}");
+                return false;
+            }
+        };
+
+        gst::debug!(CAT, imp = self, "Configuring {video_info:?}");
+
+        let mut state = self.state.borrow_mut();
+        state.video_info = Some(video_info);
+        state.sampling = Some(sampling);
+        state.pending_frame = None;
+        drop(state);
+
+        self.obj().set_src_caps(&src_caps);
+
+        true
+    }
+
+    fn drain(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+
+        self.finish_frame(&mut state)
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        state.pending_frame = None;
+    }
+
+    fn handle_packet(
+        &self,
+        packet: &crate::basedepay::Packet,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::trace!(CAT, imp = self, "Handling RTP packet {packet:?}");
+        let mut state = self.state.borrow_mut();
+
+        let (Some(video_info), Some(sampling)) = (state.video_info.clone(), state.sampling) else {
+            gst::error!(CAT, imp = self, "No caps received yet");
+            return Err(gst::FlowError::NotNegotiated);
+        };
+        let interlaced = video_info.interlace_mode() == gst_video::VideoInterlaceMode::Interleaved;
+
+        // A new RTP timestamp starts a new frame, even if the marker bit of the previous packet
+        // was not set. For interlaced video the second field can have its own timestamp.
+        if state.pending_frame.as_ref().is_some_and(|frame| {
+            frame.ext_timestamp != packet.ext_timestamp() && (!interlaced || frame.has_second_field)
+        }) {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Timestamp changed, finishing incomplete frame"
+            );
+            self.finish_frame(&mut state)?;
+        }
+
+        let segments = match parse_segments(packet.payload()) {
+            Ok(segments) => segments,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Invalid raw video RTP packet: {err}");
+                state.pending_frame = None;
+                self.obj().drop_packets(..=packet.ext_seqnum());
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        let frame = state.pending_frame.get_or_insert_with(|| PendingFrame {
+            data: vec![0; video_info.size()],
+            ext_seqnums: packet.ext_seqnum()..=packet.ext_seqnum(),
+            ext_timestamp: packet.ext_timestamp(),
+            has_second_field: false,
+        });
+        frame.ext_seqnums = *frame.ext_seqnums.start()..=packet.ext_seqnum();
+
+        let height = video_info.height() as usize;
+        let stride = video_info.stride()[0] as usize;
+        let line_size = sampling.pgroups_per_line(video_info.width() as usize) * sampling.pgroup;
+
+        let mut last_field = 0;
+        for segment in &segments {
+            last_field = segment.field;
+            frame.has_second_field |= segment.field == 1;
+
+            let row = if interlaced {
+                segment.line * 2 + segment.field
+            } else {
+                segment.line
+            };
+            let start = (segment.offset / sampling.xinc) * sampling.pgroup;
+            if row >= height || segment.offset % sampling.xinc != 0 || start >= line_size {
+                gst::warning!(CAT, imp = self, "Invalid line segment {segment:?}");
+                continue;
+            }
+
+            let length = segment.data.len().min(line_size - start);
+            frame.data[row * stride + start..][..length].copy_from_slice(&segment.data[..length]);
+        }
+
+        // The marker bit is set on the last packet of each frame or field
+        if packet.marker_bit() && (!interlaced || last_field == 1) {
+            self.finish_frame(&mut state)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+struct Segment<'a> {
+    field: usize,
+    line: usize,
+    offset: usize,
+    data: &'a [u8],
+}
+
+impl std::fmt::Debug for Segment<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Segment")
+            .field("field", &self.field)
+            .field("line", &self.line)
+            .field("offset", &self.offset)
+            .field("length", &self.data.len())
+            .finish()
+    }
+}
+
+/// Parses the payload header and returns all line segments of the packet.
+fn parse_segments(payload: &[u8]) -> Result<Vec<Segment>, &'static str> {
+    if payload.len() < EXT_SEQNUM_SIZE {
+        return Err("truncated payload header");
+    }
+
+    // All segment headers come first, followed by the data of all segments
+    let mut headers = Vec::new();
+    let mut data = &payload[EXT_SEQNUM_SIZE..];
+    loop {
+        if data.len() < SEGMENT_HEADER_SIZE {
+            return Err("truncated line segment header");
+        }
+
+        let length = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let field_line = u16::from_be_bytes([data[2], data[3]]);
+        let continuation_offset = u16::from_be_bytes([data[4], data[5]]);
+        data = &data[SEGMENT_HEADER_SIZE..];
+
+        headers.push((length, field_line, continuation_offset));
+
+        if continuation_offset & 0x8000 == 0 {
+            break;
+        }
+    }
+
+    let mut segments = Vec::with_capacity(headers.len());
+    for (length, field_line, continuation_offset) in headers {
+        if data.len() < length {
+            return Err("truncated line segment data");
+        }
+
+        segments.push(Segment {
+            field: (field_line >> 15) as usize,
+            line: (field_line & 0x7fff) as usize,
+            offset: (continuation_offset & 0x7fff) as usize,
+            data: &data[..length],
+        });
+        data = &data[length..];
+    }
+
+    Ok(segments)
+}
+
+impl RtpVRawDepay {
+    /// Outputs the current frame.
+    fn finish_frame(&self, state: &mut State) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let Some(frame) = state.pending_frame.take() else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Finishing frame from packets {:?}",
+            frame.ext_seqnums
+        );
+
+        let buffer = gst::Buffer::from_mut_slice(frame.data);
+
+        self.obj()
+            .queue_buffer(PacketToBufferRelation::Seqnums(frame.ext_seqnums), buffer)
+    }
+}
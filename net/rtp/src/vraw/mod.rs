@@ -0,0 +1,174 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Shared definitions for uncompressed video as per RFC 4175 and SMPTE ST 2110-20.
+
+pub mod depay;
+pub mod pay;
+
+#[cfg(test)]
+mod tests;
+
+/// Size of the extended sequence number at the start of the payload header.
+pub const EXT_SEQNUM_SIZE: usize = 2;
+/// Size of a single line segment header.
+pub const SEGMENT_HEADER_SIZE: usize = 6;
+
+/// Mapping between a raw video format and its RFC 4175 sampling and depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampling {
+    pub format: gst_video::VideoFormat,
+    pub sampling: &'static str,
+    pub depth: u32,
+    /// Size of a pixel group in bytes.
+    pub pgroup: usize,
+    /// Number of pixels per pixel group.
+    pub xinc: usize,
+}
+
+const SAMPLINGS: &[Sampling] = &[
+    Sampling {
+        format: gst_video::VideoFormat::Rgb,
+        sampling: "RGB",
+        depth: 8,
+        pgroup: 3,
+        xinc: 1,
+    },
+    Sampling {
+        format: gst_video::VideoFormat::Rgba,
+        sampling: "RGBA",
+        depth: 8,
+        pgroup: 4,
+        xinc: 1,
+    },
+    Sampling {
+        format: gst_video::VideoFormat::Bgr,
+        sampling: "BGR",
+        depth: 8,
+        pgroup: 3,
+        xinc: 1,
+    },
+    Sampling {
+        format: gst_video::VideoFormat::Bgra,
+        sampling: "BGRA",
+        depth: 8,
+        pgroup: 4,
+        xinc: 1,
+    },
+    // The RFC 4175 pixel group layouts of 4:2:2 are identical to these packed formats.
+    Sampling {
+        format: gst_video::VideoFormat::Uyvy,
+        sampling: "YCbCr-4:2:2",
+        depth: 8,
+        pgroup: 4,
+        xinc: 2,
+    },
+    Sampling {
+        format: gst_video::VideoFormat::Uyvp,
+        sampling: "YCbCr-4:2:2",
+        depth: 10,
+        pgroup: 5,
+        xinc: 2,
+    },
+];
+
+impl Sampling {
+    /// Returns all supported raw video formats.
+    pub fn formats() -> impl Iterator<Item = gst_video::VideoFormat> {
+        SAMPLINGS.iter().map(|sampling| sampling.format)
+    }
+
+    pub fn from_format(format: gst_video::VideoFormat) -> Option<&'static Sampling> {
+        SAMPLINGS.iter().find(|sampling| sampling.format == format)
+    }
+
+    pub fn from_sampling(sampling: &str, depth: u32) -> Option<&'static Sampling> {
+        SAMPLINGS
+            .iter()
+            .find(|s| s.sampling == sampling && s.depth == depth)
+    }
+
+    /// Returns `true` for RGB based samplings.
+    pub fn is_rgb(&self) -> bool {
+        !self.sampling.starts_with("YCbCr")
+    }
+
+    /// Number of pixel groups per line of the given width.
+    pub fn pgroups_per_line(&self, width: usize) -> usize {
+        width.div_ceil(self.xinc)
+    }
+}
+
+/// Returns the SMPTE ST 2110-20 colorimetry name for the given colorimetry.
+pub fn colorimetry_to_sdp(colorimetry: &gst_video::VideoColorimetry) -> &'static str {
+    use gst_video::{VideoColorPrimaries, VideoTransferFunction};
+
+    match colorimetry.primaries() {
+        VideoColorPrimaries::Bt709 => "BT709",
+        VideoColorPrimaries::Smpte170m | VideoColorPrimaries::Bt470bg => "BT601",
+        VideoColorPrimaries::Smpte240m => "SMPTE240M",
+        VideoColorPrimaries::Bt2020
+            if matches!(
+                colorimetry.transfer(),
+                VideoTransferFunction::Smpte2084 | VideoTransferFunction::AribStdB67
+            ) =>
+        {
+            "BT2100"
+        }
+        VideoColorPrimaries::Bt2020 => "BT2020",
+        _ => "UNSPECIFIED",
+    }
+}
+
+/// Returns the colorimetry for the given SDP colorimetry name.
+///
+/// Both the RFC 4175 and the SMPTE ST 2110-20 names are accepted.
+pub fn colorimetry_from_sdp(colorimetry: &str) -> Option<gst_video::VideoColorimetry> {
+    let colorimetry = match colorimetry {
+        "BT601" | "BT601-5" => "bt601",
+        "BT709" | "BT709-2" => "bt709",
+        "SMPTE240M" => "smpte240m",
+        "BT2020" => "bt2020",
+        "BT2100" => "bt2100-pq",
+        _ => return None,
+    };
+
+    colorimetry.parse().ok()
+}
+
+/// Returns the SMPTE ST 2110-20 transfer characteristic system for the given colorimetry.
+pub fn transfer_characteristic_system(colorimetry: &gst_video::VideoColorimetry) -> &'static str {
+    use gst_video::VideoTransferFunction;
+
+    match colorimetry.transfer() {
+        VideoTransferFunction::Smpte2084 => "PQ",
+        VideoTransferFunction::AribStdB67 => "HLG",
+        _ => "SDR",
+    }
+}
+
+/// Formats a framerate as used by the `exactframerate` SDP parameter.
+pub fn framerate_to_sdp(framerate: gst::Fraction) -> String {
+    if framerate.denom() == 1 {
+        framerate.numer().to_string()
+    } else {
+        format!("{}/{}", framerate.numer(), framerate.denom())
+    }
+}
+
+/// Parses an `exactframerate` SDP parameter.
+pub fn framerate_from_sdp(framerate: &str) -> Option<gst::Fraction> {
+    let (numer, denom) = match framerate.split_once('/') {
+        Some((numer, denom)) => (numer.parse().ok()?, denom.parse().ok()?),
+        None => (framerate.parse().ok()?, 1),
+    };
+
+    if numer <= 0 || denom <= 0 {
+        return None;
+    }
+
+    Some(gst::Fraction::new(numer, denom))
+}
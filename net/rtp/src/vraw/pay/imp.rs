@@ -0,0 +1,354 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtpvrawpay2
+ * @see_also: rtpvrawdepay2
+ *
+ * Payload uncompressed video into RTP packets as per [RFC 4175][rfc-4175] and
+ * [SMPTE ST 2110-20][st-2110-20].
+ *
+ * 8 bit RGB, RGBA, BGR, BGRA and YCbCr 4:2:2 as well as 10 bit YCbCr 4:2:2 video is supported.
+ * Each packet is filled with as many line segments as fit into the MTU. Interlaced video is sent
+ * field by field with the top field first.
+ *
+ * The caps contain the SMPTE ST 2110-20 SDP parameters, with the sender type always being
+ * signalled as wide (`2110TPW`) because no packet pacing is done by the payloader.
+ *
+ * [rfc-4175]: https://www.rfc-editor.org/rfc/rfc4175.html
+ * [st-2110-20]: https://doi.org/10.5594/SMPTE.ST2110-20.2022
+ *
+ * ## Example pipeline
+ *
+ * |[
+ * gst-launch-1.0 videotestsrc ! video/x-raw,width=1280,height=720,format=UYVP ! timeoverlay font-desc=Sans,22 ! rtpvrawpay2 mtu=1450 ! udpsink host=127.0.0.1 port=5004
+ * ]| This will create a 10 bit YCbCr 4:2:2 video stream with a test pattern and send it out
+ * via UDP to localhost port 5004.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use atomic_refcell::AtomicRefCell;
+use gst::{glib, prelude::*, subclass::prelude::*};
+use smallvec::SmallVec;
+use std::cmp;
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basepay::{PacketToBufferRelation, RtpBasePay2Ext, TimestampOffset},
+    vraw::{
+        colorimetry_to_sdp, framerate_to_sdp, transfer_characteristic_system, Sampling,
+        EXT_SEQNUM_SIZE, SEGMENT_HEADER_SIZE,
+    },
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtpvrawpay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP Raw Video Payloader"),
+    )
+});
+
+#[derive(Default)]
+struct State {
+    video_info: Option<gst_video::VideoInfo>,
+    sampling: Option<&'static Sampling>,
+
+    /// High 16 bits of the extended sequence number.
+    ext_seqnum_high: u16,
+    /// Sequence number of the last packet, used for detecting wraparounds.
+    last_seqnum: Option<u16>,
+}
+
+#[derive(Default)]
+pub struct RtpVRawPay {
+    state: AtomicRefCell<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpVRawPay {
+    const NAME: &'static str = "GstRtpVRawPay2";
+    type Type = super::RtpVRawPay;
+    type ParentType = crate::basepay::RtpBasePay2;
+}
+
+impl ObjectImpl for RtpVRawPay {}
+
+impl GstObjectImpl for RtpVRawPay {}
+
+impl ElementImpl for RtpVRawPay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP Raw Video payloader",
+                "Codec/Payloader/Network/RTP",
+                "Payload raw video as RTP packets",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst_video::VideoCapsBuilder::new()
+                    .format_list(Sampling::formats())
+                    .width_range(1..=32767)
+                    .height_range(1..=32767)
+                    .field(
+                        "interlace-mode",
+                        gst::List::new(["progressive", "interleaved"]),
+                    )
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "RAW")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basepay::RtpBasePay2Impl for RtpVRawPay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        gst::debug!(CAT, imp = self, "received caps {caps:?}");
+
+        let video_info = match gst_video::VideoInfo::from_caps(caps) {
+            Ok(video_info) => video_info,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Invalid caps: {err}");
+                return false;
+            }
+        };
+
+        let Some(sampling) = Sampling::from_format(video_info.format()) else {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Unsupported format {}",
+                video_info.format()
+            );
+            return false;
+        };
+
+        let colorimetry = video_info.colorimetry();
+        let fps = video_info.fps();
+        let interlaced = video_info.interlace_mode() == gst_video::VideoInterlaceMode::Interleaved;
+
+        let src_caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90_000i32)
+            .field("encoding-name", "RAW")
+            .field("sampling", sampling.sampling)
+            .field("depth", sampling.depth.to_string())
+            .field("width", video_info.width().to_string())
+            .field("height", video_info.height().to_string())
+            .field("colorimetry", colorimetry_to_sdp(&colorimetry))
+            .field_if_some(
+                "exactframerate",
+                (fps.numer() > 0).then(|| framerate_to_sdp(fps)),
+            )
+            .field_if("interlace", "true", interlaced)
+            .field("PM", "2110GPM")
+            .field("SSN", "ST2110-20:2017")
+            .field("TP", "2110TPW")
+            .field("TCS", transfer_characteristic_system(&colorimetry))
+            .build();
+
+        self.obj().set_src_caps(&src_caps);
+
+        let mut state = self.state.borrow_mut();
+        state.video_info = Some(video_info);
+        state.sampling = Some(sampling);
+
+        true
+    }
+
+    fn handle_buffer(
+        &self,
+        buffer: &gst::Buffer,
+        id: u64,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+
+        let (Some(video_info), Some(sampling)) = (state.video_info.clone(), state.sampling) else {
+            gst::error!(CAT, imp = self, "No caps received yet");
+            return Err(gst::FlowError::NotNegotiated);
+        };
+
+        gst::trace!(CAT, imp = self, "received buffer of size {}", buffer.size());
+
+        let frame =
+            gst_video::VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &video_info)
+                .map_err(|_| {
+                    gst::element_imp_error!(
+                        self,
+                        gst::ResourceError::Read,
+                        ["Failed to map video frame readable"]
+                    );
+
+                    gst::FlowError::Error
+                })?;
+
+        let data = frame.plane_data(0).unwrap();
+        let stride = frame.plane_stride()[0] as usize;
+        let height = video_info.height() as usize;
+        let pgroups_per_line = sampling.pgroups_per_line(video_info.width() as usize);
+
+        // Segment lengths are limited to 16 bits
+        let max_payload_size = cmp::min(self.obj().max_payload_size() as usize, u16::MAX as usize);
+        if max_payload_size < EXT_SEQNUM_SIZE + SEGMENT_HEADER_SIZE + sampling.pgroup {
+            gst::element_imp_error!(
+                self,
+                gst::ResourceError::Settings,
+                ["MTU too small for a single pixel group"]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        let interlaced = video_info.interlace_mode() == gst_video::VideoInterlaceMode::Interleaved;
+        let fields = if interlaced { 2 } else { 1 };
+
+        // The second field is sampled half a frame duration after the first one
+        let field_duration = buffer
+            .duration()
+            .or_else(|| {
+                let fps = video_info.fps();
+                (fps.numer() > 0).then(|| {
+                    gst::ClockTime::SECOND
+                        .mul_div_floor(fps.denom() as u64, fps.numer() as u64)
+                        .unwrap()
+                })
+            })
+            .map(|duration| duration / 2);
+
+        let mut header = Vec::with_capacity(EXT_SEQNUM_SIZE + 8 * SEGMENT_HEADER_SIZE);
+        for field in 0..fields {
+            let lines = if interlaced {
+                (height + 1 - field) / 2
+            } else {
+                height
+            };
+
+            let packet_to_buffer_relation = match field_duration {
+                Some(field_duration) if field == 1 => PacketToBufferRelation::IdsWithOffset {
+                    ids: id..=id,
+                    timestamp_offset: TimestampOffset::Pts(field_duration),
+                },
+                _ => id.into(),
+            };
+
+            let mut line = 0;
+            let mut pgroup_offset = 0;
+            while line < lines {
+                header.clear();
+                header.extend_from_slice(&self.ext_seqnum_high(&mut state).to_be_bytes());
+
+                // Fill the packet with as many (partial) lines as fit. The segment data is
+                // directly referenced from the frame.
+                let mut segments = SmallVec::<[&[u8]; 8]>::new();
+                let mut left = max_payload_size - EXT_SEQNUM_SIZE;
+                while line < lines && left >= SEGMENT_HEADER_SIZE + sampling.pgroup {
+                    left -= SEGMENT_HEADER_SIZE;
+
+                    let num_pgroups =
+                        cmp::min(pgroups_per_line - pgroup_offset, left / sampling.pgroup);
+                    let length = num_pgroups * sampling.pgroup;
+                    left -= length;
+
+                    let row = if interlaced { line * 2 + field } else { line };
+                    let start = row * stride + pgroup_offset * sampling.pgroup;
+                    segments.push(&data[start..][..length]);
+
+                    header.extend_from_slice(&(length as u16).to_be_bytes());
+                    header.extend_from_slice(&(((field as u16) << 15) | line as u16).to_be_bytes());
+                    header
+                        .extend_from_slice(&((pgroup_offset * sampling.xinc) as u16).to_be_bytes());
+
+                    pgroup_offset += num_pgroups;
+                    if pgroup_offset == pgroups_per_line {
+                        line += 1;
+                        pgroup_offset = 0;
+                    }
+                }
+
+                // Set the continuation bit for all but the last segment
+                for i in 0..segments.len() - 1 {
+                    header[EXT_SEQNUM_SIZE + i * SEGMENT_HEADER_SIZE + 4] |= 0x80;
+                }
+
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Sending packet with {} segments of field {field}",
+                    segments.len()
+                );
+
+                // The marker bit is set on the last packet of each frame or field
+                let mut packet = rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(line == lines)
+                    .payload(header.as_slice());
+                for segment in segments {
+                    packet = packet.payload(segment);
+                }
+
+                self.obj()
+                    .queue_packet(packet_to_buffer_relation.clone(), packet)?;
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
+
+impl RtpVRawPay {
+    /// Returns the high 16 bits of the extended sequence number of the next packet.
+    fn ext_seqnum_high(&self, state: &mut State) -> u16 {
+        let seqnum = self.obj().next_seqnum().unwrap_or(0);
+        if state
+            .last_seqnum
+            .is_some_and(|last_seqnum| seqnum < last_seqnum)
+        {
+            state.ext_seqnum_high = state.ext_seqnum_high.wrapping_add(1);
+        }
+        state.last_seqnum = Some(seqnum);
+
+        state.ext_seqnum_high
+    }
+}
@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::tests::{run_test_pipeline, ExpectedBuffer, ExpectedPacket, Source};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtpvraw test");
+    });
+}
+
+fn frame(size: usize, pts_ms: u64) -> gst::Buffer {
+    let data = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from_mseconds(pts_ms));
+        buffer.set_duration(gst::ClockTime::from_mseconds(40));
+    }
+    buffer
+}
+
+#[test]
+fn test_vraw_uyvy() {
+    init();
+
+    let caps = gst_video::VideoCapsBuilder::new()
+        .format(gst_video::VideoFormat::Uyvy)
+        .width(320)
+        .height(8)
+        .framerate(gst::Fraction::new(25, 1))
+        .build();
+    let buffers = vec![frame(320 * 8 * 2, 0), frame(320 * 8 * 2, 40)];
+
+    let pay = "rtpvrawpay2";
+    let depay = "rtpvrawdepay2";
+
+    // Each packet contains two complete and one partial line, except for the last one
+    let expected_pay = vec![
+        vec![
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(1042)
+                .build(),
+        ],
+        vec![
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(40))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(3600)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(40))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(3600)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(40))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(3600)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(40))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(3600)
+                .marker_bit(true)
+                .size(1042)
+                .build(),
+        ],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(320 * 8 * 2)
+            .flags(gst::BufferFlags::DISCONT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(320 * 8 * 2)
+            .flags(gst::BufferFlags::empty())
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps, buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_vraw_rgb_interlaced() {
+    init();
+
+    let caps = gst_video::VideoCapsBuilder::new()
+        .format(gst_video::VideoFormat::Rgb)
+        .width(100)
+        .height(6)
+        .framerate(gst::Fraction::new(25, 1))
+        .field("interlace-mode", "interleaved")
+        .build();
+    let buffers = vec![frame(300 * 6, 0)];
+
+    let pay = "rtpvrawpay2";
+    let depay = "rtpvrawdepay2";
+
+    // One packet per field, with the second field sampled half a frame later
+    let expected_pay = vec![vec![
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(true)
+            .size(932)
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(20))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(1800)
+            .marker_bit(true)
+            .size(932)
+            .build(),
+    ]];
+
+    let expected_depay = vec![vec![ExpectedBuffer::builder()
+        .pts(gst::ClockTime::from_mseconds(0))
+        .size(300 * 6)
+        .flags(gst::BufferFlags::DISCONT)
+        .build()]];
+
+    run_test_pipeline(
+        Source::Buffers(caps, buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
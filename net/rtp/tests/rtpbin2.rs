@@ -599,6 +599,328 @@ fn test_receive_list_benchmark() {
     );
 }
 
+const TEST_RTX_SSRC: u32 = 0x87654321;
+const TEST_RTX_PT: u8 = 97;
+
+#[test]
+fn test_receive_rtx() {
+    init();
+
+    let id = next_element_counter();
+
+    let elem = gst::ElementFactory::make("rtprecv")
+        .property("rtp-id", id.to_string())
+        .property("latency", 200u32)
+        .build()
+        .unwrap();
+    let h = Arc::new(Mutex::new(Harness::with_element(
+        &elem,
+        Some("rtp_sink_0"),
+        None,
+    )));
+    let weak_h = Arc::downgrade(&h);
+    elem.connect_pad_added(move |_elem, pad| {
+        weak_h
+            .upgrade()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .add_element_src_pad(pad)
+    });
+
+    let caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "custom-test")
+        .field("rtcp-fb-nack", true)
+        .build();
+    let rtx_caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_RTX_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "RTX")
+        .field("apt", TEST_PT as i32)
+        .build();
+
+    let mut inner = h.lock().unwrap();
+    inner.play();
+    inner.set_src_caps(caps.clone());
+    drop(inner);
+
+    let session = elem.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+    session.set_property(
+        "pt-map",
+        gst::Structure::builder("application/x-rtp2-pt-map")
+            .field(TEST_PT.to_string(), caps)
+            .field(TEST_RTX_PT.to_string(), rtx_caps)
+            .build(),
+    );
+
+    // Packet 502 is lost and requested for retransmission
+    let packets = [500, 501, 503].map(|seq_no| PacketInfo {
+        seq_no,
+        rtp_ts: seq_no as u32 * 10,
+        payload_len: 4,
+    });
+    receive_push(h.clone(), packets, false);
+
+    // The retransmission contains the original sequence number in front of the payload
+    let mut rtx_payload = 502u16.to_be_bytes().to_vec();
+    rtx_payload.extend([5; 4]);
+    let rtx_packet = RtpPacketBuilder::new()
+        .ssrc(TEST_RTX_SSRC)
+        .payload_type(TEST_RTX_PT)
+        .sequence_number(1)
+        .timestamp(5020)
+        .payload(rtx_payload.as_slice())
+        .write_vec()
+        .unwrap();
+    let push_pad = elem.static_pad("rtp_sink_0").unwrap().peer().unwrap();
+    push_pad
+        .push(gst::Buffer::from_mut_slice(rtx_packet))
+        .unwrap();
+
+    // The restored packet is output in order with the other packets
+    let mut inner = h.lock().unwrap();
+    for seq_no in [500, 501, 502, 503] {
+        let buffer = inner.pull().unwrap();
+        let mapped = buffer.map_readable().unwrap();
+        let rtp = RtpPacket::parse(&mapped).unwrap();
+        assert_eq!(rtp.sequence_number(), seq_no);
+        assert_eq!(rtp.ssrc(), TEST_SSRC);
+        assert_eq!(rtp.payload_type(), TEST_PT);
+        assert_eq!(rtp.timestamp(), seq_no as u32 * 10);
+        if seq_no == 502 {
+            assert_eq!(rtp.payload(), &[5; 4]);
+        }
+    }
+    drop(inner);
+
+    let stats = elem.property::<gst::Structure>("stats");
+    let session_stats = stats.get::<gst::Structure>("0").unwrap();
+    let jitterbuffers_stats = session_stats
+        .get::<gst::List>("jitterbuffer-stats")
+        .unwrap();
+    assert_eq!(jitterbuffers_stats.len(), 1);
+    let jitterbuffer_stats = jitterbuffers_stats
+        .first()
+        .unwrap()
+        .get::<gst::Structure>()
+        .unwrap();
+    assert_eq!(jitterbuffer_stats.get::<u64>("num-requested").unwrap(), 1);
+    assert_eq!(jitterbuffer_stats.get::<u64>("num-repaired").unwrap(), 1);
+    assert_eq!(jitterbuffer_stats.get::<u64>("num-lost").unwrap(), 0);
+    assert_eq!(jitterbuffer_stats.get::<u64>("num-pushed").unwrap(), 4);
+}
+
+#[test]
+fn test_send_rtx_twcc() {
+    init();
+
+    let id = next_element_counter();
+
+    let send = gst::ElementFactory::make("rtpsend")
+        .property("rtp-id", id.to_string())
+        .build()
+        .unwrap();
+    let recv = gst::ElementFactory::make("rtprecv")
+        .property("rtp-id", id.to_string())
+        .build()
+        .unwrap();
+    let mut h_send = Harness::with_element(&send, Some("rtp_sink_0"), Some("rtp_src_0"));
+    let mut h_rtcp = Harness::with_element(&recv, Some("rtcp_sink_0"), None);
+    h_send.play();
+    h_rtcp.play();
+
+    let caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "custom-test")
+        .field(
+            "extmap-1",
+            "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01",
+        )
+        .build();
+    let rtx_caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_RTX_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "RTX")
+        .field("apt", TEST_PT as i32)
+        .build();
+    h_send.set_src_caps(caps.clone());
+    h_rtcp.set_src_caps_str("application/x-rtcp");
+
+    let session = send.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+    session.set_property(
+        "pt-map",
+        gst::Structure::builder("application/x-rtp2-pt-map")
+            .field(TEST_PT.to_string(), caps)
+            .field(TEST_RTX_PT.to_string(), rtx_caps)
+            .build(),
+    );
+
+    let twcc_seqnum = |rtp: &RtpPacket| {
+        let (profile, data) = rtp.extension().unwrap();
+        assert_eq!(profile, 0xbede);
+        assert_eq!(data[0], 0x11);
+        u16::from_be_bytes([data[1], data[2]])
+    };
+
+    let mut last_twcc_seqnum = None;
+    for seq_no in 500..503 {
+        h_send
+            .push(generate_rtp_buffer(seq_no, seq_no as u32 * 10, 4))
+            .unwrap();
+        let buffer = h_send.pull().unwrap();
+        let mapped = buffer.map_readable().unwrap();
+        let rtp = RtpPacket::parse(&mapped).unwrap();
+        assert_eq!(rtp.sequence_number(), seq_no);
+        last_twcc_seqnum = Some(twcc_seqnum(&rtp));
+    }
+
+    // Request a retransmission of packet 501
+    let mut data = vec![0; 128];
+    let len = rtcp_types::Compound::builder()
+        .add_packet(rtcp_types::ReceiverReport::builder(0x55667788))
+        .add_packet(
+            rtcp_types::TransportFeedback::builder_owned(
+                rtcp_types::Nack::builder().add_rtp_sequence(501),
+            )
+            .sender_ssrc(0x55667788)
+            .media_ssrc(TEST_SSRC),
+        )
+        .write_into(&mut data)
+        .unwrap();
+    data.truncate(len);
+    h_rtcp.push(gst::Buffer::from_mut_slice(data)).unwrap();
+
+    // The retransmission gets the next transport-wide sequence number
+    let buffer = h_send.pull().unwrap();
+    let mapped = buffer.map_readable().unwrap();
+    let rtp = RtpPacket::parse(&mapped).unwrap();
+    assert_eq!(rtp.payload_type(), TEST_RTX_PT);
+    assert_ne!(rtp.ssrc(), TEST_SSRC);
+    assert_eq!(&rtp.payload()[..2], &501u16.to_be_bytes());
+    assert_eq!(twcc_seqnum(&rtp), last_twcc_seqnum.unwrap().wrapping_add(1));
+}
+
+const TEST_FEC_PT: u8 = 100;
+
+#[test]
+fn test_send_receive_flexfec() {
+    init();
+
+    let send = gst::ElementFactory::make("rtpsend")
+        .property("rtp-id", next_element_counter().to_string())
+        .build()
+        .unwrap();
+    let mut h_send = Harness::with_element(&send, Some("rtp_sink_0"), Some("rtp_src_0"));
+    h_send.play();
+
+    let recv = gst::ElementFactory::make("rtprecv")
+        .property("rtp-id", next_element_counter().to_string())
+        .property("latency", 200u32)
+        .build()
+        .unwrap();
+    let h_recv = Arc::new(Mutex::new(Harness::with_element(
+        &recv,
+        Some("rtp_sink_0"),
+        None,
+    )));
+    let weak_h = Arc::downgrade(&h_recv);
+    recv.connect_pad_added(move |_elem, pad| {
+        weak_h
+            .upgrade()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .add_element_src_pad(pad)
+    });
+
+    let caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "custom-test")
+        .build();
+    let fec_caps = Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("payload", TEST_FEC_PT as i32)
+        .field("clock-rate", TEST_CLOCK_RATE as i32)
+        .field("encoding-name", "FLEXFEC-03")
+        .build();
+    let pt_map = gst::Structure::builder("application/x-rtp2-pt-map")
+        .field(TEST_PT.to_string(), caps.clone())
+        .field(TEST_FEC_PT.to_string(), fec_caps)
+        .build();
+
+    h_send.set_src_caps(caps.clone());
+    let send_session = send.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+    send_session.set_property("pt-map", &pt_map);
+    send_session.set_property("fec-percentage", 100u32);
+
+    let mut inner = h_recv.lock().unwrap();
+    inner.play();
+    inner.set_src_caps(caps);
+    drop(inner);
+    let recv_session = recv.emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+    recv_session.set_property("pt-map", &pt_map);
+
+    // Each packet is a frame of its own and gets protected by a FEC packet once the next frame
+    // starts
+    let mut sent = vec![];
+    for seq_no in 500..505 {
+        h_send
+            .push(generate_rtp_buffer(seq_no, seq_no as u32 * 10, 4))
+            .unwrap();
+        while let Some(buffer) = h_send.try_pull() {
+            sent.push(buffer);
+        }
+    }
+    let num_fec = sent
+        .iter()
+        .filter(|buffer| {
+            let mapped = buffer.map_readable().unwrap();
+            RtpPacket::parse(&mapped).unwrap().payload_type() == TEST_FEC_PT
+        })
+        .count();
+    assert_eq!(num_fec, 4);
+    assert_eq!(sent.len(), 9);
+
+    // Packet 502 is lost and recovered from the FEC packets
+    let push_pad = recv.static_pad("rtp_sink_0").unwrap().peer().unwrap();
+    for buffer in sent {
+        let is_lost = {
+            let mapped = buffer.map_readable().unwrap();
+            let rtp = RtpPacket::parse(&mapped).unwrap();
+            rtp.payload_type() == TEST_PT && rtp.sequence_number() == 502
+        };
+        if !is_lost {
+            push_pad.push(buffer).unwrap();
+        }
+    }
+
+    let mut inner = h_recv.lock().unwrap();
+    for seq_no in 500..505 {
+        let buffer = inner.pull().unwrap();
+        let mapped = buffer.map_readable().unwrap();
+        let rtp = RtpPacket::parse(&mapped).unwrap();
+        assert_eq!(rtp.sequence_number(), seq_no);
+        assert_eq!(rtp.ssrc(), TEST_SSRC);
+        assert_eq!(rtp.payload_type(), TEST_PT);
+        assert_eq!(rtp.timestamp(), seq_no as u32 * 10);
+        assert_eq!(rtp.payload(), &[4; 4]);
+    }
+    drop(inner);
+
+    let stats = recv.property::<gst::Structure>("stats");
+    let session_stats = stats.get::<gst::Structure>("0").unwrap();
+    assert_eq!(session_stats.get::<u64>("fec-recovered").unwrap(), 1);
+}
+
 #[test]
 fn recv_release_sink_pad() {
     init();
@@ -15,10 +15,12 @@
  */
 use gst::glib;
 
+mod rtspserversink;
 mod rtspsrc;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     rtspsrc::register(plugin)?;
+    rtspserversink::register(plugin)?;
     Ok(())
 }
 
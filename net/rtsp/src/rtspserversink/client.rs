@@ -0,0 +1,457 @@
+// GStreamer RTSP Server Sink
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://www.rfc-editor.org/rfc/rfc2326.html
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use rand::distributions::{Alphanumeric, DistString};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time;
+
+use rtsp_types::headers::{
+    RtpLowerTransport, RtpProfile, RtpTransport, RtpTransportParameters, Session, Transport,
+    Transports, CONTENT_BASE, CONTENT_TYPE, CSEQ, PUBLIC, RANGE, RTP_INFO, SERVER,
+};
+use rtsp_types::{Message, Method, Request, Response, ResponseBuilder, StatusCode};
+
+use crate::rtspsrc::body::Body;
+use crate::rtspsrc::tcp_message;
+
+use super::imp::{Media, Output, CAT};
+
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const SERVER_NAME: &str = concat!("GStreamer rtspserversink ", env!("CARGO_PKG_VERSION"));
+const PUBLIC_METHODS: &str =
+    "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER, SET_PARAMETER";
+// Packets are dropped for clients that have more messages queued than this
+const SEND_QUEUE_SIZE: usize = 512;
+
+/// Transport of a stream that was set up by the client
+#[derive(Debug, Clone)]
+enum StreamTransport {
+    Tcp { channels: (u8, u8) },
+    Udp { rtp: SocketAddr, rtcp: SocketAddr },
+}
+
+struct ClientSession {
+    id: String,
+    // Control URL and transport, indexed like the streams of the media
+    streams: Vec<Option<(String, StreamTransport)>>,
+    playing: bool,
+}
+
+struct Client {
+    media: Arc<Media>,
+    peer: SocketAddr,
+    local: IpAddr,
+    sender: mpsc::Sender<Message<Body>>,
+    session: Option<ClientSession>,
+    // Set when RTCP was received over UDP
+    seen: Arc<AtomicBool>,
+}
+
+/// Handle the requests of a client until it disconnects or times out
+pub(super) async fn serve(media: Arc<Media>, stream: TcpStream, peer: SocketAddr) {
+    let _ = stream.set_nodelay(true);
+    let Ok(local) = stream.local_addr() else {
+        return;
+    };
+    let (read, write) = stream.into_split();
+    let mut stream = Box::pin(tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse());
+    let (sender, mut receiver) = mpsc::channel::<Message<Body>>(SEND_QUEUE_SIZE);
+
+    // Responses and interleaved data are all written from here, in order
+    let writer = async move {
+        let mut sink = Box::pin(tcp_message::async_write(write));
+        while let Some(msg) = receiver.recv().await {
+            sink.send(msg).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::pin!(writer);
+
+    let mut timeout = time::interval(media.session_timeout);
+    // The first tick completes immediately
+    timeout.tick().await;
+    let mut active = false;
+
+    let mut client = Client {
+        media,
+        peer,
+        local: local.ip(),
+        sender,
+        session: None,
+        seen: Arc::default(),
+    };
+
+    loop {
+        tokio::select! {
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Request(req))) => {
+                    active = true;
+                    let rsp = client.handle_request(&req);
+                    gst::debug!(CAT, "-->> {rsp:#?}");
+                    if client.sender.send(rsp.into()).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Data(data))) => {
+                    active = true;
+                    client.handle_data(data);
+                }
+                Some(Ok(Message::Response(rsp))) => {
+                    gst::debug!(CAT, "Ignoring response from {peer}: {rsp:#?}");
+                }
+                Some(Err(err)) => {
+                    gst::warning!(CAT, "Failed to read from {peer}: {err:?}");
+                    break;
+                }
+                None => {
+                    gst::info!(CAT, "Client {peer} disconnected");
+                    break;
+                }
+            },
+            res = &mut writer => {
+                if let Err(err) = res {
+                    gst::warning!(CAT, "Failed to write to {peer}: {err:?}");
+                }
+                break;
+            }
+            _ = timeout.tick() => {
+                if !active && !client.seen.swap(false, Ordering::Relaxed) {
+                    gst::info!(CAT, "Client {peer} timed out");
+                    break;
+                }
+                active = false;
+            }
+        }
+    }
+
+    client.stop();
+}
+
+impl Client {
+    fn response(&self, req: &Request<Body>, status: StatusCode) -> ResponseBuilder {
+        let mut rsp = Response::builder(req.version(), status).header(SERVER, SERVER_NAME);
+        if let Some(cseq) = req.header(&CSEQ) {
+            rsp = rsp.header(CSEQ, cseq.clone());
+        }
+        if let Some(session) = &self.session {
+            rsp = rsp.typed_header::<Session>(&Session(
+                session.id.clone(),
+                Some(self.media.session_timeout.as_secs()),
+            ));
+        }
+        rsp
+    }
+
+    fn reply(&self, req: &Request<Body>, status: StatusCode) -> Response<Body> {
+        self.response(req, status).build(Body::default())
+    }
+
+    fn handle_request(&mut self, req: &Request<Body>) -> Response<Body> {
+        gst::debug!(CAT, "<<-- {req:#?}");
+        if req.header(&CSEQ).is_none() {
+            return self.reply(req, StatusCode::BadRequest);
+        }
+
+        match req.method() {
+            Method::Options => self
+                .response(req, StatusCode::Ok)
+                .header(PUBLIC, PUBLIC_METHODS)
+                .build(Body::default()),
+            Method::Describe => self.describe(req),
+            Method::Setup => self.setup(req),
+            Method::Play => self.play(req),
+            Method::Pause => self.pause(req),
+            Method::Teardown => self.teardown(req),
+            // Used as keep-alive
+            Method::GetParameter => self.reply(req, StatusCode::Ok),
+            Method::SetParameter if req.body().is_empty() => self.reply(req, StatusCode::Ok),
+            Method::SetParameter => self.reply(req, StatusCode::ParameterNotUnderstood),
+            _ => self.reply(req, StatusCode::NotImplemented),
+        }
+    }
+
+    /// Checks that the request is for the session of this client
+    fn check_session(&self, req: &Request<Body>) -> Result<&ClientSession, StatusCode> {
+        let Ok(Some(Session(id, _))) = req.typed_header::<Session>() else {
+            return Err(StatusCode::SessionNotFound);
+        };
+        match &self.session {
+            Some(session) if session.id == id => Ok(session),
+            _ => Err(StatusCode::SessionNotFound),
+        }
+    }
+
+    fn describe(&self, req: &Request<Body>) -> Response<Body> {
+        let Some(sdp) = self.media.session_description(self.local) else {
+            gst::info!(CAT, "Media is not negotiated yet");
+            return self.reply(req, StatusCode::ServiceUnavailable);
+        };
+        let mut rsp = self
+            .response(req, StatusCode::Ok)
+            .header(CONTENT_TYPE, "application/sdp");
+        if let Some(uri) = req.request_uri() {
+            // The control paths of the streams are relative to the content base
+            let base = uri.as_str();
+            if base.ends_with('/') {
+                rsp = rsp.header(CONTENT_BASE, base);
+            } else {
+                rsp = rsp.header(CONTENT_BASE, format!("{base}/"));
+            }
+        }
+        rsp.build(Body::from(sdp.into_bytes()))
+    }
+
+    /// Picks the first supported transport, `None` if none is supported or the requested
+    /// interleaved channels or ports are invalid
+    fn select_transport(&self, transports: &Transports, index: usize) -> Option<StreamTransport> {
+        // Interleaved channels used by the other streams of the session
+        let used_channels = self
+            .session
+            .iter()
+            .flat_map(|session| session.streams.iter().enumerate())
+            .filter_map(|(i, setup)| match setup {
+                Some((_, StreamTransport::Tcp { channels })) if i != index => Some(*channels),
+                _ => None,
+            })
+            .flat_map(|(rtp, rtcp)| [rtp, rtcp])
+            .collect::<Vec<_>>();
+
+        for transport in transports.iter() {
+            let Transport::Rtp(t) = transport else {
+                continue;
+            };
+            if !matches!(t.profile, RtpProfile::Avp) || t.params.multicast {
+                continue;
+            }
+            match t.lower_transport {
+                Some(RtpLowerTransport::Tcp) => {
+                    let (rtp, rtcp) = match t.params.interleaved {
+                        Some((rtp, Some(rtcp))) => (rtp, rtcp),
+                        Some((rtp, None)) => (rtp, rtp.checked_add(1)?),
+                        None => {
+                            let rtp = u8::try_from(index.checked_mul(2)?).ok()?;
+                            (rtp, rtp.checked_add(1)?)
+                        }
+                    };
+                    if used_channels.contains(&rtp) || used_channels.contains(&rtcp) {
+                        gst::info!(
+                            CAT,
+                            "Client {} requested channels {rtp}-{rtcp} already in use",
+                            self.peer
+                        );
+                        return None;
+                    }
+                    return Some(StreamTransport::Tcp {
+                        channels: (rtp, rtcp),
+                    });
+                }
+                _ => {
+                    let (rtp, rtcp) = match t.params.client_port {
+                        Some((rtp, Some(rtcp))) => (rtp, rtcp),
+                        Some((rtp, None)) => (rtp, rtp.checked_add(1)?),
+                        None => continue,
+                    };
+                    return Some(StreamTransport::Udp {
+                        rtp: SocketAddr::new(self.peer.ip(), rtp),
+                        rtcp: SocketAddr::new(self.peer.ip(), rtcp),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn setup(&mut self, req: &Request<Body>) -> Response<Body> {
+        let Some(url) = req.request_uri() else {
+            return self.reply(req, StatusCode::BadRequest);
+        };
+        let control = url
+            .path_segments()
+            .and_then(|s| s.last())
+            .unwrap_or_default();
+        let Some((index, stream)) = self.media.stream(control) else {
+            return self.reply(req, StatusCode::NotFound);
+        };
+        let stream = stream.clone();
+
+        // Only one session per connection is supported
+        if req.typed_header::<Session>().ok().flatten().is_some() || self.session.is_some() {
+            match self.check_session(req) {
+                Ok(session) if session.playing => {
+                    return self.reply(req, StatusCode::MethodNotValidInThisState);
+                }
+                Ok(_) => {}
+                Err(status) => return self.reply(req, status),
+            }
+        }
+
+        let Ok(Some(transports)) = req.typed_header::<Transports>() else {
+            return self.reply(req, StatusCode::BadRequest);
+        };
+        let Some(transport) = self.select_transport(&transports, index) else {
+            return self.reply(req, StatusCode::UnsupportedTransport);
+        };
+        let (lower_transport, params) = match &transport {
+            StreamTransport::Tcp { channels } => (
+                RtpLowerTransport::Tcp,
+                RtpTransportParameters {
+                    unicast: true,
+                    interleaved: Some((channels.0, Some(channels.1))),
+                    ..Default::default()
+                },
+            ),
+            StreamTransport::Udp { rtp, rtcp } => {
+                let Some((server_rtp, server_rtcp)) = stream.server_ports() else {
+                    return self.reply(req, StatusCode::InternalServerError);
+                };
+                (
+                    RtpLowerTransport::Udp,
+                    RtpTransportParameters {
+                        unicast: true,
+                        client_port: Some((rtp.port(), Some(rtcp.port()))),
+                        server_port: Some((server_rtp, Some(server_rtcp))),
+                        ..Default::default()
+                    },
+                )
+            }
+        };
+        gst::info!(
+            CAT,
+            "Client {} set up {} with {transport:?}",
+            self.peer,
+            stream.control()
+        );
+
+        let num_streams = self.media.streams.len();
+        let session = self.session.get_or_insert_with(|| ClientSession {
+            id: Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+            streams: vec![None; num_streams],
+            playing: false,
+        });
+        session.streams[index] = Some((url.to_string(), transport));
+
+        let transports: Transports = [Transport::Rtp(RtpTransport {
+            profile: RtpProfile::Avp,
+            lower_transport: Some(lower_transport),
+            params,
+        })]
+        .as_slice()
+        .into();
+        self.response(req, StatusCode::Ok)
+            .typed_header::<Transports>(&transports)
+            .build(Body::default())
+    }
+
+    fn play(&mut self, req: &Request<Body>) -> Response<Body> {
+        if let Err(status) = self.check_session(req) {
+            return self.reply(req, status);
+        }
+        let session = self.session.as_mut().unwrap();
+        if session.streams.iter().all(Option::is_none) {
+            return self.reply(req, StatusCode::MethodNotValidInThisState);
+        }
+
+        let mut rtp_info = Vec::new();
+        for (stream, setup) in self.media.streams.iter().zip(&session.streams) {
+            let Some((url, transport)) = setup else {
+                continue;
+            };
+            let output = match transport {
+                StreamTransport::Tcp { channels } => Output::Tcp {
+                    sender: self.sender.clone(),
+                    channels: *channels,
+                },
+                StreamTransport::Udp { rtp, rtcp } => Output::Udp {
+                    rtp: *rtp,
+                    rtcp: *rtcp,
+                    seen: self.seen.clone(),
+                },
+            };
+            stream.add_output(&session.id, output);
+            // The client receives the packets from the next one on
+            if let Some((seq, rtptime)) = stream.last_rtp() {
+                rtp_info.push(format!(
+                    "url={url};seq={};rtptime={rtptime}",
+                    seq.wrapping_add(1)
+                ));
+            }
+            stream.request_key_unit();
+        }
+        session.playing = true;
+        gst::info!(CAT, "Client {} is playing", self.peer);
+
+        let mut rsp = self.response(req, StatusCode::Ok).header(RANGE, "npt=now-");
+        if !rtp_info.is_empty() {
+            rsp = rsp.header(RTP_INFO, rtp_info.join(","));
+        }
+        rsp.build(Body::default())
+    }
+
+    fn pause(&mut self, req: &Request<Body>) -> Response<Body> {
+        if let Err(status) = self.check_session(req) {
+            return self.reply(req, status);
+        }
+        self.remove_outputs();
+        if let Some(session) = &mut self.session {
+            session.playing = false;
+        }
+        self.reply(req, StatusCode::Ok)
+    }
+
+    fn teardown(&mut self, req: &Request<Body>) -> Response<Body> {
+        if let Err(status) = self.check_session(req) {
+            return self.reply(req, status);
+        }
+        let rsp = self.reply(req, StatusCode::Ok);
+        self.stop();
+        rsp
+    }
+
+    /// Pass RTCP received over TCP on to the session
+    fn handle_data(&self, data: rtsp_types::Data<Body>) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let channel = data.channel_id();
+        let stream =
+            self.media.streams.iter().zip(&session.streams).find_map(
+                |(stream, setup)| match setup {
+                    Some((_, StreamTransport::Tcp { channels })) if channels.1 == channel => {
+                        Some(stream)
+                    }
+                    _ => None,
+                },
+            );
+        let Some(stream) = stream else {
+            gst::trace!(CAT, "Ignoring data on channel {channel}");
+            return;
+        };
+        stream.push_rtcp(gst::Buffer::from_slice(data.into_body()));
+    }
+
+    fn remove_outputs(&self) {
+        if let Some(session) = &self.session {
+            for stream in &self.media.streams {
+                stream.remove_output(&session.id);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.remove_outputs();
+        self.session = None;
+    }
+}
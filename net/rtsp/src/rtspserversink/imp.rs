@@ -0,0 +1,769 @@
+// GStreamer RTSP Server Sink
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://www.rfc-editor.org/rfc/rfc2326.html
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::runtime;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use rtsp_types::Message;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use super::client;
+use super::sdp;
+use crate::rtspsrc::body::Body;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_PORT: u32 = 8554;
+const DEFAULT_SESSION_TIMEOUT: u32 = 60;
+
+const MAX_BIND_PORT_RETRY: u16 = 100;
+const UDP_PACKET_MAX_SIZE: usize = 65535 - 8;
+
+pub(crate) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtspserversink",
+        gst::DebugColorFlags::empty(),
+        Some("RTSP server sink"),
+    )
+});
+
+static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
+    runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(1)
+        .build()
+        .unwrap()
+});
+
+#[derive(Debug, Clone)]
+struct Settings {
+    address: String,
+    port: u32,
+    session_timeout: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            address: DEFAULT_ADDRESS.to_string(),
+            port: DEFAULT_PORT,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // Indexes of the requested sink pads, in order
+    streams: Vec<u32>,
+    media: Option<Arc<Media>>,
+    // The accept loop, which owns the client tasks, and the RTCP receive tasks
+    handles: Vec<JoinHandle<()>>,
+}
+
+/// Where to send the packets of a stream for a playing client
+pub(super) enum Output {
+    Tcp {
+        sender: mpsc::Sender<Message<Body>>,
+        channels: (u8, u8),
+    },
+    Udp {
+        rtp: SocketAddr,
+        rtcp: SocketAddr,
+        // Set when RTCP is received from the client, which keeps the session alive
+        seen: Arc<AtomicBool>,
+    },
+}
+
+/// A stream of the media, one per sink pad, shared by all clients
+pub(super) struct MediaStream {
+    index: u32,
+    sinkpad: gst::GhostPad,
+    appsink: gst_app::AppSink,
+    rtcp_appsrc: gst_app::AppSrc,
+    rtp_socket: UdpSocket,
+    rtcp_socket: UdpSocket,
+    // By session ID
+    outputs: Mutex<HashMap<String, Output>>,
+    // Sequence number and RTP time of the last packet, for the RTP-Info of PLAY responses
+    last_rtp: Mutex<Option<(u16, u32)>>,
+}
+
+impl MediaStream {
+    pub(super) fn control(&self) -> String {
+        format!("stream={}", self.index)
+    }
+
+    pub(super) fn server_ports(&self) -> Option<(u16, u16)> {
+        Some((
+            self.rtp_socket.local_addr().ok()?.port(),
+            self.rtcp_socket.local_addr().ok()?.port(),
+        ))
+    }
+
+    pub(super) fn last_rtp(&self) -> Option<(u16, u32)> {
+        *self.last_rtp.lock().unwrap()
+    }
+
+    pub(super) fn add_output(&self, session_id: &str, output: Output) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), output);
+    }
+
+    pub(super) fn remove_output(&self, session_id: &str) {
+        self.outputs.lock().unwrap().remove(session_id);
+    }
+
+    /// Ask upstream for a key unit, so that a client that just started playing can decode
+    pub(super) fn request_key_unit(&self) {
+        let s = gst::Structure::builder("GstForceKeyUnit")
+            .field("all-headers", true)
+            .build();
+        let _ = self.sinkpad.push_event(gst::event::CustomUpstream::new(s));
+    }
+
+    pub(super) fn push_rtcp(&self, buffer: gst::Buffer) {
+        if let Err(err) = self.rtcp_appsrc.push_buffer(buffer) {
+            gst::debug!(CAT, "Failed to push RTCP: {err:?}");
+        }
+    }
+
+    fn send(&self, buffer: gst::Buffer, is_rtcp: bool) {
+        let Ok(map) = buffer.map_readable() else {
+            gst::error!(CAT, "Failed to map buffer");
+            return;
+        };
+        if !is_rtcp && map.len() >= 8 {
+            *self.last_rtp.lock().unwrap() = Some((
+                u16::from_be_bytes([map[2], map[3]]),
+                u32::from_be_bytes([map[4], map[5], map[6], map[7]]),
+            ));
+        }
+
+        let socket = if is_rtcp {
+            &self.rtcp_socket
+        } else {
+            &self.rtp_socket
+        };
+        for output in self.outputs.lock().unwrap().values() {
+            match output {
+                Output::Tcp { sender, channels } => {
+                    let channel = if is_rtcp { channels.1 } else { channels.0 };
+                    let Ok(mapped) = buffer.clone().into_mapped_buffer_readable() else {
+                        continue;
+                    };
+                    let data = rtsp_types::Data::new(channel, Body::mapped(mapped));
+                    // Don't block the streaming thread on slow clients
+                    if sender.try_send(Message::Data(data)).is_err() {
+                        gst::trace!(CAT, "Dropping packet for client that is not keeping up");
+                    }
+                }
+                Output::Udp { rtp, rtcp, .. } => {
+                    let addr = if is_rtcp { rtcp } else { rtp };
+                    if let Err(err) = socket.try_send_to(&map, *addr) {
+                        gst::trace!(CAT, "Failed to send packet to {addr}: {err:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    fn mark_seen(&self, addr: SocketAddr) {
+        for output in self.outputs.lock().unwrap().values() {
+            if let Output::Udp { rtcp, seen, .. } = output {
+                if *rtcp == addr {
+                    seen.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// The media served to all clients
+pub(super) struct Media {
+    pub(super) streams: Vec<Arc<MediaStream>>,
+    pub(super) session_timeout: Duration,
+    session_id: u64,
+}
+
+impl Media {
+    /// Returns the SDP of the media, or `None` if not all streams are negotiated yet
+    pub(super) fn session_description(&self, addr: IpAddr) -> Option<String> {
+        let streams = self
+            .streams
+            .iter()
+            .map(|s| Some((s.control(), s.appsink.static_pad("sink")?.current_caps()?)))
+            .collect::<Option<Vec<_>>>()?;
+        sdp::session_description(addr, self.session_id, &streams)
+    }
+
+    pub(super) fn stream(&self, control: &str) -> Option<(usize, &Arc<MediaStream>)> {
+        self.streams
+            .iter()
+            .enumerate()
+            .find(|(_, s)| s.control() == control)
+    }
+}
+
+#[derive(Default)]
+pub struct RtspServerSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    // rtpsend and rtprecv
+    rtp_elements: once_cell::sync::OnceCell<(gst::Element, gst::Element)>,
+}
+
+/// Finds a payloader for the caps, preferring the ones of the Rust RTP plugin
+fn find_payloader(caps: &gst::CapsRef) -> Option<gst::ElementFactory> {
+    let mut factories = gst::ElementFactory::factories_with_type(
+        gst::ElementFactoryType::PAYLOADER,
+        gst::Rank::NONE,
+    )
+    .into_iter()
+    .filter(|f| f.can_sink_all_caps(caps))
+    .collect::<Vec<_>>();
+    factories.sort_by_key(|f| {
+        (
+            f.plugin_name().as_deref() != Some("rsrtp"),
+            std::cmp::Reverse(f.rank()),
+        )
+    });
+    factories.into_iter().next()
+}
+
+/// Binds a UDP socket pair on consecutive ports, the first one even
+fn bind_port_pair(addr: IpAddr) -> std::io::Result<(std::net::UdpSocket, std::net::UdpSocket)> {
+    for _ in 0..MAX_BIND_PORT_RETRY {
+        let rtp = std::net::UdpSocket::bind((addr, 0))?;
+        let port = rtp.local_addr()?.port();
+        if port % 2 != 0 {
+            continue;
+        }
+        if let Ok(rtcp) = std::net::UdpSocket::bind((addr, port + 1)) {
+            return Ok((rtp, rtcp));
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AddrInUse,
+        "Could not find a free port pair",
+    ))
+}
+
+async fn accept_loop(listener: TcpListener, media: Arc<Media>) {
+    // Dropping the set when the loop is aborted aborts the clients as well
+    let mut clients = JoinSet::new();
+    loop {
+        tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, peer)) => {
+                    gst::info!(CAT, "New client {peer}");
+                    clients.spawn(client::serve(media.clone(), stream, peer));
+                }
+                Err(err) => {
+                    gst::warning!(CAT, "Failed to accept client: {err:?}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            },
+            Some(_) = clients.join_next() => {}
+        }
+    }
+}
+
+async fn udp_rtcp_task(stream: Arc<MediaStream>) {
+    let mut buf = vec![0u8; UDP_PACKET_MAX_SIZE];
+    loop {
+        match stream.rtcp_socket.recv_from(&mut buf).await {
+            Ok((len, addr)) => {
+                gst::trace!(CAT, "Received RTCP of size {len} from {addr}");
+                stream.mark_seen(addr);
+                stream.push_rtcp(gst::Buffer::from_slice(buf[..len].to_vec()));
+            }
+            Err(err) => {
+                gst::warning!(CAT, "Failed to receive RTCP: {err:?}");
+                break;
+            }
+        }
+    }
+}
+
+impl RtspServerSink {
+    /// Creates the RTP session elements on first use, failing if the rtpbin2 elements are missing
+    fn rtp_elements(&self) -> Result<&(gst::Element, gst::Element)> {
+        self.rtp_elements.get_or_try_init(|| {
+            let rtprecv = gst::ElementFactory::make("rtprecv")
+                .build()
+                .context("Failed to create rtprecv")?;
+            let rtpsend = gst::ElementFactory::make("rtpsend")
+                .property("rtp-id", rtprecv.property::<String>("rtp-id"))
+                .build()
+                .context("Failed to create rtpsend")?;
+            self.obj().add_many([&rtprecv, &rtpsend])?;
+            Ok((rtpsend, rtprecv))
+        })
+    }
+
+    fn rtpsend(&self) -> Result<&gst::Element> {
+        self.rtp_elements().map(|(rtpsend, _)| rtpsend)
+    }
+
+    fn rtprecv(&self) -> Result<&gst::Element> {
+        self.rtp_elements().map(|(_, rtprecv)| rtprecv)
+    }
+
+    fn start(&self) -> Result<()> {
+        let settings = self.settings.lock().unwrap().clone();
+        let address = settings
+            .address
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid address {}", settings.address))?;
+        let obj = self.obj();
+        let _guard = RUNTIME.enter();
+
+        let mut state = self.state.lock().unwrap();
+        if state.streams.is_empty() {
+            anyhow::bail!("No sink pads were requested");
+        }
+
+        let mut streams = Vec::new();
+        for &index in &state.streams {
+            let (rtp_socket, rtcp_socket) = bind_port_pair(address)?;
+            rtp_socket.set_nonblocking(true)?;
+            rtcp_socket.set_nonblocking(true)?;
+            let by_name = |name: String| obj.by_name(&name).expect("created with the pad");
+            streams.push(Arc::new(MediaStream {
+                index,
+                sinkpad: obj
+                    .static_pad(&format!("sink_{index}"))
+                    .and_then(|p| p.downcast::<gst::GhostPad>().ok())
+                    .expect("created with the pad"),
+                appsink: by_name(format!("rtp_appsink_{index}"))
+                    .downcast::<gst_app::AppSink>()
+                    .unwrap(),
+                rtcp_appsrc: by_name(format!("rtcp_appsrc_{index}"))
+                    .downcast::<gst_app::AppSrc>()
+                    .unwrap(),
+                rtp_socket: UdpSocket::from_std(rtp_socket)?,
+                rtcp_socket: UdpSocket::from_std(rtcp_socket)?,
+                outputs: Mutex::default(),
+                last_rtp: Mutex::default(),
+            }));
+        }
+
+        for stream in &streams {
+            let s = stream.clone();
+            stream.appsink.set_callbacks(
+                gst_app::AppSinkCallbacks::builder()
+                    .new_sample(move |appsink| {
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        if let Some(buffer) = sample.buffer_owned() {
+                            s.send(buffer, false);
+                        }
+                        Ok(gst::FlowSuccess::Ok)
+                    })
+                    .build(),
+            );
+            let s = stream.clone();
+            obj.by_name(&format!("rtcp_appsink_{}", stream.index))
+                .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+                .expect("created with the pad")
+                .set_callbacks(
+                    gst_app::AppSinkCallbacks::builder()
+                        .new_sample(move |appsink| {
+                            let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                            if let Some(buffer) = sample.buffer_owned() {
+                                s.send(buffer, true);
+                            }
+                            Ok(gst::FlowSuccess::Ok)
+                        })
+                        .build(),
+                );
+        }
+
+        let listener = std::net::TcpListener::bind((address, settings.port as u16))
+            .with_context(|| format!("Failed to listen on {address}:{}", settings.port))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        gst::info!(CAT, imp = self, "Listening on {}", listener.local_addr()?);
+
+        let media = Arc::new(Media {
+            streams,
+            session_timeout: Duration::from_secs(settings.session_timeout.into()),
+            session_id: rand::random::<u32>().into(),
+        });
+        for stream in &media.streams {
+            state
+                .handles
+                .push(RUNTIME.spawn(udp_rtcp_task(stream.clone())));
+        }
+        state
+            .handles
+            .push(RUNTIME.spawn(accept_loop(listener, media.clone())));
+        state.media = Some(media);
+
+        Ok(())
+    }
+
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        for h in state.handles.drain(..) {
+            h.abort();
+        }
+        if let Some(media) = state.media.take() {
+            for stream in &media.streams {
+                stream.outputs.lock().unwrap().clear();
+            }
+        }
+    }
+
+    /// Payload the stream of `pad` with the given caps, once they are known
+    fn link_payloader(&self, pad: &gst::GhostPad, caps: &gst::CapsRef) -> Result<()> {
+        if pad.target().is_some() {
+            return Ok(());
+        }
+
+        let obj = self.obj();
+        let index = pad
+            .name()
+            .strip_prefix("sink_")
+            .and_then(|i| i.parse::<u32>().ok())
+            .expect("sink pads are named sink_%u");
+        let rtp_sinkpad = self
+            .rtpsend()?
+            .static_pad(&format!("rtp_sink_{index}"))
+            .expect("requested with the pad");
+
+        let s = caps.structure(0).context("Empty caps")?;
+        let target = if s.name() == "application/x-rtp" {
+            rtp_sinkpad
+        } else {
+            let factory =
+                find_payloader(caps).with_context(|| format!("No payloader for {caps}"))?;
+            gst::info!(
+                CAT,
+                imp = self,
+                "Payloading {} with {}",
+                pad.name(),
+                factory.name()
+            );
+            let pay = factory.create().name(format!("pay_{index}")).build()?;
+            obj.add(&pay)?;
+            pay.static_pad("src").unwrap().link(&rtp_sinkpad)?;
+            pay.sync_state_with_parent()?;
+            pay.static_pad("sink").unwrap()
+        };
+        pad.set_target(Some(&target))?;
+
+        Ok(())
+    }
+
+    /// Removes the elements and the rtpbin2 pads of the stream, undoing `add_stream()`
+    fn remove_stream(&self, index: u32) {
+        let obj = self.obj();
+
+        if let Some((rtpsend, rtprecv)) = self.rtp_elements.get() {
+            for (element, name) in [
+                (rtpsend, format!("rtp_sink_{index}")),
+                (rtpsend, format!("rtcp_src_{index}")),
+                (rtprecv, format!("rtcp_sink_{index}")),
+            ] {
+                if let Some(pad) = element.static_pad(&name) {
+                    element.release_request_pad(&pad);
+                }
+            }
+        }
+
+        for name in [
+            format!("pay_{index}"),
+            format!("rtp_appsink_{index}"),
+            format!("rtcp_appsink_{index}"),
+            format!("rtcp_appsrc_{index}"),
+        ] {
+            if let Some(element) = obj.by_name(&name) {
+                let _ = element.set_state(gst::State::Null);
+                let _ = obj.remove(&element);
+            }
+        }
+    }
+
+    fn add_stream(&self, index: u32) -> Result<()> {
+        let obj = self.obj();
+        let rtpsend = self.rtpsend()?;
+
+        let appsink = gst_app::AppSink::builder()
+            .name(format!("rtp_appsink_{index}"))
+            .build();
+        obj.add(&appsink)?;
+        rtpsend
+            .request_pad_simple(&format!("rtp_sink_{index}"))
+            .context("Failed to request rtpsend RTP sink pad")?;
+        rtpsend
+            .static_pad(&format!("rtp_src_{index}"))
+            .context("No rtpsend RTP src pad")?
+            .link(&appsink.static_pad("sink").unwrap())?;
+
+        let rtcp_appsink = gst_app::AppSink::builder()
+            .name(format!("rtcp_appsink_{index}"))
+            .sync(false)
+            .async_(false)
+            .build();
+        obj.add(&rtcp_appsink)?;
+        rtpsend
+            .request_pad_simple(&format!("rtcp_src_{index}"))
+            .context("Failed to request rtpsend RTCP src pad")?
+            .link(&rtcp_appsink.static_pad("sink").unwrap())?;
+
+        let rtcp_appsrc = gst_app::AppSrc::builder()
+            .name(format!("rtcp_appsrc_{index}"))
+            .caps(&gst::Caps::new_empty_simple("application/x-rtcp"))
+            .format(gst::Format::Time)
+            .is_live(true)
+            .do_timestamp(true)
+            .build();
+        obj.add(&rtcp_appsrc)?;
+        rtcp_appsrc.static_pad("src").unwrap().link(
+            &self
+                .rtprecv()?
+                .request_pad_simple(&format!("rtcp_sink_{index}"))
+                .context("Failed to request rtprecv RTCP sink pad")?,
+        )?;
+
+        for e in [
+            appsink.upcast_ref::<gst::Element>(),
+            rtcp_appsink.upcast_ref(),
+            rtcp_appsrc.upcast_ref(),
+        ] {
+            e.sync_state_with_parent()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtspServerSink {
+    const NAME: &'static str = "GstRtspServerSink";
+    type Type = super::RtspServerSink;
+    type ParentType = gst::Bin;
+}
+
+impl ObjectImpl for RtspServerSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("address")
+                    .nick("Address")
+                    .blurb("Address to listen on for RTSP clients and to send UDP from")
+                    .default_value(DEFAULT_ADDRESS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("port")
+                    .nick("Port")
+                    .blurb("Port to listen on for RTSP clients")
+                    .maximum(u16::MAX.into())
+                    .default_value(DEFAULT_PORT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("session-timeout")
+                    .nick("Session timeout")
+                    .blurb("Time after which clients without activity are disconnected, in seconds")
+                    .minimum(1)
+                    .default_value(DEFAULT_SESSION_TIMEOUT)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "address" => {
+                settings.address = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+            }
+            "port" => {
+                settings.port = value.get().expect("type checked upstream");
+            }
+            "session-timeout" => {
+                settings.session_timeout = value.get().expect("type checked upstream");
+            }
+            name => unimplemented!("Property '{name}'"),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "address" => settings.address.to_value(),
+            "port" => settings.port.to_value(),
+            "session-timeout" => settings.session_timeout.to_value(),
+            name => unimplemented!("Property '{name}'"),
+        }
+    }
+}
+
+impl GstObjectImpl for RtspServerSink {}
+
+impl ElementImpl for RtspServerSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTSP Server Sink",
+                "Sink/Network",
+                "Serve audio and video to clients via the Real Time Streaming Protocol (RTSP) (RFC 2326)",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink_%u",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &gst::Caps::new_any(),
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn request_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        name: Option<&str>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        if self.obj().current_state() > gst::State::Null {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Requesting pads on a started 'rtspserversink' is not supported"
+            );
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let index = match name.and_then(|n| n.strip_prefix("sink_")) {
+            Some(index) => index.parse::<u32>().ok()?,
+            None => state.streams.iter().max().map_or(0, |i| i + 1),
+        };
+        if state.streams.contains(&index) {
+            gst::error!(CAT, imp = self, "Pad sink_{index} already exists");
+            return None;
+        }
+
+        if let Err(err) = self.add_stream(index) {
+            gst::error!(CAT, imp = self, "Failed to add stream {index}: {err:?}");
+            self.remove_stream(index);
+            return None;
+        }
+
+        let pad = gst::GhostPad::builder_from_template(templ)
+            .name(format!("sink_{index}"))
+            .event_function(|pad, parent, event| {
+                RtspServerSink::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |imp| {
+                        if let gst::EventView::Caps(caps) = event.view() {
+                            if let Err(err) = imp.link_payloader(pad, caps.caps()) {
+                                gst::element_imp_error!(
+                                    imp,
+                                    gst::CoreError::Negotiation,
+                                    ["Failed to payload {}: {err:?}", pad.name()]
+                                );
+                                return false;
+                            }
+                        }
+                        gst::Pad::event_default(pad, parent, event)
+                    },
+                )
+            })
+            .build();
+        self.obj()
+            .add_pad(&pad)
+            .expect("Adding a ghostpad should never fail");
+        state.streams.push(index);
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, pad: &gst::Pad) {
+        let Some(index) = pad
+            .name()
+            .strip_prefix("sink_")
+            .and_then(|i| i.parse::<u32>().ok())
+        else {
+            return;
+        };
+
+        self.state.lock().unwrap().streams.retain(|&i| i != index);
+
+        if let Some(pad) = pad.downcast_ref::<gst::GhostPad>() {
+            let _ = pad.set_target(None::<&gst::Pad>);
+        }
+        self.remove_stream(index);
+        let _ = self.obj().remove_pad(pad);
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::NullToReady {
+            self.rtp_elements().map_err(|err| {
+                gst::element_imp_error!(self, gst::CoreError::MissingPlugin, ["{err:?}"]);
+                gst::StateChangeError
+            })?;
+            self.start().map_err(|err| {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to start RTSP server: {err:?}"]
+                );
+                gst::StateChangeError
+            })?;
+        }
+
+        let ret = self.parent_change_state(transition);
+        if ret.is_err() || transition == gst::StateChange::ReadyToNull {
+            self.stop();
+        }
+        ret
+    }
+}
+
+impl BinImpl for RtspServerSink {}
@@ -0,0 +1,62 @@
+// GStreamer RTSP Server Sink
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtspserversink
+ *
+ * `rtspserversink` serves the streams of its sink pads to RTSP clients. Each request pad is
+ * payloaded with a payloader picked from its caps, preferring the ones of the Rust RTP plugin,
+ * and sent through `rtpsend`. Already payloaded `application/x-rtp` streams are sent as is.
+ *
+ * All clients share the same pipeline and receive the streams live, from the moment they start
+ * playing. The media is available at any path on the server.
+ *
+ * Implemented features:
+ * * RTSP 1.0 with OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE and TEARDOWN
+ * * SDP generated from the RTP caps of the streams
+ * * Lower transports: TCP interleaved and UDP unicast
+ * * RTCP SR, and RTCP RR from the clients
+ * * Session keep-alive with `GET_PARAMETER`, `SET_PARAMETER`, RTCP or any other request
+ *
+ * Some missing features:
+ * * UDP multicast
+ * * Authentication and TLS
+ * * RECORD
+ * * Multiple mount points
+ *
+ * ## Example launch line
+ *
+ * ```shell
+ * gst-launch-1.0 rtspserversink name=s port=8554 \
+ *     videotestsrc is-live=true ! x264enc tune=zerolatency ! s.sink_0 \
+ *     audiotestsrc is-live=true ! opusenc ! s.sink_1
+ * ```
+ *
+ * The streams can then be played with `rtspsrc2 location=rtsp://127.0.0.1:8554/test`.
+ *
+ * Since: plugins-rs-0.14.0
+ */
+use gst::glib;
+use gst::prelude::*;
+
+mod client;
+mod imp;
+mod sdp;
+
+glib::wrapper! {
+    pub struct RtspServerSink(ObjectSubclass<imp::RtspServerSink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rtspserversink",
+        gst::Rank::NONE,
+        RtspServerSink::static_type(),
+    )
+}
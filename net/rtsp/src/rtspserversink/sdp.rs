@@ -0,0 +1,179 @@
+// GStreamer RTSP Server Sink
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://datatracker.ietf.org/doc/html/rfc4566
+
+use std::fmt::Write;
+use std::net::IpAddr;
+
+// RTP caps fields that are not format parameters, like in gst_sdp_media_set_media_from_caps()
+const NON_FMTP_FIELDS: &[&str] = &[
+    "media",
+    "payload",
+    "clock-rate",
+    "encoding-name",
+    "encoding-params",
+    "ssrc",
+    "timestamp-offset",
+    "seqnum-offset",
+    "seqnum-base",
+    "clock-base",
+    "npt-start",
+    "npt-stop",
+    "play-speed",
+    "play-scale",
+    "mikey",
+];
+const NON_FMTP_PREFIXES: &[&str] = &["a-", "x-", "extmap-", "rtcp-fb-", "srtp", "srtcp"];
+
+fn addrtype(addr: IpAddr) -> &'static str {
+    if addr.is_ipv4() {
+        "IP4"
+    } else {
+        "IP6"
+    }
+}
+
+/// Writes the media description of a stream with the given control path and RTP caps. Returns
+/// `None` if the caps are missing the media, payload type, encoding name or clock rate.
+fn write_media(sdp: &mut String, addr: IpAddr, control: &str, caps: &gst::CapsRef) -> Option<()> {
+    let s = caps.structure(0)?;
+    let media = s.get::<&str>("media").ok()?;
+    let pt = s.get::<i32>("payload").ok()?;
+    let encoding_name = s.get::<&str>("encoding-name").ok()?;
+    let clock_rate = s.get::<i32>("clock-rate").ok()?;
+    let unspecified = if addr.is_ipv4() { "0.0.0.0" } else { "::" };
+
+    write!(sdp, "m={media} 0 RTP/AVP {pt}\r\n").unwrap();
+    write!(sdp, "c=IN {} {unspecified}\r\n", addrtype(addr)).unwrap();
+    write!(sdp, "a=rtpmap:{pt} {encoding_name}/{clock_rate}").unwrap();
+    if let Ok(params) = s.get::<&str>("encoding-params") {
+        write!(sdp, "/{params}").unwrap();
+    }
+    sdp.push_str("\r\n");
+
+    let mut fmtp = Vec::new();
+    for (field, value) in s.iter() {
+        let Ok(value) = value.get::<&str>() else {
+            continue;
+        };
+        if let Some(attr) = field.strip_prefix("a-") {
+            write!(sdp, "a={attr}:{value}\r\n").unwrap();
+        } else if let Some(id) = field.strip_prefix("extmap-") {
+            write!(sdp, "a=extmap:{id} {value}\r\n").unwrap();
+        } else if !NON_FMTP_FIELDS.contains(&field.as_str())
+            && !NON_FMTP_PREFIXES.iter().any(|p| field.starts_with(p))
+        {
+            fmtp.push(format!("{field}={value}"));
+        }
+    }
+    if !fmtp.is_empty() {
+        write!(sdp, "a=fmtp:{pt} {}\r\n", fmtp.join(";")).unwrap();
+    }
+    write!(sdp, "a=control:{control}\r\n").unwrap();
+
+    Some(())
+}
+
+/// Describes a live session served from `addr` with streams of the given control paths and RTP
+/// caps. Returns `None` if some caps can't be described.
+pub(super) fn session_description(
+    addr: IpAddr,
+    session_id: u64,
+    streams: &[(String, gst::Caps)],
+) -> Option<String> {
+    let mut sdp = String::new();
+    write!(sdp, "v=0\r\n").unwrap();
+    write!(sdp, "o=- {session_id} 1 IN {} {addr}\r\n", addrtype(addr)).unwrap();
+    write!(sdp, "s=Session streamed with GStreamer\r\n").unwrap();
+    write!(sdp, "i=rtspserversink\r\n").unwrap();
+    write!(sdp, "t=0 0\r\n").unwrap();
+    write!(sdp, "a=tool:GStreamer\r\n").unwrap();
+    write!(sdp, "a=type:broadcast\r\n").unwrap();
+    write!(sdp, "a=control:*\r\n").unwrap();
+    write!(sdp, "a=range:npt=now-\r\n").unwrap();
+
+    for (control, caps) in streams {
+        write_media(&mut sdp, addr, control, caps)?;
+    }
+
+    Some(sdp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_description() {
+        gst::init().unwrap();
+
+        let streams = [
+            (
+                "stream=0".to_string(),
+                "application/x-rtp, media=(string)video, payload=(int)96, \
+                 clock-rate=(int)90000, encoding-name=(string)H264, \
+                 packetization-mode=(string)1, profile-level-id=(string)42c01f, \
+                 a-framerate=(string)30, ssrc=(uint)1234, seqnum-offset=(uint)42"
+                    .parse::<gst::Caps>()
+                    .unwrap(),
+            ),
+            (
+                "stream=1".to_string(),
+                "application/x-rtp, media=(string)audio, payload=(int)97, \
+                 clock-rate=(int)48000, encoding-name=(string)OPUS, \
+                 encoding-params=(string)2, sprop-stereo=(string)0"
+                    .parse::<gst::Caps>()
+                    .unwrap(),
+            ),
+        ];
+        let sdp = session_description("192.0.2.1".parse().unwrap(), 1, &streams).unwrap();
+
+        assert_eq!(
+            sdp,
+            "v=0\r\n\
+             o=- 1 1 IN IP4 192.0.2.1\r\n\
+             s=Session streamed with GStreamer\r\n\
+             i=rtspserversink\r\n\
+             t=0 0\r\n\
+             a=tool:GStreamer\r\n\
+             a=type:broadcast\r\n\
+             a=control:*\r\n\
+             a=range:npt=now-\r\n\
+             m=video 0 RTP/AVP 96\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=rtpmap:96 H264/90000\r\n\
+             a=framerate:30\r\n\
+             a=fmtp:96 packetization-mode=1;profile-level-id=42c01f\r\n\
+             a=control:stream=0\r\n\
+             m=audio 0 RTP/AVP 97\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=rtpmap:97 OPUS/48000/2\r\n\
+             a=fmtp:97 sprop-stereo=0\r\n\
+             a=control:stream=1\r\n"
+        );
+        assert_eq!(
+            sdp_types::Session::parse(sdp.as_bytes())
+                .unwrap()
+                .medias
+                .len(),
+            2
+        );
+
+        let incomplete = [(
+            "stream=0".to_string(),
+            "application/x-rtp, media=(string)video, payload=(int)96"
+                .parse::<gst::Caps>()
+                .unwrap(),
+        )];
+        assert_eq!(
+            session_description("192.0.2.1".parse().unwrap(), 1, &incomplete),
+            None
+        );
+    }
+}
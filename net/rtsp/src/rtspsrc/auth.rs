@@ -0,0 +1,447 @@
+// GStreamer RTSP Source 2
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://www.rfc-editor.org/rfc/rfc7617.html
+// https://www.rfc-editor.org/rfc/rfc7616.html
+
+use std::collections::HashMap;
+use std::fmt;
+
+use data_encoding::{BASE64, HEXLOWER};
+use md5::Md5;
+use rand::RngCore;
+use rtsp_types::headers::{AUTHORIZATION, WWW_AUTHENTICATE};
+use rtsp_types::{Message, Request, Response};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::body::Body;
+use super::imp::{RtspError, CAT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    fn parse(s: Option<&str>) -> Option<Self> {
+        // No algorithm means MD5: https://www.rfc-editor.org/rfc/rfc7616.html#section-3.3
+        match s.map(str::to_ascii_uppercase).as_deref() {
+            None | Some("MD5") => Some(Self::Md5),
+            Some("MD5-SESS") => Some(Self::Md5Sess),
+            Some("SHA-256") => Some(Self::Sha256),
+            Some("SHA-256-SESS") => Some(Self::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Md5Sess => "MD5-sess",
+            Self::Sha256 => "SHA-256",
+            Self::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_sess(&self) -> bool {
+        matches!(self, Self::Md5Sess | Self::Sha256Sess)
+    }
+
+    fn hash(&self, data: &str) -> String {
+        match self {
+            Self::Md5 | Self::Md5Sess => HEXLOWER.encode(&Md5::digest(data.as_bytes())),
+            Self::Sha256 | Self::Sha256Sess => HEXLOWER.encode(&Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Challenge {
+    Basic,
+    Digest {
+        realm: String,
+        nonce: String,
+        opaque: Option<String>,
+        algorithm: DigestAlgorithm,
+        qop_auth: bool,
+        stale: bool,
+    },
+}
+
+impl Challenge {
+    fn from_params(scheme: &str, params: &HashMap<String, String>) -> Option<Self> {
+        match scheme {
+            "basic" => Some(Challenge::Basic),
+            "digest" => {
+                let qop = params.get("qop");
+                // Only qop=auth is supported, auth-int would need the body hash
+                if qop.is_some_and(|qop| !qop.split(',').any(|q| q.trim() == "auth")) {
+                    return None;
+                }
+
+                Some(Challenge::Digest {
+                    realm: params.get("realm")?.clone(),
+                    nonce: params.get("nonce")?.clone(),
+                    opaque: params.get("opaque").cloned(),
+                    algorithm: DigestAlgorithm::parse(params.get("algorithm").map(String::as_str))?,
+                    qop_auth: qop.is_some(),
+                    stale: params
+                        .get("stale")
+                        .is_some_and(|s| s.eq_ignore_ascii_case("true")),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        matches!(self, Challenge::Digest { stale: true, .. })
+    }
+
+    /// Digest with the strongest algorithm is preferred over Basic
+    fn strength(&self) -> u8 {
+        match self {
+            Challenge::Basic => 0,
+            Challenge::Digest { algorithm, .. } => match algorithm {
+                DigestAlgorithm::Md5 | DigestAlgorithm::Md5Sess => 1,
+                DigestAlgorithm::Sha256 | DigestAlgorithm::Sha256Sess => 2,
+            },
+        }
+    }
+}
+
+/// Parse the challenges of a `WWW-Authenticate` header, several challenges can be present when
+/// the server sent multiple headers or a comma-separated list
+fn parse_challenges(value: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges: Vec<(String, HashMap<String, String>)> = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+
+        let mut token = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',' && *c != '=') {
+            token.push(c);
+        }
+        if token.is_empty() {
+            break;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        if chars.next_if_eq(&'=').is_none() {
+            challenges.push((token.to_ascii_lowercase(), HashMap::new()));
+            continue;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut param_value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => param_value.extend(chars.next()),
+                    c => param_value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',') {
+                param_value.push(c);
+            }
+        }
+
+        if let Some((_, params)) = challenges.last_mut() {
+            params.insert(token.to_ascii_lowercase(), param_value);
+        }
+    }
+
+    challenges
+}
+
+#[allow(clippy::too_many_arguments)]
+fn digest_response(
+    algorithm: DigestAlgorithm,
+    user: &str,
+    password: &str,
+    realm: &str,
+    nonce: &str,
+    qop: Option<(&str, &str)>,
+    method: &str,
+    uri: &str,
+) -> String {
+    let mut ha1 = algorithm.hash(&format!("{user}:{realm}:{password}"));
+    if let (true, Some((_, cnonce))) = (algorithm.is_sess(), qop) {
+        ha1 = algorithm.hash(&format!("{ha1}:{nonce}:{cnonce}"));
+    }
+    let ha2 = algorithm.hash(&format!("{method}:{uri}"));
+
+    match qop {
+        Some((nc, cnonce)) => algorithm.hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}")),
+        None => algorithm.hash(&format!("{ha1}:{nonce}:{ha2}")),
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Debug)]
+pub(super) struct Authenticator {
+    user: String,
+    password: String,
+    challenge: Option<Challenge>,
+    nonce_count: u32,
+}
+
+impl Authenticator {
+    pub(super) fn new(user: String, password: String) -> Self {
+        Authenticator {
+            user,
+            password,
+            challenge: None,
+            nonce_count: 0,
+        }
+    }
+
+    /// Pick the strongest supported challenge from a `401 Unauthorized` response. Fails if the
+    /// credentials were already rejected for a non-stale challenge.
+    pub(super) fn handle_unauthorized(&mut self, rsp: &Response<Body>) -> Result<(), RtspError> {
+        let Some(value) = rsp.header(&WWW_AUTHENTICATE) else {
+            return Err(RtspError::InvalidMessage(
+                "401 Unauthorized response without WWW-Authenticate header",
+            ));
+        };
+
+        let challenge = parse_challenges(value.as_str())
+            .iter()
+            .filter_map(|(scheme, params)| Challenge::from_params(scheme, params))
+            .max_by_key(Challenge::strength)
+            .ok_or_else(|| {
+                RtspError::Fatal(format!(
+                    "No supported authentication challenge in '{}'",
+                    value.as_str()
+                ))
+            })?;
+
+        if self.challenge.is_some() && !challenge.is_stale() {
+            return Err(RtspError::Unauthorized(format!(
+                "Authentication failed for user '{}'",
+                self.user
+            )));
+        }
+
+        gst::debug!(CAT, "Using authentication challenge {challenge:?}");
+        self.challenge = Some(challenge);
+        self.nonce_count = 0;
+
+        Ok(())
+    }
+
+    /// Add an `Authorization` header to `req` if the server challenged us
+    pub(super) fn authorize(&mut self, req: &mut Request<Body>) {
+        let Some(challenge) = &self.challenge else {
+            return;
+        };
+
+        let value = match challenge {
+            Challenge::Basic => {
+                let credentials = format!("{}:{}", self.user, self.password);
+                format!("Basic {}", BASE64.encode(credentials.as_bytes()))
+            }
+            Challenge::Digest {
+                realm,
+                nonce,
+                opaque,
+                algorithm,
+                qop_auth,
+                stale: _,
+            } => {
+                let method: &str = req.method().into();
+                let uri = req
+                    .request_uri()
+                    .map(Url::as_str)
+                    .unwrap_or("*")
+                    .to_string();
+
+                let mut cnonce = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut cnonce);
+                let cnonce = HEXLOWER.encode(&cnonce);
+                self.nonce_count += 1;
+                let nc = format!("{:08x}", self.nonce_count);
+
+                let qop = qop_auth.then_some((nc.as_str(), cnonce.as_str()));
+                let response = digest_response(
+                    *algorithm,
+                    &self.user,
+                    &self.password,
+                    realm,
+                    nonce,
+                    qop,
+                    method,
+                    &uri,
+                );
+
+                let mut value = format!(
+                    "Digest username={}, realm={}, nonce={}, uri={}, response={}, algorithm={}",
+                    quote(&self.user),
+                    quote(realm),
+                    quote(nonce),
+                    quote(&uri),
+                    quote(&response),
+                    algorithm.as_str(),
+                );
+                if let Some(opaque) = opaque {
+                    value.push_str(&format!(", opaque={}", quote(opaque)));
+                }
+                if *qop_auth {
+                    value.push_str(&format!(", qop=auth, nc={nc}, cnonce={}", quote(&cnonce)));
+                }
+                value
+            }
+        };
+
+        req.insert_header(AUTHORIZATION, value);
+    }
+}
+
+/// Debug formatting of a request or message with the credentials of its `Authorization` header
+/// hidden, for logging
+pub(super) struct Redacted<'a, T>(pub &'a T);
+
+impl fmt::Debug for Redacted<'_, Request<Body>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let req = self.0;
+        let headers = req
+            .headers()
+            .map(|(name, value)| {
+                if *name == AUTHORIZATION {
+                    (name, "<redacted>")
+                } else {
+                    (name, value.as_str())
+                }
+            })
+            .collect::<Vec<_>>();
+
+        f.debug_struct("Request")
+            .field("method", &req.method())
+            .field("request_uri", &req.request_uri())
+            .field("version", &req.version())
+            .field("headers", &headers)
+            .field("body", req.body())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Redacted<'_, Message<Body>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Message::Request(req) => f.debug_tuple("Request").field(&Redacted(req)).finish(),
+            msg => msg.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // https://www.rfc-editor.org/rfc/rfc7616.html#section-3.9.1
+    #[test]
+    fn test_digest_rfc7616() {
+        let challenges = parse_challenges(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS", Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=MD5, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+        );
+        assert_eq!(challenges.len(), 2);
+        let challenge = challenges
+            .iter()
+            .filter_map(|(scheme, params)| Challenge::from_params(scheme, params))
+            .max_by_key(Challenge::strength)
+            .unwrap();
+        let Challenge::Digest {
+            realm,
+            nonce,
+            algorithm,
+            qop_auth,
+            ..
+        } = challenge
+        else {
+            panic!("Expected a digest challenge");
+        };
+        assert_eq!(algorithm, DigestAlgorithm::Sha256);
+        assert!(qop_auth);
+
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        assert_eq!(
+            digest_response(
+                DigestAlgorithm::Sha256,
+                "Mufasa",
+                "Circle of Life",
+                &realm,
+                &nonce,
+                Some(("00000001", cnonce)),
+                "GET",
+                "/dir/index.html",
+            ),
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+        );
+        assert_eq!(
+            digest_response(
+                DigestAlgorithm::Md5,
+                "Mufasa",
+                "Circle of Life",
+                &realm,
+                &nonce,
+                Some(("00000001", cnonce)),
+                "GET",
+                "/dir/index.html",
+            ),
+            "8ca523f5e9506fed4657c9700eebdbec"
+        );
+    }
+
+    #[test]
+    fn test_basic_challenge() {
+        let challenges = parse_challenges(r#"Basic realm="IP Camera""#);
+        assert_eq!(challenges.len(), 1);
+        assert_eq!(challenges[0].0, "basic");
+        assert_eq!(challenges[0].1["realm"], "IP Camera");
+        assert_eq!(
+            Challenge::from_params(&challenges[0].0, &challenges[0].1),
+            Some(Challenge::Basic)
+        );
+    }
+
+    #[test]
+    fn test_redacted() {
+        let mut auth = Authenticator::new("user".to_string(), "pass".to_string());
+        auth.challenge = Some(Challenge::Basic);
+
+        let mut req = Request::builder(rtsp_types::Method::Options, rtsp_types::Version::V1_0)
+            .header(rtsp_types::headers::CSEQ, "1")
+            .build(Body::default());
+        auth.authorize(&mut req);
+        assert_eq!(
+            req.header(&AUTHORIZATION).unwrap().as_str(),
+            "Basic dXNlcjpwYXNz"
+        );
+
+        let logged = format!("{:?}", Redacted(&req));
+        assert!(!logged.contains("dXNlcjpwYXNz"));
+        assert!(logged.contains("<redacted>"));
+        assert!(logged.contains("Options"));
+
+        let logged = format!("{:?}", Redacted(&Message::from(req)));
+        assert!(!logged.contains("dXNlcjpwYXNz"));
+        assert!(logged.contains("<redacted>"));
+    }
+}
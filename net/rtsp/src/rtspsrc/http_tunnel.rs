@@ -0,0 +1,166 @@
+// GStreamer RTSP Source 2
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// RTSP-over-HTTP tunneling as introduced by QuickTime:
+// https://web.archive.org/web/20170106015833/https://developer.apple.com/quicktime/icefloe/dispatch028.html
+//
+// Server messages are read from the response body of a GET request, and client messages are
+// written base64 encoded into the request body of a POST request. The two HTTP connections are
+// tied together by the `x-sessioncookie` header.
+
+use futures::Sink;
+use rand::distributions::{Alphanumeric, DistString};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use url::Url;
+
+use data_encoding::BASE64;
+use rtsp_types::Message;
+
+use super::auth::Redacted;
+use super::body::Body;
+use super::imp::RtspError;
+
+pub(super) const DEFAULT_HTTP_PORT: u16 = 80;
+pub(super) const DEFAULT_HTTPS_PORT: u16 = 443;
+
+const CONTENT_TYPE: &str = "application/x-rtsp-tunnelled";
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// Set up the tunnel over the `get` and `post` connections, both to the server of `url`. Returns
+/// the connection to read RTSP messages from, and the one to write them to with [`async_write`].
+pub(super) async fn start<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    mut get: S,
+    mut post: S,
+    url: &Url,
+    user_agent: &str,
+) -> Result<(BufReader<S>, S), RtspError> {
+    let cookie = Alphanumeric.sample_string(&mut rand::thread_rng(), 22);
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_owned(),
+    };
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap()),
+        None => url.host_str().unwrap().to_owned(),
+    };
+
+    let req = format!(
+        "GET {path} HTTP/1.0\r\n\
+         Host: {host}\r\n\
+         User-Agent: {user_agent}\r\n\
+         x-sessioncookie: {cookie}\r\n\
+         Accept: {CONTENT_TYPE}\r\n\
+         Pragma: no-cache\r\n\
+         Cache-Control: no-cache\r\n\
+         \r\n"
+    );
+    gst::debug!(super::imp::CAT, "-->> {req}");
+    get.write_all(req.as_bytes()).await?;
+
+    let mut get = BufReader::new(get);
+    read_response_header(&mut get).await?;
+
+    // The server never replies to the POST request, and the content length is only there for
+    // proxies that insist on having one.
+    let req = format!(
+        "POST {path} HTTP/1.0\r\n\
+         Host: {host}\r\n\
+         User-Agent: {user_agent}\r\n\
+         x-sessioncookie: {cookie}\r\n\
+         Content-Type: {CONTENT_TYPE}\r\n\
+         Pragma: no-cache\r\n\
+         Cache-Control: no-cache\r\n\
+         Content-Length: 32767\r\n\
+         Expires: Sun, 9 Jan 1972 00:00:00 GMT\r\n\
+         \r\n"
+    );
+    gst::debug!(super::imp::CAT, "-->> {req}");
+    post.write_all(req.as_bytes()).await?;
+
+    gst::info!(super::imp::CAT, "HTTP tunnel established");
+
+    Ok((get, post))
+}
+
+async fn read_response_header<R: AsyncRead + Unpin>(
+    read: &mut BufReader<R>,
+) -> Result<(), RtspError> {
+    let mut header = String::new();
+    loop {
+        let len = header.len();
+        // Read at most one byte more than allowed so that a header without line breaks can't
+        // grow without bounds
+        let limit = (MAX_HEADER_SIZE + 1 - len) as u64;
+        if (&mut *read).take(limit).read_line(&mut header).await? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "HTTP tunnel GET response",
+            )
+            .into());
+        }
+        if header.len() > MAX_HEADER_SIZE {
+            return Err(RtspError::InvalidMessage(
+                "HTTP tunnel GET response header too big",
+            ));
+        }
+        if header[len..].trim_end_matches(['\r', '\n']).is_empty() {
+            break;
+        }
+    }
+    gst::debug!(super::imp::CAT, "<<-- {header}");
+
+    let mut status = header.lines().next().unwrap_or_default().split_whitespace();
+    match (status.next(), status.next()) {
+        (Some(version), Some("200")) if version.starts_with("HTTP/") => Ok(()),
+        (Some(version), Some(_)) if version.starts_with("HTTP/") => Err(RtspError::Fatal(format!(
+            "HTTP tunnel GET request failed: {}",
+            header.lines().next().unwrap()
+        ))),
+        _ => Err(RtspError::InvalidMessage(
+            "Invalid HTTP tunnel GET response",
+        )),
+    }
+}
+
+/// Like [`super::tcp_message::async_write`], but every message is base64 encoded on its own.
+pub(super) fn async_write<W: AsyncWrite + Unpin + Send>(
+    write: W,
+) -> impl Sink<Message<Body>, Error = std::io::Error> + Send {
+    struct State<W> {
+        write: W,
+        buffer: Vec<u8>,
+    }
+
+    let state = State {
+        write,
+        buffer: Vec::with_capacity(8192),
+    };
+
+    futures::sink::unfold(state, |mut state, item: Message<Body>| async move {
+        gst::trace!(
+            super::imp::CAT,
+            "Writing tunnelled message {:?}",
+            Redacted(&item)
+        );
+
+        state.buffer.clear();
+        item.write(&mut state.buffer).expect("can't fail");
+        let encoded = BASE64.encode(&state.buffer);
+
+        match state.write.write_all(encoded.as_bytes()).await {
+            Ok(_) => {
+                gst::trace!(super::imp::CAT, "Finished writing queued message");
+                Ok(state)
+            }
+            Err(err) => {
+                gst::error!(super::imp::CAT, "Write error {}", err);
+                Err(err)
+            }
+        }
+    })
+}
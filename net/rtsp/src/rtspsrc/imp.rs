@@ -11,13 +11,13 @@
 //
 // https://www.rfc-editor.org/rfc/rfc2326.html
 
-use std::collections::{btree_set::BTreeSet, HashMap};
+use std::collections::{btree_set::BTreeSet, HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::num::NonZeroUsize;
 use std::pin::Pin;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::Result;
@@ -34,11 +34,12 @@ use tokio::time;
 use rtsp_types::headers::{
     CSeq, NptRange, NptTime, Public, Range, RtpInfos, RtpLowerTransport, RtpProfile, RtpTransport,
     RtpTransportParameters, Session, Transport, TransportMode, Transports, ACCEPT, CONTENT_BASE,
-    CONTENT_LOCATION, USER_AGENT,
+    CONTENT_LOCATION, CSEQ, RANGE, SCALE, SPEED, USER_AGENT,
 };
 use rtsp_types::{Message, Method, Request, Response, StatusCode, Version};
 
 use lru::LruCache;
+use percent_encoding::percent_decode_str;
 use url::Url;
 
 use gst::buffer::{MappedBuffer, Readable};
@@ -47,6 +48,7 @@ use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst_net::gio;
 
+use super::auth::{Authenticator, Redacted};
 use super::body::Body;
 use super::sdp;
 use super::transport::RtspTransportInfo;
@@ -59,11 +61,21 @@ const DEFAULT_PROTOCOLS: &str = "udp-mcast,udp,tcp";
 // Equal to MTU + 8 by default to avoid incorrectly detecting an MTU sized buffer as having
 // possibly overflown our receive buffer, and triggering a doubling of the buffer sizes.
 const DEFAULT_RECEIVE_MTU: u32 = 1500 + 8;
+const DEFAULT_USER_ID: Option<String> = None;
+const DEFAULT_USER_PW: Option<String> = None;
+const DEFAULT_TLS_VALIDATE_CERTIFICATE: bool = true;
+const DEFAULT_TLS_CA_FILE: Option<String> = None;
+const DEFAULT_RECONNECT_ATTEMPTS: u32 = 0;
+const DEFAULT_RECONNECT_MAX_DELAY: gst::ClockTime = gst::ClockTime::from_seconds(30);
+const DEFAULT_MULTICAST_IFACE: Option<String> = None;
 
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 const MAX_BIND_PORT_RETRY: u16 = 100;
 const UDP_PACKET_MAX_SIZE: u32 = 65535 - 8;
 const RTCP_ADDR_CACHE_SIZE: usize = 100;
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+// https://www.rfc-editor.org/rfc/rfc2326.html#section-12.37
+const DEFAULT_SESSION_TIMEOUT: u64 = 60;
 
 static RTCP_CAPS: Lazy<gst::Caps> =
     Lazy::new(|| gst::Caps::from(gst::Structure::new_empty("application/x-rtcp")));
@@ -81,6 +93,7 @@ pub enum RtspProtocol {
     UdpMulticast,
     Udp,
     Tcp,
+    Http,
 }
 
 impl fmt::Display for RtspProtocol {
@@ -89,6 +102,7 @@ impl fmt::Display for RtspProtocol {
             RtspProtocol::Udp => write!(f, "udp"),
             RtspProtocol::UdpMulticast => write!(f, "udp-mcast"),
             RtspProtocol::Tcp => write!(f, "tcp"),
+            RtspProtocol::Http => write!(f, "http"),
         }
     }
 }
@@ -100,6 +114,13 @@ struct Settings {
     protocols: Vec<RtspProtocol>,
     timeout: gst::ClockTime,
     receive_mtu: u32,
+    user_id: Option<String>,
+    user_pw: Option<String>,
+    tls_validate_certificate: bool,
+    tls_ca_file: Option<String>,
+    reconnect_attempts: u32,
+    reconnect_max_delay: gst::ClockTime,
+    multicast_iface: Option<String>,
 }
 
 impl Default for Settings {
@@ -110,16 +131,58 @@ impl Default for Settings {
             timeout: DEFAULT_TIMEOUT,
             protocols: parse_protocols_str(DEFAULT_PROTOCOLS).unwrap(),
             receive_mtu: DEFAULT_RECEIVE_MTU,
+            user_id: DEFAULT_USER_ID,
+            user_pw: DEFAULT_USER_PW,
+            tls_validate_certificate: DEFAULT_TLS_VALIDATE_CERTIFICATE,
+            tls_ca_file: DEFAULT_TLS_CA_FILE,
+            reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            reconnect_max_delay: DEFAULT_RECONNECT_MAX_DELAY,
+            multicast_iface: DEFAULT_MULTICAST_IFACE,
         }
     }
 }
 
+/// Range and rate to request with the next PLAY after a seek
+#[derive(Debug, Clone, Copy)]
+struct PlayRange {
+    start: gst::ClockTime,
+    stop: Option<gst::ClockTime>,
+    rate: f64,
+    // Whether the server may skip frames to honour the rate
+    trickmode: bool,
+}
+
+impl Default for PlayRange {
+    fn default() -> Self {
+        PlayRange {
+            start: gst::ClockTime::ZERO,
+            stop: None,
+            rate: 1.0,
+            trickmode: false,
+        }
+    }
+}
+
+impl PlayRange {
+    fn npt(&self) -> String {
+        let secs = |t: gst::ClockTime| format!("{:.3}", t.nseconds() as f64 / 1_000_000_000.0);
+        format!(
+            "npt={}-{}",
+            secs(self.start),
+            self.stop.map(secs).unwrap_or_default()
+        )
+    }
+}
+
 #[derive(Debug)]
 enum Commands {
     Play,
-    //Pause,
+    Pause,
+    Seek(PlayRange),
     Teardown(Option<oneshot::Sender<()>>),
     Data(rtsp_types::Data<Body>),
+    // No RTP data was received over UDP for longer than the timeout
+    ReceiveTimeout(String),
 }
 
 #[derive(Debug, Default)]
@@ -127,6 +190,8 @@ pub struct RtspSrc {
     settings: Mutex<Settings>,
     task_handle: Mutex<Option<JoinHandle<()>>>,
     command_queue: Mutex<Option<mpsc::Sender<Commands>>>,
+    // Only known for on-demand media
+    duration: Mutex<Option<gst::ClockTime>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -145,6 +210,8 @@ pub enum RtspError {
     InvalidMessage(&'static str),
     #[error("Fatal error")]
     Fatal(String),
+    #[error("Authentication error")]
+    Unauthorized(String),
 }
 
 pub(crate) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
@@ -176,6 +243,7 @@ fn parse_protocols_str(s: &str) -> Result<Vec<RtspProtocol>, glib::Error> {
             "udp-mcast" => acc.push(RtspProtocol::UdpMulticast),
             "udp" => acc.push(RtspProtocol::Udp),
             "tcp" => acc.push(RtspProtocol::Tcp),
+            "http" => acc.push(RtspProtocol::Http),
             _ => {
                 return Err(glib::Error::new(
                     gst::CoreError::Failed,
@@ -210,11 +278,6 @@ impl RtspSrc {
             )
         })?;
 
-        if uri.password().is_some() || !uri.username().is_empty() {
-            // TODO
-            gst::fixme!(CAT, "URI credentials are currently ignored");
-        }
-
         match (uri.host_str(), uri.port()) {
             (Some(_), Some(_)) | (Some(_), None) => Ok(()),
             _ => Err(glib::Error::new(gst::URIError::BadUri, "Invalid host")),
@@ -223,6 +286,10 @@ impl RtspSrc {
         let protocols: &[RtspProtocol] = match uri.scheme() {
             "rtspu" => &[RtspProtocol::UdpMulticast, RtspProtocol::Udp],
             "rtspt" => &[RtspProtocol::Tcp],
+            // Media over UDP would not be protected by TLS and we don't support SRTP yet
+            "rtsps" => &[RtspProtocol::Tcp],
+            // The media is interleaved into the HTTP tunnel
+            "rtsph" | "rtspsh" => &[RtspProtocol::Http, RtspProtocol::Tcp],
             "rtsp" => &settings.protocols,
             scheme => {
                 return Err(glib::Error::new(
@@ -308,6 +375,103 @@ impl ObjectImpl for RtspSrc {
                     .default_value(DEFAULT_TIMEOUT.into())
                     .mutable_ready()
                     .build(),
+                /**
+                 * GstRtspSrc2:user-id:
+                 *
+                 * User ID used to authenticate with the server. Credentials in the location take
+                 * precedence over this property.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecString::builder("user-id")
+                    .nick("User ID")
+                    .blurb("RTSP location URI user id for authentication")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:user-pw:
+                 *
+                 * Password used to authenticate with the server. Credentials in the location take
+                 * precedence over this property.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecString::builder("user-pw")
+                    .nick("User Password")
+                    .blurb("RTSP location URI user password for authentication")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:tls-validate-certificate:
+                 *
+                 * Whether to validate the server certificate when connecting to an `rtsps://`
+                 * location.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecBoolean::builder("tls-validate-certificate")
+                    .nick("TLS validate certificate")
+                    .blurb("Validate the certificate of rtsps:// servers")
+                    .default_value(DEFAULT_TLS_VALIDATE_CERTIFICATE)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:tls-ca-file:
+                 *
+                 * PEM file with the certificate authorities used to validate the server
+                 * certificate. The Mozilla root certificates are used when unset.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecString::builder("tls-ca-file")
+                    .nick("TLS CA file")
+                    .blurb("PEM file with the certificate authorities to validate rtsps:// servers with")
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:reconnect-attempts:
+                 *
+                 * Number of times to reconnect to the server when the connection is lost or no
+                 * data was received over UDP for longer than #GstRtspSrc2:timeout. The media is
+                 * set up again, over TCP when UDP did not receive any data. Progress messages
+                 * with the `reconnect` code are posted for each attempt.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecUInt::builder("reconnect-attempts")
+                    .nick("Reconnect attempts")
+                    .blurb("Number of times to try reconnecting to the server (0 = disabled)")
+                    .default_value(DEFAULT_RECONNECT_ATTEMPTS)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:reconnect-max-delay:
+                 *
+                 * The delay between reconnection attempts starts at one second and is doubled
+                 * after each failed attempt, up to this value.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecUInt64::builder("reconnect-max-delay")
+                    .nick("Reconnect maximum delay")
+                    .blurb("Maximum delay between reconnection attempts, in nanoseconds")
+                    .maximum(gst::ClockTime::MAX.into())
+                    .default_value(DEFAULT_RECONNECT_MAX_DELAY.into())
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstRtspSrc2:multicast-iface:
+                 *
+                 * The network interface to join multicast groups on, by name or index. By
+                 * default the system picks the interface.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecString::builder("multicast-iface")
+                    .nick("Multicast interface")
+                    .blurb("Network interface to receive multicast on, e.g. eth0")
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -348,6 +512,41 @@ impl ObjectImpl for RtspSrc {
                 settings.timeout = timeout;
                 Ok(())
             }
+            "user-id" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.user_id = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "user-pw" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.user_pw = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "tls-validate-certificate" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.tls_validate_certificate = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "tls-ca-file" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.tls_ca_file = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "reconnect-attempts" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reconnect_attempts = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "reconnect-max-delay" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reconnect_max_delay = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "multicast-iface" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.multicast_iface = value.get().expect("type checked upstream");
+                Ok(())
+            }
             name => unimplemented!("Property '{name}'"),
         };
 
@@ -392,6 +591,34 @@ impl ObjectImpl for RtspSrc {
                 let settings = self.settings.lock().unwrap();
                 settings.timeout.to_value()
             }
+            "user-id" => {
+                let settings = self.settings.lock().unwrap();
+                settings.user_id.to_value()
+            }
+            "user-pw" => {
+                let settings = self.settings.lock().unwrap();
+                settings.user_pw.to_value()
+            }
+            "tls-validate-certificate" => {
+                let settings = self.settings.lock().unwrap();
+                settings.tls_validate_certificate.to_value()
+            }
+            "tls-ca-file" => {
+                let settings = self.settings.lock().unwrap();
+                settings.tls_ca_file.to_value()
+            }
+            "reconnect-attempts" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reconnect_attempts.to_value()
+            }
+            "reconnect-max-delay" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reconnect_max_delay.to_value()
+            }
+            "multicast-iface" => {
+                let settings = self.settings.lock().unwrap();
+                settings.multicast_iface.to_value()
+            }
             name => unimplemented!("Property '{name}'"),
         }
     }
@@ -453,6 +680,10 @@ impl ElementImpl for RtspSrc {
                 //self.async_start().map_err(|_| gst::StateChangeError)?;
                 RUNTIME.spawn(async move { cmd_queue.send(Commands::Play).await });
             }
+            gst::StateChange::PlayingToPaused => {
+                let cmd_queue = self.cmd_queue();
+                RUNTIME.spawn(async move { cmd_queue.send(Commands::Pause).await });
+            }
             _ => {}
         }
 
@@ -497,6 +728,18 @@ impl ElementImpl for RtspSrc {
 
         Ok(ret)
     }
+
+    fn send_event(&self, event: gst::Event) -> bool {
+        if let gst::EventView::Seek(seek) = event.view() {
+            return self.handle_seek(seek);
+        }
+
+        self.parent_send_event(event)
+    }
+
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        self.handle_query(query) || self.parent_query(query)
+    }
 }
 
 impl BinImpl for RtspSrc {}
@@ -505,7 +748,7 @@ impl URIHandlerImpl for RtspSrc {
     const URI_TYPE: gst::URIType = gst::URIType::Src;
 
     fn protocols() -> &'static [&'static str] {
-        &["rtsp", "rtspu", "rtspt"]
+        &["rtsp", "rtspu", "rtspt", "rtsps", "rtsph", "rtspsh"]
     }
 
     fn uri(&self) -> Option<String> {
@@ -522,6 +765,76 @@ impl URIHandlerImpl for RtspSrc {
 type RtspStream =
     Pin<Box<dyn Stream<Item = Result<Message<Body>, super::tcp_message::ReadError>> + Send>>;
 type RtspSink = Pin<Box<dyn Sink<Message<Body>, Error = std::io::Error> + Send>>;
+type InterleavedAppSrc = (gst_app::AppSrc, Option<Arc<Mutex<RtpStreamState>>>);
+type RtspTls = (
+    tokio_rustls::TlsConnector,
+    rustls_pki_types::ServerName<'static>,
+);
+
+trait AsyncReadWrite: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> AsyncReadWrite for T {}
+
+async fn open(
+    url: &Url,
+    tls: Option<&RtspTls>,
+    default_port: u16,
+) -> Result<Box<dyn AsyncReadWrite>, RtspError> {
+    let hostname_port = format!(
+        "{}:{}",
+        url.host_str().unwrap(),
+        url.port().unwrap_or(default_port)
+    );
+
+    let s = TcpStream::connect(hostname_port).await?;
+    let _ = s.set_nodelay(true);
+
+    gst::info!(CAT, "Connected!");
+
+    let Some((connector, server_name)) = tls else {
+        return Ok(Box::new(s));
+    };
+    let s = connector
+        .connect(server_name.clone(), s)
+        .await
+        .map_err(|err| RtspError::Fatal(format!("TLS handshake failed: {err:#?}")))?;
+    gst::info!(CAT, "TLS handshake complete");
+
+    Ok(Box::new(s))
+}
+
+async fn connect(
+    url: &Url,
+    tls: Option<&RtspTls>,
+    tunnel: bool,
+) -> Result<(RtspStream, RtspSink), RtspError> {
+    gst::info!(CAT, "Connecting to {url} ..");
+
+    if tunnel {
+        let default_port = if tls.is_some() {
+            super::http_tunnel::DEFAULT_HTTPS_PORT
+        } else {
+            super::http_tunnel::DEFAULT_HTTP_PORT
+        };
+        let get = open(url, tls, default_port).await?;
+        let post = open(url, tls, default_port).await?;
+        let (read, write) = super::http_tunnel::start(get, post, url, DEFAULT_USER_AGENT).await?;
+        return Ok((
+            Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse()),
+            Box::pin(super::http_tunnel::async_write(write)),
+        ));
+    }
+
+    let default_port = if tls.is_some() {
+        super::tls::DEFAULT_RTSPS_PORT
+    } else {
+        554
+    };
+    let (read, write) = tokio::io::split(open(url, tls, default_port).await?);
+    Ok((
+        Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse()),
+        Box::pin(super::tcp_message::async_write(write)),
+    ))
+}
 
 impl RtspSrc {
     #[track_caller]
@@ -530,13 +843,47 @@ impl RtspSrc {
     }
 
     fn start(&self) -> Result<(), gst::ErrorMessage> {
-        let Some(url) = self.settings.lock().unwrap().location.clone() else {
+        let settings = self.settings.lock().unwrap().clone();
+        let Some(mut url) = settings.location else {
             return Err(gst::error_msg!(
                 gst::ResourceError::Settings,
                 ["No location set"]
             ));
         };
 
+        // Credentials must not be sent as part of the request URIs
+        let auth = if !url.username().is_empty() {
+            let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+            Some(Authenticator::new(
+                decode(url.username()),
+                url.password().map(decode).unwrap_or_default(),
+            ))
+        } else {
+            settings
+                .user_id
+                .map(|user| Authenticator::new(user, settings.user_pw.unwrap_or_default()))
+        };
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        let tunnel = settings.protocols.contains(&RtspProtocol::Http);
+        let tls = if matches!(url.scheme(), "rtsps" | "rtspsh") {
+            let tls = super::tls::connector(
+                settings.tls_validate_certificate,
+                settings.tls_ca_file.as_deref(),
+            )
+            .and_then(|connector| Ok((connector, super::tls::server_name(&url)?)))
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to configure TLS: {err:?}"]
+                )
+            })?;
+            Some(tls)
+        } else {
+            None
+        };
+
         gst::info!(CAT, imp = self, "Location: {url}",);
 
         gst::info!(CAT, imp = self, "Starting RTSP connection thread.. ");
@@ -553,13 +900,8 @@ impl RtspSrc {
         }
 
         let join_handle = RUNTIME.spawn(async move {
-            gst::info!(CAT, "Connecting to {url} ..");
-            let hostname_port =
-                format!("{}:{}", url.host_str().unwrap(), url.port().unwrap_or(554));
-
-            // TODO: Add TLS support
-            let s = match TcpStream::connect(hostname_port).await {
-                Ok(s) => s,
+            let (stream, sink) = match connect(&url, tls.as_ref(), tunnel).await {
+                Ok(v) => v,
                 Err(err) => {
                     gst::element_imp_error!(
                         task_src,
@@ -569,16 +911,8 @@ impl RtspSrc {
                     return;
                 }
             };
-            let _ = s.set_nodelay(true);
 
-            gst::info!(CAT, "Connected!");
-
-            let (read, write) = s.into_split();
-
-            let stream = Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse());
-            let sink = Box::pin(super::tcp_message::async_write(write));
-
-            let mut state = RtspTaskState::new(url, stream, sink);
+            let mut state = RtspTaskState::new(url, stream, sink, auth, tls, tunnel);
 
             let task_ret = task_src.rtsp_task(&mut state, rx).await;
             gst::info!(CAT, "Exited rtsp_task");
@@ -655,6 +989,7 @@ impl RtspSrc {
         }
 
         self.command_queue.lock().unwrap().take();
+        self.duration.lock().unwrap().take();
 
         gst::info!(CAT, imp = self, "Stopped");
 
@@ -667,6 +1002,16 @@ impl RtspSrc {
         caps: &gst::Caps,
         manager: &RtspManager,
     ) -> Result<gst_app::AppSrc> {
+        // Reconnecting reuses the existing appsrc and ghost pad
+        if let Some(appsrc) = self
+            .obj()
+            .by_name(&format!("rtp_appsrc_{rtpsession_n}"))
+            .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        {
+            appsrc.set_caps(Some(caps));
+            return Ok(appsrc);
+        }
+
         let callbacks = gst_app::AppSrcCallbacks::builder()
             .enough_data(|appsrc| {
                 gst::warning!(CAT, "appsrc {} is overrunning: enough data!", appsrc.name());
@@ -694,6 +1039,25 @@ impl RtspSrc {
         let templ = obj.pad_template("stream_%u").unwrap();
         let ghostpad = gst::GhostPad::builder_from_template(&templ)
             .name(format!("stream_{}", rtpsession_n))
+            .event_function(|pad, parent, event| {
+                RtspSrc::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |imp| {
+                        if let gst::EventView::Seek(seek) = event.view() {
+                            return imp.handle_seek(seek);
+                        }
+                        gst::Pad::event_default(pad, parent, event)
+                    },
+                )
+            })
+            .query_function(|pad, parent, query| {
+                RtspSrc::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |imp| imp.handle_query(query) || gst::Pad::query_default(pad, parent, query),
+                )
+            })
             .build();
         gst::info!(CAT, "Adding ghost srcpad {}", ghostpad.name());
         obj.add_pad(&ghostpad)
@@ -707,6 +1071,14 @@ impl RtspSrc {
         rtpsession_n: usize,
         manager: &RtspManager,
     ) -> Result<gst_app::AppSrc> {
+        if let Some(appsrc) = self
+            .obj()
+            .by_name(&format!("rtcp_appsrc_{rtpsession_n}"))
+            .and_then(|e| e.downcast::<gst_app::AppSrc>().ok())
+        {
+            return Ok(appsrc);
+        }
+
         let appsrc = gst_app::AppSrc::builder()
             .name(format!("rtcp_appsrc_{rtpsession_n}"))
             .format(gst::Format::Time)
@@ -743,6 +1115,16 @@ impl RtspSrc {
             .new_sample(on_rtcp)
             .build();
 
+        // The callbacks send to the tasks of the current connection
+        if let Some(rtcp_appsink) = self
+            .obj()
+            .by_name(&format!("rtcp_appsink_{rtpsession_n}"))
+            .and_then(|e| e.downcast::<gst_app::AppSink>().ok())
+        {
+            rtcp_appsink.set_callbacks(cbs);
+            return Ok(());
+        }
+
         let rtcp_appsink = gst_app::AppSink::builder()
             .name(format!("rtcp_appsink_{rtpsession_n}"))
             .sync(false)
@@ -757,6 +1139,98 @@ impl RtspSrc {
         Ok(())
     }
 
+    fn handle_query(&self, query: &mut gst::QueryRef) -> bool {
+        match query.view_mut() {
+            gst::QueryViewMut::Duration(q) if q.format() == gst::Format::Time => {
+                let Some(duration) = *self.duration.lock().unwrap() else {
+                    return false;
+                };
+                q.set(duration);
+                true
+            }
+            gst::QueryViewMut::Seeking(q) if q.format() == gst::Format::Time => {
+                let duration = *self.duration.lock().unwrap();
+                q.set(duration.is_some(), Some(gst::ClockTime::ZERO), duration);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn position(&self) -> Option<gst::ClockTime> {
+        self.obj()
+            .src_pads()
+            .iter()
+            .find_map(|pad| pad.peer_query_position::<gst::ClockTime>())
+    }
+
+    /// Flushing seeks are translated into a PAUSE followed by a PLAY with the new range. The
+    /// seek rate is requested with a Scale header for trick modes and reverse playback, and with
+    /// a Speed header otherwise.
+    fn handle_seek(&self, seek: &gst::event::Seek) -> bool {
+        let Some(duration) = *self.duration.lock().unwrap() else {
+            gst::debug!(CAT, imp = self, "Live stream, not seekable");
+            return false;
+        };
+
+        let (rate, flags, start_type, start, stop_type, stop) = seek.get();
+        if !flags.contains(gst::SeekFlags::FLUSH) {
+            gst::warning!(CAT, imp = self, "Only flushing seeks are supported");
+            return false;
+        }
+        let (gst::GenericFormattedValue::Time(start), gst::GenericFormattedValue::Time(stop)) =
+            (start, stop)
+        else {
+            gst::warning!(CAT, imp = self, "Only seeks in TIME format are supported");
+            return false;
+        };
+        let Some(cmd_queue) = self.command_queue.lock().unwrap().clone() else {
+            return false;
+        };
+
+        let start = match start_type {
+            gst::SeekType::Set => start.unwrap_or(gst::ClockTime::ZERO),
+            gst::SeekType::End => duration,
+            _ => self.position().unwrap_or(gst::ClockTime::ZERO),
+        };
+        let stop = match stop_type {
+            gst::SeekType::Set => stop,
+            _ => None,
+        };
+        let range = PlayRange {
+            start: start.min(duration),
+            stop: stop.map(|stop| stop.min(duration)),
+            rate,
+            trickmode: flags.intersects(gst::SeekFlags::TRICKMODE),
+        };
+        gst::debug!(CAT, imp = self, "Seeking to {range:?}");
+
+        let appsrcs = self
+            .obj()
+            .children()
+            .into_iter()
+            .filter(|e| e.name().starts_with("rtp_appsrc_"))
+            .collect::<Vec<_>>();
+        for appsrc in &appsrcs {
+            appsrc.send_event(
+                gst::event::FlushStart::builder()
+                    .seqnum(seek.seqnum())
+                    .build(),
+            );
+        }
+        for appsrc in &appsrcs {
+            appsrc.send_event(
+                gst::event::FlushStop::builder(true)
+                    .seqnum(seek.seqnum())
+                    .build(),
+            );
+        }
+
+        RUNTIME.spawn(async move { cmd_queue.send(Commands::Seek(range)).await });
+
+        true
+    }
+
     fn post_start(&self, code: &str, text: &str) {
         let obj = self.obj();
         let msg = gst::message::Progress::builder(gst::ProgressType::Start, code, text)
@@ -781,39 +1255,20 @@ impl RtspSrc {
         let _ = obj.post_message(msg);
     }
 
-    async fn rtsp_task(
+    /// Create the appsrcs and spawn the tasks receiving the data of each stream. Returns the
+    /// appsrcs of the TCP interleaved channels.
+    fn start_streams(
         &self,
         state: &mut RtspTaskState,
-        mut cmd_rx: mpsc::Receiver<Commands>,
-    ) -> Result<()> {
+        manager: &RtspManager,
+        settings: &Settings,
+    ) -> Result<HashMap<u8, InterleavedAppSrc>> {
         let cmd_tx = self.cmd_queue();
-
-        let settings = { self.settings.lock().unwrap().clone() };
-
-        // OPTIONS
-        state.options().await?;
-
-        // DESCRIBE
-        state.describe().await?;
-
-        let mut session: Option<Session> = None;
-        // SETUP streams (TCP interleaved)
-        state.setup_params = {
-            state
-                .setup(
-                    &mut session,
-                    settings.port_start,
-                    &settings.protocols,
-                    TransportMode::Play,
-                )
-                .await?
+        let (timeout, receive_mtu) = (settings.timeout, settings.receive_mtu);
+        let iface = match &settings.multicast_iface {
+            Some(iface) => super::multicast::interface_index(iface)?,
+            None => 0,
         };
-        let manager = RtspManager::new(std::env::var("USE_RTP2").is_ok_and(|s| s == "1"));
-
-        let obj = self.obj();
-        manager
-            .add_to(obj.upcast_ref::<gst::Bin>())
-            .expect("Adding the manager cannot fail");
 
         let mut tcp_interleave_appsrcs = HashMap::new();
         for (rtpsession_n, p) in state.setup_params.iter_mut().enumerate() {
@@ -837,15 +1292,23 @@ impl RtspSrc {
 
                     match &dest {
                         IpAddr::V4(addr) => {
-                            rtp_socket.join_multicast_v4(*addr, Ipv4Addr::UNSPECIFIED)?;
+                            super::multicast::join(
+                                &rtp_socket,
+                                *dest,
+                                &p.multicast_sources,
+                                iface,
+                            )?;
                             if let Some(ttl) = ttl {
                                 let _ = rtp_socket.set_multicast_ttl_v4(*ttl as u32);
                             }
                             let _ = rtp_socket.set_multicast_loop_v4(false);
                             if let Some(rtcp_socket) = &rtcp_socket {
-                                if let Err(err) =
-                                    rtcp_socket.join_multicast_v4(*addr, Ipv4Addr::UNSPECIFIED)
-                                {
+                                if let Err(err) = super::multicast::join(
+                                    rtcp_socket,
+                                    *dest,
+                                    &p.multicast_sources,
+                                    iface,
+                                ) {
                                     gst::warning!(
                                         CAT,
                                         "Failed to join RTCP multicast address {addr}: {err:?}"
@@ -859,10 +1322,20 @@ impl RtspSrc {
                             }
                         }
                         IpAddr::V6(addr) => {
-                            rtp_socket.join_multicast_v6(addr, 0)?;
+                            super::multicast::join(
+                                &rtp_socket,
+                                *dest,
+                                &p.multicast_sources,
+                                iface,
+                            )?;
                             let _ = rtp_socket.set_multicast_loop_v6(false);
                             if let Some(rtcp_socket) = &rtcp_socket {
-                                if let Err(err) = rtcp_socket.join_multicast_v6(addr, 0) {
+                                if let Err(err) = super::multicast::join(
+                                    rtcp_socket,
+                                    *dest,
+                                    &p.multicast_sources,
+                                    iface,
+                                ) {
                                     gst::warning!(
                                         CAT,
                                         "Failed to join RTCP multicast address {addr}: {err:?}"
@@ -874,15 +1347,19 @@ impl RtspSrc {
                         }
                     };
 
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
+                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
                     p.rtp_appsrc = Some(rtp_appsrc.clone());
+                    let stream_state = p.stream_state.clone();
+                    let cmd_tx = cmd_tx.clone();
                     // Spawn RTP udp receive task
                     state.handles.push(RUNTIME.spawn(async move {
                         udp_rtp_task(
                             &rtp_socket,
                             rtp_appsrc,
-                            settings.timeout,
-                            settings.receive_mtu,
+                            stream_state,
+                            cmd_tx,
+                            timeout,
+                            receive_mtu,
                             None,
                         )
                         .await
@@ -891,8 +1368,8 @@ impl RtspSrc {
                     // Spawn RTCP udp send/recv task
                     if let Some(rtcp_socket) = rtcp_socket {
                         let rtcp_dest = rtcp_port.and_then(|p| Some(SocketAddr::new(*dest, p)));
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
-                        self.make_rtcp_appsink(rtpsession_n, &manager, on_rtcp)?;
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
+                        self.make_rtcp_appsink(rtpsession_n, manager, on_rtcp)?;
                         state.handles.push(RUNTIME.spawn(async move {
                             udp_rtcp_task(&rtcp_socket, rtcp_appsrc, rtcp_dest, true, rx).await
                         }));
@@ -927,14 +1404,18 @@ impl RtspSrc {
                     };
 
                     // Spawn RTP udp receive task
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
+                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
                     p.rtp_appsrc = Some(rtp_appsrc.clone());
+                    let stream_state = p.stream_state.clone();
+                    let cmd_tx = cmd_tx.clone();
                     state.handles.push(RUNTIME.spawn(async move {
                         udp_rtp_task(
                             &rtp_socket,
                             rtp_appsrc,
-                            settings.timeout,
-                            settings.receive_mtu,
+                            stream_state,
+                            cmd_tx,
+                            timeout,
+                            receive_mtu,
                             rtp_sender_addr,
                         )
                         .await
@@ -942,8 +1423,8 @@ impl RtspSrc {
 
                     // Spawn RTCP udp send/recv task
                     if let Some(rtcp_socket) = rtcp_socket {
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
-                        self.make_rtcp_appsink(rtpsession_n, &manager, on_rtcp)?;
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
+                        self.make_rtcp_appsink(rtpsession_n, manager, on_rtcp)?;
                         state.handles.push(RUNTIME.spawn(async move {
                             udp_rtcp_task(&rtcp_socket, rtcp_appsrc, rtcp_sender_addr, false, rx)
                                 .await
@@ -953,18 +1434,19 @@ impl RtspSrc {
                 RtspTransportInfo::Tcp {
                     channels: (rtp_channel, rtcp_channel),
                 } => {
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
+                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
                     p.rtp_appsrc = Some(rtp_appsrc.clone());
-                    tcp_interleave_appsrcs.insert(*rtp_channel, rtp_appsrc);
+                    tcp_interleave_appsrcs
+                        .insert(*rtp_channel, (rtp_appsrc, Some(p.stream_state.clone())));
 
                     if let Some(rtcp_channel) = rtcp_channel {
                         // RTCP SR
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
-                        tcp_interleave_appsrcs.insert(*rtcp_channel, rtcp_appsrc.clone());
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
+                        tcp_interleave_appsrcs.insert(*rtcp_channel, (rtcp_appsrc.clone(), None));
                         // RTCP RR
                         let rtcp_channel = *rtcp_channel;
                         let cmd_tx = cmd_tx.clone();
-                        self.make_rtcp_appsink(rtpsession_n, &manager, move |appsink| {
+                        self.make_rtcp_appsink(rtpsession_n, manager, move |appsink| {
                             on_rtcp_tcp(appsink, cmd_tx.clone(), rtcp_channel)
                         })?;
                     }
@@ -972,6 +1454,56 @@ impl RtspSrc {
             }
         }
 
+        Ok(tcp_interleave_appsrcs)
+    }
+
+    async fn rtsp_task(
+        &self,
+        state: &mut RtspTaskState,
+        mut cmd_rx: mpsc::Receiver<Commands>,
+    ) -> Result<()> {
+        let settings = { self.settings.lock().unwrap().clone() };
+
+        // OPTIONS
+        state.options().await?;
+
+        // DESCRIBE
+        state.describe().await?;
+
+        if let Some(duration) = state.duration {
+            gst::info!(CAT, imp = self, "On-demand media with duration {duration}");
+            *self.duration.lock().unwrap() = Some(duration);
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::DurationChanged::builder().src(&*obj).build());
+        }
+
+        let mut session: Option<Session> = None;
+        // Tunnelled media can only be interleaved into the tunnel
+        let mut protocols = if state.tunnel {
+            vec![RtspProtocol::Tcp]
+        } else {
+            settings.protocols.clone()
+        };
+        // SETUP streams (TCP interleaved)
+        state.setup_params = {
+            state
+                .setup(
+                    &mut session,
+                    settings.port_start,
+                    &protocols,
+                    TransportMode::Play,
+                )
+                .await?
+        };
+        let manager = RtspManager::new(std::env::var("USE_RTP2").is_ok_and(|s| s == "1"));
+
+        let obj = self.obj();
+        manager
+            .add_to(obj.upcast_ref::<gst::Bin>())
+            .expect("Adding the manager cannot fail");
+
+        let mut tcp_interleave_appsrcs = self.start_streams(state, &manager, &settings)?;
+
         obj.no_more_pads();
 
         // Expose RTP srcpads
@@ -1020,101 +1552,350 @@ impl RtspSrc {
             }
         });
 
-        let mut expected_response: Option<(Method, u32)> = None;
+        // Responses arrive in the order the requests were sent
+        let mut expected_response: VecDeque<(Method, u32)> = VecDeque::new();
+        let mut playing = false;
+        let mut keepalive = keepalive_interval(state.session_timeout);
+        // Set when the connection to the server or the UDP data flow was lost, along with
+        // whether the media should be set up again over TCP
+        let mut lost: Option<(String, bool)> = None;
         loop {
-            tokio::select! {
-                msg = state.stream.next() => match msg {
-                    Some(Ok(rtsp_types::Message::Data(data))) => {
-                        let Some(appsrc) = tcp_interleave_appsrcs.get(&data.channel_id()) else {
-                            gst::warning!(CAT,
-                                "ignored data of size {}: unknown channel {}",
-                                data.len(),
-                                data.channel_id()
-                            );
-                            continue;
-                        };
-                        let t = appsrc.current_running_time();
-                        let channel_id = data.channel_id();
-                        gst::trace!(CAT, "Received data on channel {channel_id}");
-                        // TODO: this should be from_mut_slice() after making the necessary
-                        // modifications to Body
-                        let mut buffer = gst::Buffer::from_slice(data.into_body());
-                        let bufref = buffer.make_mut();
-                        bufref.set_dts(t);
-                        // TODO: Allow unlinked source pads
-                        if let Err(err) = appsrc.push_buffer(buffer) {
-                            gst::error!(CAT, "Failed to push buffer on pad {} for channel {}", appsrc.name(), channel_id);
-                            return Err(err.into());
+            // Already set if the connection was lost again right after reconnecting
+            if lost.is_none() {
+                tokio::select! {
+                    msg = state.stream.next() => match msg {
+                        Some(Ok(rtsp_types::Message::Data(data))) => {
+                            let Some((appsrc, stream_state)) = tcp_interleave_appsrcs.get(&data.channel_id()) else {
+                                gst::warning!(CAT,
+                                    "ignored data of size {}: unknown channel {}",
+                                    data.len(),
+                                    data.channel_id()
+                                );
+                                continue;
+                            };
+                            let t = appsrc.current_running_time();
+                            let channel_id = data.channel_id();
+                            gst::trace!(CAT, "Received data on channel {channel_id}");
+                            // TODO: this should be from_mut_slice() after making the necessary
+                            // modifications to Body
+                            let mut buffer = gst::Buffer::from_slice(data.into_body());
+                            let bufref = buffer.make_mut();
+                            bufref.set_dts(t);
+                            let ret = match stream_state {
+                                Some(stream_state) => push_rtp_buffer(appsrc, stream_state, buffer),
+                                None => appsrc.push_buffer(buffer),
+                            };
+                            // TODO: Allow unlinked source pads
+                            if let Err(err) = ret {
+                                gst::error!(CAT, "Failed to push buffer on pad {} for channel {}", appsrc.name(), channel_id);
+                                return Err(err.into());
+                            }
                         }
-                    }
-                    Some(Ok(rtsp_types::Message::Request(req))) => {
-                        // TODO: implement incoming GET_PARAMETER requests
-                        gst::debug!(CAT, "<-- {req:#?}");
-                    }
-                    Some(Ok(rtsp_types::Message::Response(rsp))) => {
-                        gst::debug!(CAT, "<-- {rsp:#?}");
-                        let Some((expected, cseq)) = &expected_response else {
-                            continue;
-                        };
-                        let Some(s) = &session else {
-                            return Err(RtspError::Fatal(format!("Can't handle {:?} response, no SETUP", expected)).into());
-                        };
-                        match expected {
-                            Method::Play => {
-                                state.play_response(&rsp, *cseq, s).await?;
-                                self.post_complete("request", "PLAY response received");
+                        Some(Ok(rtsp_types::Message::Request(req))) => {
+                            gst::debug!(CAT, "<-- {req:#?}");
+                            check_connection_lost(state.reply(&req, session.as_ref()).await, &mut lost)?;
+                        }
+                        Some(Ok(rtsp_types::Message::Response(rsp))) => {
+                            gst::debug!(CAT, "<-- {rsp:#?}");
+                            let Some((expected, cseq)) = expected_response.pop_front() else {
+                                continue;
+                            };
+                            let Some(s) = &session else {
+                                return Err(RtspError::Fatal(format!("Can't handle {:?} response, no SETUP", expected)).into());
+                            };
+                            if rsp.status() == StatusCode::Unauthorized && expected != Method::Teardown {
+                                state.handle_unauthorized(&rsp)?;
+                                let res = match expected {
+                                    Method::Pause => state.pause(s).await.map(|cseq| (Method::Pause, cseq)),
+                                    Method::Play => state.play(s).await.map(|cseq| (Method::Play, cseq)),
+                                    _ => state.keepalive(s).await,
+                                };
+                                if let Some(request) = check_connection_lost(res, &mut lost)? {
+                                    expected_response.push_back(request);
+                                }
+                                continue;
                             }
-                            Method::Teardown => state.teardown_response(&rsp, *cseq, s).await?,
-                            m => unreachable!("BUG: unexpected response method: {m:?}"),
-                        };
-                    }
-                    Some(Err(e)) => {
-                        // TODO: reconnect or ignore if UDP sockets are still receiving data
-                        gst::error!(CAT, "I/O error: {e:?}, quitting");
-                        return Err(gst::FlowError::Error.into());
-                    }
-                    None => {
-                        // TODO: reconnect or ignore if UDP sockets are still receiving data
-                        gst::error!(CAT, "TCP connection EOF, quitting");
-                        return Err(gst::FlowError::Eos.into());
-                    }
-                },
-                Some(cmd) = cmd_rx.recv() => match cmd {
-                    Commands::Play => {
-                        let Some(s) = &session else {
-                            return Err(RtspError::InvalidMessage("Can't PLAY, no SETUP").into());
-                        };
-                        self.post_start("request", "PLAY request sent");
-                        let cseq = state.play(s).await.map_err(|err| {
-                            self.post_cancelled("request", "PLAY request cancelled");
-                            err
-                        })?;
-                        expected_response = Some((Method::Play, cseq));
+                            match expected {
+                                Method::Play => {
+                                    state.play_response(&rsp, cseq, s).await?;
+                                    self.post_complete("request", "PLAY response received");
+                                }
+                                Method::Pause => {
+                                    state.pause_response(&rsp, cseq, s).await?;
+                                    self.post_complete("request", "PAUSE response received");
+                                    // Continue the seek once the server stopped sending data
+                                    if state.seek_pending && playing {
+                                        self.post_start("request", "PLAY request sent");
+                                        if let Some(cseq) = check_connection_lost(state.play(s).await, &mut lost)? {
+                                            expected_response.push_back((Method::Play, cseq));
+                                        }
+                                    }
+                                }
+                                Method::GetParameter | Method::Options => {
+                                    // Servers that don't implement the method still refresh the session
+                                    if let Err(err) = RtspTaskState::check_response(&rsp, cseq, expected, Some(s)) {
+                                        gst::warning!(CAT, "Keep-alive request failed: {err:?}");
+                                    }
+                                }
+                                Method::Teardown => state.teardown_response(&rsp, cseq, s).await?,
+                                m => unreachable!("BUG: unexpected response method: {m:?}"),
+                            };
+                        }
+                        Some(Err(e)) => lost = Some((format!("I/O error: {e:?}"), false)),
+                        None => lost = Some(("TCP connection EOF".to_string(), false)),
                     },
-                    Commands::Teardown(tx) => {
-                        gst::info!(CAT, "Received Teardown command");
+                    _ = keepalive.tick() => {
                         let Some(s) = &session else {
-                            return Err(RtspError::InvalidMessage("Can't TEARDOWN, no SETUP").into());
+                            continue;
                         };
-                        let _ = state.teardown(s).await;
-                        if let Some(tx) = tx {
-                            let _ = tx.send(());
+                        if let Some(request) = check_connection_lost(state.keepalive(s).await, &mut lost)? {
+                            expected_response.push_back(request);
                         }
-                        break;
                     }
-                    Commands::Data(data) => {
-                        // We currently only send RTCP RR as data messages, this will change when
-                        // we support TCP ONVIF backchannels
-                        state.sink.send(Message::Data(data)).await?;
-                        gst::debug!(CAT, "Sent RTCP RR over TCP");
+                    Some(cmd) = cmd_rx.recv() => match cmd {
+                        Commands::Play => {
+                            let Some(s) = &session else {
+                                return Err(RtspError::InvalidMessage("Can't PLAY, no SETUP").into());
+                            };
+                            self.post_start("request", "PLAY request sent");
+                            let res = state.play(s).await.map_err(|err| {
+                                self.post_cancelled("request", "PLAY request cancelled");
+                                err
+                            });
+                            // Sent again after reconnecting
+                            if let Some(cseq) = check_connection_lost(res, &mut lost)? {
+                                expected_response.push_back((Method::Play, cseq));
+                            }
+                            playing = true;
+                        },
+                        Commands::Pause => {
+                            // Live streams keep running, like with other live sources
+                            if state.duration.is_none() || !playing {
+                                continue;
+                            }
+                            let Some(s) = &session else {
+                                return Err(RtspError::InvalidMessage("Can't PAUSE, no SETUP").into());
+                            };
+                            self.post_start("request", "PAUSE request sent");
+                            let res = state.pause(s).await.map_err(|err| {
+                                self.post_cancelled("request", "PAUSE request cancelled");
+                                err
+                            });
+                            if let Some(cseq) = check_connection_lost(res, &mut lost)? {
+                                expected_response.push_back((Method::Pause, cseq));
+                            }
+                            playing = false;
+                        }
+                        Commands::Seek(range) => {
+                            let Some(s) = &session else {
+                                return Err(RtspError::InvalidMessage("Can't seek, no SETUP").into());
+                            };
+                            state.play_range = range;
+                            state.seek_pending = true;
+                            // Otherwise the range is requested with the next PLAY
+                            if playing {
+                                self.post_start("request", "PAUSE request sent");
+                                if let Some(cseq) = check_connection_lost(state.pause(s).await, &mut lost)? {
+                                    expected_response.push_back((Method::Pause, cseq));
+                                }
+                            }
+                        }
+                        Commands::Teardown(tx) => {
+                            gst::info!(CAT, "Received Teardown command");
+                            let Some(s) = &session else {
+                                return Err(RtspError::InvalidMessage("Can't TEARDOWN, no SETUP").into());
+                            };
+                            let _ = state.teardown(s).await;
+                            if let Some(tx) = tx {
+                                let _ = tx.send(());
+                            }
+                            break;
+                        }
+                        Commands::Data(data) => {
+                            // We currently only send RTCP RR as data messages, this will change when
+                            // we support TCP ONVIF backchannels
+                            let res = state.sink.send(Message::Data(data)).await.map_err(RtspError::from);
+                            if check_connection_lost(res, &mut lost)?.is_some() {
+                                gst::debug!(CAT, "Sent RTCP RR over TCP");
+                            }
+                        }
+                        Commands::ReceiveTimeout(err) => lost = Some((err, true)),
+                    },
+                    else => {
+                        gst::error!(CAT, "No select statement matched, breaking loop");
+                        break;
                     }
-                },
-                else => {
-                    gst::error!(CAT, "No select statement matched, breaking loop");
-                    break;
                 }
             }
+
+            let Some((reason, udp_timeout)) = lost.take() else {
+                continue;
+            };
+            if settings.reconnect_attempts == 0 {
+                gst::error!(CAT, "{reason}, quitting");
+                return Err(RtspError::Fatal(reason).into());
+            }
+            gst::warning!(CAT, "{reason}, reconnecting");
+
+            if udp_timeout {
+                // The connection is still up, so the server can free the session right away
+                if let Some(s) = &session {
+                    let _ = state.teardown(s).await;
+                }
+                if protocols.contains(&RtspProtocol::Tcp) && protocols.len() > 1 {
+                    gst::element_imp_warning!(
+                        self,
+                        gst::ResourceError::Read,
+                        ["{reason}, maybe a firewall is blocking UDP, retrying over TCP"]
+                    );
+                    protocols = vec![RtspProtocol::Tcp];
+                }
+            }
+
+            // Resume on-demand media where it stopped
+            if state.duration.is_some() && !state.seek_pending {
+                if let Some(position) = self.position() {
+                    state.play_range.start = position;
+                    state.seek_pending = true;
+                }
+            }
+
+            if !self
+                .reconnect(
+                    state,
+                    &mut session,
+                    &protocols,
+                    &settings,
+                    &mut cmd_rx,
+                    &mut playing,
+                )
+                .await?
+            {
+                break;
+            }
+            tcp_interleave_appsrcs = self.start_streams(state, &manager, &settings)?;
+            expected_response.clear();
+            keepalive = keepalive_interval(state.session_timeout);
+
+            if playing {
+                let Some(s) = &session else {
+                    return Err(RtspError::InvalidMessage("Can't PLAY, no SETUP").into());
+                };
+                self.post_start("request", "PLAY request sent");
+                if let Some(cseq) = check_connection_lost(state.play(s).await, &mut lost)? {
+                    expected_response.push_back((Method::Play, cseq));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the media up again on a new connection, retrying with an exponential backoff.
+    /// Returns false if the element was stopped meanwhile.
+    async fn reconnect(
+        &self,
+        state: &mut RtspTaskState,
+        session: &mut Option<Session>,
+        protocols: &[RtspProtocol],
+        settings: &Settings,
+        cmd_rx: &mut mpsc::Receiver<Commands>,
+        playing: &mut bool,
+    ) -> Result<bool, RtspError> {
+        for h in state.handles.drain(..) {
+            h.abort();
+            let _ = h.await;
+        }
+
+        let max_delay = Duration::from(settings.reconnect_max_delay);
+        let mut delay = RECONNECT_INITIAL_DELAY.min(max_delay);
+        for attempt in 1..=settings.reconnect_attempts {
+            self.post_start(
+                "reconnect",
+                &format!(
+                    "Reconnecting, attempt {attempt} of {}",
+                    settings.reconnect_attempts
+                ),
+            );
+
+            // Keep handling commands while waiting so that the element can be stopped
+            let sleep = time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    cmd = cmd_rx.recv() => match cmd {
+                        Some(Commands::Teardown(tx)) => {
+                            if let Some(tx) = tx {
+                                let _ = tx.send(());
+                            }
+                            self.post_cancelled("reconnect", "Reconnection cancelled");
+                            return Ok(false);
+                        }
+                        None => return Ok(false),
+                        Some(Commands::Play) => *playing = true,
+                        Some(Commands::Pause) => *playing = false,
+                        Some(Commands::Seek(range)) => {
+                            state.play_range = range;
+                            state.seek_pending = true;
+                        }
+                        // Belongs to the lost connection
+                        Some(Commands::Data(_)) | Some(Commands::ReceiveTimeout(_)) => {}
+                    },
+                }
+            }
+
+            match self
+                .try_reconnect(state, session, protocols, settings)
+                .await
+            {
+                Ok(()) => {
+                    self.post_complete("reconnect", "Reconnected");
+                    return Ok(true);
+                }
+                Err(err) => {
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Reconnection attempt {attempt} failed: {err:?}"
+                    );
+                }
+            }
+            delay = (delay * 2).min(max_delay);
+        }
+
+        self.post_cancelled("reconnect", "Giving up reconnecting");
+        Err(RtspError::Fatal(format!(
+            "Failed to reconnect after {} attempts",
+            settings.reconnect_attempts
+        )))
+    }
+
+    async fn try_reconnect(
+        &self,
+        state: &mut RtspTaskState,
+        session: &mut Option<Session>,
+        protocols: &[RtspProtocol],
+        settings: &Settings,
+    ) -> Result<(), RtspError> {
+        let (stream, sink) = connect(&state.url, state.tls.as_ref(), state.tunnel).await?;
+        state.stream = stream;
+        state.sink = sink;
+
+        state.options().await?;
+        state.describe().await?;
+
+        *session = None;
+        let setup_params = state
+            .setup(session, settings.port_start, protocols, TransportMode::Play)
+            .await?;
+        // The appsrcs and source pads are reused, so the streams must not change
+        if setup_params.len() != state.setup_params.len() {
+            return Err(RtspError::Fatal(
+                "The streams of the media changed after reconnecting".to_string(),
+            ));
         }
+        state.setup_params = setup_params;
+
         Ok(())
     }
 }
@@ -1214,10 +1995,21 @@ struct RtspTaskState {
     content_base_or_location: Option<String>,
     aggregate_control: Option<Url>,
     sdp: Option<sdp_types::Session>,
+    duration: Option<gst::ClockTime>,
+    play_range: PlayRange,
+    seek_pending: bool,
+    seek_cseq: Option<u32>,
 
     stream:
         Pin<Box<dyn Stream<Item = Result<Message<Body>, super::tcp_message::ReadError>> + Send>>,
     sink: Pin<Box<dyn Sink<Message<Body>, Error = std::io::Error> + Send>>,
+    auth: Option<Authenticator>,
+    tls: Option<RtspTls>,
+    // RTSP-over-HTTP, the media is always interleaved then
+    tunnel: bool,
+    get_parameter_supported: bool,
+    // In seconds, from the Session header of the SETUP response
+    session_timeout: Option<u64>,
 
     setup_params: Vec<RtspSetupParams>,
     handles: Vec<JoinHandle<()>>,
@@ -1226,12 +2018,31 @@ struct RtspTaskState {
 struct RtspSetupParams {
     control_url: Url,
     transport: RtspTransportInfo,
+    // Source-specific multicast sources, from the SDP
+    multicast_sources: Vec<IpAddr>,
     rtp_appsrc: Option<gst_app::AppSrc>,
+    stream_state: Arc<Mutex<RtpStreamState>>,
     caps: gst::Caps,
 }
 
+/// State shared with the task receiving the RTP data of a stream
+#[derive(Debug, Default)]
+struct RtpStreamState {
+    // The server doesn't send data while paused, so don't time out
+    paused: bool,
+    // Segment to push with the next buffer after a seek
+    segment: Option<gst::FormattedSegment<gst::ClockTime>>,
+}
+
 impl RtspTaskState {
-    fn new(url: Url, stream: RtspStream, sink: RtspSink) -> Self {
+    fn new(
+        url: Url,
+        stream: RtspStream,
+        sink: RtspSink,
+        auth: Option<Authenticator>,
+        tls: Option<RtspTls>,
+        tunnel: bool,
+    ) -> Self {
         RtspTaskState {
             cseq: 0u32,
             url,
@@ -1239,8 +2050,17 @@ impl RtspTaskState {
             content_base_or_location: None,
             aggregate_control: None,
             sdp: None,
+            duration: None,
+            play_range: PlayRange::default(),
+            seek_pending: false,
+            seek_cseq: None,
             stream,
             sink,
+            auth,
+            tls,
+            tunnel,
+            get_parameter_supported: false,
+            session_timeout: None,
             setup_params: Vec::new(),
             handles: Vec::new(),
         }
@@ -1298,6 +2118,54 @@ impl RtspTaskState {
         Ok(())
     }
 
+    /// Pick the challenge of a `401 Unauthorized` response to authorize the next requests with
+    fn handle_unauthorized(&mut self, rsp: &Response<Body>) -> Result<(), RtspError> {
+        match &mut self.auth {
+            Some(auth) => auth.handle_unauthorized(rsp),
+            None => Err(RtspError::Unauthorized(
+                "Server requires authentication but no credentials were set".to_string(),
+            )),
+        }
+    }
+
+    fn authorize(&mut self, req: &mut Request<Body>) {
+        if let Some(auth) = &mut self.auth {
+            auth.authorize(req);
+        }
+    }
+
+    /// Send a request and wait for its response. If the server requires authentication, the
+    /// request is sent again with a new CSeq and the credentials.
+    async fn send_request(
+        &mut self,
+        mut req: Request<Body>,
+        expected: &'static str,
+    ) -> Result<Response<Body>, RtspError> {
+        loop {
+            self.authorize(&mut req);
+            gst::debug!(CAT, "-->> {:#?}", Redacted(&req));
+            self.sink.send(req.clone().into()).await?;
+
+            let rsp = match self.stream.next().await {
+                Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
+                Some(Ok(m)) => Err(RtspError::UnexpectedMessage(expected, m)),
+                Some(Err(e)) => Err(e.into()),
+                None => {
+                    Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, expected).into())
+                }
+            }?;
+
+            if rsp.status() != StatusCode::Unauthorized {
+                return Ok(rsp);
+            }
+
+            gst::debug!(CAT, "<<-- {rsp:#?}");
+            self.handle_unauthorized(&rsp)?;
+            self.cseq += 1;
+            req.insert_header(CSEQ, self.cseq.to_string());
+        }
+    }
+
     async fn options(&mut self) -> Result<(), RtspError> {
         self.cseq += 1;
         let req = Request::builder(Method::Options, self.version)
@@ -1306,17 +2174,7 @@ impl RtspTaskState {
             .header(USER_AGENT, DEFAULT_USER_AGENT)
             .build(Body::default());
 
-        gst::debug!(CAT, "-->> {req:#?}");
-        self.sink.send(req.into()).await?;
-
-        let rsp = match self.stream.next().await {
-            Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-            Some(Ok(m)) => Err(RtspError::UnexpectedMessage("OPTIONS response", m)),
-            Some(Err(e)) => Err(e.into()),
-            None => Err(
-                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "options response").into(),
-            ),
-        }?;
+        let rsp = self.send_request(req, "OPTIONS response").await?;
         gst::debug!(CAT, "<<-- {rsp:#?}");
         Self::check_response(&rsp, self.cseq, Method::Options, None)?;
 
@@ -1332,6 +2190,8 @@ impl RtspTaskState {
             Method::Play,
             Method::Teardown,
         ];
+        self.get_parameter_supported = methods.contains(&Method::GetParameter);
+
         let mut unsupported = Vec::new();
         for method in &needed {
             if !methods.contains(method) {
@@ -1358,19 +2218,7 @@ impl RtspTaskState {
             .request_uri(self.url.clone())
             .build(Body::default());
 
-        gst::debug!(CAT, "-->> {req:#?}");
-        self.sink.send(req.into()).await?;
-
-        let rsp = match self.stream.next().await {
-            Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-            Some(Ok(m)) => Err(RtspError::UnexpectedMessage("DESCRIBE response", m)),
-            Some(Err(e)) => Err(e.into()),
-            None => Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "describe response",
-            )
-            .into()),
-        }?;
+        let rsp = self.send_request(req, "DESCRIBE response").await?;
         gst::debug!(
             CAT,
             "<<-- Response {:#?}",
@@ -1384,10 +2232,29 @@ impl RtspTaskState {
             .map(|v| v.to_string());
 
         gst::info!(CAT, "{}", std::str::from_utf8(rsp.body()).unwrap());
-        // TODO: read range attribute from SDP for VOD use-cases
         let sdp = sdp_types::Session::parse(rsp.body())?;
         gst::debug!(CAT, "{sdp:#?}");
 
+        // The range is usually a session attribute, but can also be given per media
+        let range = sdp
+            .get_first_attribute_value("range")
+            .ok()
+            .flatten()
+            .or_else(|| {
+                sdp.medias
+                    .iter()
+                    .find_map(|m| m.get_first_attribute_value("range").ok().flatten())
+            });
+        self.duration = range
+            .and_then(|range| {
+                let parsed = sdp::parse_range(range);
+                if parsed.is_none() {
+                    gst::warning!(CAT, "Could not parse range {range}, ignoring");
+                }
+                parsed
+            })
+            .and_then(|range| range.duration());
+
         self.sdp.replace(sdp);
         Ok(())
     }
@@ -1446,7 +2313,8 @@ impl RtspTaskState {
         protocols: &[RtspProtocol],
         mode: TransportMode,
     ) -> Result<Vec<RtspSetupParams>, RtspError> {
-        let sdp = self.sdp.as_ref().expect("Must have SDP by now");
+        // Owned so that requests can be sent while iterating over the medias
+        let sdp = self.sdp.clone().expect("Must have SDP by now");
         let base = self
             .content_base_or_location
             .as_ref()
@@ -1460,17 +2328,21 @@ impl RtspTaskState {
             .and_then(|v| sdp::parse_control_path(v, &base));
         let mut b = gst::Structure::builder("application/x-rtp");
 
-        // TODO: parse range for VOD
-        let skip_attrs = ["control", "range"];
+        // The range was already parsed from the DESCRIBE response
+        let skip_attrs = ["control", "range", "extmap", "source-filter"];
         for sdp_types::Attribute { attribute, value } in &sdp.attributes {
             if skip_attrs.contains(&attribute.as_str()) {
                 continue;
             }
             b = b.field(format!("a-{attribute}"), value);
         }
-        // TODO: parse global extmap
 
-        let message_structure = b.build();
+        let mut message_structure = b.build();
+        for attr in sdp.attributes.iter().filter(|a| a.attribute == "extmap") {
+            if let Some(ref value) = attr.value {
+                sdp::parse_extmap(value, &mut message_structure);
+            }
+        }
 
         let conn_source = sdp
             .connection
@@ -1492,8 +2364,7 @@ impl RtspTaskState {
                 .ok()
                 .flatten()
                 .and_then(|v| sdp::parse_control_path(v, &base));
-            let Some(control_url) = media_control.as_ref().or(self.aggregate_control.as_ref())
-            else {
+            let Some(control_url) = media_control.or_else(|| self.aggregate_control.clone()) else {
                 gst::warning!(
                     CAT,
                     "No session control or media control for {} fmt {}, ignoring",
@@ -1600,29 +2471,17 @@ impl RtspTaskState {
                 req
             };
             let req = req.build(Body::default());
-            let cseq = self.cseq;
-
-            gst::debug!(CAT, "-->> {req:#?}");
-            self.sink.send(req.into()).await?;
 
             // RTSP 2 supports pipelining of SETUP requests, so this ping-pong would have to be
             // reworked if we want to support it.
-            let rsp = match self.stream.next().await {
-                Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-                Some(Ok(m)) => Err(RtspError::UnexpectedMessage("SETUP response", m)),
-                Some(Err(e)) => Err(e.into()),
-                None => Err(std::io::Error::new(
-                    std::io::ErrorKind::UnexpectedEof,
-                    "setup response",
-                )
-                .into()),
-            }?;
+            let rsp = self.send_request(req, "SETUP response").await?;
             gst::debug!(CAT, "<<-- {rsp:#?}");
-            Self::check_response(&rsp, cseq, Method::Setup, session.as_ref())?;
+            Self::check_response(&rsp, self.cseq, Method::Setup, session.as_ref())?;
             let new_session = rsp
                 .typed_header::<Session>()?
                 .ok_or(RtspError::InvalidMessage("No session in SETUP response"))?;
             // Manually strip timeout field: https://github.com/sdroege/rtsp-types/issues/24
+            self.session_timeout = new_session.1;
             session.replace(Session(new_session.0, None));
             let mut parsed_transport = if let Some(transports) = rsp.typed_header::<Transports>()? {
                 Self::parse_setup_transports(&transports, &mut s, &protocols, &mode)
@@ -1637,8 +2496,12 @@ impl RtspTaskState {
                     ))
                 }
             }?;
+            let mut multicast_sources = Vec::new();
             match &mut parsed_transport {
-                RtspTransportInfo::UdpMulticast { .. } => {}
+                RtspTransportInfo::UdpMulticast { dest, .. } => {
+                    multicast_sources =
+                        sdp::parse_multicast_sources(&sdp.attributes, &m.attributes, *dest);
+                }
                 RtspTransportInfo::Udp {
                     source,
                     server_port: _,
@@ -1693,26 +2556,54 @@ impl RtspTaskState {
             setup_params.push(RtspSetupParams {
                 control_url: control_url.clone(),
                 transport: parsed_transport,
+                multicast_sources,
                 rtp_appsrc: None,
+                stream_state: Arc::default(),
                 caps,
             });
         }
         Ok(setup_params)
     }
 
+    fn set_paused(&self, paused: bool) {
+        for params in &self.setup_params {
+            params.stream_state.lock().unwrap().paused = paused;
+        }
+    }
+
     async fn play(&mut self, session: &Session) -> Result<u32, RtspError> {
         self.cseq += 1;
         let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
-        let req = Request::builder(Method::Play, self.version)
+        let mut req = Request::builder(Method::Play, self.version)
             .typed_header::<CSeq>(&self.cseq.into())
-            .typed_header::<Range>(&Range::Npt(NptRange::From(NptTime::Now)))
             .header(USER_AGENT, DEFAULT_USER_AGENT)
             .request_uri(request_uri)
             .typed_header::<Session>(session);
 
-        let req = req.build(Body::default());
-        gst::debug!(CAT, "-->> {req:#?}");
+        if self.seek_pending {
+            req = req.header(RANGE, self.play_range.npt());
+            self.seek_pending = false;
+            self.seek_cseq = Some(self.cseq);
+        } else {
+            req = req.typed_header::<Range>(&Range::Npt(NptRange::From(NptTime::Now)));
+        }
+
+        let rate = self.play_range.rate;
+        if rate != 1.0 {
+            // Scale lets the server drop frames or play backwards, Speed delivers every frame
+            // faster than real time
+            if self.play_range.trickmode || rate < 0.0 {
+                req = req.header(SCALE, rate.to_string());
+            } else {
+                req = req.header(SPEED, rate.to_string());
+            }
+        }
+
+        let mut req = req.build(Body::default());
+        self.authorize(&mut req);
+        gst::debug!(CAT, "-->> {:#?}", Redacted(&req));
         self.sink.send(req.into()).await?;
+        self.set_paused(false);
         Ok(self.cseq)
     }
 
@@ -1723,30 +2614,159 @@ impl RtspTaskState {
         session: &Session,
     ) -> Result<(), RtspError> {
         Self::check_response(rsp, cseq, Method::Play, Some(session))?;
-        if let Some(RtpInfos::V1(rtpinfos)) = rsp.typed_header::<RtpInfos>()? {
-            for rtpinfo in rtpinfos {
-                for params in self.setup_params.iter_mut() {
-                    if params.control_url == rtpinfo.uri {
-                        let mut changed = false;
-                        let mut caps = params.rtp_appsrc.as_ref().unwrap().caps().unwrap();
-                        let capsref = caps.make_mut();
-                        if let Some(v) = rtpinfo.seq {
-                            capsref.set("seqnum-base", v as u32);
-                            changed = true;
-                        }
-                        if let Some(v) = rtpinfo.rtptime {
-                            capsref.set("clock-base", v);
-                            changed = true;
-                        }
-                        if changed {
-                            params.rtp_appsrc.as_ref().unwrap().set_caps(Some(&caps));
-                        }
+        let rtpinfos = match rsp.typed_header::<RtpInfos>()? {
+            Some(RtpInfos::V1(rtpinfos)) => rtpinfos,
+            _ => {
+                gst::warning!(CAT, "No RTPInfos V1 header in PLAY response");
+                Vec::new()
+            }
+        };
+
+        // The server may not start exactly at the requested position or honour the requested rate
+        let is_seek = self.seek_cseq == Some(cseq);
+        let range = rsp
+            .header(&RANGE)
+            .and_then(|v| sdp::parse_range(v.as_str()));
+        let npt_start = range
+            .and_then(|r| r.start)
+            .or(is_seek.then_some(self.play_range.start));
+        let npt_stop = range
+            .and_then(|r| r.stop)
+            .or(self.play_range.stop.filter(|_| is_seek))
+            .or(self.duration);
+        let header_f64 = |name| {
+            rsp.header(name)
+                .and_then(|v| v.as_str().trim().parse::<f64>().ok())
+                .filter(|v| v.is_normal())
+        };
+        let scale = header_f64(&SCALE).unwrap_or(1.0);
+        let speed = header_f64(&SPEED).unwrap_or(1.0);
+        gst::debug!(
+            CAT,
+            "Playing range {npt_start:?}-{npt_stop:?}, scale {scale}, speed {speed}"
+        );
+
+        let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        segment.set_rate(speed);
+        segment.set_applied_rate(scale);
+        segment.set_time(npt_start.unwrap_or(gst::ClockTime::ZERO));
+
+        for params in self.setup_params.iter_mut() {
+            let Some(appsrc) = &params.rtp_appsrc else {
+                continue;
+            };
+            let mut caps = appsrc.caps().unwrap();
+            let capsref = caps.make_mut();
+            for rtpinfo in &rtpinfos {
+                if params.control_url == rtpinfo.uri {
+                    if let Some(v) = rtpinfo.seq {
+                        capsref.set("seqnum-base", v as u32);
+                    }
+                    if let Some(v) = rtpinfo.rtptime {
+                        capsref.set("clock-base", v);
                     }
                 }
             }
+            // Used by the jitterbuffer and the depayloaders to timestamp the new range
+            if let Some(npt_start) = npt_start {
+                capsref.set("npt-start", npt_start.nseconds());
+            }
+            if let Some(npt_stop) = npt_stop {
+                capsref.set("npt-stop", npt_stop.nseconds());
+            }
+            capsref.set("play-speed", speed);
+            capsref.set("play-scale", scale);
+            appsrc.set_caps(Some(&caps));
+
+            if is_seek {
+                // Keep the running time of the buffers, which are timestamped on arrival
+                let mut segment = segment.clone();
+                let now = appsrc.current_running_time();
+                segment.set_start(now);
+                segment.set_base(now);
+                params.stream_state.lock().unwrap().segment = Some(segment);
+            }
+        }
+        if is_seek {
+            self.seek_cseq = None;
+        }
+        Ok(())
+    }
+
+    /// Keep the session alive, with GET_PARAMETER if the server supports it like RFC 2326
+    /// recommends, or else with OPTIONS
+    async fn keepalive(&mut self, session: &Session) -> Result<(Method, u32), RtspError> {
+        let method = if self.get_parameter_supported {
+            Method::GetParameter
         } else {
-            gst::warning!(CAT, "No RTPInfos V1 header in PLAY response");
+            Method::Options
+        };
+        self.cseq += 1;
+        let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
+        let req = Request::builder(method.clone(), self.version)
+            .typed_header::<CSeq>(&self.cseq.into())
+            .header(USER_AGENT, DEFAULT_USER_AGENT)
+            .request_uri(request_uri)
+            .typed_header::<Session>(session);
+
+        let mut req = req.build(Body::default());
+        self.authorize(&mut req);
+        gst::debug!(CAT, "-->> {:#?}", Redacted(&req));
+        self.sink.send(req.into()).await?;
+        Ok((method, self.cseq))
+    }
+
+    /// Answer a request from the server. Servers check that the client is still alive with
+    /// OPTIONS, GET_PARAMETER or a SET_PARAMETER without a body.
+    async fn reply(
+        &mut self,
+        req: &Request<Body>,
+        session: Option<&Session>,
+    ) -> Result<(), RtspError> {
+        let status = match req.method() {
+            Method::Options | Method::GetParameter => StatusCode::Ok,
+            Method::SetParameter if req.body().is_empty() => StatusCode::Ok,
+            _ => StatusCode::NotImplemented,
         };
+        let mut rsp =
+            Response::builder(req.version(), status).header(USER_AGENT, DEFAULT_USER_AGENT);
+        if let Some(cseq) = req.header(&CSEQ) {
+            rsp = rsp.header(CSEQ, cseq.clone());
+        }
+        if let Some(s) = session {
+            rsp = rsp.typed_header::<Session>(s);
+        }
+
+        let rsp = rsp.build(Body::default());
+        gst::debug!(CAT, "-->> {rsp:#?}");
+        self.sink.send(rsp.into()).await?;
+        Ok(())
+    }
+
+    async fn pause(&mut self, session: &Session) -> Result<u32, RtspError> {
+        self.cseq += 1;
+        let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
+        let req = Request::builder(Method::Pause, self.version)
+            .typed_header::<CSeq>(&self.cseq.into())
+            .header(USER_AGENT, DEFAULT_USER_AGENT)
+            .request_uri(request_uri)
+            .typed_header::<Session>(session);
+
+        let mut req = req.build(Body::default());
+        self.authorize(&mut req);
+        gst::debug!(CAT, "-->> {:#?}", Redacted(&req));
+        self.sink.send(req.into()).await?;
+        Ok(self.cseq)
+    }
+
+    async fn pause_response(
+        &mut self,
+        rsp: &Response<Body>,
+        cseq: u32,
+        session: &Session,
+    ) -> Result<(), RtspError> {
+        Self::check_response(rsp, cseq, Method::Pause, Some(session))?;
+        self.set_paused(true);
         Ok(())
     }
 
@@ -1759,8 +2779,9 @@ impl RtspTaskState {
             .request_uri(request_uri)
             .typed_header::<Session>(session);
 
-        let req = req.build(Body::default());
-        gst::debug!(CAT, "-->> {req:#?}");
+        let mut req = req.build(Body::default());
+        self.authorize(&mut req);
+        gst::debug!(CAT, "-->> {:#?}", Redacted(&req));
         self.sink.send(req.into()).await?;
         Ok(self.cseq)
     }
@@ -1776,6 +2797,31 @@ impl RtspTaskState {
     }
 }
 
+/// Record a request that failed because the connection to the server was lost as the reason for
+/// reconnecting, like a read error. Other errors are passed on.
+fn check_connection_lost<T>(
+    res: Result<T, RtspError>,
+    lost: &mut Option<(String, bool)>,
+) -> Result<Option<T>, RtspError> {
+    match res {
+        Ok(v) => Ok(Some(v)),
+        Err(RtspError::IOGeneric(err)) => {
+            *lost = Some((format!("I/O error: {err:?}"), false));
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Keep-alive requests are sent at half the session timeout
+fn keepalive_interval(session_timeout: Option<u64>) -> time::Interval {
+    let timeout = session_timeout.unwrap_or(DEFAULT_SESSION_TIMEOUT).max(2);
+    let period = Duration::from_secs(timeout / 2);
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    interval
+}
+
 fn bind_port(port: u16, is_ipv4: bool) -> Result<UdpSocket, std::io::Error> {
     let domain = if is_ipv4 {
         socket2::Domain::IPV4
@@ -1882,9 +2928,29 @@ fn on_rtcp_tcp(
     }
 }
 
+/// Push an RTP buffer, along with the new segment after a seek
+fn push_rtp_buffer(
+    appsrc: &gst_app::AppSrc,
+    stream_state: &Mutex<RtpStreamState>,
+    buffer: gst::Buffer,
+) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let segment = stream_state.lock().unwrap().segment.take();
+    match segment {
+        Some(segment) => appsrc.push_sample(
+            &gst::Sample::builder()
+                .buffer(&buffer)
+                .segment(&segment)
+                .build(),
+        ),
+        None => appsrc.push_buffer(buffer),
+    }
+}
+
 async fn udp_rtp_task(
     socket: &UdpSocket,
     appsrc: gst_app::AppSrc,
+    stream_state: Arc<Mutex<RtpStreamState>>,
+    cmd_tx: mpsc::Sender<Commands>,
     timeout: gst::ClockTime,
     receive_mtu: u32,
     sender_addr: Option<SocketAddr>,
@@ -1898,11 +2964,12 @@ async fn udp_rtp_task(
         None => {
             let ret = match time::timeout(t, socket.peek_sender()).await {
                 Ok(Ok(addr)) => Ok(addr),
-                Ok(Err(_elapsed)) => Err(format!(
-                    "No data after {} seconds, exiting",
-                    timeout.seconds()
-                )),
-                Err(err) => Err(format!("UDP socket was closed: {err:?}")),
+                Ok(Err(err)) => Err(format!("UDP socket was closed: {err:?}")),
+                Err(_elapsed) => {
+                    let error = format!("No data after {} seconds", timeout.seconds());
+                    let _ = cmd_tx.send(Commands::ReceiveTimeout(error)).await;
+                    return;
+                }
             };
             match ret {
                 Ok(addr) => addr,
@@ -1967,14 +3034,20 @@ async fn udp_rtp_task(
                 bufref.set_dts(t);
                 gst_net::NetAddressMeta::add(bufref, &gio_addr);
                 gst::trace!(CAT, "received RTP packet from {addr:?}");
-                if let Err(err) = appsrc.push_buffer(buffer) {
+                if let Err(err) = push_rtp_buffer(&appsrc, &stream_state, buffer) {
                     break format!("UDP buffer push failed: {err:?}");
                 }
             }
-            Ok(Err(_elapsed)) => {
-                break format!("No data after {} seconds, exiting", timeout.seconds())
+            Ok(Err(err)) => break format!("UDP socket was closed: {err:?}"),
+            Err(_elapsed) => {
+                if stream_state.lock().unwrap().paused {
+                    continue;
+                }
+                // The RTSP task decides whether to reconnect or to error out
+                let error = format!("No data after {} seconds", timeout.seconds());
+                let _ = cmd_tx.send(Commands::ReceiveTimeout(error)).await;
+                return;
             }
-            Err(err) => break format!("UDP socket was closed: {err:?}"),
         };
     };
     gst::element_error!(
@@ -2059,3 +3132,119 @@ impl ObjectSubclass for RtspSrc {
     type ParentType = gst::Bin;
     type Interfaces = (gst::URIHandler,);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtsp_types::headers::{AUTHORIZATION, PUBLIC, WWW_AUTHENTICATE};
+    use tokio::net::TcpListener;
+
+    fn init() {
+        use std::sync::Once;
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            gst::init().unwrap();
+        });
+    }
+
+    /// Accept a connection to the mock server and wrap it like the client connection
+    async fn accept(listener: &TcpListener) -> (RtspStream, RtspSink) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (read, write) = tokio::io::split(socket);
+        (
+            Box::pin(super::super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse()),
+            Box::pin(super::super::tcp_message::async_write(write)),
+        )
+    }
+
+    async fn next_request(stream: &mut RtspStream) -> Request<Body> {
+        match stream.next().await {
+            Some(Ok(Message::Request(req))) => req,
+            msg => panic!("Expected a request, got {msg:?}"),
+        }
+    }
+
+    fn cseq(req: &Request<Body>) -> String {
+        req.header(&CSEQ).unwrap().as_str().to_string()
+    }
+
+    #[test]
+    fn test_unauthorized_retry() {
+        init();
+
+        RUNTIME.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url =
+                Url::parse(&format!("rtsp://{}/stream", listener.local_addr().unwrap())).unwrap();
+
+            let server = tokio::spawn(async move {
+                let (mut stream, mut sink) = accept(&listener).await;
+
+                let req = next_request(&mut stream).await;
+                assert!(matches!(req.method(), Method::Options));
+                assert!(req.header(&AUTHORIZATION).is_none());
+                let rsp = Response::builder(Version::V1_0, StatusCode::Unauthorized)
+                    .header(CSEQ, cseq(&req))
+                    .header(WWW_AUTHENTICATE, r#"Basic realm="test""#)
+                    .build(Body::default());
+                sink.send(rsp.into()).await.unwrap();
+
+                let req = next_request(&mut stream).await;
+                assert!(matches!(req.method(), Method::Options));
+                assert_eq!(req.header(&CSEQ).unwrap().as_str(), "2");
+                assert_eq!(
+                    req.header(&AUTHORIZATION).unwrap().as_str(),
+                    "Basic dXNlcjpwYXNz"
+                );
+                let rsp = Response::builder(Version::V1_0, StatusCode::Ok)
+                    .header(CSEQ, cseq(&req))
+                    .header(PUBLIC, "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN")
+                    .build(Body::default());
+                sink.send(rsp.into()).await.unwrap();
+            });
+
+            let (stream, sink) = connect(&url, None, false).await.unwrap();
+            let auth = Authenticator::new("user".to_string(), "pass".to_string());
+            let mut state = RtspTaskState::new(url, stream, sink, Some(auth), None, false);
+            state.options().await.unwrap();
+            assert_eq!(state.cseq, 2);
+
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_unauthorized_rejected() {
+        init();
+
+        RUNTIME.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url =
+                Url::parse(&format!("rtsp://{}/stream", listener.local_addr().unwrap())).unwrap();
+
+            let server = tokio::spawn(async move {
+                let (mut stream, mut sink) = accept(&listener).await;
+
+                for _ in 0..2 {
+                    let req = next_request(&mut stream).await;
+                    let rsp = Response::builder(Version::V1_0, StatusCode::Unauthorized)
+                        .header(CSEQ, cseq(&req))
+                        .header(WWW_AUTHENTICATE, r#"Basic realm="test""#)
+                        .build(Body::default());
+                    sink.send(rsp.into()).await.unwrap();
+                }
+            });
+
+            let (stream, sink) = connect(&url, None, false).await.unwrap();
+            let auth = Authenticator::new("user".to_string(), "wrong".to_string());
+            let mut state = RtspTaskState::new(url, stream, sink, Some(auth), None, false);
+            assert!(matches!(
+                state.options().await,
+                Err(RtspError::Unauthorized(_))
+            ));
+
+            server.await.unwrap();
+        });
+    }
+}
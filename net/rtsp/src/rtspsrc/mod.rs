@@ -16,16 +16,21 @@
  *
  * Implemented features:
  * * RTSP 1.0 support
- * * Lower transports: TCP, UDP, UDP-Multicast
+ * * Lower transports: TCP, UDP, UDP-Multicast, including source-specific multicast
+ * * Basic and Digest (MD5, SHA-256) authentication
+ * * RTSP over TLS (`rtsps://`)
+ * * RTSP over HTTP(S) tunnelling (`rtsph://` and `rtspsh://`)
  * * RTCP SR and RTCP RR
  * * RTCP-based A/V sync
  * * Lower transport selection and priority (NEW!)
  *   - Also supports different lower transports for each SETUP
+ * * VOD support: PAUSE, flushing seeks, Scale and Speed
+ * * Session keep-alive and reconnection, with fallback from UDP to TCP
  *
  * Some missing features:
- * * SET_PARAMETER/GET_PARAMETER messages
+ * * SET_PARAMETER/GET_PARAMETER messages from the application
  * * SRTP support
- * * VOD support: PAUSE, seeking, etc
+ * * VOD support: non-flushing and segment seeks
  * * ONVIF backchannel and trick mode support
  * * and more
  *
@@ -35,10 +40,14 @@
 use gst::glib;
 use gst::prelude::*;
 
-mod body;
+mod auth;
+pub(crate) mod body;
+mod http_tunnel;
 mod imp;
+mod multicast;
 mod sdp;
-mod tcp_message;
+pub(crate) mod tcp_message;
+mod tls;
 mod transport;
 
 glib::wrapper! {
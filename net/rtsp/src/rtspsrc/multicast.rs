@@ -0,0 +1,153 @@
+// GStreamer RTSP Source 2
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// Any-source and source-specific (RFC 4607) multicast group membership
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+
+use socket2::{InterfaceIndexOrAddress, SockRef};
+use tokio::net::UdpSocket;
+
+use super::imp::RtspError;
+
+/// Resolve the name, or index, of a network interface to its index
+pub(super) fn interface_index(iface: &str) -> Result<u32, RtspError> {
+    if let Ok(index) = iface.parse::<u32>() {
+        return Ok(index);
+    }
+
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(iface)
+            .map_err(|_| RtspError::Fatal(format!("Invalid interface name {iface:?}")))?;
+        // SAFETY: name is a valid NUL-terminated string
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index != 0 {
+            return Ok(index);
+        }
+    }
+
+    Err(RtspError::Fatal(format!(
+        "Unknown multicast interface {iface:?}"
+    )))
+}
+
+/// Join the multicast `group` on the interface with index `iface`, or the default interface if
+/// 0. If `sources` is not empty, only packets from those are received.
+pub(super) fn join(
+    socket: &UdpSocket,
+    group: IpAddr,
+    sources: &[IpAddr],
+    iface: u32,
+) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+    match group {
+        IpAddr::V4(group) if sources.is_empty() => {
+            if iface == 0 {
+                socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+            } else {
+                socket.join_multicast_v4_n(&group, &InterfaceIndexOrAddress::Index(iface))
+            }
+        }
+        IpAddr::V6(group) if sources.is_empty() => socket.join_multicast_v6(&group, iface),
+        group => {
+            for source in sources {
+                if source.is_ipv4() != group.is_ipv4() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Source {source} is not in the address family of group {group}"),
+                    ));
+                }
+                join_source(&socket, group, *source, iface)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn join_source(socket: &SockRef, group: IpAddr, source: IpAddr, iface: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // From <netinet/in.h>, not available in all libc crate versions
+    const MCAST_JOIN_SOURCE_GROUP: libc::c_int = 46;
+    #[repr(C)]
+    struct GroupSourceReq {
+        interface: u32,
+        group: libc::sockaddr_storage,
+        source: libc::sockaddr_storage,
+    }
+
+    let req = GroupSourceReq {
+        interface: iface,
+        group: sockaddr(group),
+        source: sockaddr(source),
+    };
+    let level = if group.is_ipv4() {
+        libc::IPPROTO_IP
+    } else {
+        libc::IPPROTO_IPV6
+    };
+
+    // SAFETY: req is a valid group_source_req and outlives the call
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            MCAST_JOIN_SOURCE_GROUP,
+            &req as *const GroupSourceReq as *const libc::c_void,
+            std::mem::size_of::<GroupSourceReq>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sockaddr(addr: IpAddr) -> libc::sockaddr_storage {
+    // SAFETY: all-zero is a valid sockaddr_storage
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    match addr {
+        IpAddr::V4(addr) => {
+            // SAFETY: sockaddr_storage is large enough and aligned for any sockaddr
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            // SAFETY: sockaddr_storage is large enough and aligned for any sockaddr
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_addr.s6_addr = addr.octets();
+        }
+    }
+    storage
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn join_source(socket: &SockRef, group: IpAddr, source: IpAddr, iface: u32) -> io::Result<()> {
+    match (group, source) {
+        (IpAddr::V4(group), IpAddr::V4(source)) => {
+            if iface != 0 {
+                gst::warning!(
+                    super::imp::CAT,
+                    "Can't select the interface for source-specific multicast on this platform"
+                );
+            }
+            socket.join_ssm_v4(&source, &group, &Ipv4Addr::UNSPECIFIED)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "IPv6 source-specific multicast is not supported on this platform",
+        )),
+    }
+}
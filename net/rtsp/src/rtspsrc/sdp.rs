@@ -355,13 +355,39 @@ fn parse_framesize(framesize: &str, s: &mut gst::structure::Structure) {
     s.set("a-framesize", dim);
 }
 
+/// Parses an `extmap` attribute of the form `<id>[/<direction>] <uri> [<attributes>]` into an
+/// `extmap-<id>` field as expected by the RTP header extension API.
+pub fn parse_extmap(extmap: &str, s: &mut gst::structure::Structure) {
+    let mut parts = extmap.trim().splitn(3, ' ');
+    let (Some(id), Some(uri)) = (parts.next(), parts.next()) else {
+        gst::warning!(CAT, "Could not parse extmap {extmap}, ignoring");
+        return;
+    };
+    let attributes = parts.next().unwrap_or_default().trim();
+
+    let (id, direction) = id.split_once('/').unwrap_or((id, ""));
+    let Ok(id) = id.parse::<u8>() else {
+        gst::warning!(CAT, "Invalid extmap id in {extmap}, ignoring");
+        return;
+    };
+
+    if direction.is_empty() && attributes.is_empty() {
+        s.set(format!("extmap-{id}"), uri);
+    } else {
+        s.set(
+            format!("extmap-{id}"),
+            gst::Array::new([direction, uri, attributes]),
+        );
+    }
+}
+
 pub fn parse_media_attributes(
     attrs: &Vec<Attribute>,
     pt: u8,
     media: &str,
     s: &mut gst::structure::Structure,
 ) -> Result<(), RtspError> {
-    let mut skip_attrs = vec!["control", "range", "ssrc"];
+    let mut skip_attrs = vec!["control", "range", "source-filter", "ssrc"];
 
     for Attribute { attribute, value } in attrs {
         let attr = attribute.as_str();
@@ -378,11 +404,17 @@ pub fn parse_media_attributes(
             continue;
         }
 
+        // Multiple extmap attributes are allowed
+        if attr == "extmap" {
+            parse_extmap(value, s);
+            continue;
+        }
+
         match attr {
             "rtpmap" => parse_rtpmap(value, pt, media, s)?,
             "fmtp" => parse_fmtp(value, s),
             "framesize" => parse_framesize(value, s),
-            // TODO: extmap, key-mgmt, rid, rtcp-fb, source-filter, ssrc
+            // TODO: key-mgmt, rid, rtcp-fb, ssrc
             _ => s.set(format!("a-{attribute}"), value),
         };
         skip_attrs.push(attr);
@@ -429,3 +461,273 @@ pub fn parse_connections(conns: &Vec<Connection>) -> (BTreeSet<RtspProtocol>, bo
     }
     (conn_protocols, is_ipv4)
 }
+
+/// A presentation range in normal play time, as found in the SDP `range` attribute or in the
+/// `Range` header of a PLAY response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaRange {
+    /// `None` if the range starts at the live point (`now`)
+    pub start: Option<gst::ClockTime>,
+    pub stop: Option<gst::ClockTime>,
+}
+
+impl MediaRange {
+    /// Only on-demand media have a known duration
+    pub fn duration(&self) -> Option<gst::ClockTime> {
+        self.start.and(self.stop)
+    }
+}
+
+fn secs_to_clocktime(secs: f64) -> Option<gst::ClockTime> {
+    let nsecs = (secs * 1_000_000_000.0).round();
+    // Larger values would saturate the cast to u64::MAX, which is not a valid clock time
+    (secs.is_finite() && secs >= 0.0 && nsecs < gst::ClockTime::MAX.nseconds() as f64)
+        .then(|| gst::ClockTime::from_nseconds(nsecs as u64))
+}
+
+// https://datatracker.ietf.org/doc/html/rfc2326#section-3.6
+fn parse_npt_time(time: &str) -> Option<f64> {
+    // Either seconds or hours:minutes:seconds, both with an optional fraction. Only digits are
+    // allowed, unlike the exponents, signs and infinities of the float syntax of Rust.
+    let parts = time.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut secs = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let (int, fraction) = part.split_once('.').unwrap_or((part, ""));
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() || !is_digits(int) || !is_digits(fraction) {
+            return None;
+        }
+        if i + 1 < parts.len() && part.contains('.') {
+            return None;
+        }
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(secs)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc2326#section-3.5
+fn parse_smpte_time(time: &str, fps: f64) -> Option<f64> {
+    let mut parts = time.split(':');
+    let mut secs = 0.0;
+    for _ in 0..3 {
+        secs = secs * 60.0 + parts.next()?.parse::<u32>().ok()? as f64;
+    }
+    // Frames with optional hundredths of a frame
+    if let Some(frames) = parts.next() {
+        secs += frames.parse::<f64>().ok()? / fps;
+    }
+    parts.next().is_none().then_some(secs)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc2326#section-3.7
+fn parse_utc_time(time: &str) -> Option<f64> {
+    let (date, time) = time.strip_suffix('Z')?.split_once('T')?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    if date.len() != 8 || time.len() != 6 {
+        return None;
+    }
+
+    let field = |s: &str, range: std::ops::Range<usize>| s.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (field(date, 0..4)?, field(date, 4..6)?, field(date, 6..8)?);
+    let (hours, minutes, seconds) = (field(time, 0..2)?, field(time, 2..4)?, field(time, 4..6)?);
+    let fraction = format!("0.{fraction}").parse::<f64>().ok()?;
+
+    // Days since the epoch of the proleptic Gregorian calendar date
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+
+    Some((days * 86400 + hours * 3600 + minutes * 60 + seconds) as f64 + fraction)
+}
+
+/// Parses a range of the form `<unit>=[<start>]-[<stop>]` where unit is `npt`, `clock` or one of
+/// the `smpte` variants. Absolute `clock` ranges are converted to a range starting at zero.
+pub fn parse_range(range: &str) -> Option<MediaRange> {
+    // Strip the `;time=` parameter of Range headers
+    let range = range.split(';').next()?.trim();
+    let (unit, value) = range.split_once('=')?;
+    let (start, stop) = value.trim().split_once('-')?;
+    let (start, stop) = (start.trim(), stop.trim());
+
+    let parse = |time: &str| -> Option<Option<f64>> {
+        if time.is_empty() {
+            return Some(None);
+        }
+        let secs = match unit.trim() {
+            "npt" => parse_npt_time(time)?,
+            "smpte" => parse_smpte_time(time, 30.0)?,
+            "smpte-30-drop" => parse_smpte_time(time, 30000.0 / 1001.0)?,
+            "smpte-25" => parse_smpte_time(time, 25.0)?,
+            "clock" => parse_utc_time(time)?,
+            _ => return None,
+        };
+        Some(Some(secs))
+    };
+
+    if unit.trim() == "npt" && start == "now" {
+        return Some(MediaRange {
+            start: None,
+            stop: None,
+        });
+    }
+
+    let (start, stop) = (parse(start)?, parse(stop)?);
+    let (start, stop) = if unit.trim() == "clock" {
+        let begin = start?;
+        (0.0, stop.map(|stop| stop - begin))
+    } else {
+        (start.unwrap_or(0.0), stop)
+    };
+
+    Some(MediaRange {
+        start: Some(secs_to_clocktime(start)?),
+        stop: match stop {
+            Some(stop) => Some(secs_to_clocktime(stop)?),
+            None => None,
+        },
+    })
+}
+
+// https://datatracker.ietf.org/doc/html/rfc4570#section-3
+fn parse_source_filter(filter: &str) -> Option<(Option<IpAddr>, Vec<IpAddr>)> {
+    let mut parts = filter.split_whitespace();
+    match parts.next()? {
+        "incl" => {}
+        "excl" => {
+            gst::warning!(CAT, "Exclusive source filters are not supported: {filter}");
+            return None;
+        }
+        _ => return None,
+    }
+    if parts.next()? != "IN" || !["IP4", "IP6", "*"].contains(&parts.next()?) {
+        return None;
+    }
+    let dest = match parts.next()? {
+        "*" => None,
+        // Strip the TTL, if any
+        dest => Some(dest.split('/').next()?.parse::<IpAddr>().ok()?),
+    };
+    // Sources can also be FQDNs, which we don't resolve
+    let sources = parts
+        .filter_map(|source| source.parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+
+    (!sources.is_empty()).then_some((dest, sources))
+}
+
+/// Returns the sources to receive the multicast group `dest` from, according to the inclusive
+/// `source-filter` attributes of the media, or of the session if the media has none. An empty
+/// list means any source.
+pub fn parse_multicast_sources(
+    session_attrs: &[Attribute],
+    media_attrs: &[Attribute],
+    dest: IpAddr,
+) -> Vec<IpAddr> {
+    let sources = |attrs: &[Attribute]| {
+        attrs
+            .iter()
+            .filter(|a| a.attribute == "source-filter")
+            .filter_map(|a| parse_source_filter(a.value.as_deref()?))
+            .filter(|(filter_dest, _)| filter_dest.map_or(true, |d| d == dest))
+            .flat_map(|(_, sources)| sources)
+            .collect::<Vec<_>>()
+    };
+
+    let media_sources = sources(media_attrs);
+    if !media_sources.is_empty() {
+        return media_sources;
+    }
+    sources(session_attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let range = |start: Option<u64>, stop: Option<u64>| MediaRange {
+            start: start.map(gst::ClockTime::from_mseconds),
+            stop: stop.map(gst::ClockTime::from_mseconds),
+        };
+
+        assert_eq!(parse_range("npt=now-"), Some(range(None, None)));
+        assert_eq!(parse_range("npt=0-"), Some(range(Some(0), None)));
+        assert_eq!(
+            parse_range("npt=0-34.5"),
+            Some(range(Some(0), Some(34_500)))
+        );
+        assert_eq!(
+            parse_range("npt=1:02:03.5-"),
+            Some(range(Some(3_723_500), None))
+        );
+        assert_eq!(
+            parse_range("npt=10-20;time=19970123T153600Z"),
+            Some(range(Some(10_000), Some(20_000)))
+        );
+        assert_eq!(
+            parse_range("smpte-25=10:07:00-10:07:33:05"),
+            Some(range(Some(36_420_000), Some(36_453_200)))
+        );
+        assert_eq!(
+            parse_range("clock=19961108T142300Z-19961108T143520.5Z"),
+            Some(range(Some(0), Some(740_500)))
+        );
+        assert_eq!(
+            parse_range("clock=19961231T235959Z-19970101T000001Z"),
+            Some(range(Some(0), Some(2_000)))
+        );
+        assert_eq!(parse_range("npt=abc-"), None);
+        assert_eq!(parse_range("npt=0-1e30"), None);
+        assert_eq!(parse_range("npt=+1-inf"), None);
+        assert_eq!(parse_range("npt=1.5:00-"), None);
+        assert_eq!(parse_range("npt=0-100000000000"), None);
+        assert_eq!(parse_range("frames=0-10"), None);
+    }
+
+    #[test]
+    fn test_parse_multicast_sources() {
+        let attr = |value: &str| Attribute {
+            attribute: "source-filter".to_string(),
+            value: Some(value.to_string()),
+        };
+        let dest = "232.3.4.5".parse().unwrap();
+
+        let session = vec![attr("incl IN IP4 * 198.51.100.1")];
+        let media = vec![
+            attr("incl IN IP4 232.3.4.5 192.0.2.10 192.0.2.11"),
+            attr("incl IN IP4 232.9.9.9 192.0.2.99"),
+            attr("excl IN IP4 232.3.4.5 192.0.2.12"),
+        ];
+        assert_eq!(
+            parse_multicast_sources(&session, &media, dest),
+            vec![
+                "192.0.2.10".parse::<IpAddr>().unwrap(),
+                "192.0.2.11".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            parse_multicast_sources(&session, &[], dest),
+            vec!["198.51.100.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            parse_multicast_sources(&[], &[attr("incl IN IP4 232.9.9.9 192.0.2.99")], dest),
+            vec![]
+        );
+        assert_eq!(
+            parse_multicast_sources(
+                &[],
+                &[attr(
+                    "incl IN IP6 ff3e::4321 2001:db8::1 source.example.com"
+                )],
+                "ff3e::4321".parse().unwrap()
+            ),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
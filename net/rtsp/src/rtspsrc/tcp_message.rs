@@ -14,6 +14,7 @@ use std::marker::Unpin;
 use futures::{Sink, Stream};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use super::auth::Redacted;
 use super::body::Body;
 use rtsp_types::Message;
 
@@ -181,7 +182,7 @@ pub(crate) fn async_write<W: AsyncWrite + Unpin + Send>(
 
     futures::sink::unfold(state, |mut state, item: Message<Body>| {
         async move {
-            gst::trace!(super::imp::CAT, "Writing message {:?}", item);
+            gst::trace!(super::imp::CAT, "Writing message {:?}", Redacted(&item));
 
             // TODO: Write data messages more efficiently by writing header / body separately
             state.buffer.clear();
@@ -0,0 +1,115 @@
+// GStreamer RTSP Source 2
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use tokio_rustls::rustls;
+use url::{Host, Url};
+
+use super::imp::RtspError;
+
+pub(super) const DEFAULT_RTSPS_PORT: u16 = 322;
+
+#[derive(Debug)]
+struct SkipServerVerification;
+
+impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls_pki_types::CertificateDer,
+        _intermediates: &[rustls_pki_types::CertificateDer],
+        _server_name: &rustls_pki_types::ServerName,
+        _ocsp_response: &[u8],
+        _now: rustls_pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls_pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls_pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Create the TLS connector used for `rtsps://` locations. The server certificate is checked
+/// against `ca_file` if set, or else against the Mozilla root certificates.
+pub(super) fn connector(
+    validate_certificate: bool,
+    ca_file: Option<&str>,
+) -> Result<tokio_rustls::TlsConnector, RtspError> {
+    let config = if !validate_certificate {
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+            .with_no_client_auth()
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        if let Some(ca_file) = ca_file {
+            let mut reader = BufReader::new(File::open(ca_file)?);
+            for cert in rustls_pemfile::certs(&mut reader) {
+                roots.add(cert?).map_err(|err| {
+                    RtspError::Fatal(format!("Invalid CA certificate in {ca_file}: {err}"))
+                })?;
+            }
+        } else {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+    };
+
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+}
+
+/// The name the server certificate must be valid for
+pub(super) fn server_name(url: &Url) -> Result<rustls_pki_types::ServerName<'static>, RtspError> {
+    match url.host() {
+        Some(Host::Domain(domain)) => rustls_pki_types::ServerName::try_from(domain.to_string())
+            .map_err(|err| RtspError::Fatal(format!("Invalid TLS server name {domain}: {err}"))),
+        Some(Host::Ipv4(addr)) => Ok(rustls_pki_types::ServerName::from(std::net::IpAddr::from(
+            addr,
+        ))),
+        Some(Host::Ipv6(addr)) => Ok(rustls_pki_types::ServerName::from(std::net::IpAddr::from(
+            addr,
+        ))),
+        None => Err(RtspError::InvalidMessage("No host in location")),
+    }
+}
@@ -0,0 +1,355 @@
+// GStreamer RTSP Server Sink tests
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsrtp::plugin_register_static().expect("rtspserversink test");
+        gstrsrtsp::plugin_register_static().expect("rtspserversink test");
+    });
+}
+
+struct Response {
+    status: u16,
+    headers: HashMap<String, String>,
+}
+
+/// Minimal RTSP client to send requests the way a test needs them
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    cseq: u32,
+    session: Option<String>,
+}
+
+impl Client {
+    fn connect(url: &str) -> Self {
+        let addr = url
+            .strip_prefix("rtsp://")
+            .and_then(|s| s.split('/').next())
+            .unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            cseq: 0,
+            session: None,
+        }
+    }
+
+    fn request(&mut self, method: &str, url: &str, headers: &[(&str, &str)]) -> Response {
+        self.cseq += 1;
+        let mut req = format!("{method} {url} RTSP/1.0\r\nCSeq: {}\r\n", self.cseq);
+        if let Some(session) = &self.session {
+            req.push_str(&format!("Session: {session}\r\n"));
+        }
+        for (name, value) in headers {
+            req.push_str(&format!("{name}: {value}\r\n"));
+        }
+        req.push_str("\r\n");
+        self.writer.write_all(req.as_bytes()).unwrap();
+
+        self.read_response()
+    }
+
+    /// Skip an interleaved data message, returns `false` if the next message is not one
+    fn skip_data(&mut self) -> bool {
+        let buf = self.reader.fill_buf().unwrap();
+        assert!(!buf.is_empty(), "Connection closed");
+        if buf[0] != b'$' {
+            return false;
+        }
+        let mut header = [0u8; 4];
+        self.reader.read_exact(&mut header).unwrap();
+        let len = u16::from_be_bytes([header[2], header[3]]);
+        let mut data = vec![0u8; len.into()];
+        self.reader.read_exact(&mut data).unwrap();
+        true
+    }
+
+    fn read_response(&mut self) -> Response {
+        while self.skip_data() {}
+
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(|| panic!("Invalid status line {line:?}"));
+
+        let mut headers = HashMap::new();
+        loop {
+            line.clear();
+            self.reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+        if let Some(len) = headers.get("content-length") {
+            let mut body = vec![0u8; len.parse().unwrap()];
+            self.reader.read_exact(&mut body).unwrap();
+        }
+        if let Some(session) = headers.get("session") {
+            self.session = session.split(';').next().map(str::to_string);
+        }
+
+        Response { status, headers }
+    }
+
+    /// Discard interleaved data until the server closes the connection
+    fn wait_closed(&mut self) {
+        while !self.reader.fill_buf().unwrap().is_empty() {
+            assert!(self.skip_data(), "Unexpected message");
+        }
+    }
+}
+
+/// Serve `num_streams` raw video streams and wait until they can be described
+fn make_server(num_streams: u32, session_timeout: u32) -> (gst::Pipeline, String) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut desc = format!(
+        "rtspserversink name=sink address=127.0.0.1 port={port} session-timeout={session_timeout}"
+    );
+    for i in 0..num_streams {
+        desc.push_str(&format!(
+            " videotestsrc is-live=true ! video/x-raw,format=RGB,width=16,height=16,framerate=10/1 ! sink.sink_{i}"
+        ));
+    }
+    let pipeline = gst::parse::launch(&desc)
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    // DESCRIBE fails until the caps of all streams are known
+    let url = format!("rtsp://127.0.0.1:{port}/test");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let mut client = Client::connect(&url);
+        if client.request("DESCRIBE", &url, &[]).status == 200 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "Media was not negotiated");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    (pipeline, url)
+}
+
+/// Play `url` with rtspsrc2, counting the RTP packets received on all its pads
+fn make_client(url: &str, protocols: &str) -> (gst::Pipeline, Arc<AtomicUsize>) {
+    let src = gst::ElementFactory::make("rtspsrc2")
+        .property("location", url)
+        .property("protocols", protocols)
+        .build()
+        .unwrap();
+    let pipeline = gst::Pipeline::new();
+    pipeline.add(&src).unwrap();
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let pipeline_weak = pipeline.downgrade();
+    let counter = received.clone();
+    src.connect_pad_added(move |_src, pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+        let sink = gst::ElementFactory::make("fakesink")
+            .property("async", false)
+            .build()
+            .unwrap();
+        pipeline.add(&sink).unwrap();
+        sink.sync_state_with_parent().unwrap();
+
+        let counter = counter.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_pad, _info| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            gst::PadProbeReturn::Ok
+        });
+        pad.link(&sink.static_pad("sink").unwrap()).unwrap();
+    });
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    (pipeline, received)
+}
+
+/// Wait until `count` more packets than now were received, failing on errors
+fn wait_for_packets(pipeline: &gst::Pipeline, received: &AtomicUsize, count: usize) {
+    let target = received.load(Ordering::SeqCst) + count;
+    let bus = pipeline.bus().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while received.load(Ordering::SeqCst) < target {
+        if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+            panic!("Unexpected error: {msg:?}");
+        }
+        assert!(Instant::now() < deadline, "No packets received");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Two clients play the same media at once, and one keeps playing after the other left
+fn play_shared(protocols: &str) {
+    init();
+
+    let (server, url) = make_server(1, 60);
+    let (client_1, received_1) = make_client(&url, protocols);
+    let (client_2, received_2) = make_client(&url, protocols);
+
+    wait_for_packets(&client_1, &received_1, 10);
+    wait_for_packets(&client_2, &received_2, 10);
+
+    client_1.set_state(gst::State::Null).unwrap();
+    wait_for_packets(&client_2, &received_2, 10);
+
+    client_2.set_state(gst::State::Null).unwrap();
+    server.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_play_tcp() {
+    play_shared("tcp");
+}
+
+#[test]
+fn test_play_udp() {
+    play_shared("udp");
+}
+
+#[test]
+fn test_session_timeout() {
+    init();
+
+    let (pipeline, url) = make_server(1, 1);
+    let mut client = Client::connect(&url);
+    let rsp = client.request(
+        "SETUP",
+        &format!("{url}/stream=0"),
+        &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1")],
+    );
+    assert_eq!(rsp.status, 200);
+    assert!(rsp.headers["session"].ends_with(";timeout=1"));
+    let rsp = client.request("PLAY", &url, &[]);
+    assert_eq!(rsp.status, 200);
+    let played = Instant::now();
+
+    // Without any further request the client is disconnected after one to two timeouts
+    client.wait_closed();
+    let elapsed = played.elapsed();
+    assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_unsupported_transport() {
+    init();
+
+    let (pipeline, url) = make_server(2, 60);
+    let mut client = Client::connect(&url);
+    let stream_0 = format!("{url}/stream=0");
+    let stream_1 = format!("{url}/stream=1");
+
+    // The RTCP channel or port would be out of range
+    let rsp = client.request(
+        "SETUP",
+        &stream_0,
+        &[("Transport", "RTP/AVP/TCP;unicast;interleaved=255")],
+    );
+    assert_eq!(rsp.status, 461);
+    let rsp = client.request(
+        "SETUP",
+        &stream_0,
+        &[("Transport", "RTP/AVP;unicast;client_port=65535")],
+    );
+    assert_eq!(rsp.status, 461);
+
+    // Channels that are used by another stream of the session
+    let rsp = client.request(
+        "SETUP",
+        &stream_0,
+        &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1")],
+    );
+    assert_eq!(rsp.status, 200);
+    let rsp = client.request(
+        "SETUP",
+        &stream_1,
+        &[("Transport", "RTP/AVP/TCP;unicast;interleaved=1-2")],
+    );
+    assert_eq!(rsp.status, 461);
+    let rsp = client.request(
+        "SETUP",
+        &stream_1,
+        &[("Transport", "RTP/AVP/TCP;unicast;interleaved=2-3")],
+    );
+    assert_eq!(rsp.status, 200);
+    assert!(rsp.headers["transport"].contains("interleaved=2-3"));
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_release_pad() {
+    init();
+
+    let sink = gst::ElementFactory::make("rtspserversink")
+        .property("address", "127.0.0.1")
+        .property("port", 0u32)
+        .build()
+        .unwrap()
+        .downcast::<gst::Bin>()
+        .unwrap();
+
+    let pad = sink.request_pad_simple("sink_%u").unwrap();
+    assert_eq!(pad.name(), "sink_0");
+    assert!(sink.children().len() > 2);
+
+    // Only rtpsend and rtprecv are left, without any pads of the stream
+    sink.release_request_pad(&pad);
+    assert!(sink.static_pad("sink_0").is_none());
+    let children = sink.children();
+    assert_eq!(children.len(), 2);
+    for element in children {
+        assert!(
+            element.pads().iter().all(|p| !p.name().ends_with("_0")),
+            "{} has pads left",
+            element.name()
+        );
+    }
+
+    // The released stream is not served anymore
+    assert!(sink.set_state(gst::State::Ready).is_err());
+    sink.set_state(gst::State::Null).unwrap();
+
+    // and its index can be requested again
+    let pad = sink.request_pad_simple("sink_%u").unwrap();
+    assert_eq!(pad.name(), "sink_0");
+}
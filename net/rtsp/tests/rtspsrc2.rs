@@ -0,0 +1,515 @@
+// GStreamer RTSP Source 2 tests
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsrtsp::plugin_register_static().expect("rtspsrc2 test");
+    });
+}
+
+const SESSION_ID: &str = "12345678";
+const SDP: &str = "v=0\r\n\
+                   o=- 0 0 IN IP4 127.0.0.1\r\n\
+                   s=Test\r\n\
+                   c=IN IP4 127.0.0.1\r\n\
+                   t=0 0\r\n\
+                   m=video 0 RTP/AVP 96\r\n\
+                   a=rtpmap:96 H264/90000\r\n\
+                   a=control:stream=0\r\n";
+// On-demand media with a duration of one minute
+const VOD_SDP: &str = "v=0\r\n\
+                       o=- 0 0 IN IP4 127.0.0.1\r\n\
+                       s=Test\r\n\
+                       c=IN IP4 127.0.0.1\r\n\
+                       t=0 0\r\n\
+                       a=range:npt=0-60\r\n\
+                       m=video 0 RTP/AVP 96\r\n\
+                       a=rtpmap:96 H264/90000\r\n\
+                       a=control:stream=0\r\n";
+
+struct Request {
+    method: String,
+    headers: HashMap<String, String>,
+}
+
+/// Read the request line and the headers of an RTSP or HTTP request, `None` if the connection
+/// was closed
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let method = line.split_whitespace().next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    Some(Request { method, headers })
+}
+
+/// Decodes the messages the element writes base64 encoded into the POST request of an HTTP tunnel
+struct Base64Reader<R>(R);
+
+impl<R: Read> Read for Base64Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Every message is encoded on its own, so only groups of 4 characters can be decoded
+        let mut group = [0u8; 4];
+        match self.0.read_exact(&mut group) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+            res => res?,
+        }
+        let decoded = data_encoding::BASE64
+            .decode(&group)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        buf[..decoded.len()].copy_from_slice(&decoded);
+        Ok(decoded.len())
+    }
+}
+
+/// Connection of the mock RTSP server to the element
+struct Connection {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Connection {
+    fn accept(listener: &TcpListener) -> Self {
+        let (stream, _) = listener.accept().unwrap();
+        Connection {
+            reader: Box::new(BufReader::new(stream.try_clone().unwrap())),
+            writer: Box::new(stream),
+        }
+    }
+
+    /// Accept the GET and POST connections of an HTTP tunnel. Requests are read from the POST
+    /// connection and responses are written to the GET connection.
+    fn accept_tunnel(listener: &TcpListener) -> Self {
+        let (mut get, _) = listener.accept().unwrap();
+        let mut get_reader = BufReader::new(get.try_clone().unwrap());
+        let get_req = read_request(&mut get_reader).unwrap();
+        assert_eq!(get_req.method, "GET");
+        assert_eq!(get_req.headers["accept"], "application/x-rtsp-tunnelled");
+        get.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/x-rtsp-tunnelled\r\n\r\n")
+            .unwrap();
+
+        let (post, _) = listener.accept().unwrap();
+        let mut post_reader = BufReader::new(post);
+        let post_req = read_request(&mut post_reader).unwrap();
+        assert_eq!(post_req.method, "POST");
+        assert_eq!(
+            post_req.headers["content-type"],
+            "application/x-rtsp-tunnelled"
+        );
+
+        // Both connections are tied together by the session cookie
+        assert!(!get_req.headers["x-sessioncookie"].is_empty());
+        assert_eq!(
+            get_req.headers["x-sessioncookie"],
+            post_req.headers["x-sessioncookie"]
+        );
+
+        Connection {
+            reader: Box::new(BufReader::new(Base64Reader(post_reader))),
+            writer: Box::new(get),
+        }
+    }
+
+    /// Read the next request, `None` if the element closed the connection
+    fn next_request(&mut self) -> Option<Request> {
+        read_request(&mut self.reader)
+    }
+
+    fn expect_request(&mut self, method: &str) -> Request {
+        let req = self.next_request().expect("Connection closed");
+        assert_eq!(req.method, method);
+        req
+    }
+
+    fn respond(&mut self, req: &Request, headers: &[(&str, &str)], body: &str) {
+        let mut rsp = format!("RTSP/1.0 200 OK\r\nCSeq: {}\r\n", req.headers["cseq"]);
+        for (name, value) in headers {
+            rsp.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() {
+            rsp.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        rsp.push_str("\r\n");
+        rsp.push_str(body);
+        self.writer.write_all(rsp.as_bytes()).unwrap();
+    }
+
+    /// Send an interleaved data message
+    fn send_data(&mut self, channel: u8, data: &[u8]) {
+        let mut msg = vec![b'$', channel];
+        msg.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        msg.extend_from_slice(data);
+        self.writer.write_all(&msg).unwrap();
+    }
+
+    /// Answer the requests of the element up to PLAY, with the given SDP and session timeout
+    fn serve_until_play(&mut self, url: &str, sdp: &str, timeout: u32) {
+        let req = self.expect_request("OPTIONS");
+        self.respond(
+            &req,
+            &[("Public", "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN")],
+            "",
+        );
+
+        let req = self.expect_request("DESCRIBE");
+        self.respond(
+            &req,
+            &[
+                ("Content-Type", "application/sdp"),
+                ("Content-Base", &format!("{url}/")),
+            ],
+            sdp,
+        );
+
+        let req = self.expect_request("SETUP");
+        assert!(req.headers["transport"].contains("interleaved=0-1"));
+        self.respond(
+            &req,
+            &[
+                ("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1"),
+                ("Session", &format!("{SESSION_ID};timeout={timeout}")),
+            ],
+            "",
+        );
+
+        let req = self.expect_request("PLAY");
+        assert_eq!(req.headers["session"], SESSION_ID);
+        self.respond(&req, &[("Session", SESSION_ID)], "");
+    }
+
+    /// Answer all requests until the element closes the connection
+    fn serve_until_closed(&mut self) {
+        while let Some(req) = self.next_request() {
+            self.respond(&req, &[("Session", SESSION_ID)], "");
+        }
+    }
+
+    /// Ignore everything the element sends until it closes the connection
+    fn wait_closed(&mut self) {
+        let _ = io::copy(&mut self.reader, &mut io::sink());
+    }
+}
+
+/// An RTP packet with payload type 96 and a dummy payload
+fn rtp_packet(seqnum: u16, rtptime: u32) -> Vec<u8> {
+    let mut packet = vec![0x80, 96];
+    packet.extend_from_slice(&seqnum.to_be_bytes());
+    packet.extend_from_slice(&rtptime.to_be_bytes());
+    packet.extend_from_slice(&0x12345678u32.to_be_bytes());
+    packet.extend_from_slice(&[0x65, 0x88, 0x84, 0x00]);
+    packet
+}
+
+fn make_pipeline(url: &str, reconnect_attempts: u32) -> gst::Pipeline {
+    let src = gst::ElementFactory::make("rtspsrc2")
+        .name("src")
+        .property("location", url)
+        .property("protocols", "tcp")
+        .property("reconnect-attempts", reconnect_attempts)
+        .build()
+        .unwrap();
+    let pipeline = gst::Pipeline::new();
+    pipeline.add(&src).unwrap();
+    pipeline
+}
+
+/// Wait for a progress message of type `type_` with the given code, failing on errors
+fn wait_for_progress(pipeline: &gst::Pipeline, type_: gst::ProgressType, code: &str) {
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::from_seconds(10)) {
+        match msg.view() {
+            gst::MessageView::Progress(progress) => {
+                let (t, c, _text) = progress.get();
+                if t == type_ && c == code {
+                    return;
+                }
+            }
+            gst::MessageView::Error(err) => panic!("Unexpected error: {err:?}"),
+            _ => (),
+        }
+    }
+    panic!("No {type_:?} progress message for {code}");
+}
+
+#[test]
+fn test_keepalive() {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("rtsp://{}/test", listener.local_addr().unwrap());
+
+    let (tx, rx) = mpsc::channel();
+    let server_url = url.clone();
+    let server = thread::spawn(move || {
+        let mut conn = Connection::accept(&listener);
+        conn.serve_until_play(&server_url, SDP, 2);
+        let played = Instant::now();
+
+        // Keep-alive requests are sent at half the session timeout, with OPTIONS as the server
+        // doesn't support GET_PARAMETER
+        for _ in 0..2 {
+            let req = conn.expect_request("OPTIONS");
+            assert_eq!(req.headers["session"], SESSION_ID);
+            conn.respond(&req, &[("Session", SESSION_ID)], "");
+        }
+        tx.send(played.elapsed()).unwrap();
+
+        conn.serve_until_closed();
+    });
+
+    let pipeline = make_pipeline(&url, 3);
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let elapsed = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(elapsed >= Duration::from_millis(1500), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(4), "{elapsed:?}");
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_reconnect() {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("rtsp://{}/test", listener.local_addr().unwrap());
+
+    let server_url = url.clone();
+    let server = thread::spawn(move || {
+        // Drop the connection right after the media started playing
+        let mut conn = Connection::accept(&listener);
+        conn.serve_until_play(&server_url, SDP, 60);
+        drop(conn);
+
+        // The media is set up and played again on a new connection
+        let mut conn = Connection::accept(&listener);
+        conn.serve_until_play(&server_url, SDP, 60);
+        conn.serve_until_closed();
+    });
+
+    let pipeline = make_pipeline(&url, 3);
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    wait_for_progress(&pipeline, gst::ProgressType::Start, "reconnect");
+    wait_for_progress(&pipeline, gst::ProgressType::Complete, "reconnect");
+    wait_for_progress(&pipeline, gst::ProgressType::Complete, "request");
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_no_reconnect() {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("rtsp://{}/test", listener.local_addr().unwrap());
+
+    let server_url = url.clone();
+    let server = thread::spawn(move || {
+        let mut conn = Connection::accept(&listener);
+        conn.serve_until_play(&server_url, SDP, 60);
+    });
+
+    let pipeline = make_pipeline(&url, 0);
+    pipeline.set_state(gst::State::Playing).unwrap();
+    server.join().unwrap();
+
+    let bus = pipeline.bus().unwrap();
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(10), &[gst::MessageType::Error])
+        .expect("No error after the connection was lost");
+    let gst::MessageView::Error(err) = msg.view() else {
+        unreachable!();
+    };
+    assert!(
+        err.debug().unwrap().contains("TCP connection EOF"),
+        "{err:?}"
+    );
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_seek() {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("rtsp://{}/test", listener.local_addr().unwrap());
+
+    let (played_tx, played_rx) = mpsc::channel();
+    let (headers_tx, headers_rx) = mpsc::channel();
+    let server_url = url.clone();
+    let server = thread::spawn(move || {
+        let mut conn = Connection::accept(&listener);
+        conn.serve_until_play(&server_url, VOD_SDP, 60);
+        played_tx.send(()).unwrap();
+
+        // The seek pauses the media and plays it again from the new position
+        let req = conn.expect_request("PAUSE");
+        conn.respond(&req, &[("Session", SESSION_ID)], "");
+        let req = conn.expect_request("PLAY");
+        headers_tx.send(req.headers.clone()).unwrap();
+        conn.respond(
+            &req,
+            &[
+                ("Session", SESSION_ID),
+                ("Range", "npt=10-60"),
+                ("Scale", "2"),
+                (
+                    "RTP-Info",
+                    &format!("url={server_url}/stream=0;seq=100;rtptime=1000"),
+                ),
+            ],
+            "",
+        );
+        conn.send_data(0, &rtp_packet(100, 1000));
+
+        conn.wait_closed();
+    });
+
+    let pipeline = make_pipeline(&url, 0);
+    let src = pipeline.by_name("src").unwrap();
+    let pipeline_weak = pipeline.downgrade();
+    src.connect_pad_added(move |_src, pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+        let sink = gst::ElementFactory::make("fakesink")
+            .property("async", false)
+            .build()
+            .unwrap();
+        pipeline.add(&sink).unwrap();
+        sink.sync_state_with_parent().unwrap();
+        pad.link(&sink.static_pad("sink").unwrap()).unwrap();
+    });
+    pipeline.set_state(gst::State::Playing).unwrap();
+    played_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    let (segment_tx, segment_rx) = mpsc::channel();
+    let segment_tx = Mutex::new(segment_tx);
+    let appsrc = src
+        .downcast_ref::<gst::Bin>()
+        .unwrap()
+        .by_name("rtp_appsrc_0")
+        .unwrap();
+    appsrc.static_pad("src").unwrap().add_probe(
+        gst::PadProbeType::EVENT_DOWNSTREAM,
+        move |_pad, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if let gst::EventView::Segment(segment) = event.view() {
+                    let _ = segment_tx.lock().unwrap().send(segment.segment().clone());
+                }
+            }
+            gst::PadProbeReturn::Ok
+        },
+    );
+
+    let duration = src.query_duration::<gst::ClockTime>();
+    assert_eq!(duration, Some(gst::ClockTime::from_seconds(60)));
+    assert!(src.send_event(gst::event::Seek::new(
+        2.0,
+        gst::SeekFlags::FLUSH | gst::SeekFlags::TRICKMODE,
+        gst::SeekType::Set,
+        gst::ClockTime::from_seconds(10),
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+    )));
+
+    // Trick modes are requested with Scale instead of Speed
+    let headers = headers_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(headers["range"], "npt=10.000-");
+    assert_eq!(headers["scale"], "2");
+    assert!(!headers.contains_key("speed"));
+
+    // The new segment starts at the position and with the scale the server plays at
+    let segment = loop {
+        let segment = segment_rx
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+            .downcast::<gst::ClockTime>()
+            .unwrap();
+        if segment.time() == Some(gst::ClockTime::from_seconds(10)) {
+            break segment;
+        }
+    };
+    assert_eq!(segment.applied_rate(), 2.0);
+    assert_eq!(segment.rate(), 1.0);
+
+    let caps = appsrc.property::<Option<gst::Caps>>("caps").unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.get::<u32>("seqnum-base").unwrap(), 100);
+    assert_eq!(s.get::<u32>("clock-base").unwrap(), 1000);
+    assert_eq!(
+        s.get::<u64>("npt-start").unwrap(),
+        gst::ClockTime::from_seconds(10).nseconds()
+    );
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http_tunnel() {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let server_url = format!("rtsp://{addr}/test");
+    let server = thread::spawn(move || {
+        let mut conn = Connection::accept_tunnel(&listener);
+        conn.serve_until_play(&server_url, SDP, 60);
+        tx.send(()).unwrap();
+
+        conn.serve_until_closed();
+    });
+
+    let src = gst::ElementFactory::make("rtspsrc2")
+        .property("location", format!("rtsph://{addr}/test"))
+        .build()
+        .unwrap();
+    let pipeline = gst::Pipeline::new();
+    pipeline.add(&src).unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    server.join().unwrap();
+}
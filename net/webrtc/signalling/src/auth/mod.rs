@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst_plugin_webrtc_protocol as p;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("missing authentication token")]
+    MissingToken,
+    #[error("invalid authentication token: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+/// Claims carried by the token a peer authenticated with
+pub struct Claims {
+    /// Subject of the token, used to identify producers in `producers`
+    #[serde(default)]
+    pub sub: Option<String>,
+    /// Expiration time of the token, as a UNIX timestamp
+    #[serde(default)]
+    pub exp: Option<u64>,
+    /// Rooms the peer belongs to, producers in a room are only visible to
+    /// peers that share that room. An empty list means no restriction.
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    /// Roles the peer is allowed to register with, all roles when unset
+    #[serde(default)]
+    pub roles: Option<Vec<p::PeerRole>>,
+    /// Whether the peer may start sessions with producers, defaults to true
+    #[serde(default)]
+    pub consume: Option<bool>,
+    /// Subjects of the producers the peer may start sessions with, all
+    /// visible producers when unset
+    #[serde(default)]
+    pub producers: Option<Vec<String>>,
+}
+
+impl Claims {
+    /// Whether the peer may register with `role`
+    pub fn allows_role(&self, role: &p::PeerRole) -> bool {
+        self.roles
+            .as_ref()
+            .map_or(true, |roles| roles.contains(role))
+    }
+
+    /// Whether the peer may start a session with a producer holding `producer` claims
+    pub fn allows_consuming(&self, producer: Option<&Claims>) -> bool {
+        if !self.consume.unwrap_or(true) {
+            return false;
+        }
+
+        match self.producers {
+            None => true,
+            Some(ref producers) => producer
+                .and_then(|claims| claims.sub.as_ref())
+                .is_some_and(|sub| producers.contains(sub)),
+        }
+    }
+}
+
+/// Validates the token presented by a peer when it connects
+pub trait Authenticator: Send + Sync {
+    /// Check `token` and return the claims it carries
+    fn authenticate(&self, token: Option<&str>) -> Result<Claims, AuthError>;
+}
+
+/// Validates HS256 JSON Web Tokens signed with a shared secret
+pub struct JwtAuthenticator {
+    key: jsonwebtoken::DecodingKey,
+    validation: jsonwebtoken::Validation,
+}
+
+impl JwtAuthenticator {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: jsonwebtoken::DecodingKey::from_secret(secret),
+            validation: jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+        }
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(&self, token: Option<&str>) -> Result<Claims, AuthError> {
+        let token = token.ok_or(AuthError::MissingToken)?;
+
+        Ok(jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)?.claims)
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+/// Claims of the authenticated peers, shared between the server and the handler
+pub struct ClaimsRegistry(Arc<Mutex<HashMap<String, Claims>>>);
+
+impl ClaimsRegistry {
+    pub fn insert(&self, peer_id: &str, claims: Claims) {
+        self.0.lock().unwrap().insert(peer_id.to_string(), claims);
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<Claims> {
+        self.0.lock().unwrap().get(peer_id).cloned()
+    }
+
+    pub fn remove(&self, peer_id: &str) {
+        self.0.lock().unwrap().remove(peer_id);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Maximum rate at which a peer may send messages
+pub struct RateLimit {
+    /// Messages replenished per second
+    pub messages_per_second: u32,
+    /// Messages a peer may send in a burst
+    pub burst: u32,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    last: std::time::Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last: std::time::Instant::now(),
+        }
+    }
+
+    /// Consume a token, returns false if the peer exceeded its rate
+    pub(crate) fn check(&mut self) -> bool {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.limit.messages_per_second as f64)
+            .min(self.limit.burst as f64);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(claims: &Claims, secret: &[u8]) -> String {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_jwt_valid() {
+        let claims = Claims {
+            sub: Some("camera".to_string()),
+            exp: Some(u64::MAX / 2),
+            rooms: vec!["lobby".to_string()],
+            roles: Some(vec![p::PeerRole::Producer]),
+            ..Default::default()
+        };
+
+        let auth = JwtAuthenticator::new(b"secret");
+        assert_eq!(
+            auth.authenticate(Some(&token(&claims, b"secret"))).unwrap(),
+            claims
+        );
+    }
+
+    #[test]
+    fn test_jwt_invalid() {
+        let claims = Claims {
+            exp: Some(u64::MAX / 2),
+            ..Default::default()
+        };
+
+        let auth = JwtAuthenticator::new(b"secret");
+        assert!(matches!(
+            auth.authenticate(Some(&token(&claims, b"other"))),
+            Err(AuthError::InvalidToken(_))
+        ));
+        assert!(matches!(
+            auth.authenticate(None),
+            Err(AuthError::MissingToken)
+        ));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            messages_per_second: 1,
+            burst: 2,
+        });
+
+        assert!(limiter.check());
+        assert!(limiter.check());
+        assert!(!limiter.check());
+    }
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use clap::Parser;
+use gst_plugin_webrtc_signalling::auth::{ClaimsRegistry, JwtAuthenticator, RateLimit};
 use gst_plugin_webrtc_signalling::handlers::Handler;
 use gst_plugin_webrtc_signalling::server::{Server, ServerError};
 use tokio::io::AsyncReadExt;
@@ -8,6 +9,7 @@ use tokio::task;
 use tracing_subscriber::prelude::*;
 
 use anyhow::Error;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs;
 use tokio::net::TcpListener;
@@ -32,6 +34,20 @@ struct Args {
     /// password to TLS certificate
     #[clap(long)]
     cert_password: Option<String>,
+    /// Secret used to validate the HS256 JSON Web Tokens peers must present
+    /// when connecting, peers aren't authenticated when unset
+    #[clap(long)]
+    jwt_secret: Option<String>,
+    /// Only show producers to peers sharing the `room` field of their meta
+    #[clap(long)]
+    rooms: bool,
+    /// Maximum number of messages per second a peer may send
+    #[clap(long)]
+    rate_limit: Option<u32>,
+    /// Number of messages a peer may send in a burst when rate limited,
+    /// defaults to the rate limit
+    #[clap(long, requires = "rate_limit")]
+    rate_limit_burst: Option<u32>,
 }
 
 fn initialize_logging(envvar_name: &str) -> Result<(), Error> {
@@ -56,7 +72,20 @@ fn initialize_logging(envvar_name: &str) -> Result<(), Error> {
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let args = Args::parse();
-    let server = Server::spawn(Handler::new);
+    let claims = ClaimsRegistry::default();
+    let rate_limit = args.rate_limit.map(|messages_per_second| RateLimit {
+        messages_per_second,
+        burst: args.rate_limit_burst.unwrap_or(messages_per_second),
+    });
+    let handler_claims = claims.clone();
+    let meta_rooms = args.rooms;
+    let server = Server::spawn(move |stream| {
+        Handler::with_access_control(stream, handler_claims, rate_limit, meta_rooms)
+    });
+
+    if let Some(ref secret) = args.jwt_secret {
+        server.set_authenticator(Arc::new(JwtAuthenticator::new(secret.as_bytes())), claims);
+    }
 
     initialize_logging("WEBRTCSINK_SIGNALLING_SERVER_LOG")?;
 
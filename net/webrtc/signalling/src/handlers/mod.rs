@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: MPL-2.0
 
+use crate::auth::{ClaimsRegistry, RateLimit, RateLimiter};
 use anyhow::{anyhow, Error};
 use anyhow::{bail, Context};
 use futures::prelude::*;
@@ -44,6 +45,10 @@ pin_project! {
         sessions: HashMap<String, Session>,
         consumer_sessions: HashMap<String, HashSet<String>>,
         producer_sessions: HashMap<String, HashSet<String>>,
+        claims: ClaimsRegistry,
+        rate_limit: Option<RateLimit>,
+        rate_limiters: HashMap<PeerId, RateLimiter>,
+        meta_rooms: bool,
     }
 }
 
@@ -52,6 +57,20 @@ impl Handler {
     /// Create a handler
     pub fn new(
         stream: Pin<Box<dyn Stream<Item = (String, Option<p::IncomingMessage>)> + Send>>,
+    ) -> Self {
+        Self::with_access_control(stream, ClaimsRegistry::default(), None, false)
+    }
+
+    #[instrument(level = "debug", skip(stream, claims))]
+    /// Create a handler restricting peers according to the claims they
+    /// authenticated with, and optionally limiting the rate of their messages.
+    /// When `meta_rooms` is set, peers without rooms in their claims are
+    /// grouped by the `room` field of their meta.
+    pub fn with_access_control(
+        stream: Pin<Box<dyn Stream<Item = (String, Option<p::IncomingMessage>)> + Send>>,
+        claims: ClaimsRegistry,
+        rate_limit: Option<RateLimit>,
+        meta_rooms: bool,
     ) -> Self {
         Self {
             stream,
@@ -60,6 +79,10 @@ impl Handler {
             sessions: Default::default(),
             consumer_sessions: Default::default(),
             producer_sessions: Default::default(),
+            claims,
+            rate_limit,
+            rate_limiters: Default::default(),
+            meta_rooms,
         }
     }
 
@@ -69,6 +92,10 @@ impl Handler {
         peer_id: &str,
         msg: p::IncomingMessage,
     ) -> Result<(), Error> {
+        if !matches!(msg, p::IncomingMessage::NewPeer) {
+            self.check_rate_limit(peer_id)?;
+        }
+
         match msg {
             p::IncomingMessage::NewPeer => {
                 self.peers.insert(peer_id.to_string(), Default::default());
@@ -91,6 +118,58 @@ impl Handler {
         }
     }
 
+    fn check_rate_limit(&mut self, peer_id: &str) -> Result<(), Error> {
+        let Some(rate_limit) = self.rate_limit else {
+            return Ok(());
+        };
+
+        if !self
+            .rate_limiters
+            .entry(peer_id.to_string())
+            .or_insert_with(|| RateLimiter::new(rate_limit))
+            .check()
+        {
+            bail!("Peer '{peer_id}' exceeded the rate limit, message dropped");
+        }
+
+        Ok(())
+    }
+
+    /// Rooms a peer belongs to, from its claims or else from the `room` field
+    /// of its meta if enabled
+    fn rooms(&self, peer_id: &str, status: Option<&PeerStatus>) -> Vec<String> {
+        if let Some(claims) = self
+            .claims
+            .get(peer_id)
+            .filter(|claims| !claims.rooms.is_empty())
+        {
+            return claims.rooms;
+        }
+
+        if !self.meta_rooms {
+            return vec![];
+        }
+
+        status
+            .and_then(|status| status.meta.as_ref())
+            .and_then(|meta| meta.get("room"))
+            .and_then(|room| room.as_str())
+            .map(|room| vec![room.to_string()])
+            .unwrap_or_default()
+    }
+
+    /// Whether `viewer_id` may see the producer `producer_id`
+    fn is_visible(&self, viewer_id: &str, producer_id: &str, producer: &PeerStatus) -> bool {
+        let producer_rooms = self.rooms(producer_id, Some(producer));
+        if producer_rooms.is_empty() {
+            return true;
+        }
+
+        self.rooms(viewer_id, self.peers.get(viewer_id))
+            .iter()
+            .any(|room| producer_rooms.contains(room))
+    }
+
     fn handle_peer_message(&mut self, peer_id: &str, peermsg: p::PeerMessage) -> Result<(), Error> {
         let session_id = &peermsg.session_id;
         let session = self
@@ -152,9 +231,10 @@ impl Handler {
 
         self.stop_producer(peer_id);
         self.stop_consumer(peer_id);
+        self.rate_limiters.remove(peer_id);
 
         for (id, p) in self.peers.iter() {
-            if !p.listening() {
+            if !p.listening() || !self.is_visible(id, peer_id, &peer_status) {
                 continue;
             }
 
@@ -165,6 +245,8 @@ impl Handler {
             });
             self.items.push_back((id.to_string(), message));
         }
+
+        self.claims.remove(peer_id);
     }
 
     #[instrument(level = "debug", skip(self))]
@@ -200,21 +282,19 @@ impl Handler {
     /// List producer peers
     #[instrument(level = "debug", skip(self))]
     fn list_producers(&mut self, peer_id: &str) -> Result<(), Error> {
-        self.items.push_back((
-            peer_id.to_string(),
-            p::OutgoingMessage::List {
-                producers: self
-                    .peers
-                    .iter()
-                    .filter_map(|(peer_id, peer)| {
-                        peer.producing().then_some(p::Peer {
-                            id: peer_id.clone(),
-                            meta: peer.meta.clone(),
-                        })
-                    })
-                    .collect(),
-            },
-        ));
+        let producers = self
+            .peers
+            .iter()
+            .filter_map(|(id, peer)| {
+                (peer.producing() && self.is_visible(peer_id, id, peer)).then_some(p::Peer {
+                    id: id.clone(),
+                    meta: peer.meta.clone(),
+                })
+            })
+            .collect();
+
+        self.items
+            .push_back((peer_id.to_string(), p::OutgoingMessage::List { producers }));
 
         Ok(())
     }
@@ -225,14 +305,21 @@ impl Handler {
         let old_status = self
             .peers
             .get(peer_id)
-            .context(anyhow!("Peer '{peer_id}' hasn't been welcomed"))?;
+            .context(anyhow!("Peer '{peer_id}' hasn't been welcomed"))?
+            .clone();
 
-        if status == old_status {
+        if status == &old_status {
             info!("Status for '{}' hasn't changed", peer_id);
 
             return Ok(());
         }
 
+        if let Some(claims) = self.claims.get(peer_id) {
+            if let Some(role) = status.roles.iter().find(|role| !claims.allows_role(role)) {
+                bail!("Peer '{peer_id}' is not allowed to register as {role:?}");
+            }
+        }
+
         if old_status.producing() && !status.producing() {
             self.stop_producer(peer_id);
         }
@@ -245,13 +332,26 @@ impl Handler {
                 continue;
             }
 
-            self.items.push_back((
-                id.to_string(),
-                p::OutgoingMessage::PeerStatusChanged(p::PeerStatus {
+            let message = if self.is_visible(id, peer_id, &status) {
+                p::PeerStatus {
                     peer_id: Some(peer_id.to_string()),
                     roles: status.roles.clone(),
                     meta: status.meta.clone(),
-                }),
+                }
+            } else if old_status.producing() && self.is_visible(id, peer_id, &old_status) {
+                // The producer moved out of the listener's rooms
+                p::PeerStatus {
+                    peer_id: Some(peer_id.to_string()),
+                    roles: Default::default(),
+                    meta: old_status.meta.clone(),
+                }
+            } else {
+                continue;
+            };
+
+            self.items.push_back((
+                id.to_string(),
+                p::OutgoingMessage::PeerStatusChanged(message),
             ));
         }
 
@@ -268,24 +368,35 @@ impl Handler {
         consumer_id: &str,
         offer: Option<&str>,
     ) -> Result<(), Error> {
-        self.peers.get(producer_id).map_or_else(
-            || Err(anyhow!("No producer with ID: '{producer_id}'")),
-            |peer| {
-                if !peer.producing() {
-                    Err(anyhow!(
-                        "Peer with id {} is not registered as a producer",
-                        producer_id
-                    ))
-                } else {
-                    Ok(peer)
-                }
-            },
-        )?;
+        self.peers
+            .get(producer_id)
+            .filter(|peer| self.is_visible(consumer_id, producer_id, peer))
+            .map_or_else(
+                || Err(anyhow!("No producer with ID: '{producer_id}'")),
+                |peer| {
+                    if !peer.producing() {
+                        Err(anyhow!(
+                            "Peer with id {} is not registered as a producer",
+                            producer_id
+                        ))
+                    } else {
+                        Ok(peer)
+                    }
+                },
+            )?;
 
         self.peers
             .get(consumer_id)
             .map_or_else(|| Err(anyhow!("No consumer with ID: '{consumer_id}'")), Ok)?;
 
+        if let Some(claims) = self.claims.get(consumer_id) {
+            if !claims.allows_consuming(self.claims.get(producer_id).as_ref()) {
+                bail!(
+                    "Peer '{consumer_id}' is not allowed to start a session with '{producer_id}'"
+                );
+            }
+        }
+
         let session_id = uuid::Uuid::new_v4().to_string();
         self.sessions.insert(
             session_id.clone(),
@@ -362,6 +473,7 @@ impl Stream for Handler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::Claims;
     use futures::channel::mpsc;
     use serde_json::json;
 
@@ -1441,4 +1553,266 @@ mod tests {
             .get(&session0_id)
             .expect("Session should remain");
     }
+
+    #[tokio::test]
+    async fn test_room_visibility() {
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut handler =
+            Handler::with_access_control(Box::pin(rx), ClaimsRegistry::default(), None, true);
+
+        new_peer(&mut tx, &mut handler, "producer").await;
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Producer],
+            meta: Some(json!({"room": "a"})),
+            peer_id: None,
+        });
+        tx.send(("producer".to_string(), Some(message)))
+            .await
+            .unwrap();
+
+        new_peer(&mut tx, &mut handler, "listener").await;
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Listener],
+            meta: Some(json!({"room": "b"})),
+            peer_id: None,
+        });
+        tx.send(("listener".to_string(), Some(message)))
+            .await
+            .unwrap();
+        let _ = handler.next().await.unwrap();
+
+        tx.send(("listener".to_string(), Some(p::IncomingMessage::List)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::List { producers: vec![] }
+            )
+        );
+
+        let message = p::IncomingMessage::StartSession(p::StartSessionMessage {
+            peer_id: "producer".to_string(),
+            offer: None,
+        });
+        tx.send(("listener".to_string(), Some(message)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::Error {
+                    details: "No producer with ID: 'producer'".into()
+                }
+            )
+        );
+
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Listener],
+            meta: Some(json!({"room": "a"})),
+            peer_id: None,
+        });
+        tx.send(("listener".to_string(), Some(message)))
+            .await
+            .unwrap();
+        let _ = handler.next().await.unwrap();
+
+        tx.send(("listener".to_string(), Some(p::IncomingMessage::List)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::List {
+                    producers: vec![p::Peer {
+                        id: "producer".to_string(),
+                        meta: Some(json!({"room": "a"})),
+                    }]
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_meta_room_ignored_by_default() {
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut handler = Handler::new(Box::pin(rx));
+
+        new_peer(&mut tx, &mut handler, "producer").await;
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Producer],
+            meta: Some(json!({"room": "a"})),
+            peer_id: None,
+        });
+        tx.send(("producer".to_string(), Some(message)))
+            .await
+            .unwrap();
+
+        new_peer(&mut tx, &mut handler, "listener").await;
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Listener],
+            meta: Some(json!({"room": "b"})),
+            peer_id: None,
+        });
+        tx.send(("listener".to_string(), Some(message)))
+            .await
+            .unwrap();
+        let _ = handler.next().await.unwrap();
+
+        tx.send(("listener".to_string(), Some(p::IncomingMessage::List)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::List {
+                    producers: vec![p::Peer {
+                        id: "producer".to_string(),
+                        meta: Some(json!({"room": "a"})),
+                    }]
+                }
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_claims_permissions() {
+        let (mut tx, rx) = mpsc::unbounded();
+        let claims = ClaimsRegistry::default();
+        let mut handler = Handler::with_access_control(Box::pin(rx), claims.clone(), None, false);
+
+        claims.insert(
+            "producer",
+            Claims {
+                sub: Some("camera".to_string()),
+                rooms: vec!["lobby".to_string()],
+                roles: Some(vec![p::PeerRole::Producer]),
+                ..Default::default()
+            },
+        );
+        new_peer(&mut tx, &mut handler, "producer").await;
+
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Listener],
+            meta: None,
+            peer_id: None,
+        });
+        tx.send(("producer".to_string(), Some(message)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "producer".to_string(),
+                p::OutgoingMessage::Error {
+                    details: "Peer 'producer' is not allowed to register as Listener".into()
+                }
+            )
+        );
+
+        let message = p::IncomingMessage::SetPeerStatus(p::PeerStatus {
+            roles: vec![p::PeerRole::Producer],
+            meta: None,
+            peer_id: None,
+        });
+        tx.send(("producer".to_string(), Some(message)))
+            .await
+            .unwrap();
+
+        claims.insert(
+            "viewer",
+            Claims {
+                rooms: vec!["lobby".to_string()],
+                consume: Some(false),
+                ..Default::default()
+            },
+        );
+        new_peer(&mut tx, &mut handler, "viewer").await;
+
+        let message = p::IncomingMessage::StartSession(p::StartSessionMessage {
+            peer_id: "producer".to_string(),
+            offer: None,
+        });
+        tx.send(("viewer".to_string(), Some(message)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "viewer".to_string(),
+                p::OutgoingMessage::Error {
+                    details: "Peer 'viewer' is not allowed to start a session with 'producer'"
+                        .into()
+                }
+            )
+        );
+
+        claims.insert(
+            "consumer",
+            Claims {
+                rooms: vec!["lobby".to_string()],
+                producers: Some(vec!["camera".to_string()]),
+                ..Default::default()
+            },
+        );
+        new_peer(&mut tx, &mut handler, "consumer").await;
+
+        let message = p::IncomingMessage::StartSession(p::StartSessionMessage {
+            peer_id: "producer".to_string(),
+            offer: None,
+        });
+        tx.send(("consumer".to_string(), Some(message)))
+            .await
+            .unwrap();
+        let (peer_id, sent_message) = handler.next().await.unwrap();
+        assert_eq!(peer_id, "consumer");
+        assert!(matches!(
+            sent_message,
+            p::OutgoingMessage::SessionStarted { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut handler = Handler::with_access_control(
+            Box::pin(rx),
+            ClaimsRegistry::default(),
+            Some(RateLimit {
+                messages_per_second: 1,
+                burst: 1,
+            }),
+            false,
+        );
+
+        new_peer(&mut tx, &mut handler, "listener").await;
+
+        tx.send(("listener".to_string(), Some(p::IncomingMessage::List)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::List { producers: vec![] }
+            )
+        );
+
+        tx.send(("listener".to_string(), Some(p::IncomingMessage::List)))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.next().await.unwrap(),
+            (
+                "listener".to_string(),
+                p::OutgoingMessage::Error {
+                    details: "Peer 'listener' exceeded the rate limit, message dropped".into()
+                }
+            )
+        );
+    }
 }
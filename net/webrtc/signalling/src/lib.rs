@@ -1,4 +1,5 @@
 // SPDX-License-Identifier: MPL-2.0
 
+pub mod auth;
 pub mod handlers;
 pub mod server;
@@ -1,9 +1,13 @@
 // SPDX-License-Identifier: MPL-2.0
 
+use crate::auth::{AuthError, Authenticator, Claims, ClaimsRegistry};
 use anyhow::Error;
+use async_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use async_tungstenite::tungstenite::http;
 use async_tungstenite::tungstenite::Message as WsMessage;
 use futures::channel::mpsc;
 use futures::prelude::*;
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::pin::Pin;
@@ -21,6 +25,8 @@ struct Peer {
 struct State {
     tx: Option<mpsc::Sender<(String, Option<String>)>>,
     peers: HashMap<String, Peer>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    claims: ClaimsRegistry,
 }
 
 #[derive(Clone)]
@@ -36,6 +42,30 @@ pub enum ServerError {
     TLSHandshake(#[from] tokio_native_tls::native_tls::Error),
     #[error("timeout during TLS handshake {0}")]
     TLSHandshakeTimeout(#[from] tokio::time::error::Elapsed),
+    #[error("authentication failed {0}")]
+    Authentication(#[from] AuthError),
+}
+
+/// Extract the token from an `Authorization: Bearer` header, or else from
+/// a `token` query parameter for clients that can't set headers such as
+/// browsers
+fn request_token(req: &Request) -> Option<String> {
+    if let Some(token) = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(token.trim().to_string());
+    }
+
+    req.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|param| param.strip_prefix("token="))
+            .and_then(|token| percent_decode_str(token).decode_utf8().ok())
+            .map(String::from)
+    })
 }
 
 impl Server {
@@ -66,6 +96,8 @@ impl Server {
         let state = Arc::new(Mutex::new(State {
             tx: Some(tx),
             peers: HashMap::new(),
+            authenticator: None,
+            claims: ClaimsRegistry::default(),
         }));
 
         let state_clone = state.clone();
@@ -97,6 +129,14 @@ impl Server {
         Self { state }
     }
 
+    /// Require peers to authenticate when connecting, the claims of
+    /// authenticated peers are stored in `claims` for the handler to enforce
+    pub fn set_authenticator(&self, authenticator: Arc<dyn Authenticator>, claims: ClaimsRegistry) {
+        let mut state = self.state.lock().unwrap();
+        state.authenticator = Some(authenticator);
+        state.claims = claims;
+    }
+
     #[instrument(level = "debug", skip(state))]
     fn remove_peer(state: Arc<Mutex<State>>, peer_id: &str) {
         if let Some(mut peer) = state.lock().unwrap().peers.remove(peer_id) {
@@ -119,9 +159,37 @@ impl Server {
         &mut self,
         stream: S,
     ) -> Result<String, ServerError> {
-        let ws = match async_tungstenite::tokio::accept_async(stream).await {
+        let authenticator = self.state.lock().unwrap().authenticator.clone();
+        let mut auth_result: Option<Result<Claims, AuthError>> = None;
+        let callback = |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+            let Some(authenticator) = authenticator else {
+                return Ok(resp);
+            };
+
+            match authenticator.authenticate(request_token(req).as_deref()) {
+                Ok(claims) => {
+                    auth_result = Some(Ok(claims));
+                    Ok(resp)
+                }
+                Err(err) => {
+                    let details = err.to_string();
+                    auth_result = Some(Err(err));
+                    Err(http::Response::builder()
+                        .status(http::StatusCode::UNAUTHORIZED)
+                        .body(Some(details))
+                        .unwrap())
+                }
+            }
+        };
+
+        let res = async_tungstenite::tokio::accept_hdr_async(stream, callback).await;
+        let ws = match res {
             Ok(ws) => ws,
             Err(err) => {
+                if let Some(Err(err)) = auth_result {
+                    warn!("Rejecting unauthenticated connection: {}", err);
+                    return Err(ServerError::Authentication(err));
+                }
                 warn!("Error during the websocket handshake: {}", err);
                 return Err(ServerError::Handshake(err));
             }
@@ -130,6 +198,11 @@ impl Server {
         let this_id = uuid::Uuid::new_v4().to_string();
         info!(this_id = %this_id, "New WebSocket connection");
 
+        if let Some(Ok(claims)) = auth_result {
+            info!(this_id = %this_id, subject = ?claims.sub, "Peer authenticated");
+            self.state.lock().unwrap().claims.insert(&this_id, claims);
+        }
+
         // 1000 is completely arbitrary, we simply don't want infinite piling
         // up of messages as with unbounded
         let (websocket_sender, mut websocket_receiver) = mpsc::channel::<String>(1000);
@@ -235,3 +308,36 @@ impl Server {
         Ok(this_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_token() {
+        let req = Request::builder()
+            .uri("wss://localhost:8443/?foo=bar&token=a%2Eb%2Ec")
+            .body(())
+            .unwrap();
+        assert_eq!(request_token(&req).as_deref(), Some("a.b.c"));
+
+        let req = Request::builder()
+            .uri("wss://localhost:8443/?token=query")
+            .header(http::header::AUTHORIZATION, "Bearer header")
+            .body(())
+            .unwrap();
+        assert_eq!(request_token(&req).as_deref(), Some("header"));
+
+        let req = Request::builder()
+            .uri("wss://localhost:8443/")
+            .body(())
+            .unwrap();
+        assert_eq!(request_token(&req), None);
+
+        let req = Request::builder()
+            .uri("wss://localhost:8443/?token=%FF")
+            .body(())
+            .unwrap();
+        assert_eq!(request_token(&req), None);
+    }
+}
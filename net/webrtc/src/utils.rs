@@ -380,20 +380,18 @@ pub fn build_link_header(url_str: &str) -> Result<String, url::ParseError> {
         link_str = link_str + "?" + query;
     }
 
-    link_str += ">";
+    link_str += ">; rel=\"ice-server\"";
 
     if let Some(password) = url.password() {
         link_str = link_str
-            + "; "
-            + "rel=\"ice-server\""
             + "; "
             + "username=\""
             + url.username()
             + "\"; "
-            + "credential:\""
+            + "credential=\""
             + password
             + "\"; "
-            + "credential-type:\"password\";";
+            + "credential-type=\"password\"";
     }
 
     Ok(link_str)
@@ -6,7 +6,7 @@ use gst::{
 };
 use once_cell::sync::Lazy;
 
-use super::imp::VideoEncoder;
+use super::imp::{n_encoded_streams, set_encoders_bitrate, VideoEncoder};
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -341,7 +341,7 @@ impl CongestionController {
             control_op
         );
 
-        let n_encoders = encoders.len() as i32;
+        let n_encoders = n_encoded_streams(encoders) as i32;
         let prev_bitrate = i32::min(self.target_bitrate_on_delay, self.target_bitrate_on_loss);
         match &control_op {
             CongestionControlOp::Hold => {}
@@ -412,12 +412,6 @@ impl CongestionController {
 
         let fec_percentage = (fec_ratio * 50f64) as u32;
 
-        for encoder in encoders.iter_mut() {
-            if encoder.set_bitrate(element, target_bitrate).is_ok() {
-                encoder
-                    .transceiver
-                    .set_property("fec-percentage", fec_percentage);
-            }
-        }
+        set_encoders_bitrate(element, encoders, |_| target_bitrate, fec_percentage);
     }
 }
@@ -31,6 +31,7 @@ use std::sync::{mpsc, Arc, Condvar, Mutex};
 use super::homegrown_cc::CongestionController;
 use super::{
     WebRTCSinkCongestionControl, WebRTCSinkError, WebRTCSinkMitigationMode, WebRTCSinkPad,
+    WebRTCSinkSharedEncoderPolicy,
 };
 use crate::signaller::{prelude::*, Signallable, Signaller, WebRTCSignallerRole};
 use crate::{utils, RUNTIME};
@@ -52,6 +53,8 @@ const D3D11_MEMORY_FEATURE: &str = "memory:D3D11Memory";
 
 const RTP_TWCC_URI: &str =
     "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+const RTP_STREAM_ID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+const RTP_MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
 
 const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
@@ -87,6 +90,17 @@ const DEFAULT_WEB_SERVER_DIRECTORY: &str = "gstwebrtc-api/dist";
 #[cfg(feature = "web_server")]
 const DEFAULT_WEB_SERVER_HOST_ADDR: &str = "http://127.0.0.1:8080";
 const DEFAULT_FORWARD_METAS: &str = "";
+const DEFAULT_SIMULCAST_LAYERS: u32 = 1;
+/* Identifiers of the simulcast layers, from the highest to the lowest resolution */
+const SIMULCAST_RIDS: [&str; 3] = ["h", "m", "l"];
+/* Minimum bitrate of the lowest simulcast layer, higher layers require
+ * proportionally more according to their number of pixels */
+const SIMULCAST_MIN_LAYER_BITRATE: u32 = 100000;
+const DEFAULT_SHARED_ENCODER_POLICY: WebRTCSinkSharedEncoderPolicy =
+    WebRTCSinkSharedEncoderPolicy::Disabled;
+/// Keyframe requests for a shared encoder are coalesced until it produces
+/// a keyframe, or for this long at most
+const KEYFRAME_REQUEST_COALESCING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
 /* Start adding some FEC when the bitrate > 2Mbps as we found experimentally
  * that it is not worth it below that threshold */
 #[cfg(feature = "v1_22")]
@@ -129,6 +143,8 @@ struct Settings {
     #[cfg(feature = "web_server")]
     web_server_host_addr: url::Url,
     forward_metas: HashSet<String>,
+    simulcast_layers: u32,
+    shared_encoder_policy: WebRTCSinkSharedEncoderPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -276,6 +292,33 @@ struct WebRTCPad {
     stream_name: Option<String>,
     /// The payload selected in the answer, None at first
     payload: Option<i32>,
+    /// The simulcast layers sent over this pad, empty when simulcast is not used
+    simulcast_layers: Vec<SimulcastLayer>,
+}
+
+/// A spatial layer of a simulcast video stream
+#[derive(Clone, Debug)]
+struct SimulcastLayer {
+    /// The RTP stream identifier, signalled with a=rid
+    rid: String,
+    ssrc: u32,
+    /// Factor by which the input resolution is scaled down
+    scale_down_by: u32,
+}
+
+impl SimulcastLayer {
+    /// Share of the stream bitrate relative to the full resolution
+    fn weight(&self) -> f64 {
+        1. / (self.scale_down_by * self.scale_down_by) as f64
+    }
+}
+
+/// The simulcast layer produced by a VideoEncoder
+struct EncoderLayer {
+    layer: SimulcastLayer,
+    /// Drops the raw input when the layer is turned off
+    valve: gst::Element,
+    active: bool,
 }
 
 /// Wrapper around GStreamer encoder element, keeps track of factory
@@ -294,6 +337,47 @@ pub struct VideoEncoder {
     pub transceiver: gst_webrtc::WebRTCRTPTransceiver,
     /// name of the sink pad feeding this encoder
     stream_name: String,
+    /// The simulcast layer produced by this encoder, if any
+    layer: Option<EncoderLayer>,
+    /// Set when the encoder is shared with other consumers
+    shared_bitrates: Option<Arc<Mutex<SharedEncoderBitrates>>>,
+    /// ULPFEC encoder protecting the stream, if FEC is enabled
+    fec_encoder: Option<gst::Element>,
+}
+
+/// Bitrates requested for a shared encoder by its consumers
+#[derive(Debug)]
+struct SharedEncoderBitrates {
+    policy: WebRTCSinkSharedEncoderPolicy,
+    /// Requested bitrate by session id
+    requested: HashMap<String, i32>,
+}
+
+/// Keyframe requests of the consumers of a shared encoder, only the
+/// first request is forwarded until a keyframe was produced
+#[derive(Debug, Default)]
+struct KeyframeRequests {
+    requested: Option<std::time::Instant>,
+}
+
+/// The elements of a shared encoder that its consumers control
+#[derive(Clone)]
+struct SharedEncoderHandle {
+    encoder: gst::Element,
+    raw_filter: gst::Element,
+    bitrates: Arc<Mutex<SharedEncoderBitrates>>,
+}
+
+/// A video encoder running in its own pipeline, the encoded
+/// stream is consumed by the sessions using it
+struct SharedEncoder {
+    pipeline: gst::Pipeline,
+    producer: StreamProducer,
+    /// Link from the producer of the raw input stream
+    link: gst_utils::ConsumptionLink,
+    handle: SharedEncoderHandle,
+    /// Ids of the sessions consuming the encoded stream
+    sessions: HashSet<String>,
 }
 
 struct Session {
@@ -516,6 +600,8 @@ struct State {
     web_join_handle: Option<tokio::task::JoinHandle<()>>,
     session_mids: HashMap<String, HashMap<String, String>>,
     session_stream_names: HashMap<String, HashMap<String, String>>,
+    /// Encoders shared between consumers, by input stream name and codec caps
+    shared_encoders: HashMap<(String, String), SharedEncoder>,
 }
 
 fn create_navigation_event(sink: &super::BaseWebRTCSink, msg: &str, session_id: &str) {
@@ -694,6 +780,8 @@ impl Default for Settings {
             #[cfg(feature = "web_server")]
             web_server_host_addr: url::Url::parse(DEFAULT_WEB_SERVER_HOST_ADDR).unwrap(),
             forward_metas: HashSet::new(),
+            simulcast_layers: DEFAULT_SIMULCAST_LAYERS,
+            shared_encoder_policy: DEFAULT_SHARED_ENCODER_POLICY,
         }
     }
 }
@@ -721,6 +809,7 @@ impl Default for State {
             web_join_handle: None,
             session_mids: HashMap::new(),
             session_stream_names: HashMap::new(),
+            shared_encoders: HashMap::new(),
         }
     }
 }
@@ -1006,6 +1095,69 @@ fn setup_signal_accumulator(
     continue_emission
 }
 
+/// Returns the ID the header extension `uri` is mapped to in `s`
+fn extension_id(s: &gst::StructureRef, uri: &str) -> Option<u32> {
+    s.iter().find_map(|(field, value)| {
+        let id = field.strip_prefix("extmap-")?.parse::<u32>().ok()?;
+
+        // Either only the URI, or an array of direction, URI and attributes
+        let ext_uri = match value.get::<String>() {
+            Ok(ext_uri) => ext_uri,
+            Err(_) => value
+                .get::<gst::Array>()
+                .ok()?
+                .get(1)?
+                .get::<String>()
+                .ok()?,
+        };
+
+        (ext_uri == uri).then_some(id)
+    })
+}
+
+/// Returns the payload type of the ULPFEC format negotiated in `media`
+fn ulpfec_pt(media: &gst_sdp::SDPMediaRef) -> Option<u8> {
+    media.formats().find_map(|format| {
+        let pt = format.parse::<i32>().ok()?;
+        let caps = media.caps_from_media(pt)?;
+        let s = caps.structure(0)?;
+
+        s.get::<&str>("encoding-name")
+            .is_ok_and(|encoding_name| encoding_name.eq_ignore_ascii_case("ULPFEC"))
+            .then_some(pt as u8)
+    })
+}
+
+/// Appends the elements converting raw data with `input_caps` and encoding
+/// it with `codec` to `elements`, returns the raw capsfilter and the encoder
+fn build_encoding_elements(
+    input_caps: &gst::Caps,
+    codec: &Codec,
+    elements: &mut Vec<gst::Element>,
+) -> Result<(gst::Element, Option<gst::Element>), Error> {
+    elements.push(match codec.is_video() {
+        true => make_converter_for_video_caps(input_caps, codec)?.upcast(),
+        false => gst::parse::bin_from_description("audioresample ! audioconvert", true)?.upcast(),
+    });
+
+    let raw_filter = codec.raw_converter_filter()?;
+    elements.push(raw_filter.clone());
+
+    let encoder = if codec.is_raw {
+        None
+    } else {
+        let encoder = codec
+            .build_encoder()
+            .expect("We should always have an encoder for negotiated codecs")?;
+        elements.push(encoder.clone());
+        elements.push(make_element("capsfilter", None)?);
+
+        Some(encoder)
+    };
+
+    Ok((raw_filter, encoder))
+}
+
 /// Set of elements used in an EncodingChain
 struct EncodingChain {
     raw_filter: Option<gst::Element>,
@@ -1065,28 +1217,8 @@ impl PayloadChainBuilder {
         let mut elements: Vec<gst::Element> = Vec::new();
 
         let (raw_filter, encoder) = if needs_encoding {
-            elements.push(match self.codec.is_video() {
-                true => make_converter_for_video_caps(&self.input_caps, &self.codec)?.upcast(),
-                false => {
-                    gst::parse::bin_from_description("audioresample ! audioconvert", true)?.upcast()
-                }
-            });
-
-            let raw_filter = self.codec.raw_converter_filter()?;
-            elements.push(raw_filter.clone());
-
-            let encoder = if self.codec.is_raw {
-                None
-            } else {
-                let encoder = self
-                    .codec
-                    .build_encoder()
-                    .expect("We should always have an encoder for negotiated codecs")?;
-                elements.push(encoder.clone());
-                elements.push(make_element("capsfilter", None)?);
-
-                Some(encoder)
-            };
+            let (raw_filter, encoder) =
+                build_encoding_elements(&self.input_caps, &self.codec, &mut elements)?;
 
             (Some(raw_filter), encoder)
         } else {
@@ -1164,9 +1296,26 @@ impl VideoEncoder {
             mitigation_mode: WebRTCSinkMitigationMode::NONE,
             transceiver,
             stream_name,
+            layer: None,
+            shared_bitrates: None,
+            fec_encoder: None,
         })
     }
 
+    /// Sets the number of FEC packets in percent of the number of media
+    /// packets, if FEC is enabled
+    fn set_fec_percentage(&self, fec_percentage: u32) {
+        if let Some(ref fec_encoder) = self.fec_encoder {
+            fec_encoder.set_property("percentage", fec_percentage);
+        }
+    }
+
+    fn fec_percentage(&self) -> u32 {
+        self.fec_encoder
+            .as_ref()
+            .map_or(0, |fec_encoder| fec_encoder.property::<u32>("percentage"))
+    }
+
     fn is_bitrate_supported(factory_name: &str) -> bool {
         matches!(
             factory_name,
@@ -1222,6 +1371,11 @@ impl VideoEncoder {
         element: &super::BaseWebRTCSink,
         bitrate: i32,
     ) -> Result<(), WebRTCSinkError> {
+        let bitrate = match self.shared_bitrates {
+            Some(ref bitrates) => bitrates.lock().unwrap().request(&self.session_id, bitrate),
+            None => bitrate,
+        };
+
         match self.factory_name.as_str() {
             "vp8enc" | "vp9enc" => self.element.set_property("target-bitrate", bitrate),
             "av1enc" => self
@@ -1242,9 +1396,28 @@ impl VideoEncoder {
         let current_caps = self.filter.property::<gst::Caps>("caps");
         let mut s = current_caps.structure(0).unwrap().to_owned();
 
-        // Hardcoded thresholds, may be tuned further in the future, and
-        // adapted according to the codec in use
-        if bitrate < 500000 {
+        if let Some(ref layer) = self.layer {
+            // Simulcast layers have a fixed resolution, lower bitrates are
+            // handled by turning off the higher layers instead
+            let scale_down_by = layer.layer.scale_down_by;
+            if scale_down_by > 1 {
+                let height = i32::max((self.video_info.height() / scale_down_by) as i32 & !1, 2);
+                let width = self.scale_height_round_2(height);
+
+                s.set("height", height);
+                s.set("width", width);
+
+                self.mitigation_mode = WebRTCSinkMitigationMode::DOWNSCALED;
+            } else {
+                s.remove_field("height");
+                s.remove_field("width");
+
+                self.mitigation_mode = WebRTCSinkMitigationMode::NONE;
+            }
+            s.remove_field("framerate");
+        } else if bitrate < 500000 {
+            // Hardcoded thresholds, may be tuned further in the future, and
+            // adapted according to the codec in use
             let height = 360i32.min(self.video_info.height() as i32);
             let width = self.scale_height_round_2(height);
 
@@ -1304,16 +1477,337 @@ impl VideoEncoder {
         Ok(())
     }
 
+    /// Turns the simulcast layer produced by this encoder on or off
+    fn set_active(&mut self, element: &super::BaseWebRTCSink, active: bool) {
+        let Some(layer) = self.layer.as_mut() else {
+            return;
+        };
+
+        if layer.active == active {
+            return;
+        }
+
+        gst::info!(
+            CAT,
+            obj = element,
+            "session {}: turning {} simulcast layer {} of stream {}",
+            self.session_id,
+            if active { "on" } else { "off" },
+            layer.layer.rid,
+            self.stream_name,
+        );
+
+        layer.active = active;
+        layer.valve.set_property("drop", !active);
+
+        if active {
+            // Receivers can only switch to the layer on a keyframe
+            self.element.send_event(
+                gst_video::DownstreamForceKeyUnitEvent::builder()
+                    .all_headers(true)
+                    .build(),
+            );
+        }
+    }
+
     fn gather_stats(&self) -> gst::Structure {
-        gst::Structure::builder("application/x-webrtcsink-video-encoder-stats")
+        let mut s = gst::Structure::builder("application/x-webrtcsink-video-encoder-stats")
             .field("bitrate", self.bitrate().unwrap_or(0i32))
             .field("mitigation-mode", self.mitigation_mode)
             .field("codec-name", self.codec_name.as_str())
-            .field(
-                "fec-percentage",
-                self.transceiver.property::<u32>("fec-percentage"),
+            .field("fec-percentage", self.fec_percentage())
+            .build();
+
+        if let Some(ref layer) = self.layer {
+            s.set("rid", layer.layer.rid.as_str());
+            s.set("active", layer.active);
+        }
+
+        s
+    }
+}
+
+/// Number of input streams the encoders belong to, the simulcast
+/// layers of a stream are only counted once
+pub(crate) fn n_encoded_streams(encoders: &[VideoEncoder]) -> usize {
+    encoders
+        .iter()
+        .map(|encoder| &encoder.stream_name)
+        .unique()
+        .count()
+}
+
+/// Sets the bitrate returned by `stream_bitrate` for the encoders of each
+/// input stream.
+///
+/// The bitrate of a simulcast stream is distributed among its layers
+/// according to their number of pixels, starting with the lowest layer.
+/// Higher layers that can't get their minimum bitrate are turned off.
+pub(crate) fn set_encoders_bitrate(
+    element: &super::BaseWebRTCSink,
+    encoders: &mut [VideoEncoder],
+    stream_bitrate: impl Fn(&str) -> i32,
+    fec_percentage: u32,
+) {
+    let stream_names = encoders
+        .iter()
+        .map(|encoder| encoder.stream_name.clone())
+        .unique()
+        .collect::<Vec<_>>();
+
+    for stream_name in stream_names {
+        let bitrate = stream_bitrate(&stream_name);
+
+        let mut stream_encoders = encoders
+            .iter_mut()
+            .filter(|encoder| encoder.stream_name == stream_name)
+            .collect::<Vec<_>>();
+        stream_encoders.sort_by_key(|encoder| {
+            std::cmp::Reverse(
+                encoder
+                    .layer
+                    .as_ref()
+                    .map_or(1, |layer| layer.layer.scale_down_by),
             )
-            .build()
+        });
+
+        let weights = stream_encoders
+            .iter()
+            .map(|encoder| {
+                encoder
+                    .layer
+                    .as_ref()
+                    .map_or(1., |layer| layer.layer.weight())
+            })
+            .collect::<Vec<_>>();
+
+        let mut n_active = 1;
+        let mut total_weight = weights[0];
+        while n_active < weights.len()
+            && bitrate as f64
+                >= SIMULCAST_MIN_LAYER_BITRATE as f64 * (total_weight + weights[n_active])
+                    / weights[0]
+        {
+            total_weight += weights[n_active];
+            n_active += 1;
+        }
+
+        for (encoder, weight) in stream_encoders.into_iter().zip(weights) {
+            if n_active == 0 {
+                encoder.set_active(element, false);
+                continue;
+            }
+            n_active -= 1;
+
+            encoder.set_active(element, true);
+            let encoder_bitrate = (bitrate as f64 * weight / total_weight) as i32;
+            if encoder.set_bitrate(element, encoder_bitrate).is_ok() {
+                encoder.set_fec_percentage(fec_percentage);
+            }
+        }
+    }
+}
+
+impl SharedEncoderBitrates {
+    /// Records the bitrate requested by a session and returns the bitrate
+    /// the encoder should use according to the policy
+    fn request(&mut self, session_id: &str, bitrate: i32) -> i32 {
+        self.requested.insert(session_id.to_string(), bitrate);
+
+        match self.policy {
+            WebRTCSinkSharedEncoderPolicy::Average => {
+                let sum = self
+                    .requested
+                    .values()
+                    .map(|bitrate| *bitrate as i64)
+                    .sum::<i64>();
+                (sum / self.requested.len() as i64) as i32
+            }
+            _ => self.requested.values().copied().min().unwrap_or(bitrate),
+        }
+    }
+}
+
+impl KeyframeRequests {
+    /// Whether a keyframe request received at `now` must be forwarded to
+    /// the encoder, requests are coalesced until a keyframe was produced or
+    /// the previous request timed out
+    fn forward(&mut self, now: std::time::Instant) -> bool {
+        match self.requested {
+            Some(requested)
+                if now.saturating_duration_since(requested)
+                    < KEYFRAME_REQUEST_COALESCING_TIMEOUT =>
+            {
+                false
+            }
+            _ => {
+                self.requested = Some(now);
+                true
+            }
+        }
+    }
+
+    fn keyframe_produced(&mut self) {
+        self.requested = None;
+    }
+}
+
+impl SharedEncoder {
+    /// Creates and starts a pipeline encoding the raw stream of `producer`
+    fn new(
+        element: &super::BaseWebRTCSink,
+        stream_name: &str,
+        producer: &StreamProducer,
+        codec: &Codec,
+        in_caps: &gst::Caps,
+        policy: WebRTCSinkSharedEncoderPolicy,
+    ) -> Result<Self, Error> {
+        let pipeline = gst::Pipeline::builder()
+            .name(format!(
+                "webrtcsink-shared-encoder-{stream_name}-{}",
+                codec.name
+            ))
+            .build();
+
+        let appsrc = make_element("appsrc", Some(stream_name))?;
+        let mut elements = Vec::new();
+
+        let (raw_filter, encoder) = build_encoding_elements(in_caps, codec, &mut elements)?;
+        let encoder = encoder.ok_or_else(|| anyhow!("No encoder for codec {}", codec.name))?;
+
+        if let Some(parser) = codec.build_parser()? {
+            elements.push(parser);
+        }
+
+        elements.push(
+            gst::ElementFactory::make("capsfilter")
+                .property("caps", codec.parser_caps(true))
+                .build()
+                .with_context(|| "Failed to make element capsfilter")?,
+        );
+
+        let appsink = gst_app::AppSink::builder().build();
+        elements.push(appsink.clone().upcast());
+
+        elements.insert(0, appsrc.clone());
+        pipeline.add_many(&elements).unwrap();
+        gst::Element::link_many(&elements).with_context(|| "Linking shared encoder elements")?;
+
+        element.emit_by_name::<bool>(
+            "encoder-setup",
+            &[&format!("shared:{stream_name}"), &stream_name, &encoder],
+        );
+
+        // All consumers request keyframes when they start or lose packets
+        let keyframe_requests = Arc::new(Mutex::new(KeyframeRequests::default()));
+        let srcpad = encoder.static_pad("src").unwrap();
+        let element_weak = element.downgrade();
+        let keyframe_requests_clone = keyframe_requests.clone();
+        srcpad.add_probe(gst::PadProbeType::EVENT_UPSTREAM, move |_, info| {
+            let Some(gst::PadProbeData::Event(ref ev)) = info.data else {
+                return gst::PadProbeReturn::Ok;
+            };
+
+            if !gst_video::ForceKeyUnitEvent::is(ev) {
+                return gst::PadProbeReturn::Ok;
+            }
+
+            let mut keyframe_requests = keyframe_requests_clone.lock().unwrap();
+            if keyframe_requests.forward(std::time::Instant::now()) {
+                gst::PadProbeReturn::Ok
+            } else {
+                if let Some(element) = element_weak.upgrade() {
+                    gst::log!(CAT, obj = element, "Coalescing keyframe request");
+                }
+                gst::PadProbeReturn::Drop
+            }
+        });
+        srcpad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(buffer) = info.buffer() {
+                if !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+                    keyframe_requests.lock().unwrap().keyframe_produced();
+                }
+            }
+
+            gst::PadProbeReturn::Ok
+        });
+
+        let appsrc = appsrc.downcast::<gst_app::AppSrc>().unwrap();
+        StreamProducer::configure_consumer(&appsrc);
+        let encoded_producer = StreamProducer::from(&appsink);
+
+        pipeline.use_clock(element.clock().as_ref());
+        pipeline.set_start_time(gst::ClockTime::NONE);
+        pipeline.set_base_time(element.base_time().unwrap());
+
+        let mut bus_stream = CustomBusStream::new(element, &pipeline, pipeline.name().as_str());
+        let element_weak = element.downgrade();
+        let pipeline_weak = pipeline.downgrade();
+        RUNTIME.spawn(async move {
+            while let Some(msg) = bus_stream.next().await {
+                let (Some(element), Some(pipeline)) =
+                    (element_weak.upgrade(), pipeline_weak.upgrade())
+                else {
+                    break;
+                };
+
+                match msg.view() {
+                    gst::MessageView::Error(err) => {
+                        gst::error!(
+                            CAT,
+                            obj = element,
+                            "shared encoder {} error: {}, details: {:?}",
+                            pipeline.name(),
+                            err.error(),
+                            err.debug()
+                        );
+                        element.imp().remove_shared_encoder_sessions(&pipeline);
+                    }
+                    gst::MessageView::Latency(..) => {
+                        gst::info!(CAT, obj = pipeline, "Recalculating latency");
+                        let _ = pipeline.recalculate_latency();
+                    }
+                    _ => (),
+                }
+            }
+        });
+
+        let link = pipeline
+            .set_state(gst::State::Playing)
+            .with_context(|| format!("Starting shared encoder for stream {stream_name}"))
+            .and_then(|_| {
+                producer
+                    .add_consumer(&appsrc)
+                    .map_err(|err| anyhow!("Could not link producer: {:?}", err))
+            })
+            .map_err(|err| {
+                let _ = pipeline.set_state(gst::State::Null);
+                err
+            })?;
+
+        Ok(Self {
+            pipeline,
+            producer: encoded_producer,
+            link,
+            handle: SharedEncoderHandle {
+                encoder,
+                raw_filter,
+                bitrates: Arc::new(Mutex::new(SharedEncoderBitrates {
+                    policy,
+                    requested: HashMap::new(),
+                })),
+            },
+            sessions: HashSet::new(),
+        })
+    }
+
+    fn stop(self) {
+        drop(self.link);
+
+        let pipeline = self.pipeline;
+        RUNTIME.spawn_blocking(move || {
+            let _ = pipeline.set_state(gst::State::Null);
+        });
     }
 }
 
@@ -1329,6 +1823,8 @@ impl State {
             session.links.remove(ssrc);
         }
 
+        self.release_shared_encoders(element, &session.id);
+
         let stats_collection_handle = session.stats_collection_handle.take();
 
         let finalizing_sessions = self.finalizing_sessions.clone();
@@ -1356,6 +1852,36 @@ impl State {
         });
     }
 
+    /// Stops the shared encoders that are not used by any other session
+    /// than `session_id` anymore
+    fn release_shared_encoders(&mut self, element: &super::BaseWebRTCSink, session_id: &str) {
+        for shared in self.shared_encoders.values_mut() {
+            if shared.sessions.remove(session_id) {
+                let mut bitrates = shared.handle.bitrates.lock().unwrap();
+                bitrates.requested.remove(session_id);
+            }
+        }
+
+        let unused = self
+            .shared_encoders
+            .iter()
+            .filter(|(_, shared)| shared.sessions.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in unused {
+            let shared = self.shared_encoders.remove(&key).unwrap();
+            gst::info!(
+                CAT,
+                obj = element,
+                "Stopping shared encoder {}",
+                shared.pipeline.name()
+            );
+
+            shared.stop();
+        }
+    }
+
     fn end_session(
         &mut self,
         element: &super::BaseWebRTCSink,
@@ -1448,14 +1974,53 @@ impl Session {
         ret
     }
 
+    /// The codec selected for a given WebRTCPad
+    fn pad_codec(
+        &self,
+        element: &super::BaseWebRTCSink,
+        webrtc_pad: &WebRTCPad,
+        codecs: &BTreeMap<i32, Codec>,
+    ) -> Result<Codec, Error> {
+        let payload = webrtc_pad.payload.unwrap();
+
+        let codecs = match self.codecs {
+            Some(ref codecs) => {
+                gst::debug!(CAT, obj = element, "Picking codec from remote offer");
+                codecs
+            }
+            None => {
+                gst::debug!(CAT, obj = element, "Picking codec from local offer");
+                codecs
+            }
+        };
+
+        codecs
+            .get(&payload)
+            .cloned()
+            .ok_or_else(|| anyhow!("No codec for payload {}", payload))
+    }
+
+    /// Whether the video stream of a given WebRTCPad can be encoded by
+    /// an encoder shared with other consumers
+    fn can_share_encoder(&self, webrtc_pad: &WebRTCPad, codec: &Codec) -> bool {
+        // When answering, the codec was constrained by the remote offer
+        self.codecs.is_none()
+            && webrtc_pad.simulcast_layers.is_empty()
+            && codec.is_video()
+            && !codec.is_raw
+            && has_raw_caps(&webrtc_pad.in_caps)
+    }
+
     /// Called when we have received an answer, connects an InputStream
-    /// to a given WebRTCPad
+    /// to a given WebRTCPad. `producer` outputs the stream encoded by
+    /// `shared` when an encoder is shared with other consumers.
     fn connect_input_stream(
         &mut self,
         element: &super::BaseWebRTCSink,
         producer: &StreamProducer,
         webrtc_pad: &WebRTCPad,
         codecs: &BTreeMap<i32, Codec>,
+        shared: Option<&SharedEncoderHandle>,
     ) -> Result<(), Error> {
         // No stream name, pad only exists to deactivate media
         let stream_name = match webrtc_pad.stream_name {
@@ -1482,25 +2047,7 @@ impl Session {
         );
 
         let payload = webrtc_pad.payload.unwrap();
-
-        let codec = match self.codecs {
-            Some(ref codecs) => {
-                gst::debug!(CAT, obj = element, "Picking codec from remote offer");
-
-                codecs
-                    .get(&payload)
-                    .cloned()
-                    .ok_or_else(|| anyhow!("No codec for payload {}", payload))?
-            }
-            None => {
-                gst::debug!(CAT, obj = element, "Picking codec from local offer");
-
-                codecs
-                    .get(&payload)
-                    .cloned()
-                    .ok_or_else(|| anyhow!("No codec for payload {}", payload))?
-            }
-        };
+        let codec = self.pad_codec(element, webrtc_pad, codecs)?;
 
         let appsrc = make_element("appsrc", Some(stream_name))?;
         self.pipeline.add(&appsrc).unwrap();
@@ -1510,24 +2057,6 @@ impl Session {
 
         let output_caps = codec.output_filter().unwrap_or_else(gst::Caps::new_any);
 
-        let PayloadChain {
-            payloader,
-            encoding_chain,
-        } = PayloadChainBuilder::new(
-            &webrtc_pad.in_caps,
-            &output_caps,
-            &codec,
-            element.emit_by_name::<Option<gst::Element>>(
-                "request-encoded-filter",
-                &[&Some(&self.peer_id), &stream_name, &codec.caps],
-            ),
-        )
-        .build(&self.pipeline, &appsrc)?;
-
-        if let Some(ref enc) = encoding_chain.encoder {
-            element.emit_by_name::<bool>("encoder-setup", &[&self.peer_id, &stream_name, &enc]);
-        }
-
         let sdp = self.sdp.as_ref().unwrap();
         let sdp_media = sdp.media(webrtc_pad.media_idx).unwrap();
 
@@ -1544,16 +2073,6 @@ impl Session {
             .unwrap()
             .intersect(&global_caps);
 
-        element.imp().configure_payloader(
-            &self.peer_id,
-            stream_name,
-            &payloader,
-            &codec,
-            Some(webrtc_pad.ssrc),
-            Some(&caps),
-            ExtensionConfigurationType::Skip,
-        )?;
-
         // At this point, the peer has provided its answer, and we want to
         // let the payloader / encoder perform negotiation according to that.
         //
@@ -1566,63 +2085,241 @@ impl Session {
             .property::<gst_webrtc::WebRTCRTPTransceiver>("transceiver");
         transceiver.set_property("codec-preferences", None::<gst::Caps>);
 
+        // ULPFEC packets are generated by rtpulpfecenc2 and encapsulated in RED
+        // by webrtcbin, whose own ULPFEC encoder is kept disabled
+        let fec_encoder = if transceiver.property::<gst_webrtc::WebRTCFECType>("fec-type")
+            == gst_webrtc::WebRTCFECType::UlpRed
+        {
+            match ulpfec_pt(sdp_media) {
+                Some(fec_pt) => {
+                    let fec_encoder = make_element("rtpulpfecenc2", None)?;
+                    fec_encoder.set_property("pt", fec_pt as u32);
+                    self.pipeline.add(&fec_encoder).unwrap();
+                    Some(fec_encoder)
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+
         let s = caps.structure(0).unwrap();
         let mut filtered_s = gst::Structure::new_empty("application/x-rtp");
 
         filtered_s.extend(s.iter().filter_map(|(key, value)| {
-            if key.starts_with("a-") {
+            if key.starts_with("a-") || key.starts_with("rid-") {
                 None
             } else {
                 Some((key, value.to_owned()))
             }
         }));
-        filtered_s.set("ssrc", webrtc_pad.ssrc);
 
-        let caps = gst::Caps::builder_full().structure(filtered_s).build();
+        // Simulcast layers are encoded separately from the same input
+        // and sent over the same webrtcbin pad
+        let (src, funnel) = if webrtc_pad.simulcast_layers.is_empty() {
+            let mut filtered_s = filtered_s.clone();
+            filtered_s.set("ssrc", webrtc_pad.ssrc);
+            pay_filter.set_property(
+                "caps",
+                gst::Caps::builder_full().structure(filtered_s).build(),
+            );
+
+            (appsrc.clone(), None)
+        } else {
+            pay_filter.set_property(
+                "caps",
+                gst::Caps::builder_full()
+                    .structure(filtered_s.clone())
+                    .build(),
+            );
+
+            let tee = make_element("tee", None)?;
+            let funnel = make_element("rtpfunnel", None)?;
+            self.pipeline.add_many([&tee, &funnel]).unwrap();
+            appsrc.link(&tee)?;
+            funnel.link(&pay_filter)?;
 
-        pay_filter.set_property("caps", caps);
+            (tee, Some(funnel))
+        };
 
-        if codec.is_video() {
-            let video_info = gst_video::VideoInfo::from_caps(&webrtc_pad.in_caps)?;
-            if let Some(mut enc) = VideoEncoder::new(
-                &encoding_chain,
-                video_info,
-                &self.id,
-                codec.caps.structure(0).unwrap().name(),
-                transceiver,
-                stream_name.clone(),
-            ) {
-                match self.cc_info.heuristic {
-                    WebRTCSinkCongestionControl::Disabled => {
-                        // If congestion control is disabled, we simply use the highest
-                        // known "safe" value for the bitrate.
-                        let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
-                        enc.transceiver.set_property("fec-percentage", 50u32);
+        let layers = if webrtc_pad.simulcast_layers.is_empty() {
+            vec![None]
+        } else {
+            webrtc_pad.simulcast_layers.iter().map(Some).collect()
+        };
+
+        let mut encoders = Vec::new();
+        let mut pay_links = Vec::new();
+        for layer in layers {
+            let (chain_src, valve) = match layer {
+                Some(_) => {
+                    let queue = make_element("queue", None)?;
+                    let valve = make_element("valve", None)?;
+                    self.pipeline.add_many([&queue, &valve]).unwrap();
+                    gst::Element::link_many([&src, &queue, &valve])?;
+
+                    (valve.clone(), Some(valve))
+                }
+                None => (src.clone(), None),
+            };
+
+            let PayloadChain {
+                payloader,
+                encoding_chain,
+            } = PayloadChainBuilder::new(
+                match shared {
+                    Some(_) => &codec.caps,
+                    None => &webrtc_pad.in_caps,
+                },
+                &output_caps,
+                &codec,
+                element.emit_by_name::<Option<gst::Element>>(
+                    "request-encoded-filter",
+                    &[&Some(&self.peer_id), &stream_name, &codec.caps],
+                ),
+            )
+            .build(&self.pipeline, &chain_src)?;
+
+            if let Some(ref enc) = encoding_chain.encoder {
+                element.emit_by_name::<bool>("encoder-setup", &[&self.peer_id, &stream_name, &enc]);
+            }
+
+            element.imp().configure_payloader(
+                &self.peer_id,
+                stream_name,
+                &payloader,
+                &codec,
+                Some(layer.map_or(webrtc_pad.ssrc, |layer| layer.ssrc)),
+                Some(&caps),
+                ExtensionConfigurationType::Skip,
+            )?;
+
+            match (layer, funnel.as_ref()) {
+                (Some(layer), Some(funnel)) => {
+                    let mid = transceiver.property::<Option<String>>("mid");
+                    for (uri, property, value) in [
+                        (RTP_STREAM_ID_URI, "rid", Some(layer.rid.clone())),
+                        (RTP_MID_URI, "mid", mid),
+                    ] {
+                        let (Some(id), Some(value)) = (extension_id(s, uri), value) else {
+                            continue;
+                        };
+
+                        let Some(ext) = gst_rtp::RTPHeaderExtension::create_from_uri(uri) else {
+                            anyhow::bail!("Failed to create header extension {uri}, make sure 'gst-plugins-good:rtpmanager' is installed");
+                        };
+                        ext.set_id(id);
+                        ext.set_property(property, value);
+                        payloader.emit_by_name::<()>("add-extension", &[&ext]);
                     }
-                    WebRTCSinkCongestionControl::Homegrown => {
-                        if let Some(congestion_controller) = self.congestion_controller.as_mut() {
-                            if let Ok(bitrate) = enc.bitrate() {
-                                congestion_controller.target_bitrate_on_delay += bitrate;
-                                congestion_controller.target_bitrate_on_loss =
-                                    congestion_controller.target_bitrate_on_delay;
-                                enc.transceiver.set_property("fec-percentage", 0u32);
+
+                    let mut layer_s = filtered_s.clone();
+                    layer_s.set("ssrc", layer.ssrc);
+
+                    let layer_filter = gst::ElementFactory::make("capsfilter")
+                        .property("caps", gst::Caps::builder_full().structure(layer_s).build())
+                        .build()
+                        .with_context(|| "Failed to make element capsfilter")?;
+                    self.pipeline.add(&layer_filter).unwrap();
+                    layer_filter.link(funnel)?;
+
+                    pay_links.push((encoding_chain.pay_filter.clone(), layer_filter));
+                }
+                _ => pay_links.push((encoding_chain.pay_filter.clone(), pay_filter.clone())),
+            }
+
+            let encoding_chain = match shared {
+                Some(shared) => EncodingChain {
+                    raw_filter: Some(shared.raw_filter.clone()),
+                    encoder: Some(shared.encoder.clone()),
+                    pay_filter: encoding_chain.pay_filter,
+                },
+                None => encoding_chain,
+            };
+
+            if codec.is_video() {
+                let video_info = gst_video::VideoInfo::from_caps(&webrtc_pad.in_caps)?;
+                if let Some(mut enc) = VideoEncoder::new(
+                    &encoding_chain,
+                    video_info,
+                    &self.id,
+                    codec.caps.structure(0).unwrap().name(),
+                    transceiver.clone(),
+                    stream_name.clone(),
+                ) {
+                    if let (Some(layer), Some(valve)) = (layer, valve) {
+                        enc.layer = Some(EncoderLayer {
+                            layer: layer.clone(),
+                            valve,
+                            active: true,
+                        });
+                    }
+                    enc.shared_bitrates = shared.map(|shared| shared.bitrates.clone());
+                    enc.fec_encoder = fec_encoder.clone();
+
+                    encoders.push(enc);
+                }
+            }
+        }
+
+        if !encoders.is_empty() {
+            let simulcast = !webrtc_pad.simulcast_layers.is_empty();
+
+            match self.cc_info.heuristic {
+                WebRTCSinkCongestionControl::Disabled => {
+                    // If congestion control is disabled, we simply use the highest
+                    // known "safe" value for the bitrate.
+                    set_encoders_bitrate(
+                        element,
+                        &mut encoders,
+                        |_| self.cc_info.max_bitrate as i32,
+                        50,
+                    );
+                }
+                WebRTCSinkCongestionControl::Homegrown => {
+                    if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+                        if let Ok(bitrate) = encoders[0].bitrate() {
+                            congestion_controller.target_bitrate_on_delay += bitrate;
+                            congestion_controller.target_bitrate_on_loss =
+                                congestion_controller.target_bitrate_on_delay;
+                            if simulcast {
+                                set_encoders_bitrate(element, &mut encoders, |_| bitrate, 0);
+                            } else {
+                                encoders[0].set_fec_percentage(0);
                             }
-                        } else {
-                            /* If congestion control is disabled, we simply use the highest
-                             * known "safe" value for the bitrate. */
-                            let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
-                            enc.transceiver.set_property("fec-percentage", 50u32);
                         }
+                    } else {
+                        /* If congestion control is disabled, we simply use the highest
+                         * known "safe" value for the bitrate. */
+                        set_encoders_bitrate(
+                            element,
+                            &mut encoders,
+                            |_| self.cc_info.max_bitrate as i32,
+                            50,
+                        );
+                    }
+                }
+                _ => {
+                    if simulcast {
+                        // Only turn on the layers the start bitrate allows for
+                        set_encoders_bitrate(
+                            element,
+                            &mut encoders,
+                            |_| self.cc_info.start_bitrate as i32,
+                            0,
+                        );
+                    } else {
+                        encoders[0].set_fec_percentage(0);
                     }
-                    _ => enc.transceiver.set_property("fec-percentage", 0u32),
                 }
+            }
 
-                self.encoders.push(enc);
+            self.encoders.extend(encoders);
 
-                if let Some(rtpgccbwe) = self.rtpgccbwe.as_ref() {
-                    let max_bitrate = self.cc_info.max_bitrate * (self.encoders.len() as u32);
-                    rtpgccbwe.set_property("max-bitrate", max_bitrate);
-                }
+            if let Some(rtpgccbwe) = self.rtpgccbwe.as_ref() {
+                let max_bitrate =
+                    self.cc_info.max_bitrate * (n_encoded_streams(&self.encoders) as u32);
+                rtpgccbwe.set_property("max-bitrate", max_bitrate);
             }
         }
 
@@ -1632,9 +2329,17 @@ impl Session {
             .sync_children_states()
             .with_context(|| format!("Connecting input stream for {}", self.peer_id))?;
 
-        encoding_chain.pay_filter.link(&pay_filter)?;
+        for (src, sink) in pay_links {
+            src.link(&sink)?;
+        }
 
-        let srcpad = pay_filter.static_pad("src").unwrap();
+        let srcpad = match fec_encoder {
+            Some(ref fec_encoder) => {
+                pay_filter.link(fec_encoder)?;
+                fec_encoder.static_pad("src").unwrap()
+            }
+            None => pay_filter.static_pad("src").unwrap(),
+        };
 
         srcpad
             .link(&webrtc_pad.pad)
@@ -2017,17 +2722,182 @@ impl BaseWebRTCSink {
             }
         }
 
-        self.configure_congestion_control(payloader, codec, extension_configuration_type)
+        self.configure_congestion_control(payloader, codec, extension_configuration_type)
+    }
+
+    fn generate_ssrc(&self, webrtc_pads: &HashMap<u32, WebRTCPad>) -> u32 {
+        loop {
+            let ret = fastrand::u32(..);
+
+            if !webrtc_pads.contains_key(&ret)
+                && !webrtc_pads
+                    .values()
+                    .flat_map(|pad| &pad.simulcast_layers)
+                    .any(|layer| layer.ssrc == ret)
+            {
+                gst::trace!(CAT, imp = self, "Selected ssrc {}", ret);
+                return ret;
+            }
+        }
+    }
+
+    /// Returns the producer and elements of the encoder shared by the
+    /// consumers of `stream_name` that negotiated `codec`, creates it
+    /// if needed
+    fn shared_encoder(
+        &self,
+        session_id: &str,
+        stream_name: &str,
+        producer: &StreamProducer,
+        codec: &Codec,
+        in_caps: &gst::Caps,
+        policy: WebRTCSinkSharedEncoderPolicy,
+    ) -> Result<(StreamProducer, SharedEncoderHandle), Error> {
+        let key = (stream_name.to_string(), codec.caps.to_string());
+
+        if let Some(shared) = self.state.lock().unwrap().shared_encoders.get_mut(&key) {
+            shared.sessions.insert(session_id.to_string());
+            return Ok((shared.producer.clone(), shared.handle.clone()));
+        }
+
+        // The encoder-setup signal is emitted without holding the state lock
+        let shared =
+            SharedEncoder::new(&self.obj(), stream_name, producer, codec, in_caps, policy)?;
+
+        let mut state = self.state.lock().unwrap();
+        let shared = match state.shared_encoders.entry(key) {
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                // Created by another session in the meantime
+                shared.stop();
+                entry.into_mut()
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Started shared encoder {}",
+                    shared.pipeline.name()
+                );
+                entry.insert(shared)
+            }
+        };
+
+        shared.sessions.insert(session_id.to_string());
+
+        Ok((shared.producer.clone(), shared.handle.clone()))
+    }
+
+    /// Removes the sessions consuming the shared encoder running in `pipeline`
+    fn remove_shared_encoder_sessions(&self, pipeline: &gst::Pipeline) {
+        let session_ids = self
+            .state
+            .lock()
+            .unwrap()
+            .shared_encoders
+            .values()
+            .find(|shared| &shared.pipeline == pipeline)
+            .map(|shared| shared.sessions.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        for session_id in session_ids {
+            let _ = self.remove_session(&session_id, true);
+        }
+
+        // Sessions that are still being set up are not known to the state
+        // yet, stop the encoder anyway
+        let mut state = self.state.lock().unwrap();
+        let key = state
+            .shared_encoders
+            .iter()
+            .find(|(_, shared)| &shared.pipeline == pipeline)
+            .map(|(key, _)| key.clone());
+        if let Some(shared) = key.and_then(|key| state.shared_encoders.remove(&key)) {
+            shared.stop();
+        }
+    }
+
+    /// Returns the simulcast layers to send for the video `stream`, the
+    /// highest layer uses `ssrc`
+    fn simulcast_layers(
+        &self,
+        stream: &InputStream,
+        settings: &Settings,
+        ssrc: u32,
+        webrtc_pads: &HashMap<u32, WebRTCPad>,
+    ) -> Vec<SimulcastLayer> {
+        if settings.simulcast_layers < 2 {
+            return Vec::new();
+        }
+
+        // The layers are produced by encoding the input at several resolutions
+        if !has_raw_caps(stream.in_caps.as_ref().unwrap()) {
+            gst::warning!(
+                CAT,
+                obj = stream.sink_pad,
+                "Simulcast is only supported with raw input, not enabling it"
+            );
+            return Vec::new();
+        }
+
+        let mut layers: Vec<SimulcastLayer> = Vec::new();
+        for (i, rid) in SIMULCAST_RIDS
+            .iter()
+            .take(settings.simulcast_layers as usize)
+            .enumerate()
+        {
+            let ssrc = if i == 0 {
+                ssrc
+            } else {
+                loop {
+                    let layer_ssrc = self.generate_ssrc(webrtc_pads);
+                    if layer_ssrc != ssrc && layers.iter().all(|layer| layer.ssrc != layer_ssrc) {
+                        break layer_ssrc;
+                    }
+                }
+            };
+
+            layers.push(SimulcastLayer {
+                rid: rid.to_string(),
+                ssrc,
+                scale_down_by: 1 << i,
+            });
+        }
+
+        layers
     }
 
-    fn generate_ssrc(&self, webrtc_pads: &HashMap<u32, WebRTCPad>) -> u32 {
-        loop {
-            let ret = fastrand::u32(..);
+    /// Adds the a=rid and a=simulcast attributes for `layers`, as well as
+    /// the header extensions needed to identify them
+    fn add_simulcast_to_caps(caps: &mut gst::CapsRef, layers: &[SimulcastLayer]) {
+        let s = caps.structure_mut(0).unwrap();
 
-            if !webrtc_pads.contains_key(&ret) {
-                gst::trace!(CAT, imp = self, "Selected ssrc {}", ret);
-                return ret;
+        for layer in layers {
+            s.set(format!("rid-{}", layer.rid), "send");
+        }
+        // Lowest resolution first
+        s.set(
+            "a-simulcast",
+            format!(
+                "send {}",
+                layers
+                    .iter()
+                    .rev()
+                    .map(|layer| layer.rid.as_str())
+                    .join(";")
+            ),
+        );
+
+        for uri in [RTP_STREAM_ID_URI, RTP_MID_URI] {
+            if extension_id(s, uri).is_some() {
+                continue;
             }
+
+            let id = utils::find_smallest_available_ext_id(s.fields().filter_map(|field| {
+                field
+                    .strip_prefix("extmap-")
+                    .and_then(|id| id.parse::<u32>().ok())
+            }));
+            s.set(format!("extmap-{id}"), uri);
         }
     }
 
@@ -2072,6 +2942,7 @@ impl BaseWebRTCSink {
                 ssrc,
                 stream_name: None,
                 payload: None,
+                simulcast_layers: Vec::new(),
             },
         );
     }
@@ -2122,8 +2993,20 @@ impl BaseWebRTCSink {
         if payloader_caps.is_empty() {
             self.request_inactive_webrtcbin_pad(webrtcbin, webrtc_pads, stream.is_video);
         } else {
+            let simulcast_layers = if media.is_none() && stream.is_video {
+                self.simulcast_layers(stream, settings, ssrc, webrtc_pads)
+            } else {
+                Vec::new()
+            };
+
             let payloader_caps_mut = payloader_caps.make_mut();
-            payloader_caps_mut.set("ssrc", ssrc);
+            if simulcast_layers.is_empty() {
+                payloader_caps_mut.set("ssrc", ssrc);
+            } else {
+                // The layers are identified by their RTP stream ID instead
+                // of being signalled with a=ssrc
+                Self::add_simulcast_to_caps(payloader_caps_mut, &simulcast_layers);
+            }
 
             if self.settings.lock().unwrap().do_clock_signalling {
                 // Add RFC7273 attributes when using an NTP or PTP clock
@@ -2217,7 +3100,10 @@ impl BaseWebRTCSink {
 
             if stream.sink_pad.name().starts_with("video_") {
                 if settings.do_fec {
+                    // FEC packets are generated by rtpulpfecenc2 upstream of webrtcbin,
+                    // which only negotiates ULPFEC and encapsulates the packets in RED
                     transceiver.set_property("fec-type", gst_webrtc::WebRTCFECType::UlpRed);
+                    transceiver.set_property("fec-percentage", 0u32);
                 }
 
                 transceiver.set_property("do-nack", settings.do_retransmission);
@@ -2232,6 +3118,7 @@ impl BaseWebRTCSink {
                     ssrc,
                     stream_name: Some(stream.sink_pad.name().to_string()),
                     payload: None,
+                    simulcast_layers,
                 },
             );
         }
@@ -2339,6 +3226,10 @@ impl BaseWebRTCSink {
             .filter_map(|id| state.end_session(&self.obj(), id))
             .collect();
 
+        for (_, shared) in state.shared_encoders.drain() {
+            shared.stop();
+        }
+
         state
             .streams
             .iter_mut()
@@ -3612,7 +4503,7 @@ impl BaseWebRTCSink {
         if let Some(session) = state.sessions.get_mut(session_id) {
             let session = session.unwrap_mut();
 
-            let n_encoders = session.encoders.len();
+            let n_encoders = n_encoded_streams(&session.encoders);
 
             let fec_ratio = {
                 if settings.do_fec && bitrate > DO_FEC_THRESHOLD {
@@ -3634,8 +4525,13 @@ impl BaseWebRTCSink {
             }
 
             let mut s_builder = gst::Structure::builder("webrtcsink/encoder-bitrates");
-            for encoder in session.encoders.iter() {
-                s_builder = s_builder.field(&encoder.stream_name, encoder_bitrate);
+            for stream_name in session
+                .encoders
+                .iter()
+                .map(|encoder| &encoder.stream_name)
+                .unique()
+            {
+                s_builder = s_builder.field(stream_name, encoder_bitrate);
             }
             let s = s_builder.build();
 
@@ -3644,39 +4540,30 @@ impl BaseWebRTCSink {
                 &[&session.peer_id, &(encoders_bitrate as i32), &s],
             );
 
-            for encoder in session.encoders.iter_mut() {
-                let defined_encoder_bitrate =
-                    match updated_bitrates.get::<i32>(&encoder.stream_name) {
-                        Ok(bitrate) => {
-                            gst::log!(
-                                CAT,
-                                imp = self,
-                                "using defined bitrate {bitrate} for encoder {}",
-                                encoder.stream_name
-                            );
-                            bitrate
-                        }
-                        Err(e) => {
-                            gst::log!(
-                                CAT,
-                                imp = self,
-                                "Error in defined bitrate: {e}, falling back to default bitrate \
-                            {encoder_bitrate} for encoder {}",
-                                encoder.stream_name
-                            );
-                            encoder_bitrate
-                        }
-                    };
-
-                if encoder
-                    .set_bitrate(&self.obj(), defined_encoder_bitrate)
-                    .is_ok()
-                {
-                    encoder
-                        .transceiver
-                        .set_property("fec-percentage", (fec_percentage as u32).min(100));
-                }
-            }
+            set_encoders_bitrate(
+                &self.obj(),
+                &mut session.encoders,
+                |stream_name| match updated_bitrates.get::<i32>(stream_name) {
+                    Ok(bitrate) => {
+                        gst::log!(
+                            CAT,
+                            imp = self,
+                            "using defined bitrate {bitrate} for encoder {stream_name}"
+                        );
+                        bitrate
+                    }
+                    Err(e) => {
+                        gst::log!(
+                            CAT,
+                            imp = self,
+                            "Error in defined bitrate: {e}, falling back to default bitrate \
+                            {encoder_bitrate} for encoder {stream_name}"
+                        );
+                        encoder_bitrate
+                    }
+                },
+                (fec_percentage as u32).min(100),
+            );
         }
     }
 
@@ -3716,9 +4603,36 @@ impl BaseWebRTCSink {
                     .and_then(|stream| stream.producer.clone())
                 {
                     drop(state);
-                    if let Err(err) =
-                        session.connect_input_stream(&self.obj(), &producer, webrtc_pad, &codecs)
-                    {
+                    let obj = self.obj();
+                    let policy = self.settings.lock().unwrap().shared_encoder_policy;
+                    let res = session
+                        .pad_codec(&obj, webrtc_pad, &codecs)
+                        .and_then(|codec| {
+                            if policy != WebRTCSinkSharedEncoderPolicy::Disabled
+                                && session.can_share_encoder(webrtc_pad, &codec)
+                            {
+                                let (producer, shared) = self.shared_encoder(
+                                    &session_id,
+                                    stream_name,
+                                    &producer,
+                                    &codec,
+                                    &webrtc_pad.in_caps,
+                                    policy,
+                                )?;
+                                session.connect_input_stream(
+                                    &obj,
+                                    &producer,
+                                    webrtc_pad,
+                                    &codecs,
+                                    Some(&shared),
+                                )
+                            } else {
+                                session.connect_input_stream(
+                                    &obj, &producer, webrtc_pad, &codecs, None,
+                                )
+                            }
+                        });
+                    if let Err(err) = res {
                         gst::error!(
                             CAT,
                             imp = self,
@@ -3853,6 +4767,22 @@ impl BaseWebRTCSink {
                     }
                 }
 
+                if !webrtc_pad.simulcast_layers.is_empty()
+                    && sdp
+                        .media(media_idx)
+                        .and_then(|media| media.attribute_val("simulcast"))
+                        .is_none()
+                {
+                    gst::info!(
+                        CAT,
+                        imp = self,
+                        "consumer from session {} did not accept simulcast for media {}, only sending the highest layer",
+                        session_id,
+                        media_idx,
+                    );
+                    webrtc_pad.simulcast_layers.clear();
+                }
+
                 if let Some(payload) = sdp
                     .media(webrtc_pad.media_idx)
                     .and_then(|media| media.format(0))
@@ -4537,6 +5467,57 @@ impl ObjectImpl for BaseWebRTCSink {
                     .default_value(DEFAULT_DO_RETRANSMISSION)
                     .mutable_ready()
                     .build(),
+                /**
+                 * GstBaseWebRTCSink:simulcast-layers:
+                 *
+                 * Number of spatial simulcast layers to send for each raw video
+                 * stream, each one with half the resolution of the previous one.
+                 *
+                 * The layers are advertised with a=rid and a=simulcast in the offer
+                 * and identified by the RTP stream ID header extension. The
+                 * congestion controller turns off the highest layers when the
+                 * available bitrate is too low for them.
+                 *
+                 * Simulcast is mostly useful when sending to a Selective
+                 * Forwarding Unit, and is not used if the consumer does not accept
+                 * it in its answer.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecUInt::builder("simulcast-layers")
+                    .nick("Simulcast layers")
+                    .blurb("Number of simulcast layers to send for each video stream (1 = no simulcast)")
+                    .minimum(1)
+                    .maximum(SIMULCAST_RIDS.len() as u32)
+                    .default_value(DEFAULT_SIMULCAST_LAYERS)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:shared-encoder-policy:
+                 *
+                 * Whether consumers of the same raw video stream that negotiated
+                 * the same codec share a single encoder instead of each encoding
+                 * the stream separately, and how the bitrate of a shared encoder
+                 * is picked from the bitrates requested by the congestion control
+                 * of its consumers.
+                 *
+                 * Keyframe requests from the consumers of a shared encoder are
+                 * coalesced until the requested keyframe was produced. Streams
+                 * sent with simulcast layers are never shared.
+                 *
+                 * Input streams that are already encoded are never re-encoded
+                 * and always shared between consumers.
+                 *
+                 * Since: plugins-rs-0.14.0
+                 */
+                glib::ParamSpecEnum::builder_with_default(
+                    "shared-encoder-policy",
+                    DEFAULT_SHARED_ENCODER_POLICY,
+                )
+                .nick("Shared encoder policy")
+                .blurb("Whether and how to share video encoders between consumers")
+                .mutable_ready()
+                .build(),
                 glib::ParamSpecBoolean::builder("do-clock-signalling")
                     .nick("Do clock signalling")
                     .blurb("Whether PTP or NTP clock & RTP/clock offset should be signalled according to RFC 7273")
@@ -4747,6 +5728,16 @@ impl ObjectImpl for BaseWebRTCSink {
                 let mut settings = self.settings.lock().unwrap();
                 settings.do_retransmission = value.get::<bool>().expect("type checked upstream");
             }
+            "simulcast-layers" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.simulcast_layers = value.get::<u32>().expect("type checked upstream");
+            }
+            "shared-encoder-policy" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.shared_encoder_policy = value
+                    .get::<WebRTCSinkSharedEncoderPolicy>()
+                    .expect("type checked upstream");
+            }
             "do-clock-signalling" => {
                 let mut settings = self.settings.lock().unwrap();
                 settings.do_clock_signalling = value.get::<bool>().expect("type checked upstream");
@@ -4876,6 +5867,14 @@ impl ObjectImpl for BaseWebRTCSink {
                 let settings = self.settings.lock().unwrap();
                 settings.do_retransmission.to_value()
             }
+            "simulcast-layers" => {
+                let settings = self.settings.lock().unwrap();
+                settings.simulcast_layers.to_value()
+            }
+            "shared-encoder-policy" => {
+                let settings = self.settings.lock().unwrap();
+                settings.shared_encoder_policy.to_value()
+            }
             "do-clock-signalling" => {
                 let settings = self.settings.lock().unwrap();
                 settings.do_clock_signalling.to_value()
@@ -5006,8 +6005,10 @@ impl ObjectImpl for BaseWebRTCSink {
                     .build(),
                 /**
                  * GstBaseWebRTCSink::encoder-setup:
-                 * @consumer_id: Identifier of the consumer, or "discovery"
-                 *   when the encoder is used in a discovery pipeline.
+                 * @consumer_id: Identifier of the consumer, "discovery"
+                 *   when the encoder is used in a discovery pipeline, or "shared:"
+                 *   followed by the pad name when the encoder is shared between
+                 *   consumers.
                  * @pad_name: The name of the corresponding input pad
                  * @encoder: The constructed encoder
                  *
@@ -5870,6 +6871,62 @@ pub(super) mod whip {
     }
 }
 
+#[cfg(feature = "whep")]
+pub(super) mod whep {
+    use super::*;
+    use crate::whep_signaller::WhepServerSignaller;
+
+    #[derive(Default)]
+    pub struct WhepServerSink {}
+
+    impl ObjectImpl for WhepServerSink {
+        fn constructed(&self) {
+            let element = self.obj();
+            let ws = element
+                .upcast_ref::<crate::webrtcsink::BaseWebRTCSink>()
+                .imp();
+
+            let _ = ws.set_signaller(WhepServerSignaller::default().upcast());
+
+            let settings = ws.settings.lock().unwrap();
+            element
+                .bind_property("stun-server", &settings.signaller, "stun-server")
+                .build();
+            element
+                .bind_property("turn-servers", &settings.signaller, "turn-servers")
+                .build();
+        }
+    }
+
+    impl GstObjectImpl for WhepServerSink {}
+
+    impl ElementImpl for WhepServerSink {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "WhepServerSink",
+                    "Sink/Network/WebRTC",
+                    "WebRTC sink with WHEP server signaller",
+                    "Mathieu Duponchelle <mathieu@centricular.com>",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+    }
+
+    impl BinImpl for WhepServerSink {}
+
+    impl BaseWebRTCSinkImpl for WhepServerSink {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for WhepServerSink {
+        const NAME: &'static str = "GstWhepServerSink";
+        type Type = crate::webrtcsink::WhepServerSink;
+        type ParentType = crate::webrtcsink::BaseWebRTCSink;
+    }
+}
+
 #[cfg(feature = "livekit")]
 pub(super) mod livekit {
     use super::*;
@@ -5990,3 +7047,429 @@ pub(super) mod janus {
         type ParentType = crate::webrtcsink::BaseWebRTCSink;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() {
+        use std::sync::Once;
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            gst::init().unwrap();
+        });
+    }
+
+    fn shared_bitrates(policy: WebRTCSinkSharedEncoderPolicy) -> SharedEncoderBitrates {
+        SharedEncoderBitrates {
+            policy,
+            requested: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_shared_encoder_bitrate_weakest() {
+        let mut bitrates = shared_bitrates(WebRTCSinkSharedEncoderPolicy::Weakest);
+
+        assert_eq!(bitrates.request("a", 2_000_000), 2_000_000);
+        assert_eq!(bitrates.request("b", 500_000), 500_000);
+        assert_eq!(bitrates.request("c", 1_000_000), 500_000);
+
+        // The weakest consumer recovered
+        assert_eq!(bitrates.request("b", 3_000_000), 1_000_000);
+
+        bitrates.requested.remove("c");
+        assert_eq!(bitrates.request("a", 2_000_000), 2_000_000);
+    }
+
+    #[test]
+    fn test_shared_encoder_bitrate_average() {
+        let mut bitrates = shared_bitrates(WebRTCSinkSharedEncoderPolicy::Average);
+
+        assert_eq!(bitrates.request("a", 2_000_000), 2_000_000);
+        assert_eq!(bitrates.request("b", 1_000_000), 1_500_000);
+        assert_eq!(bitrates.request("c", 3_000_000), 2_000_000);
+        assert_eq!(bitrates.request("a", 5_000_000), 3_000_000);
+
+        // No overflow with many consumers at the maximum bitrate
+        for i in 0..10 {
+            bitrates.request(&i.to_string(), i32::MAX);
+        }
+        bitrates.requested.retain(|_, bitrate| *bitrate == i32::MAX);
+        assert_eq!(bitrates.request("0", i32::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn test_keyframe_requests_coalescing() {
+        let start = std::time::Instant::now();
+        let mut requests = KeyframeRequests::default();
+
+        assert!(requests.forward(start));
+        // Other consumers ask for the keyframe that is already being produced
+        assert!(!requests.forward(start));
+        assert!(!requests.forward(start + std::time::Duration::from_millis(500)));
+
+        requests.keyframe_produced();
+        assert!(requests.forward(start + std::time::Duration::from_millis(600)));
+
+        // The encoder did not produce the keyframe in time, ask again
+        assert!(!requests.forward(start + std::time::Duration::from_millis(1000)));
+        assert!(requests.forward(
+            start + std::time::Duration::from_millis(600) + KEYFRAME_REQUEST_COALESCING_TIMEOUT
+        ));
+    }
+
+    fn make_shared_encoder(
+        imp: &BaseWebRTCSink,
+        producer: &StreamProducer,
+        session_id: &str,
+    ) -> Option<SharedEncoderHandle> {
+        // Requires an encoder from the installed plugins
+        let codec = Codecs::find("VP8").filter(Codec::can_encode)?;
+        let caps = gst_video::VideoCapsBuilder::new()
+            .format(gst_video::VideoFormat::I420)
+            .width(320)
+            .height(240)
+            .framerate(gst::Fraction::new(30, 1))
+            .build();
+
+        let (_, handle) = imp
+            .shared_encoder(
+                session_id,
+                "video_0",
+                producer,
+                &codec,
+                &caps,
+                WebRTCSinkSharedEncoderPolicy::Weakest,
+            )
+            .unwrap();
+
+        Some(handle)
+    }
+
+    #[test]
+    fn test_shared_encoder_release() {
+        init();
+
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let element = element.upcast_ref::<crate::webrtcsink::BaseWebRTCSink>();
+        let imp = element.imp();
+        let producer = StreamProducer::from(&gst_app::AppSink::builder().build());
+
+        let Some(handle_a) = make_shared_encoder(imp, &producer, "a") else {
+            return;
+        };
+        let handle_b = make_shared_encoder(imp, &producer, "b").unwrap();
+        assert_eq!(handle_a.encoder, handle_b.encoder);
+        handle_a.bitrates.lock().unwrap().request("a", 1_000_000);
+        handle_b.bitrates.lock().unwrap().request("b", 2_000_000);
+
+        let mut state = imp.state.lock().unwrap();
+        assert_eq!(state.shared_encoders.len(), 1);
+
+        state.release_shared_encoders(element, "a");
+        assert_eq!(state.shared_encoders.len(), 1);
+        let requested = handle_b.bitrates.lock().unwrap().requested.clone();
+        assert_eq!(requested, HashMap::from([("b".to_string(), 2_000_000)]));
+
+        // The last consumer left
+        state.release_shared_encoders(element, "b");
+        assert!(state.shared_encoders.is_empty());
+        drop(state);
+
+        // The encoder is created again for new consumers
+        let handle_c = make_shared_encoder(imp, &producer, "c").unwrap();
+        assert_ne!(handle_c.encoder, handle_a.encoder);
+    }
+
+    #[test]
+    fn test_shared_encoder_error() {
+        init();
+
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let element = element.upcast_ref::<crate::webrtcsink::BaseWebRTCSink>();
+        let imp = element.imp();
+        let producer = StreamProducer::from(&gst_app::AppSink::builder().build());
+
+        if make_shared_encoder(imp, &producer, "a").is_none() {
+            return;
+        }
+        make_shared_encoder(imp, &producer, "b").unwrap();
+
+        let pipeline = imp
+            .state
+            .lock()
+            .unwrap()
+            .shared_encoders
+            .values()
+            .next()
+            .unwrap()
+            .pipeline
+            .clone();
+
+        // The sessions were not set up yet, the encoder is stopped nevertheless
+        imp.remove_shared_encoder_sessions(&pipeline);
+        assert!(imp.state.lock().unwrap().shared_encoders.is_empty());
+    }
+
+    fn make_layer(rid: &str, scale_down_by: u32) -> SimulcastLayer {
+        SimulcastLayer {
+            rid: rid.to_string(),
+            ssrc: scale_down_by,
+            scale_down_by,
+        }
+    }
+
+    fn make_video_encoder(
+        webrtcbin: &gst::Element,
+        stream_name: &str,
+        layer: Option<SimulcastLayer>,
+    ) -> Option<VideoEncoder> {
+        // Requires vp8enc from the installed plugins
+        let element = gst::ElementFactory::make("vp8enc").build().ok()?;
+        let video_info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, 1280, 720)
+            .fps(gst::Fraction::new(30, 1))
+            .build()
+            .unwrap();
+        let transceiver = webrtcbin.emit_by_name::<gst_webrtc::WebRTCRTPTransceiver>(
+            "add-transceiver",
+            &[
+                &gst_webrtc::WebRTCRTPTransceiverDirection::Sendonly,
+                &gst::Caps::new_empty_simple("application/x-rtp"),
+            ],
+        );
+
+        Some(VideoEncoder {
+            factory_name: "vp8enc".to_string(),
+            codec_name: "VP8".to_string(),
+            element,
+            filter: gst::ElementFactory::make("capsfilter")
+                .property("caps", gst::Caps::new_empty_simple("video/x-raw"))
+                .build()
+                .unwrap(),
+            halved_framerate: gst::Fraction::new(15, 1),
+            video_info,
+            session_id: "session".to_string(),
+            mitigation_mode: WebRTCSinkMitigationMode::NONE,
+            transceiver,
+            stream_name: stream_name.to_string(),
+            layer: layer.map(|layer| EncoderLayer {
+                layer,
+                valve: gst::ElementFactory::make("valve").build().unwrap(),
+                active: true,
+            }),
+            shared_bitrates: None,
+            fec_encoder: None,
+        })
+    }
+
+    /// Returns the bitrate of the encoders, `None` for the turned off layers
+    fn encoder_bitrates(encoders: &[VideoEncoder]) -> Vec<Option<i32>> {
+        encoders
+            .iter()
+            .map(|encoder| {
+                let active = match encoder.layer {
+                    Some(ref layer) => {
+                        assert_eq!(layer.valve.property::<bool>("drop"), !layer.active);
+                        layer.active
+                    }
+                    None => true,
+                };
+                active.then(|| encoder.bitrate().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_encoders_bitrate() {
+        init();
+
+        let Ok(webrtcbin) = gst::ElementFactory::make("webrtcbin").build() else {
+            return;
+        };
+        let Some(mut encoders) = [
+            ("video_0", Some(make_layer("h", 1))),
+            ("video_0", Some(make_layer("m", 2))),
+            ("video_0", Some(make_layer("l", 4))),
+            ("video_1", None),
+        ]
+        .into_iter()
+        .map(|(stream_name, layer)| make_video_encoder(&webrtcbin, stream_name, layer))
+        .collect::<Option<Vec<_>>>() else {
+            return;
+        };
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let element = element.upcast_ref::<crate::webrtcsink::BaseWebRTCSink>();
+
+        // The layers of a stream are only counted once
+        assert_eq!(n_encoded_streams(&encoders), 2);
+        assert_eq!(n_encoded_streams(&encoders[..1]), 1);
+
+        let stream_bitrate = |bitrate| {
+            move |stream_name: &str| {
+                if stream_name == "video_0" {
+                    bitrate
+                } else {
+                    1_500_000
+                }
+            }
+        };
+
+        // Split according to the number of pixels of each layer
+        set_encoders_bitrate(element, &mut encoders, stream_bitrate(3_000_000), 0);
+        assert_eq!(
+            encoder_bitrates(&encoders),
+            [
+                Some(2_285_714),
+                Some(571_428),
+                Some(142_857),
+                Some(1_500_000)
+            ]
+        );
+
+        // The highest layer is only turned on from 2.1 Mbps, when the lowest
+        // layer gets its minimum bitrate
+        set_encoders_bitrate(element, &mut encoders, stream_bitrate(2_000_000), 0);
+        assert_eq!(
+            encoder_bitrates(&encoders),
+            [None, Some(1_600_000), Some(400_000), Some(1_500_000)]
+        );
+
+        set_encoders_bitrate(element, &mut encoders, stream_bitrate(400_000), 0);
+        assert_eq!(
+            encoder_bitrates(&encoders),
+            [None, None, Some(400_000), Some(1_500_000)]
+        );
+
+        // The lowest layer is never turned off
+        set_encoders_bitrate(element, &mut encoders, stream_bitrate(50_000), 0);
+        assert_eq!(
+            encoder_bitrates(&encoders),
+            [None, None, Some(50_000), Some(1_500_000)]
+        );
+
+        // The layers are turned on again as the bitrate increases
+        set_encoders_bitrate(element, &mut encoders, stream_bitrate(3_000_000), 0);
+        assert_eq!(
+            encoder_bitrates(&encoders),
+            [
+                Some(2_285_714),
+                Some(571_428),
+                Some(142_857),
+                Some(1_500_000)
+            ]
+        );
+
+        // The lower layers have a fixed resolution
+        let caps = encoders[1].filter.property::<gst::Caps>("caps");
+        let s = caps.structure(0).unwrap();
+        assert_eq!(s.get::<i32>("width").unwrap(), 640);
+        assert_eq!(s.get::<i32>("height").unwrap(), 360);
+        let caps = encoders[0].filter.property::<gst::Caps>("caps");
+        assert!(!caps.structure(0).unwrap().has_field("height"));
+    }
+
+    #[test]
+    fn test_simulcast_layers() {
+        init();
+
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let sink_pad = element
+            .request_pad_simple("video_%u")
+            .unwrap()
+            .downcast::<crate::webrtcsink::WebRTCSinkPad>()
+            .unwrap();
+        let imp = element
+            .upcast_ref::<crate::webrtcsink::BaseWebRTCSink>()
+            .imp();
+
+        let mut stream = InputStream {
+            sink_pad,
+            producer: None,
+            in_caps: Some(
+                gst_video::VideoCapsBuilder::new()
+                    .format(gst_video::VideoFormat::I420)
+                    .width(1280)
+                    .height(720)
+                    .build(),
+            ),
+            out_caps: None,
+            clocksync: None,
+            serial: 0,
+            is_video: true,
+            initial_discovery_started: false,
+        };
+        let mut settings = Settings::default();
+
+        settings.simulcast_layers = 1;
+        assert!(imp
+            .simulcast_layers(&stream, &settings, 1234, &HashMap::new())
+            .is_empty());
+
+        settings.simulcast_layers = 3;
+        let layers = imp.simulcast_layers(&stream, &settings, 1234, &HashMap::new());
+        assert_eq!(
+            layers
+                .iter()
+                .map(|layer| (layer.rid.as_str(), layer.scale_down_by))
+                .collect::<Vec<_>>(),
+            [("h", 1), ("m", 2), ("l", 4)]
+        );
+        // The highest layer uses the ssrc of the pad, all are distinct
+        assert_eq!(layers[0].ssrc, 1234);
+        assert_eq!(layers.iter().map(|layer| layer.ssrc).unique().count(), 3);
+
+        settings.simulcast_layers = 2;
+        let layers = imp.simulcast_layers(&stream, &settings, 1234, &HashMap::new());
+        assert_eq!(
+            layers
+                .iter()
+                .map(|layer| layer.rid.as_str())
+                .collect::<Vec<_>>(),
+            ["h", "m"]
+        );
+
+        // Encoded input can't be scaled down
+        stream.in_caps = Some(gst::Caps::new_empty_simple("video/x-vp8"));
+        assert!(imp
+            .simulcast_layers(&stream, &settings, 1234, &HashMap::new())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_add_simulcast_to_caps() {
+        init();
+
+        let layers = [make_layer("h", 1), make_layer("m", 2), make_layer("l", 4)];
+
+        let mut caps = gst::Caps::builder("application/x-rtp")
+            .field("extmap-1", RTP_MID_URI)
+            .field("extmap-3", "urn:ietf:params:rtp-hdrext:toffset")
+            .build();
+        BaseWebRTCSink::add_simulcast_to_caps(caps.get_mut().unwrap(), &layers);
+
+        let s = caps.structure(0).unwrap();
+        for rid in ["h", "m", "l"] {
+            assert_eq!(
+                s.get::<&str>(format!("rid-{rid}").as_str()).unwrap(),
+                "send"
+            );
+        }
+        // Lowest resolution first
+        assert_eq!(s.get::<&str>("a-simulcast").unwrap(), "send l;m;h");
+        // The free extension ID is used and the existing MID mapping is kept
+        assert_eq!(s.get::<&str>("extmap-2").unwrap(), RTP_STREAM_ID_URI);
+        assert_eq!(s.get::<&str>("extmap-1").unwrap(), RTP_MID_URI);
+        assert!(!s.has_field("extmap-4"));
+
+        let mut caps = gst::Caps::new_empty_simple("application/x-rtp");
+        BaseWebRTCSink::add_simulcast_to_caps(caps.get_mut().unwrap(), &layers[..2]);
+
+        let s = caps.structure(0).unwrap();
+        assert_eq!(s.get::<&str>("a-simulcast").unwrap(), "send m;h");
+        assert!(!s.has_field("rid-l"));
+        assert_eq!(extension_id(s, RTP_STREAM_ID_URI), Some(1));
+        assert_eq!(extension_id(s, RTP_MID_URI), Some(2));
+    }
+}
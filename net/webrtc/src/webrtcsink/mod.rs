@@ -64,6 +64,11 @@ glib::wrapper! {
     pub struct WhipWebRTCSink(ObjectSubclass<imp::whip::WhipWebRTCSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
 }
 
+#[cfg(feature = "whep")]
+glib::wrapper! {
+    pub struct WhepServerSink(ObjectSubclass<imp::whep::WhepServerSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
+}
+
 #[cfg(feature = "livekit")]
 glib::wrapper! {
     pub struct LiveKitWebRTCSink(ObjectSubclass<imp::livekit::LiveKitWebRTCSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
@@ -125,6 +130,27 @@ pub enum WebRTCSinkCongestionControl {
     GoogleCongestionControl,
 }
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWebRTCSinkSharedEncoderPolicy")]
+pub enum WebRTCSinkSharedEncoderPolicy {
+    #[enum_value(
+        name = "Disabled: each consumer has its own encoder",
+        nick = "disabled"
+    )]
+    Disabled,
+    #[enum_value(
+        name = "Weakest: the bitrate of a shared encoder follows the weakest consumer",
+        nick = "weakest"
+    )]
+    Weakest,
+    #[enum_value(
+        name = "Average: the bitrate of a shared encoder is the average of its consumers",
+        nick = "average"
+    )]
+    Average,
+}
+
 #[glib::flags(name = "GstWebRTCSinkMitigationMode")]
 enum WebRTCSinkMitigationMode {
     #[flags_value(name = "No mitigation applied", nick = "none")]
@@ -139,6 +165,7 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     WebRTCSinkPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     BaseWebRTCSink::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     WebRTCSinkCongestionControl::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    WebRTCSinkSharedEncoderPolicy::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     gst::Element::register(
         Some(plugin),
         "webrtcsink",
@@ -159,6 +186,38 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         gst::Rank::NONE,
         WhipWebRTCSink::static_type(),
     )?;
+    #[cfg(feature = "whep")]
+    /**
+     * element-whepserversink:
+     *
+     * A WebRTC sink acting as a [WHEP] server: each client POSTing an SDP
+     * offer to the endpoint becomes a consumer of the stream.
+     *
+     * The server listens on `signaller::host-addr` and exposes the endpoint at
+     * `/whep/endpoint`. The answer is sent once ICE gathering is complete, and
+     * points the client to a `/whep/resource/<id>` resource where it can
+     * trickle its ICE candidates with PATCH requests, and end the session with
+     * a DELETE request. PATCH requests with an `If-Match` header must match the
+     * `ETag` returned with the answer, ICE restarts are not supported. The STUN
+     * and TURN servers are advertised with Link headers.
+     *
+     * When `signaller::auth-token` is set, requests must carry it in an
+     * `Authorization: Bearer <token>` header.
+     *
+     * ```bash
+     * $ gst-launch-1.0 videotestsrc is-live=true ! whepserversink signaller::host-addr=http://127.0.0.1:8190
+     * ```
+     *
+     * [WHEP]: https://datatracker.ietf.org/doc/draft-ietf-wish-whep/
+     *
+     * Since: plugins-rs-0.14.0
+     */
+    gst::Element::register(
+        Some(plugin),
+        "whepserversink",
+        gst::Rank::NONE,
+        WhepServerSink::static_type(),
+    )?;
     #[cfg(feature = "livekit")]
     gst::Element::register(
         Some(plugin),